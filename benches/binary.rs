@@ -0,0 +1,62 @@
+//! Binary node encoding and decoding, owned versus zero-copy
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use whatsmeow::binary::{token::*, BinaryDecoder, BinaryEncoder, Node};
+
+/// A `<message>` with an `<enc>` payload of `size` bytes, as received in bursts
+fn message_frame(size: usize) -> Vec<u8> {
+    let mut data = vec![7];
+    data.extend_from_slice(b"message");
+    data.extend_from_slice(&[LIST_8, 4]);
+    for (key, value) in [
+        ("from", "1234567890@s.whatsapp.net"),
+        ("id", "3EB0C4A3F1E2D5B6A7C8"),
+        ("type", "text"),
+        ("t", "1700000000"),
+    ] {
+        data.push(key.len() as u8);
+        data.extend_from_slice(key.as_bytes());
+        data.push(value.len() as u8);
+        data.extend_from_slice(value.as_bytes());
+    }
+    data.extend_from_slice(&[LIST_8, 1, 3]);
+    data.extend_from_slice(b"enc");
+    data.extend_from_slice(&[LIST_8, 2, 1, b'v', 1, b'2', 4]);
+    data.extend_from_slice(b"type");
+    data.extend_from_slice(&[3]);
+    data.extend_from_slice(b"msg");
+    data.push(BINARY_20);
+    data.extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+    data.resize(data.len() + size, 0xab);
+    data
+}
+
+fn bench_decode(c: &mut Criterion) {
+    for size in [256, 64 * 1024] {
+        let frame = Bytes::from(message_frame(size));
+        let mut group = c.benchmark_group(format!("decode_message_{}", size));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_function("owned", |b| {
+            b.iter(|| BinaryDecoder::new(black_box(&frame)).decode().unwrap())
+        });
+        group.bench_function("zero_copy", |b| {
+            b.iter(|| BinaryDecoder::from_bytes(black_box(&frame)).decode_ref().unwrap())
+        });
+        group.finish();
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let node = Node::builder("message")
+        .attr("to", "1234567890@s.whatsapp.net")
+        .attr("id", "3EB0C4A3F1E2D5B6A7C8")
+        .attr("type", "text")
+        .child("enc", |enc| enc.attr("v", "2").attr("type", "msg").bytes(vec![0xab; 256]))
+        .build();
+    let mut encoder = BinaryEncoder::new();
+    c.bench_function("encode_message_256", |b| b.iter(|| encoder.encode(black_box(&node)).unwrap()));
+}
+
+criterion_group!(benches, bench_decode, bench_encode);
+criterion_main!(benches);
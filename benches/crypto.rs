@@ -0,0 +1,63 @@
+//! Noise transport and Signal message encryption
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use whatsmeow::signal::SignalProtocolManager;
+use whatsmeow::socket::noise::NoiseHandshake;
+
+const SIZES: [usize; 3] = [64, 1024, 64 * 1024];
+
+/// Two ends of a finished handshake sharing the same key
+fn noise_pair() -> (NoiseHandshake, NoiseHandshake) {
+    let start = || {
+        let mut noise = NoiseHandshake::new();
+        noise.start("Noise_XX_25519_AESGCM_SHA256\0\0\0\0", &[b'W', b'A', 6, 2]).unwrap();
+        noise
+    };
+    (start(), start())
+}
+
+fn bench_noise(c: &mut Criterion) {
+    let mut group = c.benchmark_group("noise_transport");
+    for size in SIZES {
+        let frame = vec![0x5a; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt_decrypt", size), &frame, |b, frame| {
+            let (mut sender, mut receiver) = noise_pair();
+            b.iter(|| {
+                let ciphertext = sender.encrypt(black_box(frame)).unwrap();
+                receiver.decrypt(&ciphertext).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_signal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("signal");
+    for size in SIZES {
+        let plaintext = vec![0x5a; size];
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("session_encrypt", size), &plaintext, |b, plaintext| {
+            let mut alice = SignalProtocolManager::new_with_memory_stores(1);
+            let mut bob = SignalProtocolManager::new_with_memory_stores(2);
+            let bundle = bob.generate_prekey_bundle(1).unwrap();
+            alice.initialize_outgoing_session("bob", &bundle).unwrap();
+            b.iter(|| alice.encrypt_message("bob", black_box(plaintext)).unwrap())
+        });
+
+        group.bench_with_input(BenchmarkId::new("group_encrypt_decrypt", size), &plaintext, |b, plaintext| {
+            let mut manager = SignalProtocolManager::new_with_memory_stores(1);
+            let distribution = manager.initialize_group_session("group").unwrap();
+            manager.process_sender_key_distribution("group", "alice", &distribution).unwrap();
+            b.iter(|| {
+                let message = manager.encrypt_group_message("group", black_box(plaintext)).unwrap();
+                manager.decrypt_group_message("group", "alice", &message).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_noise, bench_signal);
+criterion_main!(benches);
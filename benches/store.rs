@@ -0,0 +1,45 @@
+//! SQLite store reads and writes
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use tokio::runtime::Runtime;
+use whatsmeow::database::{sqlite::SqliteContactStore, Database, DatabaseConfig};
+use whatsmeow::types::JID;
+
+fn database(runtime: &Runtime) -> Database {
+    let config = DatabaseConfig {
+        database_url: "sqlite::memory:".to_string(),
+        max_connections: 1,
+        connection_timeout: 10,
+        enable_wal: false,
+    };
+    runtime.block_on(Database::new(config)).unwrap()
+}
+
+fn bench_contacts(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let db = database(&runtime);
+    let store = SqliteContactStore::new(db.pool().clone());
+    let jids: Vec<JID> = (0..1000).map(|i| JID::new_user(&format!("{}", 1_000_000 + i))).collect();
+
+    let mut group = c.benchmark_group("contact_store");
+    let mut next = 0;
+    group.bench_function("write", |b| {
+        b.iter(|| {
+            let jid = &jids[next % jids.len()];
+            next += 1;
+            runtime.block_on(store.store_contact(jid, Some("Alice"), Some("+1000000"))).unwrap()
+        })
+    });
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            let jid = &jids[next % jids.len()];
+            next += 1;
+            runtime.block_on(store.load_contact(jid)).unwrap()
+        })
+    });
+    group.bench_function("list_1000", |b| b.iter(|| runtime.block_on(store.list_contacts()).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_contacts);
+criterion_main!(benches);
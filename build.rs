@@ -1,51 +1,45 @@
-use prost_build::Config;
-use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
+
+/// Protobuf definitions compiled into `crate::proto`
+const PROTO_FILES: &[&str] = &[
+    "src/proto/waCommon/WACommon.proto",
+    "src/proto/waAdv/WAAdv.proto",
+    "src/proto/waCompanionReg/WACompanionReg.proto",
+    "src/proto/waMmsRetry/WAMmsRetry.proto",
+    "src/proto/waStatusAttributions/WAStatusAttributions.proto",
+    "src/proto/waE2E/WAWebProtobufsE2E.proto",
+    "src/proto/waWeb/WAWebProtobufsWeb.proto",
+    "src/proto/waHistorySync/WAWebProtobufsHistorySync.proto",
+    "src/proto/waMsgTransport/WAMsgTransport.proto",
+    "src/proto/waMultiDevice/WAMultiDevice.proto",
+    "src/proto/waServerSync/WAServerSync.proto",
+    "src/proto/waSyncAction/WASyncAction.proto",
+    "src/proto/waWa6/WAWebProtobufsWa6.proto",
+];
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=src/proto");
-    
-    // Check if protoc is available
-    if let Err(_) = std::process::Command::new("protoc").arg("--version").output() {
-        println!("cargo:warning=protoc not found, skipping protobuf compilation");
-        println!("cargo:warning=Install protoc to enable full protobuf support");
-        return Ok(());
-    }
-    
-    // Configure prost
-    let mut config = Config::new();
-    config.out_dir("src/proto/generated");
-    
-    // Ensure output directory exists
-    fs::create_dir_all("src/proto/generated")?;
-    
-    // Compile core protobuf files
-    let proto_files = [
-        "src/proto/wa_common/WACommon.proto",
-        "src/proto/wa_web/WAWebProtobufsWeb.proto", 
-        "src/proto/wa_e2e/WAWebProtobufsE2E.proto",
-        "src/proto/wa_msg_transport/WAMsgTransport.proto",
-        "src/proto/wa_multi_device/WAMultiDevice.proto",
-        "src/proto/wa_companion_reg/WACompanionReg.proto",
-    ];
-    
-    // Only compile files that exist
-    let existing_files: Vec<&str> = proto_files
-        .iter()
-        .filter(|file| Path::new(file).exists())
-        .copied()
-        .collect();
-    
-    if existing_files.is_empty() {
-        println!("cargo:warning=No protobuf files found to compile");
-        return Ok(());
+    println!("cargo:rerun-if-env-changed=PROTOC");
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    let mut config = prost_build::Config::new();
+    config.out_dir(&out_dir);
+
+    // Prefer an explicitly configured or installed protoc, fall back to the
+    // vendored binary so the build works on machines without one (e.g. Windows)
+    if std::env::var_os("PROTOC").is_none() && !system_protoc_available() {
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
     }
-    
-    println!("cargo:warning=Compiling {} protobuf files", existing_files.len());
-    
-    // Compile the protobuf files
-    config.compile_protos(&existing_files, &["src/proto"])?;
-    
-    println!("cargo:warning=Protobuf compilation completed successfully");
+
+    config.compile_protos(PROTO_FILES, &["src/proto"])?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+fn system_protoc_available() -> bool {
+    std::process::Command::new("protoc")
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
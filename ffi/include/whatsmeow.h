@@ -0,0 +1,166 @@
+/*
+ * C API of the whatsmeow client.
+ *
+ * Re-entrancy:
+ * - Event callbacks run on the client's runtime threads. They must not call
+ *   the blocking functions of this library, including wm_client_free; such
+ *   calls fail with WM_STATUS_WRONG_THREAD instead of deadlocking. Hand the
+ *   work to a thread of your own instead.
+ * - The same applies to calls from any other Tokio runtime thread.
+ * - Callbacks must not unwind (C++ exceptions, longjmp) into the library.
+ * - A panic inside the library never crosses the C boundary: the call
+ *   returns WM_STATUS_PANIC, NULL or false and wm_last_error() holds the
+ *   panic message. The client may be left in an inconsistent state and
+ *   should be freed.
+ */
+
+#ifndef WHATSMEOW_H
+#define WHATSMEOW_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of an FFI call
+typedef enum WmStatus {
+  WM_STATUS_OK = 0,
+  // A pointer was null or a string was not valid UTF-8
+  WM_STATUS_INVALID_ARGUMENT = 1,
+  // The operation failed, see [`wm_last_error`]
+  WM_STATUS_ERROR = 2,
+  // The library panicked, see [`wm_last_error`]; free the client
+  WM_STATUS_PANIC = 3,
+  // A blocking function was called from a runtime thread, e.g. inside an event callback
+  WM_STATUS_WRONG_THREAD = 4,
+} WmStatus;
+
+// Opaque client handle
+typedef struct WmClient WmClient;
+
+// A byte buffer owned by the caller, release it with [`wm_buffer_free`]
+typedef struct WmBuffer {
+  uint8_t *data;
+  size_t len;
+} WmBuffer;
+
+// Called with each event as `len` bytes of JSON, NUL-terminated.
+//
+// The JSON is only valid during the call. Returning `false` stops the
+// event from reaching handlers registered after this one.
+typedef bool (*WmEventCallback)(void *user_data, const char *event_json, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message describing the last failed call on this thread, or null.
+//
+// The string stays valid until the next call on this thread.
+const char *wm_last_error(void);
+
+// Release a string returned by this library
+//
+// # Safety
+// `s` must be null or a string returned by this library that was not freed yet.
+void wm_string_free(char *s);
+
+// Release a buffer returned by this library
+//
+// # Safety
+// `buffer` must have been returned by this library and not freed yet.
+void wm_buffer_free(struct WmBuffer buffer);
+
+// Create a client storing its session in the SQLite database at `database_path`.
+//
+// Returns null on failure.
+//
+// # Safety
+// `database_path` must be a NUL-terminated string.
+struct WmClient *wm_client_new(const char *database_path);
+
+// Disconnect and release a client.
+//
+// Called from a runtime thread, e.g. inside an event callback, the client
+// is left alone and [`wm_last_error`] says so.
+//
+// # Safety
+// `client` must be null or a handle from [`wm_client_new`] that was not freed yet.
+void wm_client_free(struct WmClient *client);
+
+// Register a callback receiving every event as JSON
+//
+// # Safety
+// `client` must be a live handle. `user_data` is passed to `callback` from
+// the client's threads and must stay valid as long as the client.
+enum WmStatus wm_client_add_event_handler(struct WmClient *client,
+                                          WmEventCallback callback,
+                                          void *user_data);
+
+// Connect to WhatsApp
+//
+// # Safety
+// `client` must be a live handle.
+enum WmStatus wm_client_connect(struct WmClient *client);
+
+// Disconnect from WhatsApp
+//
+// # Safety
+// `client` must be a live handle.
+enum WmStatus wm_client_disconnect(struct WmClient *client);
+
+// Whether the client is logged in
+//
+// # Safety
+// `client` must be null or a live handle.
+bool wm_client_is_logged_in(const struct WmClient *client);
+
+// Generate the QR code contents to pair this client, written to `out_qr`
+//
+// # Safety
+// `client` must be a live handle, `out_qr` a valid pointer.
+enum WmStatus wm_client_generate_qr(struct WmClient *client, char **out_qr);
+
+// Send a text message, writing the message ID to `out_message_id` if it is not null
+//
+// # Safety
+// `client` must be a live handle, `to` and `text` NUL-terminated strings.
+enum WmStatus wm_client_send_text(struct WmClient *client,
+                                  const char *to,
+                                  const char *text,
+                                  char **out_message_id);
+
+// Upload `len` bytes of media and send them; `caption` may be null
+//
+// # Safety
+// `client` must be a live handle, `data` must point to `len` readable bytes,
+// `to`, `filename` and a non-null `caption` must be NUL-terminated strings.
+enum WmStatus wm_client_send_media(struct WmClient *client,
+                                   const char *to,
+                                   const uint8_t *data,
+                                   size_t len,
+                                   const char *filename,
+                                   const char *caption,
+                                   char **out_message_id);
+
+// Compute the safety number of the chat with `jid`.
+//
+// The 60 digits are written to `out_digits`, the verification QR code
+// contents to `out_qr_data`.
+//
+// # Safety
+// `client` must be a live handle, `jid` a NUL-terminated string and the
+// out pointers valid.
+enum WmStatus wm_client_safety_number(struct WmClient *client,
+                                      const char *jid,
+                                      char **out_digits,
+                                      struct WmBuffer *out_qr_data);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // WHATSMEOW_H
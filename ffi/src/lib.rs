@@ -0,0 +1,523 @@
+//! C ABI for the whatsmeow client
+//!
+//! The client is an opaque [`WmClient`] handle owning its own Tokio runtime,
+//! so callers never deal with async Rust. All functions are blocking and
+//! may be called from any thread of the caller, see the re-entrancy rules below.
+//!
+//! Conventions:
+//! - Strings passed in are NUL-terminated UTF-8 and only borrowed for the call.
+//! - Strings and buffers handed out are owned by the caller and released
+//!   with [`wm_string_free`] and [`wm_buffer_free`].
+//! - Functions return a [`WmStatus`]; on failure [`wm_last_error`] describes
+//!   what went wrong.
+//! - Events are delivered as JSON (`{"type": "message", "data": {...}}`) to
+//!   callbacks running on the client's runtime threads.
+//!
+//! Re-entrancy:
+//! - Event callbacks run on the client's runtime threads. They must not call
+//!   the blocking functions of this library, including [`wm_client_free`];
+//!   such calls fail with [`WmStatus::WrongThread`] instead of deadlocking.
+//!   Hand the work to a thread of your own instead.
+//! - The same applies to calls from any other Tokio runtime thread.
+//! - Callbacks must not unwind (C++ exceptions, `longjmp`) into the library.
+//! - A panic inside the library never crosses the C boundary: the call
+//!   returns [`WmStatus::Panic`], null or `false` and [`wm_last_error`]
+//!   holds the panic message. The client may be left in an inconsistent
+//!   state afterwards and should be freed.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+use whatsmeow::{
+    database::{sqlite::SqliteDeviceStore, Database, DatabaseConfig},
+    types::{Event, JID},
+    Client,
+};
+
+/// Result of an FFI call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WmStatus {
+    Ok = 0,
+    /// A pointer was null or a string was not valid UTF-8
+    InvalidArgument = 1,
+    /// The operation failed, see [`wm_last_error`]
+    Error = 2,
+    /// The library panicked, see [`wm_last_error`]; free the client
+    Panic = 3,
+    /// A blocking function was called from a runtime thread, e.g. inside an event callback
+    WrongThread = 4,
+}
+
+/// A byte buffer owned by the caller, release it with [`wm_buffer_free`]
+#[repr(C)]
+pub struct WmBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Called with each event as `len` bytes of JSON, NUL-terminated.
+///
+/// The JSON is only valid during the call. Returning `false` stops the
+/// event from reaching handlers registered after this one.
+pub type WmEventCallback = extern "C" fn(user_data: *mut c_void, event_json: *const c_char, len: usize) -> bool;
+
+/// Opaque client handle
+pub struct WmClient {
+    runtime: Runtime,
+    client: Arc<Client>,
+}
+
+/// Caller data handed back to the event callback.
+///
+/// The caller promises it can be used from other threads.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    // A method, so closures capture the whole wrapper instead of the raw pointer
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run the body of an export, returning `on_panic` instead of unwinding
+/// into the caller
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown cause".to_string());
+            set_last_error(format!("Panic in whatsmeow: {}", message));
+            on_panic
+        }
+    }
+}
+
+/// Fail if the calling thread belongs to a Tokio runtime, where blocking on
+/// the client's runtime would panic or deadlock
+fn check_thread() -> Result<(), WmStatus> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        set_last_error("Blocking call from a runtime thread, e.g. inside an event callback");
+        return Err(WmStatus::WrongThread);
+    }
+    Ok(())
+}
+
+/// Block on a future on the client's runtime and turn its result into a status
+fn run<T>(handle: &WmClient, future: impl Future<Output = whatsmeow::Result<T>>, out: impl FnOnce(T)) -> WmStatus {
+    if let Err(status) = check_thread() {
+        return status;
+    }
+    status(handle.runtime.block_on(future), out)
+}
+
+/// Turn a result into a status, remembering the error
+fn status<T>(result: whatsmeow::Result<T>, out: impl FnOnce(T)) -> WmStatus {
+    match result {
+        Ok(value) => {
+            out(value);
+            WmStatus::Ok
+        }
+        Err(e) => {
+            set_last_error(e);
+            WmStatus::Error
+        }
+    }
+}
+
+/// Borrow the client behind a handle
+unsafe fn borrow_client<'a>(client: *const WmClient) -> Option<&'a WmClient> {
+    let handle = client.as_ref();
+    if handle.is_none() {
+        set_last_error("Unexpected null client");
+    }
+    handle
+}
+
+/// Borrow a NUL-terminated UTF-8 string
+unsafe fn borrow_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error("Unexpected null string");
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(e) => {
+            set_last_error(format!("String is not valid UTF-8: {}", e));
+            None
+        }
+    }
+}
+
+unsafe fn borrow_jid(s: *const c_char) -> Option<JID> {
+    match borrow_str(s)?.parse() {
+        Ok(jid) => Some(jid),
+        Err(e) => {
+            set_last_error(e);
+            None
+        }
+    }
+}
+
+/// Hand a string to the caller through an out pointer
+unsafe fn give_string(out: *mut *mut c_char, value: String) {
+    if !out.is_null() {
+        *out = CString::new(value.replace('\0', " ")).unwrap_or_default().into_raw();
+    }
+}
+
+/// Message describing the last failed call on this thread, or null.
+///
+/// The string stays valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn wm_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+    })
+}
+
+/// Release a string returned by this library
+///
+/// # Safety
+/// `s` must be null or a string returned by this library that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wm_string_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+/// Release a buffer returned by this library
+///
+/// # Safety
+/// `buffer` must have been returned by this library and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wm_buffer_free(buffer: WmBuffer) {
+    guard((), || {
+        if !buffer.data.is_null() {
+            drop(Vec::from_raw_parts(buffer.data, buffer.len, buffer.len));
+        }
+    })
+}
+
+fn into_buffer(data: Vec<u8>) -> WmBuffer {
+    let mut data = data.into_boxed_slice();
+    let buffer = WmBuffer { data: data.as_mut_ptr(), len: data.len() };
+    std::mem::forget(data);
+    buffer
+}
+
+/// Create a client storing its session in the SQLite database at `database_path`.
+///
+/// Returns null on failure.
+///
+/// # Safety
+/// `database_path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_new(database_path: *const c_char) -> *mut WmClient {
+    guard(ptr::null_mut(), || {
+        let Some(path) = borrow_str(database_path) else {
+            return ptr::null_mut();
+        };
+        if check_thread().is_err() {
+            return ptr::null_mut();
+        }
+        let runtime = match tokio::runtime::Builder::new_multi_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                set_last_error(format!("Failed to start runtime: {}", e));
+                return ptr::null_mut();
+            }
+        };
+
+        let config = DatabaseConfig { database_url: format!("sqlite:{}", path), ..Default::default() };
+        let client = runtime.block_on(async {
+            let database = Arc::new(Database::new(config).await?);
+            let store = Arc::new(SqliteDeviceStore::new(database.pool().clone()));
+            Client::new(store, database).await
+        });
+        match client {
+            Ok(client) => Box::into_raw(Box::new(WmClient { runtime, client: Arc::new(client) })),
+            Err(e) => {
+                set_last_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Disconnect and release a client.
+///
+/// Called from a runtime thread, e.g. inside an event callback, the client
+/// is left alone and [`wm_last_error`] says so.
+///
+/// # Safety
+/// `client` must be null or a handle from [`wm_client_new`] that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_free(client: *mut WmClient) {
+    guard((), || {
+        if client.is_null() || check_thread().is_err() {
+            return;
+        }
+        let handle = Box::from_raw(client);
+        let _ = handle.runtime.block_on(handle.client.disconnect());
+    })
+}
+
+/// Register a callback receiving every event as JSON
+///
+/// # Safety
+/// `client` must be a live handle. `user_data` is passed to `callback` from
+/// the client's threads and must stay valid as long as the client.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_add_event_handler(
+    client: *mut WmClient,
+    callback: WmEventCallback,
+    user_data: *mut c_void,
+) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let Some(handle) = borrow_client(client) else {
+            return WmStatus::InvalidArgument;
+        };
+        if let Err(status) = check_thread() {
+            return status;
+        }
+        let user_data = UserData(user_data);
+        handle.runtime.block_on(handle.client.add_event_handler(Box::new(move |event: Event| {
+            let json = match serde_json::to_string(&event) {
+                Ok(json) => json,
+                Err(_) => return true,
+            };
+            let len = json.len();
+            let json = CString::new(json).unwrap_or_default();
+            callback(user_data.get(), json.as_ptr(), len)
+        })));
+        WmStatus::Ok
+    })
+}
+
+/// Connect to WhatsApp
+///
+/// # Safety
+/// `client` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_connect(client: *mut WmClient) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let Some(handle) = borrow_client(client) else {
+            return WmStatus::InvalidArgument;
+        };
+        run(handle, handle.client.connect(), |_| {})
+    })
+}
+
+/// Disconnect from WhatsApp
+///
+/// # Safety
+/// `client` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_disconnect(client: *mut WmClient) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let Some(handle) = borrow_client(client) else {
+            return WmStatus::InvalidArgument;
+        };
+        run(handle, handle.client.disconnect(), |_| {})
+    })
+}
+
+/// Whether the client is logged in
+///
+/// # Safety
+/// `client` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_is_logged_in(client: *const WmClient) -> bool {
+    guard(false, || {
+        client.as_ref().is_some_and(|handle| handle.client.is_logged_in())
+    })
+}
+
+/// Generate the QR code contents to pair this client, written to `out_qr`
+///
+/// # Safety
+/// `client` must be a live handle, `out_qr` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_generate_qr(client: *mut WmClient, out_qr: *mut *mut c_char) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let Some(handle) = borrow_client(client) else {
+            return WmStatus::InvalidArgument;
+        };
+        run(handle, handle.client.generate_qr(), |qr| give_string(out_qr, qr))
+    })
+}
+
+/// Send a text message, writing the message ID to `out_message_id` if it is not null
+///
+/// # Safety
+/// `client` must be a live handle, `to` and `text` NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_send_text(
+    client: *mut WmClient,
+    to: *const c_char,
+    text: *const c_char,
+    out_message_id: *mut *mut c_char,
+) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let (Some(handle), Some(to), Some(text)) = (borrow_client(client), borrow_jid(to), borrow_str(text)) else {
+            return WmStatus::InvalidArgument;
+        };
+        run(handle, handle.client.send_text(&to, text.to_string()), |id| give_string(out_message_id, id))
+    })
+}
+
+/// Upload `len` bytes of media and send them; `caption` may be null
+///
+/// # Safety
+/// `client` must be a live handle, `data` must point to `len` readable bytes,
+/// `to`, `filename` and a non-null `caption` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_send_media(
+    client: *mut WmClient,
+    to: *const c_char,
+    data: *const u8,
+    len: usize,
+    filename: *const c_char,
+    caption: *const c_char,
+    out_message_id: *mut *mut c_char,
+) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let (Some(handle), Some(to), Some(filename)) = (borrow_client(client), borrow_jid(to), borrow_str(filename)) else {
+            return WmStatus::InvalidArgument;
+        };
+        if data.is_null() {
+            set_last_error("Unexpected null data");
+            return WmStatus::InvalidArgument;
+        }
+        let caption = if caption.is_null() {
+            None
+        } else {
+            match borrow_str(caption) {
+                Some(caption) => Some(caption.to_string()),
+                None => return WmStatus::InvalidArgument,
+            }
+        };
+        let data = std::slice::from_raw_parts(data, len);
+        run(handle, handle.client.send_media_bytes(&to, data, filename, caption), |id| give_string(out_message_id, id))
+    })
+}
+
+/// Compute the safety number of the chat with `jid`.
+///
+/// The 60 digits are written to `out_digits`, the verification QR code
+/// contents to `out_qr_data`.
+///
+/// # Safety
+/// `client` must be a live handle, `jid` a NUL-terminated string and the
+/// out pointers valid.
+#[no_mangle]
+pub unsafe extern "C" fn wm_client_safety_number(
+    client: *mut WmClient,
+    jid: *const c_char,
+    out_digits: *mut *mut c_char,
+    out_qr_data: *mut WmBuffer,
+) -> WmStatus {
+    guard(WmStatus::Panic, || {
+        let (Some(handle), Some(jid)) = (borrow_client(client), borrow_jid(jid)) else {
+            return WmStatus::InvalidArgument;
+        };
+        run(handle, handle.client.get_safety_number(&jid), |number| {
+            give_string(out_digits, number.digits);
+            if !out_qr_data.is_null() {
+                *out_qr_data = into_buffer(number.qr_data);
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_arguments() {
+        unsafe {
+            assert!(wm_client_new(ptr::null()).is_null());
+            assert!(!wm_last_error().is_null());
+            let text = CString::new("hi").unwrap();
+            assert_eq!(
+                wm_client_send_text(ptr::null_mut(), text.as_ptr(), text.as_ptr(), ptr::null_mut()),
+                WmStatus::InvalidArgument
+            );
+            assert!(!wm_client_is_logged_in(ptr::null()));
+            wm_client_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_strings_and_buffers() {
+        unsafe {
+            let mut out = ptr::null_mut();
+            give_string(&mut out, "3EB0".to_string());
+            assert_eq!(CStr::from_ptr(out).to_str().unwrap(), "3EB0");
+            wm_string_free(out);
+
+            let buffer = into_buffer(vec![1, 2, 3]);
+            assert_eq!(std::slice::from_raw_parts(buffer.data, buffer.len), &[1, 2, 3]);
+            wm_buffer_free(buffer);
+        }
+    }
+
+    #[test]
+    fn test_panics_become_status() {
+        let status = guard(WmStatus::Panic, || -> WmStatus { panic!("boom") });
+        assert_eq!(status, WmStatus::Panic);
+        let message = unsafe { CStr::from_ptr(wm_last_error()) }.to_str().unwrap();
+        assert!(message.contains("boom"));
+        assert_eq!(guard(WmStatus::Panic, || WmStatus::Ok), WmStatus::Ok);
+    }
+
+    #[test]
+    fn test_calls_from_runtime_threads() {
+        let path = CString::new(":memory:").unwrap();
+        let client = unsafe { wm_client_new(path.as_ptr()) };
+        assert!(!client.is_null());
+
+        // As if called from inside an event callback
+        let client_addr = client as usize;
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let status = runtime.block_on(async move { unsafe { wm_client_disconnect(client_addr as *mut WmClient) } });
+        assert_eq!(status, WmStatus::WrongThread);
+        runtime.block_on(async move { unsafe { wm_client_free(client_addr as *mut WmClient) } });
+        assert!(runtime.block_on(async { unsafe { wm_client_new(path.as_ptr()) } }).is_null());
+
+        // Still alive, freed from a plain thread
+        assert_eq!(unsafe { wm_client_disconnect(client) }, WmStatus::Ok);
+        unsafe { wm_client_free(client) };
+    }
+
+    #[test]
+    fn test_event_json() {
+        let json = serde_json::to_value(Event::Disconnected { reason: "bye".to_string() }).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "disconnected", "data": {"reason": "bye"}}));
+        assert_eq!(serde_json::to_value(Event::Connected).unwrap(), serde_json::json!({"type": "connected"}));
+    }
+}
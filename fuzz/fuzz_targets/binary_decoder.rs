@@ -0,0 +1,12 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use whatsmeow::binary::{decode_frame, BinaryDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    let owned = BinaryDecoder::new(data).decode().ok();
+    let frame = Bytes::copy_from_slice(data);
+    let borrowed = decode_frame(&frame).ok().map(|node| node.into_owned());
+    assert_eq!(owned, borrowed);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use whatsmeow::types::JID;
+
+fuzz_target!(|data: &str| {
+    if let Ok(jid) = data.parse::<JID>() {
+        // Whatever we accept must survive formatting and parsing again
+        let reparsed: JID = jid.to_string().parse().expect("formatted JID parses");
+        assert_eq!(reparsed.to_string(), jid.to_string());
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use whatsmeow::msg_transport::{self, ENC_VERSION_LEGACY, ENC_VERSION_TRANSPORT};
+use whatsmeow::proto::wa_e2e;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = wa_e2e::Message::decode(data);
+    for version in [ENC_VERSION_TRANSPORT, ENC_VERSION_LEGACY] {
+        if let Ok(decoded) = msg_transport::open(version, data) {
+            let _ = decoded.message();
+        }
+    }
+});
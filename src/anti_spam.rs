@@ -0,0 +1,219 @@
+/// Anti-spam guard for automated senders
+///
+/// Notification bots are the accounts most likely to be banned: they send
+/// bursts of near identical messages at machine speed. The optional
+/// [`AntiSpamGuard`] sits in front of message sending and
+///
+/// * rejects a payload identical to one sent to the same chat within
+///   [`AntiSpamConfig::dedup_window`],
+/// * caps the number of messages per recipient in any rolling hour, and
+/// * returns a random delay from a configured range to wait before sending,
+///   so messages do not leave at a fixed rhythm.
+///
+/// Rejections and delays are reported through [`crate::metrics`].
+
+use crate::{
+    error::{Error, Result},
+    metrics,
+    types::JID,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Limits enforced by the [`AntiSpamGuard`]
+#[derive(Debug, Clone)]
+pub struct AntiSpamConfig {
+    /// Identical payloads to the same chat within this window are rejected
+    pub dedup_window: Duration,
+    /// Messages to one recipient in any rolling hour, 0 disables the cap
+    pub max_per_recipient_per_hour: u32,
+    /// Shortest random pause before a message
+    pub min_delay: Duration,
+    /// Longest random pause before a message
+    pub max_delay: Duration,
+}
+
+impl Default for AntiSpamConfig {
+    fn default() -> Self {
+        Self {
+            dedup_window: Duration::from_secs(10 * 60),
+            max_per_recipient_per_hour: 60,
+            min_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        }
+    }
+}
+
+/// Counters of the [`AntiSpamGuard`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AntiSpamStats {
+    /// Messages let through
+    pub allowed: u64,
+    /// Messages rejected as duplicates
+    pub duplicates: u64,
+    /// Messages rejected by the hourly cap
+    pub capped: u64,
+    /// Recipients with recent sends being tracked
+    pub tracked_recipients: usize,
+}
+
+#[derive(Default)]
+struct RecipientHistory {
+    /// When messages were sent in the last hour, oldest first
+    sent: VecDeque<Instant>,
+    /// Payload hashes sent within the dedup window, oldest first
+    payloads: VecDeque<(u64, Instant)>,
+}
+
+impl RecipientHistory {
+    fn prune(&mut self, now: Instant, dedup_window: Duration) {
+        while self.sent.front().is_some_and(|sent| now.duration_since(*sent) >= HOUR) {
+            self.sent.pop_front();
+        }
+        while self.payloads.front().is_some_and(|(_, sent)| now.duration_since(*sent) >= dedup_window) {
+            self.payloads.pop_front();
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    recipients: HashMap<String, RecipientHistory>,
+    stats: AntiSpamStats,
+}
+
+/// Deduplication, per-recipient caps and send jitter
+pub struct AntiSpamGuard {
+    config: AntiSpamConfig,
+    state: Mutex<State>,
+}
+
+impl AntiSpamGuard {
+    pub fn new(config: AntiSpamConfig) -> Self {
+        Self { config, state: Mutex::new(State::default()) }
+    }
+
+    pub fn config(&self) -> &AntiSpamConfig {
+        &self.config
+    }
+
+    /// Check a message about to be sent to `to` and record it.
+    ///
+    /// Returns how long to wait before sending. Duplicates fail with
+    /// [`Error::SendBlocked`], messages over the hourly cap with
+    /// [`Error::RateLimited`] carrying the time until a slot frees up.
+    pub fn check(&self, to: &JID, payload: &[u8]) -> Result<Duration> {
+        self.check_at(to, payload, Instant::now())?;
+        let delay = self.random_delay();
+        metrics::anti_spam_delay(delay);
+        Ok(delay)
+    }
+
+    fn check_at(&self, to: &JID, payload: &[u8], now: Instant) -> Result<()> {
+        let hash = payload_hash(payload);
+        let mut state = self.state.lock().unwrap();
+        let history = state.recipients.entry(to.to_non_ad().to_string()).or_default();
+        history.prune(now, self.config.dedup_window);
+
+        if history.payloads.iter().any(|(sent, _)| *sent == hash) {
+            state.stats.duplicates += 1;
+            metrics::anti_spam_rejected("duplicate");
+            return Err(Error::SendBlocked(format!("identical message already sent to {}", to)));
+        }
+        let cap = self.config.max_per_recipient_per_hour as usize;
+        if cap > 0 && history.sent.len() >= cap {
+            let retry_after = history.sent.front().map(|oldest| HOUR.saturating_sub(now.duration_since(*oldest)));
+            state.stats.capped += 1;
+            metrics::anti_spam_rejected("hourly_cap");
+            return Err(Error::RateLimited { retry_after });
+        }
+
+        history.sent.push_back(now);
+        history.payloads.push_back((hash, now));
+        state.stats.allowed += 1;
+        Ok(())
+    }
+
+    /// Forget recipients without recent sends
+    pub fn cleanup(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.recipients.retain(|_, history| {
+            history.prune(now, self.config.dedup_window);
+            !history.sent.is_empty() || !history.payloads.is_empty()
+        });
+    }
+
+    pub fn stats(&self) -> AntiSpamStats {
+        let state = self.state.lock().unwrap();
+        AntiSpamStats { tracked_recipients: state.recipients.len(), ..state.stats.clone() }
+    }
+
+    fn random_delay(&self) -> Duration {
+        let (min, max) = (self.config.min_delay, self.config.max_delay);
+        if max <= min {
+            return min;
+        }
+        rand::thread_rng().gen_range(min..=max)
+    }
+}
+
+fn payload_hash(payload: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(cap: u32) -> AntiSpamGuard {
+        AntiSpamGuard::new(AntiSpamConfig {
+            dedup_window: Duration::from_secs(60),
+            max_per_recipient_per_hour: cap,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(20),
+        })
+    }
+
+    #[test]
+    fn test_rejects_duplicates_within_window() {
+        let guard = guard(0);
+        let to = JID::new_user("1234");
+        let now = Instant::now();
+
+        assert!(guard.check_at(&to, b"hello", now).is_ok());
+        assert!(matches!(guard.check_at(&to.with_device(2), b"hello", now), Err(Error::SendBlocked(_))));
+        assert!(guard.check_at(&JID::new_user("5678"), b"hello", now).is_ok());
+        assert!(guard.check_at(&to, b"hello", now + Duration::from_secs(61)).is_ok());
+
+        let delay = guard.check(&to, b"other").unwrap();
+        assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+        assert_eq!(guard.stats(), AntiSpamStats { allowed: 4, duplicates: 1, capped: 0, tracked_recipients: 2 });
+    }
+
+    #[test]
+    fn test_hourly_cap_per_recipient() {
+        let guard = guard(2);
+        let to = JID::new_user("1234");
+        let now = Instant::now();
+
+        assert!(guard.check_at(&to, b"1", now).is_ok());
+        assert!(guard.check_at(&to, b"2", now + Duration::from_secs(600)).is_ok());
+        match guard.check_at(&to, b"3", now + Duration::from_secs(1200)) {
+            Err(Error::RateLimited { retry_after }) => assert_eq!(retry_after, Some(Duration::from_secs(2400))),
+            other => panic!("expected hourly cap, got {:?}", other),
+        }
+        // The first message left the rolling hour
+        assert!(guard.check_at(&to, b"3", now + HOUR).is_ok());
+        assert_eq!(guard.stats().capped, 1);
+    }
+}
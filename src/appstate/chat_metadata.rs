@@ -10,7 +10,7 @@
 use crate::{
     appstate::{
         AppStateSync, AppStateEvent, AppStateOperation, AppStateDataType, 
-        AppStateKey, SyncContext, SyncStatus, SyncConflict, AppStateVersion
+        AppStateKey, SyncContext, SyncStatus, SyncConflict, AppStateVersion, ConflictResolution
     },
     error::{Error, Result},
     types::JID,
@@ -46,6 +46,9 @@ pub struct ChatMetadata {
     pub last_message_timestamp: Option<SystemTime>,
     /// Unread message count
     pub unread_count: u32,
+    /// Chat was explicitly marked as unread
+    #[serde(default)]
+    pub marked_unread: bool,
     /// Chat marked as read timestamp
     pub last_read_timestamp: Option<SystemTime>,
     /// Chat display name override
@@ -185,6 +188,7 @@ impl ChatMetadata {
             theme: None,
             last_message_timestamp: None,
             unread_count: 0,
+            marked_unread: false,
             last_read_timestamp: None,
             display_name_override: None,
             labels: Vec::new(),
@@ -206,7 +210,7 @@ impl ChatMetadata {
 
     /// Check if chat has unread messages
     pub fn has_unread(&self) -> bool {
-        self.unread_count > 0
+        self.unread_count > 0 || self.marked_unread
     }
 
     /// Get mute duration in seconds
@@ -226,6 +230,7 @@ impl ChatMetadata {
     /// Mark chat as read
     pub fn mark_as_read(&mut self) {
         self.unread_count = 0;
+        self.marked_unread = false;
         self.last_read_timestamp = Some(SystemTime::now());
         self.last_updated = SystemTime::now();
         self.version.timestamp = SystemTime::now();
@@ -480,6 +485,35 @@ impl ChatMetadataSync {
         Ok(())
     }
 
+    /// Mark a chat as read, or as unread
+    pub async fn mark_chat_read(&self, jid: &JID, read: bool) -> Result<()> {
+        let mut storage = self.chat_metadata.write().await;
+        if let Some(metadata) = storage.get_mut(jid) {
+            if read {
+                metadata.mark_as_read();
+            } else {
+                metadata.marked_unread = true;
+            }
+            metadata.last_updated = SystemTime::now();
+            metadata.version.timestamp = SystemTime::now();
+            metadata.version.hash = self.calculate_metadata_hash(metadata);
+        }
+        Ok(())
+    }
+
+    /// Reset a chat whose messages were cleared
+    pub async fn clear_chat(&self, jid: &JID) -> Result<()> {
+        let mut storage = self.chat_metadata.write().await;
+        if let Some(metadata) = storage.get_mut(jid) {
+            metadata.unread_count = 0;
+            metadata.marked_unread = false;
+            metadata.last_updated = SystemTime::now();
+            metadata.version.timestamp = SystemTime::now();
+            metadata.version.hash = self.calculate_metadata_hash(metadata);
+        }
+        Ok(())
+    }
+
     /// Delete chat metadata
     pub async fn delete_chat_metadata(&self, jid: &JID) -> Result<Option<ChatMetadata>> {
         let mut storage = self.chat_metadata.write().await;
@@ -547,6 +581,7 @@ impl ChatMetadataSync {
         metadata.pinned.hash(&mut hasher);
         metadata.muted_until.hash(&mut hasher);
         metadata.unread_count.hash(&mut hasher);
+        metadata.marked_unread.hash(&mut hasher);
         metadata.labels.hash(&mut hasher);
 
         format!("{:x}", hasher.finish())
@@ -580,6 +615,7 @@ impl ChatMetadataSync {
         if remote.last_message_timestamp > local.last_message_timestamp {
             merged.last_message_timestamp = remote.last_message_timestamp;
             merged.unread_count = remote.unread_count;
+            merged.marked_unread = remote.marked_unread;
         }
 
         if remote.last_read_timestamp > local.last_read_timestamp {
@@ -626,15 +662,24 @@ impl AppStateSync for ChatMetadataSync {
                                 // Local version is newer, create conflict
                                 let conflict = SyncConflict {
                                     key: key.clone(),
-                                    local_version: existing.version,
-                                    remote_version: metadata.version,
+                                    local_version: existing.version.clone(),
+                                    remote_version: metadata.version.clone(),
                                     local_data: Some(serde_json::to_vec(&existing).unwrap()),
                                     remote_data: Some(data),
                                     detected_at: SystemTime::now(),
                                 };
-                                ctx.add_conflict(conflict).await;
-                                ctx.update_sync_status(key, SyncStatus::Conflict).await;
-                                continue;
+                                match ctx.add_conflict(conflict).await {
+                                    Some(ConflictResolution::UseRemote) => {}
+                                    Some(ConflictResolution::KeepLocal) => {
+                                        // Our version is sent on the next sync
+                                        ctx.update_sync_status(key, SyncStatus::NotSynced).await;
+                                        continue;
+                                    }
+                                    None => {
+                                        ctx.update_sync_status(key, SyncStatus::Conflict).await;
+                                        continue;
+                                    }
+                                }
                             }
                         }
 
@@ -825,6 +870,28 @@ mod tests {
         assert!(results[0].pinned);
     }
 
+    #[tokio::test]
+    async fn test_mark_unread_and_clear() {
+        let sync = ChatMetadataSync::new();
+
+        let jid = JID::new("test".to_string(), "s.whatsapp.net".to_string());
+        sync.update_chat_metadata(ChatMetadata::new(jid.clone())).await.unwrap();
+
+        sync.mark_chat_read(&jid, false).await.unwrap();
+        let metadata = sync.get_chat_metadata(&jid).await.unwrap();
+        assert_eq!(metadata.unread_count, 0);
+        assert!(metadata.has_unread());
+
+        sync.mark_chat_read(&jid, true).await.unwrap();
+        assert!(!sync.get_chat_metadata(&jid).await.unwrap().has_unread());
+
+        let mut metadata = sync.get_chat_metadata(&jid).await.unwrap();
+        metadata.unread_count = 4;
+        sync.update_chat_metadata(metadata).await.unwrap();
+        sync.clear_chat(&jid).await.unwrap();
+        assert!(!sync.get_chat_metadata(&jid).await.unwrap().has_unread());
+    }
+
     #[tokio::test]
     async fn test_mute_functionality() {
         let sync = ChatMetadataSync::new();
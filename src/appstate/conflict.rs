@@ -0,0 +1,136 @@
+/// App state conflict resolution
+///
+/// A conflict arises when a mutation received from another device changes
+/// an item we modified locally since the last sync. What happens then is
+/// decided per [`AppStateDataType`](super::AppStateDataType) by a
+/// [`ConflictStrategy`]: one of the built-in rules, a [`ConflictResolver`]
+/// supplied by the application, or [`ConflictStrategy::Manual`], which keeps
+/// the conflict pending until [`SyncContext::resolve_conflict`](super::SyncContext::resolve_conflict)
+/// is called and reports it as [`Event::AppStateConflict`](crate::types::events::Event::AppStateConflict).
+
+use super::SyncConflict;
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+
+/// Which side of a conflict wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictResolution {
+    /// Keep our version and send it to the other devices on the next sync
+    KeepLocal,
+    /// Apply the version received from the other device
+    UseRemote,
+}
+
+/// Decides conflicts of app state items
+pub trait ConflictResolver: Send + Sync {
+    /// Pick the winning side, `None` leaves the conflict to the user
+    fn resolve(&self, conflict: &SyncConflict) -> Option<ConflictResolution>;
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(&SyncConflict) -> Option<ConflictResolution> + Send + Sync,
+{
+    fn resolve(&self, conflict: &SyncConflict) -> Option<ConflictResolution> {
+        self(conflict)
+    }
+}
+
+/// How the conflicts of a data type are resolved
+#[derive(Clone, Default)]
+pub enum ConflictStrategy {
+    /// The newer version wins, the remote one if both have the same timestamp
+    #[default]
+    LastWriterWins,
+    /// Our version always wins
+    PreferLocal,
+    /// The version from the other device always wins
+    PreferRemote,
+    /// Ask application code
+    Custom(Arc<dyn ConflictResolver>),
+    /// Keep the conflict pending and surface it as an event
+    Manual,
+}
+
+impl ConflictStrategy {
+    /// Resolve conflicts with a closure, see [`ConflictResolver`]
+    pub fn custom<F>(resolver: F) -> Self
+    where
+        F: Fn(&SyncConflict) -> Option<ConflictResolution> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(resolver))
+    }
+}
+
+impl ConflictResolver for ConflictStrategy {
+    fn resolve(&self, conflict: &SyncConflict) -> Option<ConflictResolution> {
+        match self {
+            Self::LastWriterWins => {
+                if conflict.local_version.timestamp > conflict.remote_version.timestamp {
+                    Some(ConflictResolution::KeepLocal)
+                } else {
+                    Some(ConflictResolution::UseRemote)
+                }
+            }
+            Self::PreferLocal => Some(ConflictResolution::KeepLocal),
+            Self::PreferRemote => Some(ConflictResolution::UseRemote),
+            Self::Custom(resolver) => resolver.resolve(conflict),
+            Self::Manual => None,
+        }
+    }
+}
+
+impl fmt::Debug for ConflictStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LastWriterWins => f.write_str("LastWriterWins"),
+            Self::PreferLocal => f.write_str("PreferLocal"),
+            Self::PreferRemote => f.write_str("PreferRemote"),
+            Self::Custom(_) => f.write_str("Custom"),
+            Self::Manual => f.write_str("Manual"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appstate::{AppStateKey, AppStateVersion};
+    use std::time::{Duration, SystemTime};
+
+    fn conflict(local_age: u64, remote_age: u64) -> SyncConflict {
+        let now = SystemTime::now();
+        let version = |age| AppStateVersion {
+            timestamp: now - Duration::from_secs(age),
+            hash: String::new(),
+            device_id: String::new(),
+        };
+        SyncConflict {
+            key: AppStateKey::settings("theme"),
+            local_version: version(local_age),
+            remote_version: version(remote_age),
+            local_data: None,
+            remote_data: None,
+            detected_at: now,
+        }
+    }
+
+    #[test]
+    fn test_conflict_strategies() {
+        let local_newer = conflict(10, 20);
+        let remote_newer = conflict(20, 10);
+
+        let strategy = ConflictStrategy::LastWriterWins;
+        assert_eq!(strategy.resolve(&local_newer), Some(ConflictResolution::KeepLocal));
+        assert_eq!(strategy.resolve(&remote_newer), Some(ConflictResolution::UseRemote));
+
+        assert_eq!(ConflictStrategy::PreferLocal.resolve(&remote_newer), Some(ConflictResolution::KeepLocal));
+        assert_eq!(ConflictStrategy::PreferRemote.resolve(&local_newer), Some(ConflictResolution::UseRemote));
+        assert_eq!(ConflictStrategy::Manual.resolve(&local_newer), None);
+
+        let strategy = ConflictStrategy::custom(|conflict: &SyncConflict| {
+            (conflict.key.identifier == "theme").then_some(ConflictResolution::KeepLocal)
+        });
+        assert_eq!(strategy.resolve(&remote_newer), Some(ConflictResolution::KeepLocal));
+    }
+}
@@ -9,7 +9,7 @@
 use crate::{
     appstate::{
         AppStateSync, AppStateEvent, AppStateOperation, AppStateDataType, 
-        AppStateKey, SyncContext, SyncStatus, SyncConflict, AppStateVersion
+        AppStateKey, SyncContext, SyncStatus, SyncConflict, AppStateVersion, ConflictResolution
     },
     error::{Error, Result},
     types::JID,
@@ -398,15 +398,24 @@ impl AppStateSync for ContactSync {
                                 // Local version is newer, create conflict
                                 let conflict = SyncConflict {
                                     key: key.clone(),
-                                    local_version: existing.version,
-                                    remote_version: contact.version,
+                                    local_version: existing.version.clone(),
+                                    remote_version: contact.version.clone(),
                                     local_data: Some(serde_json::to_vec(&existing).unwrap()),
                                     remote_data: Some(data),
                                     detected_at: SystemTime::now(),
                                 };
-                                ctx.add_conflict(conflict).await;
-                                ctx.update_sync_status(key, SyncStatus::Conflict).await;
-                                continue;
+                                match ctx.add_conflict(conflict).await {
+                                    Some(ConflictResolution::UseRemote) => {}
+                                    Some(ConflictResolution::KeepLocal) => {
+                                        // Our version is sent on the next sync
+                                        ctx.update_sync_status(key, SyncStatus::NotSynced).await;
+                                        continue;
+                                    }
+                                    None => {
+                                        ctx.update_sync_status(key, SyncStatus::Conflict).await;
+                                        continue;
+                                    }
+                                }
                             }
                         }
 
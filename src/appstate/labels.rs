@@ -0,0 +1,188 @@
+/// Business labels
+///
+/// WhatsApp Business accounts tag chats and messages with labels. Labels are
+/// app state: a `label_edit` mutation creates, renames or deletes a label,
+/// and `label_jid` / `label_message` mutations attach it to a chat or a
+/// message. Every device applies the mutations it receives to its own copy
+/// of the labels kept here.
+
+use super::patch::{INDEX_LABEL_ASSOCIATION_CHAT, INDEX_LABEL_ASSOCIATION_MESSAGE, INDEX_LABEL_EDIT};
+use crate::{proto::wa_sync_action::SyncActionData, types::JID};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Number of colors the official clients offer for labels
+pub const LABEL_COLORS: i32 = 20;
+
+/// A label
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    /// Numeric identifier, unique per account
+    pub id: String,
+    pub name: String,
+    /// Index into the palette of the official clients
+    pub color: i32,
+    /// Set on the labels every business account starts with, e.g. "New customer"
+    pub predefined_id: Option<i32>,
+    pub order_index: Option<i32>,
+}
+
+/// A change to the labels, made by us or on another device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LabelChange {
+    /// A label was created or edited
+    Edited(Label),
+    Deleted { label_id: String },
+    /// A label was attached to or removed from a chat
+    Chat { label_id: String, chat: JID, labeled: bool },
+    /// A label was attached to or removed from a message
+    Message { label_id: String, chat: JID, message_id: String, labeled: bool },
+}
+
+/// Our labels and what they are attached to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    labels: BTreeMap<String, Label>,
+    chats: BTreeSet<(String, JID)>,
+    messages: BTreeSet<(String, JID, String)>,
+}
+
+impl Labels {
+    /// All labels, ordered by id
+    pub fn list(&self) -> Vec<Label> {
+        self.labels.values().cloned().collect()
+    }
+
+    pub fn get(&self, label_id: &str) -> Option<&Label> {
+        self.labels.get(label_id)
+    }
+
+    /// Identifier for a new label, one past the highest numeric id in use
+    pub fn next_id(&self) -> String {
+        let highest = self.labels.keys().filter_map(|id| id.parse::<u64>().ok()).max().unwrap_or(0);
+        (highest + 1).to_string()
+    }
+
+    /// Labels attached to a chat
+    pub fn chat_labels(&self, chat: &JID) -> Vec<&Label> {
+        self.chats.iter()
+            .filter(|(_, labeled)| labeled == chat)
+            .filter_map(|(label_id, _)| self.labels.get(label_id))
+            .collect()
+    }
+
+    /// Chats a label is attached to
+    pub fn labeled_chats(&self, label_id: &str) -> Vec<JID> {
+        self.chats.iter()
+            .filter(|(id, _)| id == label_id)
+            .map(|(_, chat)| chat.clone())
+            .collect()
+    }
+
+    /// Labels attached to a message
+    pub fn message_labels(&self, chat: &JID, message_id: &str) -> Vec<&Label> {
+        self.messages.iter()
+            .filter(|(_, labeled_chat, id)| labeled_chat == chat && id == message_id)
+            .filter_map(|(label_id, _, _)| self.labels.get(label_id))
+            .collect()
+    }
+
+    /// Apply a change, returning whether it changed anything
+    pub fn apply(&mut self, change: &LabelChange) -> bool {
+        match change {
+            LabelChange::Edited(label) => self.labels.insert(label.id.clone(), label.clone()).as_ref() != Some(label),
+            LabelChange::Deleted { label_id } => {
+                self.chats.retain(|(id, _)| id != label_id);
+                self.messages.retain(|(id, _, _)| id != label_id);
+                self.labels.remove(label_id).is_some()
+            }
+            LabelChange::Chat { label_id, chat, labeled: true } => self.chats.insert((label_id.clone(), chat.clone())),
+            LabelChange::Chat { label_id, chat, labeled: false } => self.chats.remove(&(label_id.clone(), chat.clone())),
+            LabelChange::Message { label_id, chat, message_id, labeled: true } => {
+                self.messages.insert((label_id.clone(), chat.clone(), message_id.clone()))
+            }
+            LabelChange::Message { label_id, chat, message_id, labeled: false } => {
+                self.messages.remove(&(label_id.clone(), chat.clone(), message_id.clone()))
+            }
+        }
+    }
+}
+
+/// Read a decoded label mutation, `None` if the mutation is about something else
+pub fn parse_label_mutation(data: &SyncActionData) -> Option<LabelChange> {
+    let index: Vec<String> = serde_json::from_slice(data.index()).ok()?;
+    let value = data.value.as_ref()?;
+    match index.as_slice() {
+        [name, label_id] if name == INDEX_LABEL_EDIT => {
+            let edit = value.label_edit_action.as_ref()?;
+            if edit.deleted.unwrap_or(false) {
+                return Some(LabelChange::Deleted { label_id: label_id.clone() });
+            }
+            Some(LabelChange::Edited(Label {
+                id: label_id.clone(),
+                name: edit.name.clone().unwrap_or_default(),
+                color: edit.color.unwrap_or(0),
+                predefined_id: edit.predefined_id,
+                order_index: edit.order_index,
+            }))
+        }
+        [name, label_id, chat] if name == INDEX_LABEL_ASSOCIATION_CHAT => Some(LabelChange::Chat {
+            label_id: label_id.clone(),
+            chat: chat.parse().ok()?,
+            labeled: value.label_association_action.as_ref()?.labeled.unwrap_or(false),
+        }),
+        [name, label_id, chat, message_id, ..] if name == INDEX_LABEL_ASSOCIATION_MESSAGE => Some(LabelChange::Message {
+            label_id: label_id.clone(),
+            chat: chat.parse().ok()?,
+            message_id: message_id.clone(),
+            labeled: value.label_association_action.as_ref()?.labeled.unwrap_or(false),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appstate::patch::{decode_mutation_value, encode_patch, ExpandedAppStateKeys, HashState, PatchInfo};
+    use crate::proto::wa_server_sync::syncd_mutation::SyncdOperation;
+
+    fn round_trip(info: &PatchInfo) -> LabelChange {
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let encoded = encode_patch(info, &[0, 0, 0, 1], &keys, &HashState::default()).unwrap();
+        let record = encoded.patch.mutations[0].record.as_ref().unwrap();
+        parse_label_mutation(&decode_mutation_value(SyncdOperation::Set, record, &keys).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_label_mutations() {
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let mut labels = Labels::default();
+        assert_eq!(labels.next_id(), "1");
+
+        let label = Label { id: labels.next_id(), name: "Paid".to_string(), color: 3, predefined_id: None, order_index: None };
+        let edit = round_trip(&PatchInfo::label_edit(&label, false));
+        assert_eq!(edit, LabelChange::Edited(label.clone()));
+        assert!(labels.apply(&edit));
+        assert!(!labels.apply(&edit));
+        assert_eq!(labels.next_id(), "2");
+
+        let attach = round_trip(&PatchInfo::label_chat(&label.id, &chat, true));
+        assert!(labels.apply(&attach));
+        assert_eq!(labels.chat_labels(&chat), vec![&label]);
+        assert_eq!(labels.labeled_chats("1"), vec![chat.clone()]);
+
+        let message = round_trip(&PatchInfo::label_message(&label.id, &chat, "MSG1", true));
+        assert!(labels.apply(&message));
+        assert_eq!(labels.message_labels(&chat, "MSG1").len(), 1);
+
+        // Deleting the label detaches it everywhere
+        let delete = round_trip(&PatchInfo::label_edit(&label, true));
+        assert_eq!(delete, LabelChange::Deleted { label_id: "1".to_string() });
+        assert!(labels.apply(&delete));
+        assert!(labels.list().is_empty());
+        assert!(labels.chat_labels(&chat).is_empty());
+        assert!(labels.message_labels(&chat, "MSG1").is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+/// Summation based hash used for app state integrity checks
+///
+/// An LT-Hash is a homomorphic hash over a set: every element is expanded
+/// with HKDF and added to (or subtracted from) the state as a vector of
+/// little-endian 16 bit integers with wrap-around. Adding and removing the
+/// value MACs of mutations in any order leads to the same state, so both
+/// sides of a sync can compare snapshots without replaying history.
+
+use crate::{error::Result, util::crypto::hkdf_expand};
+
+/// LT-Hash with the given HKDF info and state size in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct LTHash {
+    pub hkdf_info: &'static str,
+    pub hkdf_size: usize,
+}
+
+/// The LT-Hash of app state patches
+pub const WA_PATCH_INTEGRITY: LTHash = LTHash { hkdf_info: "WhatsApp Patch Integrity", hkdf_size: 128 };
+
+impl LTHash {
+    /// Remove `subtract` from and add `add` to `base` in place
+    pub fn subtract_then_add(&self, base: &mut [u8], subtract: &[Vec<u8>], add: &[Vec<u8>]) -> Result<()> {
+        for item in subtract {
+            self.apply(base, item, true)?;
+        }
+        for item in add {
+            self.apply(base, item, false)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, base: &mut [u8], item: &[u8], subtract: bool) -> Result<()> {
+        let expanded = hkdf_expand(item, self.hkdf_info.as_bytes(), self.hkdf_size)?;
+        for (chunk, other) in base.chunks_exact_mut(2).zip(expanded.chunks_exact(2)) {
+            let x = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let y = u16::from_le_bytes([other[0], other[1]]);
+            let result = if subtract { x.wrapping_sub(y) } else { x.wrapping_add(y) };
+            chunk.copy_from_slice(&result.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_independent() {
+        let a = vec![1u8; 32];
+        let b = vec![2u8; 32];
+
+        let mut first = vec![0u8; 128];
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut first, &[], &[a.clone(), b.clone()]).unwrap();
+        let mut second = vec![0u8; 128];
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut second, &[], &[b.clone()]).unwrap();
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut second, &[], &[a.clone()]).unwrap();
+        assert_eq!(first, second);
+
+        // Removing an element restores the previous state
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut first, &[a], &[]).unwrap();
+        let mut only_b = vec![0u8; 128];
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut only_b, &[], &[b]).unwrap();
+        assert_eq!(first, only_b);
+    }
+}
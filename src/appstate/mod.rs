@@ -12,12 +12,18 @@ pub mod chat_metadata;
 pub mod settings;
 pub mod sync_protocol;
 pub mod state_manager;
+pub mod lthash;
+pub mod patch;
+pub mod labels;
+pub mod conflict;
 
 use crate::{
     error::{Error, Result},
     types::JID,
-    database::Database,
+    database::{sqlite::{SqliteAppStateKeyStore, SqliteAppStateVersionStore}, Database},
+    proto::wa_server_sync::{KeyId, SyncdMutation, SyncdPatch, SyncdSnapshot},
 };
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -31,9 +37,12 @@ pub use chat_metadata::*;
 pub use settings::*;
 pub use sync_protocol::*;
 pub use state_manager::*;
+pub use conflict::{ConflictResolution, ConflictResolver, ConflictStrategy};
+pub use labels::{parse_label_mutation, Label, LabelChange, Labels};
+pub use patch::{parse_star_mutation, EncodedPatch, ExpandedAppStateKeys, HashState, PatchInfo, PatchName, StarMutation};
 
 /// App State data types that can be synchronized
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum AppStateDataType {
     /// Contact information and metadata
     Contacts,
@@ -169,7 +178,7 @@ pub struct AppStateVersion {
 }
 
 /// App State sync context
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct SyncContext {
     /// Database connection
     pub database: Arc<Database>,
@@ -179,8 +188,26 @@ pub struct SyncContext {
     pub last_sync: Arc<RwLock<HashMap<AppStateDataType, SystemTime>>>,
     /// Sync conflicts
     pub conflicts: Arc<RwLock<Vec<SyncConflict>>>,
+    /// Conflict strategy per data type, last-writer-wins if unset
+    pub conflict_strategies: Arc<RwLock<HashMap<AppStateDataType, ConflictStrategy>>>,
+    /// Called with every conflict left to the user
+    conflict_handlers: Arc<RwLock<Vec<ConflictHandler>>>,
+}
+
+impl std::fmt::Debug for SyncContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncContext")
+            .field("sync_status", &self.sync_status)
+            .field("last_sync", &self.last_sync)
+            .field("conflicts", &self.conflicts)
+            .field("conflict_strategies", &self.conflict_strategies)
+            .finish_non_exhaustive()
+    }
 }
 
+/// Callback receiving conflicts of data types with a [`ConflictStrategy::Manual`] strategy
+pub type ConflictHandler = Arc<dyn Fn(SyncConflict) + Send + Sync>;
+
 /// Sync conflict information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncConflict {
@@ -205,6 +232,8 @@ impl SyncContext {
             sync_status: Arc::new(RwLock::new(HashMap::new())),
             last_sync: Arc::new(RwLock::new(HashMap::new())),
             conflicts: Arc::new(RwLock::new(Vec::new())),
+            conflict_strategies: Arc::new(RwLock::new(HashMap::new())),
+            conflict_handlers: Arc::new(RwLock::new(Vec::new())),
         }
     }
 
@@ -232,10 +261,41 @@ impl SyncContext {
         last_sync.get(data_type).cloned()
     }
 
-    /// Add a sync conflict
-    pub async fn add_conflict(&self, conflict: SyncConflict) {
-        let mut conflicts = self.conflicts.write().await;
-        conflicts.push(conflict);
+    /// Set how conflicts of a data type are resolved
+    pub async fn set_conflict_strategy(&self, data_type: AppStateDataType, strategy: ConflictStrategy) {
+        let mut strategies = self.conflict_strategies.write().await;
+        strategies.insert(data_type, strategy);
+    }
+
+    /// Get the conflict strategy of a data type
+    pub async fn get_conflict_strategy(&self, data_type: &AppStateDataType) -> ConflictStrategy {
+        let strategies = self.conflict_strategies.read().await;
+        strategies.get(data_type).cloned().unwrap_or_default()
+    }
+
+    /// Register a handler for conflicts left to the user
+    pub async fn add_conflict_handler(&self, handler: ConflictHandler) {
+        let mut handlers = self.conflict_handlers.write().await;
+        handlers.push(handler);
+    }
+
+    /// Add a sync conflict and resolve it with the strategy of its data type.
+    ///
+    /// Returns `None` if the strategy left the conflict to the user; it is
+    /// then kept until [`resolve_conflict`](Self::resolve_conflict) and
+    /// passed to the conflict handlers.
+    pub async fn add_conflict(&self, conflict: SyncConflict) -> Option<ConflictResolution> {
+        let strategy = self.get_conflict_strategy(&conflict.key.data_type).await;
+        if let Some(resolution) = strategy.resolve(&conflict) {
+            return Some(resolution);
+        }
+
+        self.conflicts.write().await.push(conflict.clone());
+        let handlers = self.conflict_handlers.read().await;
+        for handler in handlers.iter() {
+            handler(conflict.clone());
+        }
+        None
     }
 
     /// Get all sync conflicts
@@ -244,22 +304,72 @@ impl SyncContext {
         conflicts.clone()
     }
 
-    /// Resolve conflict by key
-    pub async fn resolve_conflict(&self, key: &AppStateKey, use_remote: bool) -> Result<()> {
+    fn versions(&self) -> SqliteAppStateVersionStore {
+        SqliteAppStateVersionStore::new(self.database.pool().clone())
+    }
+
+    /// Version and LT-Hash of a collection, empty if it was never synced
+    pub async fn hash_state(&self, name: PatchName) -> Result<HashState> {
+        Ok(self.versions().get(name).await?.unwrap_or_default())
+    }
+
+    /// Persist the state of a collection after applying or sending a patch
+    pub async fn save_hash_state(&self, name: PatchName, state: &HashState) -> Result<()> {
+        self.versions().put(name, state).await
+    }
+
+    /// Forget the state of a collection, so it is fetched from a snapshot next
+    pub async fn reset_hash_state(&self, name: PatchName) -> Result<()> {
+        self.versions().delete(name).await
+    }
+
+    /// Verify a downloaded snapshot of a collection and store its state
+    pub async fn apply_snapshot_state(&self, name: PatchName, blob: &[u8]) -> Result<HashState> {
+        let snapshot = SyncdSnapshot::decode(blob)?;
+        let keys = self.expanded_keys(name, snapshot.key_id.as_ref()).await?;
+
+        let state = HashState::from_snapshot(&snapshot)?;
+        if snapshot.mac.as_deref() != Some(state.snapshot_mac(name, &keys.snapshot_mac).as_slice()) {
+            return Err(Error::Crypto(format!("App state snapshot MAC mismatch in {}", name)));
+        }
+        self.save_hash_state(name, &state).await?;
+        Ok(state)
+    }
+
+    /// Verify a patch fetched from the server on top of `state` and store
+    /// the new state of its collection
+    pub async fn apply_patch_state(&self, name: PatchName, state: &HashState, patch: &SyncdPatch, mutations: &[SyncdMutation]) -> Result<HashState> {
+        let keys = self.expanded_keys(name, patch.key_id.as_ref()).await?;
+        let state = patch::apply_patch(name, state, patch, mutations, &keys)?;
+        self.save_hash_state(name, &state).await?;
+        Ok(state)
+    }
+
+    async fn expanded_keys(&self, name: PatchName, key_id: Option<&KeyId>) -> Result<ExpandedAppStateKeys> {
+        let key_id = key_id.and_then(|key_id| key_id.id.as_deref()).unwrap_or_default();
+        let key = SqliteAppStateKeyStore::new(self.database.pool().clone())
+            .get_key(key_id).await?
+            .ok_or_else(|| Error::Protocol(format!("Missing app state sync key for {}", name)))?;
+        ExpandedAppStateKeys::expand(&key.key_data.key_data)
+    }
+
+    /// Resolve a pending conflict by key, returning it so the winning data
+    /// can be applied
+    pub async fn resolve_conflict(&self, key: &AppStateKey, resolution: ConflictResolution) -> Result<Option<SyncConflict>> {
         let mut conflicts = self.conflicts.write().await;
-        if let Some(pos) = conflicts.iter().position(|c| &c.key == key) {
-            let conflict = conflicts.remove(pos);
-            
-            // Apply resolution
-            if use_remote {
-                // Use remote version
-                self.update_sync_status(key.clone(), SyncStatus::Synced).await;
-            } else {
-                // Keep local version, mark as needing sync
-                self.update_sync_status(key.clone(), SyncStatus::NotSynced).await;
-            }
+        let Some(pos) = conflicts.iter().position(|c| &c.key == key) else {
+            return Ok(None);
+        };
+        let conflict = conflicts.remove(pos);
+        drop(conflicts);
+
+        match resolution {
+            // Use remote version
+            ConflictResolution::UseRemote => self.update_sync_status(key.clone(), SyncStatus::Synced).await,
+            // Keep local version, mark as needing sync
+            ConflictResolution::KeepLocal => self.update_sync_status(key.clone(), SyncStatus::NotSynced).await,
         }
-        Ok(())
+        Ok(Some(conflict))
     }
 }
 
@@ -321,4 +431,46 @@ mod tests {
         ctx.update_sync_status(key.clone(), SyncStatus::Syncing).await;
         assert_eq!(ctx.get_sync_status(&key).await, SyncStatus::Syncing);
     }
+
+    #[tokio::test]
+    async fn test_sync_context_hash_state() {
+        use crate::database::DatabaseConfig;
+        use crate::proto::wa_server_sync::{KeyId, SyncdVersion};
+        use crate::types::{AppStateSyncKey, AppStateSyncKeyData};
+
+        let db = Arc::new(Database::new(DatabaseConfig::in_memory()).await.unwrap());
+        let ctx = SyncContext::new(Arc::clone(&db));
+        assert_eq!(ctx.hash_state(PatchName::Regular).await.unwrap(), HashState::default());
+
+        let key_id = vec![0, 0, 0, 1];
+        SqliteAppStateKeyStore::new(db.pool().clone()).put_key(&AppStateSyncKey {
+            key_id: key_id.clone(),
+            key_data: AppStateSyncKeyData { key_data: vec![7; 32], fingerprint: Vec::new(), timestamp: SystemTime::now() },
+        }).await.unwrap();
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let encoded = patch::encode_patch(&PatchInfo::pin(&chat, true), &key_id, &keys, &HashState::default()).unwrap();
+
+        let mut snapshot = SyncdSnapshot {
+            version: Some(SyncdVersion { version: Some(1) }),
+            records: encoded.patch.mutations.iter().filter_map(|mutation| mutation.record.clone()).collect(),
+            mac: Some(vec![0; 32]),
+            key_id: Some(KeyId { id: Some(key_id.clone()) }),
+        };
+        assert!(ctx.apply_snapshot_state(PatchName::Regular, &snapshot.encode_to_vec()).await.is_err());
+        snapshot.mac = Some(encoded.state.snapshot_mac(PatchName::Regular, &keys.snapshot_mac));
+        let state = ctx.apply_snapshot_state(PatchName::Regular, &snapshot.encode_to_vec()).await.unwrap();
+        assert_eq!(state, encoded.state);
+        assert_eq!(ctx.hash_state(PatchName::Regular).await.unwrap(), encoded.state);
+
+        // A patch fetched on top of the snapshot moves the stored state along
+        let next = patch::encode_patch(&PatchInfo::label_chat("1", &chat, true), &key_id, &keys, &state).unwrap();
+        let fetched = SyncdPatch { version: Some(SyncdVersion { version: Some(2) }), ..next.patch.clone() };
+        let state = ctx.apply_patch_state(PatchName::Regular, &state, &fetched, &fetched.mutations).await.unwrap();
+        assert_eq!(state, next.state);
+        assert_eq!(ctx.hash_state(PatchName::Regular).await.unwrap(), next.state);
+
+        ctx.reset_hash_state(PatchName::Regular).await.unwrap();
+        assert_eq!(ctx.hash_state(PatchName::Regular).await.unwrap().version, 0);
+    }
 }
\ No newline at end of file
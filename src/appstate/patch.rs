@@ -0,0 +1,817 @@
+/// Encoding of outgoing app state patches
+///
+/// Changes we make to chats (archive, pin, mute, ...) are published as
+/// `SyncdPatch` protobufs so the phone and other companions apply them too.
+/// Every mutation carries
+///
+/// * an index MAC: HMAC-SHA256 of the JSON index (e.g. `["pin_v1","123@s.whatsapp.net"]`),
+/// * the AES-CBC encrypted `SyncActionData` followed by its value MAC,
+///
+/// and the patch is authenticated with a snapshot MAC over the collection's
+/// new LT-Hash state and a patch MAC over the value MACs. All keys are
+/// derived from the newest app state sync key shared by the primary device.
+///
+/// The version and LT-Hash of every collection are persisted, see
+/// [`SqliteAppStateVersionStore`](crate::database::sqlite::SqliteAppStateVersionStore).
+/// Before the first patch of a collection is sent, the collection is fetched
+/// from the server. When the server refuses a patch because our version is
+/// behind, the missing patches are fetched and applied and the patch is
+/// encoded again on top of them.
+
+use super::{labels::Label, lthash::WA_PATCH_INTEGRITY};
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    proto::{
+        wa_server_sync::{syncd_mutation::SyncdOperation, ExternalBlobReference, KeyId, SyncdIndex, SyncdMutation, SyncdPatch, SyncdRecord, SyncdSnapshot, SyncdValue},
+        wa_sync_action::{ArchiveChatAction, ClearChatAction, DeleteChatAction, LabelAssociationAction, LabelEditAction, MarkChatAsReadAction, MuteAction, PinAction, StarAction, SyncActionData, SyncActionMessageRange, SyncActionValue},
+    },
+    media::{MediaInfo, MediaType, MEDIA_HOST},
+    request::InfoQuery,
+    server_time,
+    types::JID,
+    util::crypto::{aes_cbc_decrypt, aes_cbc_encrypt, hkdf_expand, hmac_sha256, hmac_sha512, random_bytes},
+};
+use prost::Message;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Namespace of app state queries
+pub const APP_STATE_NAMESPACE: &str = "w:sync:app:state";
+
+/// Error code of a collection whose version is behind the server's
+pub const VERSION_CONFLICT: u16 = 409;
+
+/// Index name of archive mutations
+pub const INDEX_ARCHIVE: &str = "archive";
+/// Index name of pin mutations
+pub const INDEX_PIN: &str = "pin_v1";
+/// Index name of mute mutations
+pub const INDEX_MUTE: &str = "mute";
+/// Index name of star mutations
+pub const INDEX_STAR: &str = "star";
+/// Index name of delete chat mutations
+pub const INDEX_DELETE_CHAT: &str = "deleteChat";
+/// Index name of clear chat mutations
+pub const INDEX_CLEAR_CHAT: &str = "clearChat";
+/// Index name of read/unread mutations
+pub const INDEX_MARK_CHAT_AS_READ: &str = "markChatAsRead";
+/// Index name of label create, edit and delete mutations
+pub const INDEX_LABEL_EDIT: &str = "label_edit";
+/// Index name of mutations attaching a label to a chat
+pub const INDEX_LABEL_ASSOCIATION_CHAT: &str = "label_jid";
+/// Index name of mutations attaching a label to a message
+pub const INDEX_LABEL_ASSOCIATION_MESSAGE: &str = "label_message";
+
+/// Length of value and index MACs
+const MAC_LENGTH: usize = 32;
+
+/// Length of the AES-CBC IV prepended to encrypted values
+const IV_LENGTH: usize = 16;
+
+/// App state collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PatchName {
+    CriticalBlock,
+    CriticalUnblockLow,
+    RegularHigh,
+    Regular,
+    RegularLow,
+}
+
+impl PatchName {
+    /// All collections, in the order they are synced
+    pub const ALL: [PatchName; 5] = [
+        PatchName::CriticalBlock,
+        PatchName::CriticalUnblockLow,
+        PatchName::RegularHigh,
+        PatchName::Regular,
+        PatchName::RegularLow,
+    ];
+
+    /// Collection name used on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatchName::CriticalBlock => "critical_block",
+            PatchName::CriticalUnblockLow => "critical_unblock_low",
+            PatchName::RegularHigh => "regular_high",
+            PatchName::Regular => "regular",
+            PatchName::RegularLow => "regular_low",
+        }
+    }
+}
+
+impl std::fmt::Display for PatchName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Keys derived from an app state sync key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedAppStateKeys {
+    pub index: Vec<u8>,
+    pub value_encryption: Vec<u8>,
+    pub value_mac: Vec<u8>,
+    pub snapshot_mac: Vec<u8>,
+    pub patch_mac: Vec<u8>,
+}
+
+impl ExpandedAppStateKeys {
+    /// Derive the mutation keys from the key data of an app state sync key
+    pub fn expand(key_data: &[u8]) -> Result<Self> {
+        let expanded = hkdf_expand(key_data, b"WhatsApp Mutation Keys", 160)?;
+        let part = |i: usize| expanded[i * 32..(i + 1) * 32].to_vec();
+        Ok(Self {
+            index: part(0),
+            value_encryption: part(1),
+            value_mac: part(2),
+            snapshot_mac: part(3),
+            patch_mac: part(4),
+        })
+    }
+}
+
+/// Version and LT-Hash state of a collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashState {
+    pub version: u64,
+    pub hash: Vec<u8>,
+    /// Value MAC of every index MAC currently set, needed to remove old values from the hash
+    pub value_macs: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Default for HashState {
+    fn default() -> Self {
+        Self { version: 0, hash: vec![0; WA_PATCH_INTEGRITY.hkdf_size], value_macs: HashMap::new() }
+    }
+}
+
+impl HashState {
+    /// Apply mutations to the LT-Hash, replacing the previous values of their indexes
+    pub fn update(&mut self, mutations: &[SyncdMutation]) -> Result<()> {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+
+        for mutation in mutations {
+            let record = mutation.record.as_ref()
+                .ok_or_else(|| Error::Protocol("App state mutation without record".to_string()))?;
+            let index_mac = record.index.as_ref().and_then(|index| index.blob.clone())
+                .ok_or_else(|| Error::Protocol("App state mutation without index".to_string()))?;
+
+            if let Some(previous) = self.value_macs.remove(&index_mac) {
+                removed.push(previous);
+            }
+            if mutation.operation() == SyncdOperation::Set {
+                let value_mac = record_value_mac(record)?.to_vec();
+                self.value_macs.insert(index_mac, value_mac.clone());
+                added.push(value_mac);
+            }
+        }
+
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut self.hash, &removed, &added)
+    }
+
+    /// State of a collection downloaded as a snapshot
+    pub fn from_snapshot(snapshot: &SyncdSnapshot) -> Result<Self> {
+        let mut state = Self {
+            version: snapshot.version.as_ref().and_then(|version| version.version).unwrap_or_default(),
+            ..Self::default()
+        };
+        let mut added = Vec::with_capacity(snapshot.records.len());
+        for record in &snapshot.records {
+            let index_mac = record.index.as_ref().and_then(|index| index.blob.clone())
+                .ok_or_else(|| Error::Protocol("App state snapshot record without index".to_string()))?;
+            let value_mac = record_value_mac(record)?.to_vec();
+            state.value_macs.insert(index_mac, value_mac.clone());
+            added.push(value_mac);
+        }
+        WA_PATCH_INTEGRITY.subtract_then_add(&mut state.hash, &[], &added)?;
+        Ok(state)
+    }
+
+    /// MAC over the hash state, proving both sides reached the same state
+    pub fn snapshot_mac(&self, name: PatchName, key: &[u8]) -> Vec<u8> {
+        hmac_sha256(key, &[&self.hash, &self.version.to_be_bytes(), name.as_str().as_bytes()])
+    }
+}
+
+/// A single change to publish
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationInfo {
+    /// Index, e.g. `["pin_v1", "123@s.whatsapp.net"]`
+    pub index: Vec<String>,
+    /// Version of the action format
+    pub version: i32,
+    pub value: SyncActionValue,
+}
+
+/// A set of changes to one collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchInfo {
+    pub name: PatchName,
+    pub timestamp: SystemTime,
+    pub mutations: Vec<MutationInfo>,
+}
+
+impl PatchInfo {
+    /// Archive or unarchive a chat. Archiving also unpins it.
+    pub fn archive(chat: &JID, archive: bool, last_message: Option<SystemTime>) -> Self {
+        let message_range = message_range(last_message);
+        let mut mutations = vec![MutationInfo {
+            index: vec![INDEX_ARCHIVE.to_string(), chat.to_string()],
+            version: 3,
+            value: SyncActionValue {
+                archive_chat_action: Some(ArchiveChatAction { archived: Some(archive), message_range }),
+                ..Default::default()
+            },
+        }];
+        if archive {
+            mutations.push(pin_mutation(chat, false));
+        }
+        Self::new(PatchName::RegularLow, mutations)
+    }
+
+    /// Pin or unpin a chat
+    pub fn pin(chat: &JID, pin: bool) -> Self {
+        Self::new(PatchName::RegularLow, vec![pin_mutation(chat, pin)])
+    }
+
+    /// Mute a chat for `duration` (forever if `None`) or unmute it
+    pub fn mute(chat: &JID, mute: bool, duration: Option<Duration>) -> Self {
+        let mute_end_timestamp = match (mute, duration) {
+            (true, Some(duration)) => Some(unix_millis(server_time::now() + duration)),
+            (true, None) => Some(-1),
+            (false, _) => None,
+        };
+        Self::new(PatchName::RegularHigh, vec![MutationInfo {
+            index: vec![INDEX_MUTE.to_string(), chat.to_string()],
+            version: 2,
+            value: SyncActionValue {
+                mute_action: Some(MuteAction { muted: Some(mute), mute_end_timestamp, auto_muted: None }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Delete a chat with all its messages and media
+    pub fn delete_chat(chat: &JID, last_message: Option<SystemTime>) -> Self {
+        Self::new(PatchName::RegularHigh, vec![MutationInfo {
+            index: vec![INDEX_DELETE_CHAT.to_string(), chat.to_string(), "1".to_string()],
+            version: 6,
+            value: SyncActionValue {
+                delete_chat_action: Some(DeleteChatAction { message_range: message_range(last_message) }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Delete the messages of a chat but keep the chat, optionally keeping starred messages
+    pub fn clear_chat(chat: &JID, keep_starred: bool, last_message: Option<SystemTime>) -> Self {
+        Self::new(PatchName::RegularHigh, vec![MutationInfo {
+            index: vec![
+                INDEX_CLEAR_CHAT.to_string(),
+                chat.to_string(),
+                if keep_starred { "0" } else { "1" }.to_string(),
+                "0".to_string(),
+            ],
+            version: 6,
+            value: SyncActionValue {
+                clear_chat_action: Some(ClearChatAction { message_range: message_range(last_message) }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Mark a chat as read, or as unread
+    pub fn mark_read(chat: &JID, read: bool, last_message: Option<SystemTime>) -> Self {
+        Self::new(PatchName::RegularLow, vec![MutationInfo {
+            index: vec![INDEX_MARK_CHAT_AS_READ.to_string(), chat.to_string()],
+            version: 3,
+            value: SyncActionValue {
+                mark_chat_as_read_action: Some(MarkChatAsReadAction { read: Some(read), message_range: message_range(last_message) }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Create, edit or delete a label
+    pub fn label_edit(label: &Label, deleted: bool) -> Self {
+        Self::new(PatchName::Regular, vec![MutationInfo {
+            index: vec![INDEX_LABEL_EDIT.to_string(), label.id.clone()],
+            version: 3,
+            value: SyncActionValue {
+                label_edit_action: Some(LabelEditAction {
+                    name: Some(label.name.clone()),
+                    color: Some(label.color),
+                    predefined_id: label.predefined_id,
+                    deleted: Some(deleted),
+                    order_index: label.order_index,
+                }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Attach a label to a chat or remove it
+    pub fn label_chat(label_id: &str, chat: &JID, labeled: bool) -> Self {
+        Self::new(PatchName::Regular, vec![MutationInfo {
+            index: vec![INDEX_LABEL_ASSOCIATION_CHAT.to_string(), label_id.to_string(), chat.to_string()],
+            version: 3,
+            value: SyncActionValue {
+                label_association_action: Some(LabelAssociationAction { labeled: Some(labeled) }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Attach a label to a message or remove it
+    pub fn label_message(label_id: &str, chat: &JID, message_id: &str, labeled: bool) -> Self {
+        Self::new(PatchName::Regular, vec![MutationInfo {
+            index: vec![
+                INDEX_LABEL_ASSOCIATION_MESSAGE.to_string(),
+                label_id.to_string(),
+                chat.to_string(),
+                message_id.to_string(),
+                "0".to_string(),
+                "0".to_string(),
+            ],
+            version: 3,
+            value: SyncActionValue {
+                label_association_action: Some(LabelAssociationAction { labeled: Some(labeled) }),
+                ..Default::default()
+            },
+        }])
+    }
+
+    /// Star or unstar a message
+    ///
+    /// `sender` is the author of a group message; it is left out of the
+    /// index (`"0"`) for our own messages and in one-to-one chats.
+    pub fn star(chat: &JID, message_id: &str, from_me: bool, sender: Option<&JID>, starred: bool) -> Self {
+        let sender = sender
+            .filter(|sender| !from_me && sender.user != chat.user)
+            .map(|sender| sender.to_string())
+            .unwrap_or_else(|| "0".to_string());
+        Self::new(PatchName::RegularHigh, vec![MutationInfo {
+            index: vec![
+                INDEX_STAR.to_string(),
+                chat.to_string(),
+                message_id.to_string(),
+                if from_me { "1" } else { "0" }.to_string(),
+                sender,
+            ],
+            version: 2,
+            value: SyncActionValue { star_action: Some(StarAction { starred: Some(starred) }), ..Default::default() },
+        }])
+    }
+
+    fn new(name: PatchName, mutations: Vec<MutationInfo>) -> Self {
+        Self { name, timestamp: server_time::now(), mutations }
+    }
+}
+
+/// Range of messages a chat action applies to, up to the last message we know of
+fn message_range(last_message: Option<SystemTime>) -> Option<SyncActionMessageRange> {
+    last_message.map(|timestamp| SyncActionMessageRange {
+        last_message_timestamp: Some(unix_seconds(timestamp)),
+        ..Default::default()
+    })
+}
+
+fn pin_mutation(chat: &JID, pin: bool) -> MutationInfo {
+    MutationInfo {
+        index: vec![INDEX_PIN.to_string(), chat.to_string()],
+        version: 5,
+        value: SyncActionValue { pin_action: Some(PinAction { pinned: Some(pin) }), ..Default::default() },
+    }
+}
+
+/// A message starred or unstarred on another device
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarMutation {
+    pub chat: JID,
+    pub message_id: String,
+    pub from_me: bool,
+    /// Author of a group message
+    pub sender: Option<JID>,
+    pub starred: bool,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Read a decoded star mutation, `None` if the mutation is about something else
+pub fn parse_star_mutation(data: &SyncActionData) -> Option<StarMutation> {
+    let index: Vec<String> = serde_json::from_slice(data.index()).ok()?;
+    let [name, chat, message_id, from_me, sender] = index.as_slice() else {
+        return None;
+    };
+    if name != INDEX_STAR {
+        return None;
+    }
+    let value = data.value.as_ref()?;
+    Some(StarMutation {
+        chat: chat.parse().ok()?,
+        message_id: message_id.clone(),
+        from_me: from_me == "1",
+        sender: if sender == "0" { None } else { sender.parse().ok() },
+        starred: value.star_action.as_ref()?.starred.unwrap_or(false),
+        timestamp: value.timestamp.map(|millis| UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)),
+    })
+}
+
+/// An encoded patch and the collection state once the server accepted it
+#[derive(Debug, Clone)]
+pub struct EncodedPatch {
+    pub patch: SyncdPatch,
+    pub state: HashState,
+}
+
+/// Encrypt and authenticate the mutations of `info` on top of `state`
+pub fn encode_patch(info: &PatchInfo, key_id: &[u8], keys: &ExpandedAppStateKeys, state: &HashState) -> Result<EncodedPatch> {
+    let timestamp = unix_millis(info.timestamp);
+
+    let mut mutations = Vec::with_capacity(info.mutations.len());
+    for mutation in &info.mutations {
+        let index = serde_json::to_vec(&mutation.index)?;
+        let mut value = mutation.value.clone();
+        value.timestamp = Some(timestamp);
+        let data = SyncActionData {
+            index: Some(index.clone()),
+            value: Some(value),
+            padding: Some(Vec::new()),
+            version: Some(mutation.version),
+        };
+
+        let iv = random_bytes(IV_LENGTH);
+        let mut encrypted = iv.clone();
+        encrypted.extend(aes_cbc_encrypt(&keys.value_encryption, &iv, &data.encode_to_vec())?);
+        let value_mac = content_mac(SyncdOperation::Set, &encrypted, key_id, &keys.value_mac);
+        encrypted.extend(value_mac);
+
+        mutations.push(SyncdMutation {
+            operation: Some(SyncdOperation::Set as i32),
+            record: Some(SyncdRecord {
+                index: Some(SyncdIndex { blob: Some(hmac_sha256(&keys.index, &[&index])) }),
+                value: Some(SyncdValue { blob: Some(encrypted) }),
+                key_id: Some(KeyId { id: Some(key_id.to_vec()) }),
+            }),
+        });
+    }
+
+    let mut state = state.clone();
+    state.update(&mutations)?;
+    state.version += 1;
+
+    let mut patch = SyncdPatch {
+        mutations,
+        snapshot_mac: Some(state.snapshot_mac(info.name, &keys.snapshot_mac)),
+        key_id: Some(KeyId { id: Some(key_id.to_vec()) }),
+        ..Default::default()
+    };
+    patch.patch_mac = Some(patch_mac(&patch, info.name, &keys.patch_mac, state.version)?);
+
+    Ok(EncodedPatch { patch, state })
+}
+
+/// Verify and decrypt the value of a mutation record
+pub fn decode_mutation_value(operation: SyncdOperation, record: &SyncdRecord, keys: &ExpandedAppStateKeys) -> Result<SyncActionData> {
+    let blob = record.value.as_ref().and_then(|value| value.blob.as_deref())
+        .ok_or_else(|| Error::Protocol("App state mutation without value".to_string()))?;
+    if blob.len() < IV_LENGTH + MAC_LENGTH {
+        return Err(Error::Protocol("App state mutation value too short".to_string()));
+    }
+    let key_id = record.key_id.as_ref().and_then(|key_id| key_id.id.as_deref()).unwrap_or_default();
+
+    let (content, value_mac) = blob.split_at(blob.len() - MAC_LENGTH);
+    if content_mac(operation, content, key_id, &keys.value_mac) != value_mac {
+        return Err(Error::Crypto("App state mutation value MAC mismatch".to_string()));
+    }
+
+    let (iv, ciphertext) = content.split_at(IV_LENGTH);
+    let data = SyncActionData::decode(aes_cbc_decrypt(&keys.value_encryption, iv, ciphertext)?.as_slice())?;
+
+    let index_mac = record.index.as_ref().and_then(|index| index.blob.as_deref());
+    if index_mac != Some(hmac_sha256(&keys.index, &[data.index()]).as_slice()) {
+        return Err(Error::Crypto("App state mutation index MAC mismatch".to_string()));
+    }
+    Ok(data)
+}
+
+/// Build the query publishing a patch on top of collection version `version`
+pub fn build_send_patch_query(name: PatchName, version: u64, patch: &SyncdPatch) -> InfoQuery {
+    let collection = Node::builder("collection")
+        .attr("name", name)
+        .attr("version", version)
+        .attr("return_snapshot", false)
+        .child("patch", |p| p.bytes(patch.encode_to_vec()))
+        .build();
+    InfoQuery::set(APP_STATE_NAMESPACE).content(vec![Node::builder("sync").node(collection).build()])
+}
+
+/// Build the query fetching the patches of a collection after `version`.
+///
+/// Without a version (0) the server answers with a snapshot of the collection.
+pub fn build_fetch_query(name: PatchName, version: u64) -> InfoQuery {
+    let collection = Node::builder("collection")
+        .attr("name", name)
+        .attr("version", version)
+        .attr("return_snapshot", version == 0)
+        .build();
+    InfoQuery::set(APP_STATE_NAMESPACE).content(vec![Node::builder("sync").node(collection).build()])
+}
+
+fn response_collection(response: &Node, name: PatchName) -> Option<&Node> {
+    response
+        .find_child("sync")?
+        .get_children()?
+        .iter()
+        .find(|collection| collection.get_attr("name").map(String::as_str) == Some(name.as_str()))
+}
+
+/// Error code the server answered a collection with, if any
+pub fn collection_error(response: &Node, name: PatchName) -> Option<u16> {
+    let collection = response_collection(response, name)?;
+    if collection.get_attr("type").map(String::as_str) != Some("error") {
+        return None;
+    }
+    collection
+        .find_child("error")
+        .and_then(|error| error.get_attr("code"))
+        .and_then(|code| code.parse().ok())
+        .or(Some(0))
+}
+
+/// Where to download the snapshot of a collection fetched without a version
+pub fn snapshot_reference(response: &Node, name: PatchName) -> Result<Option<ExternalBlobReference>> {
+    let Some(blob) = response_collection(response, name)
+        .and_then(|collection| collection.find_child("snapshot"))
+        .and_then(Node::get_binary)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(ExternalBlobReference::decode(blob.as_slice())?))
+}
+
+/// Patches of a collection in a fetch response, oldest first
+pub fn response_patches(response: &Node, name: PatchName) -> Result<Vec<SyncdPatch>> {
+    let Some(patches) = response_collection(response, name)
+        .and_then(|collection| collection.find_child("patches"))
+        .and_then(Node::get_children)
+    else {
+        return Ok(Vec::new());
+    };
+    patches.iter()
+        .filter(|patch| patch.tag == "patch")
+        .filter_map(Node::get_binary)
+        .map(|blob| Ok(SyncdPatch::decode(blob.as_slice())?))
+        .collect()
+}
+
+/// Whether the server holds more patches of a collection than it answered with
+pub fn has_more_patches(response: &Node, name: PatchName) -> bool {
+    response_collection(response, name)
+        .and_then(|collection| collection.get_attr("has_more_patches"))
+        .is_some_and(|more| more == "true")
+}
+
+/// Apply a patch fetched from the server on top of `state`.
+///
+/// `mutations` are the ones of the patch or, for large patches, the ones
+/// downloaded from its external blob. The snapshot MAC of the patch has to
+/// match the resulting state, otherwise our state went out of step.
+pub fn apply_patch(name: PatchName, state: &HashState, patch: &SyncdPatch, mutations: &[SyncdMutation], keys: &ExpandedAppStateKeys) -> Result<HashState> {
+    let mut next = state.clone();
+    next.update(mutations)?;
+    next.version = patch.version.as_ref().and_then(|version| version.version)
+        .ok_or_else(|| Error::Protocol(format!("App state patch of {} without version", name)))?;
+    if patch.snapshot_mac.as_deref() != Some(next.snapshot_mac(name, &keys.snapshot_mac).as_slice()) {
+        return Err(Error::Crypto(format!("App state patch MAC mismatch in {} at version {}", name, next.version)));
+    }
+    Ok(next)
+}
+
+/// Download location of an app state snapshot or external mutations
+pub fn external_blob_media_info(reference: &ExternalBlobReference) -> MediaInfo {
+    let direct_path = reference.direct_path.clone().unwrap_or_default();
+    MediaInfo::new(
+        format!("{}{}", MEDIA_HOST, direct_path),
+        Some(direct_path),
+        reference.media_key.clone().unwrap_or_default(),
+        reference.file_sha256.clone().unwrap_or_default(),
+        reference.file_enc_sha256.clone().unwrap_or_default(),
+        reference.file_size_bytes.unwrap_or_default(),
+        "application/x-protobuf".to_string(),
+        MediaType::Document,
+    )
+}
+
+fn record_value_mac(record: &SyncdRecord) -> Result<&[u8]> {
+    record.value.as_ref()
+        .and_then(|value| value.blob.as_deref())
+        .filter(|blob| blob.len() >= MAC_LENGTH)
+        .map(|blob| &blob[blob.len() - MAC_LENGTH..])
+        .ok_or_else(|| Error::Protocol("App state mutation without value MAC".to_string()))
+}
+
+fn content_mac(operation: SyncdOperation, data: &[u8], key_id: &[u8], key: &[u8]) -> Vec<u8> {
+    let operation = [operation as u8 + 1];
+    let key_id_length = (key_id.len() as u64 + 1).to_be_bytes();
+    let mut mac = hmac_sha512(key, &[&operation, key_id, data, &key_id_length]);
+    mac.truncate(MAC_LENGTH);
+    mac
+}
+
+fn patch_mac(patch: &SyncdPatch, name: PatchName, key: &[u8], version: u64) -> Result<Vec<u8>> {
+    let value_macs = patch.mutations.iter()
+        .map(|mutation| mutation.record.as_ref()
+            .ok_or_else(|| Error::Protocol("App state mutation without record".to_string()))
+            .and_then(record_value_mac))
+        .collect::<Result<Vec<_>>>()?;
+
+    let version = version.to_be_bytes();
+    let mut data: Vec<&[u8]> = vec![patch.snapshot_mac()];
+    data.extend(value_macs);
+    data.push(&version);
+    data.push(name.as_str().as_bytes());
+    Ok(hmac_sha256(key, &data))
+}
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::wa_server_sync::SyncdVersion;
+
+    #[test]
+    fn test_encode_patch() {
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let key_id = vec![0, 0, 0, 1];
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+
+        let info = PatchInfo::archive(&chat, true, Some(UNIX_EPOCH + Duration::from_secs(100)));
+        let encoded = encode_patch(&info, &key_id, &keys, &HashState::default()).unwrap();
+        assert_eq!(encoded.state.version, 1);
+        assert_eq!(encoded.state.value_macs.len(), 2);
+        assert_eq!(encoded.patch.mutations.len(), 2);
+        assert_eq!(
+            encoded.patch.snapshot_mac(),
+            encoded.state.snapshot_mac(PatchName::RegularLow, &keys.snapshot_mac).as_slice()
+        );
+
+        let record = encoded.patch.mutations[0].record.as_ref().unwrap();
+        let data = decode_mutation_value(SyncdOperation::Set, record, &keys).unwrap();
+        assert_eq!(data.index(), br#"["archive","1234@s.whatsapp.net"]"#);
+        assert_eq!(data.version, Some(3));
+        let value = data.value.unwrap();
+        assert!(value.timestamp.is_some());
+        let archive = value.archive_chat_action.unwrap();
+        assert_eq!(archive.archived, Some(true));
+        assert_eq!(archive.message_range.unwrap().last_message_timestamp, Some(100));
+        assert!(decode_mutation_value(SyncdOperation::Remove, record, &keys).is_err());
+
+        // Unpinning the same chat again replaces the pin value in the hash
+        let encoded = encode_patch(&PatchInfo::pin(&chat, false), &key_id, &keys, &encoded.state).unwrap();
+        assert_eq!(encoded.state.version, 2);
+        assert_eq!(encoded.state.value_macs.len(), 2);
+
+        let query = build_send_patch_query(PatchName::RegularLow, 1, &encoded.patch);
+        assert_eq!(query.namespace, APP_STATE_NAMESPACE);
+        let collection = query.content[0].find_child("collection").unwrap();
+        assert_eq!(collection.get_attr("name").map(String::as_str), Some("regular_low"));
+        assert_eq!(collection.get_attr("version").map(String::as_str), Some("1"));
+        let patch = SyncdPatch::decode(collection.find_child("patch").unwrap().get_binary().unwrap().as_slice()).unwrap();
+        assert_eq!(patch, encoded.patch);
+    }
+
+    #[test]
+    fn test_snapshot_state() {
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let encoded = encode_patch(&PatchInfo::archive(&chat, true, None), &[0, 0, 0, 1], &keys, &HashState::default()).unwrap();
+
+        // A snapshot holding the same records leads to the same state
+        let snapshot = SyncdSnapshot {
+            version: Some(SyncdVersion { version: Some(1) }),
+            records: encoded.patch.mutations.iter().filter_map(|mutation| mutation.record.clone()).collect(),
+            ..Default::default()
+        };
+        assert_eq!(HashState::from_snapshot(&snapshot).unwrap(), encoded.state);
+
+        let query = build_fetch_query(PatchName::Regular, 0);
+        let collection = query.content[0].find_child("collection").unwrap();
+        assert_eq!(collection.get_attr("return_snapshot").map(String::as_str), Some("true"));
+
+        let reference = ExternalBlobReference { direct_path: Some("/v/t62/snapshot".to_string()), ..Default::default() };
+        let response = |collection: Node| Node::builder("iq").child("sync", |sync| sync.node(collection)).build();
+        let conflict = response(Node::builder("collection").attr("name", "regular").attr("type", "error")
+            .child("error", |error| error.attr("code", VERSION_CONFLICT)).build());
+        assert_eq!(collection_error(&conflict, PatchName::Regular), Some(VERSION_CONFLICT));
+        assert_eq!(collection_error(&conflict, PatchName::RegularLow), None);
+
+        let snapshot = response(Node::builder("collection").attr("name", "regular").attr("version", 5)
+            .child("snapshot", |s| s.bytes(reference.encode_to_vec())).build());
+        assert_eq!(collection_error(&snapshot, PatchName::Regular), None);
+        assert_eq!(snapshot_reference(&snapshot, PatchName::Regular).unwrap(), Some(reference.clone()));
+        assert_eq!(external_blob_media_info(&reference).url, format!("{}/v/t62/snapshot", MEDIA_HOST));
+    }
+
+    #[test]
+    fn test_apply_fetched_patch() {
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let first = encode_patch(&PatchInfo::pin(&chat, true), &[0, 0, 0, 1], &keys, &HashState::default()).unwrap();
+        let other = JID::new("5678".to_string(), "s.whatsapp.net".to_string());
+        let second = encode_patch(&PatchInfo::archive(&other, true, None), &[0, 0, 0, 1], &keys, &first.state).unwrap();
+
+        // Another device published both patches, the server sends them with their versions
+        let fetched = |encoded: &EncodedPatch| SyncdPatch {
+            version: Some(SyncdVersion { version: Some(encoded.state.version) }),
+            ..encoded.patch.clone()
+        };
+        let response = Node::builder("iq")
+            .child("sync", |sync| sync.child("collection", |collection| collection
+                .attr("name", "regular_low")
+                .attr("has_more_patches", true)
+                .child("patches", |patches| patches
+                    .child("patch", |p| p.bytes(fetched(&first).encode_to_vec()))
+                    .child("patch", |p| p.bytes(fetched(&second).encode_to_vec())))))
+            .build();
+        assert!(has_more_patches(&response, PatchName::RegularLow));
+        assert!(response_patches(&response, PatchName::Regular).unwrap().is_empty());
+
+        let mut state = HashState::default();
+        for patch in response_patches(&response, PatchName::RegularLow).unwrap() {
+            state = apply_patch(PatchName::RegularLow, &state, &patch, &patch.mutations, &keys).unwrap();
+        }
+        assert_eq!(state, second.state);
+
+        // Skipping a patch leaves a state the next patch does not fit on
+        assert!(apply_patch(PatchName::RegularLow, &HashState::default(), &fetched(&second), &second.patch.mutations, &keys).is_err());
+    }
+
+    #[test]
+    fn test_mute_patch() {
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let mute = |info: PatchInfo| info.mutations[0].value.mute_action.clone().unwrap();
+
+        assert_eq!(mute(PatchInfo::mute(&chat, true, None)).mute_end_timestamp, Some(-1));
+        assert!(mute(PatchInfo::mute(&chat, true, Some(Duration::from_secs(60)))).mute_end_timestamp.unwrap() > 0);
+        assert_eq!(mute(PatchInfo::mute(&chat, false, None)).muted, Some(false));
+        assert_eq!(PatchInfo::mute(&chat, true, None).name, PatchName::RegularHigh);
+    }
+
+    #[test]
+    fn test_chat_action_patches() {
+        let chat = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        let last_message = Some(UNIX_EPOCH + Duration::from_secs(100));
+
+        let delete = PatchInfo::delete_chat(&chat, last_message);
+        assert_eq!(delete.name, PatchName::RegularHigh);
+        assert_eq!(delete.mutations[0].index, vec!["deleteChat", "1234@s.whatsapp.net", "1"]);
+        let range = delete.mutations[0].value.delete_chat_action.clone().unwrap().message_range.unwrap();
+        assert_eq!(range.last_message_timestamp, Some(100));
+
+        assert_eq!(PatchInfo::clear_chat(&chat, true, None).mutations[0].index, vec!["clearChat", "1234@s.whatsapp.net", "0", "0"]);
+        assert_eq!(PatchInfo::clear_chat(&chat, false, None).mutations[0].index[2], "1");
+
+        let unread = PatchInfo::mark_read(&chat, false, last_message);
+        assert_eq!(unread.name, PatchName::RegularLow);
+        assert_eq!(unread.mutations[0].index, vec!["markChatAsRead", "1234@s.whatsapp.net"]);
+        assert_eq!(unread.mutations[0].value.mark_chat_as_read_action.clone().unwrap().read, Some(false));
+    }
+
+    #[test]
+    fn test_star_patch() {
+        let keys = ExpandedAppStateKeys::expand(&[7; 32]).unwrap();
+        let group = JID::new("123-456".to_string(), "g.us".to_string());
+        let alice = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+
+        let info = PatchInfo::star(&group, "MSG1", false, Some(&alice), true);
+        assert_eq!(info.name, PatchName::RegularHigh);
+        assert_eq!(info.mutations[0].index, vec!["star", "123-456@g.us", "MSG1", "0", "1234@s.whatsapp.net"]);
+        assert_eq!(PatchInfo::star(&alice, "MSG2", true, Some(&alice), false).mutations[0].index[3..], ["1", "0"]);
+
+        // Decoding the published value gives back the starred message
+        let encoded = encode_patch(&info, &[0, 0, 0, 1], &keys, &HashState::default()).unwrap();
+        let record = encoded.patch.mutations[0].record.as_ref().unwrap();
+        let data = decode_mutation_value(SyncdOperation::Set, record, &keys).unwrap();
+        let star = parse_star_mutation(&data).unwrap();
+        assert_eq!(star.chat, group);
+        assert_eq!(star.message_id, "MSG1");
+        assert!(!star.from_me);
+        assert_eq!(star.sender, Some(alice.clone()));
+        assert!(star.starred);
+        assert!(star.timestamp.is_some());
+
+        let pin = encode_patch(&PatchInfo::pin(&alice, true), &[0, 0, 0, 1], &keys, &HashState::default()).unwrap();
+        let data = decode_mutation_value(SyncdOperation::Set, pin.patch.mutations[0].record.as_ref().unwrap(), &keys).unwrap();
+        assert!(parse_star_mutation(&data).is_none());
+    }
+}
@@ -6,13 +6,14 @@
 use crate::{
     appstate::{
         AppStateDataType, SyncContext, ContactSync, ChatMetadataSync, 
-        SettingsSync, AppStateSyncProtocol, SyncStatistics
+        SettingsSync, AppStateSyncProtocol, SyncStatistics, ConflictStrategy, ConflictHandler
     },
     database::Database,
     error::{Error, Result},
     types::JID,
 };
 use std::{
+    collections::HashMap,
     sync::Arc,
     time::{Duration, SystemTime},
 };
@@ -37,6 +38,8 @@ pub struct AppStateManagerConfig {
     pub max_concurrent_syncs: u32,
     /// Sync timeout in seconds
     pub sync_timeout_seconds: u64,
+    /// Conflict strategy per data type, last-writer-wins for the others
+    pub conflict_strategies: HashMap<AppStateDataType, ConflictStrategy>,
 }
 
 /// App State manager
@@ -122,6 +125,7 @@ impl Default for AppStateManagerConfig {
             cleanup_interval_seconds: 300, // 5 minutes
             max_concurrent_syncs: 5,
             sync_timeout_seconds: 300, // 5 minutes
+            conflict_strategies: HashMap::new(),
         }
     }
 }
@@ -148,6 +152,9 @@ impl AppStateManager {
     /// Create new app state manager with custom configuration
     pub async fn with_config(database: Arc<Database>, config: AppStateManagerConfig) -> Result<Self> {
         let sync_context = Arc::new(SyncContext::new(database.clone()));
+        for (data_type, strategy) in &config.conflict_strategies {
+            sync_context.set_conflict_strategy(data_type.clone(), strategy.clone()).await;
+        }
         
         // Create sync handlers
         let contact_sync = Arc::new(ContactSync::new());
@@ -299,6 +306,16 @@ impl AppStateManager {
         Ok(session_ids.into_iter().next().unwrap_or_default())
     }
 
+    /// Change how conflicts of a data type are resolved
+    pub async fn set_conflict_strategy(&self, data_type: AppStateDataType, strategy: ConflictStrategy) {
+        self.sync_context.set_conflict_strategy(data_type, strategy).await;
+    }
+
+    /// Register a handler for conflicts left to the user, see [`ConflictStrategy::Manual`]
+    pub async fn add_conflict_handler(&self, handler: ConflictHandler) {
+        self.sync_context.add_conflict_handler(handler).await;
+    }
+
     /// Get manager status
     pub async fn get_status(&self) -> AppStateManagerStatus {
         let state = self.state.read().await;
@@ -0,0 +1,241 @@
+/// How the client presents itself to the server and the primary device
+///
+/// Every login carries a `UserAgent` with the WhatsApp Web version, OS and
+/// locale, and pairing sends the companion `DeviceProps` the phone shows in
+/// its list of linked devices ("Chrome (Linux)"). The server refuses logins
+/// announcing a Web version it no longer supports, so the version can be
+/// configured or fetched from web.whatsapp.com before connecting.
+
+use crate::{
+    error::{Error, Result},
+    proto::{
+        wa_companion_reg::{self, device_props::PlatformType},
+        wa_wa6::client_payload::{user_agent, UserAgent},
+    },
+    socket::ProxyConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// WhatsApp Web version announced when none is configured or fetched
+pub const WA_WEB_VERSION: WebVersion = WebVersion([2, 3000, 1023223821]);
+
+/// Script of the Web client naming the current version in `client_revision`
+pub const WEB_VERSION_URL: &str = "https://web.whatsapp.com/sw.js";
+
+/// A WhatsApp Web version such as `2.3000.1023223821`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct WebVersion(pub [u32; 3]);
+
+impl WebVersion {
+    pub fn new(primary: u32, secondary: u32, tertiary: u32) -> Self {
+        Self([primary, secondary, tertiary])
+    }
+}
+
+impl fmt::Display for WebVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [primary, secondary, tertiary] = self.0;
+        write!(f, "{}.{}.{}", primary, secondary, tertiary)
+    }
+}
+
+impl std::str::FromStr for WebVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<u32> = s
+            .split('.')
+            .map(|part| part.parse())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| Error::Protocol(format!("Invalid WhatsApp Web version: {}", s)))?;
+        match parts[..] {
+            [primary, secondary, tertiary] => Ok(Self::new(primary, secondary, tertiary)),
+            _ => Err(Error::Protocol(format!("Invalid WhatsApp Web version: {}", s))),
+        }
+    }
+}
+
+/// Browser the companion claims to run in, shown on the phone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Browser {
+    Chrome,
+    Firefox,
+    Edge,
+    Safari,
+    Opera,
+    /// The WhatsApp desktop app
+    Desktop,
+    Unknown,
+}
+
+impl Browser {
+    pub fn platform_type(&self) -> PlatformType {
+        match self {
+            Browser::Chrome => PlatformType::Chrome,
+            Browser::Firefox => PlatformType::Firefox,
+            Browser::Edge => PlatformType::Edge,
+            Browser::Safari => PlatformType::Safari,
+            Browser::Opera => PlatformType::Opera,
+            Browser::Desktop => PlatformType::Desktop,
+            Browser::Unknown => PlatformType::Unknown,
+        }
+    }
+}
+
+/// Platform, OS and version announced by the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceProps {
+    pub browser: Browser,
+    /// OS name shown next to the browser on the phone, e.g. "Linux"
+    pub os: String,
+    pub os_version: String,
+    /// Two letter language code, e.g. "en"
+    pub locale_language: String,
+    /// Two letter country code, e.g. "US"
+    pub locale_country: String,
+    /// Web version announced unless a newer one was fetched
+    pub web_version: WebVersion,
+    /// Fetch the current Web version from [`WEB_VERSION_URL`] before connecting
+    pub fetch_latest_version: bool,
+}
+
+impl Default for DeviceProps {
+    fn default() -> Self {
+        Self {
+            browser: Browser::Unknown,
+            os: "whatsmeow-rs".to_string(),
+            os_version: "0.1.0".to_string(),
+            locale_language: "en".to_string(),
+            locale_country: "US".to_string(),
+            web_version: WA_WEB_VERSION,
+            fetch_latest_version: false,
+        }
+    }
+}
+
+impl DeviceProps {
+    /// Announce the given browser and OS
+    pub fn new(browser: Browser, os: impl Into<String>) -> Self {
+        Self {
+            browser,
+            os: os.into(),
+            ..Self::default()
+        }
+    }
+
+    /// User agent of the client payload, announcing `version`
+    pub fn user_agent(&self, version: WebVersion) -> UserAgent {
+        let [primary, secondary, tertiary] = version.0;
+        UserAgent {
+            platform: Some(user_agent::Platform::Web as i32),
+            release_channel: Some(user_agent::ReleaseChannel::Release as i32),
+            app_version: Some(user_agent::AppVersion {
+                primary: Some(primary),
+                secondary: Some(secondary),
+                tertiary: Some(tertiary),
+                ..Default::default()
+            }),
+            mcc: Some("000".to_string()),
+            mnc: Some("000".to_string()),
+            os_version: Some(self.os_version.clone()),
+            manufacturer: Some(String::new()),
+            device: Some("Desktop".to_string()),
+            os_build_number: Some(self.os_version.clone()),
+            locale_language_iso6391: Some(self.locale_language.clone()),
+            locale_country_iso31661_alpha2: Some(self.locale_country.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Version to announce: the fetched one, unless the configured one is newer
+    pub fn announced_version(&self, fetched: Option<WebVersion>) -> WebVersion {
+        fetched.map_or(self.web_version, |fetched| fetched.max(self.web_version))
+    }
+
+    /// Companion properties sent when pairing
+    pub fn companion_props(&self) -> wa_companion_reg::DeviceProps {
+        wa_companion_reg::DeviceProps {
+            os: Some(self.os.clone()),
+            version: Some(wa_companion_reg::device_props::AppVersion {
+                primary: Some(0),
+                secondary: Some(1),
+                tertiary: Some(0),
+                ..Default::default()
+            }),
+            platform_type: Some(self.browser.platform_type() as i32),
+            require_full_sync: Some(false),
+            ..Default::default()
+        }
+    }
+}
+
+/// Find the version in the Web client's `sw.js`, where it sits in escaped JSON
+pub fn parse_client_revision(script: &str) -> Option<WebVersion> {
+    let start = script.find("client_revision")? + "client_revision".len();
+    let revision = script[start..].trim_start_matches(['\\', '"', ':', ' ']);
+    let digits: String = revision.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok().map(|revision| WebVersion::new(2, 3000, revision))
+}
+
+/// Fetch the version the WhatsApp Web client currently runs
+pub async fn fetch_latest_web_version(proxy: Option<&ProxyConfig>) -> Result<WebVersion> {
+    let mut builder = reqwest::Client::builder().timeout(std::time::Duration::from_secs(10));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy.to_reqwest_proxy()?);
+    }
+    let client = builder
+        .build()
+        .map_err(|e| Error::Connection(format!("Failed to create HTTP client: {}", e)))?;
+    let response = client
+        .get(WEB_VERSION_URL)
+        .send()
+        .await
+        .map_err(|e| Error::Connection(format!("Failed to fetch WhatsApp Web version: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(Error::Connection(format!("Fetching WhatsApp Web version failed with status: {}", response.status())));
+    }
+    let script = response
+        .text()
+        .await
+        .map_err(|e| Error::Connection(format!("Failed to fetch WhatsApp Web version: {}", e)))?;
+    parse_client_revision(&script).ok_or_else(|| Error::Protocol("No client_revision in WhatsApp Web script".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_web_version() {
+        let version: WebVersion = "2.3000.1023223821".parse().unwrap();
+        assert_eq!(version, WA_WEB_VERSION);
+        assert_eq!(version.to_string(), "2.3000.1023223821");
+        assert!("2.3000".parse::<WebVersion>().is_err());
+        assert!("2.x.1".parse::<WebVersion>().is_err());
+
+        let script = r#"self.__swData=JSON.parse(/*BTDS*/"{\"dynamic_data\":{\"SiteData\":{\"client_revision\":1028545679,\"server_revision\":1028545679}}}");"#;
+        assert_eq!(parse_client_revision(script), Some(WebVersion::new(2, 3000, 1028545679)));
+        let script = r#"{"client_revision": 1028545680}"#;
+        assert_eq!(parse_client_revision(script), Some(WebVersion::new(2, 3000, 1028545680)));
+        assert_eq!(parse_client_revision("{}"), None);
+    }
+
+    #[test]
+    fn test_device_props() {
+        let props = DeviceProps::new(Browser::Firefox, "Linux");
+        let companion = props.companion_props();
+        assert_eq!(companion.os.as_deref(), Some("Linux"));
+        assert_eq!(companion.platform_type, Some(PlatformType::Firefox as i32));
+
+        let user_agent = props.user_agent(WebVersion::new(2, 3000, 7));
+        assert_eq!(user_agent.app_version.unwrap().tertiary, Some(7));
+        assert_eq!(user_agent.locale_language_iso6391.as_deref(), Some("en"));
+
+        let fetched = WebVersion::new(2, 3000, WA_WEB_VERSION.0[2] + 1);
+        assert_eq!(props.announced_version(None), WA_WEB_VERSION);
+        assert_eq!(props.announced_version(Some(fetched)), fetched);
+        assert_eq!(props.announced_version(Some(WebVersion::new(2, 2400, 1))), WA_WEB_VERSION);
+    }
+}
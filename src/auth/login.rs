@@ -0,0 +1,126 @@
+/// Login of a paired device
+///
+/// After pairing, the device's noise key and JID live in the
+/// [`DeviceStore`](crate::store::DeviceStore). When connecting again the
+/// client sends that static key in the noise handshake together with a
+/// login [`ClientPayload`] naming the device. The server then answers with
+/// `<success>` instead of `<pair-device>`, or with a `<failure>` if the
+/// device was unlinked in the meantime. The user agent of the payload comes
+/// from the configured [`DeviceProps`].
+
+use crate::{
+    auth::device_props::{DeviceProps, WebVersion},
+    binary::Node,
+    error::{Error, Result},
+    proto::wa_wa6::{
+        client_payload::{web_info, ConnectReason, ConnectType, WebInfo},
+        ClientPayload,
+    },
+    store::DeviceData,
+    types::JID,
+    util::keys::ECKeyPair,
+};
+use prost::Message;
+
+/// Payload fields shared by logins and registrations
+pub fn base_payload(props: &DeviceProps, version: WebVersion) -> ClientPayload {
+    ClientPayload {
+        user_agent: Some(props.user_agent(version)),
+        web_info: Some(WebInfo {
+            web_sub_platform: Some(web_info::WebSubPlatform::WebBrowser as i32),
+            ..Default::default()
+        }),
+        connect_type: Some(ConnectType::WifiUnknown as i32),
+        connect_reason: Some(ConnectReason::UserActivated as i32),
+        ..Default::default()
+    }
+}
+
+/// Payload logging in as the stored device, announcing Web `version`
+pub fn login_payload(device: &DeviceData, props: &DeviceProps, version: WebVersion) -> Result<ClientPayload> {
+    let username = device
+        .jid
+        .user
+        .parse::<u64>()
+        .map_err(|_| Error::InvalidJID(format!("Stored device has no phone number JID: {}", device.jid)))?;
+
+    Ok(ClientPayload {
+        username: Some(username),
+        device: Some(device.jid.device as u32),
+        // Offline messages are pulled once the client is ready for them
+        passive: Some(true),
+        pull: Some(true),
+        ..base_payload(props, version)
+    })
+}
+
+/// Static noise key and encoded login payload for the stored device
+pub fn login_credentials(device: &DeviceData, props: &DeviceProps, version: WebVersion) -> Result<(ECKeyPair, Vec<u8>)> {
+    let noise_keypair = ECKeyPair::from_private_bytes(&device.noise_key)?;
+    Ok((noise_keypair, login_payload(device, props, version)?.encode_to_vec()))
+}
+
+/// Server confirmation of a login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginSuccess {
+    /// Our hidden user (LID) JID
+    pub lid: Option<JID>,
+    /// Server time of the login
+    pub timestamp: Option<u64>,
+}
+
+/// Parse the `<success>` node the server sends after a login
+pub fn parse_login_success(node: &Node) -> Option<LoginSuccess> {
+    if node.tag != "success" {
+        return None;
+    }
+    Some(LoginSuccess {
+        lid: node.get_attr("lid").and_then(|lid| lid.parse().ok()),
+        timestamp: node.get_attr("t").and_then(|t| t.parse().ok()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::device_props::WA_WEB_VERSION;
+
+    fn device(jid: JID) -> DeviceData {
+        DeviceData {
+            jid,
+            registration_id: 1,
+            noise_key: ECKeyPair::generate().private_bytes().to_vec(),
+            identity_key: vec![],
+            signed_pre_key: vec![],
+            signed_pre_key_id: 1,
+            signed_pre_key_signature: vec![],
+        }
+    }
+
+    #[test]
+    fn test_login_payload() {
+        let stored = device(JID::new_user("1234567890").with_device(3));
+        let props = DeviceProps::default();
+        let (noise_keypair, payload) = login_credentials(&stored, &props, WA_WEB_VERSION).unwrap();
+        assert_eq!(noise_keypair.private_bytes().to_vec(), stored.noise_key);
+
+        let payload = ClientPayload::decode(payload.as_slice()).unwrap();
+        assert_eq!(payload.username, Some(1234567890));
+        assert_eq!(payload.device, Some(3));
+        assert_eq!(payload.passive, Some(true));
+        assert!(payload.device_pairing_data.is_none());
+        let version = payload.user_agent.unwrap().app_version.unwrap();
+        assert_eq!(version.tertiary, Some(WA_WEB_VERSION.0[2]));
+
+        assert!(login_payload(&device(JID::new_user("not-a-number")), &props, WA_WEB_VERSION).is_err());
+    }
+
+    #[test]
+    fn test_parse_login_success() {
+        let node = Node::builder("success").attr("t", "1700000000").attr("lid", "987@lid").build();
+        let success = parse_login_success(&node).unwrap();
+        assert_eq!(success.timestamp, Some(1700000000));
+        assert_eq!(success.lid.unwrap().server, "lid");
+        assert!(parse_login_success(&Node::builder("failure").build()).is_none());
+    }
+}
@@ -0,0 +1,38 @@
+/// Logout of a paired device
+///
+/// The companion asks the server to unlink it with a
+/// `<remove-companion-device>` IQ in the `md` namespace. The server then
+/// forgets the device, so its local credentials are useless afterwards and
+/// are deleted along with the Signal state.
+
+use crate::{devices, request::InfoQuery, types::JID};
+
+/// Reason of the [`Event::LoggedOut`](crate::types::Event::LoggedOut) emitted by an explicit logout
+pub const LOGOUT_REASON_INTENTIONAL: &str = "intentional";
+
+/// Options for [`Client::logout`](crate::Client::logout)
+#[derive(Debug, Clone, Default)]
+pub struct LogoutOptions {
+    /// Keep message history, chats, contacts and groups in the database
+    pub keep_messages: bool,
+}
+
+/// Build the query unlinking our device from the account
+pub fn build_logout_query(own_jid: &JID) -> InfoQuery {
+    devices::build_remove_companion_query(own_jid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logout_query() {
+        let query = build_logout_query(&JID::new_user("1234").with_device(5));
+        assert_eq!(query.namespace, "md");
+        let node = &query.content[0];
+        assert_eq!(node.tag, "remove-companion-device");
+        assert_eq!(node.get_attr("jid").map(String::as_str), Some("1234:5@s.whatsapp.net"));
+        assert_eq!(node.get_attr("reason").map(String::as_str), Some("user_initiated"));
+    }
+}
@@ -1,13 +1,21 @@
 /// Authentication and device registration for WhatsApp multi-device protocol
 
 pub mod qr;
+pub mod login;
+pub mod device_props;
+pub mod logout;
 pub mod pairing;
 pub mod multidevice;
 pub mod session;
 pub mod device;
+pub mod primary;
 
 use crate::{
     error::{Error, Result},
+    prekeys,
+    request::IqSender,
+    signal::SignalProtocolManager,
+    store::{DeviceData, Store},
     types::JID,
     util::keys::{ECKeyPair, SigningKeyPair},
 };
@@ -16,6 +24,10 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn, error};
 
 pub use qr::{QRData, QRChannel, QREvent, QRChannelConfig};
+pub use logout::LogoutOptions;
+pub use device_props::{Browser, DeviceProps, WebVersion};
+#[cfg(feature = "qr")]
+pub use qr::{render_terminal, save_png, QRDisplay};
 
 pub use pairing::{
     PairingFlow, PairingMethod, PairingState, PairingChallenge,
@@ -37,6 +49,11 @@ pub use device::{
     DevicePlatform, DeviceStore,
 };
 
+pub use primary::{
+    PrimaryDeviceMonitor, PrimaryDeviceMonitorConfig, PrimaryDeviceStatus,
+    PrimaryDeviceTransition,
+};
+
 /// Legacy QR code data for backward compatibility
 /// (Main QR functionality is now in qr module)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -156,6 +173,14 @@ pub struct AuthManager {
     device_manager: Arc<DeviceRegistrationManager>,
     qr_channel: Option<QRChannel>,
     background_handles: Vec<tokio::task::JoinHandle<()>>,
+    /// Sender for the key upload queries after pairing
+    iq_sender: Option<Arc<dyn IqSender>>,
+    /// Signal keys uploaded after pairing
+    signal_manager: Option<Arc<tokio::sync::Mutex<SignalProtocolManager>>>,
+    /// Store the paired device and its Signal state are saved to
+    store: Option<Arc<dyn Store>>,
+    /// Age after which the signed pre-key is replaced
+    signed_prekey_rotation: std::time::Duration,
 }
 
 use std::sync::Arc;
@@ -179,6 +204,10 @@ impl AuthManager {
             device_manager,
             qr_channel: None,
             background_handles: Vec::new(),
+            iq_sender: None,
+            signal_manager: None,
+            store: None,
+            signed_prekey_rotation: prekeys::DEFAULT_SIGNED_PREKEY_ROTATION,
         }
     }
     
@@ -200,6 +229,10 @@ impl AuthManager {
             device_manager,
             qr_channel: None,
             background_handles: Vec::new(),
+            iq_sender: None,
+            signal_manager: None,
+            store: None,
+            signed_prekey_rotation: prekeys::DEFAULT_SIGNED_PREKEY_ROTATION,
         }
     }
     
@@ -226,9 +259,37 @@ impl AuthManager {
             device_manager,
             qr_channel: None,
             background_handles: Vec::new(),
+            iq_sender: None,
+            signal_manager: None,
+            store: None,
+            signed_prekey_rotation: prekeys::DEFAULT_SIGNED_PREKEY_ROTATION,
         }
     }
     
+    /// Set the sender used to upload keys to the server
+    pub fn with_iq_sender(mut self, iq_sender: Arc<dyn IqSender>) -> Self {
+        self.iq_sender = Some(iq_sender);
+        self
+    }
+    
+    /// Set the Signal keys uploaded after pairing
+    pub fn with_signal_manager(mut self, signal_manager: Arc<tokio::sync::Mutex<SignalProtocolManager>>) -> Self {
+        self.signal_manager = Some(signal_manager);
+        self
+    }
+    
+    /// Set the store the paired device is saved to
+    pub fn with_store(mut self, store: Arc<dyn Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+    
+    /// Set the age after which the signed pre-key is replaced
+    pub fn with_signed_prekey_rotation(mut self, rotation: std::time::Duration) -> Self {
+        self.signed_prekey_rotation = rotation;
+        self
+    }
+    
     /// Start background services (session validation, device cleanup, etc.)
     pub async fn start_services(&mut self) -> Result<()> {
         // Load existing sessions and devices first
@@ -314,6 +375,33 @@ impl AuthManager {
         Ok(qr_string)
     }
     
+    /// Start rotating QR codes for the refs of a `<pair-device>` IQ.
+    ///
+    /// Replaces a rotation that is already running. The returned receiver
+    /// yields a [`QREvent::Code`] whenever the shown code should change and
+    /// [`QREvent::Timeout`] once all refs expired.
+    pub async fn start_qr_rotation(&mut self, refs: Vec<String>) -> Result<tokio::sync::mpsc::Receiver<QREvent>> {
+        if self.pairing_flow.is_none() {
+            self.start_pairing(PairingMethod::QRCode)?;
+        }
+        let pairing_flow = self.pairing_flow.as_mut()
+            .ok_or_else(|| Error::Auth("No pairing flow active".to_string()))?;
+        
+        pairing_flow.stop_qr_channel().await?;
+        pairing_flow.set_server_refs(refs);
+        pairing_flow.start_qr_channel().await?;
+        pairing_flow.take_qr_events()
+            .ok_or_else(|| Error::Auth("QR channel events already taken".to_string()))
+    }
+    
+    /// Stop the QR rotation because the phone scanned a code
+    pub async fn finish_qr_rotation(&mut self) -> Result<()> {
+        match &mut self.pairing_flow {
+            Some(pairing_flow) => pairing_flow.finish_qr_channel().await,
+            None => Ok(()),
+        }
+    }
+    
     /// Handle QR code scan response
     pub fn handle_qr_scan(&mut self, response_data: &[u8]) -> Result<()> {
         if let Some(pairing_flow) = &mut self.pairing_flow {
@@ -352,6 +440,10 @@ impl AuthManager {
             // Authenticate the session
             self.session_manager.authenticate_session(&jid, registration.clone()).await?;
             
+            // Peers can only start sessions with us once our keys are on the server
+            self.save_device(&registration).await?;
+            self.upload_keys().await?;
+            
             self.state = AuthState::AuthenticatedMultiDevice(registration.clone());
             
             // Stop QR channel if it was active
@@ -366,6 +458,55 @@ impl AuthManager {
         }
     }
     
+    /// Save the credentials of the paired device and switch the Signal
+    /// manager to its identity key and registration ID
+    async fn save_device(&mut self, registration: &DeviceRegistration) -> Result<()> {
+        let (Some(store), Some(signal_manager)) = (&self.store, &self.signal_manager) else {
+            warn!("No store configured, the paired device is not saved");
+            return Ok(());
+        };
+        
+        let device = DeviceData {
+            jid: registration.jid.clone(),
+            registration_id: registration.registration_id,
+            noise_key: registration.keys.noise_private_key.clone(),
+            identity_key: registration.keys.identity_private_key.clone(),
+            signed_pre_key: Vec::new(),
+            signed_pre_key_id: 0,
+            signed_pre_key_signature: Vec::new(),
+        };
+        store.save_device(&device).await?;
+        *signal_manager.lock().await = SignalProtocolManager::load(Arc::clone(store), &device).await?;
+        Ok(())
+    }
+    
+    /// Upload the identity key, signed pre-key and a batch of one-time pre-keys,
+    /// then keep rotating the signed pre-key in the background
+    async fn upload_keys(&mut self) -> Result<()> {
+        let (Some(iq_sender), Some(signal_manager)) = (self.iq_sender.clone(), self.signal_manager.clone()) else {
+            warn!("No IQ sender or Signal keys configured, skipping key upload");
+            return Ok(());
+        };
+        
+        prekeys::upload_prekeys(iq_sender.as_ref(), &signal_manager, prekeys::WANTED_PREKEY_COUNT).await?;
+        
+        let rotation = self.signed_prekey_rotation;
+        self.background_handles.push(tokio::spawn(async move {
+            loop {
+                let created_at = signal_manager.lock().await.current_signed_prekey().map(|key| key.timestamp).unwrap_or(0);
+                let wait = prekeys::next_rotation_in(created_at, rotation, std::time::SystemTime::now());
+                tokio::time::sleep(wait).await;
+                
+                if let Err(e) = prekeys::rotate_signed_prekey(iq_sender.as_ref(), &signal_manager).await {
+                    warn!("Failed to rotate signed pre-key: {}", e);
+                    // Try again later instead of spinning on an old key
+                    tokio::time::sleep(prekeys::DEFAULT_PREKEY_CHECK_INTERVAL.min(rotation)).await;
+                }
+            }
+        }));
+        Ok(())
+    }
+    
     /// Complete authentication process (legacy)
     pub fn complete_auth_legacy(&mut self, registration: LegacyDeviceRegistration) {
         self.state = AuthState::Authenticated(registration);
@@ -4,6 +4,8 @@ use crate::{
     error::{Error, Result},
     types::JID,
     auth::DeviceRegistration,
+    metrics,
+    msg_transport::{self, DecodedTransport},
     signal::{SignalProtocolManager, PreKeyBundle},
 };
 use serde::{Deserialize, Serialize};
@@ -263,6 +265,13 @@ impl MultiDeviceManager {
         Ok(plaintext)
     }
     
+    /// Decrypt an `enc` node payload from a contact device and unwrap its envelope
+    pub fn open_from_contact(&mut self, contact_jid: &str, device_id: u32, enc_version: &str, ciphertext: &[u8]) -> Result<DecodedTransport> {
+        let plaintext = self.decrypt_from_contact(contact_jid, device_id, ciphertext)
+            .inspect_err(|_| metrics::decrypt_failure())?;
+        msg_transport::open(enc_version, &plaintext)
+    }
+    
     /// Sync device list with server
     pub fn sync_device_list(&mut self) -> Result<Vec<u32>> {
         // In a real implementation, this would query the server for the current device list
@@ -455,6 +455,19 @@ impl PairingFlow {
         }
     }
     
+    /// Take the events of the running QR channel to consume them elsewhere
+    pub fn take_qr_events(&mut self) -> Option<tokio::sync::mpsc::Receiver<QREvent>> {
+        self.qr_channel.as_mut()?.take_events()
+    }
+    
+    /// Stop the QR channel after the phone scanned a code
+    pub async fn finish_qr_channel(&mut self) -> Result<()> {
+        if let Some(channel) = self.qr_channel.take() {
+            channel.signal_success().await?;
+        }
+        Ok(())
+    }
+    
     /// Stop QR channel
     pub async fn stop_qr_channel(&mut self) -> Result<()> {
         if let Some(mut channel) = self.qr_channel.take() {
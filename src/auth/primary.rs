@@ -0,0 +1,269 @@
+/// Primary device ("phone") status tracking for companion devices
+///
+/// A companion device keeps working while the phone is offline, but WhatsApp
+/// disables companions once the primary device is gone. The server says so
+/// in two ways: a `<notification type="devices">` about our own account
+/// removing device 0, or a stream error logging the companion out. This
+/// module turns those signals into degraded-mode transitions and reports the
+/// remaining grace period. The state only lives in memory.
+
+use crate::{binary::Node, types::JID};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Time after which WhatsApp unlinks companions of an inactive primary device
+pub const COMPANION_GRACE_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// Configuration of the primary device monitor
+#[derive(Debug, Clone)]
+pub struct PrimaryDeviceMonitorConfig {
+    /// Time after the primary device went away until the companion is expected to be unlinked
+    pub grace_period: Duration,
+}
+
+impl Default for PrimaryDeviceMonitorConfig {
+    fn default() -> Self {
+        Self {
+            grace_period: COMPANION_GRACE_PERIOD,
+        }
+    }
+}
+
+/// Snapshot of the primary device state
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrimaryDeviceStatus {
+    /// Last time a stanza from the primary device was received
+    pub last_seen: Option<SystemTime>,
+    /// Set since the server reported the primary device as gone
+    pub offline_since: Option<SystemTime>,
+    /// Time left before the companion is expected to be unlinked
+    pub remaining_grace_period: Option<Duration>,
+}
+
+/// Transition reported by the monitor
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrimaryDeviceTransition {
+    /// The server reported the primary device as gone at the given time
+    WentOffline { since: SystemTime },
+    /// The primary device was seen again after being offline
+    CameOnline,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    own_user: Option<String>,
+    last_seen: Option<SystemTime>,
+    offline_since: Option<SystemTime>,
+}
+
+/// Tracks the primary device from server notifications
+pub struct PrimaryDeviceMonitor {
+    config: PrimaryDeviceMonitorConfig,
+    state: Mutex<MonitorState>,
+}
+
+impl PrimaryDeviceMonitor {
+    /// Create a new monitor with default configuration
+    pub fn new() -> Self {
+        Self::with_config(PrimaryDeviceMonitorConfig::default())
+    }
+
+    /// Create a new monitor with custom configuration
+    pub fn with_config(config: PrimaryDeviceMonitorConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(MonitorState::default()),
+        }
+    }
+
+    /// Set the account whose primary device is monitored
+    pub fn set_own_user(&self, own_jid: &JID) {
+        self.state.lock().unwrap().own_user = Some(own_jid.user.clone());
+    }
+
+    /// Check whether the account to monitor is known
+    pub fn has_own_user(&self) -> bool {
+        self.state.lock().unwrap().own_user.is_some()
+    }
+
+    /// Inspect a received stanza for news about the primary device.
+    ///
+    /// A device notification of our own account removing device 0 takes the
+    /// primary device offline; any stanza from it brings it back.
+    pub fn observe_node(&self, node: &Node, now: SystemTime) -> Option<PrimaryDeviceTransition> {
+        if node.tag == "notification" && node.get_attr("type").map(String::as_str) == Some("devices") {
+            let own_account = node
+                .get_attr("from")
+                .and_then(|from| from.parse::<JID>().ok())
+                .is_some_and(|from| self.is_own_user(&from));
+            let primary_removed = own_account
+                && node.get_children().into_iter().flatten().any(|change| {
+                    change.tag == "remove"
+                        && change
+                            .get_children()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|device| device.get_attr("jid"))
+                            .filter_map(|jid| jid.parse::<JID>().ok())
+                            .any(|jid| jid.device == 0)
+                });
+            if primary_removed {
+                return self.record_offline("primary device removed from the account", now);
+            }
+        }
+
+        let from_primary = ["participant", "from"]
+            .iter()
+            .filter_map(|attr| node.get_attr(attr))
+            .filter_map(|jid| jid.parse::<JID>().ok())
+            .any(|jid| jid.device == 0 && self.is_own_user(&jid));
+        if from_primary {
+            self.record_activity(now)
+        } else {
+            None
+        }
+    }
+
+    /// Record that the server logged the companion out, e.g. with `device_removed`
+    pub fn observe_logout(&self, reason: &str, now: SystemTime) -> Option<PrimaryDeviceTransition> {
+        self.record_offline(reason, now)
+    }
+
+    /// Record activity of the primary device
+    pub fn record_activity(&self, now: SystemTime) -> Option<PrimaryDeviceTransition> {
+        let mut state = self.state.lock().unwrap();
+        state.last_seen = Some(now);
+
+        if state.offline_since.take().is_some() {
+            info!("Primary device is back online");
+            Some(PrimaryDeviceTransition::CameOnline)
+        } else {
+            None
+        }
+    }
+
+    fn record_offline(&self, reason: &str, now: SystemTime) -> Option<PrimaryDeviceTransition> {
+        let mut state = self.state.lock().unwrap();
+        if state.offline_since.is_some() {
+            return None;
+        }
+        state.offline_since = Some(now);
+        warn!(
+            "Primary device is gone ({}), companion will be unlinked in {:?}",
+            reason, self.config.grace_period
+        );
+        Some(PrimaryDeviceTransition::WentOffline { since: now })
+    }
+
+    /// Get the current primary device status
+    pub fn status(&self, now: SystemTime) -> PrimaryDeviceStatus {
+        let state = self.state.lock().unwrap();
+        let remaining_grace_period = state.offline_since.map(|since| {
+            let elapsed = now.duration_since(since).unwrap_or_default();
+            self.config.grace_period.saturating_sub(elapsed)
+        });
+
+        PrimaryDeviceStatus {
+            last_seen: state.last_seen,
+            offline_since: state.offline_since,
+            remaining_grace_period,
+        }
+    }
+
+    fn is_own_user(&self, jid: &JID) -> bool {
+        let state = self.state.lock().unwrap();
+        jid.is_user() && state.own_user.as_deref() == Some(jid.user.as_str())
+    }
+}
+
+impl Default for PrimaryDeviceMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(h: u64) -> Duration {
+        Duration::from_secs(h * 60 * 60)
+    }
+
+    fn own() -> JID {
+        JID::new("1234".to_string(), "s.whatsapp.net".to_string())
+    }
+
+    fn device_notification(from: &str, change: &str, device: &str) -> Node {
+        Node::new("notification".to_string())
+            .attr("type".to_string(), "devices".to_string())
+            .attr("from".to_string(), from.to_string())
+            .with_children(vec![Node::new(change.to_string()).with_children(vec![
+                Node::new("device".to_string()).attr("jid".to_string(), device.to_string()),
+            ])])
+    }
+
+    #[test]
+    fn test_removal_notification_and_recovery() {
+        let monitor = PrimaryDeviceMonitor::new();
+        let start = SystemTime::UNIX_EPOCH + hours(1000);
+        monitor.set_own_user(&own());
+
+        // Inactivity alone is no signal
+        assert_eq!(monitor.status(start + hours(100)).offline_since, None);
+
+        let removed = device_notification("1234@s.whatsapp.net", "remove", "1234@s.whatsapp.net");
+        assert_eq!(
+            monitor.observe_node(&removed, start),
+            Some(PrimaryDeviceTransition::WentOffline { since: start })
+        );
+        // Only signaled once
+        assert_eq!(monitor.observe_node(&removed, start + hours(1)), None);
+
+        let status = monitor.status(start + hours(48));
+        assert_eq!(status.offline_since, Some(start));
+        assert_eq!(status.remaining_grace_period, Some(COMPANION_GRACE_PERIOD - hours(48)));
+
+        let receipt = Node::new("receipt".to_string()).attr("from".to_string(), own().to_string());
+        assert_eq!(
+            monitor.observe_node(&receipt, start + hours(50)),
+            Some(PrimaryDeviceTransition::CameOnline)
+        );
+        let status = monitor.status(start + hours(50));
+        assert_eq!((status.offline_since, status.last_seen), (None, Some(start + hours(50))));
+    }
+
+    #[test]
+    fn test_logout_takes_primary_offline() {
+        let monitor = PrimaryDeviceMonitor::new();
+        let now = SystemTime::UNIX_EPOCH + hours(10);
+        assert_eq!(
+            monitor.observe_logout("device_removed", now),
+            Some(PrimaryDeviceTransition::WentOffline { since: now })
+        );
+        assert_eq!(monitor.status(now).remaining_grace_period, Some(COMPANION_GRACE_PERIOD));
+    }
+
+    #[test]
+    fn test_ignores_other_devices() {
+        let monitor = PrimaryDeviceMonitor::new();
+        let now = SystemTime::UNIX_EPOCH + hours(10);
+        monitor.set_own_user(&own());
+
+        // Companions leaving and other accounts changing are not about our phone
+        let companion_removed = device_notification("1234@s.whatsapp.net", "remove", "1234:3@s.whatsapp.net");
+        let stranger_removed = device_notification("999@s.whatsapp.net", "remove", "999@s.whatsapp.net");
+        assert_eq!(monitor.observe_node(&companion_removed, now), None);
+        assert_eq!(monitor.observe_node(&stranger_removed, now), None);
+
+        monitor.observe_logout("device_removed", now);
+        let companion = Node::new("message".to_string())
+            .attr("from".to_string(), "1234:3@s.whatsapp.net".to_string());
+        let stranger = Node::new("message".to_string())
+            .attr("from".to_string(), "999@s.whatsapp.net".to_string());
+        assert_eq!(monitor.observe_node(&companion, now + hours(1)), None);
+        assert_eq!(monitor.observe_node(&stranger, now + hours(1)), None);
+        assert!(monitor.status(now + hours(1)).offline_since.is_some());
+    }
+}
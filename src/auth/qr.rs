@@ -4,11 +4,15 @@
 /// including QR generation, channel management, timeout handling, and refresh cycles.
 
 use crate::{
+    binary::Node,
     error::{Error, Result}, 
+    types::jid::DEFAULT_USER_SERVER,
     util::keys::{ECKeyPair, SigningKeyPair},
 };
 use base64::{Engine as _, engine::general_purpose::STANDARD};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "qr")]
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, watch};
 use tracing::{debug, warn, info};
@@ -21,7 +25,22 @@ pub enum QREvent {
         code: String, 
         timeout: Duration,
         expires_at: Instant,
+        /// Wall clock time the code was issued
+        issued_at: SystemTime,
+        /// 1-based position of this code in the rotation
+        index: usize,
+        /// Maximum number of codes in the rotation
+        total: usize,
     },
+    /// Periodic countdown for the currently displayed code
+    Countdown {
+        remaining: Duration,
+        expires_at: Instant,
+    },
+    /// The phone scanned the code; code rotation stops
+    ScanDetected { at: SystemTime },
+    /// The pairing handshake with the phone is running
+    PairingInProgress { at: SystemTime },
     /// QR code scanning succeeded
     Success,
     /// QR code operation timed out
@@ -126,6 +145,74 @@ impl QRData {
     pub fn time_until_expiration(&self) -> Option<Duration> {
         self.expires_at.duration_since(SystemTime::now()).ok()
     }
+    
+    /// Render the code as unicode half blocks for printing to a terminal
+    #[cfg(feature = "qr")]
+    pub fn render_terminal(&self) -> Result<String> {
+        render_terminal(&self.to_qr_string())
+    }
+    
+    /// Write the code to a PNG image at `path`
+    #[cfg(feature = "qr")]
+    pub fn to_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_png(&self.to_qr_string(), path)
+    }
+}
+
+/// Minimum width and height of PNG codes in pixels
+#[cfg(feature = "qr")]
+pub const PNG_MIN_SIZE: u32 = 256;
+
+/// Where the QR channel renders each code as it rotates
+#[cfg(feature = "qr")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QRDisplay {
+    /// Print every code to stdout
+    Terminal,
+    /// Overwrite the PNG image at this path with every code
+    Png(PathBuf),
+}
+
+#[cfg(feature = "qr")]
+impl QRDisplay {
+    /// Render a code, replacing the previously shown one
+    pub fn show(&self, code: &str) -> Result<()> {
+        match self {
+            QRDisplay::Terminal => {
+                println!("{}", render_terminal(code)?);
+                Ok(())
+            }
+            QRDisplay::Png(path) => save_png(code, path),
+        }
+    }
+}
+
+#[cfg(feature = "qr")]
+fn encode_qr(code: &str) -> Result<qrcode::QrCode> {
+    qrcode::QrCode::new(code).map_err(|e| Error::Auth(format!("Failed to encode QR code: {}", e)))
+}
+
+/// Render a QR code string as unicode half blocks, two modules per character
+#[cfg(feature = "qr")]
+pub fn render_terminal(code: &str) -> Result<String> {
+    // Blocks draw the light modules, so the code reads right on dark terminals
+    Ok(encode_qr(code)?
+        .render::<qrcode::render::unicode::Dense1x2>()
+        .dark_color(qrcode::render::unicode::Dense1x2::Light)
+        .light_color(qrcode::render::unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Write a QR code string to a PNG image at `path`
+#[cfg(feature = "qr")]
+pub fn save_png(code: &str, path: impl AsRef<Path>) -> Result<()> {
+    encode_qr(code)?
+        .render::<image::Luma<u8>>()
+        .min_dimensions(PNG_MIN_SIZE, PNG_MIN_SIZE)
+        .build()
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| Error::Io(format!("Failed to write QR code image: {}", e)))
 }
 
 /// QR channel configuration
@@ -139,6 +226,11 @@ pub struct QRChannelConfig {
     pub max_codes: usize,
     /// Buffer size for the event channel
     pub channel_buffer_size: usize,
+    /// Interval of countdown events for the displayed code, `None` disables them
+    pub countdown_interval: Option<Duration>,
+    /// Render every new code here in addition to sending the event
+    #[cfg(feature = "qr")]
+    pub display: Option<QRDisplay>,
 }
 
 impl Default for QRChannelConfig {
@@ -148,10 +240,59 @@ impl Default for QRChannelConfig {
             standard_timeout: Duration::from_secs(20),
             max_codes: 6, // WhatsApp typically sends 6 QR codes max
             channel_buffer_size: 16,
+            countdown_interval: Some(Duration::from_secs(1)),
+            #[cfg(feature = "qr")]
+            display: None,
         }
     }
 }
 
+/// Localized step-by-step instructions for scanning the pairing QR code.
+///
+/// `locale` is matched on its language part (`pt-BR` uses `pt`); unknown
+/// languages fall back to English.
+pub fn pairing_instructions(locale: &str) -> &'static [&'static str] {
+    let language = locale.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+    match language.as_str() {
+        "es" => &[
+            "Abre WhatsApp en tu teléfono",
+            "Toca Menú o Configuración y selecciona Dispositivos vinculados",
+            "Toca Vincular un dispositivo",
+            "Apunta tu teléfono a esta pantalla para escanear el código QR",
+        ],
+        "pt" => &[
+            "Abra o WhatsApp no seu celular",
+            "Toque em Mais opções ou Configurações e selecione Aparelhos conectados",
+            "Toque em Conectar um aparelho",
+            "Aponte seu celular para esta tela para escanear o QR code",
+        ],
+        "de" => &[
+            "Öffne WhatsApp auf deinem Telefon",
+            "Tippe auf Menü oder Einstellungen und wähle Verknüpfte Geräte",
+            "Tippe auf Gerät hinzufügen",
+            "Richte dein Telefon auf diesen Bildschirm, um den QR-Code zu scannen",
+        ],
+        "fr" => &[
+            "Ouvrez WhatsApp sur votre téléphone",
+            "Appuyez sur Menu ou Paramètres et sélectionnez Appareils connectés",
+            "Appuyez sur Connecter un appareil",
+            "Pointez votre téléphone vers cet écran pour scanner le code QR",
+        ],
+        "id" => &[
+            "Buka WhatsApp di telepon Anda",
+            "Ketuk Menu atau Setelan dan pilih Perangkat tertaut",
+            "Ketuk Tautkan perangkat",
+            "Arahkan telepon Anda ke layar ini untuk memindai kode QR",
+        ],
+        _ => &[
+            "Open WhatsApp on your phone",
+            "Tap Menu or Settings and select Linked Devices",
+            "Tap Link a Device",
+            "Point your phone at this screen to scan the QR code",
+        ],
+    }
+}
+
 /// QR channel manager for handling QR code lifecycle
 pub struct QRChannel {
     config: QRChannelConfig,
@@ -159,7 +300,8 @@ pub struct QRChannel {
     identity_keypair: SigningKeyPair,
     adv_secret: Vec<u8>,
     event_sender: mpsc::Sender<QREvent>,
-    event_receiver: mpsc::Receiver<QREvent>,
+    /// `None` once taken with [`QRChannel::take_events`]
+    event_receiver: Option<mpsc::Receiver<QREvent>>,
     shutdown_sender: watch::Sender<bool>,
     shutdown_receiver: watch::Receiver<bool>,
     is_active: bool,
@@ -183,7 +325,7 @@ impl QRChannel {
             identity_keypair: SigningKeyPair::generate(),
             adv_secret: crate::util::crypto::random_bytes(32),
             event_sender,
-            event_receiver,
+            event_receiver: Some(event_receiver),
             shutdown_sender,
             shutdown_receiver,
             is_active: false,
@@ -207,7 +349,7 @@ impl QRChannel {
             identity_keypair,
             adv_secret,
             event_sender,
-            event_receiver,
+            event_receiver: Some(event_receiver),
             shutdown_sender,
             shutdown_receiver,
             is_active: false,
@@ -227,6 +369,7 @@ impl QRChannel {
         
         self.is_active = true;
         self.codes_generated = 0;
+        let _ = self.shutdown_sender.send(false);
         
         info!("Starting QR channel with {} reference codes", ref_codes.len());
         
@@ -265,6 +408,7 @@ impl QRChannel {
     ) {
         let mut codes_iter = ref_codes.into_iter();
         let mut codes_sent = 0;
+        let mut stopped = false;
         
         while codes_sent < config.max_codes {
             // Check for shutdown signal
@@ -298,11 +442,21 @@ impl QRChannel {
             
             debug!("Generating QR code {} with timeout {:?}", codes_sent + 1, timeout);
             
+            #[cfg(feature = "qr")]
+            if let Some(display) = &config.display {
+                if let Err(e) = display.show(&qr_string) {
+                    warn!("Failed to render QR code: {}", e);
+                }
+            }
+            
             // Send QR code event
             let event = QREvent::Code {
                 code: qr_string,
                 timeout,
                 expires_at,
+                issued_at: SystemTime::now(),
+                index: codes_sent + 1,
+                total: config.max_codes,
             };
             
             if let Err(e) = event_sender.send(event).await {
@@ -312,22 +466,40 @@ impl QRChannel {
             
             codes_sent += 1;
             
-            // Wait for timeout or shutdown
-            tokio::select! {
-                _ = tokio::time::sleep(timeout) => {
-                    debug!("QR code {} timed out, generating next", codes_sent);
-                }
-                _ = shutdown_receiver.changed() => {
-                    if *shutdown_receiver.borrow() {
-                        debug!("QR generation task shutting down");
-                        break;
+            // Wait for timeout or shutdown, ticking the countdown in between
+            loop {
+                let remaining = expires_at.saturating_duration_since(Instant::now());
+                let tick = config
+                    .countdown_interval
+                    .filter(|interval| !interval.is_zero())
+                    .map(|interval| interval.min(remaining))
+                    .unwrap_or(remaining);
+                
+                tokio::select! {
+                    _ = tokio::time::sleep(tick) => {
+                        let remaining = expires_at.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            debug!("QR code {} timed out, generating next", codes_sent);
+                            break;
+                        }
+                        let _ = event_sender.send(QREvent::Countdown { remaining, expires_at }).await;
+                    }
+                    _ = shutdown_receiver.changed() => {
+                        if *shutdown_receiver.borrow() {
+                            debug!("QR generation task shutting down");
+                            stopped = true;
+                            break;
+                        }
                     }
                 }
             }
+            if stopped {
+                break;
+            }
         }
         
         // If we exhausted all codes, send timeout
-        if codes_sent >= config.max_codes {
+        if !stopped && codes_sent >= config.max_codes {
             warn!("Exhausted all QR codes, sending timeout event");
             let _ = event_sender.send(QREvent::Timeout).await;
         }
@@ -337,7 +509,14 @@ impl QRChannel {
     
     /// Get the next QR event
     pub async fn next_event(&mut self) -> Option<QREvent> {
-        self.event_receiver.recv().await
+        self.event_receiver.as_mut()?.recv().await
+    }
+    
+    /// Take the event receiver to consume events outside the channel.
+    ///
+    /// [`next_event`](Self::next_event) returns `None` afterwards.
+    pub fn take_events(&mut self) -> Option<mpsc::Receiver<QREvent>> {
+        self.event_receiver.take()
     }
     
     /// Stop the QR channel
@@ -354,21 +533,38 @@ impl QRChannel {
         }
         
         // Drain any remaining events
-        while let Ok(_) = self.event_receiver.try_recv() {
-            // Drain the channel
+        if let Some(receiver) = &mut self.event_receiver {
+            while receiver.try_recv().is_ok() {}
         }
         
         Ok(())
     }
     
-    /// Signal successful pairing
+    /// Signal that the phone scanned the code, stopping code rotation
+    pub async fn signal_scan_detected(&self) -> Result<()> {
+        if let Err(e) = self.shutdown_sender.send(true) {
+            warn!("Failed to stop QR rotation: {}", e);
+        }
+        self.event_sender.send(QREvent::ScanDetected { at: SystemTime::now() }).await
+            .map_err(|e| Error::Auth(format!("Failed to signal scan: {}", e)))
+    }
+    
+    /// Signal that the pairing handshake is running
+    pub async fn signal_pairing_in_progress(&self) -> Result<()> {
+        self.event_sender.send(QREvent::PairingInProgress { at: SystemTime::now() }).await
+            .map_err(|e| Error::Auth(format!("Failed to signal pairing progress: {}", e)))
+    }
+    
+    /// Signal successful pairing, stopping code rotation
     pub async fn signal_success(&self) -> Result<()> {
+        let _ = self.shutdown_sender.send(true);
         self.event_sender.send(QREvent::Success).await
             .map_err(|e| Error::Auth(format!("Failed to signal success: {}", e)))
     }
     
-    /// Signal pairing error
+    /// Signal pairing error, stopping code rotation
     pub async fn signal_error(&self, error: String) -> Result<()> {
+        let _ = self.shutdown_sender.send(true);
         self.event_sender.send(QREvent::Error(error)).await
             .map_err(|e| Error::Auth(format!("Failed to signal error: {}", e)))
     }
@@ -423,6 +619,42 @@ impl Default for QRChannel {
     }
 }
 
+/// Refs of a `<pair-device>` IQ.
+///
+/// Unpaired clients receive this right after connecting. Each ref becomes
+/// one QR code, shown in order until the phone scans one of them.
+pub fn parse_pair_device(node: &Node) -> Option<Vec<String>> {
+    if node.tag != "iq" {
+        return None;
+    }
+    let refs = node
+        .find_child("pair-device")?
+        .get_children()
+        .into_iter()
+        .flatten()
+        .filter(|child| child.tag == "ref")
+        .filter_map(|child| match child.get_text() {
+            Some(text) => Some(text.clone()),
+            None => String::from_utf8(child.get_binary()?.clone()).ok(),
+        })
+        .collect();
+    Some(refs)
+}
+
+/// Whether the node is the `<pair-success>` IQ sent after the phone scanned a code
+pub fn is_pair_success(node: &Node) -> bool {
+    node.tag == "iq" && node.find_child("pair-success").is_some()
+}
+
+/// Empty result acknowledging a pairing IQ from the server
+pub fn pairing_ack(node: &Node) -> Node {
+    Node::builder("iq")
+        .attr("to", DEFAULT_USER_SERVER)
+        .attr("type", "result")
+        .attr_opt("id", node.get_attr("id"))
+        .build()
+}
+
 /// QR channel iterator for convenient usage
 pub struct QRChannelIterator {
     channel: QRChannel,
@@ -508,6 +740,8 @@ mod tests {
             standard_timeout: Duration::from_millis(50),
             max_codes: 2,
             channel_buffer_size: 16,
+            countdown_interval: None,
+            ..Default::default()
         });
         
         let ref_codes = vec!["ref1".to_string(), "ref2".to_string()];
@@ -518,8 +752,10 @@ mod tests {
         // Should receive first QR code
         let event = timeout(Duration::from_millis(200), qr_channel.next_event()).await.unwrap();
         match event {
-            Some(QREvent::Code { code, timeout: _, expires_at: _ }) => {
+            Some(QREvent::Code { code, timeout: _, expires_at: _, index, total, .. }) => {
                 assert!(code.contains("ref1"));
+                assert_eq!(index, 1);
+                assert_eq!(total, 2);
             }
             other => panic!("Expected QR code event, got {:?}", other),
         }
@@ -535,6 +771,8 @@ mod tests {
             standard_timeout: Duration::from_millis(50),
             max_codes: 1,
             channel_buffer_size: 16,
+            countdown_interval: None,
+            ..Default::default()
         });
         
         let ref_codes = vec!["ref1".to_string()];
@@ -551,6 +789,144 @@ mod tests {
         qr_channel.stop().await.unwrap();
     }
     
+    #[tokio::test]
+    async fn test_qr_channel_countdown_and_scan() {
+        let mut qr_channel = QRChannel::with_config(QRChannelConfig {
+            initial_timeout: Duration::from_millis(200),
+            standard_timeout: Duration::from_millis(200),
+            max_codes: 1,
+            channel_buffer_size: 16,
+            countdown_interval: Some(Duration::from_millis(40)),
+            ..Default::default()
+        });
+        qr_channel.start(vec!["ref1".to_string()]).await.unwrap();
+        
+        let event = timeout(Duration::from_millis(100), qr_channel.next_event()).await.unwrap();
+        assert!(matches!(event, Some(QREvent::Code { .. })));
+        
+        let event = timeout(Duration::from_millis(100), qr_channel.next_event()).await.unwrap();
+        match event {
+            Some(QREvent::Countdown { remaining, .. }) => assert!(remaining < Duration::from_millis(200)),
+            other => panic!("Expected countdown event, got {:?}", other),
+        }
+        
+        qr_channel.signal_scan_detected().await.unwrap();
+        qr_channel.signal_pairing_in_progress().await.unwrap();
+        qr_channel.signal_success().await.unwrap();
+        
+        let mut events = Vec::new();
+        while let Ok(Some(event)) = timeout(Duration::from_millis(300), qr_channel.next_event()).await {
+            events.push(event);
+            if events.last() == Some(&QREvent::Success) {
+                break;
+            }
+        }
+        let events: Vec<_> = events.into_iter().filter(|e| !matches!(e, QREvent::Countdown { .. })).collect();
+        assert!(matches!(events[0], QREvent::ScanDetected { .. }));
+        assert!(matches!(events[1], QREvent::PairingInProgress { .. }));
+        assert_eq!(events[2], QREvent::Success);
+        
+        // Rotation stopped, so no timeout follows
+        assert!(timeout(Duration::from_millis(300), qr_channel.next_event()).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_qr_channel_rotation() {
+        let mut qr_channel = QRChannel::with_config(QRChannelConfig {
+            initial_timeout: Duration::from_millis(60),
+            standard_timeout: Duration::from_millis(20),
+            max_codes: 6,
+            countdown_interval: None,
+            ..Default::default()
+        });
+        qr_channel.start(vec!["ref1".to_string(), "ref2".to_string(), "ref3".to_string()]).await.unwrap();
+        let mut events = qr_channel.take_events().unwrap();
+        assert!(qr_channel.next_event().await.is_none());
+        
+        let mut codes = Vec::new();
+        loop {
+            match timeout(Duration::from_millis(500), events.recv()).await.unwrap() {
+                Some(QREvent::Code { code, timeout, .. }) => codes.push((code, timeout)),
+                Some(QREvent::Timeout) => break,
+                other => panic!("Unexpected event {:?}", other),
+            }
+        }
+        let refs: Vec<_> = codes.iter().map(|(code, _)| code.split(',').next().unwrap()).collect();
+        assert_eq!(refs, ["ref1", "ref2", "ref3"]);
+        // The first code stays valid longer than the rest
+        assert_eq!(codes[0].1, Duration::from_millis(60));
+        assert!(codes[1..].iter().all(|(_, timeout)| *timeout == Duration::from_millis(20)));
+    }
+    
+    #[tokio::test]
+    async fn test_qr_channel_stops_on_success() {
+        let mut qr_channel = QRChannel::with_config(QRChannelConfig {
+            initial_timeout: Duration::from_millis(30),
+            standard_timeout: Duration::from_millis(30),
+            countdown_interval: None,
+            ..Default::default()
+        });
+        qr_channel.start(vec!["ref1".to_string(), "ref2".to_string()]).await.unwrap();
+        let event = timeout(Duration::from_millis(100), qr_channel.next_event()).await.unwrap();
+        assert!(matches!(event, Some(QREvent::Code { .. })));
+        
+        qr_channel.signal_success().await.unwrap();
+        assert_eq!(timeout(Duration::from_millis(100), qr_channel.next_event()).await.unwrap(), Some(QREvent::Success));
+        // Neither the second code nor a timeout follows
+        assert!(timeout(Duration::from_millis(150), qr_channel.next_event()).await.is_err());
+    }
+    
+    #[test]
+    fn test_parse_pair_device() {
+        let node = Node::builder("iq")
+            .attr("id", "42")
+            .attr("type", "set")
+            .child("pair-device", |pair| {
+                pair.node(Node::builder("ref").text("ref1").build())
+                    .node(Node::builder("ref").bytes(b"ref2".to_vec()).build())
+            })
+            .build();
+        assert_eq!(parse_pair_device(&node).unwrap(), ["ref1", "ref2"]);
+        assert!(parse_pair_device(&Node::builder("iq").build()).is_none());
+        
+        let ack = pairing_ack(&node);
+        assert_eq!(ack.get_attr("id").map(String::as_str), Some("42"));
+        assert_eq!(ack.get_attr("type").map(String::as_str), Some("result"));
+        
+        assert!(is_pair_success(&Node::builder("iq").empty_child("pair-success").build()));
+        assert!(!is_pair_success(&node));
+    }
+    
+    #[test]
+    fn test_pairing_instructions() {
+        assert_eq!(pairing_instructions("en")[0], "Open WhatsApp on your phone");
+        assert_eq!(pairing_instructions("pt-BR")[0], "Abra o WhatsApp no seu celular");
+        assert_eq!(pairing_instructions("es_MX").len(), 4);
+        assert_eq!(pairing_instructions("xx"), pairing_instructions("en"));
+    }
+    
+    #[cfg(feature = "qr")]
+    #[test]
+    fn test_qr_rendering() {
+        let qr_data = QRData::new("ref".to_string(), &ECKeyPair::generate(), &SigningKeyPair::generate(), vec![7; 32]);
+        
+        let rendered = qr_data.render_terminal().unwrap();
+        let lines: Vec<_> = rendered.lines().collect();
+        assert!(lines.len() > 10);
+        assert!(lines.iter().all(|line| line.chars().count() == lines[0].chars().count()));
+        
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qr.png");
+        qr_data.to_png(&path).unwrap();
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.width(), image.height());
+        assert!(image.width() >= PNG_MIN_SIZE);
+        
+        // Rotation overwrites the same file
+        QRDisplay::Png(path.clone()).show("other-ref,a,b,c").unwrap();
+        assert!(image::open(&path).is_ok());
+    }
+    
     #[test]
     fn test_qr_data_expiration() {
         let noise_keypair = ECKeyPair::generate();
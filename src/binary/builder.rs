@@ -0,0 +1,397 @@
+/// Builder DSL for binary protocol nodes
+///
+/// [`NodeBuilder`] builds arbitrary nodes with nested children:
+///
+/// ```ignore
+/// let create = Node::builder("create")
+///     .attr("subject", "Team")
+///     .child("participant", |p| p.attr("jid", &member))
+///     .build();
+/// ```
+///
+/// The common stanzas get typed builders on top of it. An IQ can only be
+/// built once its type is chosen (`Node::iq().to(&server).set().query("w:g2", |q| ...)`),
+/// and messages and receipts take their required id and recipient up front.
+
+use super::{Node, NodeContent};
+use crate::types::JID;
+use std::marker::PhantomData;
+
+/// Builder for a single node and its children
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    node: Node,
+}
+
+impl NodeBuilder {
+    /// Start a node with the given tag
+    pub fn new(tag: impl Into<String>) -> Self {
+        Self { node: Node::new(tag.into()) }
+    }
+
+    /// Set an attribute
+    pub fn attr(mut self, key: &str, value: impl ToString) -> Self {
+        self.node.attrs.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set an attribute if a value is given
+    pub fn attr_opt(self, key: &str, value: Option<impl ToString>) -> Self {
+        match value {
+            Some(value) => self.attr(key, value),
+            None => self,
+        }
+    }
+
+    /// Append a child built by `build`.
+    ///
+    /// Content is either text, binary or children; adding a child replaces
+    /// text or binary content.
+    pub fn child(self, tag: &str, build: impl FnOnce(NodeBuilder) -> NodeBuilder) -> Self {
+        self.node(build(NodeBuilder::new(tag)).build())
+    }
+
+    /// Append a child without attributes or content
+    pub fn empty_child(self, tag: &str) -> Self {
+        self.node(Node::new(tag.to_string()))
+    }
+
+    /// Append an already built child
+    pub fn node(mut self, child: Node) -> Self {
+        match &mut self.node.content {
+            NodeContent::Children(children) => children.push(child),
+            content => *content = NodeContent::Children(vec![child]),
+        }
+        self
+    }
+
+    /// Append already built children
+    pub fn nodes(self, children: impl IntoIterator<Item = Node>) -> Self {
+        children.into_iter().fold(self, NodeBuilder::node)
+    }
+
+    /// Set text content
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.node.content = NodeContent::Text(text.into());
+        self
+    }
+
+    /// Set binary content
+    pub fn bytes(mut self, data: Vec<u8>) -> Self {
+        self.node.content = NodeContent::Binary(data);
+        self
+    }
+
+    /// Finish the node
+    pub fn build(self) -> Node {
+        self.node
+    }
+}
+
+impl From<NodeBuilder> for Node {
+    fn from(builder: NodeBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// IQ whose type has not been chosen yet
+#[derive(Debug, Clone)]
+pub struct Untyped;
+
+/// IQ with a type, ready to be built
+#[derive(Debug, Clone)]
+pub struct Typed;
+
+/// Builder for `iq` stanzas
+#[derive(Debug, Clone)]
+pub struct IqBuilder<State = Untyped> {
+    node: NodeBuilder,
+    state: PhantomData<State>,
+}
+
+impl<State> IqBuilder<State> {
+    fn with_state<Next>(self) -> IqBuilder<Next> {
+        IqBuilder { node: self.node, state: PhantomData }
+    }
+
+    /// Set the request id
+    pub fn id(mut self, id: &str) -> Self {
+        self.node = self.node.attr("id", id);
+        self
+    }
+
+    /// Set the recipient
+    pub fn to(mut self, to: &JID) -> Self {
+        self.node = self.node.attr("to", to);
+        self
+    }
+
+    /// Set the target JID, e.g. of group queries
+    pub fn target(mut self, target: &JID) -> Self {
+        self.node = self.node.attr("target", target);
+        self
+    }
+
+    /// Set the namespace and the children of the query
+    pub fn query(mut self, namespace: &str, build: impl FnOnce(NodeBuilder) -> NodeBuilder) -> Self {
+        let content = build(NodeBuilder::new("")).build();
+        self.node = self.node.attr("xmlns", namespace);
+        if let NodeContent::Children(children) = content.content {
+            self.node = self.node.nodes(children);
+        }
+        self
+    }
+}
+
+impl IqBuilder<Untyped> {
+    /// Make this a `get` query
+    pub fn get(self) -> IqBuilder<Typed> {
+        self.kind("get")
+    }
+
+    /// Make this a `set` query
+    pub fn set(self) -> IqBuilder<Typed> {
+        self.kind("set")
+    }
+
+    /// Make this a `result` response
+    pub fn result(self) -> IqBuilder<Typed> {
+        self.kind("result")
+    }
+
+    fn kind(mut self, kind: &str) -> IqBuilder<Typed> {
+        self.node = self.node.attr("type", kind);
+        self.with_state()
+    }
+}
+
+impl IqBuilder<Typed> {
+    /// Finish the stanza
+    pub fn build(self) -> Node {
+        self.node.build()
+    }
+}
+
+/// Builder for `message` stanzas
+#[derive(Debug, Clone)]
+pub struct MessageStanzaBuilder {
+    node: NodeBuilder,
+}
+
+impl MessageStanzaBuilder {
+    /// Set the message type (`text`, `media`, `reaction`, ...)
+    pub fn kind(mut self, kind: &str) -> Self {
+        self.node = self.node.attr("type", kind);
+        self
+    }
+
+    /// Set the sender
+    pub fn from(mut self, from: &JID) -> Self {
+        self.node = self.node.attr("from", from);
+        self
+    }
+
+    /// Set the participant, for messages sent on behalf of a group member
+    pub fn participant(mut self, participant: &JID) -> Self {
+        self.node = self.node.attr("participant", participant);
+        self
+    }
+
+    /// Set any other attribute
+    pub fn attr(mut self, key: &str, value: impl ToString) -> Self {
+        self.node = self.node.attr(key, value);
+        self
+    }
+
+    /// Append a child built by `build`
+    pub fn child(mut self, tag: &str, build: impl FnOnce(NodeBuilder) -> NodeBuilder) -> Self {
+        self.node = self.node.child(tag, build);
+        self
+    }
+
+    /// Append already built children
+    pub fn nodes(mut self, children: impl IntoIterator<Item = Node>) -> Self {
+        self.node = self.node.nodes(children);
+        self
+    }
+
+    /// Finish the stanza
+    pub fn build(self) -> Node {
+        self.node.build()
+    }
+}
+
+/// Builder for `receipt` stanzas
+#[derive(Debug, Clone)]
+pub struct ReceiptBuilder {
+    node: NodeBuilder,
+}
+
+impl ReceiptBuilder {
+    /// Set the receipt type (`read`, `played`, ...); plain delivery receipts have none
+    pub fn kind(mut self, kind: &str) -> Self {
+        self.node = self.node.attr("type", kind);
+        self
+    }
+
+    /// Set the participant who sent the message in a group
+    pub fn participant(mut self, participant: &JID) -> Self {
+        self.node = self.node.attr("participant", participant);
+        self
+    }
+
+    /// Set the time the receipt was generated
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.node = self.node.attr("t", timestamp);
+        self
+    }
+
+    /// Acknowledge more messages of the same chat in one receipt
+    pub fn more_ids<'a>(mut self, ids: impl IntoIterator<Item = &'a str>) -> Self {
+        let items: Vec<Node> = ids
+            .into_iter()
+            .map(|id| NodeBuilder::new("item").attr("id", id).build())
+            .collect();
+        if !items.is_empty() {
+            self.node = self.node.child("list", |list| list.nodes(items));
+        }
+        self
+    }
+
+    /// Finish the stanza
+    pub fn build(self) -> Node {
+        self.node.build()
+    }
+}
+
+/// Builder for `presence` stanzas
+#[derive(Debug, Clone)]
+pub struct PresenceBuilder {
+    node: NodeBuilder,
+}
+
+impl PresenceBuilder {
+    /// Announce the client as available
+    pub fn available(mut self) -> Self {
+        self.node = self.node.attr("type", "available");
+        self
+    }
+
+    /// Announce the client as unavailable
+    pub fn unavailable(mut self) -> Self {
+        self.node = self.node.attr("type", "unavailable");
+        self
+    }
+
+    /// Set the push name shown to contacts
+    pub fn name(mut self, name: &str) -> Self {
+        self.node = self.node.attr("name", name);
+        self
+    }
+
+    /// Address the presence to a JID, e.g. to subscribe to it
+    pub fn to(mut self, to: &JID) -> Self {
+        self.node = self.node.attr("to", to);
+        self
+    }
+
+    /// Subscribe to the presence of the recipient
+    pub fn subscribe(mut self) -> Self {
+        self.node = self.node.attr("type", "subscribe");
+        self
+    }
+
+    /// Finish the stanza
+    pub fn build(self) -> Node {
+        self.node.build()
+    }
+}
+
+impl Node {
+    /// Start building a node with the given tag
+    pub fn builder(tag: &str) -> NodeBuilder {
+        NodeBuilder::new(tag)
+    }
+
+    /// Start building an `iq` stanza
+    pub fn iq() -> IqBuilder<Untyped> {
+        IqBuilder { node: NodeBuilder::new("iq"), state: PhantomData }
+    }
+
+    /// Start building a `message` stanza
+    pub fn message(id: &str, to: &JID) -> MessageStanzaBuilder {
+        MessageStanzaBuilder { node: NodeBuilder::new("message").attr("id", id).attr("to", to) }
+    }
+
+    /// Start building a `receipt` stanza for the message with the given id
+    pub fn receipt(id: &str, to: &JID) -> ReceiptBuilder {
+        ReceiptBuilder { node: NodeBuilder::new("receipt").attr("id", id).attr("to", to) }
+    }
+
+    /// Start building a `presence` stanza
+    pub fn presence() -> PresenceBuilder {
+        PresenceBuilder { node: NodeBuilder::new("presence") }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_builder() {
+        let member = JID::new("123".to_string(), "s.whatsapp.net".to_string());
+        let node = Node::builder("create")
+            .attr("subject", "Team")
+            .attr_opt("key", None::<&str>)
+            .child("participant", |p| p.attr("jid", &member))
+            .child("description", |d| d.child("body", |b| b.text("Hello")))
+            .build();
+
+        let expected = Node::new("create".to_string())
+            .attr("subject".to_string(), "Team".to_string())
+            .with_children(vec![
+                Node::new("participant".to_string()).attr("jid".to_string(), member.to_string()),
+                Node::new("description".to_string())
+                    .with_children(vec![Node::new("body".to_string()).with_text("Hello".to_string())]),
+            ]);
+        assert_eq!(node, expected);
+    }
+
+    #[test]
+    fn test_iq_builder() {
+        let server = JID::new(String::new(), "g.us".to_string());
+        let iq = Node::iq()
+            .id("1")
+            .to(&server)
+            .set()
+            .query("w:g2", |q| q.empty_child("invite"))
+            .build();
+
+        assert_eq!(iq.tag, "iq");
+        assert_eq!(iq.get_attr("type").map(String::as_str), Some("set"));
+        assert_eq!(iq.get_attr("xmlns").map(String::as_str), Some("w:g2"));
+        assert_eq!(iq.get_attr("to"), Some(&server.to_string()));
+        assert!(iq.find_child("invite").is_some());
+    }
+
+    #[test]
+    fn test_stanza_builders() {
+        let chat = JID::new("friend".to_string(), "s.whatsapp.net".to_string());
+
+        let message = Node::message("M1", &chat)
+            .kind("text")
+            .child("body", |b| b.bytes(vec![1, 2]))
+            .build();
+        assert_eq!(message.get_attr("id").map(String::as_str), Some("M1"));
+        assert_eq!(message.find_child("body").and_then(Node::get_binary), Some(&vec![1, 2]));
+
+        let receipt = Node::receipt("M1", &chat).kind("read").more_ids(["M2", "M3"]).build();
+        assert_eq!(receipt.get_attr("type").map(String::as_str), Some("read"));
+        assert_eq!(receipt.find_child("list").and_then(Node::get_children).map(Vec::len), Some(2));
+
+        let presence = Node::presence().available().name("Bot").build();
+        assert_eq!(presence.get_attr("type").map(String::as_str), Some("available"));
+        assert_eq!(presence.get_attr("name").map(String::as_str), Some("Bot"));
+    }
+}
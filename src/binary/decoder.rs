@@ -1,25 +1,55 @@
 use crate::{
-    binary::{Node, NodeContent, token::*},
+    binary::{Node, NodeContent, NodeRef, NodeRefContent, token::*},
     error::{Error, Result}
 };
+use bytes::Bytes;
+use std::borrow::Cow;
 use std::collections::HashMap;
 
+/// Deepest nesting of children accepted from the server.
+///
+/// Real stanzas are a handful of levels deep; the limit keeps hostile
+/// frames from overflowing the stack.
+pub const MAX_NODE_DEPTH: usize = 64;
+
 /// Decoder for WhatsApp binary protocol
 pub struct BinaryDecoder<'a> {
     data: &'a [u8],
+    /// The frame `data` belongs to, binary content is sliced out of it instead of copied
+    frame: Option<&'a Bytes>,
     pos: usize,
+    depth: usize,
+}
+
+/// Decode a frame without copying its strings and binary content
+pub fn decode_frame(frame: &Bytes) -> Result<NodeRef<'_>> {
+    BinaryDecoder::from_bytes(frame).decode_ref()
 }
 
 impl<'a> BinaryDecoder<'a> {
     /// Create a new decoder with the given data
     pub fn new(data: &'a [u8]) -> Self {
-        Self { data, pos: 0 }
+        Self { data, frame: None, pos: 0, depth: 0 }
+    }
+    
+    /// Create a decoder whose nodes share the frame's buffer
+    pub fn from_bytes(frame: &'a Bytes) -> Self {
+        Self { data: frame.as_ref(), frame: Some(frame), pos: 0, depth: 0 }
     }
     
     /// Decode a node from the binary data
+    ///
+    /// Builds the owned node directly, copying each string and binary
+    /// content once, rather than going through [`decode_ref`](Self::decode_ref).
     pub fn decode(&mut self) -> Result<Node> {
-        let tag = self.read_string_or_token()?;
-        let attrs = self.read_attributes()?;
+        let tag = self.read_string_or_token()?.into_owned();
+        let attr_count = self.read_list_size()?;
+        let mut attrs = HashMap::with_capacity(attr_count.min(self.remaining()));
+        for _ in 0..attr_count {
+            let key = self.read_string_or_token()?.into_owned();
+            let value = self.read_string_or_token()?.into_owned();
+            attrs.insert(key, value);
+        }
         
         let content = if self.pos >= self.data.len() {
             NodeContent::None
@@ -28,14 +58,46 @@ impl<'a> BinaryDecoder<'a> {
             if content_type == LIST_EMPTY {
                 self.read_byte()?; // consume the LIST_EMPTY byte
                 NodeContent::None
-            } else if content_type >= LIST_8 && content_type <= JID_PAIR {
+            } else if (LIST_8..=JID_PAIR).contains(&content_type) {
+                let child_count = self.read_list_size()?;
+                let mut children = Vec::with_capacity(child_count.min(self.remaining()));
+                self.enter()?;
+                for _ in 0..child_count {
+                    children.push(self.decode()?);
+                }
+                self.depth -= 1;
+                NodeContent::Children(children)
+            } else {
+                match self.read_binary_slice()? {
+                    Some(data) => NodeContent::Binary(data.to_vec()),
+                    None => NodeContent::Text(self.read_string_or_token()?.into_owned()),
+                }
+            }
+        };
+        
+        Ok(Node { tag, attrs, content })
+    }
+    
+    /// Decode a node referencing the binary data instead of copying it
+    pub fn decode_ref(&mut self) -> Result<NodeRef<'a>> {
+        let tag = self.read_string_or_token()?;
+        let attrs = self.read_attributes()?;
+        
+        let content = if self.pos >= self.data.len() {
+            NodeRefContent::None
+        } else {
+            let content_type = self.peek_byte()?;
+            if content_type == LIST_EMPTY {
+                self.read_byte()?; // consume the LIST_EMPTY byte
+                NodeRefContent::None
+            } else if (LIST_8..=JID_PAIR).contains(&content_type) {
                 self.read_children()?
             } else {
                 self.read_binary_content()?
             }
         };
         
-        Ok(Node {
+        Ok(NodeRef {
             tag,
             attrs,
             content,
@@ -43,11 +105,11 @@ impl<'a> BinaryDecoder<'a> {
     }
     
     /// Read string or token from the data
-    fn read_string_or_token(&mut self) -> Result<String> {
+    fn read_string_or_token(&mut self) -> Result<Cow<'a, str>> {
         let byte = self.read_byte()?;
         
         match byte {
-            0 => Ok(String::new()),
+            0 => Ok(Cow::Borrowed("")),
             1..=PACKED_MAX => {
                 // Packed string
                 let length = byte as usize;
@@ -57,7 +119,7 @@ impl<'a> BinaryDecoder<'a> {
                 // Single-byte token
                 if let Some(token) = get_single_token(byte) {
                     if !token.is_empty() {
-                        Ok(token.to_string())
+                        Ok(Cow::Borrowed(token))
                     } else {
                         Err(Error::Protocol(format!("Invalid single-byte token: {}", byte)))
                     }
@@ -70,7 +132,7 @@ impl<'a> BinaryDecoder<'a> {
                 let dict = byte - DICTIONARY_0;
                 let index = self.read_byte()?;
                 if let Some(token) = get_double_token(dict, index) {
-                    Ok(token.to_string())
+                    Ok(Cow::Borrowed(token))
                 } else {
                     Err(Error::Protocol(format!("Unknown double-byte token: {}:{}", dict, index)))
                 }
@@ -92,58 +154,75 @@ impl<'a> BinaryDecoder<'a> {
     }
     
     /// Read attributes from the data
-    fn read_attributes(&mut self) -> Result<HashMap<String, String>> {
+    fn read_attributes(&mut self) -> Result<Vec<(Cow<'a, str>, Cow<'a, str>)>> {
         let attr_count = self.read_list_size()?;
-        let mut attrs = HashMap::new();
+        let mut attrs = Vec::with_capacity(attr_count.min(self.remaining()));
         
         for _ in 0..attr_count {
             let key = self.read_string_or_token()?;
             let value = self.read_string_or_token()?;
-            attrs.insert(key, value);
+            attrs.push((key, value));
         }
         
         Ok(attrs)
     }
     
     /// Read children nodes
-    fn read_children(&mut self) -> Result<NodeContent> {
+    fn read_children(&mut self) -> Result<NodeRefContent<'a>> {
         let child_count = self.read_list_size()?;
-        let mut children = Vec::new();
+        let mut children = Vec::with_capacity(child_count.min(self.remaining()));
         
+        self.enter()?;
         for _ in 0..child_count {
-            children.push(self.decode()?);
+            children.push(self.decode_ref()?);
         }
+        self.depth -= 1;
         
-        Ok(NodeContent::Children(children))
+        Ok(NodeRefContent::Children(children))
+    }
+    
+    /// Go one level deeper into the children, failing past [`MAX_NODE_DEPTH`]
+    fn enter(&mut self) -> Result<()> {
+        if self.depth >= MAX_NODE_DEPTH {
+            return Err(Error::Protocol(format!("Nodes nested deeper than {} levels", MAX_NODE_DEPTH)));
+        }
+        self.depth += 1;
+        Ok(())
     }
     
     /// Read binary content
-    fn read_binary_content(&mut self) -> Result<NodeContent> {
-        let content_type = self.read_byte()?;
-        
-        match content_type {
+    fn read_binary_content(&mut self) -> Result<NodeRefContent<'a>> {
+        match self.read_binary_slice()? {
+            Some(data) => {
+                let data = match self.frame {
+                    Some(frame) => frame.slice_ref(data),
+                    None => Bytes::copy_from_slice(data),
+                };
+                Ok(NodeRefContent::Binary(data))
+            }
+            // Assume it's a string/text content
+            None => Ok(NodeRefContent::Text(self.read_string_or_token()?)),
+        }
+    }
+    
+    /// Read length-prefixed binary content, or nothing if the next value is not binary
+    fn read_binary_slice(&mut self) -> Result<Option<&'a [u8]>> {
+        let length = match self.peek_byte()? {
             BINARY_8 => {
-                let length = self.read_byte()? as usize;
-                let data = self.read_bytes(length)?;
-                Ok(NodeContent::Binary(data))
+                self.read_byte()?;
+                self.read_byte()? as usize
             },
             BINARY_20 => {
-                let length = self.read_int20()? as usize;
-                let data = self.read_bytes(length)?;
-                Ok(NodeContent::Binary(data))
+                self.read_byte()?;
+                self.read_int20()? as usize
             },
             BINARY_32 => {
-                let length = self.read_int32()? as usize;
-                let data = self.read_bytes(length)?;
-                Ok(NodeContent::Binary(data))
+                self.read_byte()?;
+                self.read_int32()? as usize
             },
-            _ => {
-                // Assume it's a string/text content
-                self.pos -= 1; // Put back the byte
-                let text = self.read_string_or_token()?;
-                Ok(NodeContent::Text(text))
-            }
-        }
+            _ => return Ok(None),
+        };
+        self.read_slice(length).map(Some)
     }
     
     /// Read list size based on the current byte
@@ -181,21 +260,28 @@ impl<'a> BinaryDecoder<'a> {
         Ok(self.data[self.pos])
     }
     
+    /// Number of bytes not read yet
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+    
     /// Read multiple bytes from the data
-    fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>> {
-        if self.pos + count > self.data.len() {
+    fn read_slice(&mut self, count: usize) -> Result<&'a [u8]> {
+        if count > self.remaining() {
             return Err(Error::Protocol("Unexpected end of data".to_string()));
         }
         
-        let bytes = self.data[self.pos..self.pos + count].to_vec();
+        let data: &'a [u8] = self.data;
+        let bytes = &data[self.pos..self.pos + count];
         self.pos += count;
         Ok(bytes)
     }
     
     /// Read a string from the data
-    fn read_string(&mut self, length: usize) -> Result<String> {
-        let bytes = self.read_bytes(length)?;
-        String::from_utf8(bytes)
+    fn read_string(&mut self, length: usize) -> Result<Cow<'a, str>> {
+        let bytes = self.read_slice(length)?;
+        std::str::from_utf8(bytes)
+            .map(Cow::Borrowed)
             .map_err(|e| Error::Protocol(format!("Invalid UTF-8 string: {}", e)))
     }
     
@@ -222,4 +308,91 @@ impl<'a> BinaryDecoder<'a> {
         let b4 = self.read_byte()? as u32;
         Ok((b1 << 24) | (b2 << 16) | (b3 << 8) | b4)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::NodeContent;
+    use proptest::prelude::*;
+
+    /// `<iq id="1" type="result"><enc v="2">\x01\x02\x03</enc></iq>`
+    fn frame() -> Vec<u8> {
+        let mut data = vec![2, b'i', b'q', LIST_8, 2];
+        data.extend_from_slice(&[2, b'i', b'd', 1, b'1']);
+        data.extend_from_slice(&[4, b't', b'y', b'p', b'e', 6]);
+        data.extend_from_slice(b"result");
+        data.extend_from_slice(&[LIST_8, 1]);
+        data.extend_from_slice(&[3, b'e', b'n', b'c', LIST_8, 1, 1, b'v', 1, b'2']);
+        data.extend_from_slice(&[BINARY_8, 3, 1, 2, 3]);
+        data
+    }
+
+    #[test]
+    fn test_decode_ref_shares_frame() {
+        let frame = Bytes::from(frame());
+        let node = decode_frame(&frame).unwrap();
+        assert_eq!(node.tag, "iq");
+        assert_eq!(node.get_attr("type"), Some("result"));
+        assert!(matches!(node.tag, Cow::Borrowed(_)));
+
+        let enc = node.find_child("enc").unwrap();
+        assert_eq!(enc.get_attr("v"), Some("2"));
+        let content = enc.get_binary().unwrap();
+        assert_eq!(content.as_ref(), &[1, 2, 3]);
+        // The content points into the frame instead of a copy
+        let offset = content.as_ptr() as usize - frame.as_ptr() as usize;
+        assert_eq!(offset, frame.len() - 3);
+    }
+
+    #[test]
+    fn test_decode_owned_matches_ref() {
+        let data = frame();
+        let node = BinaryDecoder::new(&data).decode().unwrap();
+        assert_eq!(node.get_attr("id").map(String::as_str), Some("1"));
+        assert_eq!(node.find_child("enc").and_then(|enc| enc.get_binary()), Some(&vec![1, 2, 3]));
+        assert_eq!(decode_frame(&Bytes::from(data)).unwrap().into_owned(), node);
+        assert!(matches!(node.content, NodeContent::Children(_)));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let data = frame();
+        // Cutting into a length-prefixed value fails instead of reading past the end
+        assert!(BinaryDecoder::new(&data[..data.len() - 1]).decode_ref().is_err());
+        assert!(BinaryDecoder::new(&data[..8]).decode_ref().is_err());
+        for len in 0..data.len() {
+            let _ = BinaryDecoder::new(&data[..len]).decode_ref();
+        }
+    }
+
+    #[test]
+    fn test_decode_depth_limit() {
+        // Each level is a one-letter tag without attributes holding one child
+        let nested = |depth: usize| {
+            let mut data = Vec::new();
+            for _ in 0..depth {
+                data.extend_from_slice(&[1, b'n', LIST_EMPTY, LIST_8, 1]);
+            }
+            data.extend_from_slice(&[1, b'n', LIST_EMPTY]);
+            data
+        };
+        assert!(BinaryDecoder::new(&nested(MAX_NODE_DEPTH)).decode().is_ok());
+        assert!(BinaryDecoder::new(&nested(MAX_NODE_DEPTH + 1)).decode().is_err());
+        assert!(BinaryDecoder::new(&nested(100_000)).decode().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_decode_never_panics(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = BinaryDecoder::new(&data).decode();
+        }
+
+        #[test]
+        fn prop_decode_ref_matches_decode(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            let owned = BinaryDecoder::new(&data).decode().ok();
+            let frame = Bytes::from(data);
+            prop_assert_eq!(decode_frame(&frame).ok().map(NodeRef::into_owned), owned);
+        }
+    }
+}
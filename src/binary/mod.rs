@@ -5,8 +5,10 @@ pub mod node;
 pub mod decoder;
 pub mod encoder;
 pub mod token;
+pub mod builder;
 
 pub use node::*;
 pub use decoder::*;
 pub use encoder::*;
-pub use token::*;
\ No newline at end of file
+pub use token::*;
+pub use builder::*;
\ No newline at end of file
@@ -1,3 +1,5 @@
+use bytes::Bytes;
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 /// Represents a node in the WhatsApp binary protocol
@@ -96,4 +98,79 @@ impl Node {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+/// A decoded node borrowing its strings and binary content from the frame.
+///
+/// Produced by [`BinaryDecoder::decode_ref`](crate::binary::BinaryDecoder::decode_ref).
+/// Strings point into the frame or the token tables and binary content is
+/// a [`Bytes`] slice sharing the frame buffer, so decoding allocates little
+/// more than the attribute and child lists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRef<'a> {
+    pub tag: Cow<'a, str>,
+    pub attrs: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    pub content: NodeRefContent<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeRefContent<'a> {
+    None,
+    Text(Cow<'a, str>),
+    Binary(Bytes),
+    Children(Vec<NodeRef<'a>>),
+}
+
+impl<'a> NodeRef<'a> {
+    /// Get attribute value
+    pub fn get_attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_ref())
+    }
+    
+    /// Get children if content is children
+    pub fn get_children(&self) -> Option<&[NodeRef<'a>]> {
+        match &self.content {
+            NodeRefContent::Children(children) => Some(children),
+            _ => None,
+        }
+    }
+    
+    /// Get text content if content is text
+    pub fn get_text(&self) -> Option<&str> {
+        match &self.content {
+            NodeRefContent::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+    
+    /// Get binary content if content is binary
+    pub fn get_binary(&self) -> Option<&Bytes> {
+        match &self.content {
+            NodeRefContent::Binary(data) => Some(data),
+            _ => None,
+        }
+    }
+    
+    /// Find child by tag
+    pub fn find_child(&self, tag: &str) -> Option<&NodeRef<'a>> {
+        self.get_children()?.iter().find(|child| child.tag == tag)
+    }
+    
+    /// Copy everything out of the frame into an owned [`Node`]
+    pub fn into_owned(self) -> Node {
+        let content = match self.content {
+            NodeRefContent::None => NodeContent::None,
+            NodeRefContent::Text(text) => NodeContent::Text(text.into_owned()),
+            NodeRefContent::Binary(data) => NodeContent::Binary(data.to_vec()),
+            NodeRefContent::Children(children) => {
+                NodeContent::Children(children.into_iter().map(NodeRef::into_owned).collect())
+            }
+        };
+        
+        Node {
+            tag: self.tag.into_owned(),
+            attrs: self.attrs.into_iter().map(|(k, v)| (k.into_owned(), v.into_owned())).collect(),
+            content,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+/// Block list queries and notifications
+///
+/// The server keeps the list of users we blocked. It is fetched with a
+/// `blocklist` get IQ, changed with a set IQ carrying one `<item>` and
+/// pushed to us as `blocklist` notifications whenever another device
+/// changes it. Notifications either replace the whole list or, with
+/// `action="modify"`, only list the changed entries.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    request::InfoQuery,
+    types::{BlocklistChange, BlocklistChangeAction, BlocklistEvent, JID},
+};
+use std::collections::HashSet;
+
+/// Namespace of block list queries
+pub const BLOCKLIST_NAMESPACE: &str = "blocklist";
+
+/// Our current block list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    /// Hash of the list as known to the server
+    pub dhash: Option<String>,
+    pub jids: HashSet<JID>,
+}
+
+impl Blocklist {
+    /// Whether the given user is blocked
+    pub fn contains(&self, jid: &JID) -> bool {
+        self.jids.contains(&jid.to_non_ad())
+    }
+
+    /// Apply a notification, returning whether it changed the list
+    pub fn apply(&mut self, event: &BlocklistEvent) -> bool {
+        let before = self.jids.clone();
+        if !event.is_modification() {
+            self.jids.clear();
+        }
+        for change in &event.changes {
+            match change.action {
+                BlocklistChangeAction::Block => self.jids.insert(change.jid.to_non_ad()),
+                BlocklistChangeAction::Unblock => self.jids.remove(&change.jid.to_non_ad()),
+            };
+        }
+        self.dhash = event.dhash.clone();
+        self.jids != before
+    }
+}
+
+/// Build the query for the full block list
+pub fn build_get_query() -> InfoQuery {
+    InfoQuery::get(BLOCKLIST_NAMESPACE)
+}
+
+/// Build the query blocking or unblocking a user
+pub fn build_update_query(jid: &JID, action: BlocklistChangeAction) -> InfoQuery {
+    InfoQuery::set(BLOCKLIST_NAMESPACE).content(vec![
+        Node::builder("item")
+            .attr("action", action.as_str())
+            .attr("jid", jid.to_non_ad())
+            .build(),
+    ])
+}
+
+/// Parse the `<list>` returned by block list queries
+pub fn parse_blocklist(response: &Node) -> Result<Blocklist> {
+    let list = response
+        .find_child("list")
+        .ok_or_else(|| Error::ElementMissing("list".to_string()))?;
+
+    let jids = list
+        .get_children()
+        .into_iter()
+        .flatten()
+        .filter(|item| item.tag == "item")
+        .map(|item| {
+            item.get_attr("jid")
+                .ok_or_else(|| Error::ElementMissing("jid".to_string()))?
+                .parse::<JID>()
+        })
+        .collect::<Result<HashSet<_>>>()?;
+
+    Ok(Blocklist { dhash: list.get_attr("dhash").cloned(), jids })
+}
+
+/// Parse a `blocklist` notification
+pub fn parse_blocklist_notification(node: &Node) -> Option<BlocklistEvent> {
+    if node.tag != "notification" || node.get_attr("type").map(String::as_str) != Some(BLOCKLIST_NAMESPACE) {
+        return None;
+    }
+
+    let changes = node
+        .get_children()
+        .into_iter()
+        .flatten()
+        .filter(|item| item.tag == "item")
+        .filter_map(|item| {
+            Some(BlocklistChange {
+                jid: item.get_attr("jid")?.parse().ok()?,
+                action: item.get_attr("action")?.parse().ok()?,
+            })
+        })
+        .collect();
+
+    Some(BlocklistEvent {
+        action: node.get_attr("action").cloned(),
+        dhash: node.get_attr("dhash").cloned(),
+        prev_dhash: node.get_attr("prev_dhash").cloned(),
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(jid: &str, action: &str) -> Node {
+        Node::builder("item").attr("jid", jid).attr("action", action).build()
+    }
+
+    #[test]
+    fn test_blocklist_queries() {
+        let jid = JID::new_user("1234").with_device(2);
+        let query = build_update_query(&jid, BlocklistChangeAction::Block);
+        assert_eq!(query.namespace, BLOCKLIST_NAMESPACE);
+        assert_eq!(query.content[0].get_attr("jid").map(String::as_str), Some("1234@s.whatsapp.net"));
+        assert_eq!(query.content[0].get_attr("action").map(String::as_str), Some("block"));
+
+        let response = Node::builder("iq")
+            .child("list", |list| {
+                list.attr("dhash", "abc")
+                    .node(Node::builder("item").attr("jid", "1234@s.whatsapp.net").build())
+                    .node(Node::builder("item").attr("jid", "5678@s.whatsapp.net").build())
+            })
+            .build();
+        let blocklist = parse_blocklist(&response).unwrap();
+        assert_eq!(blocklist.dhash.as_deref(), Some("abc"));
+        assert!(blocklist.contains(&jid));
+        assert_eq!(blocklist.jids.len(), 2);
+        assert!(parse_blocklist(&Node::builder("iq").build()).is_err());
+    }
+
+    #[test]
+    fn test_blocklist_notification() {
+        let mut blocklist = Blocklist::default();
+        blocklist.jids.insert(JID::new_user("1"));
+
+        let modify = Node::builder("notification")
+            .attr("type", "blocklist")
+            .attr("action", "modify")
+            .attr("dhash", "h2")
+            .node(item("2@s.whatsapp.net", "block"))
+            .node(item("1@s.whatsapp.net", "unblock"))
+            .build();
+        let event = parse_blocklist_notification(&modify).unwrap();
+        assert_eq!(event.changes.len(), 2);
+        assert!(blocklist.apply(&event));
+        assert_eq!(blocklist.jids, HashSet::from([JID::new_user("2")]));
+        assert_eq!(blocklist.dhash.as_deref(), Some("h2"));
+        assert!(!blocklist.apply(&event));
+
+        // Without an action the notification carries the whole list
+        let full = Node::builder("notification")
+            .attr("type", "blocklist")
+            .node(item("3@s.whatsapp.net", "block"))
+            .build();
+        blocklist.apply(&parse_blocklist_notification(&full).unwrap());
+        assert_eq!(blocklist.jids, HashSet::from([JID::new_user("3")]));
+
+        assert!(parse_blocklist_notification(&Node::builder("notification").attr("type", "devices").build()).is_none());
+    }
+}
@@ -0,0 +1,331 @@
+/// HTTP gateway in front of a client
+///
+/// Enabled with the `bridge` feature. [`Bridge`] serves a small REST API
+/// for sending messages and managing groups and forwards every event to a
+/// webhook, so other services can use WhatsApp without linking this crate.
+///
+/// | Method | Path | Body |
+/// |---|---|---|
+/// | `GET` | `/health` | |
+/// | `POST` | `/messages/text` | `{"to", "text"}` |
+/// | `POST` | `/messages/media` | `{"to", "filename", "data" (base64), "caption"?}` |
+/// | `GET` | `/chats` | query `archived`, `pinned`, `muted`, `unread` |
+/// | `POST` | `/groups` | `{"name", "participants", "description"?}` |
+/// | `GET` | `/groups/{jid}` | |
+/// | `POST` | `/groups/{jid}/participants` | `{"action": "add" \| "remove", "participants"}` |
+/// | `POST` | `/groups/{jid}/leave` | |
+///
+/// JIDs are plain strings like `1234@s.whatsapp.net`. With an API token
+/// configured every request needs an `Authorization: Bearer <token>` header.
+/// Media bodies may be up to [`BridgeConfig::media_body_limit`] bytes, the
+/// other routes keep axum's default limit.
+
+pub mod webhook;
+
+pub use webhook::{WebhookConfig, WebhookSender, SIGNATURE_HEADER};
+
+use crate::{
+    appstate::{ChatFilter, ChatMetadata},
+    error::Error,
+    group::{CreateGroupRequest, GroupInfo, ParticipantOperationResult},
+    types::JID,
+    Client,
+};
+use axum::{
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+/// Default size limit of `/messages/media` bodies, base64 inflates files by a third
+pub const DEFAULT_MEDIA_BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Bridge settings
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    pub listen: SocketAddr,
+    /// Token clients must send as `Authorization: Bearer <token>`, no check without one
+    pub api_token: Option<String>,
+    /// Where to post events, none are forwarded without one
+    pub webhook: Option<WebhookConfig>,
+    /// Largest accepted `/messages/media` body in bytes
+    pub media_body_limit: usize,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            api_token: None,
+            webhook: None,
+            media_body_limit: DEFAULT_MEDIA_BODY_LIMIT,
+        }
+    }
+}
+
+/// REST server and webhook forwarder for one client
+pub struct Bridge {
+    client: Arc<Client>,
+    config: BridgeConfig,
+}
+
+impl Bridge {
+    pub fn new(client: Arc<Client>, config: BridgeConfig) -> Self {
+        Self { client, config }
+    }
+
+    /// Routes of the REST API
+    pub fn router(&self) -> Router {
+        let api_token = self.config.api_token.clone();
+        Router::new()
+            .route("/health", get(health))
+            .route("/messages/text", post(send_text))
+            .route(
+                "/messages/media",
+                post(send_media).layer(DefaultBodyLimit::max(self.config.media_body_limit)),
+            )
+            .route("/chats", get(list_chats))
+            .route("/groups", post(create_group))
+            .route("/groups/{jid}", get(group_info))
+            .route("/groups/{jid}/participants", post(update_participants))
+            .route("/groups/{jid}/leave", post(leave_group))
+            .layer(middleware::from_fn(move |request: Request, next: Next| {
+                let authorized = check_token(api_token.as_deref(), &request);
+                async move {
+                    if authorized {
+                        next.run(request).await
+                    } else {
+                        (StatusCode::UNAUTHORIZED, Json(ErrorBody { error: "Invalid API token".to_string(), code: None }))
+                            .into_response()
+                    }
+                }
+            }))
+            .with_state(self.client.clone())
+    }
+
+    /// Start forwarding events and serve the API until the server fails
+    pub async fn run(self) -> crate::Result<()> {
+        if let Some(config) = self.config.webhook.clone() {
+            let sender = WebhookSender::spawn(config);
+            self.client
+                .add_event_handler(Box::new(move |event| {
+                    sender.send(event);
+                    true
+                }))
+                .await;
+        }
+
+        let listener = tokio::net::TcpListener::bind(self.config.listen).await?;
+        info!("Bridge listening on {}", listener.local_addr()?);
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+}
+
+fn check_token(expected: Option<&str>, request: &Request) -> bool {
+    let Some(expected) = expected else {
+        return true;
+    };
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())))
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+/// Client errors as HTTP responses
+struct ApiError(Error);
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        Self(error)
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self.0 {
+            Error::InvalidJID(_) | Error::Json(_) | Error::Serialization(_) => StatusCode::BAD_REQUEST,
+            Error::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            Error::SendBlocked(_) | Error::PassiveMode | Error::NotInGroup => StatusCode::FORBIDDEN,
+            Error::MediaSizeExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::MediaExpired => StatusCode::GONE,
+            Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::Connection(_) | Error::Disconnected(_) | Error::WebSocket(_) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(ErrorBody { error: self.0.to_string(), code: Some(self.0.code()) })).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
+
+fn parse_jids(jids: &[String]) -> crate::Result<Vec<JID>> {
+    jids.iter().map(|jid| jid.parse()).collect()
+}
+
+#[derive(Serialize)]
+struct Health {
+    connected: bool,
+    logged_in: bool,
+}
+
+async fn health(State(client): State<Arc<Client>>) -> Json<Health> {
+    Json(Health { connected: client.is_connected().await, logged_in: client.is_logged_in() })
+}
+
+#[derive(Deserialize)]
+struct SendTextBody {
+    to: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct Sent {
+    id: String,
+}
+
+async fn send_text(State(client): State<Arc<Client>>, Json(body): Json<SendTextBody>) -> ApiResult<Sent> {
+    let id = client.send_text(&body.to.parse()?, body.text).await?;
+    Ok(Json(Sent { id }))
+}
+
+#[derive(Deserialize)]
+struct SendMediaBody {
+    to: String,
+    filename: String,
+    /// Base64 encoded file contents
+    data: String,
+    caption: Option<String>,
+}
+
+async fn send_media(State(client): State<Arc<Client>>, Json(body): Json<SendMediaBody>) -> ApiResult<Sent> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(&body.data)
+        .map_err(|e| Error::Serialization(format!("Invalid base64 data: {}", e)))?;
+    let id = client.send_media_bytes(&body.to.parse()?, &data, &body.filename, body.caption).await?;
+    Ok(Json(Sent { id }))
+}
+
+#[derive(Deserialize)]
+struct ChatQuery {
+    archived: Option<bool>,
+    pinned: Option<bool>,
+    muted: Option<bool>,
+    unread: Option<bool>,
+}
+
+async fn list_chats(State(client): State<Arc<Client>>, Query(query): Query<ChatQuery>) -> ApiResult<Vec<ChatMetadata>> {
+    let filter = ChatFilter {
+        archived: query.archived,
+        pinned: query.pinned,
+        muted: query.muted,
+        has_unread: query.unread,
+        ..Default::default()
+    };
+    Ok(Json(client.search_chats(filter).await?))
+}
+
+#[derive(Deserialize)]
+struct CreateGroupBody {
+    name: String,
+    participants: Vec<String>,
+    description: Option<String>,
+}
+
+async fn create_group(State(client): State<Arc<Client>>, Json(body): Json<CreateGroupBody>) -> ApiResult<GroupInfo> {
+    let mut request = CreateGroupRequest::new(body.name, parse_jids(&body.participants)?);
+    if let Some(description) = body.description {
+        request = request.with_description(description);
+    }
+    Ok(Json(client.create_group(request).await?))
+}
+
+async fn group_info(State(client): State<Arc<Client>>, Path(jid): Path<String>) -> ApiResult<GroupInfo> {
+    Ok(Json(client.get_group_info(&jid.parse()?).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ParticipantAction {
+    Add,
+    Remove,
+}
+
+#[derive(Deserialize)]
+struct ParticipantsBody {
+    action: ParticipantAction,
+    participants: Vec<String>,
+}
+
+async fn update_participants(
+    State(client): State<Arc<Client>>,
+    Path(jid): Path<String>,
+    Json(body): Json<ParticipantsBody>,
+) -> ApiResult<ParticipantOperationResult> {
+    let group = jid.parse()?;
+    let participants = parse_jids(&body.participants)?;
+    let result = match body.action {
+        ParticipantAction::Add => client.add_group_participants(&group, participants).await?,
+        ParticipantAction::Remove => client.remove_group_participants(&group, participants).await?,
+    };
+    Ok(Json(result))
+}
+
+async fn leave_group(State(client): State<Arc<Client>>, Path(jid): Path<String>) -> std::result::Result<StatusCode, ApiError> {
+    client.leave_group(&jid.parse()?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    #[test]
+    fn test_check_token() {
+        let request = |auth: Option<&str>| {
+            let mut builder = Request::builder().uri("/health");
+            if let Some(auth) = auth {
+                builder = builder.header(header::AUTHORIZATION, auth);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+        assert!(check_token(None, &request(None)));
+        assert!(check_token(Some("secret"), &request(Some("Bearer secret"))));
+        assert!(!check_token(Some("secret"), &request(Some("Bearer wrong"))));
+        assert!(!check_token(Some("secret"), &request(Some("secret"))));
+        assert!(!check_token(Some("secret"), &request(None)));
+        assert!(!check_token(Some("secret"), &request(Some("Bearer secret2"))));
+    }
+
+    #[test]
+    fn test_error_status() {
+        assert_eq!(ApiError(Error::InvalidJID("x".to_string())).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ApiError(Error::NotLoggedIn).status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ApiError(Error::Disconnected("x".to_string())).status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ApiError(Error::Protocol("x".to_string())).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,182 @@
+/// Delivery of client events to a webhook
+///
+/// Every event is POSTed as JSON (`{"type": "message", "data": {...}}`) in
+/// the order the client emitted it. With a secret configured the body is
+/// signed with HMAC-SHA256 and the hex digest sent in [`SIGNATURE_HEADER`]
+/// as `sha256=<digest>`, so the receiver can check the request came from us.
+///
+/// Failed deliveries are retried with exponential backoff when the failure
+/// may be temporary (connection errors, 5xx and 429 answers), up to
+/// [`WebhookConfig::max_attempts`] times. Later events wait meanwhile to keep
+/// the order.
+
+use crate::{types::Event, util::crypto::hmac_sha256};
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Header carrying the body signature
+pub const SIGNATURE_HEADER: &str = "X-Whatsmeow-Signature";
+
+/// Events waiting for delivery before new ones are dropped
+const QUEUE_SIZE: usize = 1024;
+
+/// Where and how events are delivered
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key signing the request bodies, no signature is sent without one
+    pub secret: Option<Vec<u8>>,
+    pub timeout: Duration,
+    /// Tries per event before it is dropped, at least one
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub retry_backoff: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: None,
+            timeout: Duration::from_secs(10),
+            max_attempts: 5,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_retries(mut self, max_attempts: u32, retry_backoff: Duration) -> Self {
+        self.max_attempts = max_attempts;
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Wait before the given retry, starting at 1
+    fn backoff(&self, retry: u32) -> Duration {
+        self.retry_backoff.saturating_mul(1 << (retry - 1).min(16))
+    }
+
+    pub fn with_secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+}
+
+/// Signature header value of a body
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(hmac_sha256(secret, &[body])))
+}
+
+/// Queues events and posts them to the webhook from a background task
+pub struct WebhookSender {
+    queue: mpsc::Sender<Event>,
+}
+
+impl WebhookSender {
+    /// Start the delivery task
+    pub fn spawn(config: WebhookConfig) -> Self {
+        let (queue, mut events) = mpsc::channel::<Event>(QUEUE_SIZE);
+        tokio::spawn(async move {
+            let http = reqwest::Client::new();
+            while let Some(event) = events.recv().await {
+                deliver_with_retries(&http, &config, &event).await;
+            }
+        });
+        Self { queue }
+    }
+
+    /// Queue an event, dropping it if the webhook can't keep up
+    pub fn send(&self, event: Event) {
+        if self.queue.try_send(event).is_err() {
+            warn!("Webhook queue full, dropping event");
+        }
+    }
+}
+
+async fn deliver_with_retries(http: &reqwest::Client, config: &WebhookConfig, event: &Event) {
+    let max_attempts = config.max_attempts.max(1);
+    for attempt in 1..=max_attempts {
+        match deliver(http, config, event).await {
+            Ok(()) => return,
+            Err(failure) if failure.retry && attempt < max_attempts => {
+                let wait = config.backoff(attempt);
+                debug!("Webhook delivery attempt {} failed, retrying in {:?}: {}", attempt, wait, failure.error);
+                tokio::time::sleep(wait).await;
+            }
+            Err(failure) => {
+                warn!("Failed to deliver event to webhook after {} attempts: {}", attempt, failure.error);
+                return;
+            }
+        }
+    }
+}
+
+/// Failed delivery and whether trying again may help
+struct DeliveryFailure {
+    error: crate::Error,
+    retry: bool,
+}
+
+impl DeliveryFailure {
+    fn new(error: crate::Error, retry: bool) -> Self {
+        Self { error, retry }
+    }
+}
+
+/// Server errors and rate limiting are temporary, other rejections are not
+fn should_retry(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+async fn deliver(http: &reqwest::Client, config: &WebhookConfig, event: &Event) -> Result<(), DeliveryFailure> {
+    let body = serde_json::to_vec(event).map_err(|e| DeliveryFailure::new(e.into(), false))?;
+    let mut request = http
+        .post(&config.url)
+        .timeout(config.timeout)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(secret) = &config.secret {
+        request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+    }
+
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| DeliveryFailure::new(crate::Error::Connection(format!("Webhook request failed: {}", e)), true))?;
+    let status = response.status();
+    if !status.is_success() {
+        let error = crate::Error::Connection(format!("Webhook answered {}", status));
+        return Err(DeliveryFailure::new(error, should_retry(status)));
+    }
+    debug!("Delivered event to webhook");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        assert!(should_retry(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(should_retry(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(should_retry(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!should_retry(StatusCode::BAD_REQUEST));
+        assert!(!should_retry(StatusCode::UNAUTHORIZED));
+
+        let config = WebhookConfig::new("http://localhost").with_retries(4, Duration::from_millis(100));
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+        assert_eq!(config.backoff(3), Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,329 @@
+/// Business catalogs, products and orders
+///
+/// Business accounts publish a catalog of products. It is fetched page by
+/// page with a `w:biz:catalog` IQ carrying a `<product_catalog>` query, and
+/// single products with a `<product>` query in the same namespace. Products
+/// are shared in chats as `productMessage`s, and customers place orders
+/// with `orderMessage`s; both travel as regular end-to-end encrypted
+/// messages and are converted here from and to their protobufs.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    proto::wa_e2e,
+    request::{node_content_string, InfoQuery},
+    types::{OrderMessage, OrderStatus, ProductMessage, JID},
+};
+use serde::{Deserialize, Serialize};
+
+/// Namespace of catalog queries
+pub const CATALOG_NAMESPACE: &str = "w:biz:catalog";
+
+/// Number of products fetched per catalog page by default
+pub const DEFAULT_CATALOG_PAGE_SIZE: u32 = 10;
+
+/// Side length of the product images the server links to
+const PRODUCT_IMAGE_SIZE: u32 = 100;
+
+/// A product in a business catalog
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Product {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// The business' own identifier of the product
+    pub retailer_id: Option<String>,
+    pub url: Option<String>,
+    /// Price in thousandths of the currency unit
+    pub price_amount_1000: Option<i64>,
+    pub currency: Option<String>,
+    pub image_urls: Vec<String>,
+    /// Whether the business hid the product from its catalog
+    pub is_hidden: bool,
+    /// Review status, e.g. `APPROVED`
+    pub status: Option<String>,
+}
+
+/// One page of a business catalog
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Catalog {
+    pub products: Vec<Product>,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// An incoming business message
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusinessMessage {
+    Product(ProductMessage),
+    Order(OrderMessage),
+}
+
+/// Build the query for a page of `business`'s catalog, starting after `cursor`
+pub fn catalog_query(business: &JID, limit: u32, cursor: Option<&str>) -> InfoQuery {
+    let mut catalog = Node::builder("product_catalog")
+        .attr("jid", business.to_non_ad())
+        .attr("allow_shop_source", "true")
+        .child("limit", |l| l.text(limit.to_string()))
+        .child("width", |w| w.text(PRODUCT_IMAGE_SIZE.to_string()))
+        .child("height", |h| h.text(PRODUCT_IMAGE_SIZE.to_string()));
+    if let Some(cursor) = cursor {
+        catalog = catalog.child("after", |a| a.text(cursor));
+    }
+    InfoQuery::get(CATALOG_NAMESPACE).content(vec![catalog.build()])
+}
+
+/// Build the query for a single product of `business`'s catalog
+pub fn product_query(business: &JID, product_id: &str) -> InfoQuery {
+    InfoQuery::get(CATALOG_NAMESPACE).content(vec![Node::builder("product")
+        .attr("jid", business.to_non_ad())
+        .child("id", |i| i.text(product_id))
+        .child("width", |w| w.text(PRODUCT_IMAGE_SIZE.to_string()))
+        .child("height", |h| h.text(PRODUCT_IMAGE_SIZE.to_string()))
+        .build()])
+}
+
+/// Parse the `<product_catalog>` answering [`catalog_query`]
+pub fn parse_catalog(response: &Node) -> Result<Catalog> {
+    let catalog = response
+        .find_child("product_catalog")
+        .ok_or_else(|| Error::ElementMissing("product_catalog".to_string()))?;
+
+    let products = catalog
+        .get_children()
+        .into_iter()
+        .flatten()
+        .filter(|child| child.tag == "product")
+        .map(parse_product)
+        .collect::<Result<Vec<_>>>()?;
+    let next_cursor = catalog
+        .find_child("paging")
+        .and_then(|paging| paging.find_child("after"))
+        .and_then(node_content_string)
+        .filter(|cursor| !cursor.is_empty());
+
+    Ok(Catalog { products, next_cursor })
+}
+
+/// Parse the `<product>` answering [`product_query`]
+pub fn parse_product_response(response: &Node) -> Result<Product> {
+    let product = response
+        .find_child("product")
+        .ok_or_else(|| Error::ElementMissing("product".to_string()))?;
+    parse_product(product)
+}
+
+/// Parse a `<product>` node of a catalog
+pub fn parse_product(node: &Node) -> Result<Product> {
+    let text = |tag: &str| node.find_child(tag).and_then(node_content_string).filter(|t| !t.is_empty());
+
+    let image_urls = node
+        .find_child("media")
+        .and_then(|media| media.get_children())
+        .into_iter()
+        .flatten()
+        .filter(|image| image.tag == "image")
+        .filter_map(|image| {
+            image.find_child("original_image_url")
+                .or_else(|| image.find_child("request_image_url"))
+                .and_then(node_content_string)
+        })
+        .collect();
+
+    Ok(Product {
+        id: text("id").ok_or_else(|| Error::ElementMissing("product id".to_string()))?,
+        name: text("name").unwrap_or_default(),
+        description: text("description"),
+        retailer_id: text("retailer_id"),
+        url: text("url"),
+        price_amount_1000: text("price").and_then(|p| p.parse().ok()),
+        currency: text("currency"),
+        image_urls,
+        is_hidden: node.get_attr("is_hidden").map(String::as_str) == Some("true"),
+        status: node
+            .find_child("status_info")
+            .and_then(|info| info.find_child("status"))
+            .and_then(node_content_string),
+    })
+}
+
+/// Share a catalog product of `business` in a chat
+pub fn product_message(business: &JID, product: &Product) -> ProductMessage {
+    ProductMessage {
+        business_owner_jid: business.to_non_ad(),
+        product_id: product.id.clone(),
+        title: Some(product.name.clone()).filter(|name| !name.is_empty()),
+        description: product.description.clone(),
+        currency_code: product.currency.clone(),
+        price_amount_1000: product.price_amount_1000,
+        retailer_id: product.retailer_id.clone(),
+        url: product.url.clone(),
+        product_image_count: Some(product.image_urls.len() as u32),
+        body: None,
+        footer: None,
+    }
+}
+
+/// Encode a product message as the `Message` protobuf
+pub fn product_message_to_proto(message: &ProductMessage) -> wa_e2e::Message {
+    let product = wa_e2e::product_message::ProductSnapshot {
+        product_id: Some(message.product_id.clone()),
+        title: message.title.clone(),
+        description: message.description.clone(),
+        currency_code: message.currency_code.clone(),
+        price_amount1000: message.price_amount_1000,
+        retailer_id: message.retailer_id.clone(),
+        url: message.url.clone(),
+        product_image_count: message.product_image_count,
+        ..Default::default()
+    };
+    wa_e2e::Message {
+        product_message: Some(Box::new(wa_e2e::ProductMessage {
+            product: Some(Box::new(product)),
+            business_owner_jid: Some(message.business_owner_jid.to_string()),
+            body: message.body.clone(),
+            footer: message.footer.clone(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// Extract a product or order from a decrypted message, if it is one
+pub fn parse_business_message(message: &wa_e2e::Message) -> Option<BusinessMessage> {
+    if let Some(product) = &message.product_message {
+        return parse_product_message(product).map(BusinessMessage::Product);
+    }
+    message.order_message.as_deref().and_then(parse_order_message).map(BusinessMessage::Order)
+}
+
+fn parse_product_message(message: &wa_e2e::ProductMessage) -> Option<ProductMessage> {
+    let product = message.product.as_ref()?;
+    Some(ProductMessage {
+        business_owner_jid: message.business_owner_jid.as_ref()?.parse().ok()?,
+        product_id: product.product_id.clone()?,
+        title: product.title.clone(),
+        description: product.description.clone(),
+        currency_code: product.currency_code.clone(),
+        price_amount_1000: product.price_amount1000,
+        retailer_id: product.retailer_id.clone(),
+        url: product.url.clone(),
+        product_image_count: product.product_image_count,
+        body: message.body.clone(),
+        footer: message.footer.clone(),
+    })
+}
+
+fn parse_order_message(message: &wa_e2e::OrderMessage) -> Option<OrderMessage> {
+    let status = message.status.and_then(|status| {
+        match wa_e2e::order_message::OrderStatus::try_from(status).ok()? {
+            wa_e2e::order_message::OrderStatus::Inquiry => Some(OrderStatus::Inquiry),
+            wa_e2e::order_message::OrderStatus::Accepted => Some(OrderStatus::Accepted),
+            wa_e2e::order_message::OrderStatus::Declined => Some(OrderStatus::Declined),
+        }
+    });
+    Some(OrderMessage {
+        order_id: message.order_id.clone()?,
+        seller_jid: message.seller_jid.as_ref().and_then(|jid| jid.parse().ok()),
+        item_count: message.item_count.unwrap_or_default().max(0) as u32,
+        status,
+        title: message.order_title.clone(),
+        message: message.message.clone(),
+        token: message.token.clone(),
+        total_amount_1000: message.total_amount1000,
+        total_currency_code: message.total_currency_code.clone(),
+        thumbnail: message.thumbnail.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product_node(id: &str) -> Node {
+        Node::builder("product")
+            .attr("is_hidden", "false")
+            .child("id", |i| i.text(id))
+            .child("name", |n| n.text("Sourdough loaf"))
+            .child("retailer_id", |r| r.text("SKU-1"))
+            .child("price", |p| p.text("4500000"))
+            .child("currency", |c| c.text("IDR"))
+            .child("media", |m| {
+                m.child("image", |i| i.child("original_image_url", |u| u.text("https://cdn.example/1.jpg")))
+            })
+            .child("status_info", |s| s.child("status", |s| s.text("APPROVED")))
+            .build()
+    }
+
+    #[test]
+    fn test_catalog_queries() {
+        let business = JID::new_user("6281234").with_device(3);
+        let query = catalog_query(&business, 5, Some("cursor-1")).to_node("1");
+        assert_eq!(query.get_attr("xmlns").map(String::as_str), Some(CATALOG_NAMESPACE));
+        let catalog = query.find_child("product_catalog").unwrap();
+        assert_eq!(catalog.get_attr("jid").map(String::as_str), Some("6281234@s.whatsapp.net"));
+        assert_eq!(catalog.find_child("limit").and_then(node_content_string).as_deref(), Some("5"));
+        assert_eq!(catalog.find_child("after").and_then(node_content_string).as_deref(), Some("cursor-1"));
+
+        let query = product_query(&business, "P1").to_node("2");
+        assert!(query.find_child("product").and_then(|p| p.find_child("id")).is_some());
+    }
+
+    #[test]
+    fn test_parse_catalog() {
+        let response = Node::builder("iq")
+            .child("product_catalog", |c| {
+                c.node(product_node("P1"))
+                    .node(product_node("P2"))
+                    .child("paging", |p| p.child("after", |a| a.text("next")))
+            })
+            .build();
+        let catalog = parse_catalog(&response).unwrap();
+        assert_eq!(catalog.products.len(), 2);
+        assert_eq!(catalog.next_cursor.as_deref(), Some("next"));
+
+        let product = &catalog.products[0];
+        assert_eq!(product.id, "P1");
+        assert_eq!(product.name, "Sourdough loaf");
+        assert_eq!(product.price_amount_1000, Some(4_500_000));
+        assert_eq!(product.image_urls, vec!["https://cdn.example/1.jpg".to_string()]);
+        assert_eq!(product.status.as_deref(), Some("APPROVED"));
+        assert!(!product.is_hidden);
+
+        let single = Node::builder("iq").node(product_node("P3")).build();
+        assert_eq!(parse_product_response(&single).unwrap().id, "P3");
+        assert!(parse_catalog(&Node::builder("iq").build()).is_err());
+    }
+
+    #[test]
+    fn test_business_messages() {
+        let business = JID::new_user("6281234");
+        let product = parse_product(&product_node("P1")).unwrap();
+        let message = product_message(&business, &product);
+        let proto = product_message_to_proto(&message);
+        assert_eq!(parse_business_message(&proto), Some(BusinessMessage::Product(message)));
+
+        let order = wa_e2e::Message {
+            order_message: Some(Box::new(wa_e2e::OrderMessage {
+                order_id: Some("O1".to_string()),
+                item_count: Some(2),
+                status: Some(wa_e2e::order_message::OrderStatus::Inquiry as i32),
+                seller_jid: Some(business.to_string()),
+                total_amount1000: Some(9_000_000),
+                total_currency_code: Some("IDR".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        match parse_business_message(&order) {
+            Some(BusinessMessage::Order(order)) => {
+                assert_eq!(order.order_id, "O1");
+                assert_eq!(order.item_count, 2);
+                assert_eq!(order.status, Some(OrderStatus::Inquiry));
+                assert_eq!(order.seller_jid, Some(business));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse_business_message(&wa_e2e::Message::default()).is_none());
+    }
+}
@@ -0,0 +1,302 @@
+/// Export of chat history for archival and data access requests
+///
+/// [`Client::export_chat`](crate::Client::export_chat) writes the messages of
+/// a chat in one of two formats: JSON Lines with one object per message, for
+/// further processing, or the text layout of the official "Export chat"
+/// feature (`16/10/2026, 14:03 - Alice: Hello`), for people. Media is not
+/// downloaded; each media message gets a file name in the export and an
+/// entry in the [media manifest](MediaManifestEntry) with what is needed to
+/// download it later.
+
+use crate::types::{MediaMessage, MessageInfo, MessageType, SendableMessage};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Layout of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// One JSON object per line, see [`ExportedMessage`]
+    JsonLines,
+    /// The layout of "WhatsApp Chat with X.txt"
+    WhatsAppText,
+}
+
+/// Time span of the exported messages, both ends inclusive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRange {
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl ExportRange {
+    /// All messages
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn new(since: Option<SystemTime>, until: Option<SystemTime>) -> Self {
+        Self { since, until }
+    }
+
+    pub fn contains(&self, time: SystemTime) -> bool {
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
+    }
+}
+
+/// Media of an exported message, to download it separately
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaManifestEntry {
+    pub message_id: String,
+    /// Name the export refers to the media by
+    pub file_name: String,
+    pub mime_type: Option<String>,
+    pub direct_path: Option<String>,
+    /// Hex encoded SHA-256 of the decrypted file
+    pub file_sha256: Option<String>,
+    pub file_length: Option<u64>,
+}
+
+/// Result of an export
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatExport {
+    /// Number of exported messages
+    pub messages: usize,
+    pub media: Vec<MediaManifestEntry>,
+}
+
+/// A message as written to a JSON Lines export
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedMessage<'a> {
+    pub id: &'a str,
+    pub chat: String,
+    pub sender: String,
+    pub sender_name: &'a str,
+    /// Unix seconds
+    pub timestamp: u64,
+    pub from_me: bool,
+    #[serde(rename = "type")]
+    pub message_type: &'a MessageType,
+    pub text: Option<String>,
+    /// File name of the media, see [`MediaManifestEntry`]
+    pub media: Option<&'a str>,
+    pub quoted_id: Option<&'a str>,
+    pub edited: bool,
+    pub revoked: bool,
+    pub starred: bool,
+}
+
+/// Writes messages in an export format and collects the media manifest
+pub struct ChatExporter {
+    format: ExportFormat,
+    offset: FixedOffset,
+    export: ChatExport,
+}
+
+impl ChatExporter {
+    /// Create an exporter; text exports show times at `offset` from UTC
+    pub fn new(format: ExportFormat, offset: FixedOffset) -> Self {
+        Self {
+            format,
+            offset,
+            export: ChatExport::default(),
+        }
+    }
+
+    /// Lines for one message, ending with a newline
+    pub fn format_message(&mut self, message: &MessageInfo, sender_name: &str) -> crate::error::Result<String> {
+        let media = media_entry(message);
+        let file_name = media.as_ref().map(|entry| entry.file_name.clone());
+        let text = if message.revoked { None } else { message_text(message) };
+
+        let line = match self.format {
+            ExportFormat::JsonLines => {
+                let exported = ExportedMessage {
+                    id: &message.id,
+                    chat: message.chat.to_string(),
+                    sender: message.sender.to_non_ad().to_string(),
+                    sender_name,
+                    timestamp: message.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                    from_me: message.from_me,
+                    message_type: &message.message_type,
+                    text,
+                    media: file_name.as_deref(),
+                    quoted_id: message.quoted.as_ref().map(|quoted| quoted.id.as_str()),
+                    edited: message.edited_at.is_some(),
+                    revoked: message.revoked,
+                    starred: message.starred,
+                };
+                format!("{}\n", serde_json::to_string(&exported)?)
+            }
+            ExportFormat::WhatsAppText => {
+                let time = DateTime::<Utc>::from(message.timestamp).with_timezone(&self.offset);
+                let body = if message.revoked {
+                    "This message was deleted".to_string()
+                } else {
+                    match (file_name, text) {
+                        (Some(file), Some(caption)) => format!("{} (file attached)\n{}", file, caption),
+                        (Some(file), None) => format!("{} (file attached)", file),
+                        (None, Some(text)) => text,
+                        (None, None) => "<Media omitted>".to_string(),
+                    }
+                };
+                let edited = if message.edited_at.is_some() && !message.revoked { " <This message was edited>" } else { "" };
+                format!("{} - {}: {}{}\n", time.format("%d/%m/%Y, %H:%M"), sender_name, body, edited)
+            }
+        };
+
+        self.export.messages += 1;
+        self.export.media.extend(media);
+        Ok(line)
+    }
+
+    pub fn finish(self) -> ChatExport {
+        self.export
+    }
+}
+
+/// Readable text of a message: its text, caption or a short description
+pub fn message_text(message: &MessageInfo) -> Option<String> {
+    match message.content.as_ref()? {
+        SendableMessage::Text(text) => Some(text.text.clone()),
+        SendableMessage::ExtendedText(text) => Some(text.text.clone()),
+        SendableMessage::Image(media)
+        | SendableMessage::Video(media)
+        | SendableMessage::Document(media) => media.caption.clone(),
+        SendableMessage::Location(location) => Some(match &location.name {
+            Some(name) => format!("location: {} ({}, {})", name, location.latitude, location.longitude),
+            None => format!("location: {}, {}", location.latitude, location.longitude),
+        }),
+        SendableMessage::Contact(contact) => Some(format!("{}.vcf (file attached)", contact.display_name)),
+        SendableMessage::ContactsArray(contacts) => Some(format!("{}.vcf (file attached)", contacts.display_name)),
+        SendableMessage::Poll(poll) => {
+            let options: Vec<_> = poll.options.iter().map(|option| format!("OPTION: {}", option.name)).collect();
+            Some(format!("POLL:\n{}\n{}", poll.name, options.join("\n")))
+        }
+        _ => None,
+    }
+}
+
+/// Manifest entry of a media message, named like the official exports (`IMG-<id>.jpg`)
+pub fn media_entry(message: &MessageInfo) -> Option<MediaManifestEntry> {
+    let (prefix, media): (&str, &MediaMessage) = match message.content.as_ref()? {
+        SendableMessage::Image(media) => ("IMG", media),
+        SendableMessage::Video(media) => ("VID", media),
+        SendableMessage::Audio(media) => ("AUD", media),
+        SendableMessage::Voice(media) => ("PTT", media),
+        SendableMessage::Document(media) => ("DOC", media),
+        SendableMessage::Sticker(media) => ("STK", media),
+        _ => return None,
+    };
+    let extension = media.mime_type.as_deref().map(extension_for).unwrap_or("bin");
+    Some(MediaManifestEntry {
+        message_id: message.id.clone(),
+        file_name: format!("{}-{}.{}", prefix, message.id, extension),
+        mime_type: media.mime_type.clone(),
+        direct_path: media.direct_path.clone(),
+        file_sha256: media.file_sha256.as_deref().map(hex::encode),
+        file_length: media.file_length,
+    })
+}
+
+fn extension_for(mime_type: &str) -> &str {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    match essence {
+        "image/jpeg" => "jpg",
+        "audio/ogg" => "opus",
+        "audio/mpeg" => "mp3",
+        "video/quicktime" => "mov",
+        "text/plain" => "txt",
+        _ => essence.rsplit('/').next().filter(|subtype| !subtype.is_empty()).unwrap_or("bin"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{TextMessage, JID};
+    use std::time::Duration;
+
+    fn message(id: &str, content: SendableMessage, message_type: MessageType) -> MessageInfo {
+        MessageInfo {
+            id: id.to_string(),
+            chat: JID::new_user("1111"),
+            sender: JID::new_user("1111").with_device(2),
+            sender_alt: None,
+            push_name: None,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_792_152_180),
+            message_type,
+            from_me: false,
+            edited_at: None,
+            revoked: false,
+            quoted: None,
+            mentioned_jids: Vec::new(),
+            mentions_all: false,
+            group_mentions: Vec::new(),
+            forwarding_score: None,
+            content: Some(content),
+            starred: false,
+            kept: false,
+        }
+    }
+
+    fn image() -> SendableMessage {
+        SendableMessage::Image(MediaMessage {
+            url: None,
+            direct_path: Some("/v/t62/abc".to_string()),
+            media_key: None,
+            file_sha256: Some(vec![0xab, 0xcd]),
+            file_enc_sha256: None,
+            file_length: Some(2048),
+            mime_type: Some("image/jpeg".to_string()),
+            caption: Some("Look".to_string()),
+            width: None,
+            height: None,
+            page_count: None,
+            seconds: None,
+            ptt: None,
+            gif_playback: None,
+            jpeg_thumbnail: None,
+            context_info: None,
+        })
+    }
+
+    #[test]
+    fn test_text_export() {
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let mut exporter = ChatExporter::new(ExportFormat::WhatsAppText, offset);
+        let text = message("M1", SendableMessage::Text(TextMessage { text: "Hello\nthere".to_string() }), MessageType::Text);
+        assert_eq!(exporter.format_message(&text, "Alice").unwrap(), "16/10/2026, 14:03 - Alice: Hello\nthere\n");
+
+        let photo = message("M2", image(), MessageType::Image);
+        assert_eq!(exporter.format_message(&photo, "Alice").unwrap(), "16/10/2026, 14:03 - Alice: IMG-M2.jpg (file attached)\nLook\n");
+
+        let mut deleted = photo.clone();
+        deleted.revoked = true;
+        assert_eq!(exporter.format_message(&deleted, "Alice").unwrap(), "16/10/2026, 14:03 - Alice: This message was deleted\n");
+
+        let export = exporter.finish();
+        assert_eq!(export.messages, 3);
+        assert_eq!(export.media.len(), 2);
+        assert_eq!(export.media[0].file_sha256.as_deref(), Some("abcd"));
+        assert_eq!(export.media[0].direct_path.as_deref(), Some("/v/t62/abc"));
+    }
+
+    #[test]
+    fn test_json_lines_export() {
+        let mut exporter = ChatExporter::new(ExportFormat::JsonLines, FixedOffset::east_opt(0).unwrap());
+        let line = exporter.format_message(&message("M2", image(), MessageType::Image), "Alice").unwrap();
+        assert!(line.ends_with('\n'));
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["sender"], "1111@s.whatsapp.net");
+        assert_eq!(json["timestamp"], 1_792_152_180);
+        assert_eq!(json["text"], "Look");
+        assert_eq!(json["media"], "IMG-M2.jpg");
+
+        let range = ExportRange::new(Some(UNIX_EPOCH + Duration::from_secs(10)), None);
+        assert!(!range.contains(UNIX_EPOCH));
+        assert!(range.contains(UNIX_EPOCH + Duration::from_secs(10)));
+        assert!(ExportRange::all().contains(UNIX_EPOCH));
+    }
+}
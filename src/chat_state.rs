@@ -0,0 +1,166 @@
+/// Typing and recording indicators
+///
+/// Users announce that they are typing or recording a voice note with
+/// `<chatstate>` stanzas: `<composing/>`, `<composing media="audio"/>` or
+/// `<paused/>`. In groups the stanza comes from the group and names the user
+/// in its `participant` attribute. Clients repeat `composing` while the user
+/// is still typing but do not always send `paused`, so [`TypingTracker`]
+/// forgets indicators that were not repeated within a timeout.
+
+use crate::{binary::Node, types::JID};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a typing indicator lasts without being repeated
+pub const DEFAULT_TYPING_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// What a user is doing in a chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatState {
+    Composing,
+    /// Recording a voice note
+    Recording,
+    Paused,
+}
+
+/// A user started or stopped typing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatStateEvent {
+    pub chat: JID,
+    pub sender: JID,
+    pub state: ChatState,
+}
+
+/// A user currently typing or recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ActiveTyper {
+    pub jid: JID,
+    pub state: ChatState,
+}
+
+/// Parse an incoming `<chatstate>` stanza
+pub fn parse_chat_state(node: &Node) -> Option<ChatStateEvent> {
+    if node.tag != "chatstate" {
+        return None;
+    }
+    let chat: JID = node.get_attr("from")?.parse().ok()?;
+    let sender = match node.get_attr("participant") {
+        Some(participant) => participant.parse().ok()?,
+        None => chat.clone(),
+    };
+    let child = node.get_children()?.first()?;
+    let state = match (child.tag.as_str(), child.get_attr("media").map(String::as_str)) {
+        ("composing", Some("audio")) => ChatState::Recording,
+        ("composing", _) => ChatState::Composing,
+        ("paused", _) => ChatState::Paused,
+        _ => return None,
+    };
+    Some(ChatStateEvent { chat: chat.to_non_ad(), sender: sender.to_non_ad(), state })
+}
+
+/// Who is typing in which chat
+pub struct TypingTracker {
+    timeout: Duration,
+    chats: Mutex<HashMap<JID, HashMap<JID, (ChatState, Instant)>>>,
+}
+
+impl TypingTracker {
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_TYPING_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            chats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Apply a chat state update
+    pub fn update(&self, event: &ChatStateEvent, now: Instant) {
+        let mut chats = self.chats.lock().unwrap();
+        if event.state == ChatState::Paused {
+            self.remove(&mut chats, &event.chat, &event.sender);
+        } else {
+            chats.entry(event.chat.clone()).or_default().insert(event.sender.clone(), (event.state, now));
+        }
+    }
+
+    /// Forget the indicator of a user, e.g. because their message arrived
+    pub fn clear(&self, chat: &JID, sender: &JID) {
+        let mut chats = self.chats.lock().unwrap();
+        self.remove(&mut chats, &chat.to_non_ad(), &sender.to_non_ad());
+    }
+
+    /// Users typing or recording in a chat, dropping expired indicators
+    pub fn active(&self, chat: &JID, now: Instant) -> Vec<ActiveTyper> {
+        let chat = chat.to_non_ad();
+        let mut chats = self.chats.lock().unwrap();
+        let Some(typers) = chats.get_mut(&chat) else {
+            return Vec::new();
+        };
+        typers.retain(|_, (_, since)| now.duration_since(*since) < self.timeout);
+        let mut active: Vec<_> = typers.iter().map(|(jid, (state, _))| ActiveTyper { jid: jid.clone(), state: *state }).collect();
+        if typers.is_empty() {
+            chats.remove(&chat);
+        }
+        active.sort_by(|a, b| a.jid.to_string().cmp(&b.jid.to_string()));
+        active
+    }
+
+    fn remove(&self, chats: &mut HashMap<JID, HashMap<JID, (ChatState, Instant)>>, chat: &JID, sender: &JID) {
+        if let Some(typers) = chats.get_mut(chat) {
+            typers.remove(sender);
+            if typers.is_empty() {
+                chats.remove(chat);
+            }
+        }
+    }
+}
+
+impl Default for TypingTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_state(participant: &str, child: Node) -> Node {
+        Node::builder("chatstate").attr("from", "1-2@g.us").attr("participant", participant).nodes(vec![child]).build()
+    }
+
+    #[test]
+    fn test_track_group_typers() {
+        let group = JID::new_group("1-2");
+        let alice = JID::new_user("1111");
+        let bob = JID::new_user("2222");
+        let tracker = TypingTracker::with_timeout(Duration::from_secs(10));
+        let now = Instant::now();
+
+        let typing = parse_chat_state(&chat_state("1111@s.whatsapp.net", Node::builder("composing").build())).unwrap();
+        assert_eq!(typing, ChatStateEvent { chat: group.clone(), sender: alice.clone(), state: ChatState::Composing });
+        tracker.update(&typing, now);
+        let recording = parse_chat_state(&chat_state("2222@s.whatsapp.net", Node::builder("composing").attr("media", "audio").build())).unwrap();
+        tracker.update(&recording, now + Duration::from_secs(5));
+        assert_eq!(tracker.active(&group, now + Duration::from_secs(6)), vec![
+            ActiveTyper { jid: alice.clone(), state: ChatState::Composing },
+            ActiveTyper { jid: bob.clone(), state: ChatState::Recording },
+        ]);
+
+        // Alice's indicator expires, Bob pauses
+        assert_eq!(tracker.active(&group, now + Duration::from_secs(11)).len(), 1);
+        let paused = parse_chat_state(&chat_state("2222@s.whatsapp.net", Node::builder("paused").build())).unwrap();
+        tracker.update(&paused, now + Duration::from_secs(12));
+        assert!(tracker.active(&group, now + Duration::from_secs(12)).is_empty());
+
+        tracker.update(&typing, now);
+        tracker.clear(&group, &alice.with_device(2));
+        assert!(tracker.active(&group, now).is_empty());
+    }
+}
@@ -1,27 +1,64 @@
 use crate::{
-    appstate::{AppStateManager, AppStateManagerConfig, AppStateDataType, SyncRequest, SyncPriority},
-    auth::{AuthManager, AuthState},
+    anti_spam::{AntiSpamConfig, AntiSpamGuard, AntiSpamStats},
+    appstate::{self, AppStateManager, AppStateManagerConfig, AppStateDataType, ExpandedAppStateKeys, HashState, PatchInfo, PatchName, SyncRequest, SyncPriority},
+    auth::{device_props, login, logout, qr, DeviceProps, WebVersion, AuthManager, LogoutOptions, AuthState, PrimaryDeviceMonitor, PrimaryDeviceStatus, PrimaryDeviceTransition, QREvent},
+    chat_export::{ChatExport, ChatExporter, ExportFormat, ExportRange},
+    chat_state::{self, ActiveTyper, TypingTracker},
+    receipt_batch::{Batched, ReceiptBatch, ReceiptBatcher, ReceiptKey},
     connection::{
         ConnectionConfig, ConnectionEvent, ConnectionEventHandler,
-        manager::ConnectionManager,
-        rate_limit::{MultiRateLimiter, RateLimitResult},
+        manager::{self, ConnectionManager, SessionConnector},
+        stream_error::{self, StreamError, StreamErrorAction},
+        is_recoverable_error,
+        rate_limit::{self, MultiRateLimiter, RateLimitConfig, RateLimitResult},
         retry::{RetryExecutor, RetryPolicy, RetryResult},
     },
-    database::Database,
+    contacts::{self, AddressBookDiff, ContactSyncMode, ContactSyncResult, PhoneContact},
+    decrypt_retry::{self, DecryptFailureAction, DecryptRetryTracker},
+    dedup::{self, MessageDedup, OfflineSync, SeenMessages},
+    database::{sqlite::{OutboxEntry, SqliteAppStateKeyStore, SqliteContactStore, SqliteDecryptFailureStore, SqliteLidStore, SqliteOutboxStore, SqlitePollStore, SqliteScheduledMessageStore, SqliteSettingsStore, ScheduledMessage}, Database},
+    devices::{self, DeviceCache, LinkedDevice},
+    dispatch::{self, NodeHandler, ReceiveWorkerPool},
     error::{Error, Result},
+    group::{self, CommunityInfo, CommunityManager, CreateCommunityRequest, CreateGroupRequest, GroupInfo, GroupManager, GroupService, LinkedGroup, MembershipRequest, ParticipantOperationResult},
+    history_sync::{self, HistoryAnchor},
     messaging::{
-        MessageBuilder, MessageQueue, MessageStatusTracker, MessageEditor,
-        MessageThreadManager, FailedMessage
+        self, MessageBuilder, MessageQueue, MessageStatusTracker, MessageEditor, ChatSendScheduler,
+        MessageThreadManager, FailedMessage, PendingMessage, PollResults, PollResultsTracker,
+        MessageReactions, ReactionTracker, EphemeralTimers,
     },
-    socket::NoiseSocket,
-    store::DeviceStore,
+    binary::{BinaryEncoder, Node},
+    blocklist::{self, Blocklist, BLOCKLIST_NAMESPACE},
+    business::{self, Catalog, Product},
+    replay::{self, ReplayFilter},
+    safety::SendGuard,
+    server_time,
+    request::{InfoQuery, IqSender, ResponseWaiters, SocketIqSender},
+    signal::{self, IdentityChangePolicy, IdentityCheck, SafetyNumber, SignalProtocolManager, TrustLevel},
+    socket::{NoiseSocket, ProxyConfig, TransportFactory},
+    stanza::{StanzaHandler, StanzaHandlerId, StanzaHandlerRegistry, StanzaMatcher},
+    store::{DeviceData, DeviceStore, IntoStore, Store},
     types::{
         Event, EventHandler, JID, SendableMessage, MessageInfo, MessageReceipt,
         MessageStatus, TextMessage, ExtendedTextMessage, MediaMessage, LocationMessage,
-        ContactMessage, ReactionMessage, PollMessage, MessageKey, ContextInfo
+        ContactMessage, ContactsArrayMessage, ReactionMessage, PollMessage, MessageKey, ContextInfo,
+        IsOnWhatsAppResult, PollUpdateMessage, CommunityJoinRequestEvent, GroupJoinRequestEvent,
+        ReactionUpdatedEvent, MessageEditedEvent, MessageRevokeEvent, ProtocolMessage, ProtocolMessageType,
+        OutboxFlushedEvent, ScheduledMessageSentEvent, DeliverySummary, ReceiptAggregateEvent, BlocklistChange, BlocklistChangeAction,
+        KeepInChatMessage, MessageKeptEvent, MessageStarredEvent, HistorySyncNotification,
     },
-    media::MediaManager,
+    usync,
+    wirelog::{Direction, LoggedStanza, WireLog},
+    media::{retry as media_retry, AutoDownloadPolicy, MediaInfo, MediaManager, MediaRetryEvent, MediaRetryOutcome, StickerMetadata},
+    metrics,
+    msg_transport,
+    notification::{self, NotificationKind, UnhandledNotification},
+    prekeys,
+    presence::{self, PresenceTracker},
+    proto::wa_server_sync::{ExternalBlobReference, SyncdMutations},
+    push::{self, PushConfig},
 };
+use prost::Message as _;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use tracing::{debug, info, warn};
@@ -35,6 +72,130 @@ pub struct ClientConfig {
     pub connection_config: ConnectionConfig,
     pub app_state_config: AppStateManagerConfig,
     pub enable_app_state_sync: bool,
+    /// Number of contacts sent per usync query when syncing the address book
+    pub contact_batch_size: usize,
+    /// How often expired disappearing messages are removed from the local history
+    pub ephemeral_reap_interval: std::time::Duration,
+    /// Proxy for the WebSocket connection and media transfers
+    pub proxy: Option<ProxyConfig>,
+    /// Time to wait for the server to acknowledge a sent message
+    pub message_ack_timeout: std::time::Duration,
+    /// How long messages sent while offline are kept in the outbox, `None` keeps them forever
+    pub outbox_ttl: Option<std::time::Duration>,
+    /// Number of chats sending messages at the same time; sends within a chat are always ordered
+    pub max_parallel_chat_sends: usize,
+    /// How often the number of our pre-keys left on the server is checked
+    pub prekey_check_interval: std::time::Duration,
+    /// What to do when a contact's identity key changes
+    pub identity_change_policy: IdentityChangePolicy,
+    /// Number of workers processing incoming stanzas, see [`Client::start_receive_workers`]
+    pub receive_workers: usize,
+    /// Stanzas queued per receive worker before [`Client::receive_node`] waits
+    pub receive_queue_size: usize,
+    /// Limits replacing the [`WhatsAppRateLimits`](crate::connection::rate_limit::WhatsAppRateLimits)
+    /// defaults, by category
+    pub rate_limits: std::collections::HashMap<String, RateLimitConfig>,
+    /// Number of recent stanzas kept for [`Client::dump_recent_stanzas`], 0 disables the log
+    pub stanza_log_capacity: usize,
+    /// Send a delivery receipt for every received message
+    pub auto_mark_delivered: bool,
+    /// Collect delivery and read receipts to the same chat and sender for
+    /// this long and send them as one stanza, zero sends each right away
+    pub receipt_batch_window: std::time::Duration,
+    /// Listen only: receive, decrypt and emit events, but refuse every send
+    /// with [`Error::PassiveMode`] and send no read receipts or presence
+    pub passive: bool,
+    /// Deduplicate, cap and space out messages per recipient, see [`crate::anti_spam`]
+    pub anti_spam: Option<AntiSpamConfig>,
+    /// Directory downloaded media is cached in, `None` disables the cache
+    pub media_cache_dir: Option<std::path::PathBuf>,
+    /// Size the media cache is trimmed to
+    pub media_cache_size: u64,
+    /// Received media downloaded right away into the media cache
+    pub auto_download: AutoDownloadPolicy,
+    /// Endpoint registered for offline wakeups after every connect
+    pub push_config: Option<PushConfig>,
+    /// Reconnect when [`Client::handle_push_wakeup`] is called while disconnected
+    pub reconnect_on_push: bool,
+    /// How often [`Client::start_message_scheduler`] looks for due scheduled messages
+    pub scheduled_message_interval: std::time::Duration,
+    /// Download and apply the history syncs the primary device sends after
+    /// pairing and in answer to [`Client::fetch_history`]
+    pub enable_history_sync: bool,
+    /// Platform, OS and WhatsApp Web version announced on login
+    pub device_props: DeviceProps,
+}
+
+/// Fields of [`ClientConfig`] that only take effect when the client is created
+const STARTUP_ONLY_CONFIG: &[&str] = &[
+    "app_state_config",
+    "enable_app_state_sync",
+    "ephemeral_reap_interval",
+    "max_parallel_chat_sends",
+    "prekey_check_interval",
+    "receive_workers",
+    "receive_queue_size",
+    "stanza_log_capacity",
+    "anti_spam",
+    "media_cache_dir",
+    "media_cache_size",
+    "scheduled_message_interval",
+];
+
+impl ClientConfig {
+    /// Names of the fields that differ between two configurations
+    pub fn changed_fields(&self, other: &ClientConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        macro_rules! compare {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if format!("{:?}", self.$field) != format!("{:?}", other.$field) {
+                        changed.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+        compare!(
+            auto_reconnect,
+            initial_auto_reconnect,
+            synchronous_ack,
+            connection_config,
+            app_state_config,
+            enable_app_state_sync,
+            contact_batch_size,
+            ephemeral_reap_interval,
+            proxy,
+            message_ack_timeout,
+            outbox_ttl,
+            max_parallel_chat_sends,
+            prekey_check_interval,
+            identity_change_policy,
+            receive_workers,
+            receive_queue_size,
+            stanza_log_capacity,
+            auto_mark_delivered,
+            receipt_batch_window,
+            passive,
+            anti_spam,
+            media_cache_dir,
+            media_cache_size,
+            auto_download,
+            push_config,
+            reconnect_on_push,
+            scheduled_message_interval,
+            enable_history_sync,
+            device_props,
+        );
+        // Map iteration order is unstable, compare by category
+        let rate_limits_changed = self.rate_limits.len() != other.rate_limits.len()
+            || self.rate_limits.iter().any(|(category, limits)| {
+                other.rate_limits.get(category).map(|other| format!("{:?}", other)) != Some(format!("{:?}", limits))
+            });
+        if rate_limits_changed {
+            changed.push("rate_limits");
+        }
+        changed
+    }
 }
 
 impl Default for ClientConfig {
@@ -46,64 +207,607 @@ impl Default for ClientConfig {
             connection_config: ConnectionConfig::default(),
             app_state_config: AppStateManagerConfig::default(),
             enable_app_state_sync: true,
+            contact_batch_size: contacts::DEFAULT_CONTACT_BATCH_SIZE,
+            ephemeral_reap_interval: std::time::Duration::from_secs(30),
+            proxy: None,
+            message_ack_timeout: std::time::Duration::from_secs(20),
+            outbox_ttl: Some(std::time::Duration::from_secs(24 * 60 * 60)),
+            max_parallel_chat_sends: messaging::DEFAULT_MAX_PARALLEL_CHATS,
+            prekey_check_interval: prekeys::DEFAULT_PREKEY_CHECK_INTERVAL,
+            identity_change_policy: IdentityChangePolicy::default(),
+            receive_workers: dispatch::DEFAULT_RECEIVE_WORKERS,
+            receive_queue_size: dispatch::DEFAULT_RECEIVE_QUEUE_SIZE,
+            rate_limits: std::collections::HashMap::new(),
+            stanza_log_capacity: 0,
+            auto_mark_delivered: true,
+            receipt_batch_window: std::time::Duration::ZERO,
+            passive: false,
+            anti_spam: None,
+            media_cache_dir: None,
+            media_cache_size: crate::media::DEFAULT_MEDIA_CACHE_SIZE,
+            auto_download: AutoDownloadPolicy::default(),
+            push_config: None,
+            reconnect_on_push: true,
+            scheduled_message_interval: std::time::Duration::from_secs(15),
+            enable_history_sync: true,
+            device_props: DeviceProps::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Reject settings that cannot work, alone or together
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::Protocol(format!("Invalid client configuration: {}", message)));
+        if self.receive_workers == 0 || self.receive_queue_size == 0 {
+            return invalid("receive_workers and receive_queue_size must be at least 1");
+        }
+        if self.max_parallel_chat_sends == 0 {
+            return invalid("max_parallel_chat_sends must be at least 1");
         }
+        if self.media_cache_dir.is_some() && self.media_cache_size == 0 {
+            return invalid("media_cache_size must not be 0 when media_cache_dir is set");
+        }
+        if self.auto_download.is_enabled() && self.media_cache_dir.is_none() {
+            return invalid("auto_download needs a media_cache_dir to download into");
+        }
+        if self.passive && self.anti_spam.is_some() {
+            return invalid("anti_spam has no effect on a passive client, which sends nothing");
+        }
+        Ok(())
+    }
+}
+
+/// Builder of a [`Client`]
+///
+/// ```ignore
+/// let client = Client::builder()
+///     .store(store)
+///     .database(database)
+///     .proxy(proxy)
+///     .enable_history_sync(true)
+///     .build()
+///     .await?;
+/// ```
+///
+/// [`build`](Self::build) validates the configuration and only sets up the
+/// client in memory; the app state manager and the caches saved by the last
+/// run are loaded from the database on the first [`Client::connect`].
+#[derive(Default)]
+pub struct ClientBuilder {
+    store: Option<Arc<dyn Store>>,
+    database: Option<Arc<Database>>,
+    config: ClientConfig,
+}
+
+impl ClientBuilder {
+    /// Store holding the device credentials and Signal state, required
+    pub fn store(mut self, store: impl IntoStore) -> Self {
+        self.store = Some(store.into_store());
+        self
+    }
+    
+    /// Database for messages, contacts and app state, required
+    pub fn database(mut self, database: Arc<Database>) -> Self {
+        self.database = Some(database);
+        self
+    }
+    
+    /// Start from a complete configuration; later calls change single fields of it
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+    
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.config.proxy = Some(proxy);
+        self
+    }
+    
+    pub fn enable_history_sync(mut self, enabled: bool) -> Self {
+        self.config.enable_history_sync = enabled;
+        self
+    }
+    
+    pub fn enable_app_state_sync(mut self, enabled: bool) -> Self {
+        self.config.enable_app_state_sync = enabled;
+        self
+    }
+    
+    pub fn auto_reconnect(mut self, enabled: bool) -> Self {
+        self.config.auto_reconnect = enabled;
+        self
+    }
+    
+    /// See [`ClientConfig::passive`]
+    pub fn passive(mut self, passive: bool) -> Self {
+        self.config.passive = passive;
+        self
+    }
+    
+    pub fn anti_spam(mut self, config: AntiSpamConfig) -> Self {
+        self.config.anti_spam = Some(config);
+        self
+    }
+    
+    /// Cache downloaded media in `dir`, trimmed to `size` bytes
+    pub fn media_cache(mut self, dir: impl Into<std::path::PathBuf>, size: u64) -> Self {
+        self.config.media_cache_dir = Some(dir.into());
+        self.config.media_cache_size = size;
+        self
+    }
+    
+    /// Needs a [`media_cache`](Self::media_cache) to download into
+    pub fn auto_download(mut self, policy: AutoDownloadPolicy) -> Self {
+        self.config.auto_download = policy;
+        self
+    }
+    
+    /// Send receipts to the same chat and sender collected over `window` as one stanza
+    pub fn receipt_batch_window(mut self, window: std::time::Duration) -> Self {
+        self.config.receipt_batch_window = window;
+        self
+    }
+    
+    pub fn push(mut self, config: PushConfig) -> Self {
+        self.config.push_config = Some(config);
+        self
+    }
+    
+    /// Replace the default limits of a rate limit category
+    pub fn rate_limit(mut self, category: impl Into<String>, limits: RateLimitConfig) -> Self {
+        self.config.rate_limits.insert(category.into(), limits);
+        self
+    }
+    
+    /// Browser, OS and Web version announced on login
+    pub fn device_props(mut self, props: DeviceProps) -> Self {
+        self.config.device_props = props;
+        self
+    }
+    
+    pub fn receive_workers(mut self, workers: usize) -> Self {
+        self.config.receive_workers = workers;
+        self
+    }
+    
+    /// Validate the configuration and create the client
+    pub async fn build(self) -> Result<Client> {
+        let store = self.store.ok_or_else(|| Error::Protocol("ClientBuilder needs a device store".to_string()))?;
+        let database = self.database.ok_or_else(|| Error::Protocol("ClientBuilder needs a database".to_string()))?;
+        self.config.validate()?;
+        Ok(Client::create(store, database, self.config))
     }
 }
 
 /// Main WhatsApp client
 pub struct Client {
-    store: Arc<dyn DeviceStore>,
+    store: Arc<dyn Store>,
     socket: Arc<Mutex<Option<NoiseSocket>>>,
-    config: ClientConfig,
+    config: std::sync::RwLock<ClientConfig>,
     event_handlers: Arc<RwLock<Vec<EventHandler>>>,
     is_logged_in: Arc<std::sync::atomic::AtomicBool>,
     auth_manager: Arc<Mutex<AuthManager>>,
     message_queue: Arc<Mutex<MessageQueue>>,
+    send_scheduler: Arc<ChatSendScheduler>,
     message_status_tracker: Arc<MessageStatusTracker>,
     message_thread_manager: Arc<Mutex<MessageThreadManager>>,
     media_manager: Arc<tokio::sync::Mutex<MediaManager>>,
+    /// Media retry receipts awaiting their `mediaretry` notification, by message id
+    media_retries: Arc<Mutex<std::collections::HashMap<String, PendingMediaRetry>>>,
     connection_manager: Arc<Mutex<Option<ConnectionManager>>>,
     rate_limiter: Arc<MultiRateLimiter>,
     retry_executor: Arc<RetryExecutor>,
     app_state_manager: Arc<Mutex<Option<AppStateManager>>>,
+    /// Persisted version and LT-Hash of each app state collection, the base of outgoing patches
+    app_state: Arc<appstate::SyncContext>,
+    /// Held while a collection is fetched or a patch is published so versions stay in order
+    app_state_lock: Arc<Mutex<()>>,
     database: Arc<Database>,
+    outbox: Arc<SqliteOutboxStore>,
+    scheduled_messages: Arc<SqliteScheduledMessageStore>,
+    /// Held while due scheduled messages are sent, so none is sent twice
+    scheduled_dispatch: Mutex<()>,
+    stanza_handlers: Arc<StanzaHandlerRegistry>,
+    response_waiters: Arc<ResponseWaiters>,
+    iq_sender: Arc<SocketIqSender>,
+    poll_results: Arc<PollResultsTracker>,
+    /// Persisted copy of the polls tracked by `poll_results`
+    polls: Arc<SqlitePollStore>,
+    reactions: Arc<ReactionTracker>,
+    ephemeral_timers: Arc<EphemeralTimers>,
+    device_cache: Arc<DeviceCache>,
+    signal_manager: Arc<Mutex<SignalProtocolManager>>,
+    primary_monitor: Arc<PrimaryDeviceMonitor>,
+    replay_filter: Arc<ReplayFilter>,
+    message_dedup: Arc<MessageDedup>,
+    typing: Arc<TypingTracker>,
+    presence: Arc<PresenceTracker>,
+    contacts: Arc<SqliteContactStore>,
+    /// Stanzas announced by the last `<ib><offline>`
+    offline_stanzas: std::sync::atomic::AtomicU32,
+    decrypt_retries: Arc<DecryptRetryTracker>,
+    decrypt_failures: Arc<SqliteDecryptFailureStore>,
+    send_guard: Arc<SendGuard>,
+    receipt_batcher: Arc<ReceiptBatcher>,
+    anti_spam: Option<Arc<AntiSpamGuard>>,
+    blocklist: Arc<RwLock<Blocklist>>,
+    labels: Arc<RwLock<appstate::Labels>>,
+    transport_factory: std::sync::RwLock<Option<TransportFactory>>,
+    receive_pool: RwLock<Option<ReceiveWorkerPool>>,
+    group_manager: Arc<GroupManager>,
+    community_manager: Arc<Mutex<CommunityManager>>,
+    /// Group service sharing `group_manager` and `community_manager`
+    groups: Arc<GroupService>,
+    /// Set by [`Client::shutdown`], new sends are refused from then on
+    shutting_down: std::sync::atomic::AtomicBool,
+    /// Tasks aborted on shutdown
+    background_tasks: std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>,
+    wire_log: Arc<WireLog>,
+    /// Set once [`Client::initialize`] loaded the saved state
+    initialized: tokio::sync::OnceCell<()>,
+    /// WhatsApp Web version fetched by [`Client::refresh_web_version`]
+    web_version: Arc<std::sync::RwLock<Option<WebVersion>>>,
+    /// WhatsApp Web version sent in the latest login
+    announced_version: Arc<std::sync::RwLock<Option<WebVersion>>>,
+}
+
+/// Settings key of the reactions saved on shutdown
+const REACTIONS_SETTING: &str = "reaction_cache";
+
+/// Settings key of the recently seen message ids, see [`crate::dedup`]
+const SEEN_MESSAGES_SETTING: &str = "seen_message_ids";
+
+/// Media retry request waiting for the sender's answer
+struct PendingMediaRetry {
+    media_key: Vec<u8>,
+    waiter: Option<tokio::sync::oneshot::Sender<MediaRetryEvent>>,
 }
 
 impl Client {
     /// Create a new WhatsApp client
-    pub async fn new(store: Arc<dyn DeviceStore>, database: Arc<Database>) -> Result<Self> {
+    ///
+    /// A plain `Arc<dyn DeviceStore>` is accepted too and keeps the Signal
+    /// state in memory, see [`DeviceStoreAdapter`](crate::store::DeviceStoreAdapter).
+    pub async fn new(store: impl IntoStore, database: Arc<Database>) -> Result<Self> {
         Self::with_config(store, database, ClientConfig::default()).await
     }
     
     /// Create a new WhatsApp client with custom configuration
-    pub async fn with_config(store: Arc<dyn DeviceStore>, database: Arc<Database>, config: ClientConfig) -> Result<Self> {
-        // Initialize app state manager if enabled
-        let app_state_manager = if config.enable_app_state_sync {
-            let manager = AppStateManager::with_config(database.clone(), config.app_state_config.clone()).await?;
-            Some(manager)
-        } else {
-            None
-        };
+    pub async fn with_config(store: impl IntoStore, database: Arc<Database>, config: ClientConfig) -> Result<Self> {
+        let client = Self::create(store.into_store(), database, config);
+        client.initialize().await?;
+        Ok(client)
+    }
+    
+    /// Start building a client, see [`ClientBuilder`]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+    
+    /// Set up the client without reading the database, see [`initialize`](Self::initialize)
+    fn create(store: Arc<dyn Store>, database: Arc<Database>, config: ClientConfig) -> Self {
+        let socket = Arc::new(Mutex::new(None));
+        let response_waiters = Arc::new(ResponseWaiters::new());
+        let wire_log = Arc::new(WireLog::new(config.stanza_log_capacity));
+        let iq_sender = Arc::new(
+            SocketIqSender::new(socket.clone(), response_waiters.clone()).with_wire_log(wire_log.clone()),
+        );
+        let group_manager = Arc::new(GroupManager::new().with_iq_sender(iq_sender.clone()));
+        let community_manager = Arc::new(Mutex::new(CommunityManager::new().with_iq_sender(iq_sender.clone())));
 
-        Ok(Self {
+        let mut media_manager = match &config.media_cache_dir {
+            Some(dir) => MediaManager::with_cache(dir, config.media_cache_size),
+            None => MediaManager::new(),
+        };
+        media_manager.set_proxy(config.proxy.clone());
+        
+        let message_thread_manager = Arc::new(Mutex::new(MessageThreadManager::new()));
+        let ephemeral_reaper = Self::spawn_ephemeral_reaper(Arc::downgrade(&message_thread_manager), config.ephemeral_reap_interval);
+        
+        // Loaded from the store by `initialize` once a device is paired
+        let signal_manager = Arc::new(Mutex::new(SignalProtocolManager::unregistered()));
+        let prekey_monitor = Self::spawn_prekey_monitor(
+            Arc::downgrade(&iq_sender),
+            Arc::downgrade(&signal_manager),
+            config.prekey_check_interval,
+        );
+        let groups = Arc::new(GroupService::shared(
+            group_manager.clone(),
+            community_manager.clone(),
+            signal_manager.clone(),
+            store.clone(),
+        ));
+        let auth_manager = Arc::new(Mutex::new(
+            AuthManager::new()
+                .with_iq_sender(iq_sender.clone())
+                .with_signal_manager(signal_manager.clone())
+                .with_store(store.clone()),
+        ));
+        
+        Self {
             store,
-            socket: Arc::new(Mutex::new(None)),
-            config: config.clone(),
+            socket,
+            config: std::sync::RwLock::new(config.clone()),
             event_handlers: Arc::new(RwLock::new(Vec::new())),
             is_logged_in: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            auth_manager: Arc::new(Mutex::new(AuthManager::new())),
+            auth_manager,
             message_queue: Arc::new(Mutex::new(MessageQueue::new())),
+            send_scheduler: Arc::new(ChatSendScheduler::new(config.max_parallel_chat_sends)),
             message_status_tracker: Arc::new(MessageStatusTracker::new()),
-            message_thread_manager: Arc::new(Mutex::new(MessageThreadManager::new())),
-            media_manager: Arc::new(tokio::sync::Mutex::new(MediaManager::new())),
+            message_thread_manager,
+            media_manager: Arc::new(tokio::sync::Mutex::new(media_manager)),
+            media_retries: Arc::new(Mutex::new(std::collections::HashMap::new())),
             connection_manager: Arc::new(Mutex::new(None)),
-            rate_limiter: Arc::new(MultiRateLimiter::new()),
+            rate_limiter: Arc::new({
+                let rate_limiter = MultiRateLimiter::new();
+                for (category, limits) in &config.rate_limits {
+                    rate_limiter.configure(category, limits.clone());
+                }
+                rate_limiter
+            }),
             retry_executor: Arc::new(RetryExecutor::new(RetryPolicy::network_operations())),
-            app_state_manager: Arc::new(Mutex::new(app_state_manager)),
+            app_state_manager: Arc::new(Mutex::new(None)),
+            app_state: Arc::new(appstate::SyncContext::new(database.clone())),
+            app_state_lock: Arc::new(Mutex::new(())),
+            outbox: Arc::new(SqliteOutboxStore::new(database.pool().clone())),
+            scheduled_messages: Arc::new(SqliteScheduledMessageStore::new(database.pool().clone())),
+            contacts: Arc::new(SqliteContactStore::new(database.pool().clone())),
+            polls: Arc::new(SqlitePollStore::new(database.pool().clone())),
+            decrypt_failures: Arc::new(SqliteDecryptFailureStore::new(database.pool().clone())),
+            scheduled_dispatch: Mutex::new(()),
             database,
+            stanza_handlers: Arc::new(StanzaHandlerRegistry::new()),
+            response_waiters,
+            iq_sender,
+            poll_results: Arc::new(PollResultsTracker::new()),
+            reactions: Arc::new(ReactionTracker::new()),
+            ephemeral_timers: Arc::new(EphemeralTimers::new()),
+            device_cache: Arc::new(DeviceCache::new()),
+            signal_manager,
+            primary_monitor: Arc::new(PrimaryDeviceMonitor::new()),
+            replay_filter: Arc::new(ReplayFilter::new()),
+            message_dedup: Arc::new(MessageDedup::new()),
+            typing: Arc::new(TypingTracker::new()),
+            presence: Arc::new(PresenceTracker::new()),
+            offline_stanzas: std::sync::atomic::AtomicU32::new(0),
+            decrypt_retries: Arc::new(DecryptRetryTracker::new()),
+            send_guard: Arc::new({
+                let send_guard = SendGuard::new();
+                send_guard.set_passive(config.passive);
+                send_guard
+            }),
+            receipt_batcher: Arc::new(ReceiptBatcher::new()),
+            anti_spam: config.anti_spam.clone().map(|config| Arc::new(AntiSpamGuard::new(config))),
+            blocklist: Arc::new(RwLock::new(Blocklist::default())),
+            labels: Arc::new(RwLock::new(appstate::Labels::default())),
+            transport_factory: std::sync::RwLock::new(None),
+            receive_pool: RwLock::new(None),
+            group_manager,
+            community_manager,
+            groups,
+            shutting_down: std::sync::atomic::AtomicBool::new(false),
+            background_tasks: std::sync::Mutex::new(vec![ephemeral_reaper, prekey_monitor]),
+            wire_log,
+            initialized: tokio::sync::OnceCell::new(),
+            web_version: Arc::new(std::sync::RwLock::new(None)),
+            announced_version: Arc::new(std::sync::RwLock::new(None)),
+        }
+    }
+    
+    /// Create the app state manager, load the Signal state of the paired
+    /// device and restore the caches saved by the last run. Runs once, on creation or, for clients built with a
+    /// [`ClientBuilder`], on the first [`connect`](Self::connect).
+    async fn initialize(&self) -> Result<()> {
+        self.initialized.get_or_try_init(|| async {
+            let config = self.config();
+            if config.enable_app_state_sync {
+                let manager = AppStateManager::with_config(self.database.clone(), config.app_state_config.clone()).await?;
+                // Report conflicts left to the user as events
+                let handlers = Arc::clone(&self.event_handlers);
+                manager.add_conflict_handler(Arc::new(move |conflict| {
+                    let handlers = Arc::clone(&handlers);
+                    tokio::spawn(async move {
+                        let event = Event::AppStateConflict(conflict);
+                        let handlers = handlers.read().await;
+                        for handler in handlers.iter() {
+                            if !handler(event.clone()) {
+                                break;
+                            }
+                        }
+                    });
+                })).await;
+                *self.app_state_manager.lock().await = Some(manager);
+            }
+            if let Some(device) = self.store.load_device().await? {
+                Self::load_signal_state(&self.store, &self.signal_manager, &device).await?;
+                self.primary_monitor.set_own_user(&device.jid);
+                self.group_manager.set_own_jid(device.jid);
+            }
+            match Self::load_saved_reactions(&self.database).await {
+                Ok(saved) => self.reactions.restore(saved).await,
+                Err(e) => warn!("Failed to restore saved reactions: {}", e),
+            }
+            match self.polls.load().await {
+                Ok(saved) => self.poll_results.restore(saved).await,
+                Err(e) => warn!("Failed to restore tracked polls: {}", e),
+            }
+            match Self::load_seen_messages(&self.database).await {
+                Ok(saved) => self.message_dedup.restore(saved),
+                Err(e) => warn!("Failed to restore seen message ids: {}", e),
+            }
+            match self.decrypt_failures.load().await {
+                Ok(saved) => {
+                    self.decrypt_retries.restore(&saved);
+                    let mut threads = self.message_thread_manager.lock().await;
+                    for placeholder in saved.into_iter().filter_map(|failure| failure.placeholder) {
+                        threads.add_to_thread(&placeholder.chat.to_string(), placeholder);
+                    }
+                }
+                Err(e) => warn!("Failed to restore undecryptable messages: {}", e),
+            }
+            Ok::<_, Error>(())
+        }).await?;
+        Ok(())
+    }
+    
+    /// Switch the Signal manager to the persisted state of a paired device,
+    /// unless it already works on it
+    async fn load_signal_state(store: &Arc<dyn Store>, signal_manager: &Mutex<SignalProtocolManager>, device: &DeviceData) -> Result<()> {
+        let mut signal = signal_manager.lock().await;
+        if signal.device_jid() != Some(&device.jid) {
+            *signal = SignalProtocolManager::load(Arc::clone(store), device).await?;
+            debug!("Loaded Signal state of {}", device.jid);
+        }
+        Ok(())
+    }
+    
+    /// Reactions saved by the last [`shutdown`](Self::shutdown)
+    async fn load_saved_reactions(database: &Database) -> Result<Vec<messaging::StoredReaction>> {
+        let settings = SqliteSettingsStore::new(database.pool().clone());
+        match settings.get_setting(REACTIONS_SETTING).await? {
+            Some(saved) => Ok(serde_json::from_str(&saved)?),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    /// Message ids saved by the last [`save_seen_messages`](Self::save_seen_messages)
+    async fn load_seen_messages(database: &Database) -> Result<Vec<SeenMessages>> {
+        let settings = SqliteSettingsStore::new(database.pool().clone());
+        match settings.get_setting(SEEN_MESSAGES_SETTING).await? {
+            Some(saved) => Ok(serde_json::from_str(&saved)?),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    /// Save the recently seen message ids, so messages delivered again after a restart are dropped
+    async fn save_seen_messages(&self) -> Result<()> {
+        if self.initialized.get().is_none() {
+            return Ok(());
+        }
+        let seen = serde_json::to_string(&self.message_dedup.snapshot())?;
+        SqliteSettingsStore::new(self.database.pool().clone())
+            .set_setting(SEEN_MESSAGES_SETTING, &seen)
+            .await
+    }
+    
+    /// Save the in-memory caches that should survive a restart
+    async fn save_caches(&self) -> Result<()> {
+        // Saving before the saved caches were loaded would overwrite them
+        if self.initialized.get().is_none() {
+            return Ok(());
+        }
+        let reactions = serde_json::to_string(&self.reactions.snapshot().await)?;
+        SqliteSettingsStore::new(self.database.pool().clone())
+            .set_setting(REACTIONS_SETTING, &reactions)
+            .await?;
+        self.save_seen_messages().await
+    }
+    
+    /// Shut the client down for good.
+    ///
+    /// New sends are refused right away. Within `timeout` the client waits
+    /// for messages being sent, flushes the outbox when connected, stops the
+    /// receive workers after the queued stanzas and saves the reaction cache;
+    /// whatever is left when the timeout expires is abandoned. Then it
+    /// disconnects, aborts its background tasks and closes the database.
+    ///
+    /// Group participants and permissions are always fetched from the server,
+    /// so there is nothing to save for them.
+    pub async fn shutdown(&self, timeout: std::time::Duration) -> Result<()> {
+        if self.shutting_down.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            return Ok(());
+        }
+        info!("Shutting down...");
+        
+        let drained = tokio::time::timeout(timeout, async {
+            self.send_scheduler.drain().await;
+            if self.is_connected().await {
+                let flushed = self.flush_outbox().await;
+                if flushed.remaining > 0 {
+                    warn!("{} outbox messages left for the next start", flushed.remaining);
+                }
+            }
+            self.stop_receive_workers().await;
+            if let Err(e) = self.save_caches().await {
+                warn!("Failed to save caches: {}", e);
+            }
+        })
+        .await;
+        if drained.is_err() {
+            warn!("Shutdown did not finish within {:?}, dropping pending work", timeout);
+        }
+        
+        if let Err(e) = self.disconnect().await {
+            warn!("Failed to disconnect: {}", e);
+        }
+        for task in self.background_tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.database.close().await;
+        
+        info!("Shut down");
+        Ok(())
+    }
+    
+    /// Periodically delete expired disappearing messages from the local history.
+    ///
+    /// The task stops once the client (and with it the history) is dropped.
+    fn spawn_ephemeral_reaper(
+        threads: std::sync::Weak<Mutex<MessageThreadManager>>,
+        period: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(threads) = threads.upgrade() else {
+                    break;
+                };
+                let expired = threads.lock().await.remove_expired(server_time::now());
+                if !expired.is_empty() {
+                    debug!("Removed {} expired disappearing messages", expired.len());
+                }
+            }
+        })
+    }
+    
+    /// Periodically upload new pre-keys when the server is running low.
+    ///
+    /// Checks fail quietly while disconnected. The task stops once the client is dropped.
+    fn spawn_prekey_monitor(
+        iq_sender: std::sync::Weak<SocketIqSender>,
+        signal_manager: std::sync::Weak<Mutex<SignalProtocolManager>>,
+        period: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let (Some(iq_sender), Some(signal_manager)) = (iq_sender.upgrade(), signal_manager.upgrade()) else {
+                    break;
+                };
+                if let Err(e) = prekeys::replenish_prekeys(iq_sender.as_ref(), &signal_manager).await {
+                    debug!("Pre-key check failed: {}", e);
+                }
+            }
         })
     }
     
+    /// Upload new pre-keys if the server is running low.
+    ///
+    /// Returns the number of uploaded pre-keys.
+    pub async fn replenish_prekeys(&self) -> Result<u32> {
+        prekeys::replenish_prekeys(self.iq_sender.as_ref(), &self.signal_manager).await
+    }
+    
+    /// Use a custom transport instead of the built-in WebSocket for new connections.
+    ///
+    /// The proxy configured in [`ClientConfig`] is not applied to custom transports.
+    pub fn set_transport_factory(&self, factory: TransportFactory) {
+        *self.transport_factory.write().unwrap() = Some(factory);
+    }
+    
     /// Add an event handler
     pub async fn add_event_handler(&self, handler: EventHandler) {
         let mut handlers = self.event_handlers.write().await;
@@ -113,13 +817,23 @@ impl Client {
     /// Connect to WhatsApp
     pub async fn connect(&self) -> Result<()> {
         info!("Connecting to WhatsApp...");
+        self.initialize().await?;
+        if self.config().device_props.fetch_latest_version {
+            if let Err(e) = self.refresh_web_version().await {
+                warn!("Failed to fetch the WhatsApp Web version, announcing {}: {}", self.web_version(), e);
+            }
+        }
         
-        if self.config.auto_reconnect {
+        if self.config().auto_reconnect {
             // Use connection manager for automatic reconnection
             let mut manager_guard = self.connection_manager.lock().await;
             
             if manager_guard.is_none() {
-                let mut connection_manager = ConnectionManager::new(self.config.connection_config.clone());
+                // The manager opens, logs in and replaces the socket the client sends on
+                let mut connection_manager = ConnectionManager::new(self.config().connection_config.clone())
+                    .with_iq_sender(self.iq_sender.clone())
+                    .with_connector(self.session_connector())
+                    .with_session(Arc::clone(&self.socket));
                 
                 // Add client event handler to bridge connection events to client events
                 connection_manager.add_event_handler(Box::new(ClientConnectionEventHandler {
@@ -147,13 +861,23 @@ impl Client {
                 let _: Result<()> = manager.connect().await;
                 
                 // Wait for connection with timeout
-                manager.wait_for_connection(self.config.connection_config.connection_timeout).await?;
+                manager.wait_for_connection(self.config().connection_config.connection_timeout).await?;
                 
                 self.emit_event(Event::Connected).await;
                 info!("Successfully connected to WhatsApp with connection manager");
+                if let Err(e) = self.sync_server_time().await {
+                    warn!("Failed to query server time: {}", e);
+                }
+                self.resend_queued_messages().await;
+                self.flush_outbox().await;
+                if let Err(e) = self.fetch_blocklist().await {
+                    warn!("Failed to fetch block list: {}", e);
+                }
+                self.register_configured_push().await;
+                self.send_due_scheduled_messages().await;
                 
                 // Start app state sync if enabled
-                if self.config.enable_app_state_sync {
+                if self.config().enable_app_state_sync {
                     if let Err(e) = self.start_app_state_sync().await {
                         warn!("Failed to start app state sync: {}", e);
                     }
@@ -161,25 +885,14 @@ impl Client {
             }
         } else {
             // Manual connection without reconnection management
+            let connector = self.session_connector();
             let result = self.retry_executor.execute(|attempt| {
                 let socket_arc = Arc::clone(&self.socket);
+                let connector = Arc::clone(&connector);
                 async move {
                     info!("Connection attempt #{}", attempt.attempt);
-                    
-                    // Create and connect socket
-                    let mut socket = NoiseSocket::new().await?;
-                    socket.connect().await?;
-                    
-                    // Perform Noise handshake
-                    info!("Performing Noise protocol handshake...");
-                    if let Err(e) = socket.perform_handshake().await {
-                        debug!("Handshake attempt completed with result: {}", e);
-                    }
-                    
-                    // Store the socket
-                    let mut socket_guard = socket_arc.lock().await;
-                    *socket_guard = Some(socket);
-                    
+                    let socket = connector().await?;
+                    *socket_arc.lock().await = Some(socket);
                     Ok(())
                 }
             }).await;
@@ -188,9 +901,19 @@ impl Client {
                 RetryResult::Success(_) => {
                     self.emit_event(Event::Connected).await;
                     info!("Successfully connected to WhatsApp WebSocket");
+                    if let Err(e) = self.sync_server_time().await {
+                        warn!("Failed to query server time: {}", e);
+                    }
+                    self.resend_queued_messages().await;
+                    self.flush_outbox().await;
+                    if let Err(e) = self.fetch_blocklist().await {
+                        warn!("Failed to fetch block list: {}", e);
+                    }
+                    self.register_configured_push().await;
+                    self.send_due_scheduled_messages().await;
                     
                     // Start app state sync if enabled
-                    if self.config.enable_app_state_sync {
+                    if self.config().enable_app_state_sync {
                         if let Err(e) = self.start_app_state_sync().await {
                             warn!("Failed to start app state sync: {}", e);
                         }
@@ -206,17 +929,80 @@ impl Client {
         Ok(())
     }
     
+    /// Open a connection for this client.
+    ///
+    /// Uses the custom transport or the configured proxy. A paired device
+    /// logs in with its stored keys instead of pairing again and gets its
+    /// Signal identity, sessions and pre-keys back; the store is read on
+    /// every attempt as the device may have been paired since.
+    fn session_connector(&self) -> SessionConnector {
+        let store = Arc::clone(&self.store);
+        let signal_manager = Arc::clone(&self.signal_manager);
+        let group_manager = Arc::clone(&self.group_manager);
+        let primary_monitor = Arc::clone(&self.primary_monitor);
+        let config = self.config();
+        let proxy = config.proxy.clone();
+        let props = Arc::new(config.device_props);
+        let web_version = Arc::clone(&self.web_version);
+        let announced_version = Arc::clone(&self.announced_version);
+        let transport_factory = self.transport_factory.read().unwrap().clone();
+        Arc::new(move || {
+            let store = Arc::clone(&store);
+            let signal_manager = Arc::clone(&signal_manager);
+            let group_manager = Arc::clone(&group_manager);
+            let primary_monitor = Arc::clone(&primary_monitor);
+            let proxy = proxy.clone();
+            let props = Arc::clone(&props);
+            let version = props.announced_version(*web_version.read().unwrap());
+            let announced_version = Arc::clone(&announced_version);
+            let transport = transport_factory.as_ref().map(|factory| factory());
+            Box::pin(async move {
+                let credentials = match store.load_device().await? {
+                    Some(device) => {
+                        info!("Restoring session for {} as WhatsApp Web {}", device.jid, version);
+                        Self::load_signal_state(&store, &signal_manager, &device).await?;
+                        group_manager.set_own_jid(device.jid.clone());
+                        primary_monitor.set_own_user(&device.jid);
+                        *announced_version.write().unwrap() = Some(version);
+                        Some(login::login_credentials(&device, &props, version)?)
+                    }
+                    None => None,
+                };
+                let socket = match transport {
+                    Some(transport) => NoiseSocket::with_transport(transport),
+                    None => NoiseSocket::new().await?.with_proxy(proxy),
+                };
+                manager::open_session(socket, credentials.as_ref()).await
+            })
+        })
+    }
+    
+    /// Fetch the current WhatsApp Web version and announce it from the next login on
+    pub async fn refresh_web_version(&self) -> Result<WebVersion> {
+        let version = device_props::fetch_latest_web_version(self.config().proxy.as_ref()).await?;
+        info!("Current WhatsApp Web version is {}", version);
+        *self.web_version.write().unwrap() = Some(version);
+        Ok(version)
+    }
+    
+    /// WhatsApp Web version announced on login
+    pub fn web_version(&self) -> WebVersion {
+        self.config().device_props.announced_version(*self.web_version.read().unwrap())
+    }
+    
     /// Disconnect from WhatsApp
     pub async fn disconnect(&self) -> Result<()> {
         info!("Disconnecting from WhatsApp...");
         
         // Stop app state sync first
-        if self.config.enable_app_state_sync {
+        if self.config().enable_app_state_sync {
             if let Err(e) = self.stop_app_state_sync().await {
                 warn!("Failed to stop app state sync: {}", e);
             }
         }
         
+        self.flush_receipts().await;
+        
         // If using connection manager, disconnect through that
         let manager_guard = self.connection_manager.lock().await;
         if let Some(ref manager) = *manager_guard {
@@ -236,11 +1022,60 @@ impl Client {
         Ok(())
     }
     
+    /// Unlink this device from the account and delete its credentials.
+    ///
+    /// Sends the unlink request, wipes the credentials and Signal state
+    /// (and the message history unless `keep_messages` is set), closes the
+    /// connection and emits [`Event::LoggedOut`] with the reason
+    /// [`LOGOUT_REASON_INTENTIONAL`](logout::LOGOUT_REASON_INTENTIONAL).
+    pub async fn logout(&self, options: LogoutOptions) -> Result<()> {
+        let device = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?;
+        self.iq_sender.send_iq(logout::build_logout_query(&device.jid)).await?;
+        info!("Unlinked device {}", device.jid);
+        
+        self.disconnect().await?;
+        self.store.delete_device().await?;
+        self.database.wipe_account(options.keep_messages).await?;
+        *self.signal_manager.lock().await = SignalProtocolManager::unregistered();
+        *self.blocklist.write().await = Blocklist::default();
+        
+        self.emit_event(Event::LoggedOut { reason: logout::LOGOUT_REASON_INTENTIONAL.to_string() }).await;
+        Ok(())
+    }
+    
     /// Check if the client is logged in
     pub fn is_logged_in(&self) -> bool {
         self.is_logged_in.load(std::sync::atomic::Ordering::SeqCst)
     }
     
+    /// Check if the socket is currently connected
+    pub async fn is_connected(&self) -> bool {
+        self.socket.lock().await.as_ref().is_some_and(|socket| socket.is_connected())
+    }
+    
+    /// Current time on the server's clock, see [`crate::server_time`]
+    pub fn server_now(&self) -> std::time::SystemTime {
+        server_time::now()
+    }
+    
+    /// Measure the skew between the local and the server clock.
+    ///
+    /// Runs after every connect; returns the milliseconds the server is ahead.
+    pub async fn sync_server_time(&self) -> Result<i64> {
+        let sent = std::time::SystemTime::now();
+        let response = self.iq_sender.send_iq(server_time::build_time_query()).await?;
+        let received = std::time::SystemTime::now();
+        let server = server_time::parse_server_time(&response)
+            .ok_or_else(|| Error::Protocol("Ping result has no server time".to_string()))?;
+        
+        let skew = server_time::measure_skew(server, sent, received);
+        if skew != server_time::skew_millis() {
+            info!("Local clock is off by {} ms from the server", skew);
+        }
+        server_time::set_skew_millis(skew);
+        Ok(skew)
+    }
+    
     /// Generate QR code for authentication
     pub async fn generate_qr(&self) -> Result<String> {
         let mut auth = self.auth_manager.lock().await;
@@ -259,67 +1094,7 @@ impl Client {
     
     /// Send a message
     pub async fn send_message(&self, to: &JID, message: SendableMessage) -> Result<String> {
-        if !self.is_logged_in() {
-            return Err(Error::NotLoggedIn);
-        }
-        
-        // Apply rate limiting for message sending
-        match self.rate_limiter.wait_for_rate_limit("messages").await {
-            RateLimitResult::Allowed => {
-                debug!("Message sending allowed by rate limiter");
-            }
-            RateLimitResult::Limited { retry_after } => {
-                warn!("Message sending rate limited, waited {:?}", retry_after);
-            }
-        }
-        
-        debug!("Sending message to {}: {:?}", to, message);
-        
-        let message_id = uuid::Uuid::new_v4().to_string();
-        
-        // Use retry executor for sending messages
-        let result = self.retry_executor.execute(|attempt| {
-            let to = to.clone();
-            let message = message.clone();
-            let message_id = message_id.clone();
-            let message_queue = Arc::clone(&self.message_queue);
-            
-            async move {
-                info!("Sending message attempt #{}", attempt.attempt);
-                
-                // Build the message node
-                let from_jid = JID::new("placeholder".to_string(), "s.whatsapp.net".to_string());
-                let builder = MessageBuilder::new(to);
-                
-                let node = match message {
-                    SendableMessage::Text(text_msg) => {
-                        builder.text(text_msg.text).build(message_id.clone(), from_jid)?
-                    },
-                    _ => {
-                        return Err(Error::Protocol("Unsupported message type".to_string()));
-                    }
-                };
-                
-                // Add to message queue
-                let mut queue = message_queue.lock().await;
-                queue.enqueue(message_id.clone(), node);
-                
-                // TODO: Actually send the message through the socket
-                
-                Ok(())
-            }
-        }).await;
-        
-        match result {
-            RetryResult::Success(_) => {
-                debug!("Message sent successfully: {}", message_id);
-                Ok(message_id)
-            }
-            RetryResult::Failed { error, attempts } => {
-                warn!("Failed to send message after {} attempts", attempts.len());
-                Err(error)
-            }
-        }
+        self.send_message_enhanced(to, message).await
     }
     
     /// Start listening for events
@@ -332,374 +1107,2688 @@ impl Client {
         Ok(())
     }
     
-    /// Emit an event to all handlers
-    async fn emit_event(&self, event: Event) {
-        let handlers = self.event_handlers.read().await;
-        for handler in handlers.iter() {
-            if !handler(event.clone()) {
-                break;
-            }
-        }
+    /// Register a handler for stanzas not covered by the built-in receive loop
+    pub async fn register_stanza_handler(&self, matcher: StanzaMatcher, handler: Arc<dyn StanzaHandler>) -> StanzaHandlerId {
+        self.stanza_handlers.register(matcher, handler).await
     }
     
-    /// Get connection statistics
-    pub async fn get_connection_stats(&self) -> Option<crate::connection::ConnectionStats> {
-        let manager_guard = self.connection_manager.lock().await;
-        manager_guard.as_ref().map(|manager| manager.get_stats())
+    /// Unregister a custom stanza handler
+    pub async fn unregister_stanza_handler(&self, id: StanzaHandlerId) -> bool {
+        self.stanza_handlers.unregister(id).await
     }
     
-    /// Get rate limit status
-    pub async fn get_rate_limit_status(&self) -> std::collections::HashMap<String, crate::connection::rate_limit::RateLimitStatus> {
-        self.rate_limiter.get_all_status().await
+    /// The most recent stanzas sent and received, oldest first, with keys
+    /// and message bodies redacted.
+    ///
+    /// Empty unless [`ClientConfig::stanza_log_capacity`] is set.
+    pub fn dump_recent_stanzas(&self) -> Vec<LoggedStanza> {
+        self.wire_log.recent()
     }
     
-    /// Force reconnection
-    pub async fn reconnect(&self) -> Result<()> {
-        let manager_guard = self.connection_manager.lock().await;
-        if let Some(ref manager) = *manager_guard {
-            let result: Result<()> = manager.reconnect().await;
-            result
-        } else {
-            Err(Error::Connection("Connection manager not initialized".to_string()))
-        }
+    /// Encode and send a node through the socket
+    pub async fn send_node(&self, node: &Node) -> Result<()> {
+        self.send_guard.check_node(node)?;
+        self.iq_sender.send_node(node).await
     }
     
-    // ===== ENHANCED MESSAGING METHODS =====
+    /// Stop all messages, receipts, presence and calls without disconnecting.
+    ///
+    /// IQs and acks still go out so the connection and sync state survive.
+    pub fn pause_outbound(&self) {
+        warn!("Outbound traffic paused");
+        self.send_guard.pause();
+    }
     
-    /// Send a text message
-    pub async fn send_text(&self, to: &JID, text: String) -> Result<String> {
-        let message = SendableMessage::Text(TextMessage { text });
-        self.send_message_enhanced(to, message).await
+    /// Lift a pause set with [`pause_outbound`](Self::pause_outbound)
+    pub fn resume_outbound(&self) {
+        info!("Outbound traffic resumed");
+        self.send_guard.resume();
     }
     
-    /// Send an extended text message with formatting
-    pub async fn send_extended_text(&self, to: &JID, text: String, context: Option<ContextInfo>) -> Result<String> {
-        let extended_text = ExtendedTextMessage {
-            text,
-            matched_text: None,
-            canonical_url: None,
-            description: None,
-            title: None,
-            text_arg_b: None,
-            thumbnail: None,
-            jpeg_thumbnail: None,
-            context_info: context,
-            font: None,
-            preview_type: None,
-        };
-        let message = SendableMessage::ExtendedText(extended_text);
-        self.send_message_enhanced(to, message).await
+    /// Whether outbound traffic is paused
+    pub fn is_outbound_paused(&self) -> bool {
+        self.send_guard.is_paused()
     }
     
-    /// Send a media message (image, video, audio, document)
-    pub async fn send_media(&self, to: &JID, media_path: &str, caption: Option<String>) -> Result<String> {
-        // Use media manager to process and upload the media
-        let media_info = self.media_manager.lock().await.upload_media(media_path, crate::media::MediaType::Auto).await?;
+    /// Whether the client is a passive listener, see [`ClientConfig::passive`]
+    pub fn is_passive(&self) -> bool {
+        self.send_guard.is_passive()
+    }
+    
+    /// Counters of the anti-spam guard, `None` if it is not enabled
+    pub fn anti_spam_stats(&self) -> Option<AntiSpamStats> {
+        self.anti_spam.as_ref().map(|anti_spam| {
+            anti_spam.cleanup();
+            anti_spam.stats()
+        })
+    }
+    
+    /// Never send anything to the given JID until it is unblocked
+    pub fn block_sending_to(&self, jid: &JID) {
+        self.send_guard.block(jid);
+    }
+    
+    /// Remove a JID from the send blocklist
+    pub fn unblock_sending_to(&self, jid: &JID) -> bool {
+        self.send_guard.unblock(jid)
+    }
+    
+    /// Send an info query and wait for the response
+    pub async fn send_iq(&self, query: InfoQuery) -> Result<Node> {
+        self.iq_sender.send_iq(query).await
+    }
+    
+    /// Get a handle for sending info queries, e.g. to pass to group managers
+    pub fn iq_sender(&self) -> Arc<dyn IqSender> {
+        self.iq_sender.clone()
+    }
+    
+    /// Handle to the group service, which can be shared between tasks
+    pub fn groups(&self) -> Arc<GroupService> {
+        self.groups.clone()
+    }
+    
+    /// Handle to the media manager used for uploads and downloads
+    pub fn media(&self) -> Arc<tokio::sync::Mutex<MediaManager>> {
+        self.media_manager.clone()
+    }
+    
+    /// Handle to the Signal protocol manager holding our sessions and keys
+    pub fn signal(&self) -> Arc<Mutex<SignalProtocolManager>> {
+        self.signal_manager.clone()
+    }
+    
+    /// Handle to the stored contacts
+    pub fn contacts(&self) -> Arc<SqliteContactStore> {
+        self.contacts.clone()
+    }
+    
+    /// Handle to the latest presence of the contacts we subscribed to
+    pub fn presence(&self) -> Arc<PresenceTracker> {
+        self.presence.clone()
+    }
+    
+    /// Handle to the app state manager, holding `None` if app state sync is disabled
+    pub fn app_state(&self) -> Arc<Mutex<Option<AppStateManager>>> {
+        self.app_state_manager.clone()
+    }
+    
+    /// Ask the server for the presence of a contact, see [`presence`](Self::presence)
+    pub async fn subscribe_presence(&self, jid: &JID) -> Result<()> {
+        self.send_node(&Node::presence().to(&jid.to_non_ad()).subscribe().build()).await
+    }
+    
+    /// Create a group
+    pub async fn create_group(&self, request: CreateGroupRequest) -> Result<GroupInfo> {
+        self.group_manager.create_group(request).await
+    }
+    
+    /// Fetch the metadata and participants of a group
+    pub async fn get_group_info(&self, group: &JID) -> Result<GroupInfo> {
+        self.group_manager.get_group_info(group).await
+    }
+    
+    /// Fetch all groups we participate in
+    pub async fn get_joined_groups(&self) -> Result<Vec<GroupInfo>> {
+        self.group_manager.get_joined_groups().await
+    }
+    
+    /// Add participants to a group
+    pub async fn add_group_participants(&self, group: &JID, participants: Vec<JID>) -> Result<ParticipantOperationResult> {
+        self.group_manager.add_participants(group, participants).await
+    }
+    
+    /// Remove participants from a group
+    pub async fn remove_group_participants(&self, group: &JID, participants: Vec<JID>) -> Result<ParticipantOperationResult> {
+        self.group_manager.remove_participants(group, participants).await
+    }
+    
+    /// Leave a group
+    pub async fn leave_group(&self, group: &JID) -> Result<()> {
+        let own_jid = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        self.group_manager.leave_group(group, &own_jid.to_non_ad()).await
+    }
+    
+    /// Fetch the pending requests to join a group
+    pub async fn get_group_join_requests(&self, group: &JID) -> Result<Vec<MembershipRequest>> {
+        self.group_manager.get_join_requests(group).await
+    }
+    
+    /// Approve or reject pending requests to join a group
+    pub async fn handle_group_join_requests(
+        &self,
+        group: &JID,
+        participants: Vec<JID>,
+        approve: bool,
+    ) -> Result<ParticipantOperationResult> {
+        self.group_manager.handle_join_requests(group, participants, approve).await
+    }
+    
+    /// Turn admin approval of new group members on or off
+    pub async fn set_group_membership_approval_mode(&self, group: &JID, enabled: bool) -> Result<()> {
+        self.group_manager.set_membership_approval_mode(group, enabled).await
+    }
+    
+    /// Create a community together with its default announcement group
+    pub async fn create_community(&self, request: CreateCommunityRequest) -> Result<CommunityInfo> {
+        let own_jid = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        self.community_manager.lock().await.create_community(request, own_jid).await
+    }
+    
+    /// Link an existing group into a community
+    pub async fn link_group(&self, community: &JID, group: &JID) -> Result<()> {
+        self.community_manager.lock().await.link_group(community, group).await
+    }
+    
+    /// Unlink a group from a community
+    pub async fn unlink_group(&self, community: &JID, group: &JID) -> Result<()> {
+        self.community_manager.lock().await.remove_group_from_community(community, group).await
+    }
+    
+    /// Fetch the groups linked to a community
+    pub async fn get_linked_groups(&self, community: &JID) -> Result<Vec<LinkedGroup>> {
+        self.community_manager.lock().await.get_linked_groups(community).await
+    }
+    
+    /// Post an announcement to a community.
+    ///
+    /// Announcements are normal messages sent to the community's announcement
+    /// group, which the server created with the community. Only community
+    /// admins may send to it.
+    pub async fn post_announcement(&self, community: &JID, message: SendableMessage) -> Result<String> {
+        let announcement_group = self.groups.prepare_announcement(community).await?;
+        self.send_message(&announcement_group, message).await
+    }
+    
+    /// Fetch the pending requests to join a community
+    pub async fn get_community_join_requests(&self, community: &JID) -> Result<Vec<MembershipRequest>> {
+        self.community_manager.lock().await.get_join_requests(community).await
+    }
+    
+    /// Approve or reject pending requests to join a community
+    pub async fn update_community_join_requests(
+        &self,
+        community: &JID,
+        participants: Vec<JID>,
+        approve: bool,
+    ) -> Result<ParticipantOperationResult> {
+        self.community_manager.lock().await.update_join_requests(community, participants, approve).await
+    }
+    
+    /// Check which of the given phone numbers are registered on WhatsApp
+    pub async fn is_on_whatsapp(&self, phones: &[String]) -> Result<Vec<IsOnWhatsAppResult>> {
+        if !self.is_logged_in() {
+            return Err(Error::NotLoggedIn);
+        }
         
-        let media_message = MediaMessage {
-            url: Some(media_info.url),
-            direct_path: media_info.direct_path,
-            media_key: Some(media_info.media_key),
-            file_sha256: Some(media_info.file_sha256),
-            file_length: Some(media_info.file_length),
-            mime_type: Some(media_info.mime_type),
-            caption,
-            width: media_info.width,
-            height: media_info.height,
-            page_count: None,
-            seconds: media_info.duration,
-            ptt: Some(false),
-            gif_playback: None,
-            jpeg_thumbnail: media_info.thumbnail,
-            context_info: None,
-        };
+        let users = phones.iter().map(|phone| usync::contact_user_node(phone)).collect();
+        let query = vec![
+            Node::new("business".to_string()).with_children(vec![Node::new("verified_name".to_string())]),
+            Node::new("contact".to_string()),
+            Node::new("lid".to_string()),
+        ];
+        let sid = self.response_waiters.generate_request_id();
         
-        let message = match media_info.media_type {
-            crate::media::MediaType::Image => SendableMessage::Image(media_message),
-            crate::media::MediaType::Video => SendableMessage::Video(media_message),
-            crate::media::MediaType::Audio => SendableMessage::Audio(media_message),
-            crate::media::MediaType::Document => SendableMessage::Document(media_message),
-            _ => SendableMessage::Document(media_message),
-        };
+        self.throttle(rate_limit::USYNC, None).await;
+        let response = self.send_iq(
+            InfoQuery::get(usync::USYNC_NAMESPACE)
+                .content(vec![usync::build_usync_node(&sid, "query", "interactive", query, users)]),
+        ).await?;
         
-        self.send_message_enhanced(to, message).await
+        Ok(usync::parse_usync_list(&response)?
+            .iter()
+            .map(usync::parse_is_on_whatsapp_user)
+            .collect())
     }
     
-    /// Send a voice note
-    pub async fn send_voice_note(&self, to: &JID, audio_path: &str) -> Result<String> {
-        let media_info = self.media_manager.lock().await.upload_media(audio_path, crate::media::MediaType::Audio).await?;
+    /// Register the given phone numbers as the device's address book.
+    ///
+    /// Shorthand for [`Client::sync_address_book`] with nameless contacts.
+    pub async fn sync_contacts(&self, phones: &[String]) -> Result<ContactSyncResult> {
+        let contacts: Vec<PhoneContact> = phones.iter().map(|phone| PhoneContact::new(phone.clone(), None)).collect();
+        self.sync_address_book(&contacts).await
+    }
+    
+    /// Upload the address book like the phone app does.
+    ///
+    /// The first upload sends the whole book, later ones only the difference
+    /// to the previously uploaded numbers. Uploaded numbers are stored as
+    /// contacts under their address book name, resolved to their JID if
+    /// registered; [`ContactSyncResult::registered`] lists the numbers on
+    /// WhatsApp.
+    pub async fn sync_address_book(&self, book: &[PhoneContact]) -> Result<ContactSyncResult> {
+        if !self.is_logged_in() {
+            return Err(Error::NotLoggedIn);
+        }
         
-        let media_message = MediaMessage {
-            url: Some(media_info.url),
-            direct_path: media_info.direct_path,
-            media_key: Some(media_info.media_key),
-            file_sha256: Some(media_info.file_sha256),
-            file_length: Some(media_info.file_length),
-            mime_type: Some(media_info.mime_type),
-            caption: None,
-            width: None,
-            height: None,
-            page_count: None,
-            seconds: media_info.duration,
-            ptt: Some(true), // Push to talk
-            gif_playback: None,
-            jpeg_thumbnail: None,
-            context_info: None,
-        };
+        let contact_store = &self.contacts;
+        let stored: std::collections::HashMap<String, (JID, Option<String>)> = contact_store
+            .list_contacts()
+            .await?
+            .into_iter()
+            .filter_map(|contact| Some((contacts::normalize_phone(contact.phone_number.as_deref()?)?, (contact.jid, contact.name))))
+            .collect();
+        let uploaded: Vec<&String> = stored.keys().collect();
+        let phones: Vec<&str> = book.iter().map(|contact| contact.phone.as_str()).collect();
+        let diff = AddressBookDiff::between(&uploaded, &phones);
+        let mode = ContactSyncMode::for_uploaded(&uploaded);
+        let stored_names = stored.iter().map(|(phone, (_, name))| (phone.clone(), name.clone())).collect();
+        let renamed = contacts::renamed_contacts(&stored_names, book);
         
-        let message = SendableMessage::Voice(media_message);
-        self.send_message_enhanced(to, message).await
+        let mut result = ContactSyncResult { mode, ..Default::default() };
+        for (phone, name) in renamed {
+            let (jid, _) = &stored[&phone];
+            contact_store.store_contact(jid, Some(name), Some(&phone)).await?;
+            result.renamed.push(phone);
+        }
+        if diff.is_empty() {
+            return Ok(result);
+        }
+        
+        for users in diff.batches(self.config().contact_batch_size) {
+            let query = vec![
+                Node::new("business".to_string()).with_children(vec![Node::new("verified_name".to_string())]),
+                Node::new("contact".to_string()),
+                Node::new("lid".to_string()),
+            ];
+            let sid = self.response_waiters.generate_request_id();
+            self.throttle(rate_limit::USYNC, None).await;
+            let response = self.send_iq(
+                InfoQuery::get(usync::USYNC_NAMESPACE)
+                    .content(vec![usync::build_usync_node(&sid, mode.as_str(), "interactive", query, users)]),
+            ).await?;
+            
+            for user in usync::parse_usync_list(&response)? {
+                let parsed = usync::parse_is_on_whatsapp_user(&user);
+                let is_added = contacts::normalize_phone(&parsed.query)
+                    .map(|phone| diff.added.contains(&phone))
+                    .unwrap_or(false);
+                if is_added {
+                    result.uploaded.push(parsed);
+                }
+            }
+        }
+        
+        for phone in &diff.removed {
+            contact_store.delete_contact(&JID::new(phone.clone(), "s.whatsapp.net".to_string())).await?;
+            result.removed.push(phone.clone());
+        }
+        // Numbers missing from the response are still part of the uploaded book
+        for phone in &diff.added {
+            let jid = result
+                .uploaded
+                .iter()
+                .find(|r| contacts::normalize_phone(&r.query).as_deref() == Some(phone.as_str()))
+                .and_then(|r| r.jid.clone())
+                .unwrap_or_else(|| JID::new(phone.clone(), "s.whatsapp.net".to_string()));
+            let name = book
+                .iter()
+                .rev()
+                .find(|contact| contacts::normalize_phone(&contact.phone).as_deref() == Some(phone.as_str()))
+                .and_then(|contact| contact.name.as_deref());
+            contact_store.store_contact(&jid, name, Some(phone)).await?;
+        }
+        
+        info!(
+            "Synced address book ({}): {} added, {} removed, {} renamed",
+            mode.as_str(),
+            diff.added.len(),
+            diff.removed.len(),
+            result.renamed.len()
+        );
+        Ok(result)
     }
     
-    /// Send a location message
-    pub async fn send_location(&self, to: &JID, latitude: f64, longitude: f64, name: Option<String>, address: Option<String>) -> Result<String> {
-        let location = LocationMessage {
-            latitude,
-            longitude,
-            name,
-            address,
-        };
-        let message = SendableMessage::Location(location);
-        self.send_message_enhanced(to, message).await
+    /// Get the device JIDs of the given users, querying the server for uncached lists
+    pub async fn get_user_devices(&self, jids: &[JID]) -> Result<Vec<JID>> {
+        let mut devices = Vec::new();
+        let mut missing = Vec::new();
+        
+        for jid in jids {
+            match self.device_cache.get(jid).await {
+                Some(cached) => devices.extend(cached),
+                None => missing.push(jid.clone()),
+            }
+        }
+        
+        if missing.is_empty() {
+            return Ok(devices);
+        }
+        
+        let users = missing.iter().map(usync::device_user_node).collect();
+        let query = vec![Node::new("devices".to_string()).attr("version".to_string(), "2".to_string())];
+        let sid = self.response_waiters.generate_request_id();
+        
+        self.throttle(rate_limit::USYNC, None).await;
+        let response = self.send_iq(
+            InfoQuery::get(usync::USYNC_NAMESPACE)
+                .content(vec![usync::build_usync_node(&sid, "query", "message", query, users)]),
+        ).await?;
+        
+        for user in usync::parse_usync_list(&response)? {
+            if let Some(list) = usync::parse_device_list(&user) {
+                self.device_cache.store(&list).await;
+                devices.extend(list.devices);
+            }
+        }
+        
+        Ok(devices)
     }
     
-    /// Send a contact message
-    pub async fn send_contact(&self, to: &JID, display_name: String, vcard: String) -> Result<String> {
-        let contact = ContactMessage {
-            display_name,
-            vcard,
-        };
-        let message = SendableMessage::Contact(contact);
-        self.send_message_enhanced(to, message).await
+    /// List the devices linked to our account, fetched fresh from the server
+    pub async fn get_linked_devices(&self) -> Result<Vec<LinkedDevice>> {
+        let own = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        self.device_cache.invalidate(&own).await;
+        let list = self.get_user_devices(std::slice::from_ref(&own)).await?;
+        Ok(devices::linked_devices(&list, &own))
     }
     
-    /// React to a message
-    pub async fn react_to_message(&self, to: &JID, message_key: MessageKey, emoji: String) -> Result<String> {
-        let reaction = ReactionMessage {
-            key: message_key,
-            text: emoji,
-            sender_timestamp: Some(std::time::SystemTime::now()),
-        };
-        let message = SendableMessage::Reaction(reaction);
-        self.send_message_enhanced(to, message).await
+    /// Unlink another companion device from our account
+    pub async fn remove_linked_device(&self, device: &JID) -> Result<()> {
+        let own = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        devices::check_removable_companion(device, &own)?;
+        self.iq_sender.send_iq(devices::build_remove_companion_query(device)).await?;
+        self.device_cache.invalidate(&own).await;
+        info!("Unlinked companion device {}", device);
+        Ok(())
     }
     
-    /// Send a poll
-    pub async fn send_poll(&self, to: &JID, question: String, options: Vec<String>, selectable_count: u32) -> Result<String> {
-        let poll_options = options.into_iter()
-            .map(|name| crate::types::PollOption { name })
-            .collect();
-            
-        let poll = PollMessage {
-            name: question,
-            options: poll_options,
-            selectable_options_count: selectable_count,
-            context_info: None,
+    /// Get the status of the primary device (phone) this companion is linked to
+    pub fn primary_device_status(&self) -> PrimaryDeviceStatus {
+        self.primary_monitor.status(std::time::SystemTime::now())
+    }
+    
+    /// Compute the safety number of our chat with `jid` from both identity keys.
+    ///
+    /// Fails if we never received the contact's identity key.
+    pub async fn get_safety_number(&self, jid: &JID) -> Result<SafetyNumber> {
+        let own_jid = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        let peer = jid.to_non_ad();
+        self.signal_manager.lock().await.safety_number(&own_jid.user, &peer.user, &peer.signal_address())
+    }
+    
+    /// Trust the current identity key of `jid` again after it was blocked
+    /// by [`IdentityChangePolicy::Block`]
+    pub async fn trust_identity(&self, jid: &JID) -> Result<()> {
+        let mut signal = self.signal_manager.lock().await;
+        signal.set_trust_level(&jid.signal_address(), TrustLevel::Trusted)?;
+        signal.persist().await
+    }
+    
+    /// Compare the identity keys in the `pkmsg` payloads of an incoming
+    /// message with the stored ones and emit [`Event::IdentityChanged`]
+    async fn check_sender_identity(&self, node: &Node) {
+        let Some(sender) = node.get_attr("participant").or_else(|| node.get_attr("from"))
+            .and_then(|jid| jid.parse::<JID>().ok())
+        else {
+            return;
         };
-        let message = SendableMessage::Poll(poll);
-        self.send_message_enhanced(to, message).await
+        
+        let prekey_messages = node.get_children().into_iter().flatten()
+            .filter(|child| child.tag == "enc" && child.get_attr("type").map(String::as_str) == Some("pkmsg"));
+        for enc in prekey_messages {
+            let Some(identity_key) = enc.get_binary().and_then(|data| signal::prekey_message_identity(data).ok()) else {
+                continue;
+            };
+            let check = {
+                let mut signal = self.signal_manager.lock().await;
+                let check = signal.check_identity(&sender.signal_address(), &identity_key, self.config().identity_change_policy);
+                match signal.persist().await {
+                    Ok(()) => check,
+                    Err(e) => check.and(Err(e)),
+                }
+            };
+            match check {
+                Ok(IdentityCheck::Changed { blocked }) => {
+                    info!("Identity key of {} changed{}", sender, if blocked { ", blocked" } else { "" });
+                    self.emit_event(Event::IdentityChanged { jid: sender.to_non_ad(), blocked }).await;
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to check identity key of {}: {}", sender, e),
+            }
+        }
     }
     
-    /// Reply to a message
-    pub async fn reply_to_message(&self, to: &JID, original_message: MessageKey, reply_text: String) -> Result<String> {
-        let quoted = crate::types::QuotedMessage {
-            id: original_message.id,
-            remote_jid: original_message.remote_jid,
-            participant: original_message.participant,
-            message_type: crate::types::MessageType::Text,
-            text: Some(reply_text.clone()),
-            media_type: None,
+    /// Decrypt a received message stanza and hand it to
+    /// [`process_incoming_message`](Self::process_incoming_message).
+    ///
+    /// Stanzas that cannot be decrypted go to [`handle_decrypt_failure`](Self::handle_decrypt_failure).
+    async fn handle_message_stanza(&self, node: &Node) {
+        let opened = if node.find_child("enc").is_some() {
+            let opened = {
+                let mut signal = self.signal_manager.lock().await;
+                let opened = devices::open_message(&mut signal, node);
+                if let Err(e) = signal.persist().await {
+                    warn!("Failed to store the Signal state: {}", e);
+                }
+                opened
+            };
+            match opened {
+                Ok(Some(opened)) => {
+                    self.clear_decrypt_failure(node).await;
+                    opened
+                }
+                // Only carried a sender key
+                Ok(None) => return,
+                Err(e) => {
+                    metrics::decrypt_failure();
+                    if let Err(e) = self.handle_decrypt_failure(node, &e).await {
+                        warn!("Failed to handle undecryptable message {:?}: {}", node.get_attr("id"), e);
+                    }
+                    return;
+                }
+            }
+        } else {
+            node.clone()
         };
         
-        let context = ContextInfo {
-            quoted_message: Some(Box::new(quoted)),
-            mentioned_jids: Vec::new(),
-            forwarded: None,
-            forwarding_score: None,
-            is_forwarded: None,
-            ephemeral_setting: None,
-            ephemeral_shared_secret: None,
-            external_ad_reply: None,
+        match messaging::MessageProcessor::process_message(&opened) {
+            Ok(info) => self.process_incoming_message(info).await,
+            Err(e) => warn!("Failed to parse message {:?}: {}", node.get_attr("id"), e),
+        }
+    }
+    
+    async fn emit_primary_transition(&self, transition: PrimaryDeviceTransition) {
+        let event = match transition {
+            PrimaryDeviceTransition::WentOffline { since } => Event::PrimaryDeviceOffline { since },
+            PrimaryDeviceTransition::CameOnline => Event::PrimaryDeviceOnline,
         };
+        self.emit_event(event).await;
+    }
+    
+    /// Process incoming stanzas on a pool of workers instead of the receiving task.
+    ///
+    /// Stanzas passed to [`Client::receive_node`] are then decrypted and
+    /// dispatched in parallel across chats, keeping the order within a chat.
+    /// The workers stop when the client is dropped or [`Client::stop_receive_workers`] is called.
+    pub async fn start_receive_workers(self: &Arc<Self>) {
+        let mut pool = self.receive_pool.write().await;
+        if pool.is_some() {
+            return;
+        }
         
-        self.send_extended_text(to, reply_text, Some(context)).await
+        let client = Arc::downgrade(self);
+        let handler: NodeHandler = Arc::new(move |node| {
+            let client = client.clone();
+            Box::pin(async move {
+                let Some(client) = client.upgrade() else {
+                    return;
+                };
+                if let Err(e) = client.process_node(node).await {
+                    warn!("Failed to process stanza: {}", e);
+                }
+            })
+        });
+        *pool = Some(ReceiveWorkerPool::new(self.config().receive_workers, self.config().receive_queue_size, handler));
     }
     
-    /// Edit a message
-    pub async fn edit_message(&self, to: &JID, message_key: MessageKey, new_text: String) -> Result<String> {
-        let edit_message = MessageEditor::create_edit_message(message_key, new_text);
-        self.send_message_enhanced(to, edit_message).await
+    /// Stop the receive workers after they processed the queued stanzas
+    pub async fn stop_receive_workers(&self) {
+        let pool = self.receive_pool.write().await.take();
+        if let Some(pool) = pool {
+            pool.shutdown().await;
+        }
     }
     
-    /// Delete a message
-    pub async fn delete_message(&self, to: &JID, message_key: MessageKey) -> Result<String> {
-        let delete_message = MessageEditor::create_delete_message(message_key);
-        self.send_message_enhanced(to, delete_message).await
+    /// Hand a stanza read from the socket to the client.
+    ///
+    /// With receive workers running this only waits while the chat's worker
+    /// is backed up, so the reader stops reading until the consumers caught
+    /// up. IQ responses are always handled right away, as workers may be
+    /// waiting for them.
+    pub async fn receive_node(&self, node: Node) -> Result<()> {
+        self.wire_log.record(Direction::Received, &node);
+        if self.response_waiters.receive_response(&node) {
+            return Ok(());
+        }
+        
+        let pool = self.receive_pool.read().await;
+        match pool.as_ref() {
+            Some(pool) => pool.submit(node).await,
+            None => self.process_node(node).await,
+        }
     }
     
-    /// Enhanced message sending with full feature support
-    async fn send_message_enhanced(&self, to: &JID, message: SendableMessage) -> Result<String> {
-        if !self.is_logged_in() {
-            return Err(Error::NotLoggedIn);
+    /// Process a decoded stanza received from the server
+    pub async fn process_node(&self, node: Node) -> Result<()> {
+        if self.response_waiters.receive_response(&node) {
+            return Ok(());
         }
         
-        // Apply rate limiting for message sending
-        match self.rate_limiter.wait_for_rate_limit("messages").await {
-            RateLimitResult::Allowed => {
-                debug!("Message sending allowed by rate limiter");
+        if let Some(error) = stream_error::parse_stream_error(&node) {
+            self.handle_stream_error(error).await;
+            return Ok(());
+        }
+        
+        if !self.replay_filter.check(&node, std::time::Instant::now()) {
+            // Confirm it again so the server stops re-delivering it, but skip the handlers
+            debug!("Dropping replayed <{}> {:?}", node.tag, node.get_attr("id"));
+            if node.tag == "message" {
+                if let Ok(info) = messaging::MessageProcessor::process_message(&node) {
+                    self.send_delivery_receipt(&info).await;
+                }
             }
-            RateLimitResult::Limited { retry_after } => {
-                warn!("Message sending rate limited, waited {:?}", retry_after);
+            self.send_ack(&node).await;
+            return Ok(());
+        }
+        
+        if let Some(transition) = self.primary_monitor.observe_node(&node, std::time::SystemTime::now()) {
+            self.emit_primary_transition(transition).await;
+        }
+        
+        if node.tag == "message" {
+            self.check_sender_identity(&node).await;
+            self.handle_message_stanza(&node).await;
+        }
+        
+        for receipt in messaging::parse_receipt(&node) {
+            self.process_message_receipt(receipt).await;
+        }
+        
+        let unhandled_notification = match NotificationKind::of(&node) {
+            Some(kind) => (!self.route_notification(&kind, &node).await).then_some(kind),
+            None => None,
+        };
+        
+        if let Some(success) = login::parse_login_success(&node) {
+            info!("Logged in (lid {:?})", success.lid);
+            if let Some(server) = server_time::parse_server_time(&node) {
+                let now = std::time::SystemTime::now();
+                server_time::set_skew_millis(server_time::measure_skew(server, now, now));
             }
+            self.is_logged_in.store(true, std::sync::atomic::Ordering::SeqCst);
+            self.emit_event(Event::LoggedIn).await;
         }
         
-        debug!("Sending enhanced message to {}: {:?}", to, message);
+        if let Some(refs) = qr::parse_pair_device(&node) {
+            self.send_node(&qr::pairing_ack(&node)).await?;
+            self.start_qr_rotation(refs).await?;
+        }
         
-        let message_id = uuid::Uuid::new_v4().to_string();
+        if let Some(state) = chat_state::parse_chat_state(&node) {
+            self.typing.update(&state, std::time::Instant::now());
+            self.emit_event(Event::ChatState(state)).await;
+        }
         
-        // Update message status to pending
-        self.message_status_tracker.update_status(&message_id, MessageStatus::Pending).await;
+        if let Some(presence) = presence::parse_presence(&node) {
+            self.presence.update(&presence);
+            self.emit_event(Event::Presence(presence)).await;
+        }
+        
+        if let Some(sync) = dedup::parse_offline_sync(&node) {
+            self.process_offline_sync(sync).await;
+        }
+        
+        if qr::is_pair_success(&node) {
+            if let Err(e) = self.auth_manager.lock().await.finish_qr_rotation().await {
+                warn!("Failed to stop QR rotation: {}", e);
+            }
+        }
+        
+        if !self.stanza_handlers.dispatch(&node).await {
+            if let Some(kind) = unhandled_notification {
+                self.emit_event(Event::UnhandledNotification(UnhandledNotification::new(&kind, &node))).await;
+            }
+            debug!("Unhandled stanza <{}> {:?}", node.tag, node.attrs);
+        }
+        
+        self.send_ack(&node).await;
+        Ok(())
+    }
+    
+    /// Acknowledge a received message, receipt, notification or call
+    async fn send_ack(&self, node: &Node) {
+        let Some(ack) = replay::ack_for(node) else {
+            return;
+        };
+        if let Err(e) = self.send_node(&ack).await {
+            warn!("Failed to ack <{}> {:?}: {}", node.tag, node.get_attr("id"), e);
+        }
+    }
+    
+    /// Track the delivery of the offline queue; once it is done the seen
+    /// message ids are saved, as a restart would otherwise get the same
+    /// messages again
+    async fn process_offline_sync(&self, sync: OfflineSync) {
+        match sync {
+            OfflineSync::Preview { messages, .. } => debug!("Receiving {} messages queued while offline", messages),
+            OfflineSync::Completed { count } => {
+                info!("Received {} stanzas queued while offline", count);
+                self.offline_stanzas.store(count, std::sync::atomic::Ordering::Relaxed);
+                if let Err(e) = self.save_seen_messages().await {
+                    warn!("Failed to save seen message ids: {}", e);
+                }
+            }
+        }
+        self.emit_event(Event::OfflineSync(sync)).await;
+    }
+    
+    /// Hand a notification to the subsystem owning its type.
+    ///
+    /// Returns `false` if no subsystem handles notifications of this type.
+    async fn route_notification(&self, kind: &NotificationKind, node: &Node) -> bool {
+        match kind {
+            NotificationKind::ServerSync => {
+                let collections = notification::server_sync_collections(node);
+                debug!("App state changed on another device: {:?}", collections);
+                self.sync_changed_app_state(&collections).await;
+            }
+            NotificationKind::AccountSync => {
+                for change in notification::account_sync_changes(node) {
+                    match change {
+                        "devices" => {
+                            if let Ok(Some(device)) = self.store.load_device().await {
+                                self.device_cache.invalidate(&device.jid).await;
+                            }
+                        }
+                        BLOCKLIST_NAMESPACE => {
+                            if let Err(e) = self.fetch_blocklist().await {
+                                warn!("Failed to fetch block list: {}", e);
+                            }
+                        }
+                        other => debug!("Ignoring account_sync change <{}>", other),
+                    }
+                }
+            }
+            // The server notifies us when our one-time pre-keys are running low
+            NotificationKind::Encrypt => {
+                if node.find_child("count").is_some() {
+                    if let Err(e) = self.replenish_prekeys().await {
+                        warn!("Failed to replenish pre-keys: {}", e);
+                    }
+                }
+            }
+            NotificationKind::Devices => self.device_cache.handle_device_notification(node).await,
+            NotificationKind::Group => self.process_group_notification(node).await,
+            NotificationKind::Picture | NotificationKind::Status => {
+                if let Some(change) = notification::parse_profile_change(node) {
+                    self.emit_event(Event::ProfileChanged(change)).await;
+                } else {
+                    self.process_group_notification(node).await;
+                }
+            }
+            NotificationKind::Blocklist => {
+                if let Some(event) = blocklist::parse_blocklist_notification(node) {
+                    if self.blocklist.write().await.apply(&event) {
+                        self.sync_contact_blocks(&event.changes).await;
+                    }
+                    self.emit_event(Event::BlocklistChanged(event)).await;
+                }
+            }
+            NotificationKind::MediaRetry => return self.process_media_retry(node).await,
+            NotificationKind::Other(_) => return false,
+        }
+        true
+    }
+    
+    /// Sync the app state collections after another device changed them
+    async fn sync_changed_app_state(&self, collections: &[PatchName]) {
+        // Catch up with the new versions so our next patches do not conflict
+        for &name in collections {
+            let _lock = self.app_state_lock.lock().await;
+            if let Err(e) = self.fetch_app_state(name).await {
+                warn!("Failed to fetch app state {} after server_sync: {}", name, e);
+            }
+        }
+        
+        let manager_guard = self.app_state_manager.lock().await;
+        let Some(ref manager) = *manager_guard else {
+            return;
+        };
+        let request = SyncRequest {
+            data_types: vec![AppStateDataType::Contacts, AppStateDataType::ChatMetadata, AppStateDataType::Settings],
+            force_full_sync: false,
+            priority: SyncPriority::High,
+            timeout: None,
+        };
+        if let Err(e) = manager.request_sync(request).await {
+            warn!("Failed to sync app state after server_sync: {}", e);
+        }
+    }
+    
+    /// Apply a group notification and emit its events
+    async fn process_group_notification(&self, node: &Node) {
+        if let Some(change) = group::parse_group_notification(node) {
+            self.emit_event(Event::GroupInfoChanged(change)).await;
+        }
+        
+        for change in group::parse_group_events(node) {
+            self.group_manager.apply_event(&change);
+            self.emit_event(Event::GroupChange(change)).await;
+        }
+        
+        if let Some((group, requests)) = group::protocol::parse_membership_request_notification(node) {
+            let is_community = self.community_manager.lock().await.get_community(&group).is_some();
+            for request in requests {
+                let event = if is_community {
+                    Event::CommunityJoinRequest(CommunityJoinRequestEvent {
+                        community: group.clone(),
+                        requester: request.jid,
+                        request_method: request.request_method,
+                        timestamp: request.requested_at,
+                    })
+                } else {
+                    Event::GroupJoinRequest(GroupJoinRequestEvent {
+                        group: group.clone(),
+                        requester: request.jid,
+                        request_method: request.request_method,
+                        timestamp: request.requested_at,
+                    })
+                };
+                self.emit_event(event).await;
+            }
+        }
+    }
+    
+    /// Emit a QR code event for each ref of a `<pair-device>` IQ as the codes rotate
+    async fn start_qr_rotation(&self, refs: Vec<String>) -> Result<()> {
+        info!("Received {} pairing refs", refs.len());
+        let mut events = self.auth_manager.lock().await.start_qr_rotation(refs).await?;
+        let handlers = Arc::clone(&self.event_handlers);
+        let forwarder = tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let event = match event {
+                    QREvent::Code { code, .. } => Event::QRCode { code },
+                    QREvent::Timeout => Event::QRTimeout,
+                    QREvent::Success | QREvent::Error(_) => break,
+                    _ => continue,
+                };
+                let handlers = handlers.read().await;
+                for handler in handlers.iter() {
+                    if !handler(event.clone()) {
+                        break;
+                    }
+                }
+            }
+        });
+        
+        let mut tasks = self.background_tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(forwarder);
+        Ok(())
+    }
+    
+    /// React to the server ending the session.
+    ///
+    /// Emits the matching event, deletes the credentials if the device was
+    /// logged out and lets the connection manager decide whether to reconnect.
+    async fn handle_stream_error(&self, error: StreamError) {
+        warn!("Server ended the session: {}", error);
+        self.is_logged_in.store(false, std::sync::atomic::Ordering::SeqCst);
+        
+        let rejected = *self.announced_version.read().unwrap();
+        if let (StreamError::ClientOutdated, Some(rejected)) = (&error, rejected) {
+            match self.newer_web_version(rejected).await {
+                Some(version) => self.retry_login(rejected, version).await,
+                None => {
+                    self.stop_session(error).await;
+                    self.emit_event(Event::ClientOutdated { version: rejected }).await;
+                }
+            }
+            return;
+        }
+        
+        let event = match &error {
+            StreamError::LoggedOut { reason } => Event::LoggedOut { reason: reason.clone() },
+            StreamError::Replaced => Event::StreamReplaced,
+            StreamError::TemporaryBan { code, expires } => Event::TemporaryBan { code: *code, expires: *expires },
+            other => Event::Disconnected { reason: other.to_string() },
+        };
+        
+        if let StreamError::LoggedOut { reason } = &error {
+            if let Some(transition) = self.primary_monitor.observe_logout(reason, std::time::SystemTime::now()) {
+                self.emit_primary_transition(transition).await;
+            }
+        }
+        
+        if error.action(std::time::SystemTime::now()) == StreamErrorAction::ClearCredentials {
+            if let Err(e) = self.store.delete_device().await {
+                warn!("Failed to delete credentials after logout: {}", e);
+            }
+        }
+        
+        self.stop_session(error).await;
+        self.emit_event(event).await;
+    }
+    
+    /// Let the connection manager react to a stream error, or close the socket without one
+    async fn stop_session(&self, error: StreamError) {
+        let manager_guard = self.connection_manager.lock().await;
+        match manager_guard.as_ref() {
+            Some(manager) => {
+                if let Err(e) = manager.handle_stream_error(error).await {
+                    warn!("Failed to pass stream error to connection manager: {}", e);
+                }
+            }
+            None => {
+                if let Some(socket) = self.socket.lock().await.take() {
+                    let _ = socket.close().await;
+                }
+            }
+        }
+    }
+    
+    /// Find a Web version newer than the `rejected` one, from the
+    /// configuration or by fetching the current one
+    async fn newer_web_version(&self, rejected: WebVersion) -> Option<WebVersion> {
+        if self.web_version() <= rejected {
+            if let Err(e) = self.refresh_web_version().await {
+                warn!("Failed to fetch the WhatsApp Web version: {}", e);
+            }
+        }
+        let version = self.web_version();
+        (version > rejected).then_some(version)
+    }
+    
+    /// Log in again announcing `version` after the server refused `rejected`
+    async fn retry_login(&self, rejected: WebVersion, version: WebVersion) {
+        info!("WhatsApp Web {} is outdated, logging in again as {}", rejected, version);
+        self.emit_event(Event::Disconnected { reason: format!("client version {} outdated", rejected) }).await;
+        
+        let manager_guard = self.connection_manager.lock().await;
+        let result = match manager_guard.as_ref() {
+            Some(manager) => manager.reconnect().await,
+            None => {
+                if let Some(socket) = self.socket.lock().await.take() {
+                    let _ = socket.close().await;
+                }
+                match self.session_connector()().await {
+                    Ok(socket) => {
+                        *self.socket.lock().await = Some(socket);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to log in again as WhatsApp Web {}: {}", version, e);
+        }
+    }
+    
+    /// Emit an event to all handlers
+    async fn emit_event(&self, event: Event) {
+        let handlers = self.event_handlers.read().await;
+        for handler in handlers.iter() {
+            if !handler(event.clone()) {
+                break;
+            }
+        }
+    }
+    
+    /// Get connection statistics
+    pub async fn get_connection_stats(&self) -> Option<crate::connection::ConnectionStats> {
+        let manager_guard = self.connection_manager.lock().await;
+        manager_guard.as_ref().map(|manager| {
+            let mut stats = manager.get_stats();
+            stats.duplicate_messages = self.message_dedup.duplicates();
+            stats.offline_stanzas = self.offline_stanzas.load(std::sync::atomic::Ordering::Relaxed);
+            stats
+        })
+    }
+    
+    /// Get rate limit status
+    pub async fn get_rate_limit_status(&self) -> std::collections::HashMap<String, crate::connection::rate_limit::RateLimitStatus> {
+        self.rate_limiter.get_all_status().await
+    }
+    
+    /// Current configuration
+    pub fn config(&self) -> ClientConfig {
+        self.config.read().unwrap().clone()
+    }
+    
+    /// Change the configuration while the client is running.
+    ///
+    /// `update` edits a copy of the current configuration. Rate limits,
+    /// connection settings such as the keep-alive interval, and the other
+    /// fields read per operation take effect without reconnecting; a new proxy
+    /// is used for media right away and for the socket on the next connection.
+    /// Fields only read when the client is created are rejected. Emits
+    /// [`Event::ConfigChanged`] and returns the names of the changed fields.
+    pub async fn update_config(&self, update: impl FnOnce(&mut ClientConfig)) -> Result<Vec<&'static str>> {
+        let old = self.config();
+        let mut new = old.clone();
+        update(&mut new);
+        
+        let changed = old.changed_fields(&new);
+        if let Some(field) = changed.iter().find(|field| STARTUP_ONLY_CONFIG.contains(field)) {
+            return Err(Error::Protocol(format!("{} cannot be changed while the client is running", field)));
+        }
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+        *self.config.write().unwrap() = new.clone();
+        
+        if changed.contains(&"rate_limits") {
+            for (category, defaults) in rate_limit::WhatsAppRateLimits::defaults() {
+                if old.rate_limits.contains_key(category) && !new.rate_limits.contains_key(category) {
+                    self.rate_limiter.configure(category, defaults);
+                }
+            }
+            for (category, limits) in &new.rate_limits {
+                self.rate_limiter.configure(category, limits.clone());
+            }
+        }
+        if changed.contains(&"connection_config") {
+            if let Some(manager) = self.connection_manager.lock().await.as_mut() {
+                manager.update_config(new.connection_config.clone()).await?;
+            }
+        }
+        if changed.contains(&"proxy") {
+            self.media_manager.lock().await.set_proxy(new.proxy.clone());
+        }
+        if changed.contains(&"passive") {
+            self.send_guard.set_passive(new.passive);
+        }
+        
+        info!("Client configuration changed: {}", changed.join(", "));
+        self.emit_event(Event::ConfigChanged { fields: changed.iter().map(|field| field.to_string()).collect() }).await;
+        Ok(changed)
+    }
+    
+    /// Change the limits of a rate limiter category while the client is running
+    pub fn configure_rate_limit(&self, category: &str, config: RateLimitConfig) {
+        self.rate_limiter.configure(category, config);
+    }
+    
+    /// Wait until the rate limiter allows a request, emitting
+    /// [`Event::RateLimited`] if it has to wait
+    async fn throttle(&self, category: &str, key: Option<&str>) {
+        let result = match key {
+            Some(key) => self.rate_limiter.check_rate_limit_for(category, key).await,
+            None => self.rate_limiter.check_rate_limit(category).await,
+        };
+        if let RateLimitResult::Limited { retry_after } = result {
+            warn!("Rate limited ({}), retrying in {:?}", category, retry_after);
+            self.emit_event(Event::RateLimited { category: category.to_string(), retry_after }).await;
+            match key {
+                Some(key) => self.rate_limiter.wait_for_rate_limit_for(category, key).await,
+                None => self.rate_limiter.wait_for_rate_limit(category).await,
+            };
+        }
+    }
+    
+    /// Force reconnection
+    pub async fn reconnect(&self) -> Result<()> {
+        let manager_guard = self.connection_manager.lock().await;
+        if let Some(ref manager) = *manager_guard {
+            let result: Result<()> = manager.reconnect().await;
+            result
+        } else {
+            Err(Error::Connection("Connection manager not initialized".to_string()))
+        }
+    }
+    
+    // ===== PUSH NOTIFICATIONS =====
+    
+    /// Register an endpoint the server wakes this device through while it is offline
+    pub async fn register_push_notifications(&self, config: &PushConfig) -> Result<()> {
+        if self.is_passive() {
+            return Err(Error::PassiveMode);
+        }
+        self.iq_sender.send_iq(push::build_register_query(config)).await?;
+        info!("Registered {} push endpoint", config.platform());
+        Ok(())
+    }
+    
+    /// Remove the registered push endpoint
+    pub async fn unregister_push_notifications(&self) -> Result<()> {
+        if self.is_passive() {
+            return Err(Error::PassiveMode);
+        }
+        self.iq_sender.send_iq(push::build_unregister_query()).await?;
+        info!("Unregistered push endpoint");
+        Ok(())
+    }
+    
+    /// Register [`ClientConfig::push_config`] after connecting
+    async fn register_configured_push(&self) {
+        let Some(config) = self.config().push_config else {
+            return;
+        };
+        if self.is_passive() {
+            return;
+        }
+        if let Err(e) = self.register_push_notifications(&config).await {
+            warn!("Failed to register push endpoint: {}", e);
+        }
+    }
+    
+    /// Handle a push received by the application through the registered endpoint.
+    ///
+    /// Emits [`Event::PushWakeup`] and, if [`ClientConfig::reconnect_on_push`]
+    /// is set and the client is disconnected, reconnects so the pending
+    /// messages are delivered.
+    pub async fn handle_push_wakeup(&self) -> Result<()> {
+        let connected = self.is_connected().await;
+        self.emit_event(Event::PushWakeup { connected }).await;
+        if connected || !self.config().reconnect_on_push {
+            return Ok(());
+        }
+        
+        info!("Reconnecting after push wakeup");
+        let has_manager = self.connection_manager.lock().await.is_some();
+        if has_manager {
+            self.reconnect().await
+        } else {
+            self.connect().await
+        }
+    }
+    
+    // ===== ENHANCED MESSAGING METHODS =====
+    
+    /// Send a text message
+    pub async fn send_text(&self, to: &JID, text: String) -> Result<String> {
+        let message = SendableMessage::Text(TextMessage { text });
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Send an extended text message with formatting
+    pub async fn send_extended_text(&self, to: &JID, text: String, context: Option<ContextInfo>) -> Result<String> {
+        let extended_text = ExtendedTextMessage {
+            text,
+            matched_text: None,
+            canonical_url: None,
+            description: None,
+            title: None,
+            text_arg_b: None,
+            thumbnail: None,
+            jpeg_thumbnail: None,
+            context_info: context,
+            font: None,
+            preview_type: None,
+        };
+        let message = SendableMessage::ExtendedText(extended_text);
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Send a media message (image, video, audio, document)
+    pub async fn send_media(&self, to: &JID, media_path: &str, caption: Option<String>) -> Result<String> {
+        // Use media manager to process and upload the media
+        self.throttle(rate_limit::MEDIA, None).await;
+        let media_info = self.media_manager.lock().await.upload_media(media_path, crate::media::MediaType::Auto).await?;
+        self.send_uploaded_media(to, media_info, caption).await
+    }
+    
+    /// Send media held in memory, `filename` is used to detect the MIME type
+    pub async fn send_media_bytes(&self, to: &JID, data: &[u8], filename: &str, caption: Option<String>) -> Result<String> {
+        self.throttle(rate_limit::MEDIA, None).await;
+        let media_info = self
+            .media_manager
+            .lock()
+            .await
+            .upload_media_bytes(data, filename, crate::media::MediaType::Auto)
+            .await?;
+        self.send_uploaded_media(to, media_info, caption).await
+    }
+    
+    async fn send_uploaded_media(&self, to: &JID, media_info: crate::media::MediaInfo, caption: Option<String>) -> Result<String> {
+        let media_message = MediaMessage {
+            url: Some(media_info.url),
+            direct_path: media_info.direct_path,
+            media_key: Some(media_info.media_key),
+            file_sha256: Some(media_info.file_sha256),
+            file_enc_sha256: Some(media_info.file_enc_sha256),
+            file_length: Some(media_info.file_length),
+            mime_type: Some(media_info.mime_type),
+            caption,
+            width: media_info.width,
+            height: media_info.height,
+            page_count: None,
+            seconds: media_info.duration,
+            ptt: Some(false),
+            gif_playback: None,
+            jpeg_thumbnail: media_info.thumbnail,
+            context_info: None,
+        };
+        
+        let message = match media_info.media_type {
+            crate::media::MediaType::Image => SendableMessage::Image(media_message),
+            crate::media::MediaType::Video => SendableMessage::Video(media_message),
+            crate::media::MediaType::Audio => SendableMessage::Audio(media_message),
+            crate::media::MediaType::Document => SendableMessage::Document(media_message),
+            _ => SendableMessage::Document(media_message),
+        };
+        
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Send a voice note
+    pub async fn send_voice_note(&self, to: &JID, audio_path: &str) -> Result<String> {
+        self.throttle(rate_limit::MEDIA, None).await;
+        let media_info = self.media_manager.lock().await.upload_media(audio_path, crate::media::MediaType::Audio).await?;
+        
+        let media_message = MediaMessage {
+            url: Some(media_info.url),
+            direct_path: media_info.direct_path,
+            media_key: Some(media_info.media_key),
+            file_sha256: Some(media_info.file_sha256),
+            file_enc_sha256: Some(media_info.file_enc_sha256),
+            file_length: Some(media_info.file_length),
+            mime_type: Some(media_info.mime_type),
+            caption: None,
+            width: None,
+            height: None,
+            page_count: None,
+            seconds: media_info.duration,
+            ptt: Some(true), // Push to talk
+            gif_playback: None,
+            jpeg_thumbnail: None,
+            context_info: None,
+        };
+        
+        let message = SendableMessage::Voice(media_message);
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Send a WebP image as a sticker of the pack described by `metadata`.
+    ///
+    /// The metadata is written into the image's EXIF chunk, which is how
+    /// WhatsApp learns the pack and the emojis of a sticker.
+    pub async fn send_sticker(&self, to: &JID, sticker_path: &str, metadata: &StickerMetadata) -> Result<String> {
+        let data = tokio::fs::read(sticker_path).await?;
+        let animated = crate::media::validate_sticker(&data)?;
+        let data = crate::media::inject_sticker_metadata(&data, metadata)?;
+        let media_type = if animated {
+            crate::media::MediaType::AnimatedSticker
+        } else {
+            crate::media::MediaType::Sticker
+        };
+        
+        self.throttle(rate_limit::MEDIA, None).await;
+        let media_info = self.media_manager.lock().await.upload_media_bytes(&data, "sticker.webp", media_type).await?;
+        let (width, height) = crate::media::sticker_dimensions(&data)?;
+        
+        let media_message = MediaMessage {
+            url: Some(media_info.url),
+            direct_path: media_info.direct_path,
+            media_key: Some(media_info.media_key),
+            file_sha256: Some(media_info.file_sha256),
+            file_enc_sha256: Some(media_info.file_enc_sha256),
+            file_length: Some(media_info.file_length),
+            mime_type: Some("image/webp".to_string()),
+            caption: None,
+            width: Some(width),
+            height: Some(height),
+            page_count: None,
+            seconds: None,
+            ptt: None,
+            gif_playback: None,
+            jpeg_thumbnail: None,
+            context_info: None,
+        };
+        self.send_message_enhanced(to, SendableMessage::Sticker(media_message)).await
+    }
+    
+    /// Send a location message
+    pub async fn send_location(&self, to: &JID, latitude: f64, longitude: f64, name: Option<String>, address: Option<String>) -> Result<String> {
+        let location = LocationMessage {
+            latitude,
+            longitude,
+            name,
+            address,
+            context_info: None,
+        };
+        let message = SendableMessage::Location(location);
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Send a contact message
+    pub async fn send_contact(&self, to: &JID, display_name: String, vcard: String) -> Result<String> {
+        let contact = ContactMessage {
+            display_name,
+            vcard,
+            context_info: None,
+        };
+        let message = SendableMessage::Contact(contact);
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Forward a received message
+    ///
+    /// The message is sent again with its forwarding score raised by one, so
+    /// it shows as forwarded, or as forwarded many times once the score
+    /// reaches [`FREQUENTLY_FORWARDED_THRESHOLD`](crate::messaging::FREQUENTLY_FORWARDED_THRESHOLD).
+    pub async fn forward_message(&self, to: &JID, original: &MessageInfo) -> Result<String> {
+        let message = crate::messaging::forward_content(original)?;
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// Forward a received message to several chats, returning the new message IDs
+    ///
+    /// Frequently forwarded messages can only be forwarded to one chat at a time.
+    pub async fn forward_message_to_chats(&self, chats: &[JID], original: &MessageInfo) -> Result<Vec<String>> {
+        crate::messaging::check_forward_targets(original, chats.len())?;
+        let message = crate::messaging::forward_content(original)?;
+        let mut message_ids = Vec::with_capacity(chats.len());
+        for chat in chats {
+            message_ids.push(self.send_message_enhanced(chat, message.clone()).await?);
+        }
+        Ok(message_ids)
+    }
+    
+    /// Send several contacts in one message
+    pub async fn send_contacts(&self, to: &JID, contacts: Vec<ContactMessage>) -> Result<String> {
+        let message = match contacts.len() {
+            0 => return Err(Error::Protocol("No contacts to send".to_string())),
+            1 => SendableMessage::Contact(contacts.into_iter().next().unwrap()),
+            _ => SendableMessage::ContactsArray(ContactsArrayMessage::new(contacts)),
+        };
+        self.send_message_enhanced(to, message).await
+    }
+    
+    /// React to a message
+    pub async fn react_to_message(&self, to: &JID, message_key: MessageKey, emoji: String) -> Result<String> {
+        let reaction = ReactionMessage {
+            key: message_key,
+            text: emoji,
+            sender_timestamp: Some(server_time::now()),
+        };
+        let message = SendableMessage::Reaction(reaction.clone());
+        let message_id = self.send_message_enhanced(to, message).await?;
+        
+        if let Ok(Some(device)) = self.store.load_device().await {
+            self.reactions.apply_reaction(&device.jid, &reaction).await;
+        }
+        Ok(message_id)
+    }
+    
+    /// Apply an incoming reaction to the message it targets and emit [`Event::ReactionUpdated`]
+    pub async fn process_reaction(&self, sender: &JID, reaction: &ReactionMessage) -> MessageReactions {
+        let reactions = self.reactions.apply_reaction(sender, reaction).await;
+        let emoji = Some(reaction.text.clone()).filter(|emoji| !emoji.is_empty());
+        
+        // Outdated reactions leave the state unchanged and are not announced
+        if reactions.reaction_of(sender) == emoji.as_deref() {
+            self.emit_event(Event::ReactionUpdated(ReactionUpdatedEvent {
+                message_key: reaction.key.clone(),
+                sender: sender.clone(),
+                emoji,
+                timestamp: reaction.sender_timestamp,
+            })).await;
+        }
+        
+        reactions
+    }
+    
+    /// Replace `@<phone number>` mentions in a message text with contact names.
+    ///
+    /// Names come from the contact store, preferring the address book name over
+    /// the user's push name. Mentions of unknown users are left as they are.
+    pub async fn resolve_mentions(&self, text: &str, mentioned_jids: &[JID]) -> Result<String> {
+        let contact_store = &self.contacts;
+        let mut names = std::collections::HashMap::new();
+        for jid in mentioned_jids {
+            let user = JID::new(jid.user.clone(), jid.server.clone());
+            if let Some(contact) = contact_store.load_contact(&user).await? {
+                if let Some(name) = contact.name.or(contact.notify_name) {
+                    names.insert(user, name);
+                }
+            }
+        }
+        
+        Ok(messaging::resolve_mentions(text, mentioned_jids, &names))
+    }
+    
+    /// Get the reactions on a message, with per-emoji counts and senders
+    pub async fn get_reactions(&self, message_key: &MessageKey) -> Option<MessageReactions> {
+        self.reactions.get_reactions(message_key).await
+    }
+    
+    /// Send a poll
+    pub async fn send_poll(&self, to: &JID, question: String, options: Vec<String>, selectable_count: u32) -> Result<String> {
+        let poll_options = options.into_iter()
+            .map(|name| crate::types::PollOption { name })
+            .collect();
+            
+        let poll = PollMessage {
+            name: question,
+            options: poll_options,
+            selectable_options_count: selectable_count,
+            context_info: None,
+            message_secret: Some(crate::util::crypto::random_bytes(32)),
+        };
+        let message = SendableMessage::Poll(poll.clone());
+        let message_id = self.send_message_enhanced(to, message).await?;
+        
+        let poll_key = MessageKey {
+            remote_jid: to.clone(),
+            from_me: true,
+            id: message_id.clone(),
+            participant: None,
+        };
+        self.track_poll(poll_key, poll).await;
+        
+        Ok(message_id)
+    }
+    
+    /// Fetch a page of a business' catalog, starting after `cursor`
+    pub async fn get_catalog(&self, business: &JID, limit: u32, cursor: Option<&str>) -> Result<Catalog> {
+        if !self.is_logged_in() {
+            return Err(Error::NotLoggedIn);
+        }
+        let response = self.send_iq(business::catalog_query(business, limit, cursor)).await?;
+        business::parse_catalog(&response)
+    }
+    
+    /// Fetch a single product of a business' catalog
+    pub async fn get_product(&self, business: &JID, product_id: &str) -> Result<Product> {
+        if !self.is_logged_in() {
+            return Err(Error::NotLoggedIn);
+        }
+        let response = self.send_iq(business::product_query(business, product_id)).await?;
+        business::parse_product_response(&response)
+    }
+    
+    /// Share a product of `business`'s catalog in a chat
+    pub async fn send_product(&self, to: &JID, business: &JID, product: &Product, body: Option<String>) -> Result<String> {
+        let mut message = business::product_message(business, product);
+        message.body = body;
+        self.send_message_enhanced(to, SendableMessage::Product(message)).await
+    }
+    
+    /// Start aggregating votes for a received poll
+    pub async fn track_poll(&self, poll_key: MessageKey, poll: PollMessage) {
+        if self.poll_results.register_poll(poll_key.clone(), poll.clone()).await {
+            if let Err(e) = self.polls.put_poll(&poll_key, &poll).await {
+                warn!("Failed to store poll {}: {}", poll_key.id, e);
+            }
+        }
+    }
+    
+    /// Apply an incoming poll vote and return the updated results
+    pub async fn process_poll_update(&self, voter: &JID, update: &PollUpdateMessage) -> Option<PollResults> {
+        let results = self.poll_results.apply_vote(voter, update).await?;
+        if let Some(vote) = self.poll_results.vote_of(&results.poll_key, voter).await {
+            if let Err(e) = self.polls.put_vote(&results.poll_key, &vote).await {
+                warn!("Failed to store poll vote from {}: {}", voter, e);
+            }
+        }
+        Some(results)
+    }
+    
+    /// Decrypt a received poll vote with the secret of its poll and count it
+    async fn process_encrypted_poll_vote(&self, chat: &JID, voter: &JID, update: &mut PollUpdateMessage) {
+        // The poll key is written from the voter's side, in a one-to-one chat
+        // its remote JID is our own. The poll is in the chat the vote came in.
+        update.poll_creation_message_key.remote_jid = chat.clone();
+        let Some(encrypted) = &update.encrypted_vote else {
+            return;
+        };
+        let Some((poll_key, poll)) = self.poll_results.get_poll(&update.poll_creation_message_key).await else {
+            debug!("Dropping vote from {} on untracked poll {}", voter, update.poll_creation_message_key.id);
+            return;
+        };
+        let Some(secret) = &poll.message_secret else {
+            warn!("Poll {} has no secret to decrypt the vote from {} with", poll_key.id, voter);
+            return;
+        };
+        let creator = if poll_key.from_me {
+            match self.store.load_device().await {
+                Ok(Some(device)) => device.jid,
+                _ => return,
+            }
+        } else {
+            poll_key.participant.clone().unwrap_or_else(|| poll_key.remote_jid.clone())
+        };
+        
+        match messaging::decrypt_poll_vote(encrypted, &poll_key.id, &creator, voter, secret) {
+            Ok(vote) => {
+                update.vote = vote;
+                self.process_poll_update(voter, update).await;
+            }
+            Err(e) => warn!("Failed to decrypt poll vote from {}: {}", voter, e),
+        }
+    }
+    
+    /// Get the aggregated results of a tracked poll
+    pub async fn get_poll_results(&self, poll_key: &MessageKey) -> Option<PollResults> {
+        self.poll_results.get_results(poll_key).await
+    }
+    
+    /// Reply to a message
+    pub async fn reply_to_message(&self, to: &JID, original_message: MessageKey, reply_text: String) -> Result<String> {
+        let quoted = crate::types::QuotedMessage {
+            id: original_message.id,
+            remote_jid: original_message.remote_jid,
+            participant: original_message.participant,
+            message_type: crate::types::MessageType::Text,
+            text: Some(reply_text.clone()),
+            media_type: None,
+        };
+        
+        let context = ContextInfo {
+            quoted_message: Some(Box::new(quoted)),
+            mentioned_jids: Vec::new(),
+            non_jid_mentions: None,
+            group_mentions: Vec::new(),
+            forwarded: None,
+            forwarding_score: None,
+            is_forwarded: None,
+            ephemeral_setting: None,
+            ephemeral_shared_secret: None,
+            external_ad_reply: None,
+        };
+        
+        self.send_extended_text(to, reply_text, Some(context)).await
+    }
+    
+    /// Edit a message
+    pub async fn edit_message(&self, to: &JID, message_key: MessageKey, new_text: String) -> Result<String> {
+        let edit_message = MessageEditor::create_edit_message(message_key, new_text);
+        self.send_message_enhanced(to, edit_message).await
+    }
+    
+    /// Delete a message
+    pub async fn delete_message(&self, to: &JID, message_key: MessageKey) -> Result<String> {
+        let delete_message = MessageEditor::create_delete_message(message_key);
+        self.send_message_enhanced(to, delete_message).await
+    }
+    
+    /// Enhanced message sending with full feature support
+    async fn send_message_enhanced(&self, to: &JID, message: SendableMessage) -> Result<String> {
+        if self.shutting_down.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(Error::SendBlocked("Client is shutting down".to_string()));
+        }
+        if !self.is_logged_in() {
+            return Err(Error::NotLoggedIn);
+        }
+        self.send_guard.check_recipient(to)?;
+        if let Some(anti_spam) = &self.anti_spam {
+            let delay = anti_spam.check(to, &serde_json::to_vec(&message)?)?;
+            tokio::time::sleep(delay).await;
+        }
+        
+        let message_id = uuid::Uuid::new_v4().to_string();
+        
+        // Keep the message until the connection is back
+        if !self.is_connected().await {
+            self.store_in_outbox(to, &message, &message_id).await?;
+            return Ok(message_id);
+        }
+        
+        self.deliver_message(to, message, message_id).await
+    }
+    
+    /// Build, encrypt and transmit a message under the given ID
+    async fn deliver_message(&self, to: &JID, message: SendableMessage, message_id: String) -> Result<String> {
+        // Apply rate limiting for message sending, overall and per chat
+        self.throttle(rate_limit::MESSAGES, None).await;
+        self.throttle(rate_limit::CHAT_MESSAGES, Some(&to.to_non_ad().to_string())).await;
+        
+        debug!("Sending enhanced message to {}: {:?}", to, message);
+        
+        // Held until the server acked the message so the next message to the
+        // same chat is encrypted and sent after this one
+        let _permit = self.send_scheduler.acquire(to).await;
+        
+        // Update message status to pending
+        self.message_status_tracker.update_status(&message_id, MessageStatus::Pending).await;
+        
+        // Resolve the devices the message is encrypted for: the recipient's, or
+        // every participant's for groups, plus our own other devices
+        let (recipient_devices, own_devices) = match self.resolve_fanout_devices(to).await {
+            Ok(devices) => devices,
+            Err(e) => {
+                self.message_status_tracker.update_status(&message_id, MessageStatus::Failed).await;
+                return Err(e);
+            }
+        };
+        
+        // An explicit ephemeral setting wins over the chat's current timer
+        let context_ephemeral = message.context_info()
+            .and_then(|context| context.ephemeral_setting)
+            .filter(|expiration| *expiration > 0);
+        let ephemeral = match context_ephemeral {
+            Some(expiration) => Some(expiration),
+            None => self.ephemeral_timers.get_timer(to).await,
+        };
         
         // Use retry executor for sending messages
         let result = self.retry_executor.execute(|attempt| {
             let to = to.clone();
             let message = message.clone();
             let message_id = message_id.clone();
-            let message_queue = Arc::clone(&self.message_queue);
-            let status_tracker = Arc::clone(&self.message_status_tracker);
+            let signal_manager = Arc::clone(&self.signal_manager);
+            let recipient_devices = recipient_devices.clone();
+            let own_devices = own_devices.clone();
+            
+            async move {
+                info!("Sending message attempt #{}", attempt.attempt);
+                
+                // Build the message node with enhanced builder
+                let from_jid = JID::new("placeholder".to_string(), "s.whatsapp.net".to_string());
+                let mut builder = MessageBuilder::new(to.clone());
+                if let Some(expiration) = ephemeral {
+                    builder = builder.ephemeral(expiration);
+                }
+                if let Some(context) = message.context_info() {
+                    builder = builder.with_context(context.clone());
+                }
+                
+                let node = match &message {
+                    SendableMessage::Text(text_msg) => {
+                        builder.text(text_msg.text.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::ExtendedText(ext_text) => {
+                        builder.extended_text(ext_text.text.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Image(media) => {
+                        builder.media(crate::types::MessageType::Image, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Video(media) => {
+                        builder.media(crate::types::MessageType::Video, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Audio(media) => {
+                        builder.media(crate::types::MessageType::Audio, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Voice(media) => {
+                        builder.media(crate::types::MessageType::Voice, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Document(media) => {
+                        builder.media(crate::types::MessageType::Document, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Sticker(media) => {
+                        builder.media(crate::types::MessageType::Sticker, media.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Location(location) => {
+                        builder.location(location.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Contact(contact) => {
+                        builder.contact(contact.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::ContactsArray(contacts) => {
+                        builder.contacts_array(contacts.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Reaction(reaction) => {
+                        builder.reaction(reaction.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Poll(poll) => {
+                        builder.poll(poll.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::Product(product) => {
+                        builder.product(product.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    SendableMessage::KeepInChat(keep) => {
+                        builder.keep_in_chat(keep.clone()).build(message_id.clone(), from_jid)?
+                    },
+                    _ => {
+                        return Err(Error::Protocol("Unsupported message type".to_string()));
+                    }
+                };
+                
+                // Encrypt the payload for every recipient device, and a
+                // device-sent copy for our own other devices
+                let own_devices = if devices::wants_device_fanout(&node) { own_devices } else { Vec::new() };
+                let node = if to.is_group() {
+                    let mut devices = recipient_devices;
+                    devices.extend(own_devices);
+                    let plaintext = msg_transport::seal(BinaryEncoder::new().encode(&node)?, None);
+                    let fanout = {
+                        let mut signal = signal_manager.lock().await;
+                        let fanout = devices::build_group_fanout(&mut signal, &to, &devices, &plaintext)?;
+                        signal.persist().await?;
+                        fanout
+                    };
+                    let mut children = node.get_children().cloned().unwrap_or_default();
+                    children.push(fanout.enc);
+                    if fanout.participants.get_children().is_some_and(|c| !c.is_empty()) {
+                        children.push(fanout.participants);
+                    }
+                    Node { content: crate::binary::NodeContent::Children(children), ..node }
+                        .attr("phash".to_string(), fanout.phash)
+                } else if recipient_devices.is_empty() && own_devices.is_empty() {
+                    node
+                } else {
+                    let payload = BinaryEncoder::new().encode(&node)?;
+                    let plaintext = msg_transport::seal(payload.clone(), None);
+                    let device_sent = msg_transport::DeviceSentMetadata {
+                        destination: to.clone(),
+                        phash: Some(devices::participant_list_hash_v2(&recipient_devices)),
+                    };
+                    let own_plaintext = msg_transport::seal(payload, Some(&device_sent));
+                    let participants = {
+                        let mut signal = signal_manager.lock().await;
+                        let participants = devices::build_fanout_participants(&mut signal, &recipient_devices, &plaintext, &own_devices, &own_plaintext)?;
+                        signal.persist().await?;
+                        participants
+                    };
+                    let mut children = node.get_children().cloned().unwrap_or_default();
+                    children.push(participants);
+                    Node { content: crate::binary::NodeContent::Children(children), ..node }
+                };
+                
+                Ok(node)
+            }
+        }).await;
+        
+        match result {
+            RetryResult::Success(node) => {
+                self.message_queue.lock().await.enqueue(message_id.clone(), to, node.clone());
+                if to.is_group() {
+                    self.expect_group_receipts(to, &message_id).await;
+                }
+                
+                match self.transmit_message(&message_id, &node).await {
+                    Ok(()) => {
+                        debug!("Enhanced message sent successfully: {}", message_id);
+                        Ok(message_id)
+                    }
+                    // Stays queued and is sent again after reconnecting
+                    Err(e) if is_recoverable_error(&e) => {
+                        info!("Message {} queued for resend after reconnect: {}", message_id, e);
+                        Ok(message_id)
+                    }
+                    Err(e) => {
+                        warn!("Server rejected message {}: {}", message_id, e);
+                        self.message_status_tracker.update_status(&message_id, MessageStatus::Failed).await;
+                        self.message_queue.lock().await.mark_failed(&message_id, e.to_string());
+                        Err(e)
+                    }
+                }
+            }
+            RetryResult::Failed { error, attempts } => {
+                warn!("Failed to send enhanced message after {} attempts", attempts.len());
+                
+                // Update status to failed
+                self.message_status_tracker.update_status(&message_id, MessageStatus::Failed).await;
+                
+                // Mark message as failed in queue
+                let mut queue = self.message_queue.lock().await;
+                queue.mark_failed(&message_id, error.to_string());
+                
+                Err(error)
+            }
+        }
+    }
+    
+    /// Write a queued message to the socket and wait for the server ack.
+    ///
+    /// The message is removed from the queue and the outbox once acknowledged.
+    async fn transmit_message(&self, message_id: &str, node: &Node) -> Result<()> {
+        let receiver = self.response_waiters.add(message_id);
+        if let Err(e) = self.send_node(node).await {
+            self.response_waiters.cancel(message_id);
+            return Err(e);
+        }
+        self.message_status_tracker.update_status(message_id, MessageStatus::Sent).await;
+        
+        let ack = self.response_waiters.wait(message_id, receiver, self.config().message_ack_timeout).await?;
+        if let Some(code) = ack.get_attr("error") {
+            return Err(Error::Protocol(format!("Server rejected message {} with error {}", message_id, code)));
+        }
+        
+        self.message_status_tracker.update_status(message_id, MessageStatus::ServerAck).await;
+        self.message_queue.lock().await.acknowledge(message_id);
+        // Messages sent from the outbox stay stored until the server has them
+        if let Err(e) = self.outbox.remove(message_id).await {
+            warn!("Failed to remove message {} from the outbox: {}", message_id, e);
+        }
+        metrics::message_sent();
+        Ok(())
+    }
+    
+    /// Devices a message to `to` is encrypted for, and our own other devices.
+    ///
+    /// Our own devices are left out while not logged in. Signal sessions
+    /// with devices that have none are established on the way.
+    async fn resolve_fanout_devices(&self, to: &JID) -> Result<(Vec<JID>, Vec<JID>)> {
+        let own = self.store.load_device().await?.map(|device| device.jid);
+        let recipients = if to.is_group() {
+            let participants: Vec<JID> = self.group_manager.get_group_info(to).await?
+                .participants
+                .into_iter()
+                .filter(|participant| Some(&participant.user) != own.as_ref().map(|own| &own.user))
+                .collect();
+            self.get_user_devices(&participants).await?
+        } else {
+            self.get_user_devices(std::slice::from_ref(to)).await?
+        };
+        let own_devices = match own {
+            Some(own) => devices::own_fanout_devices(&self.get_user_devices(&[own.to_non_ad()]).await?, &own),
+            None => Vec::new(),
+        };
+        let all: Vec<JID> = recipients.iter().chain(&own_devices).cloned().collect();
+        self.ensure_sessions(&all).await?;
+        Ok((recipients, own_devices))
+    }
+    
+    /// Establish missing Signal sessions, reporting devices whose identity key changed
+    async fn ensure_sessions(&self, devices: &[JID]) -> Result<()> {
+        let changed = devices::ensure_sessions(self.iq_sender.as_ref(), &self.signal_manager, devices, self.config().identity_change_policy).await?;
+        for (device, blocked) in changed {
+            info!("Identity key of {} changed{}", device, if blocked { ", blocked" } else { "" });
+            self.emit_event(Event::IdentityChanged { jid: device.to_non_ad(), blocked }).await;
+        }
+        Ok(())
+    }
+    
+    /// Track per-participant receipts of a group message
+    async fn expect_group_receipts(&self, group: &JID, message_id: &str) {
+        let participants = match self.group_manager.get_group_info(group).await {
+            Ok(info) => info.participants,
+            Err(e) => {
+                debug!("Not tracking receipts of {}, group members unknown: {}", message_id, e);
+                return;
+            }
+        };
+        let own_user = self.store.load_device().await.ok().flatten().map(|device| device.jid.user);
+        let recipients: Vec<JID> = participants
+            .into_iter()
+            .filter(|participant| Some(&participant.user) != own_user.as_ref())
+            .collect();
+        self.message_status_tracker.expect_receipts(message_id, &recipients).await;
+    }
+    
+    /// Send all queued messages that have not been acknowledged by the server,
+    /// e.g. after reconnecting. Chats are resent in parallel, each in its
+    /// original order. Returns the number of acknowledged messages.
+    pub async fn resend_queued_messages(&self) -> usize {
+        let chats = self.message_queue.lock().await.pending_by_chat();
+        let sent: usize = futures_util::future::join_all(
+            chats.into_iter().map(|messages| self.resend_chat(messages))
+        ).await.into_iter().sum();
+        
+        if sent > 0 {
+            info!("Resent {} queued messages", sent);
+        }
+        sent
+    }
+    
+    /// Persist a message sent while offline so it survives restarts
+    async fn store_in_outbox(&self, to: &JID, message: &SendableMessage, message_id: &str) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let entry = OutboxEntry {
+            message_id: message_id.to_string(),
+            chat: to.clone(),
+            message: message.clone(),
+            created_at: now,
+            expires_at: self.config().outbox_ttl.map(|ttl| now + ttl.as_secs() as i64),
+        };
+        self.outbox.enqueue(&entry).await?;
+        self.message_status_tracker.update_status(message_id, MessageStatus::Pending).await;
+        
+        info!("Offline, stored message {} to {} in the outbox", message_id, to);
+        Ok(())
+    }
+    
+    /// Send the messages stored in the outbox while offline, oldest first.
+    ///
+    /// Messages past their TTL are dropped. Flushing stops when the connection
+    /// is lost again, the rest stays for the next reconnect.
+    pub async fn flush_outbox(&self) -> OutboxFlushedEvent {
+        let mut summary = OutboxFlushedEvent::default();
+        let entries = match self.outbox.list().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to load the outbox: {}", e);
+                return summary;
+            }
+        };
+        if entries.is_empty() {
+            return summary;
+        }
+        
+        let now = chrono::Utc::now().timestamp();
+        let total = entries.len();
+        let mut processed = 0;
+        
+        for entry in entries {
+            // Already sent once and waiting to be resent after reconnecting
+            if self.message_queue.lock().await.is_pending(&entry.message_id) {
+                continue;
+            }
             
-            async move {
-                info!("Sending message attempt #{}", attempt.attempt);
-                
-                // Build the message node with enhanced builder
-                let from_jid = JID::new("placeholder".to_string(), "s.whatsapp.net".to_string());
-                let mut builder = MessageBuilder::new(to);
-                
-                let node = match &message {
-                    SendableMessage::Text(text_msg) => {
-                        builder.text(text_msg.text.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::ExtendedText(ext_text) => {
-                        builder.extended_text(ext_text.text.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Image(media) => {
-                        builder.media(crate::types::MessageType::Image, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Video(media) => {
-                        builder.media(crate::types::MessageType::Video, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Audio(media) => {
-                        builder.media(crate::types::MessageType::Audio, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Voice(media) => {
-                        builder.media(crate::types::MessageType::Voice, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Document(media) => {
-                        builder.media(crate::types::MessageType::Document, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Sticker(media) => {
-                        builder.media(crate::types::MessageType::Sticker, media.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Location(location) => {
-                        builder.location(location.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Contact(contact) => {
-                        builder.contact(contact.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Reaction(reaction) => {
-                        builder.reaction(reaction.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    SendableMessage::Poll(poll) => {
-                        builder.poll(poll.clone()).build(message_id.clone(), from_jid)?
-                    },
-                    _ => {
-                        return Err(Error::Protocol("Unsupported message type".to_string()));
+            if entry.is_expired(now) {
+                debug!("Outbox message {} expired", entry.message_id);
+                self.message_status_tracker.update_status(&entry.message_id, MessageStatus::Failed).await;
+                summary.expired += 1;
+            } else {
+                match self.deliver_message(&entry.chat, entry.message, entry.message_id.clone()).await {
+                    // Not acked yet, the row is kept until the resend is acked
+                    Ok(_) if self.message_queue.lock().await.is_pending(&entry.message_id) => {
+                        debug!("Stopped flushing the outbox, message {} awaits resend", entry.message_id);
+                        break;
+                    }
+                    // Acked, `transmit_message` removed the row
+                    Ok(_) => {
+                        summary.sent += 1;
+                        processed += 1;
+                        continue;
+                    }
+                    Err(e) if is_recoverable_error(&e) => {
+                        debug!("Stopped flushing the outbox: {}", e);
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Dropping outbox message {}: {}", entry.message_id, e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+            
+            if let Err(e) = self.outbox.remove(&entry.message_id).await {
+                warn!("Failed to remove message {} from the outbox: {}", entry.message_id, e);
+            }
+            processed += 1;
+        }
+        
+        summary.remaining = total - processed;
+        info!(
+            "Flushed outbox: {} sent, {} expired, {} failed, {} remaining",
+            summary.sent, summary.expired, summary.failed, summary.remaining
+        );
+        self.emit_event(Event::OutboxFlushed(summary.clone())).await;
+        summary
+    }
+    
+    /// Schedule a message to be sent at `at` (server time), returning the ID
+    /// it will be sent with.
+    ///
+    /// Scheduled messages are stored in the database and survive restarts.
+    /// They are sent by [`start_message_scheduler`](Self::start_message_scheduler)
+    /// and after every connect; messages that came due while offline are sent
+    /// after reconnecting.
+    pub async fn schedule_message(&self, to: &JID, message: SendableMessage, at: std::time::SystemTime) -> Result<String> {
+        self.send_guard.check_recipient(to)?;
+        let unix = |time: std::time::SystemTime| {
+            time.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+        };
+        let scheduled = ScheduledMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            chat: to.clone(),
+            message,
+            send_at: unix(at),
+            created_at: unix(server_time::now()),
+        };
+        self.scheduled_messages.add(&scheduled).await?;
+        debug!("Scheduled message {} to {} at {}", scheduled.id, to, scheduled.send_at);
+        
+        if scheduled.is_due(scheduled.created_at) {
+            self.send_due_scheduled_messages().await;
+        }
+        Ok(scheduled.id)
+    }
+    
+    /// Cancel a scheduled message, returning whether it was still waiting
+    pub async fn cancel_scheduled_message(&self, id: &str) -> Result<bool> {
+        let _dispatch = self.scheduled_dispatch.lock().await;
+        self.scheduled_messages.remove(id).await
+    }
+    
+    /// Messages waiting to be sent, the earliest first
+    pub async fn list_scheduled_messages(&self) -> Result<Vec<ScheduledMessage>> {
+        self.scheduled_messages.list().await
+    }
+    
+    /// Send the scheduled messages that are due, returning how many were sent.
+    ///
+    /// Messages stay scheduled while disconnected. Messages that fail for good
+    /// are dropped and marked failed, like outbox messages.
+    pub async fn send_due_scheduled_messages(&self) -> usize {
+        if !self.is_logged_in() || !self.is_connected().await {
+            return 0;
+        }
+        let _dispatch = self.scheduled_dispatch.lock().await;
+        let due = match self.scheduled_messages.due(server_time::unix_now() as i64).await {
+            Ok(due) => due,
+            Err(e) => {
+                warn!("Failed to load scheduled messages: {}", e);
+                return 0;
+            }
+        };
+        
+        let mut sent = 0;
+        for scheduled in due {
+            let result = match self.send_guard.check_recipient(&scheduled.chat) {
+                Ok(()) => self.deliver_message(&scheduled.chat, scheduled.message, scheduled.id.clone()).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => {
+                    sent += 1;
+                    self.emit_event(Event::ScheduledMessageSent(ScheduledMessageSentEvent {
+                        id: scheduled.id.clone(),
+                        chat: scheduled.chat,
+                        scheduled_for: std::time::UNIX_EPOCH + std::time::Duration::from_secs(scheduled.send_at.max(0) as u64),
+                        sent_at: server_time::now(),
+                    })).await;
+                }
+                Err(e) if is_recoverable_error(&e) => {
+                    debug!("Stopped sending scheduled messages: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Dropping scheduled message {}: {}", scheduled.id, e);
+                    self.message_status_tracker.update_status(&scheduled.id, MessageStatus::Failed).await;
+                }
+            }
+            if let Err(e) = self.scheduled_messages.remove(&scheduled.id).await {
+                warn!("Failed to remove scheduled message {}: {}", scheduled.id, e);
+            }
+        }
+        if sent > 0 {
+            info!("Sent {} scheduled messages", sent);
+        }
+        sent
+    }
+    
+    /// Send scheduled messages as they come due.
+    ///
+    /// Checks every [`ClientConfig::scheduled_message_interval`], starting
+    /// right away so messages that came due while the process was down are
+    /// sent. The task stops when the client is dropped or shut down.
+    pub fn start_message_scheduler(self: &Arc<Self>) {
+        let client = Arc::downgrade(self);
+        let period = self.config().scheduled_message_interval;
+        let scheduler = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                client.send_due_scheduled_messages().await;
+            }
+        });
+        
+        let mut tasks = self.background_tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(scheduler);
+    }
+    
+    /// Resend the queued messages of one chat in order
+    async fn resend_chat(&self, messages: Vec<PendingMessage>) -> usize {
+        let mut sent = 0;
+        
+        for message in messages {
+            let _permit = self.send_scheduler.acquire(&message.chat).await;
+            match self.transmit_message(&message.id, &message.node).await {
+                Ok(()) => sent += 1,
+                Err(e) if is_recoverable_error(&e) => {
+                    // Connection is gone again, keep the rest for the next reconnect
+                    debug!("Stopped resending queued messages to {}: {}", message.chat, e);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Server rejected queued message {}: {}", message.id, e);
+                    self.message_status_tracker.update_status(&message.id, MessageStatus::Failed).await;
+                    self.message_queue.lock().await.mark_failed(&message.id, e.to_string());
+                }
+            }
+        }
+        
+        sent
+    }
+    
+    /// Get message status
+    pub async fn get_message_status(&self, message_id: &str) -> Option<MessageStatus> {
+        self.message_status_tracker.get_status(message_id).await
+    }
+    
+    /// Get recent messages from a chat
+    pub async fn get_recent_messages(&self, chat_id: &str, count: usize) -> Vec<MessageInfo> {
+        let thread_manager = self.message_thread_manager.lock().await;
+        thread_manager.get_recent_messages(chat_id, count)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+    
+    /// Users currently typing or recording in a chat, most useful for groups.
+    ///
+    /// Indicators expire after [`chat_state::DEFAULT_TYPING_TIMEOUT`] unless
+    /// the user's client repeats them, and end when the user's message arrives.
+    pub fn get_active_typers(&self, chat: &JID) -> Vec<ActiveTyper> {
+        self.typing.active(chat, std::time::Instant::now())
+    }
+    
+    /// Write the stored messages of a chat within `range` to `writer`, oldest
+    /// first, see [`crate::chat_export`].
+    ///
+    /// Senders are named after their contact, then their push name, then their
+    /// number; our own messages are from "You". Returns the number of
+    /// exported messages and the manifest of their media.
+    pub async fn export_chat<W: tokio::io::AsyncWrite + Unpin>(
+        &self,
+        chat: &JID,
+        format: ExportFormat,
+        range: ExportRange,
+        writer: &mut W,
+    ) -> Result<ChatExport> {
+        use tokio::io::AsyncWriteExt;
+        
+        let mut messages: Vec<MessageInfo> = {
+            let threads = self.message_thread_manager.lock().await;
+            threads.get_thread(&chat.to_string())
+                .map(|thread| thread.iter().filter(|message| range.contains(message.timestamp)).cloned().collect())
+                .unwrap_or_default()
+        };
+        messages.sort_by_key(|message| message.timestamp);
+        
+        let names: std::collections::HashMap<JID, String> = self.contacts
+            .list_contacts()
+            .await?
+            .into_iter()
+            .filter_map(|contact| Some((contact.jid.to_non_ad(), contact.display_name()?.to_string())))
+            .collect();
+        
+        let offset = *chrono::Local::now().offset();
+        let mut exporter = ChatExporter::new(format, offset);
+        for message in &messages {
+            let sender = message.sender.to_non_ad();
+            let sender_name = if message.from_me {
+                "You".to_string()
+            } else if let Some(name) = names.get(&sender).or(message.push_name.as_ref()) {
+                name.clone()
+            } else {
+                format!("+{}", sender.user)
+            };
+            writer.write_all(exporter.format_message(message, &sender_name)?.as_bytes()).await?;
+        }
+        writer.flush().await?;
+        
+        let export = exporter.finish();
+        info!("Exported {} messages of {}", export.messages, chat);
+        Ok(export)
+    }
+    
+    /// Tell the sender that voice notes or view-once media were played.
+    ///
+    /// `sender` is the author of the messages in a group and `None` in a
+    /// one-to-one chat. Played implies read, so no read receipt is needed.
+    pub async fn mark_played(&self, chat: &JID, sender: Option<&JID>, message_ids: &[String]) -> Result<()> {
+        let Some((first, rest)) = message_ids.split_first() else {
+            return Ok(());
+        };
+        let mut receipt = Node::receipt(first, chat).kind("played").timestamp(server_time::unix_now());
+        if let Some(sender) = sender {
+            receipt = receipt.participant(sender);
+        }
+        self.send_node(&receipt.more_ids(rest.iter().map(String::as_str)).build()).await
+    }
+    
+    /// Process incoming message receipt
+    pub async fn process_message_receipt(&self, receipt: MessageReceipt) {
+        // Update status tracker
+        let aggregate = self.message_status_tracker.process_receipt(&receipt).await;
+        
+        // Acknowledge in queue if delivered
+        if receipt.status == MessageStatus::Delivered {
+            let mut queue = self.message_queue.lock().await;
+            queue.acknowledge(&receipt.message_id);
+        }
+        
+        // Emit receipt event
+        let message_id = receipt.message_id.clone();
+        self.emit_event(Event::MessageReceipt { receipt }).await;
+        if let Some((status, summary)) = aggregate {
+            self.emit_event(Event::MessageReceiptAggregate(ReceiptAggregateEvent { message_id, status, summary })).await;
+        }
+    }
+    
+    /// Get the receipt counts of a group message sent by us
+    pub async fn get_delivery_summary(&self, message_id: &str) -> Option<DeliverySummary> {
+        self.message_status_tracker.get_delivery_summary(message_id).await
+    }
+    
+    /// Process incoming message
+    pub async fn process_incoming_message(&self, message_info: MessageInfo) {
+        metrics::message_received();
+        let mut message_info = self.resolve_sender(message_info).await;
+        
+        if !self.message_dedup.check(&message_info.chat, &message_info.id) {
+            // Delivered again because our receipt was lost, confirm it once more
+            debug!("Dropping duplicate message {} in {}", message_info.id, message_info.chat);
+            self.send_delivery_receipt(&message_info).await;
+            return;
+        }
+        
+        if let Some(SendableMessage::KeepInChat(keep)) = &message_info.content {
+            self.process_keep_in_chat(&message_info.sender, keep).await;
+            return;
+        }
+        match &mut message_info.content {
+            Some(SendableMessage::Poll(poll)) => {
+                let poll_key = MessageKey {
+                    remote_jid: message_info.chat.clone(),
+                    from_me: message_info.from_me,
+                    id: message_info.id.clone(),
+                    participant: message_info.chat.is_group().then(|| message_info.sender.clone()),
+                };
+                self.track_poll(poll_key, poll.clone()).await;
+            }
+            Some(SendableMessage::PollUpdate(update)) => {
+                self.process_encrypted_poll_vote(&message_info.chat, &message_info.sender, update).await;
+            }
+            _ => {}
+        }
+        self.typing.clear(&message_info.chat, &message_info.sender);
+        
+        // Add to thread manager
+        let timer = self.ephemeral_timers.get_timer(&message_info.chat).await;
+        {
+            let mut thread_manager = self.message_thread_manager.lock().await;
+            let chat_id = message_info.chat.to_string();
+            thread_manager.add_to_thread(&chat_id, message_info.clone());
+            if let Some(timer) = timer {
+                let expires_at = message_info.timestamp + std::time::Duration::from_secs(timer as u64);
+                thread_manager.schedule_expiry(&chat_id, &message_info.id, expires_at);
+            }
+        }
+        
+        if let Some(media_info) = self.config().auto_download.media_to_download(&message_info) {
+            self.spawn_auto_download(&message_info, media_info);
+        }
+        
+        self.send_delivery_receipt(&message_info).await;
+        
+        // Emit message event
+        self.emit_event(Event::Message(message_info)).await;
+    }
+    
+    /// Send the delivery receipt of a received message if [`ClientConfig::auto_mark_delivered`] is set
+    async fn send_delivery_receipt(&self, message_info: &MessageInfo) {
+        if !self.config().auto_mark_delivered || message_info.from_me {
+            return;
+        }
+        let sender = message_info.chat.is_group().then_some(&message_info.sender);
+        let ids = [message_info.id.clone()];
+        if let Err(e) = self.send_receipts(&message_info.chat, sender, None, &ids).await {
+            warn!("Failed to send delivery receipt for {}: {}", message_info.id, e);
+        }
+    }
+    
+    /// Tell the sender that messages were read.
+    ///
+    /// `sender` is the author of the messages in a group and `None` in a
+    /// one-to-one chat.
+    pub async fn mark_read(&self, chat: &JID, sender: Option<&JID>, message_ids: &[String]) -> Result<()> {
+        self.send_receipts(chat, sender, Some("read"), message_ids).await
+    }
+    
+    /// Send receipts of type `kind`, batched per [`ClientConfig::receipt_batch_window`]
+    async fn send_receipts(&self, chat: &JID, sender: Option<&JID>, kind: Option<&str>, message_ids: &[String]) -> Result<()> {
+        let key = ReceiptKey { chat: chat.clone(), sender: sender.cloned(), kind: kind.map(str::to_string) };
+        let window = self.config().receipt_batch_window;
+        if window.is_zero() {
+            let batch = ReceiptBatch { key, ids: message_ids.to_vec() };
+            return match batch.to_node(server_time::unix_now()) {
+                Some(receipt) => self.send_node(&receipt).await,
+                None => Ok(()),
+            };
+        }
+        
+        // Refuse right away instead of when the batch is due
+        if let Some(receipt) = (ReceiptBatch { key: key.clone(), ids: message_ids.to_vec() }).to_node(0) {
+            self.send_guard.check_node(&receipt)?;
+        }
+        for id in message_ids {
+            match self.receipt_batcher.add(key.clone(), id.clone()) {
+                Batched::Started => self.schedule_receipt_flush(key.clone(), window),
+                Batched::Added => {}
+                Batched::Full(batch) => {
+                    if let Some(receipt) = batch.to_node(server_time::unix_now()) {
+                        self.send_node(&receipt).await?;
                     }
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Send the batch of `key` once `window` has passed
+    fn schedule_receipt_flush(&self, key: ReceiptKey, window: std::time::Duration) {
+        let batcher = Arc::clone(&self.receipt_batcher);
+        let send_guard = Arc::clone(&self.send_guard);
+        let iq_sender = Arc::clone(&self.iq_sender);
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let Some(receipt) = batcher.take(&key).and_then(|batch| batch.to_node(server_time::unix_now())) else {
+                return;
+            };
+            let result = match send_guard.check_node(&receipt) {
+                Ok(()) => iq_sender.send_node(&receipt).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!("Failed to send batched receipts to {}: {}", key.chat, e);
+            }
+        });
+    }
+    
+    /// Send all batched receipts now instead of when their window ends
+    pub async fn flush_receipts(&self) {
+        for batch in self.receipt_batcher.take_all() {
+            let Some(receipt) = batch.to_node(server_time::unix_now()) else {
+                continue;
+            };
+            if let Err(e) = self.send_node(&receipt).await {
+                warn!("Failed to send batched receipts to {}: {}", batch.key.chat, e);
+            }
+        }
+    }
+    
+    /// Download the media of a received message.
+    ///
+    /// If the media expired on the CDN, the sender's phone is asked to upload
+    /// it again and the download is repeated from the new location.
+    pub async fn download_message_media(&self, message: &MessageInfo, media_info: &MediaInfo) -> Result<Vec<u8>> {
+        let result = self.media_manager.lock().await.download_media_bytes(media_info).await;
+        match result {
+            Err(Error::MediaExpired) => {}
+            other => return other,
+        }
+        
+        info!("Media of {} expired, requesting re-upload", message.id);
+        let refreshed = self.request_media_reupload(message, media_info).await?;
+        self.media_manager.lock().await.download_media_bytes(&refreshed).await
+    }
+    
+    /// Ask the sender of `message` to upload its expired media again.
+    ///
+    /// Waits up to [`MEDIA_RETRY_TIMEOUT`](media_retry::MEDIA_RETRY_TIMEOUT)
+    /// for the answer and returns the media pointing to its new location.
+    pub async fn request_media_reupload(&self, message: &MessageInfo, media_info: &MediaInfo) -> Result<MediaInfo> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.send_media_retry(message, &media_info.media_key, Some(sender)).await?;
+        
+        let event = match tokio::time::timeout(media_retry::MEDIA_RETRY_TIMEOUT, receiver).await {
+            Ok(Ok(event)) => event,
+            _ => {
+                self.media_retries.lock().await.remove(&message.id);
+                return Err(Error::Protocol(format!("No media retry answer for {}", message.id)));
+            }
+        };
+        match event.outcome {
+            MediaRetryOutcome::Uploaded { direct_path } => Ok(media_info.clone().with_direct_path(direct_path)),
+            MediaRetryOutcome::NotFound => Err(Error::MediaExpired),
+            other => Err(Error::Protocol(format!("Media retry for {} failed: {:?}", message.id, other))),
+        }
+    }
+    
+    /// Send a media retry receipt; the answer is emitted as [`Event::MediaRetry`]
+    pub async fn send_media_retry_receipt(&self, message: &MessageInfo, media_key: &[u8]) -> Result<()> {
+        self.send_media_retry(message, media_key, None).await
+    }
+    
+    async fn send_media_retry(
+        &self,
+        message: &MessageInfo,
+        media_key: &[u8],
+        waiter: Option<tokio::sync::oneshot::Sender<MediaRetryEvent>>,
+    ) -> Result<()> {
+        let own = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        let receipt = media_retry::build_retry_receipt(message, &own, media_key)?;
+        self.media_retries.lock().await.insert(
+            message.id.clone(),
+            PendingMediaRetry { media_key: media_key.to_vec(), waiter },
+        );
+        if let Err(e) = self.send_node(&receipt).await {
+            self.media_retries.lock().await.remove(&message.id);
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Decrypt the answer to a media retry receipt and hand it to its waiter.
+    ///
+    /// Returns `false` for answers to receipts this client did not send.
+    async fn process_media_retry(&self, node: &Node) -> bool {
+        let Some(message_id) = media_retry::media_retry_message_id(node) else {
+            return false;
+        };
+        let Some(pending) = self.media_retries.lock().await.remove(message_id) else {
+            debug!("Media retry answer for unknown message {}", message_id);
+            return false;
+        };
+        
+        let event = match media_retry::parse_media_retry_notification(node, &pending.media_key) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Failed to decrypt media retry answer for {}: {}", message_id, e);
+                return true;
+            }
+        };
+        debug!("Media retry for {}: {:?}", event.message_id, event.outcome);
+        if let Some(waiter) = pending.waiter {
+            let _ = waiter.send(event.clone());
+        }
+        self.emit_event(Event::MediaRetry(event)).await;
+        true
+    }
+    
+    /// Download received media into the media cache in the background and emit
+    /// [`Event::MediaDownloaded`] with its location
+    fn spawn_auto_download(&self, message: &MessageInfo, media_info: crate::media::MediaInfo) {
+        let media_manager = Arc::clone(&self.media_manager);
+        let event_handlers = Arc::clone(&self.event_handlers);
+        let (chat, sender, message_id) = (message.chat.clone(), message.sender.clone(), message.id.clone());
+        
+        tokio::spawn(async move {
+            let path = {
+                let mut manager = media_manager.lock().await;
+                if !manager.has_cache() {
+                    debug!("Not auto-downloading media of {}, no media cache configured", message_id);
+                    return;
+                }
+                if let Err(e) = manager.download_media_bytes(&media_info).await {
+                    warn!("Failed to auto-download media of {}: {}", message_id, e);
+                    return;
+                }
+                manager.cached_path(&media_info)
+            };
+            let Some(path) = path else {
+                warn!("Auto-downloaded media of {} was not cached", message_id);
+                return;
+            };
+            
+            let event = Event::MediaDownloaded(crate::media::MediaDownloadedEvent {
+                chat,
+                sender,
+                message_id,
+                media_type: media_info.media_type.clone(),
+                path,
+            });
+            for handler in event_handlers.read().await.iter() {
+                if !handler(event.clone()) {
+                    break;
+                }
+            }
+        });
+    }
+    
+    /// Handle a message stanza that could not be decrypted.
+    ///
+    /// Asks the sender to encrypt the message again until
+    /// [`MAX_DECRYPT_RETRIES`](decrypt_retry::MAX_DECRYPT_RETRIES) is reached,
+    /// then stores a placeholder in the chat history and emits
+    /// [`Event::UndecryptableMessage`].
+    pub async fn handle_decrypt_failure(&self, node: &Node, error: &Error) -> Result<()> {
+        let sender = node.get_attr("participant").or_else(|| node.get_attr("from")).cloned().unwrap_or_default();
+        let id = node.get_attr("id").cloned().unwrap_or_default();
+        
+        match self.decrypt_retries.record_failure(&sender, &id) {
+            DecryptFailureAction::Retry { count } => {
+                debug!("Failed to decrypt {} from {} ({}), requesting retry #{}", id, sender, error, count);
+                let registration_id = self.signal_manager.lock().await.get_local_registration_id();
+                let receipt = decrypt_retry::build_retry_receipt(node, count, registration_id)?;
+                // The sender resends under the same id
+                self.replay_filter.forget(node);
+                if let Err(e) = self.decrypt_failures.put_retry(&sender, &id, count).await {
+                    warn!("Failed to store retry of {}: {}", id, e);
+                }
+                self.send_node(&receipt).await
+            }
+            DecryptFailureAction::GiveUp => {
+                warn!("Giving up on decrypting {} from {}: {}", id, sender, error);
+                let info = decrypt_retry::placeholder_message(node)?;
+                self.decrypt_failures.put_placeholder(&sender, &info, &error.to_string()).await?;
+                self.message_thread_manager.lock().await.add_to_thread(&info.chat.to_string(), info.clone());
+                self.emit_event(Event::UndecryptableMessage { info, reason: error.to_string() }).await;
+                Ok(())
+            }
+        }
+    }
+    
+    /// Forget earlier decryption failures of a message that was decrypted after a retry
+    pub fn decrypt_succeeded(&self, node: &Node) {
+        let sender = node.get_attr("participant").or_else(|| node.get_attr("from"));
+        if let (Some(sender), Some(id)) = (sender, node.get_attr("id")) {
+            self.decrypt_retries.record_success(sender, id);
+        }
+    }
+    
+    /// [`decrypt_succeeded`](Self::decrypt_succeeded), also removing the stored retry state
+    async fn clear_decrypt_failure(&self, node: &Node) {
+        let sender = node.get_attr("participant").or_else(|| node.get_attr("from"));
+        let (Some(sender), Some(id)) = (sender, node.get_attr("id")) else {
+            return;
+        };
+        if self.decrypt_retries.failures(sender, id) == 0 {
+            return;
+        }
+        self.decrypt_succeeded(node);
+        if let Err(e) = self.decrypt_failures.remove(sender, id).await {
+            warn!("Failed to remove retry state of {}: {}", id, e);
+        }
+    }
+    
+    /// Remember the push name and LID mapping of a message sender and fill in
+    /// the sender's other identifier from known mappings
+    async fn resolve_sender(&self, mut message_info: MessageInfo) -> MessageInfo {
+        let lid_store = SqliteLidStore::new(self.database.pool().clone());
+        let sender = message_info.sender.to_non_ad();
+        
+        match &message_info.sender_alt {
+            Some(alt) => {
+                let (lid, pn) = if sender.is_lid() {
+                    (sender.clone(), alt.to_non_ad())
+                } else {
+                    (alt.to_non_ad(), sender.clone())
                 };
-                
-                // Add to message queue
-                let mut queue = message_queue.lock().await;
-                queue.enqueue(message_id.clone(), node);
-                
-                // Update status to sent
-                status_tracker.update_status(&message_id, MessageStatus::Sent).await;
-                
-                // TODO: Actually send the message through the socket
-                
-                Ok(())
+                if let Err(e) = lid_store.put_mapping(&lid, &pn).await {
+                    warn!("Failed to store LID mapping of {}: {}", sender, e);
+                }
             }
-        }).await;
-        
-        match result {
-            RetryResult::Success(_) => {
-                debug!("Enhanced message sent successfully: {}", message_id);
-                Ok(message_id)
+            None => {
+                let alt = if sender.is_lid() {
+                    lid_store.get_pn(&sender).await
+                } else if sender.is_user() {
+                    lid_store.get_lid(&sender).await
+                } else {
+                    Ok(None)
+                };
+                match alt {
+                    Ok(alt) => message_info.sender_alt = alt,
+                    Err(e) => warn!("Failed to look up LID mapping of {}: {}", sender, e),
+                }
             }
-            RetryResult::Failed { error, attempts } => {
-                warn!("Failed to send enhanced message after {} attempts", attempts.len());
-                
-                // Update status to failed
-                self.message_status_tracker.update_status(&message_id, MessageStatus::Failed).await;
-                
-                // Mark message as failed in queue
-                let mut queue = self.message_queue.lock().await;
-                queue.mark_failed(&message_id, error.to_string());
-                
-                Err(error)
+        }
+        
+        if let Some(push_name) = &message_info.push_name {
+            if let Err(e) = self.contacts.update_push_name(&sender, push_name).await {
+                warn!("Failed to store push name of {}: {}", sender, e);
             }
         }
+        
+        message_info
     }
     
-    /// Get message status
-    pub async fn get_message_status(&self, message_id: &str) -> Option<MessageStatus> {
-        self.message_status_tracker.get_status(message_id).await
+    /// Apply an incoming edit, revoke or disappearing timer change.
+    ///
+    /// Emits [`Event::MessageEdited`] or [`Event::MessageRevoked`]. Edits and
+    /// revokes violating the sender or time window rules are dropped with an
+    /// error. Other protocol message types are ignored.
+    pub async fn process_protocol_message(
+        &self,
+        chat: &JID,
+        sender: &JID,
+        message: &ProtocolMessage,
+        timestamp: std::time::SystemTime,
+    ) -> Result<()> {
+        let chat_id = chat.to_string();
+        
+        match message.message_type {
+            ProtocolMessageType::MessageEdit => {
+                let Some(key) = &message.key else {
+                    return Ok(());
+                };
+                self.message_thread_manager.lock().await
+                    .apply_edit(&chat_id, &key.id, sender, timestamp)?;
+                self.emit_event(Event::MessageEdited(MessageEditedEvent {
+                    chat: chat.clone(),
+                    sender: sender.clone(),
+                    id: key.id.clone(),
+                    new_text: message.edited_text.clone(),
+                    timestamp,
+                })).await;
+            }
+            ProtocolMessageType::Revoke => {
+                let Some(key) = &message.key else {
+                    return Ok(());
+                };
+                self.message_thread_manager.lock().await
+                    .apply_revoke(&chat_id, &key.id, sender, timestamp)?;
+                self.reactions.remove_message(key).await;
+                if self.poll_results.remove_poll(key).await {
+                    if let Err(e) = self.polls.remove(key).await {
+                        warn!("Failed to remove poll {}: {}", key.id, e);
+                    }
+                }
+                self.emit_event(Event::MessageRevoked(MessageRevokeEvent {
+                    chat: chat.clone(),
+                    sender: sender.clone(),
+                    id: key.id.clone(),
+                    timestamp,
+                })).await;
+            }
+            ProtocolMessageType::AppStateSyncKeyShare => {
+                let Some(share) = &message.app_state_sync_key_share else {
+                    return Ok(());
+                };
+                let key_store = SqliteAppStateKeyStore::new(self.database.pool().clone());
+                for key in &share.keys {
+                    key_store.put_key(key).await?;
+                }
+                info!("Stored {} app state sync keys from {}", share.keys.len(), sender);
+            }
+            ProtocolMessageType::HistorySyncNotification => {
+                let Some(notification) = &message.history_sync_notification else {
+                    return Ok(());
+                };
+                if !self.config().enable_history_sync {
+                    debug!("Ignoring history sync from {}, history sync is disabled", sender);
+                    return Ok(());
+                }
+                self.process_history_sync_notification(notification).await?;
+            }
+            // Group timers are managed by the group module
+            ProtocolMessageType::EphemeralSetting if !chat.is_group() => {
+                let expiration = message.ephemeral_expiration.unwrap_or(0);
+                let setting_timestamp = message.ephemeral_setting_timestamp.or(Some(timestamp));
+                if self.ephemeral_timers.set_timer(chat, expiration, setting_timestamp).await {
+                    info!("Disappearing messages in {} set to {}s", chat, expiration);
+                }
+            }
+            _ => {}
+        }
+        
+        Ok(())
     }
     
-    /// Get recent messages from a chat
-    pub async fn get_recent_messages(&self, chat_id: &str, count: usize) -> Vec<MessageInfo> {
-        let thread_manager = self.message_thread_manager.lock().await;
-        thread_manager.get_recent_messages(chat_id, count)
-            .into_iter()
-            .cloned()
-            .collect()
+    /// Ask the primary device for up to `count` messages of `chat` sent before `before`.
+    ///
+    /// The request is anchored at the oldest message of the chat we have from
+    /// `before` on. The messages arrive later as [`Event::HistorySync`] and are
+    /// added to the chat history. Returns the id of the request message.
+    pub async fn fetch_history(&self, chat: &JID, before: std::time::SystemTime, count: u32) -> Result<String> {
+        let own = self.store.load_device().await?.map(|device| device.jid).ok_or(Error::NotLoggedIn)?;
+        let anchor = {
+            let thread_manager = self.message_thread_manager.lock().await;
+            let known = thread_manager.get_thread(&chat.to_string()).map(Vec::as_slice).unwrap_or_default();
+            HistoryAnchor::before(chat, before, known)
+        };
+        
+        // Peer messages go to our primary device only
+        let request = history_sync::build_on_demand_request(&anchor, count);
+        self.ensure_sessions(&[own.to_non_ad()]).await?;
+        let participants = {
+            let mut signal = self.signal_manager.lock().await;
+            let participants = devices::build_fanout_participants(&mut signal, &[own.to_non_ad()], &msg_transport::seal_message(&request, None), &[], &[])?;
+            signal.persist().await?;
+            participants
+        };
+        let message_id = uuid::Uuid::new_v4().to_string();
+        let node = history_sync::peer_message_node(&message_id, &own, participants);
+        self.transmit_message(&message_id, &node).await?;
+        debug!("Requested {} messages of {} before {:?}", count, chat, anchor.id);
+        Ok(message_id)
     }
     
-    /// Process incoming message receipt
-    pub async fn process_message_receipt(&self, receipt: MessageReceipt) {
-        // Update status tracker
-        self.message_status_tracker.process_receipt(&receipt).await;
+    /// Download a history sync announced by the primary device, store it and emit [`Event::HistorySync`]
+    async fn process_history_sync_notification(&self, notification: &HistorySyncNotification) -> Result<()> {
+        let media_info = history_sync::notification_media_info(notification);
+        let blob = self.media_manager.lock().await.download_media_bytes(&media_info).await?;
+        let history = history_sync::decode_history_blob(&blob)?;
+        self.contacts.apply_history_sync(&history).await?;
         
-        // Acknowledge in queue if delivered
-        if receipt.status == MessageStatus::Delivered {
-            let mut queue = self.message_queue.lock().await;
-            queue.acknowledge(&receipt.message_id);
+        let event = history_sync::history_sync_event(&history);
+        {
+            let mut thread_manager = self.message_thread_manager.lock().await;
+            for conversation in &event.conversations {
+                let added = thread_manager.prepend_history(&conversation.chat.to_string(), conversation.messages.clone());
+                debug!("Added {} history messages to {}", added, conversation.chat);
+            }
         }
-        
-        // Emit receipt event
-        self.emit_event(Event::MessageReceipt { receipt }).await;
+        info!("Received {:?} history sync with {} chats", event.sync_type, event.conversations.len());
+        self.emit_event(Event::HistorySync(event)).await;
+        Ok(())
     }
     
-    /// Process incoming message
-    pub async fn process_incoming_message(&self, message_info: MessageInfo) {
-        // Add to thread manager
+    /// Apply an incoming keep or unkeep of a message and emit [`Event::MessageKept`]
+    ///
+    /// Unkept messages expire again once the chat's disappearing timer has
+    /// passed since they were sent.
+    pub async fn process_keep_in_chat(&self, sender: &JID, keep: &KeepInChatMessage) {
+        let chat = &keep.key.remote_jid;
+        let timer = self.ephemeral_timers.get_timer(chat).await;
         {
+            let chat_id = chat.to_string();
             let mut thread_manager = self.message_thread_manager.lock().await;
-            thread_manager.add_to_thread(&message_info.chat.to_string(), message_info.clone());
+            if let Some(message) = thread_manager.apply_keep(&chat_id, &keep.key.id, keep.keep) {
+                if let (false, Some(timer)) = (keep.keep, timer) {
+                    let expires_at = message.timestamp + std::time::Duration::from_secs(timer as u64);
+                    thread_manager.schedule_expiry(&chat_id, &message.id, expires_at);
+                }
+            }
         }
-        
-        // Emit message event
-        self.emit_event(Event::Message(message_info)).await;
+        self.emit_event(Event::MessageKept(MessageKeptEvent {
+            message_key: keep.key.clone(),
+            sender: sender.clone(),
+            kept: keep.keep,
+            timestamp: keep.timestamp,
+        })).await;
+    }
+    
+    /// Keep a message of a disappearing chat for everyone, or undo that
+    pub async fn keep_message(&self, message_key: MessageKey, keep: bool) -> Result<String> {
+        let own = self.store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid;
+        let keep = KeepInChatMessage {
+            key: message_key,
+            keep,
+            timestamp: server_time::now(),
+        };
+        let chat = keep.key.remote_jid.clone();
+        let message_id = self.send_message_enhanced(&chat, SendableMessage::KeepInChat(keep.clone())).await?;
+        self.process_keep_in_chat(&own, &keep).await;
+        Ok(message_id)
+    }
+    
+    /// Get the disappearing message timer of a chat in seconds
+    pub async fn get_ephemeral_timer(&self, chat: &JID) -> Option<u32> {
+        self.ephemeral_timers.get_timer(chat).await
     }
     
     /// Retry failed message
     pub async fn retry_failed_message(&self, message_id: &str) -> Result<Option<String>> {
         let mut queue = self.message_queue.lock().await;
         if let Some(pending) = queue.retry_failed(message_id) {
-            drop(queue); // Release lock before recursive call
+            drop(queue); // Release lock before sending
             
-            // Resend the message
-            // Note: This is a simplified retry - in practice you'd need to reconstruct the original message
             self.message_status_tracker.update_status(&pending.id, MessageStatus::Pending).await;
+            let _permit = self.send_scheduler.acquire(&pending.chat).await;
+            match self.transmit_message(&pending.id, &pending.node).await {
+                Ok(()) => {}
+                Err(e) if is_recoverable_error(&e) => {
+                    debug!("Message {} queued for resend after reconnect: {}", pending.id, e);
+                }
+                Err(e) => {
+                    self.message_status_tracker.update_status(&pending.id, MessageStatus::Failed).await;
+                    self.message_queue.lock().await.mark_failed(&pending.id, e.to_string());
+                    return Err(e);
+                }
+            }
             
             Ok(Some(pending.id))
         } else {
@@ -807,37 +3896,306 @@ impl Client {
         }
     }
 
+    /// Encrypt a patch with the newest app state sync key and publish it so
+    /// our other devices apply the changes too
+    pub async fn send_app_state_patch(&self, patch: PatchInfo) -> Result<()> {
+        if self.send_guard.is_passive() {
+            return Err(Error::PassiveMode);
+        }
+        let key = SqliteAppStateKeyStore::new(self.database.pool().clone())
+            .get_latest_key().await?
+            .ok_or_else(|| Error::Protocol("No app state sync key received from the primary device".to_string()))?;
+        let keys = ExpandedAppStateKeys::expand(&key.key_data.key_data)?;
+        
+        // Hold the lock until the server answered so patches of a collection stay in order
+        let _lock = self.app_state_lock.lock().await;
+        let mut state = self.app_state.hash_state(patch.name).await?;
+        if state.version == 0 {
+            state = self.fetch_app_state(patch.name).await?;
+        }
+        
+        let mut refetched = false;
+        loop {
+            let encoded = appstate::patch::encode_patch(&patch, &key.key_id, &keys, &state)?;
+            let query = appstate::patch::build_send_patch_query(patch.name, state.version, &encoded.patch);
+            let response = self.iq_sender.send_iq(query).await?;
+            match appstate::patch::collection_error(&response, patch.name) {
+                None => {
+                    debug!("Published {} app state mutations to {}, now at version {}", patch.mutations.len(), patch.name, encoded.state.version);
+                    return self.app_state.save_hash_state(patch.name, &encoded.state).await;
+                }
+                // Another device published first, catch up and encode the patch again on top
+                Some(appstate::patch::VERSION_CONFLICT) if !refetched => {
+                    debug!("App state {} moved past version {}, fetching it before retrying", patch.name, state.version);
+                    state = self.fetch_app_state(patch.name).await?;
+                    refetched = true;
+                }
+                Some(code) => {
+                    return Err(Error::Protocol(format!("Server refused app state patch for {} with code {}", patch.name, code)));
+                }
+            }
+        }
+    }
+    
+    /// Fetch the patches of a collection newer than its persisted state and
+    /// apply them, starting from a snapshot when there is no state yet.
+    ///
+    /// Callers hold `app_state_lock`.
+    async fn fetch_app_state(&self, name: PatchName) -> Result<HashState> {
+        let mut state = self.app_state.hash_state(name).await?;
+        let mut refetched = false;
+        loop {
+            let response = self.iq_sender.send_iq(appstate::patch::build_fetch_query(name, state.version)).await?;
+            if let Some(code) = appstate::patch::collection_error(&response, name) {
+                if state.version == 0 || refetched {
+                    return Err(Error::Protocol(format!("Server refused to sync app state {} with code {}", name, code)));
+                }
+                // Our state does not fit the server's any more, start over from a snapshot
+                warn!("Fetching app state {} from version {} failed with code {}, resyncing from a snapshot", name, state.version, code);
+                self.app_state.reset_hash_state(name).await?;
+                state = HashState::default();
+                refetched = true;
+                continue;
+            }
+            
+            if let Some(reference) = appstate::patch::snapshot_reference(&response, name)? {
+                let blob = self.download_app_state_blob(&reference).await?;
+                state = self.app_state.apply_snapshot_state(name, &blob).await?;
+            }
+            for patch in appstate::patch::response_patches(&response, name)? {
+                let mutations = match patch.external_mutations {
+                    Some(ref reference) => {
+                        let blob = self.download_app_state_blob(reference).await?;
+                        SyncdMutations::decode(blob.as_slice())?.mutations
+                    }
+                    None => patch.mutations.clone(),
+                };
+                state = self.app_state.apply_patch_state(name, &state, &patch, &mutations).await?;
+            }
+            
+            if !appstate::patch::has_more_patches(&response, name) {
+                debug!("Fetched app state {} up to version {}", name, state.version);
+                return Ok(state);
+            }
+        }
+    }
+    
+    async fn download_app_state_blob(&self, reference: &ExternalBlobReference) -> Result<Vec<u8>> {
+        let info = appstate::patch::external_blob_media_info(reference);
+        self.media_manager.lock().await.download_media_bytes(&info).await
+    }
+    
+    /// Publish a chat action to our other devices.
+    ///
+    /// Until the primary device shared an app state sync key the action
+    /// only applies locally.
+    async fn publish_chat_action(&self, patch: PatchInfo) -> Result<()> {
+        if SqliteAppStateKeyStore::new(self.database.pool().clone()).get_latest_key().await?.is_none() {
+            warn!("No app state sync key received yet, not publishing {} change to other devices", patch.name);
+            return Ok(());
+        }
+        self.send_app_state_patch(patch).await
+    }
+    
     /// Archive a chat
     pub async fn archive_chat(&self, jid: &JID) -> Result<()> {
         let chat_sync = self.get_chat_metadata_sync().await?;
-        chat_sync.archive_chat(jid).await?;
-        
-        // Trigger sync for chat metadata
-        let _ = self.sync_data_type(AppStateDataType::ChatMetadata).await;
-        
-        Ok(())
+        let last_message = self.get_recent_messages(&jid.to_string(), 1).await.last().map(|message| message.timestamp);
+        self.publish_chat_action(PatchInfo::archive(jid, true, last_message)).await?;
+        chat_sync.archive_chat(jid).await
+    }
+    
+    /// Move a chat out of the archive
+    pub async fn unarchive_chat(&self, jid: &JID) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        self.publish_chat_action(PatchInfo::archive(jid, false, None)).await?;
+        chat_sync.unarchive_chat(jid).await
     }
 
     /// Pin a chat
     pub async fn pin_chat(&self, jid: &JID) -> Result<()> {
         let chat_sync = self.get_chat_metadata_sync().await?;
-        chat_sync.pin_chat(jid).await?;
-        
-        // Trigger sync for chat metadata
-        let _ = self.sync_data_type(AppStateDataType::ChatMetadata).await;
-        
+        self.publish_chat_action(PatchInfo::pin(jid, true)).await?;
+        chat_sync.pin_chat(jid).await
+    }
+    
+    /// Unpin a chat
+    pub async fn unpin_chat(&self, jid: &JID) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        self.publish_chat_action(PatchInfo::pin(jid, false)).await?;
+        chat_sync.unpin_chat(jid).await
+    }
+
+    /// Delete a chat with all its messages on all our devices
+    pub async fn delete_chat(&self, jid: &JID) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        let last_message = self.get_recent_messages(&jid.to_string(), 1).await.last().map(|message| message.timestamp);
+        self.publish_chat_action(PatchInfo::delete_chat(jid, last_message)).await?;
+        self.message_thread_manager.lock().await.clear_thread(&jid.to_string(), false);
+        chat_sync.delete_chat_metadata(jid).await?;
+        Ok(())
+    }
+    
+    /// Delete the messages of a chat but keep the chat, optionally keeping starred messages
+    pub async fn clear_chat(&self, jid: &JID, keep_starred: bool) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        let last_message = self.get_recent_messages(&jid.to_string(), 1).await.last().map(|message| message.timestamp);
+        self.publish_chat_action(PatchInfo::clear_chat(jid, keep_starred, last_message)).await?;
+        self.message_thread_manager.lock().await.clear_thread(&jid.to_string(), keep_starred);
+        chat_sync.clear_chat(jid).await
+    }
+    
+    /// Mark a chat as unread on all our devices
+    pub async fn mark_chat_unread(&self, jid: &JID) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        let last_message = self.get_recent_messages(&jid.to_string(), 1).await.last().map(|message| message.timestamp);
+        self.publish_chat_action(PatchInfo::mark_read(jid, false, last_message)).await?;
+        chat_sync.mark_chat_read(jid, false).await
+    }
+    
+    /// Get our business labels
+    pub async fn list_labels(&self) -> Vec<appstate::Label> {
+        self.labels.read().await.list()
+    }
+    
+    /// Get the labels attached to a chat
+    pub async fn get_chat_labels(&self, chat: &JID) -> Vec<appstate::Label> {
+        self.labels.read().await.chat_labels(chat).into_iter().cloned().collect()
+    }
+    
+    /// Create a label with one of the [`LABEL_COLORS`](appstate::labels::LABEL_COLORS) colors
+    pub async fn create_label(&self, name: String, color: i32) -> Result<appstate::Label> {
+        let label = appstate::Label {
+            id: self.labels.read().await.next_id(),
+            name,
+            color: color.rem_euclid(appstate::labels::LABEL_COLORS),
+            predefined_id: None,
+            order_index: None,
+        };
+        self.edit_label(label.clone()).await?;
+        Ok(label)
+    }
+    
+    /// Rename or recolor a label
+    pub async fn edit_label(&self, label: appstate::Label) -> Result<()> {
+        self.send_app_state_patch(PatchInfo::label_edit(&label, false)).await?;
+        self.process_label_change(appstate::LabelChange::Edited(label)).await;
+        Ok(())
+    }
+    
+    /// Delete a label, detaching it from all chats and messages
+    pub async fn remove_label(&self, label_id: &str) -> Result<()> {
+        let label = self.labels.read().await.get(label_id).cloned()
+            .ok_or_else(|| Error::Protocol(format!("Unknown label {}", label_id)))?;
+        self.send_app_state_patch(PatchInfo::label_edit(&label, true)).await?;
+        self.process_label_change(appstate::LabelChange::Deleted { label_id: label.id }).await;
+        Ok(())
+    }
+    
+    /// Attach a label to a chat
+    pub async fn add_label_to_chat(&self, chat: &JID, label_id: &str) -> Result<()> {
+        self.label_chat(chat, label_id, true).await
+    }
+    
+    /// Remove a label from a chat
+    pub async fn remove_label_from_chat(&self, chat: &JID, label_id: &str) -> Result<()> {
+        self.label_chat(chat, label_id, false).await
+    }
+    
+    async fn label_chat(&self, chat: &JID, label_id: &str, labeled: bool) -> Result<()> {
+        self.send_app_state_patch(PatchInfo::label_chat(label_id, chat, labeled)).await?;
+        self.process_label_change(appstate::LabelChange::Chat {
+            label_id: label_id.to_string(),
+            chat: chat.clone(),
+            labeled,
+        }).await;
+        Ok(())
+    }
+    
+    /// Attach a label to a message, or remove it
+    pub async fn label_message(&self, chat: &JID, message_id: &str, label_id: &str, labeled: bool) -> Result<()> {
+        self.send_app_state_patch(PatchInfo::label_message(label_id, chat, message_id, labeled)).await?;
+        self.process_label_change(appstate::LabelChange::Message {
+            label_id: label_id.to_string(),
+            chat: chat.clone(),
+            message_id: message_id.to_string(),
+            labeled,
+        }).await;
+        Ok(())
+    }
+    
+    /// Apply a label change, made here or on another device, and emit
+    /// [`Event::LabelChanged`] if it changed anything
+    pub async fn process_label_change(&self, change: appstate::LabelChange) {
+        if !self.labels.write().await.apply(&change) {
+            return;
+        }
+        // Keep the label ids on the chat metadata in step
+        if let (appstate::LabelChange::Chat { label_id, chat, labeled }, Ok(chat_sync)) = (&change, self.get_chat_metadata_sync().await) {
+            let result = if *labeled {
+                chat_sync.add_label_to_chat(chat, label_id.clone()).await
+            } else {
+                chat_sync.remove_label_from_chat(chat, label_id).await
+            };
+            if let Err(e) = result {
+                warn!("Failed to update the labels of {}: {}", chat, e);
+            }
+        }
+        self.emit_event(Event::LabelChanged(change)).await;
+    }
+    
+    /// Star or unstar a message
+    ///
+    /// Stars are private: they are only synced to our other devices.
+    pub async fn star_message(&self, message_key: &MessageKey, starred: bool) -> Result<()> {
+        let patch = PatchInfo::star(
+            &message_key.remote_jid,
+            &message_key.id,
+            message_key.from_me,
+            message_key.participant.as_ref(),
+            starred,
+        );
+        self.publish_chat_action(patch).await?;
+        self.apply_star(message_key.clone(), starred, Some(server_time::now())).await;
         Ok(())
     }
+    
+    /// Apply a star or unstar made on another device and emit [`Event::MessageStarred`]
+    pub async fn process_star_mutation(&self, mutation: appstate::StarMutation) {
+        let message_key = MessageKey {
+            remote_jid: mutation.chat,
+            from_me: mutation.from_me,
+            id: mutation.message_id,
+            participant: mutation.sender,
+        };
+        self.apply_star(message_key, mutation.starred, mutation.timestamp).await;
+    }
+    
+    async fn apply_star(&self, message_key: MessageKey, starred: bool, timestamp: Option<std::time::SystemTime>) {
+        self.message_thread_manager.lock().await
+            .set_starred(&message_key.remote_jid.to_string(), &message_key.id, starred);
+        self.emit_event(Event::MessageStarred(MessageStarredEvent { message_key, starred, timestamp })).await;
+    }
+    
+    /// Get the starred messages of a chat, oldest first
+    pub async fn get_starred_messages(&self, chat: &JID) -> Vec<MessageInfo> {
+        let thread_manager = self.message_thread_manager.lock().await;
+        thread_manager.get_starred_messages(&chat.to_string()).into_iter().cloned().collect()
+    }
 
-    /// Mute a chat
+    /// Mute a chat, forever if no duration is given
     pub async fn mute_chat(&self, jid: &JID, duration_seconds: Option<u64>) -> Result<()> {
         let chat_sync = self.get_chat_metadata_sync().await?;
-        chat_sync.mute_chat(jid, duration_seconds).await?;
-        
-        // Trigger sync for chat metadata
-        let _ = self.sync_data_type(AppStateDataType::ChatMetadata).await;
-        
-        Ok(())
+        let duration = duration_seconds.map(std::time::Duration::from_secs);
+        self.publish_chat_action(PatchInfo::mute(jid, true, duration)).await?;
+        chat_sync.mute_chat(jid, duration_seconds).await
+    }
+    
+    /// Unmute a chat
+    pub async fn unmute_chat(&self, jid: &JID) -> Result<()> {
+        let chat_sync = self.get_chat_metadata_sync().await?;
+        self.publish_chat_action(PatchInfo::mute(jid, false, None)).await?;
+        chat_sync.unmute_chat(jid).await
     }
 
     /// Update privacy settings
@@ -882,13 +4240,61 @@ impl Client {
 
     /// Block a contact
     pub async fn block_contact(&self, jid: &JID) -> Result<()> {
-        let contact_sync = self.get_contact_sync().await?;
-        contact_sync.block_contact(jid).await?;
-        
-        // Trigger sync for contacts
-        let _ = self.sync_data_type(AppStateDataType::Contacts).await;
+        self.block(jid).await.map(|_| ())
+    }
+    
+    /// Fetch the full block list from the server
+    pub async fn fetch_blocklist(&self) -> Result<Blocklist> {
+        let response = self.iq_sender.send_iq(blocklist::build_get_query()).await?;
+        self.replace_blocklist(blocklist::parse_blocklist(&response)?).await
+    }
+    
+    /// Get the block list as of the last fetch or server push
+    pub async fn get_blocklist(&self) -> Blocklist {
+        self.blocklist.read().await.clone()
+    }
+    
+    /// Block a user, returning the updated block list
+    pub async fn block(&self, jid: &JID) -> Result<Blocklist> {
+        self.update_blocklist(jid, BlocklistChangeAction::Block).await
+    }
+    
+    /// Unblock a user, returning the updated block list
+    pub async fn unblock(&self, jid: &JID) -> Result<Blocklist> {
+        self.update_blocklist(jid, BlocklistChangeAction::Unblock).await
+    }
+    
+    async fn update_blocklist(&self, jid: &JID, action: BlocklistChangeAction) -> Result<Blocklist> {
+        let response = self.iq_sender.send_iq(blocklist::build_update_query(jid, action)).await?;
+        self.replace_blocklist(blocklist::parse_blocklist(&response)?).await
+    }
+    
+    async fn replace_blocklist(&self, new: Blocklist) -> Result<Blocklist> {
+        let old = std::mem::replace(&mut *self.blocklist.write().await, new.clone());
         
-        Ok(())
+        let changes: Vec<BlocklistChange> = new.jids.difference(&old.jids)
+            .map(|jid| BlocklistChange { jid: jid.clone(), action: BlocklistChangeAction::Block })
+            .chain(old.jids.difference(&new.jids)
+                .map(|jid| BlocklistChange { jid: jid.clone(), action: BlocklistChangeAction::Unblock }))
+            .collect();
+        self.sync_contact_blocks(&changes).await;
+        Ok(new)
+    }
+    
+    /// Mirror block list changes into the app state contacts
+    async fn sync_contact_blocks(&self, changes: &[BlocklistChange]) {
+        let Ok(contact_sync) = self.get_contact_sync().await else {
+            return;
+        };
+        for change in changes {
+            let result = match change.action {
+                BlocklistChangeAction::Block => contact_sync.block_contact(&change.jid).await,
+                BlocklistChangeAction::Unblock => contact_sync.unblock_contact(&change.jid).await,
+            };
+            if let Err(e) = result {
+                warn!("Failed to update blocked flag of {}: {}", change.jid, e);
+            }
+        }
     }
 
     /// Get chat metadata
@@ -934,4 +4340,253 @@ impl ConnectionEventHandler for ClientConnectionEventHandler {
         
         (self.client_event_emitter)(client_event);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{sqlite::SqliteDeviceStore, DatabaseConfig};
+    use crate::msg_transport;
+    use crate::proto::wa_e2e;
+    use crate::signal::{
+        group::MemoryGroupSessionStore,
+        identity::MemoryIdentityKeyStore,
+        prekey::MemoryPreKeyStore,
+        session::{ChainState, MemorySessionStore, SessionState, SessionStore},
+    };
+
+    async fn test_client() -> Client {
+        test_client_with(ClientConfig::default()).await
+    }
+
+    async fn test_client_with(config: ClientConfig) -> Client {
+        let database = Database::new(DatabaseConfig {
+            database_url: "sqlite::memory:".to_string(),
+            max_connections: 5,
+            connection_timeout: 10,
+            enable_wal: false,
+        }).await.unwrap();
+        client_on(Arc::new(database), config).await
+    }
+
+    /// A client on an existing database, e.g. to simulate a restart
+    async fn client_on(database: Arc<Database>, config: ClientConfig) -> Client {
+        let store = Arc::new(SqliteDeviceStore::new(database.pool().clone()));
+        Client::with_config(store, database, config).await.unwrap()
+    }
+
+    /// A Signal manager holding one session with `remote`, set up to either send or receive
+    fn manager_with_session(remote: &JID, sending: bool) -> SignalProtocolManager {
+        let chain = ChainState { chain_key: [7; 32], message_number: 0, ephemeral_public: None };
+        let mut state = SessionState::new([1; 32], [2; 32], [8; 32]);
+        if sending {
+            state.sending_chain_key = Some(chain);
+        } else {
+            state.receiving_chain_key = Some(chain);
+        }
+        let mut sessions = MemorySessionStore::new();
+        sessions.store_session(&remote.signal_address(), state);
+        SignalProtocolManager::new_with_stores(
+            Box::new(MemoryIdentityKeyStore::new(1)),
+            Box::new(sessions),
+            Box::new(MemoryPreKeyStore::new()),
+            Box::new(MemoryGroupSessionStore::new()),
+        )
+    }
+
+    fn collect_events(events: &Arc<std::sync::Mutex<Vec<Event>>>) -> EventHandler {
+        let events = events.clone();
+        Box::new(move |event| {
+            events.lock().unwrap().push(event);
+            true
+        })
+    }
+
+    #[tokio::test]
+    async fn test_client_on_device_store() {
+        let database = Database::new(DatabaseConfig {
+            database_url: "sqlite::memory:".to_string(),
+            max_connections: 5,
+            connection_timeout: 10,
+            enable_wal: false,
+        }).await.unwrap();
+        let device_store: Arc<dyn DeviceStore> = Arc::new(crate::store::MemoryStore::new());
+        let client = Client::new(device_store.clone(), Arc::new(database)).await.unwrap();
+        assert!(!client.is_logged_in());
+
+        let device = JID::new_user("111").with_device(1);
+        client.store.save_device(&DeviceData {
+            jid: device.clone(),
+            registration_id: 1,
+            noise_key: vec![1; 32],
+            identity_key: vec![2; 32],
+            signed_pre_key: Vec::new(),
+            signed_pre_key_id: 1,
+            signed_pre_key_signature: vec![3; 64],
+        }).await.unwrap();
+        assert!(device_store.is_registered().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_receive_node_decrypts_message() {
+        let client = test_client().await;
+        let me = JID::new_user("111").with_device(0);
+        let alice = JID::new_user("222").with_device(1);
+        *client.signal_manager.lock().await = manager_with_session(&alice, false);
+        let mut peer = manager_with_session(&me, true);
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        client.add_event_handler(collect_events(&events)).await;
+
+        let sent = wa_e2e::Message { conversation: Some("hello".to_string()), ..Default::default() };
+        let payload = msg_transport::seal_message(&sent, None);
+        let encrypted = peer.encrypt_message(&me.signal_address(), &payload).unwrap();
+        let stanza = Node::builder("message")
+            .attr("id", "MSG1")
+            .attr("from", &alice)
+            .attr("t", "1700000000")
+            .attr("type", "text")
+            .node(Node::builder("enc").attr("v", "3").attr("type", "msg").bytes(encrypted.serialized).build())
+            .build();
+        client.receive_node(stanza).await;
+
+        let events = events.lock().unwrap();
+        let info = events.iter().find_map(|event| match event {
+            Event::Message(info) => Some(info),
+            _ => None,
+        }).expect("no message event emitted");
+        assert_eq!(info.id, "MSG1");
+        assert_eq!(info.sender, alice);
+        assert_eq!(info.chat, alice.to_non_ad());
+        assert_eq!(info.message_type, crate::types::MessageType::Text);
+        let Some(SendableMessage::Text(text)) = &info.content else {
+            panic!("text content expected, got {:?}", info.content)
+        };
+        assert_eq!(text.text, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_replayed_message_is_acked_but_not_dispatched() {
+        let client = test_client_with(ClientConfig { stanza_log_capacity: 64, ..ClientConfig::default() }).await;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        client.add_event_handler(collect_events(&events)).await;
+
+        let stanza = Node::builder("notification")
+            .attr("id", "N1")
+            .attr("from", "s.whatsapp.net")
+            .attr("type", "unknown_kind")
+            .build();
+        client.receive_node(stanza.clone()).await.unwrap();
+        client.receive_node(stanza).await.unwrap();
+
+        let acks = client.dump_recent_stanzas().into_iter()
+            .filter(|logged| logged.direction == Direction::Sent && logged.stanza.starts_with("<ack") && logged.stanza.contains("N1"))
+            .count();
+        assert_eq!(acks, 2);
+        let unhandled = events.lock().unwrap().iter()
+            .filter(|event| matches!(event, Event::UnhandledNotification(_)))
+            .count();
+        assert_eq!(unhandled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_undecryptable_placeholder_survives_restart() {
+        let database = Arc::new(Database::new(DatabaseConfig {
+            database_url: "sqlite::memory:".to_string(),
+            max_connections: 1,
+            connection_timeout: 10,
+            enable_wal: false,
+        }).await.unwrap());
+        let alice = JID::new_user("222").with_device(1);
+        // No session with alice, so this cannot be decrypted
+        let stanza = Node::builder("message")
+            .attr("id", "MSG1")
+            .attr("from", &alice)
+            .attr("t", "1700000000")
+            .attr("type", "text")
+            .node(Node::builder("enc").attr("v", "3").attr("type", "msg").bytes(vec![1, 2, 3]).build())
+            .build();
+
+        let client = client_on(database.clone(), ClientConfig::default()).await;
+        client.receive_node(stanza.clone()).await.unwrap();
+        client.receive_node(stanza.clone()).await.unwrap();
+        drop(client);
+
+        // The retries continue where they stopped
+        let client = client_on(database.clone(), ClientConfig::default()).await;
+        assert_eq!(client.decrypt_retries.failures(&alice.to_string(), "MSG1"), 2);
+        for _ in 2..decrypt_retry::MAX_DECRYPT_RETRIES {
+            client.receive_node(stanza.clone()).await.unwrap();
+        }
+        assert!(client.get_recent_messages(&alice.to_non_ad().to_string(), 10).await.is_empty());
+        client.receive_node(stanza).await.unwrap();
+        drop(client);
+
+        let client = client_on(database, ClientConfig::default()).await;
+        let messages = client.get_recent_messages(&alice.to_non_ad().to_string(), 10).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, "MSG1");
+        assert_eq!(messages[0].message_type, crate::types::MessageType::Undecryptable);
+    }
+
+    #[tokio::test]
+    async fn test_receive_node_counts_poll_vote() {
+        let client = test_client().await;
+        let me = JID::new_user("111").with_device(0);
+        let alice = JID::new_user("222").with_device(1);
+        *client.signal_manager.lock().await = manager_with_session(&alice, false);
+        let mut peer = manager_with_session(&me, true);
+        let secret = vec![7u8; 32];
+
+        let mut deliver = |id: &str, kind: &str, payload: Vec<u8>| {
+            let encrypted = peer.encrypt_message(&me.signal_address(), &payload).unwrap();
+            Node::builder("message")
+                .attr("id", id)
+                .attr("from", &alice)
+                .attr("t", "1700000000")
+                .attr("type", kind)
+                .node(Node::builder("enc").attr("v", "3").attr("type", "msg").bytes(encrypted.serialized).build())
+                .build()
+        };
+
+        // Alice asks, then votes on her own poll
+        let option = |name: &str| wa_e2e::poll_creation_message::Option { option_name: Some(name.to_string()), ..Default::default() };
+        let poll = wa_e2e::Message {
+            poll_creation_message: Some(Box::new(wa_e2e::PollCreationMessage {
+                name: Some("Lunch?".to_string()),
+                options: vec![option("Pizza"), option("Sushi")],
+                selectable_options_count: Some(1),
+                ..Default::default()
+            })),
+            message_context_info: Some(wa_e2e::MessageContextInfo { message_secret: Some(secret.clone()), ..Default::default() }),
+            ..Default::default()
+        };
+        let poll = deliver("POLL1", "poll", msg_transport::seal_message(&poll, None));
+
+        let encrypted = messaging::encrypt_poll_vote(&["Sushi".to_string()], "POLL1", &alice, &alice, &secret).unwrap();
+        let vote = wa_e2e::Message {
+            poll_update_message: Some(wa_e2e::PollUpdateMessage {
+                poll_creation_message_key: Some(crate::proto::wa_common::MessageKey {
+                    remote_jid: Some(me.to_non_ad().to_string()),
+                    from_me: Some(true),
+                    id: Some("POLL1".to_string()),
+                    participant: None,
+                }),
+                vote: Some(wa_e2e::PollEncValue { enc_payload: Some(encrypted.payload), enc_iv: Some(encrypted.iv) }),
+                sender_timestamp_ms: Some(1_700_000_000_000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let vote = deliver("VOTE1", "pollUpdate", msg_transport::seal_message(&vote, None));
+
+        client.receive_node(poll).await.unwrap();
+        client.receive_node(vote).await.unwrap();
+
+        let key = MessageKey { remote_jid: alice.to_non_ad(), from_me: false, id: "POLL1".to_string(), participant: None };
+        let results = client.get_poll_results(&key).await.expect("poll not tracked");
+        assert_eq!(results.total_voters, 1);
+        assert_eq!(results.options[1].name, "Sushi");
+        assert_eq!(results.options[1].vote_count, 1);
+    }
+}
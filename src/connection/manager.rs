@@ -4,12 +4,19 @@ use super::{
     ConnectionState, ConnectionConfig, ConnectionStats, ConnectionEvent, 
     ConnectionEventHandler, LoggingEventHandler, calculate_backoff_delay, 
     is_recoverable_error,
+    stream_error::{StreamError, StreamErrorAction},
 };
 use crate::{
+    binary::Node,
     error::{Error, Result},
+    metrics,
+    request::{InfoQuery, IqSender},
     socket::NoiseSocket,
+    util::keys::ECKeyPair,
 };
 use std::{
+    future::Future,
+    pin::Pin,
     sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
@@ -19,6 +26,26 @@ use tokio::{
     task::JoinHandle,
 };
 
+/// Opens a connection ready for use: connects the transport, runs the Noise
+/// handshake and restores the login of the paired device
+pub type SessionConnector = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<NoiseSocket>> + Send>> + Send + Sync>;
+
+/// Where the manager puts the authenticated socket, shared with the client
+pub type SessionHandle = Arc<tokio::sync::Mutex<Option<NoiseSocket>>>;
+
+/// Connect `socket` and run the Noise handshake, logging in with
+/// `credentials` (static noise key and login payload) when given
+pub async fn open_session(mut socket: NoiseSocket, credentials: Option<&(ECKeyPair, Vec<u8>)>) -> Result<NoiseSocket> {
+    socket.connect().await?;
+    
+    tracing::info!("Performing Noise protocol handshake...");
+    match credentials {
+        Some((noise_keypair, payload)) => socket.perform_handshake_with(noise_keypair, payload).await?,
+        None => socket.perform_handshake().await?,
+    }
+    Ok(socket)
+}
+
 /// Connection manager that handles automatic reconnection
 pub struct ConnectionManager {
     /// Current connection state
@@ -35,6 +62,17 @@ pub struct ConnectionManager {
     command_sender: Option<mpsc::UnboundedSender<ConnectionCommand>>,
     /// Background task handle
     task_handle: Option<JoinHandle<()>>,
+    /// Sender for keep-alive pings
+    iq_sender: Option<Arc<dyn IqSender>>,
+    /// How new connections are opened and where they are handed over
+    session: SessionContext,
+}
+
+/// How the manager opens connections and where it hands them over
+#[derive(Clone)]
+struct SessionContext {
+    connector: SessionConnector,
+    handle: SessionHandle,
 }
 
 /// Commands for controlling the connection manager
@@ -48,6 +86,8 @@ enum ConnectionCommand {
     Reconnect,
     /// Update configuration
     UpdateConfig(ConnectionConfig),
+    /// React to a stream error sent by the server
+    StreamError(StreamError),
     /// Shutdown the manager
     Shutdown,
 }
@@ -65,6 +105,11 @@ impl ConnectionManager {
             event_sender,
             command_sender: None,
             task_handle: None,
+            iq_sender: None,
+            session: SessionContext {
+                connector: Arc::new(|| Box::pin(async { open_session(NoiseSocket::new().await?, None).await })),
+                handle: Arc::new(tokio::sync::Mutex::new(None)),
+            },
         };
         
         // Add default logging handler
@@ -73,6 +118,29 @@ impl ConnectionManager {
         manager
     }
     
+    /// Use the given sender for keep-alive pings
+    pub fn with_iq_sender(mut self, iq_sender: Arc<dyn IqSender>) -> Self {
+        self.iq_sender = Some(iq_sender);
+        self
+    }
+    
+    /// Open connections with `connector` instead of a fresh unpaired session
+    pub fn with_connector(mut self, connector: SessionConnector) -> Self {
+        self.session.connector = connector;
+        self
+    }
+    
+    /// Hand connected sockets over through `handle`
+    pub fn with_session(mut self, handle: SessionHandle) -> Self {
+        self.session.handle = handle;
+        self
+    }
+    
+    /// The slot holding the current socket, empty while disconnected
+    pub fn session(&self) -> SessionHandle {
+        Arc::clone(&self.session.handle)
+    }
+    
     /// Start the connection manager
     pub async fn start(&mut self) -> Result<()> {
         if self.task_handle.is_some() {
@@ -80,6 +148,10 @@ impl ConnectionManager {
         }
         
         let (command_sender, command_receiver) = mpsc::unbounded_channel();
+        let keepalive = KeepaliveContext {
+            iq_sender: self.iq_sender.clone(),
+            commands: command_sender.clone(),
+        };
         self.command_sender = Some(command_sender);
         
         // Clone necessary data for the background task
@@ -88,6 +160,7 @@ impl ConnectionManager {
         let stats = Arc::clone(&self.stats);
         let event_handlers = Arc::clone(&self.event_handlers);
         let event_sender = self.event_sender.clone();
+        let session = self.session.clone();
         
         // Start background connection management task
         let handle = tokio::spawn(async move {
@@ -98,6 +171,8 @@ impl ConnectionManager {
                 event_handlers,
                 event_sender,
                 command_receiver,
+                keepalive,
+                session,
             ).await;
         });
         
@@ -148,6 +223,16 @@ impl ConnectionManager {
         Ok(())
     }
     
+    /// Close the connection after a server stream error and reconnect,
+    /// wait or stay disconnected depending on the error
+    pub async fn handle_stream_error(&self, error: StreamError) -> Result<()> {
+        if let Some(sender) = &self.command_sender {
+            sender.send(ConnectionCommand::StreamError(error))
+                .map_err(|e| Error::Connection(format!("Failed to send stream error: {}", e)))?;
+        }
+        Ok(())
+    }
+    
     /// Update connection configuration
     pub async fn update_config(&mut self, config: ConnectionConfig) -> Result<()> {
         self.config = config.clone();
@@ -220,8 +305,9 @@ async fn connection_management_task(
     event_handlers: Arc<RwLock<Vec<Box<dyn ConnectionEventHandler>>>>,
     event_sender: broadcast::Sender<ConnectionEvent>,
     mut command_receiver: mpsc::UnboundedReceiver<ConnectionCommand>,
+    keepalive: KeepaliveContext,
+    session: SessionContext,
 ) {
-    let mut current_socket: Option<NoiseSocket> = None;
     let mut keepalive_handle: Option<JoinHandle<()>> = None;
     
     loop {
@@ -237,8 +323,9 @@ async fn connection_management_task(
                                 &stats,
                                 &event_handlers,
                                 &event_sender,
-                                &mut current_socket,
+                                &session,
                                 &mut keepalive_handle,
+                                &keepalive,
                             ).await;
                         }
                     }
@@ -248,7 +335,7 @@ async fn connection_management_task(
                             &stats,
                             &event_handlers,
                             &event_sender,
-                            &mut current_socket,
+                            &session,
                             &mut keepalive_handle,
                         ).await;
                     }
@@ -259,7 +346,7 @@ async fn connection_management_task(
                             &stats,
                             &event_handlers,
                             &event_sender,
-                            &mut current_socket,
+                            &session,
                             &mut keepalive_handle,
                         ).await;
                         
@@ -269,12 +356,68 @@ async fn connection_management_task(
                             &stats,
                             &event_handlers,
                             &event_sender,
-                            &mut current_socket,
+                            &session,
                             &mut keepalive_handle,
+                            &keepalive,
                         ).await;
                     }
                     Some(ConnectionCommand::UpdateConfig(new_config)) => {
+                        let keepalive_changed = new_config.keepalive_interval != config.keepalive_interval
+                            || new_config.keepalive_timeout != config.keepalive_timeout
+                            || new_config.max_missed_keepalives != config.max_missed_keepalives;
                         config = new_config;
+                        
+                        // Restart pinging with the new settings, keeping the connection
+                        if keepalive_changed {
+                            if let Some(handle) = keepalive_handle.take() {
+                                handle.abort();
+                                keepalive_handle = Some(start_keepalive_task(
+                                    &config,
+                                    Arc::clone(&stats),
+                                    event_sender.clone(),
+                                    keepalive.clone(),
+                                ));
+                            }
+                        }
+                    }
+                    Some(ConnectionCommand::StreamError(error)) => {
+                        disconnect(
+                            &state,
+                            &stats,
+                            &event_handlers,
+                            &event_sender,
+                            &session,
+                            &mut keepalive_handle,
+                        ).await;
+                        
+                        let action = error.action(std::time::SystemTime::now());
+                        let event = ConnectionEvent::StreamError(error.clone());
+                        broadcast_event(&event_handlers, &event_sender, event).await;
+                        
+                        match action {
+                            StreamErrorAction::Reconnect => {
+                                attempt_connection(
+                                    &state,
+                                    &config,
+                                    &stats,
+                                    &event_handlers,
+                                    &event_sender,
+                                    &session,
+                                    &mut keepalive_handle,
+                                    &keepalive,
+                                ).await;
+                            }
+                            StreamErrorAction::ReconnectAfter(delay) => {
+                                *state.write().await = ConnectionState::Waiting {
+                                    until: Instant::now() + delay,
+                                };
+                            }
+                            StreamErrorAction::ClearCredentials | StreamErrorAction::Stop => {
+                                *state.write().await = ConnectionState::Failed {
+                                    reason: error.to_string(),
+                                };
+                            }
+                        }
                     }
                     Some(ConnectionCommand::Shutdown) | None => {
                         disconnect(
@@ -282,7 +425,7 @@ async fn connection_management_task(
                             &stats,
                             &event_handlers,
                             &event_sender,
-                            &mut current_socket,
+                            &session,
                             &mut keepalive_handle,
                         ).await;
                         break;
@@ -312,8 +455,9 @@ async fn connection_management_task(
                                     &stats,
                                     &event_handlers,
                                     &event_sender,
-                                    &mut current_socket,
+                                    &session,
                                     &mut keepalive_handle,
+                                    &keepalive,
                                     attempt + 1,
                                 ).await;
                             }
@@ -327,6 +471,18 @@ async fn connection_management_task(
                             broadcast_event(&event_handlers, &event_sender, event).await;
                         }
                     }
+                    ConnectionState::Waiting { until } if Instant::now() >= until => {
+                        attempt_connection(
+                            &state,
+                            &config,
+                            &stats,
+                            &event_handlers,
+                            &event_sender,
+                            &session,
+                            &mut keepalive_handle,
+                            &keepalive,
+                        ).await;
+                    }
                     _ => {}
                 }
             }
@@ -343,22 +499,25 @@ async fn attempt_connection(
     stats: &Arc<Mutex<ConnectionStats>>,
     event_handlers: &Arc<RwLock<Vec<Box<dyn ConnectionEventHandler>>>>,
     event_sender: &broadcast::Sender<ConnectionEvent>,
-    current_socket: &mut Option<NoiseSocket>,
+    session: &SessionContext,
     keepalive_handle: &mut Option<JoinHandle<()>>,
+    keepalive: &KeepaliveContext,
 ) {
     *state.write().await = ConnectionState::Connecting;
     stats.lock().unwrap().record_attempt();
     
-    match timeout(config.connection_timeout, connect_to_whatsapp()).await {
+    match timeout(config.connection_timeout, (session.connector)()).await {
         Ok(Ok(socket)) => {
-            *current_socket = Some(socket);
+            *session.handle.lock().await = Some(socket);
             *state.write().await = ConnectionState::Connected;
             stats.lock().unwrap().record_success();
             
             // Start keep-alive task
             *keepalive_handle = Some(start_keepalive_task(
-                config.keepalive_interval,
+                config,
+                Arc::clone(stats),
                 event_sender.clone(),
+                keepalive.clone(),
             ));
             
             let event = ConnectionEvent::Connected;
@@ -404,25 +563,29 @@ async fn attempt_reconnection(
     stats: &Arc<Mutex<ConnectionStats>>,
     event_handlers: &Arc<RwLock<Vec<Box<dyn ConnectionEventHandler>>>>,
     event_sender: &broadcast::Sender<ConnectionEvent>,
-    current_socket: &mut Option<NoiseSocket>,
+    session: &SessionContext,
     keepalive_handle: &mut Option<JoinHandle<()>>,
+    keepalive: &KeepaliveContext,
     attempt: u32,
 ) {
     let event = ConnectionEvent::ReconnectAttempt { attempt };
     broadcast_event(event_handlers, event_sender, event).await;
+    metrics::reconnect();
     
     stats.lock().unwrap().record_attempt();
     
-    match timeout(config.connection_timeout, connect_to_whatsapp()).await {
+    match timeout(config.connection_timeout, (session.connector)()).await {
         Ok(Ok(socket)) => {
-            *current_socket = Some(socket);
+            *session.handle.lock().await = Some(socket);
             *state.write().await = ConnectionState::Connected;
             stats.lock().unwrap().record_success();
             
             // Start keep-alive task
             *keepalive_handle = Some(start_keepalive_task(
-                config.keepalive_interval,
+                config,
+                Arc::clone(stats),
                 event_sender.clone(),
+                keepalive.clone(),
             ));
             
             let event = ConnectionEvent::Reconnected;
@@ -465,7 +628,7 @@ async fn disconnect(
     stats: &Arc<Mutex<ConnectionStats>>,
     event_handlers: &Arc<RwLock<Vec<Box<dyn ConnectionEventHandler>>>>,
     event_sender: &broadcast::Sender<ConnectionEvent>,
-    current_socket: &mut Option<NoiseSocket>,
+    session: &SessionContext,
     keepalive_handle: &mut Option<JoinHandle<()>>,
 ) {
     // Stop keep-alive task
@@ -474,9 +637,11 @@ async fn disconnect(
     }
     
     // Close socket
-    if let Some(socket) = current_socket.take() {
-        // TODO: Gracefully close the socket
-        drop(socket);
+    let socket = session.handle.lock().await.take();
+    if let Some(socket) = socket {
+        if let Err(e) = socket.close().await {
+            tracing::debug!("Failed to close socket: {}", e);
+        }
     }
     
     *state.write().await = ConnectionState::Disconnected;
@@ -488,47 +653,68 @@ async fn disconnect(
     broadcast_event(event_handlers, event_sender, event).await;
 }
 
-/// Placeholder for actual WhatsApp connection logic
-async fn connect_to_whatsapp() -> Result<NoiseSocket> {
-    // TODO: Implement actual connection logic
-    // This would involve:
-    // 1. Creating WebSocket connection to WhatsApp servers
-    // 2. Performing Noise protocol handshake
-    // 3. Authenticating with stored credentials
-    
-    // For now, simulate connection delay and potential failure
-    sleep(Duration::from_millis(500)).await;
-    
-    // Simulate occasional connection failures for testing
-    use rand::Rng;
-    if rand::thread_rng().gen_bool(0.1) {
-        return Err(Error::Connection("Simulated connection failure".to_string()));
-    }
-    
-    // Return a placeholder socket
-    Ok(NoiseSocket::new().await?)
+/// What the keep-alive task needs to ping the server and force a reconnect
+#[derive(Clone)]
+struct KeepaliveContext {
+    iq_sender: Option<Arc<dyn IqSender>>,
+    commands: mpsc::UnboundedSender<ConnectionCommand>,
+}
+
+/// Build the `w:p` ping query
+fn keepalive_query(timeout: Duration) -> InfoQuery {
+    InfoQuery::get("w:p")
+        .content(vec![Node::new("ping".to_string())])
+        .timeout(timeout)
 }
 
-/// Start keep-alive task
+/// Start keep-alive task.
+///
+/// Pings the server every `keepalive_interval` and records the round-trip
+/// time. After `max_missed_keepalives` consecutive unanswered pings the
+/// connection is considered dead and a reconnect is forced.
 fn start_keepalive_task(
-    interval: Duration,
+    config: &ConnectionConfig,
+    stats: Arc<Mutex<ConnectionStats>>,
     event_sender: broadcast::Sender<ConnectionEvent>,
+    keepalive: KeepaliveContext,
 ) -> JoinHandle<()> {
+    let interval = config.keepalive_interval;
+    let response_timeout = config.keepalive_timeout;
+    let max_missed = config.max_missed_keepalives;
+    
     tokio::spawn(async move {
+        let Some(iq_sender) = keepalive.iq_sender else {
+            tracing::debug!("No IQ sender configured, keep-alive pings disabled");
+            return;
+        };
+        
         let mut interval_timer = tokio::time::interval(interval);
+        // The first tick completes immediately, the connection was just established
+        interval_timer.tick().await;
         
         loop {
             interval_timer.tick().await;
             
-            // Send keep-alive ping
             let _ = event_sender.send(ConnectionEvent::KeepAlivePing);
+            let sent_at = Instant::now();
             
-            // TODO: Implement actual keep-alive logic
-            // This would involve sending a ping message and waiting for pong
-            
-            // Simulate pong response
-            tokio::time::sleep(Duration::from_millis(100)).await;
-            let _ = event_sender.send(ConnectionEvent::KeepAlivePong);
+            match iq_sender.send_iq(keepalive_query(response_timeout)).await {
+                Ok(_) => {
+                    let rtt = sent_at.elapsed();
+                    stats.lock().unwrap().record_keepalive(rtt);
+                    let _ = event_sender.send(ConnectionEvent::KeepAlivePong { rtt });
+                }
+                Err(e) => {
+                    let missed = stats.lock().unwrap().record_missed_keepalive();
+                    tracing::debug!("Keep-alive ping failed ({} in a row): {}", missed, e);
+                    
+                    if missed >= max_missed {
+                        let _ = event_sender.send(ConnectionEvent::KeepAliveTimeout { missed });
+                        let _ = keepalive.commands.send(ConnectionCommand::Reconnect);
+                        break;
+                    }
+                }
+            }
         }
     })
 }
@@ -568,6 +754,42 @@ mod tests {
         assert!(manager.task_handle.is_none());
     }
     
+    #[tokio::test]
+    async fn test_manager_hands_over_sessions() {
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let connector: SessionConnector = Arc::new({
+            let opened = Arc::clone(&opened);
+            move || {
+                let (transport, server_rx, server_tx) = crate::socket::transport::ChannelTransport::pair();
+                opened.lock().unwrap().push((server_rx, server_tx));
+                Box::pin(async move {
+                    let mut socket = NoiseSocket::with_transport(Box::new(transport));
+                    socket.connect_with_url("ws://localhost/ws").await?;
+                    Ok(socket)
+                })
+            }
+        });
+        let session: SessionHandle = Arc::new(tokio::sync::Mutex::new(None));
+        let mut manager = ConnectionManager::new(ConnectionConfig::default())
+            .with_connector(connector)
+            .with_session(Arc::clone(&session));
+        manager.start().await.unwrap();
+        
+        manager.connect().await.unwrap();
+        manager.wait_for_connection(Duration::from_secs(1)).await.unwrap();
+        assert!(session.lock().await.as_ref().unwrap().is_connected());
+        
+        // A reconnect replaces the socket with a new session
+        manager.reconnect().await.unwrap();
+        while opened.lock().unwrap().len() < 2 || !manager.is_connected().await {
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(session.lock().await.is_some());
+        
+        manager.stop().await.unwrap();
+        assert!(session.lock().await.is_none());
+    }
+    
     #[tokio::test]
     async fn test_connection_stats_tracking() {
         let config = ConnectionConfig::default();
@@ -579,6 +801,44 @@ mod tests {
         assert_eq!(initial_stats.failed_connections, 0);
     }
     
+    #[tokio::test]
+    async fn test_keepalive_forces_reconnect() {
+        let config = ConnectionConfig {
+            keepalive_interval: Duration::from_millis(10),
+            max_missed_keepalives: 2,
+            ..ConnectionConfig::default()
+        };
+        let error = Node::new("iq".to_string())
+            .attr("type".to_string(), "error".to_string());
+        let iq_sender = Arc::new(crate::request::StaticIqSender::new(error));
+        let stats = Arc::new(Mutex::new(ConnectionStats::default()));
+        let (event_sender, mut events) = broadcast::channel(16);
+        let (commands, mut command_receiver) = mpsc::unbounded_channel();
+        
+        let handle = start_keepalive_task(
+            &config,
+            Arc::clone(&stats),
+            event_sender,
+            KeepaliveContext { iq_sender: Some(iq_sender.clone()), commands },
+        );
+        handle.await.unwrap();
+        
+        assert!(matches!(command_receiver.try_recv(), Ok(ConnectionCommand::Reconnect)));
+        assert_eq!(stats.lock().unwrap().missed_keepalives, 2);
+        {
+            let queries = iq_sender.queries.lock().unwrap();
+            assert_eq!(queries.len(), 2);
+            assert_eq!(queries[0].namespace, "w:p");
+            assert_eq!(queries[0].content[0].tag, "ping");
+        }
+        
+        let mut timed_out = false;
+        while let Ok(event) = events.try_recv() {
+            timed_out |= matches!(event, ConnectionEvent::KeepAliveTimeout { missed: 2 });
+        }
+        assert!(timed_out);
+    }
+    
     #[tokio::test]
     async fn test_event_subscription() {
         let config = ConnectionConfig::default();
@@ -3,8 +3,10 @@
 pub mod manager;
 pub mod retry;
 pub mod rate_limit;
+pub mod stream_error;
 
 use crate::error::Error;
+use stream_error::StreamError;
 use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
 
@@ -19,6 +21,8 @@ pub enum ConnectionState {
     Connected,
     /// Connection lost, attempting to reconnect
     Reconnecting { attempt: u32, last_attempt: Instant },
+    /// Told by the server to stay away until the given time
+    Waiting { until: Instant },
     /// Failed to connect after max retries
     Failed { reason: String },
 }
@@ -38,6 +42,10 @@ pub struct ConnectionConfig {
     pub connection_timeout: Duration,
     /// Keep-alive interval
     pub keepalive_interval: Duration,
+    /// Time to wait for the response to a keep-alive ping
+    pub keepalive_timeout: Duration,
+    /// Consecutive unanswered pings after which the connection is considered dead
+    pub max_missed_keepalives: u32,
     /// Max idle time before considering connection stale
     pub max_idle_time: Duration,
 }
@@ -51,6 +59,8 @@ impl Default for ConnectionConfig {
             backoff_multiplier: 2.0,
             connection_timeout: Duration::from_secs(30),
             keepalive_interval: Duration::from_secs(30),
+            keepalive_timeout: Duration::from_secs(10),
+            max_missed_keepalives: 3,
             max_idle_time: Duration::from_secs(300), // 5 minutes
         }
     }
@@ -75,6 +85,16 @@ pub struct ConnectionStats {
     pub total_uptime: Duration,
     /// Average connection duration
     pub average_connection_duration: Duration,
+    /// Round-trip time of the last answered keep-alive ping
+    pub last_keepalive_rtt: Option<Duration>,
+    /// Moving average of keep-alive round-trip times
+    pub average_keepalive_rtt: Option<Duration>,
+    /// Keep-alive pings left unanswered since the last answered one
+    pub missed_keepalives: u32,
+    /// Messages delivered again after a reconnect and dropped as duplicates
+    pub duplicate_messages: u64,
+    /// Stanzas the server delivered from the offline queue after the last connect
+    pub offline_stanzas: u32,
 }
 
 impl ConnectionStats {
@@ -87,6 +107,24 @@ impl ConnectionStats {
     pub fn record_success(&mut self) {
         self.successful_connections += 1;
         self.last_connection_time = Some(Instant::now());
+        self.missed_keepalives = 0;
+    }
+    
+    /// Record an answered keep-alive ping
+    pub fn record_keepalive(&mut self, rtt: Duration) {
+        self.last_keepalive_rtt = Some(rtt);
+        self.average_keepalive_rtt = Some(match self.average_keepalive_rtt {
+            // Exponential moving average weighting the newest sample by 1/8
+            Some(average) => (average * 7 + rtt) / 8,
+            None => rtt,
+        });
+        self.missed_keepalives = 0;
+    }
+    
+    /// Record an unanswered keep-alive ping, returning the number of consecutive misses
+    pub fn record_missed_keepalive(&mut self) -> u32 {
+        self.missed_keepalives += 1;
+        self.missed_keepalives
     }
     
     /// Record a failed connection
@@ -147,11 +185,15 @@ pub enum ConnectionEvent {
     /// Keep-alive ping sent
     KeepAlivePing,
     /// Keep-alive pong received
-    KeepAlivePong,
+    KeepAlivePong { rtt: Duration },
+    /// Too many keep-alive pings went unanswered, the connection is considered dead
+    KeepAliveTimeout { missed: u32 },
     /// Connection timeout
     Timeout,
     /// Rate limit hit
     RateLimited { retry_after: Duration },
+    /// The server ended the session
+    StreamError(StreamError),
 }
 
 /// Connection event handler trait
@@ -187,8 +229,11 @@ impl ConnectionEventHandler for LoggingEventHandler {
             ConnectionEvent::KeepAlivePing => {
                 tracing::debug!("Sent keep-alive ping");
             }
-            ConnectionEvent::KeepAlivePong => {
-                tracing::debug!("Received keep-alive pong");
+            ConnectionEvent::KeepAlivePong { rtt } => {
+                tracing::debug!("Received keep-alive pong after {:?}", rtt);
+            }
+            ConnectionEvent::KeepAliveTimeout { missed } => {
+                tracing::warn!("{} keep-alive pings went unanswered, connection is dead", missed);
             }
             ConnectionEvent::Timeout => {
                 tracing::warn!("Connection timeout");
@@ -196,6 +241,9 @@ impl ConnectionEventHandler for LoggingEventHandler {
             ConnectionEvent::RateLimited { retry_after } => {
                 tracing::warn!("Rate limited, retry after {:?}", retry_after);
             }
+            ConnectionEvent::StreamError(error) => {
+                tracing::warn!("Server ended the session: {}", error);
+            }
         }
     }
 }
@@ -240,8 +288,12 @@ pub fn is_recoverable_error(error: &Error) -> bool {
         // Other errors
         Error::NotLoggedIn => false,
         Error::InvalidJID(_) => false,
-        Error::IQ { .. } => false,
+        Error::Iq { .. } => false,
+        Error::NotInGroup => false,
+        Error::MediaSizeExceeded { .. } | Error::MediaExpired => false,
+        Error::RateLimited { .. } => false,
         Error::Database(_) => false,
+        Error::SendBlocked(_) | Error::PassiveMode => false,
         
         // JSON/Protobuf errors usually indicate a bug
         Error::Json(_) => false,
@@ -280,6 +332,26 @@ mod tests {
         assert!(!stats.is_connected());
     }
     
+    #[test]
+    fn test_keepalive_stats() {
+        let mut stats = ConnectionStats::default();
+        assert_eq!(stats.record_missed_keepalive(), 1);
+        assert_eq!(stats.record_missed_keepalive(), 2);
+        
+        stats.record_keepalive(Duration::from_millis(80));
+        assert_eq!(stats.missed_keepalives, 0);
+        assert_eq!(stats.last_keepalive_rtt, Some(Duration::from_millis(80)));
+        assert_eq!(stats.average_keepalive_rtt, Some(Duration::from_millis(80)));
+        
+        stats.record_keepalive(Duration::from_millis(160));
+        assert_eq!(stats.last_keepalive_rtt, Some(Duration::from_millis(160)));
+        assert_eq!(stats.average_keepalive_rtt, Some(Duration::from_millis(90)));
+        
+        stats.record_missed_keepalive();
+        stats.record_success();
+        assert_eq!(stats.missed_keepalives, 0);
+    }
+    
     #[test]
     fn test_backoff_calculation() {
         let initial = Duration::from_secs(1);
@@ -8,6 +8,27 @@ use std::{
 use tokio::sync::Mutex;
 use serde::{Serialize, Deserialize};
 
+/// Messages sent in total
+pub const MESSAGES: &str = "messages";
+/// Messages sent to a single chat, limited per chat
+pub const CHAT_MESSAGES: &str = "chat_messages";
+/// Group management queries
+pub const GROUPS: &str = "groups";
+/// Media uploads
+pub const MEDIA: &str = "media";
+/// Presence and chat state updates
+pub const PRESENCE: &str = "presence";
+/// Contact and status queries
+pub const CONTACTS: &str = "contacts";
+/// usync queries, i.e. device lists and registration checks
+pub const USYNC: &str = "usync";
+
+/// Categories limited separately for every key, e.g. every chat
+const PER_KEY_CATEGORIES: &[&str] = &[CHAT_MESSAGES];
+
+/// Per-key limiters kept before the idle ones are dropped
+const MAX_KEYED_LIMITERS: usize = 4096;
+
 /// Rate limiter configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitConfig {
@@ -33,9 +54,46 @@ impl Default for RateLimitConfig {
 }
 
 /// Pre-configured rate limits for different WhatsApp operations
+///
+/// The values stay below what the server tolerates before it answers with
+/// `429` or flags the account; bots sending in bulk are the usual reason for
+/// bans, so they err on the slow side.
 pub struct WhatsAppRateLimits;
 
 impl WhatsAppRateLimits {
+    /// Default limits of every category
+    pub fn defaults() -> Vec<(&'static str, RateLimitConfig)> {
+        vec![
+            (MESSAGES, Self::message_sending()),
+            (CHAT_MESSAGES, Self::chat_messages()),
+            (GROUPS, Self::group_operations()),
+            (MEDIA, Self::media_upload()),
+            (PRESENCE, Self::presence_updates()),
+            (CONTACTS, Self::contact_queries()),
+            (USYNC, Self::usync_queries()),
+        ]
+    }
+    
+    /// Rate limit for messages to a single chat, about one per second
+    pub fn chat_messages() -> RateLimitConfig {
+        RateLimitConfig {
+            max_requests: 1,
+            window_duration: Duration::from_secs(1),
+            sliding_window: true,
+            burst_allowance: 3,
+        }
+    }
+    
+    /// Rate limit for usync queries
+    pub fn usync_queries() -> RateLimitConfig {
+        RateLimitConfig {
+            max_requests: 20,
+            window_duration: Duration::from_secs(60),
+            sliding_window: true,
+            burst_allowance: 5,
+        }
+    }
+    
     /// Rate limit for sending messages
     pub fn message_sending() -> RateLimitConfig {
         RateLimitConfig {
@@ -237,6 +295,17 @@ impl RateLimiter {
         }
     }
     
+    /// Configuration of this limiter
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+    
+    /// Whether no request was made within the last window
+    async fn is_idle(&self, now: Instant) -> bool {
+        let requests = self.requests.lock().await;
+        requests.last().map_or(true, |&last| now - last >= self.config.window_duration)
+    }
+    
     /// Reset rate limiter state
     pub async fn reset(&self) {
         let mut requests = self.requests.lock().await;
@@ -280,38 +349,101 @@ pub struct RateLimitStatus {
 }
 
 /// Multi-category rate limiter for different operation types
+///
+/// Categories in [`PER_KEY_CATEGORIES`] get a limiter per key, e.g. per
+/// chat, created from the category's configuration on first use. Limits can
+/// be changed at runtime with [`MultiRateLimiter::configure`].
 pub struct MultiRateLimiter {
-    limiters: HashMap<String, RateLimiter>,
+    limiters: std::sync::RwLock<HashMap<String, Arc<RateLimiter>>>,
+    per_key: std::sync::RwLock<HashMap<String, RateLimitConfig>>,
+    keyed: std::sync::Mutex<HashMap<(String, String), Arc<RateLimiter>>>,
 }
 
 impl MultiRateLimiter {
     /// Create a new multi-category rate limiter
     pub fn new() -> Self {
-        let mut limiters = HashMap::new();
+        let limiter = Self {
+            limiters: std::sync::RwLock::new(HashMap::new()),
+            per_key: std::sync::RwLock::new(HashMap::new()),
+            keyed: std::sync::Mutex::new(HashMap::new()),
+        };
         
         // Add default WhatsApp rate limiters
-        limiters.insert("messages".to_string(), 
-                        RateLimiter::new(WhatsAppRateLimits::message_sending()));
-        limiters.insert("groups".to_string(), 
-                        RateLimiter::new(WhatsAppRateLimits::group_operations()));
-        limiters.insert("media".to_string(), 
-                        RateLimiter::new(WhatsAppRateLimits::media_upload()));
-        limiters.insert("presence".to_string(), 
-                        RateLimiter::new(WhatsAppRateLimits::presence_updates()));
-        limiters.insert("contacts".to_string(), 
-                        RateLimiter::new(WhatsAppRateLimits::contact_queries()));
+        for (category, config) in WhatsAppRateLimits::defaults() {
+            limiter.configure(category, config);
+        }
         
-        Self { limiters }
+        limiter
     }
     
     /// Add a custom rate limiter
     pub fn add_limiter(&mut self, category: String, limiter: RateLimiter) {
-        self.limiters.insert(category, limiter);
+        self.limiters.get_mut().unwrap().insert(category, Arc::new(limiter));
+    }
+    
+    /// Set the limits of a category, replacing its limiters and their state
+    pub fn configure(&self, category: &str, config: RateLimitConfig) {
+        if PER_KEY_CATEGORIES.contains(&category) {
+            self.per_key.write().unwrap().insert(category.to_string(), config);
+            self.keyed.lock().unwrap().retain(|(keyed_category, _), _| keyed_category != category);
+        } else {
+            self.limiters.write().unwrap().insert(category.to_string(), Arc::new(RateLimiter::new(config)));
+        }
+    }
+    
+    /// Current limits of a category
+    pub fn config(&self, category: &str) -> Option<RateLimitConfig> {
+        if let Some(config) = self.per_key.read().unwrap().get(category) {
+            return Some(config.clone());
+        }
+        self.limiters.read().unwrap().get(category).map(|limiter| limiter.config().clone())
+    }
+    
+    fn limiter(&self, category: &str) -> Option<Arc<RateLimiter>> {
+        self.limiters.read().unwrap().get(category).cloned()
+    }
+    
+    async fn keyed_limiter(&self, category: &str, key: &str) -> Option<Arc<RateLimiter>> {
+        let config = self.per_key.read().unwrap().get(category).cloned()?;
+        let id = (category.to_string(), key.to_string());
+        if let Some(limiter) = self.keyed.lock().unwrap().get(&id) {
+            return Some(limiter.clone());
+        }
+        
+        let full = self.keyed.lock().unwrap().len() >= MAX_KEYED_LIMITERS;
+        if full {
+            let limiters: Vec<_> = self.keyed.lock().unwrap().iter().map(|(id, l)| (id.clone(), l.clone())).collect();
+            let now = Instant::now();
+            let mut idle = Vec::new();
+            for (id, limiter) in limiters {
+                if limiter.is_idle(now).await {
+                    idle.push(id);
+                }
+            }
+            let mut keyed = self.keyed.lock().unwrap();
+            for id in idle {
+                keyed.remove(&id);
+            }
+        }
+        
+        Some(self.keyed.lock().unwrap()
+            .entry(id)
+            .or_insert_with(|| Arc::new(RateLimiter::new(config)))
+            .clone())
     }
     
     /// Check rate limit for a category
     pub async fn check_rate_limit(&self, category: &str) -> RateLimitResult {
-        if let Some(limiter) = self.limiters.get(category) {
+        if let Some(limiter) = self.limiter(category) {
+            limiter.check_rate_limit().await
+        } else {
+            RateLimitResult::Allowed
+        }
+    }
+    
+    /// Check rate limit for one key of a per-key category
+    pub async fn check_rate_limit_for(&self, category: &str, key: &str) -> RateLimitResult {
+        if let Some(limiter) = self.keyed_limiter(category, key).await {
             limiter.check_rate_limit().await
         } else {
             RateLimitResult::Allowed
@@ -320,7 +452,16 @@ impl MultiRateLimiter {
     
     /// Wait for rate limit to allow request in category
     pub async fn wait_for_rate_limit(&self, category: &str) -> RateLimitResult {
-        if let Some(limiter) = self.limiters.get(category) {
+        if let Some(limiter) = self.limiter(category) {
+            limiter.wait_for_rate_limit().await
+        } else {
+            RateLimitResult::Allowed
+        }
+    }
+    
+    /// Wait for rate limit to allow request for one key of a per-key category
+    pub async fn wait_for_rate_limit_for(&self, category: &str, key: &str) -> RateLimitResult {
+        if let Some(limiter) = self.keyed_limiter(category, key).await {
             limiter.wait_for_rate_limit().await
         } else {
             RateLimitResult::Allowed
@@ -331,8 +472,12 @@ impl MultiRateLimiter {
     pub async fn get_all_status(&self) -> HashMap<String, RateLimitStatus> {
         let mut status_map = HashMap::new();
         
-        for (category, limiter) in &self.limiters {
-            status_map.insert(category.clone(), limiter.get_status().await);
+        let limiters: Vec<_> = self.limiters.read().unwrap()
+            .iter()
+            .map(|(category, limiter)| (category.clone(), limiter.clone()))
+            .collect();
+        for (category, limiter) in limiters {
+            status_map.insert(category, limiter.get_status().await);
         }
         
         status_map
@@ -340,9 +485,11 @@ impl MultiRateLimiter {
     
     /// Reset all rate limiters
     pub async fn reset_all(&self) {
-        for limiter in self.limiters.values() {
+        let limiters: Vec<_> = self.limiters.read().unwrap().values().cloned().collect();
+        for limiter in limiters {
             limiter.reset().await;
         }
+        self.keyed.lock().unwrap().clear();
     }
 }
 
@@ -463,6 +610,30 @@ mod tests {
         assert_eq!(status.burst_tokens_available, 1);
     }
     
+    #[tokio::test]
+    async fn test_per_chat_rate_limit() {
+        let multi_limiter = MultiRateLimiter::new();
+        multi_limiter.configure(CHAT_MESSAGES, RateLimitConfig {
+            max_requests: 1,
+            window_duration: Duration::from_secs(10),
+            sliding_window: true,
+            burst_allowance: 0,
+        });
+        
+        assert!(matches!(multi_limiter.check_rate_limit_for(CHAT_MESSAGES, "a@s.whatsapp.net").await, RateLimitResult::Allowed));
+        assert!(matches!(multi_limiter.check_rate_limit_for(CHAT_MESSAGES, "a@s.whatsapp.net").await, RateLimitResult::Limited { .. }));
+        // Other chats have their own budget
+        assert!(matches!(multi_limiter.check_rate_limit_for(CHAT_MESSAGES, "b@s.whatsapp.net").await, RateLimitResult::Allowed));
+        
+        // Reconfiguring starts over with the new limits
+        multi_limiter.configure(CHAT_MESSAGES, WhatsAppRateLimits::chat_messages());
+        assert!(matches!(multi_limiter.check_rate_limit_for(CHAT_MESSAGES, "a@s.whatsapp.net").await, RateLimitResult::Allowed));
+        assert_eq!(multi_limiter.config(CHAT_MESSAGES).unwrap().max_requests, 1);
+        
+        multi_limiter.configure(USYNC, RateLimitConfig { max_requests: 0, burst_allowance: 0, ..Default::default() });
+        assert!(matches!(multi_limiter.check_rate_limit(USYNC).await, RateLimitResult::Limited { .. }));
+    }
+    
     #[test]
     fn test_whatsapp_rate_limits() {
         // Test that predefined rate limits are reasonable
@@ -1,10 +1,61 @@
 /// Retry mechanisms with exponential backoff and jitter
 
 use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use serde::{Serialize, Deserialize};
 
+/// Kind of failure, each kind can back off differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorClass {
+    /// Connection drops, timeouts and I/O errors
+    Network,
+    /// The server answered with a 5xx error such as 503
+    ServerOverload,
+    /// Too many requests
+    RateLimited,
+    /// Everything else
+    Other,
+}
+
+impl ErrorClass {
+    /// Classify an error
+    pub fn of(error: &Error) -> Self {
+        match error {
+            Error::WebSocket(_) | Error::Connection(_) | Error::Disconnected(_) | Error::Io(_) => ErrorClass::Network,
+            Error::Iq { code: 408, .. } => ErrorClass::Network,
+            Error::Iq { code: 429, .. } | Error::RateLimited { .. } => ErrorClass::RateLimited,
+            Error::Iq { code: 500..=599, .. } => ErrorClass::ServerOverload,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// How random jitter is added to backoff delays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JitterStrategy {
+    /// Up to `jitter_factor` of the delay is added on top
+    #[default]
+    Proportional,
+    /// Anywhere between zero and the delay
+    Full,
+    /// Half the delay plus up to the other half
+    Equal,
+    /// Between the initial delay and three times the previous delay
+    Decorrelated,
+}
+
+/// Limit on retries across all operations of an executor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryBudgetConfig {
+    /// Retries allowed per window
+    pub max_retries: u32,
+    /// Length of the window
+    pub window: Duration,
+}
+
 /// Retry policy configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RetryPolicy {
@@ -20,6 +71,15 @@ pub struct RetryPolicy {
     pub jitter_factor: f64,
     /// Timeout for each individual attempt
     pub attempt_timeout: Option<Duration>,
+    /// How jitter is applied to the delays
+    #[serde(default)]
+    pub jitter: JitterStrategy,
+    /// Backoff used instead of this one for some error classes
+    #[serde(default)]
+    pub class_policies: HashMap<ErrorClass, RetryPolicy>,
+    /// Cap on retries shared by all operations, `None` for no cap
+    #[serde(default)]
+    pub budget: Option<RetryBudgetConfig>,
 }
 
 impl Default for RetryPolicy {
@@ -31,13 +91,34 @@ impl Default for RetryPolicy {
             backoff_multiplier: 2.0,
             jitter_factor: 0.1,
             attempt_timeout: Some(Duration::from_secs(10)),
+            jitter: JitterStrategy::Proportional,
+            class_policies: HashMap::new(),
+            budget: None,
         }
     }
 }
 
 impl RetryPolicy {
-    /// Create a policy for network operations
+    /// Create a policy for network operations.
+    ///
+    /// Overloaded servers get longer, fully jittered delays so clients don't
+    /// come back in lockstep, rate limits wait longer still. At most 30
+    /// retries a minute are made in total.
     pub fn network_operations() -> Self {
+        let mut class_policies = HashMap::new();
+        class_policies.insert(ErrorClass::ServerOverload, Self {
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(120),
+            jitter: JitterStrategy::Full,
+            ..Self::default()
+        }.with_max_attempts(5));
+        class_policies.insert(ErrorClass::RateLimited, Self {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+            jitter: JitterStrategy::Equal,
+            ..Self::default()
+        }.with_max_attempts(4));
+        
         Self {
             max_attempts: 5,
             initial_delay: Duration::from_millis(500),
@@ -45,6 +126,9 @@ impl RetryPolicy {
             backoff_multiplier: 2.0,
             jitter_factor: 0.2,
             attempt_timeout: Some(Duration::from_secs(30)),
+            jitter: JitterStrategy::Decorrelated,
+            class_policies,
+            budget: Some(RetryBudgetConfig { max_retries: 30, window: Duration::from_secs(60) }),
         }
     }
     
@@ -57,6 +141,9 @@ impl RetryPolicy {
             backoff_multiplier: 1.5,
             jitter_factor: 0.1,
             attempt_timeout: Some(Duration::from_secs(60)),
+            jitter: JitterStrategy::Proportional,
+            class_policies: HashMap::new(),
+            budget: None,
         }
     }
     
@@ -69,6 +156,9 @@ impl RetryPolicy {
             backoff_multiplier: 2.0,
             jitter_factor: 0.05,
             attempt_timeout: Some(Duration::from_secs(5)),
+            jitter: JitterStrategy::Proportional,
+            class_policies: HashMap::new(),
+            budget: None,
         }
     }
     
@@ -81,28 +171,90 @@ impl RetryPolicy {
             backoff_multiplier: 1.0,
             jitter_factor: 0.0,
             attempt_timeout: None,
+            jitter: JitterStrategy::Proportional,
+            class_policies: HashMap::new(),
+            budget: None,
+        }
+    }
+    
+    /// Set the maximum number of attempts
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+    
+    /// Back off differently for errors of `class`
+    pub fn with_class_policy(mut self, class: ErrorClass, policy: RetryPolicy) -> Self {
+        self.class_policies.insert(class, policy);
+        self
+    }
+    
+    /// The policy applying to errors of `class`
+    pub fn for_class(&self, class: ErrorClass) -> &RetryPolicy {
+        self.class_policies.get(&class).unwrap_or(self)
+    }
+    
+    /// Exponential delay of an attempt before jitter, in seconds
+    fn base_delay(&self, attempt: u32) -> f64 {
+        if attempt == 0 {
+            return 0.0;
         }
+        let delay = self.initial_delay.as_secs_f64() * self.backoff_multiplier.powi((attempt - 1) as i32);
+        delay.min(self.max_delay.as_secs_f64())
     }
     
     /// Calculate delay for given attempt number
     pub fn calculate_delay(&self, attempt: u32) -> Duration {
+        let previous = Duration::from_secs_f64(self.base_delay(attempt.saturating_sub(1)));
+        self.next_delay(attempt, previous)
+    }
+    
+    /// Calculate the delay of an attempt given the delay before the previous one
+    pub fn next_delay(&self, attempt: u32, previous: Duration) -> Duration {
         if attempt == 0 {
             return Duration::from_secs(0);
         }
         
-        let base_delay = self.initial_delay.as_secs_f64() 
-            * self.backoff_multiplier.powi((attempt - 1) as i32);
-        let capped_delay = base_delay.min(self.max_delay.as_secs_f64());
-        
-        // Add jitter
-        let jitter = if self.jitter_factor > 0.0 {
-            let jitter_amount = capped_delay * self.jitter_factor;
-            fastrand::f64() * jitter_amount
-        } else {
-            0.0
+        let base = self.base_delay(attempt);
+        let delay = match self.jitter {
+            JitterStrategy::Proportional => base + fastrand::f64() * base * self.jitter_factor.max(0.0),
+            JitterStrategy::Full => fastrand::f64() * base,
+            JitterStrategy::Equal => base / 2.0 + fastrand::f64() * base / 2.0,
+            JitterStrategy::Decorrelated => {
+                let low = self.initial_delay.as_secs_f64();
+                let high = (previous.as_secs_f64() * 3.0).max(low);
+                low + fastrand::f64() * (high - low)
+            }
         };
         
-        Duration::from_secs_f64((capped_delay + jitter).min(self.max_delay.as_secs_f64()))
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+}
+
+/// Retries left in the current window of a [`RetryBudgetConfig`]
+#[derive(Debug)]
+struct RetryBudget {
+    config: RetryBudgetConfig,
+    window_start: Instant,
+    used: u32,
+}
+
+impl RetryBudget {
+    fn new(config: RetryBudgetConfig) -> Self {
+        Self { config, window_start: Instant::now(), used: 0 }
+    }
+    
+    /// Take one retry from the budget, false when it is spent
+    fn try_acquire(&mut self) -> bool {
+        if self.window_start.elapsed() >= self.config.window {
+            self.window_start = Instant::now();
+            self.used = 0;
+        }
+        if self.used >= self.config.max_retries {
+            return false;
+        }
+        self.used += 1;
+        true
     }
 }
 
@@ -133,15 +285,69 @@ pub enum RetryResult<T> {
     },
 }
 
-/// Retry executor
+/// Retry executor.
+///
+/// The retry budget and the circuit breaker are shared by every operation
+/// run through the executor (and its clones), so a flapping connection
+/// stops being retried instead of spinning.
+#[derive(Clone)]
 pub struct RetryExecutor {
     policy: RetryPolicy,
+    budget: Option<Arc<Mutex<RetryBudget>>>,
+    breaker: Option<Arc<Mutex<CircuitBreaker>>>,
 }
 
 impl RetryExecutor {
     /// Create a new retry executor with the given policy
     pub fn new(policy: RetryPolicy) -> Self {
-        Self { policy }
+        let budget = policy.budget.clone().map(|config| Arc::new(Mutex::new(RetryBudget::new(config))));
+        Self { policy, budget, breaker: None }
+    }
+    
+    /// Reject attempts while the breaker is open
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = Some(Arc::new(Mutex::new(CircuitBreaker::new(config))));
+        self
+    }
+    
+    /// State of the circuit breaker, if there is one
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(|breaker| breaker.lock().unwrap().state().clone())
+    }
+    
+    /// Check the circuit breaker before an attempt
+    fn attempt_allowed(&self) -> bool {
+        self.breaker.as_ref().is_none_or(|breaker| breaker.lock().unwrap().is_request_allowed())
+    }
+    
+    fn record_outcome(&self, success: bool) {
+        if let Some(breaker) = &self.breaker {
+            let mut breaker = breaker.lock().unwrap();
+            if success {
+                breaker.record_success();
+            } else {
+                breaker.record_failure();
+            }
+        }
+    }
+    
+    /// Delay before retrying after the `attempt`th attempt failed with
+    /// `error`, or `None` to give up
+    fn next_retry(&self, error: &Error, attempt: u32, previous_delay: Duration) -> Option<Duration> {
+        if !error.is_retryable() {
+            return None;
+        }
+        let policy = self.policy.for_class(ErrorClass::of(error));
+        if attempt >= policy.max_attempts {
+            return None;
+        }
+        if let Some(budget) = &self.budget {
+            if !budget.lock().unwrap().try_acquire() {
+                tracing::warn!("Retry budget spent, giving up after: {}", error);
+                return None;
+            }
+        }
+        Some(error.retry_after().unwrap_or_else(|| policy.next_delay(attempt, previous_delay)))
     }
     
     /// Execute an async operation with retries
@@ -153,17 +359,18 @@ impl RetryExecutor {
         let start_time = Instant::now();
         let mut attempts = Vec::new();
         let mut last_error = None;
+        let mut delay = Duration::from_secs(0);
         
-        for attempt_num in 1..=self.policy.max_attempts {
-            let delay = if attempt_num == 1 {
-                Duration::from_secs(0)
-            } else {
-                self.policy.calculate_delay(attempt_num - 1)
-            };
-            
+        for attempt_num in 1.. {
             if delay > Duration::from_secs(0) {
                 sleep(delay).await;
             }
+            if !self.attempt_allowed() {
+                return RetryResult::Failed {
+                    error: last_error.unwrap_or_else(|| Error::Connection("Circuit breaker open".to_string())),
+                    attempts,
+                };
+            }
             
             let attempt = RetryAttempt {
                 attempt: attempt_num,
@@ -182,26 +389,21 @@ impl RetryExecutor {
             } else {
                 operation(attempt).await
             };
+            self.record_outcome(result.is_ok());
             
             match result {
                 Ok(value) => return RetryResult::Success(value),
-                Err(error) => {
-                    if !should_retry(&error) || attempt_num == self.policy.max_attempts {
-                        return RetryResult::Failed {
-                            error,
-                            attempts,
-                        };
+                Err(error) => match self.next_retry(&error, attempt_num, delay) {
+                    Some(next) => {
+                        delay = next;
+                        last_error = Some(error);
                     }
-                    last_error = Some(error);
-                }
+                    None => return RetryResult::Failed { error, attempts },
+                },
             }
         }
         
-        // This should never be reached due to the loop structure, but just in case
-        RetryResult::Failed {
-            error: last_error.unwrap_or_else(|| Error::Connection("Unknown error".to_string())),
-            attempts,
-        }
+        unreachable!("the attempt loop only ends by returning")
     }
     
     /// Execute a sync operation with retries (runs on blocking thread pool)
@@ -210,23 +412,24 @@ impl RetryExecutor {
         F: Fn(RetryAttempt) -> Result<T> + Send + 'static,
         T: Send + 'static,
     {
-        let policy = self.policy.clone();
+        let executor = self.clone();
         
         tokio::task::spawn_blocking(move || {
             let start_time = Instant::now();
             let mut attempts = Vec::new();
             let mut last_error = None;
+            let mut delay = Duration::from_secs(0);
             
-            for attempt_num in 1..=policy.max_attempts {
-                let delay = if attempt_num == 1 {
-                    Duration::from_secs(0)
-                } else {
-                    policy.calculate_delay(attempt_num - 1)
-                };
-                
+            for attempt_num in 1.. {
                 if delay > Duration::from_secs(0) {
                     std::thread::sleep(delay);
                 }
+                if !executor.attempt_allowed() {
+                    return RetryResult::Failed {
+                        error: last_error.unwrap_or_else(|| Error::Connection("Circuit breaker open".to_string())),
+                        attempts,
+                    };
+                }
                 
                 let attempt = RetryAttempt {
                     attempt: attempt_num,
@@ -237,24 +440,22 @@ impl RetryExecutor {
                 
                 attempts.push(attempt.clone());
                 
-                match operation(attempt) {
+                let result = operation(attempt);
+                executor.record_outcome(result.is_ok());
+                
+                match result {
                     Ok(value) => return RetryResult::Success(value),
-                    Err(error) => {
-                        if !should_retry(&error) || attempt_num == policy.max_attempts {
-                            return RetryResult::Failed {
-                                error,
-                                attempts,
-                            };
+                    Err(error) => match executor.next_retry(&error, attempt_num, delay) {
+                        Some(next) => {
+                            delay = next;
+                            last_error = Some(error);
                         }
-                        last_error = Some(error);
-                    }
+                        None => return RetryResult::Failed { error, attempts },
+                    },
                 }
             }
             
-            RetryResult::Failed {
-                error: last_error.unwrap_or_else(|| Error::Connection("Unknown error".to_string())),
-                attempts,
-            }
+            unreachable!("the attempt loop only ends by returning")
         })
         .await
         .unwrap_or_else(|_| RetryResult::Failed {
@@ -264,57 +465,6 @@ impl RetryExecutor {
     }
 }
 
-/// Determine if an error should trigger a retry
-pub fn should_retry(error: &Error) -> bool {
-    match error {
-        // Network errors are generally retryable
-        Error::WebSocket(_) => true,
-        Error::Connection(_) => true,
-        Error::Disconnected(_) => true,
-        Error::Io(_) => true,
-        
-        // Protocol errors usually indicate a permanent issue
-        Error::Protocol(_) => false,
-        Error::Auth(_) => false,
-        Error::Crypto(_) => false,
-        Error::InvalidJID(_) => false,
-        Error::NotLoggedIn => false,
-        
-        // Data errors usually indicate a bug
-        Error::Json(_) => false,
-        Error::ProtobufDecode(_) => false,
-        Error::UrlParse(_) => false,
-        Error::ElementMissing(_) => false,
-        
-        // Database errors might be retryable depending on the cause
-        Error::Database(msg) => {
-            // Simple heuristic: if it mentions connection, it might be retryable
-            msg.to_lowercase().contains("connection") || 
-            msg.to_lowercase().contains("timeout") ||
-            msg.to_lowercase().contains("busy")
-        }
-        
-        // IQ errors depend on the specific error code
-        Error::IQ { code, .. } => {
-            match code {
-                // Temporary errors that might be retryable
-                408 => true,       // Request timeout
-                429 => true,       // Too many requests
-                500..=599 => true, // Server errors (includes 503, 504)
-                
-                // Client errors that are generally not retryable
-                400..=499 => false,
-                
-                // Other codes
-                _ => false,
-            }
-        }
-        
-        // Serialization errors are generally not retryable
-        Error::Serialization(_) => false,
-    }
-}
-
 /// Convenience function for retrying an operation
 pub async fn retry_operation<T, F, Fut>(
     operation: F,
@@ -560,27 +710,6 @@ mod tests {
         }
     }
     
-    #[test]
-    fn test_should_retry() {
-        // Retryable errors
-        assert!(should_retry(&Error::Connection("test".to_string())));
-        assert!(should_retry(&Error::WebSocket("connection closed".to_string())));
-        assert!(should_retry(&Error::Io("timeout".to_string())));
-        
-        // Non-retryable errors
-        assert!(!should_retry(&Error::Auth("test".to_string())));
-        assert!(!should_retry(&Error::Protocol("test".to_string())));
-        assert!(!should_retry(&Error::InvalidJID("test".to_string())));
-        
-        // IQ errors with retryable codes
-        assert!(should_retry(&Error::IQ { code: 500, text: "Server error".to_string() }));
-        assert!(should_retry(&Error::IQ { code: 429, text: "Rate limited".to_string() }));
-        
-        // IQ errors with non-retryable codes
-        assert!(!should_retry(&Error::IQ { code: 400, text: "Bad request".to_string() }));
-        assert!(!should_retry(&Error::IQ { code: 401, text: "Unauthorized".to_string() }));
-    }
-    
     #[test]
     fn test_circuit_breaker() {
         let config = CircuitBreakerConfig {
@@ -613,6 +742,79 @@ mod tests {
         assert!(breaker.is_request_allowed());
     }
     
+    #[test]
+    fn test_jitter_strategies() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let base = Duration::from_millis(400);
+        
+        for _ in 0..100 {
+            let full = RetryPolicy { jitter: JitterStrategy::Full, ..policy.clone() }.calculate_delay(3);
+            assert!(full <= base);
+            let equal = RetryPolicy { jitter: JitterStrategy::Equal, ..policy.clone() }.calculate_delay(3);
+            assert!(equal >= base / 2 && equal <= base);
+            let decorrelated = RetryPolicy { jitter: JitterStrategy::Decorrelated, ..policy.clone() }
+                .next_delay(3, Duration::from_secs(1));
+            assert!(decorrelated >= policy.initial_delay && decorrelated <= Duration::from_secs(3));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_class_policies() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(1),
+            ..Default::default()
+        }
+        .with_class_policy(ErrorClass::ServerOverload, RetryPolicy {
+            initial_delay: Duration::from_millis(1),
+            ..Default::default()
+        }.with_max_attempts(2));
+        let executor = RetryExecutor::new(policy);
+        
+        let result = executor.execute(|_| async {
+            Err::<(), Error>(Error::Iq { code: 503, text: "service-unavailable".to_string(), node: None })
+        }).await;
+        assert!(matches!(result, RetryResult::Failed { ref attempts, .. } if attempts.len() == 2));
+        
+        // Rate limits wait as long as the server asked
+        let result = executor.execute(|attempt| async move {
+            match attempt.attempt {
+                1 => Err(Error::RateLimited { retry_after: Some(Duration::from_millis(20)) }),
+                _ => Ok(attempt.delay),
+            }
+        }).await;
+        assert!(matches!(result, RetryResult::Success(delay) if delay == Duration::from_millis(20)));
+    }
+    
+    #[tokio::test]
+    async fn test_retry_budget_and_breaker() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(1),
+            budget: Some(RetryBudgetConfig { max_retries: 3, window: Duration::from_secs(60) }),
+            ..Default::default()
+        };
+        let executor = RetryExecutor::new(policy.clone());
+        let failing = |_| async { Err::<(), Error>(Error::Connection("flapping".to_string())) };
+        
+        // The budget is shared, the second operation gets no retries
+        assert!(matches!(executor.execute(failing).await, RetryResult::Failed { ref attempts, .. } if attempts.len() == 4));
+        assert!(matches!(executor.execute(failing).await, RetryResult::Failed { ref attempts, .. } if attempts.len() == 1));
+        
+        let executor = RetryExecutor::new(RetryPolicy { budget: None, ..policy })
+            .with_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 2,
+                timeout: Duration::from_secs(60),
+                success_threshold: 1,
+            });
+        assert!(matches!(executor.execute(failing).await, RetryResult::Failed { ref attempts, .. } if attempts.len() == 2));
+        assert!(matches!(executor.circuit_state(), Some(CircuitState::Open { .. })));
+    }
+    
     #[tokio::test]
     async fn test_convenience_functions() {
         let result = retry_operation(
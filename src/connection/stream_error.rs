@@ -0,0 +1,210 @@
+/// Stream errors and connect failures sent by the server
+///
+/// The server ends a session with either a `stream:error` node (while
+/// connected) or a `failure` node (in response to the login). Both carry a
+/// code deciding whether the client should reconnect right away, wait before
+/// reconnecting, or give up because the credentials are no longer valid.
+
+use crate::binary::Node;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Delay before reconnecting when the server reports itself unavailable
+pub const SERVICE_UNAVAILABLE_DELAY: Duration = Duration::from_secs(30);
+
+/// A server-initiated end of the session
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    /// The server asks the client to reconnect (code 515)
+    RestartRequired,
+    /// The companion was unlinked or the session was logged out
+    LoggedOut { reason: String },
+    /// Another client connected with the same credentials
+    Replaced,
+    /// The account is temporarily banned
+    TemporaryBan { code: Option<u32>, expires: Option<SystemTime> },
+    /// The server is overloaded or restarting (codes 500 and 503)
+    ServiceUnavailable,
+    /// The announced WhatsApp Web version is no longer accepted (codes 405 and 415)
+    ClientOutdated,
+    /// Any other error
+    Unknown { code: Option<u16>, text: String },
+}
+
+/// What to do with the connection after a stream error
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamErrorAction {
+    /// Reconnect immediately
+    Reconnect,
+    /// Reconnect once the given time has passed
+    ReconnectAfter(Duration),
+    /// The credentials are invalid: delete them and stay disconnected
+    ClearCredentials,
+    /// Stay disconnected until the application intervenes
+    Stop,
+}
+
+impl StreamError {
+    /// Decide how the connection should react to this error
+    pub fn action(&self, now: SystemTime) -> StreamErrorAction {
+        match self {
+            StreamError::RestartRequired => StreamErrorAction::Reconnect,
+            StreamError::LoggedOut { .. } => StreamErrorAction::ClearCredentials,
+            // Reconnecting would kick the other client off again
+            StreamError::Replaced => StreamErrorAction::Stop,
+            StreamError::TemporaryBan { expires, .. } => match expires {
+                Some(expires) => StreamErrorAction::ReconnectAfter(
+                    expires.duration_since(now).unwrap_or(Duration::ZERO),
+                ),
+                None => StreamErrorAction::Stop,
+            },
+            StreamError::ServiceUnavailable => StreamErrorAction::ReconnectAfter(SERVICE_UNAVAILABLE_DELAY),
+            StreamError::ClientOutdated => StreamErrorAction::Stop,
+            StreamError::Unknown { .. } => StreamErrorAction::Reconnect,
+        }
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::RestartRequired => write!(f, "server requested a restart"),
+            StreamError::LoggedOut { reason } => write!(f, "logged out: {}", reason),
+            StreamError::Replaced => write!(f, "stream replaced by another connection"),
+            StreamError::TemporaryBan { code, .. } => match code {
+                Some(code) => write!(f, "temporarily banned (code {})", code),
+                None => write!(f, "temporarily banned"),
+            },
+            StreamError::ServiceUnavailable => write!(f, "service unavailable"),
+            StreamError::ClientOutdated => write!(f, "client version outdated"),
+            StreamError::Unknown { code: Some(code), text } => write!(f, "stream error {}: {}", code, text),
+            StreamError::Unknown { code: None, text } => write!(f, "stream error: {}", text),
+        }
+    }
+}
+
+/// Parse a `stream:error` or `failure` node.
+///
+/// Returns `None` for any other node.
+pub fn parse_stream_error(node: &Node) -> Option<StreamError> {
+    match node.tag.as_str() {
+        "stream:error" => Some(parse_stream_error_node(node)),
+        "failure" => Some(parse_failure_node(node)),
+        _ => None,
+    }
+}
+
+fn parse_stream_error_node(node: &Node) -> StreamError {
+    let code = node.get_attr("code").and_then(|c| c.parse::<u16>().ok());
+    let conflict = node
+        .find_child("conflict")
+        .and_then(|conflict| conflict.get_attr("type"))
+        .map(|t| t.as_str());
+
+    match (code, conflict) {
+        (_, Some("replaced")) => StreamError::Replaced,
+        (_, Some("device_removed")) => StreamError::LoggedOut { reason: "device_removed".to_string() },
+        (Some(401), _) => StreamError::LoggedOut { reason: "unauthorized".to_string() },
+        (Some(515), _) => StreamError::RestartRequired,
+        (Some(500), _) | (Some(503), _) => StreamError::ServiceUnavailable,
+        _ => {
+            let text = node
+                .get_children()
+                .and_then(|children| children.first())
+                .map(|child| child.tag.clone())
+                .unwrap_or_default();
+            StreamError::Unknown { code, text }
+        }
+    }
+}
+
+fn parse_failure_node(node: &Node) -> StreamError {
+    let reason = node.get_attr("reason").and_then(|r| r.parse::<u16>().ok());
+    let message = node.get_attr("message").cloned().unwrap_or_default();
+
+    match reason {
+        Some(401) => StreamError::LoggedOut { reason: "logged_out".to_string() },
+        Some(403) => StreamError::LoggedOut { reason: "main_device_gone".to_string() },
+        Some(406) => StreamError::LoggedOut { reason: "unknown_logout".to_string() },
+        Some(402) => StreamError::TemporaryBan {
+            code: node.get_attr("code").and_then(|c| c.parse().ok()),
+            // `expire` is the remaining ban time in seconds
+            expires: node
+                .get_attr("expire")
+                .and_then(|e| e.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(|secs| SystemTime::now() + Duration::from_secs(secs)),
+        },
+        Some(405) | Some(415) => StreamError::ClientOutdated,
+        Some(500) | Some(503) => StreamError::ServiceUnavailable,
+        code => StreamError::Unknown { code, text: message },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_error(code: &str, conflict: Option<&str>) -> Node {
+        let node = Node::new("stream:error".to_string()).attr("code".to_string(), code.to_string());
+        match conflict {
+            Some(conflict) => node.with_children(vec![
+                Node::new("conflict".to_string()).attr("type".to_string(), conflict.to_string()),
+            ]),
+            None => node,
+        }
+    }
+
+    #[test]
+    fn test_parse_stream_errors() {
+        assert_eq!(parse_stream_error(&stream_error("515", None)), Some(StreamError::RestartRequired));
+        assert_eq!(parse_stream_error(&stream_error("409", Some("replaced"))), Some(StreamError::Replaced));
+        assert!(matches!(
+            parse_stream_error(&stream_error("401", Some("device_removed"))),
+            Some(StreamError::LoggedOut { .. })
+        ));
+        assert_eq!(parse_stream_error(&stream_error("503", None)), Some(StreamError::ServiceUnavailable));
+        assert_eq!(parse_stream_error(&Node::new("iq".to_string())), None);
+    }
+
+    #[test]
+    fn test_parse_failures() {
+        let failure = |reason: &str| Node::new("failure".to_string()).attr("reason".to_string(), reason.to_string());
+
+        assert!(matches!(parse_stream_error(&failure("401")), Some(StreamError::LoggedOut { .. })));
+        assert_eq!(parse_stream_error(&failure("405")), Some(StreamError::ClientOutdated));
+        assert_eq!(parse_stream_error(&failure("415")), Some(StreamError::ClientOutdated));
+
+        let ban = failure("402")
+            .attr("code".to_string(), "101".to_string())
+            .attr("expire".to_string(), "3600".to_string());
+        match parse_stream_error(&ban) {
+            Some(StreamError::TemporaryBan { code, expires }) => {
+                assert_eq!(code, Some(101));
+                assert!(expires.unwrap() > SystemTime::now() + Duration::from_secs(3500));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_actions() {
+        let now = SystemTime::now();
+
+        assert_eq!(StreamError::RestartRequired.action(now), StreamErrorAction::Reconnect);
+        assert_eq!(StreamError::Replaced.action(now), StreamErrorAction::Stop);
+        assert_eq!(
+            StreamError::LoggedOut { reason: "logged_out".to_string() }.action(now),
+            StreamErrorAction::ClearCredentials
+        );
+        assert_eq!(
+            StreamError::ServiceUnavailable.action(now),
+            StreamErrorAction::ReconnectAfter(SERVICE_UNAVAILABLE_DELAY)
+        );
+
+        let ban = StreamError::TemporaryBan { code: None, expires: Some(now + Duration::from_secs(60)) };
+        assert_eq!(ban.action(now), StreamErrorAction::ReconnectAfter(Duration::from_secs(60)));
+        let ban = StreamError::TemporaryBan { code: None, expires: None };
+        assert_eq!(ban.action(now), StreamErrorAction::Stop);
+    }
+}
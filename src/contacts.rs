@@ -0,0 +1,231 @@
+/// Address book synchronization
+///
+/// The phone's address book is mirrored to the server with usync contact
+/// queries: numbers in the address book are uploaded with `type="add"` and
+/// numbers that were removed with `type="delete"`. The server uses the
+/// uploaded book to decide which contacts may see the account and answers
+/// with the registration status of every added number. To keep uploads small
+/// the new book is diffed against the numbers uploaded previously and only
+/// the changes are sent, split into batches. The very first upload is sent
+/// in `full` mode, later ones in `delta` mode.
+
+use crate::{
+    binary::Node,
+    types::IsOnWhatsAppResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Default number of contacts sent in a single usync query
+pub const DEFAULT_CONTACT_BATCH_SIZE: usize = 500;
+
+/// Change applied to an address book entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactAction {
+    Add,
+    Delete,
+}
+
+impl ContactAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ContactAction::Add => "add",
+            ContactAction::Delete => "delete",
+        }
+    }
+}
+
+/// Entry of the address book
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhoneContact {
+    /// Phone number in any format, normalized before upload
+    pub phone: String,
+    /// Name the contact is saved under, stored locally
+    pub name: Option<String>,
+}
+
+impl PhoneContact {
+    pub fn new(phone: impl Into<String>, name: Option<String>) -> Self {
+        Self { phone: phone.into(), name }
+    }
+}
+
+/// How the address book is uploaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContactSyncMode {
+    /// Nothing was uploaded before, the whole book is sent
+    Full,
+    /// Only the changes to the previously uploaded book are sent
+    #[default]
+    Delta,
+}
+
+impl ContactSyncMode {
+    /// Mode to use given the numbers uploaded before
+    pub fn for_uploaded<S: AsRef<str>>(uploaded: &[S]) -> Self {
+        if uploaded.is_empty() {
+            ContactSyncMode::Full
+        } else {
+            ContactSyncMode::Delta
+        }
+    }
+
+    /// Value of the usync `mode` attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContactSyncMode::Full => "full",
+            ContactSyncMode::Delta => "delta",
+        }
+    }
+}
+
+/// Reduce a phone number to its digits, e.g. `+1 (555) 010-0000` to `15550100000`.
+///
+/// Returns `None` if the input contains no digits.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let digits: String = phone.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        None
+    } else {
+        Some(digits)
+    }
+}
+
+/// Difference between the uploaded and the desired address book
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddressBookDiff {
+    /// Numbers to upload
+    pub added: Vec<String>,
+    /// Numbers to remove from the server
+    pub removed: Vec<String>,
+}
+
+impl AddressBookDiff {
+    /// Compare the previously uploaded numbers with the new address book.
+    ///
+    /// Numbers are normalized before comparison and duplicates are ignored.
+    pub fn between<S: AsRef<str>, D: AsRef<str>>(uploaded: &[S], desired: &[D]) -> Self {
+        let uploaded: BTreeSet<String> = uploaded.iter().filter_map(|p| normalize_phone(p.as_ref())).collect();
+        let desired: BTreeSet<String> = desired.iter().filter_map(|p| normalize_phone(p.as_ref())).collect();
+
+        Self {
+            added: desired.difference(&uploaded).cloned().collect(),
+            removed: uploaded.difference(&desired).cloned().collect(),
+        }
+    }
+
+    /// Whether the address book is unchanged
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Split the changes into batches of `<user>` nodes for usync queries
+    pub fn batches(&self, batch_size: usize) -> Vec<Vec<Node>> {
+        let users: Vec<Node> = self
+            .added
+            .iter()
+            .map(|phone| contact_sync_user_node(phone, ContactAction::Add))
+            .chain(self.removed.iter().map(|phone| contact_sync_user_node(phone, ContactAction::Delete)))
+            .collect();
+
+        users.chunks(batch_size.max(1)).map(|chunk| chunk.to_vec()).collect()
+    }
+}
+
+/// Contacts whose name differs from the stored one, by normalized number.
+///
+/// Contacts without a name are skipped so a nameless sync never clears names.
+pub fn renamed_contacts<'a>(stored: &HashMap<String, Option<String>>, contacts: &'a [PhoneContact]) -> Vec<(String, &'a str)> {
+    contacts
+        .iter()
+        .filter_map(|contact| {
+            let name = contact.name.as_deref()?;
+            let phone = normalize_phone(&contact.phone)?;
+            match stored.get(&phone) {
+                Some(stored_name) if stored_name.as_deref() != Some(name) => Some((phone, name)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Build a `<user>` node adding or deleting an address book entry
+pub fn contact_sync_user_node(phone: &str, action: ContactAction) -> Node {
+    Node::new("user".to_string()).with_children(vec![Node::new("contact".to_string())
+        .attr("type".to_string(), action.as_str().to_string())
+        .with_text(format!("+{}", phone.trim_start_matches('+')))])
+}
+
+/// Outcome of an address book sync
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContactSyncResult {
+    pub mode: ContactSyncMode,
+    /// Registration status of every uploaded number
+    pub uploaded: Vec<IsOnWhatsAppResult>,
+    /// Numbers removed from the server's copy of the address book
+    pub removed: Vec<String>,
+    /// Numbers already uploaded whose stored name was updated
+    pub renamed: Vec<String>,
+}
+
+impl ContactSyncResult {
+    /// Uploaded numbers that are registered on WhatsApp
+    pub fn registered(&self) -> impl Iterator<Item = &IsOnWhatsAppResult> {
+        self.uploaded.iter().filter(|r| r.is_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(normalize_phone("+1 (555) 010-0000").as_deref(), Some("15550100000"));
+        assert_eq!(normalize_phone("n/a"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let uploaded = ["+100", "200", "300"];
+        let desired = ["200", "+1 00", "400", "400"];
+
+        let diff = AddressBookDiff::between(&uploaded, &desired);
+        assert_eq!(diff.added, vec!["400".to_string()]);
+        assert_eq!(diff.removed, vec!["300".to_string()]);
+        assert!(AddressBookDiff::between(&desired, &["100", "200", "400"]).is_empty());
+    }
+
+    #[test]
+    fn test_batches() {
+        let diff = AddressBookDiff {
+            added: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+            removed: vec!["4".to_string()],
+        };
+
+        let batches = diff.batches(3);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 3);
+        let contact = batches[1][0].find_child("contact").unwrap();
+        assert_eq!(contact.get_attr("type").unwrap(), "delete");
+        assert_eq!(contact.get_text().unwrap(), "+4");
+    }
+
+    #[test]
+    fn test_mode_and_renames() {
+        assert_eq!(ContactSyncMode::for_uploaded::<&str>(&[]), ContactSyncMode::Full);
+        assert_eq!(ContactSyncMode::for_uploaded(&["100"]).as_str(), "delta");
+
+        let stored = HashMap::from([
+            ("100".to_string(), Some("Alice".to_string())),
+            ("200".to_string(), None),
+        ]);
+        let contacts = [
+            PhoneContact::new("+1 00", Some("Alice".to_string())),
+            PhoneContact::new("200", Some("Bob".to_string())),
+            PhoneContact::new("300", Some("Carol".to_string())),
+            PhoneContact::new("100", None),
+        ];
+        assert_eq!(renamed_contacts(&stored, &contacts), vec![("200".to_string(), "Bob")]);
+    }
+}
@@ -0,0 +1,117 @@
+/// Encrypted archives of the database for moving a session between machines
+///
+/// An archive holds a compressed [dump](super::dump) of every table:
+/// credentials, Signal state, app state keys, contacts and history. Restoring
+/// it on another machine continues the session without linking the device
+/// again. As the archive contains the device's private keys it is encrypted
+/// with AES-256-GCM under a key derived from a passphrase with
+/// PBKDF2-HMAC-SHA256. The header (magic, iteration count, salt and nonce)
+/// is authenticated along with the data.
+///
+/// A session must only run on one machine at a time; the server logs out
+/// companions whose keys are used from two places.
+
+use crate::{
+    error::{Error, Result},
+    util::crypto::{random_bytes, AesGcm},
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use sqlx::SqlitePool;
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"WMRSARC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 4 + SALT_LEN + NONCE_LEN;
+
+/// PBKDF2 rounds used for new archives
+pub const PBKDF2_ITERATIONS: u32 = 200_000;
+
+/// Write every table of the database to an archive encrypted with `passphrase`
+pub async fn export_encrypted(pool: &SqlitePool, path: &Path, passphrase: &str) -> Result<()> {
+    let dump = super::dump::export_dump(pool).await?;
+    tokio::fs::write(path, seal(dump.as_bytes(), passphrase)?).await?;
+    Ok(())
+}
+
+/// Replace the contents of the database with an archive from [`export_encrypted`]
+pub async fn import_encrypted(pool: &SqlitePool, path: &Path, passphrase: &str) -> Result<()> {
+    let archive = tokio::fs::read(path).await?;
+    let dump = String::from_utf8(open(&archive, passphrase)?)
+        .map_err(|e| Error::Database(format!("Archive does not contain a dump: {}", e)))?;
+    super::dump::import_dump(pool, &dump).await
+}
+
+/// Compress and encrypt `data` with a passphrase
+pub fn seal(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(Error::Crypto("Archive passphrase must not be empty".to_string()));
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    let salt = random_bytes(SALT_LEN);
+    let nonce = random_bytes(NONCE_LEN);
+    let mut archive = Vec::with_capacity(HEADER_LEN + compressed.len() + 16);
+    archive.extend_from_slice(MAGIC);
+    archive.extend_from_slice(&PBKDF2_ITERATIONS.to_be_bytes());
+    archive.extend_from_slice(&salt);
+    archive.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, PBKDF2_ITERATIONS)?;
+    let ciphertext = AesGcm::new(&key)?.encrypt_with_aad(&nonce, &compressed, &archive)?;
+    archive.extend_from_slice(&ciphertext);
+    Ok(archive)
+}
+
+/// Decrypt and decompress an archive made by [`seal`]
+pub fn open(archive: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if archive.len() < HEADER_LEN || &archive[..MAGIC.len()] != MAGIC {
+        return Err(Error::Database("Not an encrypted store archive".to_string()));
+    }
+    let (header, ciphertext) = archive.split_at(HEADER_LEN);
+    let iterations = u32::from_be_bytes(header[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+    let salt = &header[MAGIC.len() + 4..MAGIC.len() + 4 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, salt, iterations)?;
+    let compressed = AesGcm::new(&key)?
+        .decrypt_with_aad(nonce, ciphertext, header)
+        .map_err(|_| Error::Crypto("Wrong passphrase or damaged archive".to_string()))?;
+
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<[u8; 32]> {
+    let iterations = NonZeroU32::new(iterations)
+        .ok_or_else(|| Error::Database("Archive has no key derivation rounds".to_string()))?;
+    let mut key = [0u8; 32];
+    ring::pbkdf2::derive(ring::pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let data = b"INSERT INTO devices VALUES ('secret');\n".repeat(10);
+        let archive = seal(&data, "correct horse").unwrap();
+        assert_eq!(&archive[..MAGIC.len()], MAGIC);
+        assert!(!archive.windows(6).any(|window| window == b"secret"));
+        assert_eq!(open(&archive, "correct horse").unwrap(), data);
+
+        assert!(matches!(open(&archive, "wrong"), Err(Error::Crypto(_))));
+        let mut tampered = archive.clone();
+        tampered[MAGIC.len() + 5] ^= 1;
+        assert!(open(&tampered, "correct horse").is_err());
+        assert!(open(b"plain text", "correct horse").is_err());
+        assert!(seal(&data, "").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+/// SQL dumps of the database for backups
+///
+/// A dump is a header naming the schema version followed by one `INSERT`
+/// statement per row. Values are written with SQLite's `quote()`, so blobs
+/// and text round-trip exactly. Dumps only load into a database at the same
+/// schema version; migrate first when restoring an older backup.
+
+use super::migrations::get_current_version;
+use crate::error::{Error, Result};
+use sqlx::SqlitePool;
+
+const HEADER: &str = "-- whatsmeow-rs dump, schema version ";
+
+/// Tables copied by dumps; the schema version is part of the header instead
+async fn dump_tables(pool: &SqlitePool) -> Result<Vec<String>> {
+    sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name != 'schema_version' ORDER BY name"
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| Error::Database(format!("Failed to list tables: {}", e)))
+}
+
+/// Write every row of the database as SQL
+pub async fn export_dump(pool: &SqlitePool) -> Result<String> {
+    let mut dump = format!("{}{}\n", HEADER, get_current_version(pool).await?);
+    
+    for table in dump_tables(pool).await? {
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?) ORDER BY cid")
+            .bind(&table)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to read columns of {}: {}", table, e)))?;
+        if columns.is_empty() {
+            continue;
+        }
+        
+        let values = columns.iter()
+            .map(|column| format!("quote(\"{}\")", column))
+            .collect::<Vec<_>>()
+            .join(" || ', ' || ");
+        let rows: Vec<String> = sqlx::query_scalar(&format!("SELECT {} FROM \"{}\"", values, table))
+            .fetch_all(pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to dump {}: {}", table, e)))?;
+        
+        let column_list = columns.iter().map(|c| format!("\"{}\"", c)).collect::<Vec<_>>().join(", ");
+        for row in rows {
+            dump.push_str(&format!("INSERT INTO \"{}\" ({}) VALUES ({});\n", table, column_list, row));
+        }
+    }
+    
+    Ok(dump)
+}
+
+/// Replace every row of the database with the rows of a dump
+///
+/// The dump is executed as SQL, so only load backups you trust.
+pub async fn import_dump(pool: &SqlitePool, dump: &str) -> Result<()> {
+    let dump_version: i32 = dump.lines()
+        .next()
+        .and_then(|line| line.strip_prefix(HEADER))
+        .and_then(|version| version.trim().parse().ok())
+        .ok_or_else(|| Error::Database("Not a database dump".to_string()))?;
+    let current_version = get_current_version(pool).await?;
+    if dump_version != current_version {
+        return Err(Error::Database(format!(
+            "Dump is from schema version {}, database is at version {}", dump_version, current_version
+        )));
+    }
+    
+    let tables = dump_tables(pool).await?;
+    let mut tx = pool.begin().await
+        .map_err(|e| Error::Database(format!("Failed to begin transaction: {}", e)))?;
+    
+    // Rows are inserted table by table, so check foreign keys at commit
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to defer foreign keys: {}", e)))?;
+    
+    for table in tables {
+        sqlx::query(&format!("DELETE FROM \"{}\"", table))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to clear {}: {}", table, e)))?;
+    }
+    
+    sqlx::raw_sql(dump)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to load dump: {}", e)))?;
+    
+    tx.commit().await
+        .map_err(|e| Error::Database(format!("Failed to commit dump: {}", e)))
+}
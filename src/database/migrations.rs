@@ -1,39 +1,192 @@
 /// Database migrations for WhatsApp client
+///
+/// Version 1 creates the initial schema. Every later version is a
+/// [`Migration`] with the statements applying it and the statements
+/// reverting it, so a database can be moved to any version in between.
 
 use crate::error::{Error, Result};
-use super::schema::{SCHEMA_VERSION, CREATE_TABLES, CREATE_INDEXES, CREATE_TRIGGERS};
+use super::schema::{SCHEMA_VERSION, CREATE_TABLES, CREATE_TABLES_V2, CREATE_TABLES_V3, CREATE_TABLES_V4, CREATE_TABLES_V5, CREATE_TABLES_V6, CREATE_TABLES_V7, CREATE_TABLES_V8, CREATE_TABLES_V9, CREATE_TABLES_V10, CREATE_TABLES_V11, CREATE_INDEXES, CREATE_TRIGGERS};
 use sqlx::SqlitePool;
 
+/// A schema version on top of the initial schema
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    /// Statements applying this version
+    pub up: &'static [&'static str],
+    /// Statements reverting to the previous version
+    pub down: &'static [&'static str],
+}
+
+/// Migrations after version 1, in version order
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        description: "outbox",
+        up: CREATE_TABLES_V2,
+        down: &["DROP TABLE IF EXISTS outbox"],
+    },
+    Migration {
+        version: 3,
+        description: "LID mappings",
+        up: CREATE_TABLES_V3,
+        down: &["DROP TABLE IF EXISTS lid_mappings"],
+    },
+    Migration {
+        version: 4,
+        description: "contact names",
+        up: CREATE_TABLES_V4,
+        down: &[
+            "ALTER TABLE contacts DROP COLUMN first_name",
+            "ALTER TABLE contacts DROP COLUMN full_name",
+            "ALTER TABLE contacts DROP COLUMN business_name",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "app state sync keys",
+        up: CREATE_TABLES_V5,
+        down: &["DROP TABLE IF EXISTS app_state_sync_keys"],
+    },
+    Migration {
+        version: 6,
+        description: "per-device Signal stores",
+        up: CREATE_TABLES_V6,
+        down: &[
+            "DROP TABLE IF EXISTS device_identity_keys",
+            "DROP TABLE IF EXISTS device_sessions",
+            "DROP TABLE IF EXISTS device_pre_keys",
+            "DROP TABLE IF EXISTS device_sender_keys",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "scheduled messages",
+        up: CREATE_TABLES_V7,
+        down: &["DROP TABLE IF EXISTS scheduled_messages"],
+    },
+    Migration {
+        version: 8,
+        description: "app state versions",
+        up: CREATE_TABLES_V8,
+        down: &[
+            "DROP TABLE IF EXISTS app_state_versions",
+            "DROP TABLE IF EXISTS app_state_mutation_macs",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "user:device Signal addresses",
+        up: CREATE_TABLES_V9,
+        // Drop the user:device copies made by the step and restore the kept rows
+        down: &[
+            r#"
+            DELETE FROM device_identity_keys WHERE EXISTS (
+                SELECT 1 FROM v9_dotted_identity_keys d
+                WHERE d.converted AND d.our_jid = device_identity_keys.our_jid
+                    AND REPLACE(d.their_id, '.', ':') = device_identity_keys.their_id
+            )
+            "#,
+            "INSERT OR REPLACE INTO device_identity_keys (our_jid, their_id, identity) SELECT our_jid, their_id, identity FROM v9_dotted_identity_keys",
+            r#"
+            DELETE FROM device_sessions WHERE EXISTS (
+                SELECT 1 FROM v9_dotted_sessions d
+                WHERE d.converted AND d.our_jid = device_sessions.our_jid
+                    AND REPLACE(d.their_id, '.', ':') = device_sessions.their_id
+            )
+            "#,
+            "INSERT OR REPLACE INTO device_sessions (our_jid, their_id, session) SELECT our_jid, their_id, session FROM v9_dotted_sessions",
+            r#"
+            DELETE FROM device_sender_keys WHERE EXISTS (
+                SELECT 1 FROM v9_dotted_sender_keys d
+                WHERE d.converted AND d.our_jid = device_sender_keys.our_jid AND d.chat_id = device_sender_keys.chat_id
+                    AND REPLACE(d.sender_id, '.', ':') = device_sender_keys.sender_id
+            )
+            "#,
+            "INSERT OR REPLACE INTO device_sender_keys (our_jid, chat_id, sender_id, sender_key) SELECT our_jid, chat_id, sender_id, sender_key FROM v9_dotted_sender_keys",
+            "DROP TABLE IF EXISTS v9_dotted_identity_keys",
+            "DROP TABLE IF EXISTS v9_dotted_sessions",
+            "DROP TABLE IF EXISTS v9_dotted_sender_keys",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "poll tallies",
+        up: CREATE_TABLES_V10,
+        down: &[
+            "DROP TABLE IF EXISTS polls",
+            "DROP TABLE IF EXISTS poll_votes",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "decrypt failures",
+        up: CREATE_TABLES_V11,
+        down: &["DROP TABLE IF EXISTS decrypt_failures"],
+    },
+];
+
 /// Run all database migrations
 pub async fn run_migrations(pool: &SqlitePool) -> Result<()> {
+    let current_version = get_current_version(pool).await?;
+    if current_version >= SCHEMA_VERSION {
+        tracing::info!("Database schema is up to date (version {})", current_version);
+        return Ok(());
+    }
+    migrate_to(pool, SCHEMA_VERSION).await
+}
+
+/// Migrate the database up or down to a schema version
+pub async fn migrate_to(pool: &SqlitePool, target_version: i32) -> Result<()> {
+    if !(1..=SCHEMA_VERSION).contains(&target_version) {
+        return Err(Error::Database(format!(
+            "Unknown schema version {}, expected 1 to {}", target_version, SCHEMA_VERSION
+        )));
+    }
+    
     // Check current schema version
     let current_version = get_current_version(pool).await?;
     
-    if current_version >= SCHEMA_VERSION {
+    if current_version == target_version {
         tracing::info!("Database schema is up to date (version {})", current_version);
         return Ok(());
     }
+    if current_version > SCHEMA_VERSION {
+        return Err(Error::Database(format!(
+            "Database schema version {} is newer than this library ({})", current_version, SCHEMA_VERSION
+        )));
+    }
     
-    tracing::info!("Running database migrations from version {} to {}", current_version, SCHEMA_VERSION);
+    tracing::info!("Running database migrations from version {} to {}", current_version, target_version);
     
     // Start transaction for all migrations
     let mut tx = pool.begin().await
         .map_err(|e| Error::Database(format!("Failed to begin migration transaction: {}", e)))?;
     
-    // Run migrations based on current version
-    match current_version {
-        0 => {
+    if current_version < target_version {
+        if current_version < 1 {
             // Initial migration - create all tables
             migrate_to_v1(&mut tx).await?;
         }
-        _ => {
-            return Err(Error::Database(format!("Unknown schema version: {}", current_version)));
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current_version && m.version <= target_version) {
+            tracing::info!("Running migration to version {} ({})", migration.version, migration.description);
+            execute_all(&mut tx, migration.up).await?;
+        }
+    } else {
+        for migration in MIGRATIONS.iter().rev().filter(|m| m.version > target_version && m.version <= current_version) {
+            tracing::info!("Reverting migration to version {} ({})", migration.version, migration.description);
+            execute_all(&mut tx, migration.down).await?;
         }
     }
     
     // Update schema version
+    sqlx::query("DELETE FROM schema_version WHERE version > ?")
+        .bind(target_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to update schema version: {}", e)))?;
     sqlx::query("INSERT OR REPLACE INTO schema_version (version) VALUES (?)")
-        .bind(SCHEMA_VERSION)
+        .bind(target_version)
         .execute(&mut *tx)
         .await
         .map_err(|e| Error::Database(format!("Failed to update schema version: {}", e)))?;
@@ -46,8 +199,18 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<()> {
     Ok(())
 }
 
+async fn execute_all(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, statements: &[&str]) -> Result<()> {
+    for sql in statements {
+        sqlx::query(sql)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| Error::Database(format!("Migration statement failed: {}", e)))?;
+    }
+    Ok(())
+}
+
 /// Get current database schema version
-async fn get_current_version(pool: &SqlitePool) -> Result<i32> {
+pub async fn get_current_version(pool: &SqlitePool) -> Result<i32> {
     // Check if schema_version table exists
     let table_exists: bool = sqlx::query_scalar(
         "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='schema_version'"
@@ -213,15 +376,17 @@ pub async fn validate_database(pool: &SqlitePool) -> Result<Vec<String>> {
         issues.extend(fk_violations.into_iter().map(|v| format!("Foreign key violation: {}", v)));
     }
     
-    // Check database integrity
-    let integrity_check: String = sqlx::query_scalar("PRAGMA integrity_check")
-        .fetch_one(pool)
+    // Check database integrity, which reports one row per problem
+    let integrity_check: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_all(pool)
         .await
         .map_err(|e| Error::Database(format!("Failed to check integrity: {}", e)))?;
     
-    if integrity_check != "ok" {
-        issues.push(format!("Database integrity issue: {}", integrity_check));
-    }
+    issues.extend(
+        integrity_check.into_iter()
+            .filter(|row| row != "ok")
+            .map(|row| format!("Database integrity issue: {}", row))
+    );
     
     // Check for orphaned records
     let orphaned_participants: i64 = sqlx::query_scalar(
@@ -273,7 +438,11 @@ mod tests {
         let expected_tables = vec![
             "devices", "identity_keys", "sessions", "pre_keys", "signed_pre_keys",
             "group_sessions", "sender_keys", "groups", "group_participants",
-            "contacts", "messages", "chats", "media_files", "settings", "schema_version"
+            "contacts", "messages", "chats", "media_files", "settings", "schema_version",
+            "outbox", "lid_mappings", "app_state_sync_keys", "device_identity_keys",
+            "device_sessions", "device_pre_keys", "device_sender_keys", "scheduled_messages",
+            "app_state_versions", "app_state_mutation_macs", "polls", "poll_votes",
+            "decrypt_failures"
         ];
         
         for expected_table in expected_tables {
@@ -284,6 +453,99 @@ mod tests {
         db.close().await;
     }
     
+    #[tokio::test]
+    async fn test_migrate_from_v1() {
+        let db = create_test_db().await;
+        
+        // Roll the database back to a version 1 layout
+        migrate_to(db.pool(), 1).await.unwrap();
+        assert_eq!(get_current_version(db.pool()).await.unwrap(), 1);
+        for table in ["outbox", "lid_mappings", "app_state_sync_keys", "device_sessions", "scheduled_messages", "app_state_versions", "polls", "decrypt_failures"] {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name=?"
+            )
+            .bind(table)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+            assert!(!exists, "Table {} not dropped", table);
+        }
+        assert!(sqlx::query("SELECT first_name FROM contacts").fetch_all(db.pool()).await.is_err());
+        
+        run_migrations(db.pool()).await.unwrap();
+        assert_eq!(get_current_version(db.pool()).await.unwrap(), SCHEMA_VERSION);
+        
+        for table in ["outbox", "lid_mappings", "app_state_sync_keys", "device_sessions", "scheduled_messages", "app_state_versions", "polls", "decrypt_failures"] {
+            let exists: bool = sqlx::query_scalar(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name=?"
+            )
+            .bind(table)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+            assert!(exists, "Table {} not created", table);
+        }
+        sqlx::query("SELECT first_name, full_name, business_name FROM contacts").fetch_all(db.pool()).await.unwrap();
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_migrate_to_version() {
+        let db = create_test_db().await;
+        
+        migrate_to(db.pool(), 4).await.unwrap();
+        assert_eq!(get_current_version(db.pool()).await.unwrap(), 4);
+        sqlx::query("SELECT first_name FROM contacts").fetch_all(db.pool()).await.unwrap();
+        assert!(sqlx::query("SELECT * FROM app_state_sync_keys").fetch_all(db.pool()).await.is_err());
+        
+        migrate_to(db.pool(), 5).await.unwrap();
+        sqlx::query("SELECT * FROM app_state_sync_keys").fetch_all(db.pool()).await.unwrap();
+        
+        assert!(migrate_to(db.pool(), 0).await.is_err());
+        assert!(migrate_to(db.pool(), SCHEMA_VERSION + 1).await.is_err());
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_signal_addresses_migration() {
+        let db = create_test_db().await;
+        migrate_to(db.pool(), 8).await.unwrap();
+        for (their_id, session) in [("111.0", b"old".as_slice()), ("222.1", b"imported"), ("111:0", b"current")] {
+            sqlx::query("INSERT INTO device_sessions (our_jid, their_id, session) VALUES ('me', ?, ?)")
+                .bind(their_id)
+                .bind(session)
+                .execute(db.pool())
+                .await
+                .unwrap();
+        }
+        
+        run_migrations(db.pool()).await.unwrap();
+        let sessions: Vec<(String, Vec<u8>)> = sqlx::query_as("SELECT their_id, session FROM device_sessions ORDER BY their_id")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(sessions, vec![
+            ("111:0".to_string(), b"current".to_vec()),
+            ("222:1".to_string(), b"imported".to_vec()),
+        ]);
+        
+        // Reverting restores the rows as they were
+        migrate_to(db.pool(), 8).await.unwrap();
+        let sessions: Vec<(String, Vec<u8>)> = sqlx::query_as("SELECT their_id, session FROM device_sessions ORDER BY their_id")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(sessions, vec![
+            ("111.0".to_string(), b"old".to_vec()),
+            ("111:0".to_string(), b"current".to_vec()),
+            ("222.1".to_string(), b"imported".to_vec()),
+        ]);
+        
+        db.close().await;
+    }
+    
     #[tokio::test]
     async fn test_database_validation() {
         let db = create_test_db().await;
@@ -4,9 +4,12 @@ pub mod schema;
 pub mod sqlite;
 pub mod migrations;
 pub mod pool;
+pub mod dump;
+pub mod archive;
 
 use crate::error::{Error, Result};
 use sqlx::{Pool, Sqlite, Row};
+use std::path::Path;
 
 /// Database connection pool type
 pub type DatabasePool = Pool<Sqlite>;
@@ -78,6 +81,46 @@ impl Database {
         migrations::run_migrations(&self.pool).await
     }
     
+    /// Move the schema up or down to a version, see [`migrations::MIGRATIONS`]
+    pub async fn migrate_to(&self, version: i32) -> Result<()> {
+        migrations::migrate_to(&self.pool, version).await
+    }
+    
+    /// Current schema version
+    pub async fn schema_version(&self) -> Result<i32> {
+        migrations::get_current_version(&self.pool).await
+    }
+    
+    /// Run SQLite's integrity and foreign key checks, returning every problem found
+    pub async fn verify_integrity(&self) -> Result<Vec<String>> {
+        migrations::validate_database(&self.pool).await
+    }
+    
+    /// Dump every row as SQL for a backup
+    pub async fn export_dump(&self) -> Result<String> {
+        dump::export_dump(&self.pool).await
+    }
+    
+    /// Replace the contents of the database with a dump from [`Database::export_dump`]
+    pub async fn import_dump(&self, dump: &str) -> Result<()> {
+        dump::import_dump(&self.pool, dump).await
+    }
+    
+    /// Write the whole device state to an archive encrypted with `passphrase`.
+    ///
+    /// The archive holds the credentials, Signal sessions and keys, app state
+    /// keys and contacts, so [`Database::import_encrypted`] on another machine
+    /// continues the session without linking again. Stop the client before
+    /// importing and never run the session on both machines.
+    pub async fn export_encrypted(&self, path: impl AsRef<Path>, passphrase: &str) -> Result<()> {
+        archive::export_encrypted(&self.pool, path.as_ref(), passphrase).await
+    }
+    
+    /// Replace the contents of the database with an archive from [`Database::export_encrypted`]
+    pub async fn import_encrypted(&self, path: impl AsRef<Path>, passphrase: &str) -> Result<()> {
+        archive::import_encrypted(&self.pool, path.as_ref(), passphrase).await
+    }
+    
     /// Get database pool
     pub fn pool(&self) -> &DatabasePool {
         &self.pool
@@ -99,6 +142,23 @@ impl Database {
         Ok(())
     }
     
+    /// Delete everything tied to the logged in account.
+    ///
+    /// Credentials, Signal state and queued messages are always deleted.
+    /// With `keep_messages` the message history, chats, contacts and groups
+    /// stay so they can still be read or exported. Settings always stay.
+    pub async fn wipe_account(&self, keep_messages: bool) -> Result<()> {
+        let history: &[&str] = if keep_messages { &[] } else { HISTORY_TABLES };
+        let mut tx = Transaction::begin(&self.pool).await?;
+        for table in ACCOUNT_TABLES.iter().chain(history) {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut **tx.inner())
+                .await
+                .map_err(|e| Error::Database(format!("Failed to clear {}: {}", table, e)))?;
+        }
+        tx.commit().await
+    }
+    
     /// Get database statistics
     pub async fn get_stats(&self) -> Result<DatabaseStats> {
         let row = sqlx::query("SELECT page_count, page_size FROM pragma_page_count(), pragma_page_size()")
@@ -117,6 +177,40 @@ impl Database {
     }
 }
 
+/// Tables holding the credentials and Signal state of the paired device
+const ACCOUNT_TABLES: &[&str] = &[
+    "devices",
+    "identity_keys",
+    "sessions",
+    "pre_keys",
+    "signed_pre_keys",
+    "group_sessions",
+    "sender_keys",
+    "app_state_sync_keys",
+    "app_state_versions",
+    "app_state_mutation_macs",
+    "device_identity_keys",
+    "device_sessions",
+    "device_pre_keys",
+    "device_sender_keys",
+    "outbox",
+    "scheduled_messages",
+];
+
+/// Tables holding chat history and metadata, in an order respecting foreign keys
+const HISTORY_TABLES: &[&str] = &[
+    "chats",
+    "messages",
+    "media_files",
+    "group_participants",
+    "groups",
+    "contacts",
+    "lid_mappings",
+    "polls",
+    "poll_votes",
+    "decrypt_failures",
+];
+
 /// Database statistics
 #[derive(Debug, Clone)]
 pub struct DatabaseStats {
@@ -202,6 +296,97 @@ mod tests {
         db.close().await;
     }
     
+    #[tokio::test]
+    async fn test_wipe_account() {
+        let db = create_test_database().await;
+        for statement in [
+            "INSERT INTO settings (key, value) VALUES ('theme', 'dark')",
+            "INSERT INTO contacts (jid, name) VALUES ('1@s.whatsapp.net', 'Alice')",
+            "INSERT INTO app_state_sync_keys (key_id, key_data, fingerprint, timestamp) VALUES (x'01', x'02', x'03', 0)",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+        let count = |table: &'static str| {
+            let pool = db.pool.clone();
+            async move {
+                sqlx::query(&format!("SELECT COUNT(*) FROM {}", table))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+                    .get::<i64, _>(0)
+            }
+        };
+        
+        db.wipe_account(true).await.unwrap();
+        assert_eq!(count("app_state_sync_keys").await, 0);
+        assert_eq!(count("contacts").await, 1);
+        
+        db.wipe_account(false).await.unwrap();
+        assert_eq!(count("contacts").await, 0);
+        assert_eq!(count("settings").await, 1);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_dump_roundtrip() {
+        let db = create_test_database().await;
+        for statement in [
+            "INSERT INTO settings (key, value) VALUES ('note', 'it''s\nmulti-line')",
+            "INSERT INTO app_state_sync_keys (key_id, key_data, fingerprint, timestamp) VALUES (x'00ff', x'02', x'03', 7)",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+        assert!(db.verify_integrity().await.unwrap().is_empty());
+        
+        let dump = db.export_dump().await.unwrap();
+        db.wipe_account(false).await.unwrap();
+        sqlx::query("DELETE FROM settings").execute(&db.pool).await.unwrap();
+        
+        db.import_dump(&dump).await.unwrap();
+        let note: String = sqlx::query_scalar("SELECT value FROM settings WHERE key = 'note'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(note, "it's\nmulti-line");
+        let key_id: Vec<u8> = sqlx::query_scalar("SELECT key_id FROM app_state_sync_keys")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(key_id, vec![0x00, 0xff]);
+        
+        // Dumps only load at the schema version they were taken at
+        db.migrate_to(db.schema_version().await.unwrap() - 1).await.unwrap();
+        assert!(db.import_dump(&dump).await.is_err());
+        assert!(db.import_dump("not a dump").await.is_err());
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_encrypted_archive_roundtrip() {
+        let db = create_test_database().await;
+        sqlx::query("INSERT INTO settings (key, value) VALUES ('note', 'moved')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.archive");
+        db.export_encrypted(&path, "passphrase").await.unwrap();
+        
+        let target = create_test_database().await;
+        assert!(matches!(target.import_encrypted(&path, "other").await, Err(Error::Crypto(_))));
+        target.import_encrypted(&path, "passphrase").await.unwrap();
+        let note: String = sqlx::query_scalar("SELECT value FROM settings WHERE key = 'note'")
+            .fetch_one(&target.pool)
+            .await
+            .unwrap();
+        assert_eq!(note, "moved");
+        
+        db.close().await;
+        target.close().await;
+    }
+    
     #[tokio::test]
     async fn test_database_optimization() {
         let db = create_test_database().await;
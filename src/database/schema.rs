@@ -1,7 +1,7 @@
 /// Database schema definitions for WhatsApp client
 
 /// Database schema version
-pub const SCHEMA_VERSION: i32 = 1;
+pub const SCHEMA_VERSION: i32 = 11;
 
 /// SQL statements for creating tables
 pub const CREATE_TABLES: &[&str] = &[
@@ -212,6 +212,207 @@ pub const CREATE_TABLES: &[&str] = &[
     "#,
 ];
 
+/// SQL statements added in schema version 2
+pub const CREATE_TABLES_V2: &[&str] = &[
+    // Messages sent while offline, flushed in `seq` order after reconnecting
+    r#"
+    CREATE TABLE IF NOT EXISTS outbox (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        message_id TEXT NOT NULL UNIQUE,
+        chat_jid TEXT NOT NULL,
+        payload TEXT NOT NULL, -- JSON encoded SendableMessage
+        created_at INTEGER NOT NULL, -- Unix seconds
+        expires_at INTEGER -- Unix seconds, NULL = never
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_outbox_chat ON outbox(chat_jid)",
+];
+
+/// SQL statements added in schema version 3
+pub const CREATE_TABLES_V3: &[&str] = &[
+    // Canonical mapping between hidden user (LID) and phone number users
+    r#"
+    CREATE TABLE IF NOT EXISTS lid_mappings (
+        lid TEXT PRIMARY KEY, -- user part of the @lid JID
+        pn TEXT NOT NULL UNIQUE, -- user part of the @s.whatsapp.net JID
+        updated_at INTEGER NOT NULL -- Unix seconds
+    )
+    "#,
+];
+
+/// SQL statements added in schema version 4
+pub const CREATE_TABLES_V4: &[&str] = &[
+    // Names from app state contact actions, history sync and business profiles
+    "ALTER TABLE contacts ADD COLUMN first_name TEXT",
+    "ALTER TABLE contacts ADD COLUMN full_name TEXT",
+    "ALTER TABLE contacts ADD COLUMN business_name TEXT",
+];
+
+/// SQL statements added in schema version 5
+pub const CREATE_TABLES_V5: &[&str] = &[
+    // App state sync keys shared by the primary device
+    r#"
+    CREATE TABLE IF NOT EXISTS app_state_sync_keys (
+        key_id BLOB PRIMARY KEY,
+        key_data BLOB NOT NULL,
+        fingerprint BLOB NOT NULL,
+        timestamp INTEGER NOT NULL -- Unix milliseconds, as sent by the phone
+    )
+    "#,
+];
+
+/// SQL statements added in schema version 6
+pub const CREATE_TABLES_V6: &[&str] = &[
+    // Signal state keyed by the device it belongs to, see `store::Container`
+    r#"
+    CREATE TABLE IF NOT EXISTS device_identity_keys (
+        our_jid TEXT NOT NULL,
+        their_id TEXT NOT NULL, -- Signal address, user:device
+        identity BLOB NOT NULL,
+        PRIMARY KEY (our_jid, their_id)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS device_sessions (
+        our_jid TEXT NOT NULL,
+        their_id TEXT NOT NULL,
+        session BLOB NOT NULL,
+        PRIMARY KEY (our_jid, their_id)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS device_pre_keys (
+        our_jid TEXT NOT NULL,
+        key_id INTEGER NOT NULL,
+        key BLOB NOT NULL,
+        uploaded BOOLEAN NOT NULL DEFAULT FALSE,
+        PRIMARY KEY (our_jid, key_id)
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS device_sender_keys (
+        our_jid TEXT NOT NULL,
+        chat_id TEXT NOT NULL,
+        sender_id TEXT NOT NULL,
+        sender_key BLOB NOT NULL,
+        PRIMARY KEY (our_jid, chat_id, sender_id)
+    )
+    "#,
+];
+
+/// SQL statements added in schema version 7
+pub const CREATE_TABLES_V7: &[&str] = &[
+    // Messages sent at a later time, see `Client::schedule_message`
+    r#"
+    CREATE TABLE IF NOT EXISTS scheduled_messages (
+        id TEXT PRIMARY KEY, -- ID the message is sent with
+        chat_jid TEXT NOT NULL,
+        payload TEXT NOT NULL, -- JSON encoded SendableMessage
+        send_at INTEGER NOT NULL, -- Unix seconds
+        created_at INTEGER NOT NULL -- Unix seconds
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_scheduled_messages_send_at ON scheduled_messages(send_at)",
+];
+
+/// SQL statements added in schema version 8
+pub const CREATE_TABLES_V8: &[&str] = &[
+    // Version and LT-Hash of each app state collection, see `appstate::HashState`
+    r#"
+    CREATE TABLE IF NOT EXISTS app_state_versions (
+        name TEXT PRIMARY KEY, -- collection, e.g. regular_high
+        version INTEGER NOT NULL,
+        hash BLOB NOT NULL
+    )
+    "#,
+    // Value MAC of every index currently set, needed to update the LT-Hash
+    r#"
+    CREATE TABLE IF NOT EXISTS app_state_mutation_macs (
+        name TEXT NOT NULL,
+        index_mac BLOB NOT NULL,
+        value_mac BLOB NOT NULL,
+        PRIMARY KEY (name, index_mac)
+    )
+    "#,
+];
+
+/// SQL statements added in schema version 9
+pub const CREATE_TABLES_V9: &[&str] = &[
+    // Older whatsmeow imports wrote Signal addresses as user.device, the
+    // runtime reads user:device. Rows the runtime already wrote win. The
+    // user.device rows are kept in v9_dotted_* tables so the step can be
+    // reverted; `converted` marks rows whose user:device copy is new.
+    r#"
+    CREATE TABLE IF NOT EXISTS v9_dotted_identity_keys AS
+    SELECT d.*, NOT EXISTS (
+        SELECT 1 FROM device_identity_keys c WHERE c.our_jid = d.our_jid AND c.their_id = REPLACE(d.their_id, '.', ':')
+    ) AS converted
+    FROM device_identity_keys d WHERE d.their_id LIKE '%.%'
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS v9_dotted_sessions AS
+    SELECT d.*, NOT EXISTS (
+        SELECT 1 FROM device_sessions c WHERE c.our_jid = d.our_jid AND c.their_id = REPLACE(d.their_id, '.', ':')
+    ) AS converted
+    FROM device_sessions d WHERE d.their_id LIKE '%.%'
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS v9_dotted_sender_keys AS
+    SELECT d.*, NOT EXISTS (
+        SELECT 1 FROM device_sender_keys c
+        WHERE c.our_jid = d.our_jid AND c.chat_id = d.chat_id AND c.sender_id = REPLACE(d.sender_id, '.', ':')
+    ) AS converted
+    FROM device_sender_keys d WHERE d.sender_id LIKE '%.%'
+    "#,
+    "UPDATE OR IGNORE device_identity_keys SET their_id = REPLACE(their_id, '.', ':') WHERE their_id LIKE '%.%'",
+    "DELETE FROM device_identity_keys WHERE their_id LIKE '%.%'",
+    "UPDATE OR IGNORE device_sessions SET their_id = REPLACE(their_id, '.', ':') WHERE their_id LIKE '%.%'",
+    "DELETE FROM device_sessions WHERE their_id LIKE '%.%'",
+    "UPDATE OR IGNORE device_sender_keys SET sender_id = REPLACE(sender_id, '.', ':') WHERE sender_id LIKE '%.%'",
+    "DELETE FROM device_sender_keys WHERE sender_id LIKE '%.%'",
+];
+
+/// SQL statements added in schema version 10
+pub const CREATE_TABLES_V10: &[&str] = &[
+    // Polls whose votes are tallied, see `messaging::PollResultsTracker`
+    r#"
+    CREATE TABLE IF NOT EXISTS polls (
+        chat_jid TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        message_key TEXT NOT NULL, -- JSON encoded MessageKey
+        poll TEXT NOT NULL, -- JSON encoded PollMessage, including the vote secret
+        PRIMARY KEY (chat_jid, message_id)
+    )
+    "#,
+    // Latest vote of each voter, options resolved to their names
+    r#"
+    CREATE TABLE IF NOT EXISTS poll_votes (
+        chat_jid TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        voter TEXT NOT NULL,
+        selected TEXT NOT NULL, -- JSON encoded option names
+        sent_at INTEGER, -- Unix milliseconds of the vote
+        PRIMARY KEY (chat_jid, message_id, voter)
+    )
+    "#,
+];
+
+/// SQL statements added in schema version 11
+pub const CREATE_TABLES_V11: &[&str] = &[
+    // Messages that failed to decrypt, see `decrypt_retry`
+    r#"
+    CREATE TABLE IF NOT EXISTS decrypt_failures (
+        sender TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        retry_count INTEGER NOT NULL DEFAULT 0, -- Retry receipts sent so far
+        last_retry_at INTEGER, -- Unix seconds of the last retry receipt
+        placeholder TEXT, -- JSON encoded MessageInfo once retries are exhausted
+        reason TEXT,
+        PRIMARY KEY (sender, message_id)
+    )
+    "#,
+];
+
 /// SQL statements for creating indexes
 pub const CREATE_INDEXES: &[&str] = &[
     "CREATE INDEX IF NOT EXISTS idx_messages_chat_timestamp ON messages(chat_jid, timestamp)",
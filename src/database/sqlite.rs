@@ -1,18 +1,25 @@
 /// SQLite implementations for persistent storage
 
 use crate::{
+    appstate::{HashState, PatchName},
     error::{Error, Result},
-    types::JID,
-    store::{DeviceStore, DeviceData},
+    types::{AppStateSyncKey, AppStateSyncKeyData, JID, MessageInfo, MessageKey, PollMessage, SendableMessage},
+    decrypt_retry::StoredDecryptFailure,
+    messaging::{StoredPoll, StoredPollVote},
+    store::{
+        address_user, AppStateKeyStore, DeviceData, DeviceStore, IdentityStore, PreKeyRecordStore,
+        SenderKeyStore, SessionRecordStore, Store,
+    },
     group::types::{GroupInfo, GroupSettings},
 };
 use async_trait::async_trait;
 use sqlx::{SqlitePool, Row};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use chrono::{DateTime, Utc};
 
-/// SQLite implementation of DeviceStore
+/// SQLite implementation of DeviceStore and the per-device [`Store`]
 pub struct SqliteDeviceStore {
     pool: SqlitePool,
 }
@@ -21,6 +28,16 @@ impl SqliteDeviceStore {
     pub fn new(pool: SqlitePool) -> Self {
         Self { pool }
     }
+    
+    /// Write the whole device state to an encrypted archive, see [`Database::export_encrypted`](super::Database::export_encrypted)
+    pub async fn export_encrypted(&self, path: impl AsRef<std::path::Path>, passphrase: &str) -> Result<()> {
+        super::archive::export_encrypted(&self.pool, path.as_ref(), passphrase).await
+    }
+    
+    /// Restore the device state from an archive made by [`SqliteDeviceStore::export_encrypted`]
+    pub async fn import_encrypted(&self, path: impl AsRef<std::path::Path>, passphrase: &str) -> Result<()> {
+        super::archive::import_encrypted(&self.pool, path.as_ref(), passphrase).await
+    }
 }
 
 #[async_trait]
@@ -216,6 +233,11 @@ impl SqliteGroupStore {
                 created_at: created_at.into(),
                 settings,
                 invite_link,
+                is_parent: false,
+                is_default_sub_group: false,
+                linked_parent_jid: None,
+                member_add_mode: Default::default(),
+                join_approval_mode: false,
             }))
         } else {
             Ok(None)
@@ -288,15 +310,19 @@ impl SqliteContactStore {
         Self { pool }
     }
     
-    /// Store contact information
+    /// Store contact information, keeping names learned from other sources
     pub async fn store_contact(&self, jid: &JID, name: Option<&str>, phone: Option<&str>) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT OR REPLACE INTO contacts (jid, name, phone_number)
+            INSERT INTO contacts (jid, name, phone_number)
             VALUES (?, ?, ?)
+            ON CONFLICT(jid) DO UPDATE SET
+                name = excluded.name,
+                phone_number = excluded.phone_number,
+                updated_at = CURRENT_TIMESTAMP
             "#
         )
-        .bind(&jid.to_string())
+        .bind(jid.to_non_ad().to_string())
         .bind(name)
         .bind(phone)
         .execute(&self.pool)
@@ -306,211 +332,1248 @@ impl SqliteContactStore {
         Ok(())
     }
     
-    /// Load contact information
-    pub async fn load_contact(&self, jid: &JID) -> Result<Option<ContactInfo>> {
-        let row = sqlx::query(
-            "SELECT name, notify_name, phone_number, status_text, last_seen FROM contacts WHERE jid = ?"
-        )
-        .bind(&jid.to_string())
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(|e| Error::Database(format!("Failed to load contact: {}", e)))?;
+    /// Store the push name a user set for themselves.
+    ///
+    /// Returns whether the push name changed.
+    pub async fn update_push_name(&self, jid: &JID, push_name: &str) -> Result<bool> {
+        let jid = jid.to_non_ad().to_string();
+        let previous: Option<Option<String>> = sqlx::query_scalar("SELECT notify_name FROM contacts WHERE jid = ?")
+            .bind(&jid)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load push name: {}", e)))?;
         
-        if let Some(row) = row {
-            Ok(Some(ContactInfo {
-                jid: jid.clone(),
-                name: row.get(0),
-                notify_name: row.get(1),
-                phone_number: row.get(2),
-                status_text: row.get(3),
-                last_seen: row.get(4),
-            }))
-        } else {
-            Ok(None)
+        if previous.flatten().as_deref() == Some(push_name) {
+            return Ok(false);
         }
+        
+        self.upsert_column(&jid, "notify_name", Some(push_name)).await?;
+        Ok(true)
     }
     
-    /// List all contacts
-    pub async fn list_contacts(&self) -> Result<Vec<ContactInfo>> {
-        let rows = sqlx::query(
-            "SELECT jid, name, notify_name, phone_number, status_text, last_seen FROM contacts"
+    /// Store the verified business name of a user
+    pub async fn update_business_name(&self, jid: &JID, business_name: &str) -> Result<()> {
+        self.upsert_column(&jid.to_non_ad().to_string(), "business_name", Some(business_name)).await
+    }
+    
+    /// Store the first and full name from the address book, e.g. from a contact action
+    pub async fn update_contact_names(&self, jid: &JID, first_name: Option<&str>, full_name: Option<&str>) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO contacts (jid, first_name, full_name)
+            VALUES (?, ?, ?)
+            ON CONFLICT(jid) DO UPDATE SET
+                first_name = excluded.first_name,
+                full_name = excluded.full_name,
+                updated_at = CURRENT_TIMESTAMP
+            "#
         )
-        .fetch_all(&self.pool)
+        .bind(jid.to_non_ad().to_string())
+        .bind(first_name)
+        .bind(full_name)
+        .execute(&self.pool)
         .await
-        .map_err(|e| Error::Database(format!("Failed to list contacts: {}", e)))?;
+        .map_err(|e| Error::Database(format!("Failed to store contact names: {}", e)))?;
         
-        let mut contacts = Vec::new();
-        for row in rows {
-            let jid_str: String = row.get(0);
-            let jid = JID::parse(&jid_str)?;
-            
-            contacts.push(ContactInfo {
-                jid,
-                name: row.get(1),
-                notify_name: row.get(2),
-                phone_number: row.get(3),
-                status_text: row.get(4),
-                last_seen: row.get(5),
-            });
+        Ok(())
+    }
+    
+    /// Store the push names and LID mappings of a history sync blob.
+    ///
+    /// Returns the number of stored push names.
+    pub async fn apply_history_sync(&self, history: &crate::proto::wa_history_sync::HistorySync) -> Result<usize> {
+        let mut stored = 0;
+        for pushname in &history.pushnames {
+            let (Some(id), Some(name)) = (&pushname.id, &pushname.pushname) else {
+                continue;
+            };
+            let Ok(jid) = JID::parse(id) else {
+                continue;
+            };
+            self.update_push_name(&jid, name).await?;
+            stored += 1;
+        }
+        
+        let lid_store = SqliteLidStore::new(self.pool.clone());
+        for mapping in &history.phone_number_to_lid_mappings {
+            let (Some(pn), Some(lid)) = (&mapping.pn_jid, &mapping.lid_jid) else {
+                continue;
+            };
+            if let (Ok(pn), Ok(lid)) = (JID::parse(pn), JID::parse(lid)) {
+                lid_store.put_mapping(&lid.to_non_ad(), &pn.to_non_ad()).await?;
+            }
         }
         
-        Ok(contacts)
+        Ok(stored)
+    }
+    
+    async fn upsert_column(&self, jid: &str, column: &str, value: Option<&str>) -> Result<()> {
+        // Column names are internal constants, never user input
+        sqlx::query(&format!(
+            "INSERT INTO contacts (jid, {column}) VALUES (?, ?) \
+             ON CONFLICT(jid) DO UPDATE SET {column} = excluded.{column}, updated_at = CURRENT_TIMESTAMP"
+        ))
+        .bind(jid)
+        .bind(value)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to update contact {}: {}", column, e)))?;
+        
+        Ok(())
+    }
+    
+    /// Load contact information
+    pub async fn load_contact(&self, jid: &JID) -> Result<Option<ContactInfo>> {
+        let jid = jid.to_non_ad();
+        let row = sqlx::query(&format!("{} WHERE c.jid = ?", CONTACT_QUERY))
+            .bind(jid.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load contact: {}", e)))?;
+        
+        row.map(|row| contact_from_row(&row)).transpose()
+    }
+    
+    /// Delete a contact
+    pub async fn delete_contact(&self, jid: &JID) -> Result<()> {
+        sqlx::query("DELETE FROM contacts WHERE jid = ?")
+            .bind(jid.to_non_ad().to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to delete contact: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// List the phone numbers of all contacts
+    pub async fn list_phone_numbers(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT phone_number FROM contacts WHERE phone_number IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list phone numbers: {}", e)))?;
+        
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+    
+    /// List all contacts
+    pub async fn list_contacts(&self) -> Result<Vec<ContactInfo>> {
+        let rows = sqlx::query(CONTACT_QUERY)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list contacts: {}", e)))?;
+        
+        rows.iter().map(contact_from_row).collect()
     }
 }
 
+/// Contact columns joined with the LID of phone number contacts
+const CONTACT_QUERY: &str = r#"
+    SELECT c.jid, c.name, c.notify_name, c.phone_number, c.status_text, c.last_seen,
+           c.first_name, c.full_name, c.business_name, m.lid
+    FROM contacts c
+    LEFT JOIN lid_mappings m ON c.jid = m.pn || '@s.whatsapp.net'
+"#;
+
+fn contact_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<ContactInfo> {
+    let jid: String = row.get(0);
+    let lid: Option<String> = row.get(9);
+    
+    Ok(ContactInfo {
+        jid: JID::parse(&jid)?,
+        name: row.get(1),
+        notify_name: row.get(2),
+        phone_number: row.get(3),
+        status_text: row.get(4),
+        last_seen: row.get(5),
+        first_name: row.get(6),
+        full_name: row.get(7),
+        business_name: row.get(8),
+        lid: lid.map(|lid| JID::new_lid(&lid)),
+    })
+}
+
 /// Contact information structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContactInfo {
     pub jid: JID,
+    /// Name uploaded by us in a contact sync
     pub name: Option<String>,
+    /// Push name the user set for themselves
     pub notify_name: Option<String>,
     pub phone_number: Option<String>,
     pub status_text: Option<String>,
     pub last_seen: Option<DateTime<Utc>>,
+    /// Names from the address book of the primary device
+    pub first_name: Option<String>,
+    pub full_name: Option<String>,
+    /// Verified name of a business account
+    pub business_name: Option<String>,
+    /// Hidden user JID of the contact, if the server announced it
+    pub lid: Option<JID>,
 }
 
-/// Settings store for key-value configuration
-pub struct SqliteSettingsStore {
+impl ContactInfo {
+    /// Best name to show for the contact
+    pub fn display_name(&self) -> Option<&str> {
+        self.full_name.as_deref()
+            .or(self.first_name.as_deref())
+            .or(self.name.as_deref())
+            .or(self.business_name.as_deref())
+            .or(self.notify_name.as_deref())
+    }
+}
+
+/// A message waiting in the outbox for the connection to come back
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub message_id: String,
+    pub chat: JID,
+    pub message: SendableMessage,
+    /// Unix seconds
+    pub created_at: i64,
+    /// Unix seconds after which the message is dropped instead of sent
+    pub expires_at: Option<i64>,
+}
+
+impl OutboxEntry {
+    /// Check whether the entry outlived its TTL
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// SQLite-based outbox for messages sent while offline
+pub struct SqliteOutboxStore {
     pool: SqlitePool,
 }
 
-impl SqliteSettingsStore {
+impl SqliteOutboxStore {
     pub fn new(pool: SqlitePool) -> Self {
         Self { pool }
     }
     
-    /// Store a setting
-    pub async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+    /// Append a message to the outbox; re-adding a stored message is a no-op
+    pub async fn enqueue(&self, entry: &OutboxEntry) -> Result<()> {
+        let payload = serde_json::to_string(&entry.message)?;
+        
         sqlx::query(
-            "INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)"
+            r#"
+            INSERT OR IGNORE INTO outbox (message_id, chat_jid, payload, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#
         )
-        .bind(key)
-        .bind(value)
+        .bind(&entry.message_id)
+        .bind(entry.chat.to_string())
+        .bind(payload)
+        .bind(entry.created_at)
+        .bind(entry.expires_at)
         .execute(&self.pool)
         .await
-        .map_err(|e| Error::Database(format!("Failed to set setting: {}", e)))?;
+        .map_err(|e| Error::Database(format!("Failed to enqueue outbox message: {}", e)))?;
         
         Ok(())
     }
     
-    /// Get a setting
-    pub async fn get_setting(&self, key: &str) -> Result<Option<String>> {
-        let value: Option<String> = sqlx::query_scalar(
-            "SELECT value FROM settings WHERE key = ?"
+    /// List all stored messages in the order they were sent
+    pub async fn list(&self) -> Result<Vec<OutboxEntry>> {
+        let rows = sqlx::query(
+            "SELECT message_id, chat_jid, payload, created_at, expires_at FROM outbox ORDER BY seq"
         )
-        .bind(key)
-        .fetch_optional(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .map_err(|e| Error::Database(format!("Failed to get setting: {}", e)))?;
+        .map_err(|e| Error::Database(format!("Failed to list outbox: {}", e)))?;
         
-        Ok(value)
+        let mut entries = Vec::new();
+        for row in rows {
+            let chat: String = row.get(1);
+            let payload: String = row.get(2);
+            
+            entries.push(OutboxEntry {
+                message_id: row.get(0),
+                chat: JID::parse(&chat)?,
+                message: serde_json::from_str(&payload)?,
+                created_at: row.get(3),
+                expires_at: row.get(4),
+            });
+        }
+        
+        Ok(entries)
     }
     
-    /// Delete a setting
-    pub async fn delete_setting(&self, key: &str) -> Result<()> {
-        sqlx::query("DELETE FROM settings WHERE key = ?")
-            .bind(key)
+    /// Remove a message from the outbox
+    pub async fn remove(&self, message_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM outbox WHERE message_id = ?")
+            .bind(message_id)
             .execute(&self.pool)
             .await
-            .map_err(|e| Error::Database(format!("Failed to delete setting: {}", e)))?;
+            .map_err(|e| Error::Database(format!("Failed to remove outbox message: {}", e)))?;
         
         Ok(())
     }
     
-    /// Get all settings
-    pub async fn get_all_settings(&self) -> Result<HashMap<String, String>> {
-        let rows = sqlx::query("SELECT key, value FROM settings")
-            .fetch_all(&self.pool)
+    /// Number of messages waiting in the outbox
+    pub async fn len(&self) -> Result<usize> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM outbox")
+            .fetch_one(&self.pool)
             .await
-            .map_err(|e| Error::Database(format!("Failed to get all settings: {}", e)))?;
-        
-        let mut settings = HashMap::new();
-        for row in rows {
-            let key: String = row.get(0);
-            let value: String = row.get(1);
-            settings.insert(key, value);
-        }
+            .map_err(|e| Error::Database(format!("Failed to count outbox: {}", e)))?;
         
-        Ok(settings)
+        Ok(count as usize)
+    }
+    
+    /// Check whether the outbox is empty
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::database::Database;
-    
-    async fn create_test_db() -> Database {
-        let config = crate::database::DatabaseConfig {
-            database_url: "sqlite::memory:".to_string(),
-            max_connections: 5,
-            connection_timeout: 10,
-            enable_wal: false,
-        };
-        
-        Database::new(config).await.unwrap()
+/// A message waiting to be sent at a later time
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledMessage {
+    /// ID the message is sent with
+    pub id: String,
+    pub chat: JID,
+    pub message: SendableMessage,
+    /// Unix seconds the message is due at
+    pub send_at: i64,
+    /// Unix seconds
+    pub created_at: i64,
+}
+
+impl ScheduledMessage {
+    /// Check whether the message should be sent
+    pub fn is_due(&self, now: i64) -> bool {
+        self.send_at <= now
+    }
+}
+
+/// SQLite-based store of scheduled messages
+pub struct SqliteScheduledMessageStore {
+    pool: SqlitePool,
+}
+
+impl SqliteScheduledMessageStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
     }
     
-    #[tokio::test]
-    async fn test_device_store() {
-        let db = create_test_db().await;
-        let store = SqliteDeviceStore::new(db.pool().clone());
+    /// Store a message, replacing a scheduled message with the same ID
+    pub async fn add(&self, message: &ScheduledMessage) -> Result<()> {
+        let payload = serde_json::to_string(&message.message)?;
         
-        let device_data = DeviceData {
-            jid: JID::new("test".to_string(), "s.whatsapp.net".to_string()),
-            registration_id: 12345,
-            noise_key: vec![1, 2, 3],
-            identity_key: vec![4, 5, 6],
-            signed_pre_key: vec![7, 8, 9],
-            signed_pre_key_id: 1,
-            signed_pre_key_signature: vec![10, 11, 12],
-        };
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO scheduled_messages (id, chat_jid, payload, send_at, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(&message.id)
+        .bind(message.chat.to_string())
+        .bind(payload)
+        .bind(message.send_at)
+        .bind(message.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to schedule message: {}", e)))?;
         
-        // Test save and load
-        store.save_device(&device_data).await.unwrap();
-        assert!(store.is_registered().await.unwrap());
+        Ok(())
+    }
+    
+    /// List all scheduled messages, the earliest first
+    pub async fn list(&self) -> Result<Vec<ScheduledMessage>> {
+        self.list_until(i64::MAX).await
+    }
+    
+    /// List the messages due at `now`, the earliest first
+    pub async fn due(&self, now: i64) -> Result<Vec<ScheduledMessage>> {
+        self.list_until(now).await
+    }
+    
+    async fn list_until(&self, until: i64) -> Result<Vec<ScheduledMessage>> {
+        let rows = sqlx::query(
+            "SELECT id, chat_jid, payload, send_at, created_at FROM scheduled_messages WHERE send_at <= ? ORDER BY send_at, created_at"
+        )
+        .bind(until)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to list scheduled messages: {}", e)))?;
         
-        let loaded = store.load_device().await.unwrap().unwrap();
-        assert_eq!(loaded.jid, device_data.jid);
-        assert_eq!(loaded.registration_id, device_data.registration_id);
+        let mut messages = Vec::new();
+        for row in rows {
+            let chat: String = row.get(1);
+            let payload: String = row.get(2);
+            
+            messages.push(ScheduledMessage {
+                id: row.get(0),
+                chat: JID::parse(&chat)?,
+                message: serde_json::from_str(&payload)?,
+                send_at: row.get(3),
+                created_at: row.get(4),
+            });
+        }
         
-        // Test delete
-        store.delete_device().await.unwrap();
-        assert!(!store.is_registered().await.unwrap());
+        Ok(messages)
+    }
+    
+    /// Remove a scheduled message, returning whether it existed
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM scheduled_messages WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to remove scheduled message: {}", e)))?;
         
-        db.close().await;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// SQLite-based store of tracked polls and their votes
+pub struct SqlitePollStore {
+    pool: SqlitePool,
+}
+
+impl SqlitePollStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
     }
     
-    #[tokio::test]
-    async fn test_settings_store() {
-        let db = create_test_db().await;
-        let store = SqliteSettingsStore::new(db.pool().clone());
+    fn poll_id(key: &MessageKey) -> (String, &str) {
+        (key.remote_jid.to_non_ad().to_string(), &key.id)
+    }
+    
+    /// Store a poll; storing a known poll again keeps its votes
+    pub async fn put_poll(&self, key: &MessageKey, poll: &PollMessage) -> Result<()> {
+        let (chat, id) = Self::poll_id(key);
         
-        // Test set and get
-        store.set_setting("test_key", "test_value").await.unwrap();
-        let value = store.get_setting("test_key").await.unwrap();
-        assert_eq!(value, Some("test_value".to_string()));
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO polls (chat_jid, message_id, message_key, poll)
+            VALUES (?, ?, ?, ?)
+            "#
+        )
+        .bind(chat)
+        .bind(id)
+        .bind(serde_json::to_string(key)?)
+        .bind(serde_json::to_string(poll)?)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to store poll: {}", e)))?;
         
-        // Test get non-existent
-        let none_value = store.get_setting("non_existent").await.unwrap();
-        assert_eq!(none_value, None);
+        Ok(())
+    }
+    
+    /// Store the latest vote of a participant on a poll
+    pub async fn put_vote(&self, key: &MessageKey, vote: &StoredPollVote) -> Result<()> {
+        let (chat, id) = Self::poll_id(key);
+        let sent_at = vote.timestamp
+            .map(|timestamp| timestamp.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as i64);
         
-        // Test delete
-        store.delete_setting("test_key").await.unwrap();
-        let deleted_value = store.get_setting("test_key").await.unwrap();
-        assert_eq!(deleted_value, None);
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO poll_votes (chat_jid, message_id, voter, selected, sent_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(chat)
+        .bind(id)
+        .bind(vote.voter.to_string())
+        .bind(serde_json::to_string(&vote.selected)?)
+        .bind(sent_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to store poll vote: {}", e)))?;
         
-        db.close().await;
+        Ok(())
     }
     
-    #[tokio::test]
-    async fn test_contact_store() {
-        let db = create_test_db().await;
-        let store = SqliteContactStore::new(db.pool().clone());
-        
-        let jid = JID::new("contact".to_string(), "s.whatsapp.net".to_string());
+    /// Load all polls with their votes
+    pub async fn load(&self) -> Result<Vec<StoredPoll>> {
+        let rows = sqlx::query("SELECT chat_jid, message_id, message_key, poll FROM polls")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load polls: {}", e)))?;
         
-        // Test store and load
+        let mut polls = HashMap::new();
+        for row in rows {
+            let key: String = row.get(2);
+            let poll: String = row.get(3);
+            polls.insert((row.get::<String, _>(0), row.get::<String, _>(1)), StoredPoll {
+                key: serde_json::from_str(&key)?,
+                poll: serde_json::from_str(&poll)?,
+                votes: Vec::new(),
+            });
+        }
+        
+        let rows = sqlx::query("SELECT chat_jid, message_id, voter, selected, sent_at FROM poll_votes ORDER BY voter")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load poll votes: {}", e)))?;
+        
+        for row in rows {
+            let Some(poll) = polls.get_mut(&(row.get::<String, _>(0), row.get::<String, _>(1))) else {
+                continue;
+            };
+            let voter: String = row.get(2);
+            let selected: String = row.get(3);
+            let sent_at: Option<i64> = row.get(4);
+            poll.votes.push(StoredPollVote {
+                voter: JID::parse(&voter)?,
+                selected: serde_json::from_str(&selected)?,
+                timestamp: sent_at.map(|ms| std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms.max(0) as u64)),
+            });
+        }
+        
+        Ok(polls.into_values().collect())
+    }
+    
+    /// Remove a poll and its votes, returning whether it existed
+    pub async fn remove(&self, key: &MessageKey) -> Result<bool> {
+        let (chat, id) = Self::poll_id(key);
+        
+        sqlx::query("DELETE FROM poll_votes WHERE chat_jid = ? AND message_id = ?")
+            .bind(&chat)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to remove poll votes: {}", e)))?;
+        let result = sqlx::query("DELETE FROM polls WHERE chat_jid = ? AND message_id = ?")
+            .bind(&chat)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to remove poll: {}", e)))?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// SQLite-based store of messages that failed to decrypt: the retry
+/// receipts sent for them and the placeholders of those given up on
+pub struct SqliteDecryptFailureStore {
+    pool: SqlitePool,
+}
+
+impl SqliteDecryptFailureStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    
+    /// Record that retry receipt number `count` was sent for a message
+    pub async fn put_retry(&self, sender: &str, message_id: &str, count: u32) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO decrypt_failures (sender, message_id, retry_count, last_retry_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (sender, message_id) DO UPDATE SET
+                retry_count = excluded.retry_count,
+                last_retry_at = excluded.last_retry_at
+            "#
+        )
+        .bind(sender)
+        .bind(message_id)
+        .bind(count as i64)
+        .bind(Utc::now().timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to store decrypt retry: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Store the placeholder of a message whose retries are exhausted
+    pub async fn put_placeholder(&self, sender: &str, placeholder: &MessageInfo, reason: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO decrypt_failures (sender, message_id, placeholder, reason)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (sender, message_id) DO UPDATE SET
+                placeholder = excluded.placeholder,
+                reason = excluded.reason
+            "#
+        )
+        .bind(sender)
+        .bind(&placeholder.id)
+        .bind(serde_json::to_string(placeholder)?)
+        .bind(reason)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to store undecryptable message: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Load all recorded failures
+    pub async fn load(&self) -> Result<Vec<StoredDecryptFailure>> {
+        let rows = sqlx::query("SELECT sender, message_id, retry_count, placeholder, reason FROM decrypt_failures")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load decrypt failures: {}", e)))?;
+        
+        rows.into_iter()
+            .map(|row| {
+                let placeholder: Option<String> = row.get(3);
+                Ok(StoredDecryptFailure {
+                    sender: row.get(0),
+                    message_id: row.get(1),
+                    retries: row.get::<i64, _>(2) as u32,
+                    placeholder: placeholder.map(|p| serde_json::from_str(&p)).transpose()?,
+                    reason: row.get(4),
+                })
+            })
+            .collect()
+    }
+    
+    /// Forget a message that was decrypted after all, returning whether it was recorded
+    pub async fn remove(&self, sender: &str, message_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM decrypt_failures WHERE sender = ? AND message_id = ?")
+            .bind(sender)
+            .bind(message_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to remove decrypt failure: {}", e)))?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// SQLite-based mapping between hidden user (LID) and phone number JIDs.
+///
+/// Only the user parts are stored, lookups keep the device of the given JID
+/// so a message from `lid:device` can be attributed to `pn:device`.
+pub struct SqliteLidStore {
+    pool: SqlitePool,
+}
+
+impl SqliteLidStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    
+    /// Store a mapping announced by the server, replacing older mappings of either side
+    pub async fn put_mapping(&self, lid: &JID, pn: &JID) -> Result<()> {
+        if !lid.is_lid() || !pn.is_user() {
+            return Err(Error::InvalidJID(format!("Invalid LID mapping {} -> {}", lid, pn)));
+        }
+        
+        let mut tx = self.pool.begin().await
+            .map_err(|e| Error::Database(format!("Failed to begin transaction: {}", e)))?;
+        
+        sqlx::query("DELETE FROM lid_mappings WHERE lid = ? OR pn = ?")
+            .bind(&lid.user)
+            .bind(&pn.user)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to replace LID mapping: {}", e)))?;
+        
+        sqlx::query("INSERT INTO lid_mappings (lid, pn, updated_at) VALUES (?, ?, ?)")
+            .bind(&lid.user)
+            .bind(&pn.user)
+            .bind(Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save LID mapping: {}", e)))?;
+        
+        tx.commit().await
+            .map_err(|e| Error::Database(format!("Failed to commit transaction: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Get the phone number JID of a LID
+    pub async fn get_pn(&self, lid: &JID) -> Result<Option<JID>> {
+        let pn: Option<String> = sqlx::query_scalar("SELECT pn FROM lid_mappings WHERE lid = ?")
+            .bind(&lid.user)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load LID mapping: {}", e)))?;
+        
+        Ok(pn.map(|user| JID { user, server: crate::types::DEFAULT_USER_SERVER.to_string(), ..lid.clone() }))
+    }
+    
+    /// Get the LID of a phone number JID
+    pub async fn get_lid(&self, pn: &JID) -> Result<Option<JID>> {
+        let lid: Option<String> = sqlx::query_scalar("SELECT lid FROM lid_mappings WHERE pn = ?")
+            .bind(&pn.user)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load LID mapping: {}", e)))?;
+        
+        Ok(lid.map(|user| JID { user, server: crate::types::HIDDEN_USER_SERVER.to_string(), ..pn.clone() }))
+    }
+    
+    /// Map a JID to its phone number form, leaving other JIDs unchanged
+    pub async fn to_pn(&self, jid: &JID) -> Result<JID> {
+        if !jid.is_lid() {
+            return Ok(jid.clone());
+        }
+        Ok(self.get_pn(jid).await?.unwrap_or_else(|| jid.clone()))
+    }
+}
+
+/// SQLite-based store of the app state sync keys shared by the primary device
+pub struct SqliteAppStateKeyStore {
+    pool: SqlitePool,
+}
+
+impl SqliteAppStateKeyStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    
+    /// Store a key, replacing an older copy with the same ID
+    pub async fn put_key(&self, key: &AppStateSyncKey) -> Result<()> {
+        let timestamp = key.key_data.timestamp
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        
+        sqlx::query(
+            "INSERT OR REPLACE INTO app_state_sync_keys (key_id, key_data, fingerprint, timestamp) VALUES (?, ?, ?, ?)"
+        )
+        .bind(&key.key_id)
+        .bind(&key.key_data.key_data)
+        .bind(&key.key_data.fingerprint)
+        .bind(timestamp)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to save app state sync key: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Get a key by ID
+    pub async fn get_key(&self, key_id: &[u8]) -> Result<Option<AppStateSyncKey>> {
+        let row = sqlx::query("SELECT key_id, key_data, fingerprint, timestamp FROM app_state_sync_keys WHERE key_id = ?")
+            .bind(key_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load app state sync key: {}", e)))?;
+        
+        Ok(row.map(|row| app_state_key_from_row(&row)))
+    }
+    
+    /// Get the most recently created key, used to encrypt outgoing patches
+    pub async fn get_latest_key(&self) -> Result<Option<AppStateSyncKey>> {
+        let row = sqlx::query(
+            "SELECT key_id, key_data, fingerprint, timestamp FROM app_state_sync_keys ORDER BY timestamp DESC, key_id DESC LIMIT 1"
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to load app state sync key: {}", e)))?;
+        
+        Ok(row.map(|row| app_state_key_from_row(&row)))
+    }
+}
+
+#[async_trait]
+impl AppStateKeyStore for SqliteAppStateKeyStore {
+    async fn put_app_state_key(&self, key: &AppStateSyncKey) -> Result<()> {
+        self.put_key(key).await
+    }
+    
+    async fn get_app_state_key(&self, key_id: &[u8]) -> Result<Option<AppStateSyncKey>> {
+        self.get_key(key_id).await
+    }
+    
+    async fn get_latest_app_state_key(&self) -> Result<Option<AppStateSyncKey>> {
+        self.get_latest_key().await
+    }
+}
+
+/// SQLite-based store of the version and LT-Hash of each app state collection
+pub struct SqliteAppStateVersionStore {
+    pool: SqlitePool,
+}
+
+impl SqliteAppStateVersionStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    
+    /// Load the state of a collection, `None` if it was never synced
+    pub async fn get(&self, name: PatchName) -> Result<Option<HashState>> {
+        let row = sqlx::query("SELECT version, hash FROM app_state_versions WHERE name = ?")
+            .bind(name.as_str())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load app state version: {}", e)))?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        
+        let rows = sqlx::query("SELECT index_mac, value_mac FROM app_state_mutation_macs WHERE name = ?")
+            .bind(name.as_str())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load app state mutation MACs: {}", e)))?;
+        
+        Ok(Some(HashState {
+            version: row.get::<i64, _>(0) as u64,
+            hash: row.get(1),
+            value_macs: rows.iter().map(|row| (row.get(0), row.get(1))).collect(),
+        }))
+    }
+    
+    /// Replace the state of a collection
+    pub async fn put(&self, name: PatchName, state: &HashState) -> Result<()> {
+        let mut tx = self.pool.begin().await
+            .map_err(|e| Error::Database(format!("Failed to begin transaction: {}", e)))?;
+        
+        sqlx::query("INSERT OR REPLACE INTO app_state_versions (name, version, hash) VALUES (?, ?, ?)")
+            .bind(name.as_str())
+            .bind(state.version as i64)
+            .bind(&state.hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save app state version: {}", e)))?;
+        sqlx::query("DELETE FROM app_state_mutation_macs WHERE name = ?")
+            .bind(name.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save app state mutation MACs: {}", e)))?;
+        for (index_mac, value_mac) in &state.value_macs {
+            sqlx::query("INSERT INTO app_state_mutation_macs (name, index_mac, value_mac) VALUES (?, ?, ?)")
+                .bind(name.as_str())
+                .bind(index_mac)
+                .bind(value_mac)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to save app state mutation MACs: {}", e)))?;
+        }
+        
+        tx.commit().await
+            .map_err(|e| Error::Database(format!("Failed to commit app state version: {}", e)))
+    }
+    
+    /// Forget the state of a collection, so the next sync starts from a snapshot
+    pub async fn delete(&self, name: PatchName) -> Result<()> {
+        for table in ["app_state_versions", "app_state_mutation_macs"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE name = ?", table))
+                .bind(name.as_str())
+                .execute(&self.pool)
+                .await
+                .map_err(|e| Error::Database(format!("Failed to delete app state version: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Store for SqliteDeviceStore {
+    fn identities(&self, device: &JID) -> Arc<dyn IdentityStore> {
+        Arc::new(SqliteSignalStore::new(self.pool.clone(), device))
+    }
+    
+    fn sessions(&self, device: &JID) -> Arc<dyn SessionRecordStore> {
+        Arc::new(SqliteSignalStore::new(self.pool.clone(), device))
+    }
+    
+    fn pre_keys(&self, device: &JID) -> Arc<dyn PreKeyRecordStore> {
+        Arc::new(SqliteSignalStore::new(self.pool.clone(), device))
+    }
+    
+    fn sender_keys(&self, device: &JID) -> Arc<dyn SenderKeyStore> {
+        Arc::new(SqliteSignalStore::new(self.pool.clone(), device))
+    }
+    
+    /// App state keys come from the primary device, so every companion of
+    /// the account shares them
+    fn app_state_keys(&self, _device: &JID) -> Arc<dyn AppStateKeyStore> {
+        Arc::new(SqliteAppStateKeyStore::new(self.pool.clone()))
+    }
+}
+
+/// SQLite-based Signal state of one of our devices
+pub struct SqliteSignalStore {
+    pool: SqlitePool,
+    our_jid: String,
+}
+
+impl SqliteSignalStore {
+    pub fn new(pool: SqlitePool, device: &JID) -> Self {
+        Self { pool, our_jid: device.to_string() }
+    }
+}
+
+#[async_trait]
+impl IdentityStore for SqliteSignalStore {
+    async fn put_identity(&self, address: &str, key: &[u8]) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO device_identity_keys (our_jid, their_id, identity) VALUES (?, ?, ?)")
+            .bind(&self.our_jid)
+            .bind(address)
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save identity key: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn get_identity(&self, address: &str) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar("SELECT identity FROM device_identity_keys WHERE our_jid = ? AND their_id = ?")
+            .bind(&self.our_jid)
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load identity key: {}", e)))
+    }
+    
+    async fn delete_identity(&self, address: &str) -> Result<()> {
+        sqlx::query("DELETE FROM device_identity_keys WHERE our_jid = ? AND their_id = ?")
+            .bind(&self.our_jid)
+            .bind(address)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to delete identity key: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn all_identities(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        sqlx::query_as("SELECT their_id, identity FROM device_identity_keys WHERE our_jid = ?")
+            .bind(&self.our_jid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list identity keys: {}", e)))
+    }
+}
+
+#[async_trait]
+impl SessionRecordStore for SqliteSignalStore {
+    async fn get_session(&self, address: &str) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar("SELECT session FROM device_sessions WHERE our_jid = ? AND their_id = ?")
+            .bind(&self.our_jid)
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load session: {}", e)))
+    }
+    
+    async fn put_session(&self, address: &str, session: &[u8]) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO device_sessions (our_jid, their_id, session) VALUES (?, ?, ?)")
+            .bind(&self.our_jid)
+            .bind(address)
+            .bind(session)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save session: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn delete_session(&self, address: &str) -> Result<()> {
+        sqlx::query("DELETE FROM device_sessions WHERE our_jid = ? AND their_id = ?")
+            .bind(&self.our_jid)
+            .bind(address)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to delete session: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn delete_all_sessions(&self, user: &str) -> Result<()> {
+        // Addresses are `user:device`, matched in Rust to avoid LIKE escaping
+        let addresses: Vec<String> = sqlx::query_scalar("SELECT their_id FROM device_sessions WHERE our_jid = ?")
+            .bind(&self.our_jid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list sessions: {}", e)))?;
+        
+        for address in addresses.iter().filter(|address| address_user(address) == user) {
+            self.delete_session(address).await?;
+        }
+        Ok(())
+    }
+    
+    async fn all_sessions(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        sqlx::query_as("SELECT their_id, session FROM device_sessions WHERE our_jid = ?")
+            .bind(&self.our_jid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list sessions: {}", e)))
+    }
+}
+
+#[async_trait]
+impl PreKeyRecordStore for SqliteSignalStore {
+    async fn put_pre_key(&self, key_id: u32, key: &[u8]) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO device_pre_keys (our_jid, key_id, key, uploaded) VALUES (?, ?, ?, FALSE)")
+            .bind(&self.our_jid)
+            .bind(key_id as i64)
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save pre-key: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn get_pre_key(&self, key_id: u32) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar("SELECT key FROM device_pre_keys WHERE our_jid = ? AND key_id = ?")
+            .bind(&self.our_jid)
+            .bind(key_id as i64)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load pre-key: {}", e)))
+    }
+    
+    async fn remove_pre_key(&self, key_id: u32) -> Result<()> {
+        sqlx::query("DELETE FROM device_pre_keys WHERE our_jid = ? AND key_id = ?")
+            .bind(&self.our_jid)
+            .bind(key_id as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to remove pre-key: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn mark_pre_keys_uploaded(&self, up_to_id: u32) -> Result<()> {
+        sqlx::query("UPDATE device_pre_keys SET uploaded = TRUE WHERE our_jid = ? AND key_id <= ?")
+            .bind(&self.our_jid)
+            .bind(up_to_id as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to mark pre-keys uploaded: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn uploaded_pre_key_count(&self) -> Result<usize> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM device_pre_keys WHERE our_jid = ? AND uploaded = TRUE")
+            .bind(&self.our_jid)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to count pre-keys: {}", e)))?;
+        Ok(count as usize)
+    }
+    
+    async fn all_pre_keys(&self) -> Result<Vec<(u32, Vec<u8>, bool)>> {
+        let rows: Vec<(i64, Vec<u8>, bool)> = sqlx::query_as("SELECT key_id, key, uploaded FROM device_pre_keys WHERE our_jid = ?")
+            .bind(&self.our_jid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list pre-keys: {}", e)))?;
+        Ok(rows.into_iter().map(|(key_id, key, uploaded)| (key_id as u32, key, uploaded)).collect())
+    }
+}
+
+#[async_trait]
+impl SenderKeyStore for SqliteSignalStore {
+    async fn put_sender_key(&self, group: &str, sender: &str, key: &[u8]) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO device_sender_keys (our_jid, chat_id, sender_id, sender_key) VALUES (?, ?, ?, ?)")
+            .bind(&self.our_jid)
+            .bind(group)
+            .bind(sender)
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to save sender key: {}", e)))?;
+        Ok(())
+    }
+    
+    async fn get_sender_key(&self, group: &str, sender: &str) -> Result<Option<Vec<u8>>> {
+        sqlx::query_scalar("SELECT sender_key FROM device_sender_keys WHERE our_jid = ? AND chat_id = ? AND sender_id = ?")
+            .bind(&self.our_jid)
+            .bind(group)
+            .bind(sender)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to load sender key: {}", e)))
+    }
+    
+    async fn all_sender_keys(&self) -> Result<Vec<(String, String, Vec<u8>)>> {
+        sqlx::query_as("SELECT chat_id, sender_id, sender_key FROM device_sender_keys WHERE our_jid = ?")
+            .bind(&self.our_jid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to list sender keys: {}", e)))
+    }
+}
+
+fn app_state_key_from_row(row: &sqlx::sqlite::SqliteRow) -> AppStateSyncKey {
+    let timestamp: i64 = row.get("timestamp");
+    AppStateSyncKey {
+        key_id: row.get("key_id"),
+        key_data: AppStateSyncKeyData {
+            key_data: row.get("key_data"),
+            fingerprint: row.get("fingerprint"),
+            timestamp: std::time::UNIX_EPOCH + std::time::Duration::from_millis(timestamp.max(0) as u64),
+        },
+    }
+}
+
+/// Settings store for key-value configuration
+pub struct SqliteSettingsStore {
+    pool: SqlitePool,
+}
+
+impl SqliteSettingsStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+    
+    /// Store a setting
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)"
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to set setting: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Get a setting
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let value: Option<String> = sqlx::query_scalar(
+            "SELECT value FROM settings WHERE key = ?"
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to get setting: {}", e)))?;
+        
+        Ok(value)
+    }
+    
+    /// Delete a setting
+    pub async fn delete_setting(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM settings WHERE key = ?")
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to delete setting: {}", e)))?;
+        
+        Ok(())
+    }
+    
+    /// Get all settings
+    pub async fn get_all_settings(&self) -> Result<HashMap<String, String>> {
+        let rows = sqlx::query("SELECT key, value FROM settings")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| Error::Database(format!("Failed to get all settings: {}", e)))?;
+        
+        let mut settings = HashMap::new();
+        for row in rows {
+            let key: String = row.get(0);
+            let value: String = row.get(1);
+            settings.insert(key, value);
+        }
+        
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    
+    async fn create_test_db() -> Database {
+        let config = crate::database::DatabaseConfig {
+            database_url: "sqlite::memory:".to_string(),
+            max_connections: 5,
+            connection_timeout: 10,
+            enable_wal: false,
+        };
+        
+        Database::new(config).await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_device_store() {
+        let db = create_test_db().await;
+        let store = SqliteDeviceStore::new(db.pool().clone());
+        
+        let device_data = DeviceData {
+            jid: JID::new("test".to_string(), "s.whatsapp.net".to_string()),
+            registration_id: 12345,
+            noise_key: vec![1, 2, 3],
+            identity_key: vec![4, 5, 6],
+            signed_pre_key: vec![7, 8, 9],
+            signed_pre_key_id: 1,
+            signed_pre_key_signature: vec![10, 11, 12],
+        };
+        
+        // Test save and load
+        store.save_device(&device_data).await.unwrap();
+        assert!(store.is_registered().await.unwrap());
+        
+        let loaded = store.load_device().await.unwrap().unwrap();
+        assert_eq!(loaded.jid, device_data.jid);
+        assert_eq!(loaded.registration_id, device_data.registration_id);
+        
+        // Test delete
+        store.delete_device().await.unwrap();
+        assert!(!store.is_registered().await.unwrap());
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_signal_store_per_device() {
+        let db = create_test_db().await;
+        let store = SqliteDeviceStore::new(db.pool().clone());
+        let first = store.device(&JID::new_user("111").with_device(1));
+        let second = store.device(&JID::new_user("222").with_device(1));
+        
+        first.identities.put_identity("333:0", &[1; 32]).await.unwrap();
+        assert_eq!(first.identities.get_identity("333:0").await.unwrap(), Some(vec![1; 32]));
+        assert_eq!(second.identities.get_identity("333:0").await.unwrap(), None);
+        
+        first.sessions.put_session("333:0", b"first").await.unwrap();
+        first.sessions.put_session("333:5", b"first").await.unwrap();
+        second.sessions.put_session("333:0", b"second").await.unwrap();
+        first.sessions.delete_all_sessions("333").await.unwrap();
+        assert!(!first.sessions.has_session("333:5").await.unwrap());
+        assert_eq!(second.sessions.get_session("333:0").await.unwrap(), Some(b"second".to_vec()));
+        
+        first.pre_keys.put_pre_key(1, b"key").await.unwrap();
+        first.pre_keys.put_pre_key(2, b"key").await.unwrap();
+        first.pre_keys.mark_pre_keys_uploaded(1).await.unwrap();
+        assert_eq!(first.pre_keys.uploaded_pre_key_count().await.unwrap(), 1);
+        assert_eq!(second.pre_keys.get_pre_key(1).await.unwrap(), None);
+        
+        first.sender_keys.put_sender_key("123@g.us", "333:0", b"sender").await.unwrap();
+        assert_eq!(first.sender_keys.get_sender_key("123@g.us", "333:0").await.unwrap(), Some(b"sender".to_vec()));
+        assert_eq!(second.sender_keys.get_sender_key("123@g.us", "333:0").await.unwrap(), None);
+        
+        assert_eq!(first.identities.all_identities().await.unwrap(), vec![("333:0".to_string(), vec![1; 32])]);
+        assert_eq!(first.pre_keys.all_pre_keys().await.unwrap().len(), 2);
+        assert_eq!(first.sender_keys.all_sender_keys().await.unwrap().len(), 1);
+        assert!(first.sessions.all_sessions().await.unwrap().is_empty());
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_settings_store() {
+        let db = create_test_db().await;
+        let store = SqliteSettingsStore::new(db.pool().clone());
+        
+        // Test set and get
+        store.set_setting("test_key", "test_value").await.unwrap();
+        let value = store.get_setting("test_key").await.unwrap();
+        assert_eq!(value, Some("test_value".to_string()));
+        
+        // Test get non-existent
+        let none_value = store.get_setting("non_existent").await.unwrap();
+        assert_eq!(none_value, None);
+        
+        // Test delete
+        store.delete_setting("test_key").await.unwrap();
+        let deleted_value = store.get_setting("test_key").await.unwrap();
+        assert_eq!(deleted_value, None);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_contact_store() {
+        let db = create_test_db().await;
+        let store = SqliteContactStore::new(db.pool().clone());
+        
+        let jid = JID::new("contact".to_string(), "s.whatsapp.net".to_string());
+        
+        // Test store and load
         store.store_contact(&jid, Some("Test Contact"), Some("+1234567890")).await.unwrap();
         
         let contact = store.load_contact(&jid).await.unwrap().unwrap();
@@ -525,4 +1588,228 @@ mod tests {
         
         db.close().await;
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_outbox_store() {
+        let db = create_test_db().await;
+        let store = SqliteOutboxStore::new(db.pool().clone());
+        
+        let chat = JID::new("friend".to_string(), "s.whatsapp.net".to_string());
+        let entry = |id: &str, expires_at: Option<i64>| OutboxEntry {
+            message_id: id.to_string(),
+            chat: chat.clone(),
+            message: SendableMessage::Text(crate::types::TextMessage { text: id.to_string() }),
+            created_at: 100,
+            expires_at,
+        };
+        
+        store.enqueue(&entry("first", None)).await.unwrap();
+        store.enqueue(&entry("second", Some(200))).await.unwrap();
+        // Enqueueing again keeps the original position
+        store.enqueue(&entry("first", None)).await.unwrap();
+        
+        let entries = store.list().await.unwrap();
+        let ids: Vec<_> = entries.iter().map(|e| e.message_id.as_str()).collect();
+        assert_eq!(ids, vec!["first", "second"]);
+        assert_eq!(entries[0].chat, chat);
+        assert!(matches!(&entries[0].message, SendableMessage::Text(text) if text.text == "first"));
+        assert!(!entries[0].is_expired(300));
+        assert!(entries[1].is_expired(300));
+        
+        store.remove("first").await.unwrap();
+        assert_eq!(store.len().await.unwrap(), 1);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_poll_store() {
+        let db = create_test_db().await;
+        let store = SqlitePollStore::new(db.pool().clone());
+        
+        let key = MessageKey {
+            remote_jid: JID::new_group("poll-group"),
+            from_me: true,
+            id: "POLL1".to_string(),
+            participant: None,
+        };
+        let poll = PollMessage {
+            name: "Lunch?".to_string(),
+            options: vec![crate::types::PollOption { name: "Pizza".to_string() }, crate::types::PollOption { name: "Sushi".to_string() }],
+            selectable_options_count: 1,
+            context_info: None,
+            message_secret: Some(vec![7; 32]),
+        };
+        let alice = StoredPollVote {
+            voter: JID::new_user("111"),
+            selected: vec!["Sushi".to_string()],
+            timestamp: Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(5000)),
+        };
+        
+        store.put_poll(&key, &poll).await.unwrap();
+        store.put_vote(&key, &alice).await.unwrap();
+        // A changed vote replaces the earlier one
+        store.put_vote(&key, &StoredPollVote { selected: vec!["Pizza".to_string()], ..alice.clone() }).await.unwrap();
+        store.put_vote(&key, &StoredPollVote { voter: JID::new_user("222"), selected: Vec::new(), timestamp: None }).await.unwrap();
+        
+        let polls = store.load().await.unwrap();
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0].key.id, "POLL1");
+        assert_eq!(polls[0].poll.message_secret, poll.message_secret);
+        assert_eq!(polls[0].votes, vec![
+            StoredPollVote { selected: vec!["Pizza".to_string()], ..alice },
+            StoredPollVote { voter: JID::new_user("222"), selected: Vec::new(), timestamp: None },
+        ]);
+        
+        // The tallies survive a restart
+        let tracker = crate::messaging::PollResultsTracker::new();
+        tracker.restore(polls).await;
+        let results = tracker.get_results(&key).await.unwrap();
+        assert_eq!(results.options[0].vote_count, 1);
+        assert_eq!(results.total_voters, 1);
+        
+        assert!(store.remove(&key).await.unwrap());
+        assert!(store.load().await.unwrap().is_empty());
+        let votes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM poll_votes").fetch_one(db.pool()).await.unwrap();
+        assert_eq!(votes, 0);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_scheduled_message_store() {
+        let db = create_test_db().await;
+        let store = SqliteScheduledMessageStore::new(db.pool().clone());
+        
+        let chat = JID::new_user("15550001111");
+        let scheduled = |id: &str, send_at: i64| ScheduledMessage {
+            id: id.to_string(),
+            chat: chat.clone(),
+            message: SendableMessage::Text(crate::types::TextMessage { text: id.to_string() }),
+            send_at,
+            created_at: 100,
+        };
+        
+        store.add(&scheduled("later", 500)).await.unwrap();
+        store.add(&scheduled("sooner", 200)).await.unwrap();
+        store.add(&scheduled("soonest", 150)).await.unwrap();
+        
+        let ids: Vec<_> = store.list().await.unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec!["soonest", "sooner", "later"]);
+        let due = store.due(200).await.unwrap();
+        assert_eq!(due.len(), 2);
+        assert!(due.iter().all(|m| m.is_due(200) && m.chat == chat));
+        
+        // Scheduling an ID again replaces the stored message
+        store.add(&scheduled("soonest", 600)).await.unwrap();
+        assert_eq!(store.due(200).await.unwrap().len(), 1);
+        
+        assert!(store.remove("sooner").await.unwrap());
+        assert!(!store.remove("sooner").await.unwrap());
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_contact_names() {
+        let db = create_test_db().await;
+        let store = SqliteContactStore::new(db.pool().clone());
+        let jid = JID::new_user("15551234567");
+        
+        assert!(store.update_push_name(&jid.with_device(2), "Alice").await.unwrap());
+        assert!(!store.update_push_name(&jid, "Alice").await.unwrap());
+        store.store_contact(&jid, Some("Ali"), Some("15551234567")).await.unwrap();
+        store.update_contact_names(&jid, Some("Alice"), Some("Alice Smith")).await.unwrap();
+        store.update_business_name(&jid, "Alice's Bakery").await.unwrap();
+        SqliteLidStore::new(db.pool().clone())
+            .put_mapping(&JID::new_lid("8888"), &jid)
+            .await
+            .unwrap();
+        
+        let contact = store.load_contact(&jid).await.unwrap().unwrap();
+        assert_eq!(contact.notify_name.as_deref(), Some("Alice"));
+        assert_eq!(contact.name.as_deref(), Some("Ali"));
+        assert_eq!(contact.business_name.as_deref(), Some("Alice's Bakery"));
+        assert_eq!(contact.display_name(), Some("Alice Smith"));
+        assert_eq!(contact.lid, Some(JID::new_lid("8888")));
+        assert_eq!(store.list_contacts().await.unwrap().len(), 1);
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_lid_store() {
+        let db = create_test_db().await;
+        let store = SqliteLidStore::new(db.pool().clone());
+        
+        let lid = JID::new_lid("123456789012345");
+        let pn = JID::new_user("15551234567");
+        assert!(store.get_pn(&lid).await.unwrap().is_none());
+        assert!(store.put_mapping(&pn, &lid).await.is_err());
+        
+        store.put_mapping(&lid, &pn).await.unwrap();
+        assert_eq!(store.get_pn(&lid).await.unwrap(), Some(pn.clone()));
+        assert_eq!(store.get_lid(&pn).await.unwrap(), Some(lid.clone()));
+        assert_eq!(store.to_pn(&lid.with_device(3)).await.unwrap(), pn.with_device(3));
+        
+        // A new LID for the same number replaces the old mapping
+        let new_lid = JID::new_lid("999");
+        store.put_mapping(&new_lid, &pn).await.unwrap();
+        assert!(store.get_pn(&lid).await.unwrap().is_none());
+        assert_eq!(store.get_lid(&pn).await.unwrap(), Some(new_lid));
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_app_state_key_store() {
+        let db = create_test_db().await;
+        let store = SqliteAppStateKeyStore::new(db.pool().clone());
+        assert!(store.get_latest_key().await.unwrap().is_none());
+        
+        let key = |id: u8, millis: u64| AppStateSyncKey {
+            key_id: vec![0, 0, 0, id],
+            key_data: AppStateSyncKeyData {
+                key_data: vec![id; 32],
+                fingerprint: vec![1, 2],
+                timestamp: std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis),
+            },
+        };
+        store.put_key(&key(2, 2000)).await.unwrap();
+        store.put_key(&key(1, 1000)).await.unwrap();
+        
+        let latest = store.get_latest_key().await.unwrap().unwrap();
+        assert_eq!(latest.key_id, vec![0, 0, 0, 2]);
+        assert_eq!(latest.key_data.timestamp, key(2, 2000).key_data.timestamp);
+        assert_eq!(store.get_key(&[0, 0, 0, 1]).await.unwrap().unwrap().key_data.key_data, vec![1; 32]);
+        assert!(store.get_key(&[9]).await.unwrap().is_none());
+        
+        db.close().await;
+    }
+    
+    #[tokio::test]
+    async fn test_app_state_version_store() {
+        let db = create_test_db().await;
+        let store = SqliteAppStateVersionStore::new(db.pool().clone());
+        assert!(store.get(PatchName::Regular).await.unwrap().is_none());
+        
+        let mut state = HashState { version: 7, hash: vec![3; 128], ..HashState::default() };
+        state.value_macs.insert(vec![1], vec![10]);
+        state.value_macs.insert(vec![2], vec![20]);
+        store.put(PatchName::Regular, &state).await.unwrap();
+        assert_eq!(store.get(PatchName::Regular).await.unwrap(), Some(state.clone()));
+        assert!(store.get(PatchName::RegularLow).await.unwrap().is_none());
+        
+        // Saving again replaces the removed indexes too
+        state.version = 8;
+        state.value_macs.remove(&vec![1]);
+        store.put(PatchName::Regular, &state).await.unwrap();
+        assert_eq!(store.get(PatchName::Regular).await.unwrap(), Some(state));
+        
+        store.delete(PatchName::Regular).await.unwrap();
+        assert!(store.get(PatchName::Regular).await.unwrap().is_none());
+        
+        db.close().await;
+    }
+}
@@ -0,0 +1,194 @@
+/// Retry receipts and placeholders for messages that fail to decrypt
+///
+/// When a message cannot be decrypted, e.g. because the sender used a
+/// session we no longer have, the client asks the sender to encrypt it again
+/// with a `retry` receipt carrying our registration id. After
+/// [`MAX_DECRYPT_RETRIES`] failed attempts the message is given up on: a
+/// placeholder takes its place in the chat history, the way the official
+/// clients show "Waiting for this message", and the application is told
+/// with [`Event::UndecryptableMessage`](crate::types::Event::UndecryptableMessage).
+/// Retry counts and placeholders are stored in the database, so a restart
+/// neither starts the retries over nor loses the placeholder.
+
+use crate::{
+    binary::Node,
+    error::Result,
+    messaging::MessageProcessor,
+    types::{MessageInfo, MessageType},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of retry receipts sent for a message before giving up on it
+pub const MAX_DECRYPT_RETRIES: u32 = 5;
+
+/// Number of messages tracked before the oldest failures are forgotten
+const MAX_TRACKED_MESSAGES: usize = 1024;
+
+/// What to do after a message failed to decrypt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptFailureAction {
+    /// Ask the sender to send the message again, `count` being the retry number
+    Retry { count: u32 },
+    /// Retries are exhausted; store a placeholder instead
+    GiveUp,
+}
+
+/// A failed message as persisted, see [`SqliteDecryptFailureStore`](crate::database::sqlite::SqliteDecryptFailureStore)
+#[derive(Debug, Clone)]
+pub struct StoredDecryptFailure {
+    pub sender: String,
+    pub message_id: String,
+    /// Retry receipts sent so far
+    pub retries: u32,
+    /// Set once retries are exhausted
+    pub placeholder: Option<MessageInfo>,
+    pub reason: Option<String>,
+}
+
+#[derive(Default)]
+struct Failures {
+    /// Failed attempts and the sequence number of the last one, by message
+    counts: HashMap<String, (u32, u64)>,
+    sequence: u64,
+}
+
+/// Per-message count of failed decryption attempts
+#[derive(Default)]
+pub struct DecryptRetryTracker {
+    failures: Mutex<Failures>,
+}
+
+impl DecryptRetryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a failed attempt to decrypt a message from `sender`
+    pub fn record_failure(&self, sender: &str, message_id: &str) -> DecryptFailureAction {
+        let mut failures = self.failures.lock().unwrap();
+        failures.sequence += 1;
+        let sequence = failures.sequence;
+        let key = retry_key(sender, message_id);
+
+        if failures.counts.len() >= MAX_TRACKED_MESSAGES && !failures.counts.contains_key(&key) {
+            let oldest = failures.counts.iter().min_by_key(|(_, (_, seq))| *seq).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                failures.counts.remove(&oldest);
+            }
+        }
+
+        let entry = failures.counts.entry(key.clone()).or_insert((0, sequence));
+        entry.0 += 1;
+        entry.1 = sequence;
+        let count = entry.0;
+        if count > MAX_DECRYPT_RETRIES {
+            failures.counts.remove(&key);
+            return DecryptFailureAction::GiveUp;
+        }
+        DecryptFailureAction::Retry { count }
+    }
+
+    /// Restore the retry counts of messages still being retried after a restart
+    pub fn restore(&self, saved: &[StoredDecryptFailure]) {
+        let mut failures = self.failures.lock().unwrap();
+        for failure in saved.iter().filter(|failure| failure.placeholder.is_none()) {
+            failures.sequence += 1;
+            let sequence = failures.sequence;
+            failures.counts.insert(retry_key(&failure.sender, &failure.message_id), (failure.retries, sequence));
+        }
+    }
+
+    /// Forget the failures of a message that was decrypted after all
+    pub fn record_success(&self, sender: &str, message_id: &str) {
+        self.failures.lock().unwrap().counts.remove(&retry_key(sender, message_id));
+    }
+
+    /// Number of failed attempts recorded for a message
+    pub fn failures(&self, sender: &str, message_id: &str) -> u32 {
+        self.failures.lock().unwrap().counts.get(&retry_key(sender, message_id)).map_or(0, |(count, _)| *count)
+    }
+}
+
+fn retry_key(sender: &str, message_id: &str) -> String {
+    format!("{}|{}", sender, message_id)
+}
+
+/// Build the receipt asking the sender of `message` to encrypt it again
+pub fn build_retry_receipt(message: &Node, count: u32, registration_id: u32) -> Result<Node> {
+    let info = MessageProcessor::process_message(message)?;
+    let timestamp = message.get_attr("t").cloned().unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let mut receipt = Node::builder("receipt")
+        .attr("id", &info.id)
+        .attr("to", &info.sender)
+        .attr("type", "retry");
+    if let Some(participant) = message.get_attr("participant") {
+        receipt = receipt.attr("participant", participant);
+    }
+    Ok(receipt
+        .nodes(vec![
+            Node::builder("retry")
+                .attr("count", count)
+                .attr("id", &info.id)
+                .attr("t", if timestamp.is_empty() { now.to_string() } else { timestamp })
+                .attr("v", 1)
+                .build(),
+            Node::new("registration".to_string()).with_binary(registration_id.to_be_bytes().to_vec()),
+        ])
+        .build())
+}
+
+/// Placeholder standing in for a message that could not be decrypted
+pub fn placeholder_message(message: &Node) -> Result<MessageInfo> {
+    let mut info = MessageProcessor::process_message(message)?;
+    info.message_type = MessageType::Undecryptable;
+    info.content = None;
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Node {
+        Node::new("message".to_string())
+            .attr("id".to_string(), "MSG1".to_string())
+            .attr("from".to_string(), "1234@s.whatsapp.net".to_string())
+            .attr("to".to_string(), "5678@s.whatsapp.net".to_string())
+            .attr("t".to_string(), "1700000000".to_string())
+            .attr("type".to_string(), "text".to_string())
+    }
+
+    #[test]
+    fn test_gives_up_after_max_retries() {
+        let tracker = DecryptRetryTracker::new();
+        for count in 1..=MAX_DECRYPT_RETRIES {
+            assert_eq!(tracker.record_failure("1234@s.whatsapp.net", "MSG1"), DecryptFailureAction::Retry { count });
+        }
+        assert_eq!(tracker.record_failure("1234@s.whatsapp.net", "MSG1"), DecryptFailureAction::GiveUp);
+        assert_eq!(tracker.failures("1234@s.whatsapp.net", "MSG1"), 0);
+
+        tracker.record_failure("1234@s.whatsapp.net", "MSG2");
+        tracker.record_success("1234@s.whatsapp.net", "MSG2");
+        assert_eq!(tracker.failures("1234@s.whatsapp.net", "MSG2"), 0);
+    }
+
+    #[test]
+    fn test_retry_receipt_and_placeholder() {
+        let receipt = build_retry_receipt(&message(), 2, 0x01020304).unwrap();
+        assert_eq!(receipt.get_attr("type").map(String::as_str), Some("retry"));
+        assert_eq!(receipt.get_attr("to").map(String::as_str), Some("1234@s.whatsapp.net"));
+        let retry = receipt.find_child("retry").unwrap();
+        assert_eq!(retry.get_attr("count").map(String::as_str), Some("2"));
+        assert_eq!(retry.get_attr("t").map(String::as_str), Some("1700000000"));
+        assert_eq!(receipt.find_child("registration").and_then(|r| r.get_binary()), Some(&vec![1, 2, 3, 4]));
+
+        let placeholder = placeholder_message(&message()).unwrap();
+        assert_eq!(placeholder.id, "MSG1");
+        assert_eq!(placeholder.message_type, MessageType::Undecryptable);
+        assert!(placeholder.content.is_none());
+    }
+}
@@ -0,0 +1,207 @@
+/// Deduplication of incoming messages across reconnects and restarts
+///
+/// The server keeps messages queued until their delivery receipt arrives, so
+/// after a reconnect it may deliver messages again that were processed just
+/// before the connection dropped. The [replay filter](crate::replay) only
+/// remembers stanzas for a few minutes of one process; this window remembers
+/// the ids of the last messages of every chat and is saved with the other
+/// caches, so a restart does not emit the same [`Event::Message`] twice.
+///
+/// After connecting, the server first delivers the messages queued while we
+/// were offline and then announces their number with `<ib><offline count/>`.
+///
+/// [`Event::Message`]: crate::types::Event::Message
+
+use crate::{binary::Node, types::JID};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Default number of message ids remembered per chat
+pub const DEFAULT_IDS_PER_CHAT: usize = 256;
+
+/// Default number of chats remembered; the least recently active are dropped first
+pub const DEFAULT_MAX_CHATS: usize = 1024;
+
+/// Message ids recently seen in a chat, oldest first, as saved between runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeenMessages {
+    pub chat: String,
+    pub ids: Vec<String>,
+}
+
+#[derive(Default)]
+struct ChatWindow {
+    seen: HashSet<String>,
+    order: VecDeque<String>,
+    /// Value of the activity counter when the chat last received a message
+    active: u64,
+}
+
+/// Window of recently processed message ids per chat
+pub struct MessageDedup {
+    ids_per_chat: usize,
+    max_chats: usize,
+    chats: Mutex<HashMap<String, ChatWindow>>,
+    activity: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+impl MessageDedup {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_IDS_PER_CHAT, DEFAULT_MAX_CHATS)
+    }
+
+    pub fn with_capacity(ids_per_chat: usize, max_chats: usize) -> Self {
+        Self {
+            ids_per_chat,
+            max_chats,
+            chats: Mutex::new(HashMap::new()),
+            activity: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a message, returning `false` if it was already processed
+    pub fn check(&self, chat: &JID, message_id: &str) -> bool {
+        let chat = chat.to_non_ad().to_string();
+        let active = self.activity.fetch_add(1, Ordering::Relaxed);
+        let mut chats = self.chats.lock().unwrap();
+        if !chats.contains_key(&chat) && chats.len() >= self.max_chats {
+            if let Some(idle) = chats.iter().min_by_key(|(_, window)| window.active).map(|(chat, _)| chat.clone()) {
+                chats.remove(&idle);
+            }
+        }
+
+        let window = chats.entry(chat).or_default();
+        window.active = active;
+        if window.seen.contains(message_id) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        window.seen.insert(message_id.to_string());
+        window.order.push_back(message_id.to_string());
+        if window.order.len() > self.ids_per_chat {
+            if let Some(oldest) = window.order.pop_front() {
+                window.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forget a message so it is processed again, e.g. after asking the sender to retry
+    pub fn forget(&self, chat: &JID, message_id: &str) {
+        if let Some(window) = self.chats.lock().unwrap().get_mut(&chat.to_non_ad().to_string()) {
+            if window.seen.remove(message_id) {
+                window.order.retain(|id| id != message_id);
+            }
+        }
+    }
+
+    /// Number of duplicate messages suppressed
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// The remembered ids of every chat, least recently active chat first
+    pub fn snapshot(&self) -> Vec<SeenMessages> {
+        let chats = self.chats.lock().unwrap();
+        let mut windows: Vec<_> = chats.iter().collect();
+        windows.sort_by_key(|(_, window)| window.active);
+        windows
+            .into_iter()
+            .map(|(chat, window)| SeenMessages { chat: chat.clone(), ids: window.order.iter().cloned().collect() })
+            .collect()
+    }
+
+    /// Restore ids saved by [`snapshot`](Self::snapshot)
+    pub fn restore(&self, saved: Vec<SeenMessages>) {
+        for seen in saved {
+            let Ok(chat) = JID::parse(&seen.chat) else {
+                continue;
+            };
+            for id in &seen.ids {
+                self.check(&chat, id);
+            }
+        }
+    }
+}
+
+impl Default for MessageDedup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Progress of the delivery of messages queued while offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum OfflineSync {
+    /// Delivery is about to start; the counts are what is waiting
+    Preview { messages: u32, notifications: u32, receipts: u32 },
+    /// All `count` queued stanzas were delivered
+    Completed { count: u32 },
+}
+
+/// Parse an `<ib>` stanza about the offline queue
+pub fn parse_offline_sync(node: &Node) -> Option<OfflineSync> {
+    if node.tag != "ib" {
+        return None;
+    }
+    let count = |node: &Node, attr: &str| node.get_attr(attr).and_then(|count| count.parse().ok()).unwrap_or(0);
+    if let Some(preview) = node.find_child("offline_preview") {
+        return Some(OfflineSync::Preview {
+            messages: count(preview, "message"),
+            notifications: count(preview, "notification"),
+            receipts: count(preview, "receipt"),
+        });
+    }
+    node.find_child("offline").map(|offline| OfflineSync::Completed { count: count(offline, "count") })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppresses_duplicates() {
+        let dedup = MessageDedup::with_capacity(2, 2);
+        let alice = JID::new_user("1111");
+        let group = JID::new_group("1-2");
+
+        assert!(dedup.check(&alice, "A"));
+        assert!(!dedup.check(&alice.with_device(3), "A"));
+        assert!(dedup.check(&group, "A"));
+        assert_eq!(dedup.duplicates(), 1);
+
+        // Capacity per chat evicts the oldest id
+        dedup.check(&alice, "B");
+        dedup.check(&alice, "C");
+        assert!(dedup.check(&alice, "A"));
+
+        dedup.forget(&alice, "C");
+        assert!(dedup.check(&alice, "C"));
+
+        let restored = MessageDedup::new();
+        restored.restore(dedup.snapshot());
+        assert!(!restored.check(&alice, "C"));
+        assert!(!restored.check(&group, "A"));
+
+        // A third chat drops the least recently active one
+        dedup.check(&JID::new_user("2222"), "X");
+        assert!(dedup.check(&group, "A"));
+    }
+
+    #[test]
+    fn test_parse_offline_sync() {
+        let preview = Node::builder("ib")
+            .nodes(vec![Node::builder("offline_preview").attr("message", 12).attr("receipt", 3).build()])
+            .build();
+        assert_eq!(parse_offline_sync(&preview), Some(OfflineSync::Preview { messages: 12, notifications: 0, receipts: 3 }));
+
+        let done = Node::builder("ib").nodes(vec![Node::builder("offline").attr("count", 15).build()]).build();
+        assert_eq!(parse_offline_sync(&done), Some(OfflineSync::Completed { count: 15 }));
+        assert!(parse_offline_sync(&Node::builder("ib").build()).is_none());
+    }
+}
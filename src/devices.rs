@@ -0,0 +1,676 @@
+/// Device list cache for multi-device message fan-out
+///
+/// Messages have to be encrypted separately for every device of a recipient,
+/// so the client keeps a cache of each user's device list. Lists are fetched
+/// with a usync device query and kept up to date from `devices` notifications,
+/// which carry a hash of the resulting list. If our locally updated list does
+/// not match that hash the entry is dropped and re-fetched on the next send.
+///
+/// Devices we have no Signal session with yet get one from their pre-key
+/// bundle, fetched with [`ensure_sessions`] before the message is encrypted.
+///
+/// Every message is also encrypted for our own other devices, so the phone and
+/// other companions see what this device sent. Those copies are wrapped as
+/// device-sent messages naming the original chat. Group messages are
+/// encrypted once with our sender key; participant devices that have not
+/// received that key yet get it in a separate `pkmsg` alongside.
+///
+/// Received messages are decrypted with [`open_message`], the counterpart of
+/// the fan-out: it decrypts the `enc` nodes, stores sender keys distributed
+/// along and unwraps the message envelope.
+///
+/// Devices are trusted on first use: a device that appears in a server
+/// provided list is accepted, and its identity key is pinned by the Signal
+/// identity store when the first session with it is established. A bundle
+/// with a different identity key is handled by the configured
+/// [`IdentityChangePolicy`]; devices whose key is blocked get no session and
+/// are left out of the fan-out.
+
+use crate::{
+    binary::{BinaryDecoder, Node},
+    error::{Error, Result},
+    msg_transport::{self, SenderKeyDistribution, ENC_VERSION_LEGACY, ENC_VERSION_TRANSPORT},
+    prekeys,
+    request::{InfoQuery, IqSender},
+    signal::{group, IdentityChangePolicy, IdentityCheck, IdentityKey, SignalMessage, SignalMessageType, SignalProtocolManager},
+    types::JID,
+    usync::UserDeviceList,
+};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, warn};
+
+/// Default time after which cached device lists are refreshed
+pub const DEFAULT_DEVICE_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Message attribute that, set to `false`, keeps a message off our own other devices
+pub const DEVICE_FANOUT_ATTR: &str = "device_fanout";
+
+/// Cached device list of a single user
+#[derive(Debug, Clone)]
+pub struct CachedDeviceList {
+    pub devices: Vec<JID>,
+    pub hash: String,
+    pub fetched_at: SystemTime,
+}
+
+/// Cache of user device lists
+pub struct DeviceCache {
+    entries: RwLock<HashMap<String, CachedDeviceList>>,
+    ttl: Duration,
+}
+
+impl DeviceCache {
+    /// Create a new cache with the default TTL
+    pub fn new() -> Self {
+        Self::with_ttl(DEFAULT_DEVICE_CACHE_TTL)
+    }
+
+    /// Create a new cache with a custom TTL
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Get the cached devices of a user, if present and not expired
+    pub async fn get(&self, user: &JID) -> Option<Vec<JID>> {
+        let entries = self.entries.read().await;
+        let entry = entries.get(&user.to_non_ad().to_string())?;
+
+        let age = SystemTime::now()
+            .duration_since(entry.fetched_at)
+            .unwrap_or_default();
+        if age > self.ttl {
+            return None;
+        }
+
+        Some(entry.devices.clone())
+    }
+
+    /// Store a freshly fetched device list
+    pub async fn store(&self, list: &UserDeviceList) {
+        let mut entries = self.entries.write().await;
+        entries.insert(
+            list.user.to_non_ad().to_string(),
+            CachedDeviceList {
+                hash: participant_list_hash_v2(&list.devices),
+                devices: list.devices.clone(),
+                fetched_at: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Drop the cached devices of a user
+    pub async fn invalidate(&self, user: &JID) {
+        self.entries.write().await.remove(&user.to_non_ad().to_string());
+    }
+
+    /// Drop all cached device lists
+    pub async fn clear(&self) {
+        self.entries.write().await.clear();
+    }
+
+    /// Apply a `<notification type="devices">` stanza to the cache
+    pub async fn handle_device_notification(&self, node: &Node) {
+        let Some(from) = node.get_attr("from").and_then(|f| f.parse::<JID>().ok()) else {
+            return;
+        };
+        let key = from.to_non_ad().to_string();
+
+        let mut entries = self.entries.write().await;
+        let Some(mut cached) = entries.remove(&key) else {
+            debug!("Ignoring device notification for uncached user {}", key);
+            return;
+        };
+
+        for change in node.get_children().map(|c| c.as_slice()).unwrap_or_default() {
+            let device = change
+                .find_child("device")
+                .and_then(|d| d.get_attr("jid"))
+                .and_then(|j| j.parse::<JID>().ok());
+
+            match (change.tag.as_str(), device) {
+                ("add", Some(device)) => {
+                    if !cached.devices.contains(&device) {
+                        cached.devices.push(device);
+                    }
+                }
+                ("remove", Some(device)) => cached.devices.retain(|d| *d != device),
+                _ => continue,
+            }
+
+            let expected = change.get_attr("device_hash").cloned().unwrap_or_default();
+            cached.hash = participant_list_hash_v2(&cached.devices);
+            if cached.hash != expected {
+                debug!("Device hash mismatch for {}, invalidating cached list", key);
+                return;
+            }
+        }
+
+        entries.insert(key, cached);
+    }
+}
+
+impl Default for DeviceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Establish Signal sessions with the devices that have none yet.
+///
+/// Their pre-key bundles are fetched in a single query. Devices without a
+/// usable bundle stay without session and are skipped by the fan-out.
+///
+/// The identity key of each bundle is checked against the pinned one with
+/// `policy`. Returns the devices whose key changed and whether it was blocked.
+pub async fn ensure_sessions(
+    iq_sender: &dyn IqSender,
+    signal: &Mutex<SignalProtocolManager>,
+    devices: &[JID],
+    policy: IdentityChangePolicy,
+) -> Result<Vec<(JID, bool)>> {
+    let missing: Vec<JID> = {
+        let signal = signal.lock().await;
+        devices.iter().filter(|device| !signal.has_session(&device.signal_address())).cloned().collect()
+    };
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    debug!("Fetching pre-key bundles of {} devices", missing.len());
+    let bundles = prekeys::fetch_bundles(iq_sender, &missing).await?;
+    let mut signal = signal.lock().await;
+    let mut changed = Vec::new();
+    for (device, bundle) in bundles {
+        let address = device.signal_address();
+        // Another send may have established it while we were fetching
+        if signal.has_session(&address) {
+            continue;
+        }
+        let Ok(key) = <[u8; 32]>::try_from(bundle.identity_key.as_slice()) else {
+            warn!("Pre-key bundle of {} has an invalid identity key", device);
+            continue;
+        };
+        let identity = IdentityKey::new(key);
+        match signal.check_identity(&address, &identity, policy)? {
+            IdentityCheck::Changed { blocked } => changed.push((device.clone(), blocked)),
+            IdentityCheck::New | IdentityCheck::Unchanged => {}
+        }
+        if !signal.is_trusted_identity(&address, &identity) {
+            warn!("Identity key of {} is blocked, not starting a Signal session", device);
+            continue;
+        }
+        if let Err(e) = signal.initialize_outgoing_session(&address, &bundle) {
+            warn!("Failed to start Signal session with {}: {}", device, e);
+        }
+    }
+    signal.persist().await?;
+    Ok(changed)
+}
+
+/// Encrypt a payload for every device that has an established Signal session.
+///
+/// Returns the `<participants>` node holding one `<to>` child per device.
+/// Devices without a session are skipped, see [`ensure_sessions`].
+/// The plaintext must be sealed with [`msg_transport::seal`](crate::msg_transport::seal).
+pub fn build_participants_node(signal: &mut SignalProtocolManager, devices: &[JID], plaintext: &[u8]) -> Result<Node> {
+    build_fanout_participants(signal, devices, plaintext, &[], &[])
+}
+
+/// Encrypt a direct message for the recipient's devices and our own other devices.
+///
+/// `own_plaintext` is the same message sealed with
+/// [`DeviceSentMetadata`](crate::msg_transport::DeviceSentMetadata) naming the chat.
+pub fn build_fanout_participants(
+    signal: &mut SignalProtocolManager,
+    recipients: &[JID],
+    plaintext: &[u8],
+    own_devices: &[JID],
+    own_plaintext: &[u8],
+) -> Result<Node> {
+    let mut nodes = Vec::with_capacity(recipients.len() + own_devices.len());
+    for device in recipients {
+        nodes.extend(encrypt_for_device(signal, device, plaintext)?);
+    }
+    for device in own_devices {
+        nodes.extend(encrypt_for_device(signal, device, own_plaintext)?);
+    }
+    Ok(Node::new("participants".to_string()).with_children(nodes))
+}
+
+/// A group message encrypted with our sender key
+#[derive(Debug, Clone)]
+pub struct GroupFanout {
+    /// The `skmsg` every participant device decrypts
+    pub enc: Node,
+    /// Sender key distribution for devices that have not received it yet
+    pub participants: Node,
+    /// Participant list hash of the devices the message went to
+    pub phash: String,
+}
+
+/// Encrypt a group message for the participant devices, including our own other devices.
+///
+/// The sender key is created on first use and distributed to every device
+/// that has a Signal session and has not received it before.
+pub fn build_group_fanout(
+    signal: &mut SignalProtocolManager,
+    group: &JID,
+    devices: &[JID],
+    plaintext: &[u8],
+) -> Result<GroupFanout> {
+    let group_id = group.to_string();
+    let distribution = signal.group_sender_key(&group_id)?;
+
+    let addresses: Vec<String> = devices.iter().map(JID::signal_address).collect();
+    let missing = signal.missing_sender_key(&group_id, &addresses);
+    let skdm = msg_transport::seal_sender_key_distribution(&SenderKeyDistribution {
+        group_id: group_id.clone(),
+        distribution_message: distribution.serialize()?.serialized,
+    });
+
+    let mut recipients = Vec::new();
+    let mut distributed = Vec::new();
+    for device in devices.iter().filter(|device| missing.contains(&device.signal_address())) {
+        if let Some(node) = encrypt_for_device(signal, device, &skdm)? {
+            recipients.push(node);
+            distributed.push(device.signal_address());
+        }
+    }
+    signal.mark_sender_key_distributed(&group_id, &distributed);
+
+    let encrypted = signal.encrypt_group_message(&group_id, plaintext)?;
+    Ok(GroupFanout {
+        enc: enc_node("skmsg", encrypted.serialized),
+        participants: Node::new("participants".to_string()).with_children(recipients),
+        phash: participant_list_hash_v2(devices),
+    })
+}
+
+/// Our devices that get a copy of what this device sends: all but the current one
+pub fn own_fanout_devices(devices: &[JID], own: &JID) -> Vec<JID> {
+    devices
+        .iter()
+        .filter(|device| device.user == own.user && device.device != own.device)
+        .cloned()
+        .collect()
+}
+
+/// Whether a message stanza is copied to our own other devices
+pub fn wants_device_fanout(message: &Node) -> bool {
+    message.get_attr(DEVICE_FANOUT_ATTR).map(String::as_str) != Some("false")
+}
+
+/// Encrypt for one device, `None` without an established session
+fn encrypt_for_device(signal: &mut SignalProtocolManager, device: &JID, plaintext: &[u8]) -> Result<Option<Node>> {
+    let address = device.signal_address();
+    if !signal.has_session(&address) {
+        debug!("No Signal session for {}, skipping device", device);
+        return Ok(None);
+    }
+    if signal.get_identity(&address).is_some_and(|identity| !signal.is_trusted_identity(&address, &identity)) {
+        debug!("Identity key of {} is blocked, skipping device", device);
+        return Ok(None);
+    }
+
+    let encrypted = signal.encrypt_message(&address, plaintext)?;
+    let enc_type = match encrypted.message_type {
+        SignalMessageType::PreKeyWhisperMessage => "pkmsg",
+        _ => "msg",
+    };
+
+    Ok(Some(
+        Node::new("to".to_string())
+            .attr("jid".to_string(), device.to_string())
+            .with_children(vec![enc_node(enc_type, encrypted.serialized)]),
+    ))
+}
+
+/// Decrypt the `enc` nodes of a received message stanza.
+///
+/// Returns the stanza with the decrypted message in place of the `enc`
+/// nodes, or `None` if it only carried a sender key. Messages sent by this
+/// crate carry the whole sent stanza, others a `Message` proto that is put in
+/// a `<body>`. Sender keys distributed along are stored for the group.
+pub fn open_message(signal: &mut SignalProtocolManager, message: &Node) -> Result<Option<Node>> {
+    let from = message.get_attr("from")
+        .ok_or_else(|| Error::Protocol("Message missing from".to_string()))?;
+    let sender: JID = message.get_attr("participant").unwrap_or(from).parse()?;
+    let address = sender.signal_address();
+
+    let mut opened = None;
+    for enc in message.get_children().into_iter().flatten().filter(|child| child.tag == "enc") {
+        let serialized = enc.get_binary()
+            .ok_or_else(|| Error::Protocol("Empty enc node".to_string()))?
+            .clone();
+        let plaintext = match enc.get_attr("type").map(String::as_str) {
+            Some("pkmsg") => signal.process_prekey_message(&address, &SignalMessage {
+                message_type: SignalMessageType::PreKeyWhisperMessage,
+                serialized,
+            })?,
+            Some("msg") => signal.decrypt_message(&address, &SignalMessage {
+                message_type: SignalMessageType::WhisperMessage,
+                serialized,
+            })?,
+            Some("skmsg") => signal.decrypt_group_message(from, &address, &SignalMessage {
+                message_type: SignalMessageType::SenderKeyMessage,
+                serialized,
+            })?,
+            other => return Err(Error::Protocol(format!("Unsupported enc type: {:?}", other))),
+        };
+
+        let version = enc.get_attr("v").map(String::as_str).unwrap_or(ENC_VERSION_LEGACY);
+        let transport = msg_transport::open(version, &plaintext)?;
+        if let Some(skdm) = &transport.sender_key_distribution {
+            let distribution = group::SenderKeyDistribution::deserialize(&SignalMessage {
+                message_type: SignalMessageType::SenderKeyDistributionMessage,
+                serialized: skdm.distribution_message.clone(),
+            })?;
+            signal.process_sender_key_distribution(&skdm.group_id, &address, &distribution)?;
+        }
+        if !transport.payload.is_empty() {
+            opened = Some(plaintext_stanza(message, &transport.payload));
+        }
+    }
+    Ok(opened)
+}
+
+/// The received stanza with its `enc` nodes replaced by the decrypted payload
+fn plaintext_stanza(message: &Node, payload: &[u8]) -> Node {
+    let mut attrs = message.attrs.clone();
+    let children = match BinaryDecoder::new(payload).decode() {
+        Ok(sent) if sent.tag == "message" => {
+            let children = sent.get_children().cloned().unwrap_or_default();
+            // The server's addressing wins over the one the sender wrote
+            for (key, value) in sent.attrs {
+                if key != "from" && key != "to" {
+                    attrs.entry(key).or_insert(value);
+                }
+            }
+            children
+        }
+        _ => vec![Node::new("body".to_string()).with_binary(payload.to_vec())],
+    };
+    Node { attrs, ..Node::new("message".to_string()) }.with_children(children)
+}
+
+fn enc_node(enc_type: &str, ciphertext: Vec<u8>) -> Node {
+    Node::new("enc".to_string())
+        .attr("v".to_string(), ENC_VERSION_TRANSPORT.to_string())
+        .attr("type".to_string(), enc_type.to_string())
+        .with_binary(ciphertext)
+}
+
+/// A device linked to our account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedDevice {
+    pub jid: JID,
+    /// The phone holding the account (device 0)
+    pub is_primary: bool,
+    /// The device this client runs as
+    pub is_current: bool,
+}
+
+/// Describe the devices of our own device list relative to the current device
+pub fn linked_devices(devices: &[JID], own: &JID) -> Vec<LinkedDevice> {
+    devices
+        .iter()
+        .map(|jid| LinkedDevice {
+            jid: jid.clone(),
+            is_primary: jid.device == 0,
+            is_current: jid.device == own.device,
+        })
+        .collect()
+}
+
+/// Build the query unlinking a companion device from the account.
+///
+/// Companions may unlink themselves; unlinking other companions is
+/// accepted from the primary device's session.
+pub fn build_remove_companion_query(device: &JID) -> InfoQuery {
+    InfoQuery::set("md").content(vec![
+        Node::builder("remove-companion-device")
+            .attr("jid", device)
+            .attr("reason", "user_initiated")
+            .build(),
+    ])
+}
+
+/// Check that `device` is a companion of our account other than ourselves
+pub fn check_removable_companion(device: &JID, own: &JID) -> Result<()> {
+    if device.user != own.user || device.server != own.server {
+        return Err(Error::InvalidJID(format!("{} is not one of our devices", device)));
+    }
+    if device.device == 0 {
+        return Err(Error::InvalidJID("The primary device cannot be unlinked".to_string()));
+    }
+    if device.device == own.device {
+        return Err(Error::InvalidJID("Use logout to unlink the current device".to_string()));
+    }
+    Ok(())
+}
+
+/// Compute the v2 participant list hash used by WhatsApp for device lists.
+///
+/// The devices are hashed in their [`JID::ad_string`] form, which keeps the
+/// agent and device even when they are 0.
+pub fn participant_list_hash_v2(devices: &[JID]) -> String {
+    let mut jids: Vec<String> = devices.iter().map(JID::ad_string).collect();
+    jids.sort();
+
+    let digest = Sha256::digest(jids.concat().as_bytes());
+    format!("2:{}", base64::engine::general_purpose::STANDARD.encode(&digest[..6]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> JID {
+        JID::new("1234".to_string(), "s.whatsapp.net".to_string())
+    }
+
+    fn device_change(tag: &str, device: &JID, hash: &str) -> Node {
+        Node::new(tag.to_string())
+            .attr("device_hash".to_string(), hash.to_string())
+            .with_children(vec![Node::new("device".to_string()).attr("jid".to_string(), device.to_string())])
+    }
+
+    fn notification(change: Node) -> Node {
+        Node::new("notification".to_string())
+            .attr("from".to_string(), user().to_string())
+            .attr("type".to_string(), "devices".to_string())
+            .with_children(vec![change])
+    }
+
+    #[tokio::test]
+    async fn test_store_and_expire() {
+        let cache = DeviceCache::with_ttl(Duration::from_secs(60));
+        cache.store(&UserDeviceList {
+            user: user(),
+            devices: vec![user().with_device(0)],
+            key_index_timestamp: None,
+        }).await;
+        assert_eq!(cache.get(&user()).await.unwrap().len(), 1);
+
+        let expired = DeviceCache::with_ttl(Duration::ZERO);
+        expired.store(&UserDeviceList {
+            user: user(),
+            devices: vec![user().with_device(0)],
+            key_index_timestamp: None,
+        }).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(expired.get(&user()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_device_notification_hash_check() {
+        let cache = DeviceCache::new();
+        let primary = user().with_device(0);
+        let companion = user().with_device(5);
+        cache.store(&UserDeviceList {
+            user: user(),
+            devices: vec![primary.clone()],
+            key_index_timestamp: None,
+        }).await;
+
+        let hash = participant_list_hash_v2(&[primary.clone(), companion.clone()]);
+        cache.handle_device_notification(&notification(device_change("add", &companion, &hash))).await;
+        assert_eq!(cache.get(&user()).await.unwrap(), vec![primary.clone(), companion.clone()]);
+
+        cache.handle_device_notification(&notification(device_change("remove", &companion, "2:bogus"))).await;
+        assert!(cache.get(&user()).await.is_none());
+    }
+
+    #[test]
+    fn test_participant_list_hash_v2() {
+        let lid = JID::new_lid("5678");
+        assert_eq!(user().with_device(5).ad_string(), "1234.0:5@s.whatsapp.net");
+        assert_eq!(lid.ad_string(), "5678.0:0@lid");
+        // sha256("1234.0:0@s.whatsapp.net1234.0:5@s.whatsapp.net5678.0:0@lid"), first 6 bytes
+        assert_eq!(participant_list_hash_v2(&[lid, user().with_device(5), user().with_device(0)]), "2:vgEHSF/A");
+    }
+
+    #[test]
+    fn test_linked_devices() {
+        let own = user().with_device(7);
+        let devices = linked_devices(&[user().with_device(0), user().with_device(3), own.clone()], &own);
+        assert!(devices[0].is_primary && !devices[0].is_current);
+        assert!(!devices[1].is_primary && !devices[1].is_current);
+        assert!(devices[2].is_current);
+
+        assert!(check_removable_companion(&user().with_device(3), &own).is_ok());
+        assert!(check_removable_companion(&user().with_device(0), &own).is_err());
+        assert!(check_removable_companion(&own, &own).is_err());
+        assert!(check_removable_companion(&JID::new_user("999").with_device(3), &own).is_err());
+
+        let query = build_remove_companion_query(&user().with_device(3));
+        assert_eq!(query.namespace, "md");
+        assert_eq!(query.content[0].get_attr("jid").map(String::as_str), Some("1234:3@s.whatsapp.net"));
+    }
+
+    #[test]
+    fn test_participants_node_skips_devices_without_session() {
+        let mut signal = SignalProtocolManager::new_with_memory_stores(1);
+        let node = build_participants_node(&mut signal, &[user().with_device(0)], b"hello").unwrap();
+        assert_eq!(node.tag, "participants");
+        assert!(node.get_children().unwrap().is_empty());
+    }
+
+    /// Hands out the bundle of one device for every key query
+    struct BundleServer {
+        peer: std::sync::Mutex<SignalProtocolManager>,
+        queries: std::sync::Mutex<Vec<InfoQuery>>,
+    }
+
+    #[async_trait::async_trait]
+    impl IqSender for BundleServer {
+        async fn send_iq(&self, query: InfoQuery) -> Result<Node> {
+            let jid = query.content[0].get_children().unwrap()[0].get_attr("jid").unwrap().clone();
+            self.queries.lock().unwrap().push(query);
+            let bundle = self.peer.lock().unwrap().generate_prekey_bundle(0)?;
+            let prekey = bundle.prekey.unwrap();
+            Ok(Node::builder("iq")
+                .child("list", |list| list
+                    .child("user", |user| user
+                        .attr("jid", jid)
+                        .child("registration", |r| r.bytes(bundle.registration_id.to_be_bytes().to_vec()))
+                        .child("type", |t| t.bytes(vec![crate::signal::DJB_TYPE]))
+                        .child("identity", |i| i.bytes(bundle.identity_key.clone()))
+                        .child("key", |key| key
+                            .child("id", |id| id.bytes(prekey.id.to_be_bytes()[1..].to_vec()))
+                            .child("value", |value| value.bytes(prekey.public_key().to_vec())))
+                        .node(prekeys::signed_prekey_node(&bundle.signed_prekey)))
+                    .child("user", |user| user
+                        .attr("jid", "5550009:0@s.whatsapp.net")
+                        .child("error", |error| error.attr("code", 404))))
+                .build())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sessions_from_fetched_bundles() {
+        let signal = Mutex::new(SignalProtocolManager::new_with_memory_stores(1));
+        let server = BundleServer {
+            peer: std::sync::Mutex::new(SignalProtocolManager::new_with_memory_stores(2)),
+            queries: std::sync::Mutex::new(Vec::new()),
+        };
+        let device = user().with_device(0);
+
+        ensure_sessions(&server, &signal, std::slice::from_ref(&device), IdentityChangePolicy::Block).await.unwrap();
+        assert!(signal.lock().await.has_session(&device.signal_address()));
+        assert_eq!(server.queries.lock().unwrap()[0].namespace, prekeys::ENCRYPT_NAMESPACE);
+
+        let node = build_participants_node(&mut *signal.lock().await, std::slice::from_ref(&device), b"hello").unwrap();
+        let to = &node.get_children().unwrap()[0];
+        assert_eq!(to.get_attr("jid"), Some(&device.to_string()));
+        assert_eq!(to.find_child("enc").unwrap().get_attr("type").map(String::as_str), Some("pkmsg"));
+
+        // Devices with a session are not queried again
+        ensure_sessions(&server, &signal, &[device], IdentityChangePolicy::Block).await.unwrap();
+        assert_eq!(server.queries.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_changed_identity_is_blocked() {
+        let signal = Mutex::new(SignalProtocolManager::new_with_memory_stores(1));
+        let server = BundleServer {
+            peer: std::sync::Mutex::new(SignalProtocolManager::new_with_memory_stores(2)),
+            queries: std::sync::Mutex::new(Vec::new()),
+        };
+        let device = user().with_device(0);
+        let address = device.signal_address();
+        // A key pinned earlier, before the peer reinstalled
+        signal.lock().await.check_identity(&address, &IdentityKey::new([9; 32]), IdentityChangePolicy::Block).unwrap();
+
+        let changed = ensure_sessions(&server, &signal, std::slice::from_ref(&device), IdentityChangePolicy::Block).await.unwrap();
+        assert_eq!(changed.iter().map(|(jid, blocked)| (jid.signal_address(), *blocked)).collect::<Vec<_>>(), vec![(address.clone(), true)]);
+        assert!(!signal.lock().await.has_session(&address));
+
+        // Still refused until the new key is trusted explicitly
+        let changed = ensure_sessions(&server, &signal, std::slice::from_ref(&device), IdentityChangePolicy::AutoTrust).await.unwrap();
+        assert!(changed.is_empty());
+        assert!(!signal.lock().await.has_session(&address));
+
+        signal.lock().await.set_trust_level(&address, crate::signal::TrustLevel::Trusted).unwrap();
+        ensure_sessions(&server, &signal, std::slice::from_ref(&device), IdentityChangePolicy::Block).await.unwrap();
+        assert!(signal.lock().await.has_session(&address));
+        let node = build_participants_node(&mut *signal.lock().await, std::slice::from_ref(&device), b"hello").unwrap();
+        assert_eq!(node.get_children().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_own_device_fanout() {
+        let own = user().with_device(7);
+        let other = JID::new_user("999").with_device(2);
+        let devices = own_fanout_devices(&[user().with_device(0), own.clone(), user().with_device(3), other], &own);
+        assert_eq!(devices, vec![user().with_device(0), user().with_device(3)]);
+
+        let message = Node::new("message".to_string());
+        assert!(wants_device_fanout(&message));
+        assert!(!wants_device_fanout(&message.attr(DEVICE_FANOUT_ATTR.to_string(), "false".to_string())));
+    }
+
+    #[test]
+    fn test_group_fanout_creates_sender_key() {
+        let mut signal = SignalProtocolManager::new_with_memory_stores(1);
+        let group = JID::new("123".to_string(), "g.us".to_string());
+        let devices = [user().with_device(0), user().with_device(3)];
+
+        let fanout = build_group_fanout(&mut signal, &group, &devices, b"hello").unwrap();
+        assert_eq!(fanout.enc.get_attr("type").map(String::as_str), Some("skmsg"));
+        assert_eq!(fanout.phash, participant_list_hash_v2(&devices));
+        assert!(signal.has_group_session(&group.to_string()));
+
+        // Without sessions nobody received the key, so it is offered again next time
+        assert!(fanout.participants.get_children().unwrap().is_empty());
+        let addresses: Vec<String> = devices.iter().map(JID::signal_address).collect();
+        assert_eq!(signal.missing_sender_key(&group.to_string(), &addresses).len(), 2);
+    }
+}
@@ -0,0 +1,180 @@
+/// Worker pool for incoming stanzas
+///
+/// Decrypting a message and running the event handlers can take a while,
+/// so the socket reader hands stanzas to a fixed number of workers instead
+/// of processing them itself. All stanzas from one chat go to the same
+/// worker and are processed in the order they arrived, different chats are
+/// processed in parallel. Each worker has a bounded queue; when it is full
+/// [`ReceiveWorkerPool::submit`] waits, which stops the reader from pulling
+/// more frames off the socket until the consumers caught up.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+};
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Default number of workers processing incoming stanzas
+pub const DEFAULT_RECEIVE_WORKERS: usize = 4;
+
+/// Default number of stanzas queued per worker before the reader waits
+pub const DEFAULT_RECEIVE_QUEUE_SIZE: usize = 64;
+
+/// Processes one stanza on a worker
+pub type NodeHandler = Arc<dyn Fn(Node) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Bounded pool of workers processing stanzas with per-chat ordering
+pub struct ReceiveWorkerPool {
+    queues: Vec<mpsc::Sender<Node>>,
+    queue_size: usize,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl ReceiveWorkerPool {
+    /// Spawn `workers` workers, each queueing up to `queue_size` stanzas
+    pub fn new(workers: usize, queue_size: usize, handler: NodeHandler) -> Self {
+        let queue_size = queue_size.max(1);
+        let (queues, handles) = (0..workers.max(1))
+            .map(|worker| {
+                let (sender, mut receiver) = mpsc::channel::<Node>(queue_size);
+                let handler = handler.clone();
+                let handle = tokio::spawn(async move {
+                    while let Some(node) = receiver.recv().await {
+                        handler(node).await;
+                    }
+                    debug!("Receive worker {} stopped", worker);
+                });
+                (sender, handle)
+            })
+            .unzip();
+
+        Self { queues, queue_size, handles }
+    }
+
+    /// Number of workers
+    pub fn workers(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Index of the worker processing the stanza
+    pub fn worker_for(&self, node: &Node) -> usize {
+        let mut hasher = DefaultHasher::new();
+        ordering_key(node).hash(&mut hasher);
+        (hasher.finish() % self.queues.len() as u64) as usize
+    }
+
+    /// Queue a stanza, waiting while its worker's queue is full
+    pub async fn submit(&self, node: Node) -> Result<()> {
+        self.queues[self.worker_for(&node)]
+            .send(node)
+            .await
+            .map_err(|_| Error::Protocol("Receive worker stopped".to_string()))
+    }
+
+    /// Queue a stanza without waiting, handing it back if the worker is busy
+    pub fn try_submit(&self, node: Node) -> std::result::Result<(), Node> {
+        self.queues[self.worker_for(&node)].try_send(node).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(node) | mpsc::error::TrySendError::Closed(node) => node,
+        })
+    }
+
+    /// Number of stanzas waiting in all queues
+    pub fn queued(&self) -> usize {
+        self.queues.iter().map(|queue| self.queue_size - queue.capacity()).sum()
+    }
+
+    /// Stop accepting stanzas and wait until the queued ones are processed
+    pub async fn shutdown(self) {
+        drop(self.queues);
+        for handle in self.handles {
+            let _ = handle.await;
+        }
+    }
+}
+
+/// Stanzas with the same key are processed in order.
+///
+/// This is the chat the stanza belongs to, so messages, receipts and
+/// notifications of one chat never overtake each other.
+pub fn ordering_key(node: &Node) -> &str {
+    node.get_attr("from").map(String::as_str).unwrap_or(node.tag.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Semaphore;
+
+    fn message(from: &str, id: &str) -> Node {
+        Node::builder("message").attr("from", from).attr("id", id).build()
+    }
+
+    #[tokio::test]
+    async fn test_per_chat_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler: NodeHandler = {
+            let seen = seen.clone();
+            Arc::new(move |node| {
+                let seen = seen.clone();
+                Box::pin(async move {
+                    // Later stanzas finish faster, they must still not overtake
+                    let id: u64 = node.get_attr("id").unwrap().parse().unwrap();
+                    tokio::time::sleep(Duration::from_millis(10 - id)).await;
+                    seen.lock().unwrap().push((ordering_key(&node).to_string(), id));
+                })
+            })
+        };
+
+        let pool = ReceiveWorkerPool::new(3, 16, handler);
+        for id in 0..10 {
+            let chat = if id % 2 == 0 { "a@s.whatsapp.net" } else { "b@g.us" };
+            pool.submit(message(chat, &id.to_string())).await.unwrap();
+        }
+        pool.shutdown().await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 10);
+        for chat in ["a@s.whatsapp.net", "b@g.us"] {
+            let ids: Vec<_> = seen.iter().filter(|(c, _)| c == chat).map(|(_, id)| *id).collect();
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let gate = Arc::new(Semaphore::new(0));
+        let handler: NodeHandler = {
+            let gate = gate.clone();
+            Arc::new(move |_| {
+                let gate = gate.clone();
+                Box::pin(async move {
+                    gate.acquire().await.unwrap().forget();
+                })
+            })
+        };
+
+        let pool = ReceiveWorkerPool::new(1, 2, handler);
+        // One stanza is being processed, two fill the queue
+        for id in 0..3 {
+            pool.submit(message("a@s.whatsapp.net", &id.to_string())).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(pool.queued(), 2);
+        let rejected = pool.try_submit(message("a@s.whatsapp.net", "3")).unwrap_err();
+        assert!(tokio::time::timeout(Duration::from_millis(20), pool.submit(rejected.clone())).await.is_err());
+
+        gate.add_permits(1);
+        pool.submit(rejected).await.unwrap();
+        gate.add_permits(10);
+        pool.shutdown().await;
+    }
+}
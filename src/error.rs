@@ -1,3 +1,4 @@
+use crate::binary::Node;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -43,14 +44,38 @@ pub enum Error {
     #[error("Element missing: {0}")]
     ElementMissing(String),
     
+    /// The server answered a query with an error; `node` is the error
+    /// response, kept for debugging
     #[error("IQ error - code: {code}, text: {text}")]
-    IQ { code: u16, text: String },
+    Iq { code: u16, text: String, node: Option<Box<Node>> },
+    
+    #[error("Not a participant of the group")]
+    NotInGroup,
+    
+    #[error("Media size {size} exceeds the limit of {max} bytes")]
+    MediaSizeExceeded { size: u64, max: u64 },
+    
+    /// Too many requests; `retry_after` is how long the server asked to wait
+    #[error("Rate limited")]
+    RateLimited { retry_after: Option<std::time::Duration> },
     
     #[error("Database error: {0}")]
     Database(String),
     
     #[error("Serialization error: {0}")]
     Serialization(String),
+    
+    #[error("Send blocked: {0}")]
+    SendBlocked(String),
+    
+    /// The client was configured as a passive listener and does not send
+    #[error("Client is in passive mode")]
+    PassiveMode,
+    
+    /// The media is gone from the CDN; the sender can upload it again, see
+    /// [`crate::media::retry`]
+    #[error("Media expired")]
+    MediaExpired,
 }
 
 impl From<tokio_tungstenite::tungstenite::Error> for Error {
@@ -81,4 +106,144 @@ impl From<prost::DecodeError> for Error {
     fn from(err: prost::DecodeError) -> Self {
         Error::ProtobufDecode(err.to_string())
     }
-}
\ No newline at end of file
+}
+impl Error {
+    /// IQ error from a server response, keeping the response
+    pub fn iq(code: u16, text: impl Into<String>, node: Node) -> Self {
+        Error::Iq { code, text: text.into(), node: Some(Box::new(node)) }
+    }
+    
+    /// Stable identifier of the error kind, e.g. for metrics or bindings
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::WebSocket(_) => "websocket",
+            Error::Json(_) => "json",
+            Error::Io(_) => "io",
+            Error::UrlParse(_) => "url_parse",
+            Error::ProtobufDecode(_) => "protobuf_decode",
+            Error::Crypto(_) => "crypto",
+            Error::Auth(_) => "auth",
+            Error::Connection(_) => "connection",
+            Error::Protocol(_) => "protocol",
+            Error::InvalidJID(_) => "invalid_jid",
+            Error::NotLoggedIn => "not_logged_in",
+            Error::Disconnected(_) => "disconnected",
+            Error::ElementMissing(_) => "element_missing",
+            Error::Iq { .. } => "iq",
+            Error::NotInGroup => "not_in_group",
+            Error::MediaSizeExceeded { .. } => "media_size_exceeded",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::Database(_) => "database",
+            Error::Serialization(_) => "serialization",
+            Error::SendBlocked(_) => "send_blocked",
+            Error::PassiveMode => "passive_mode",
+            Error::MediaExpired => "media_expired",
+        }
+    }
+    
+    /// The stanza that caused the error, if it was kept
+    pub fn stanza(&self) -> Option<&Node> {
+        match self {
+            Error::Iq { node, .. } => node.as_deref(),
+            _ => None,
+        }
+    }
+    
+    /// How long to wait before retrying, if the server said so
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+    
+    /// Whether repeating the failed operation may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Network errors are generally retryable
+            Error::WebSocket(_) => true,
+            Error::Connection(_) => true,
+            Error::Disconnected(_) => true,
+            Error::Io(_) => true,
+            
+            // Rate limits pass, retry after waiting
+            Error::RateLimited { .. } => true,
+            
+            // Protocol errors usually indicate a permanent issue
+            Error::Protocol(_) => false,
+            Error::Auth(_) => false,
+            Error::Crypto(_) => false,
+            Error::InvalidJID(_) => false,
+            Error::NotLoggedIn => false,
+            Error::NotInGroup => false,
+            Error::MediaSizeExceeded { .. } => false,
+            Error::MediaExpired => false,
+            
+            // Data errors usually indicate a bug
+            Error::Json(_) => false,
+            Error::ProtobufDecode(_) => false,
+            Error::UrlParse(_) => false,
+            Error::ElementMissing(_) => false,
+            
+            // Database errors might be retryable depending on the cause
+            Error::Database(msg) => {
+                // Simple heuristic: if it mentions connection, it might be retryable
+                msg.to_lowercase().contains("connection") || 
+                msg.to_lowercase().contains("timeout") ||
+                msg.to_lowercase().contains("busy")
+            }
+            
+            // IQ errors depend on the specific error code
+            Error::Iq { code, .. } => matches!(
+                code,
+                408 // Request timeout
+                | 429 // Too many requests
+                | 500..=599 // Server errors (includes 503, 504)
+            ),
+            
+            // Serialization errors are generally not retryable
+            Error::Serialization(_) => false,
+            
+            // Blocked sends stay blocked until an operator lifts the block
+            Error::SendBlocked(_) => false,
+            Error::PassiveMode => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_is_retryable() {
+        // Retryable errors
+        assert!(Error::Connection("test".to_string()).is_retryable());
+        assert!(Error::WebSocket("connection closed".to_string()).is_retryable());
+        assert!(Error::Io("timeout".to_string()).is_retryable());
+        
+        // Non-retryable errors
+        assert!(!Error::Auth("test".to_string()).is_retryable());
+        assert!(!Error::Protocol("test".to_string()).is_retryable());
+        assert!(!Error::InvalidJID("test".to_string()).is_retryable());
+        assert!(!Error::NotInGroup.is_retryable());
+        assert!(!Error::MediaSizeExceeded { size: 2, max: 1 }.is_retryable());
+        
+        // IQ errors with retryable codes
+        assert!(Error::Iq { code: 500, text: "Server error".to_string(), node: None }.is_retryable());
+        assert!(Error::Iq { code: 429, text: "Rate limited".to_string(), node: None }.is_retryable());
+        
+        // IQ errors with non-retryable codes
+        assert!(!Error::Iq { code: 400, text: "Bad request".to_string(), node: None }.is_retryable());
+        assert!(!Error::Iq { code: 401, text: "Unauthorized".to_string(), node: None }.is_retryable());
+    }
+    
+    #[test]
+    fn test_iq_error_keeps_stanza() {
+        let node = Node::builder("iq").attr("type", "error").build();
+        let error = Error::iq(404, "item-not-found", node);
+        assert_eq!(error.code(), "iq");
+        assert_eq!(error.stanza().and_then(|n| n.get_attr("type")).map(String::as_str), Some("error"));
+        assert!(Error::NotLoggedIn.stanza().is_none());
+    }
+}
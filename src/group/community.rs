@@ -7,10 +7,12 @@
 use crate::{
     error::{Error, Result},
     types::JID,
-    group::{GroupInfo, GroupSettings, ParticipantPermission},
+    group::{protocol, GroupInfo, GroupSettings, MembershipRequest, ParticipantOperationResult, ParticipantPermission},
+    request::IqSender,
 };
 use serde::{Deserialize, Serialize};
-use std::{time::SystemTime, collections::HashMap};
+use std::{time::SystemTime, collections::HashMap, sync::Arc};
+use uuid::Uuid;
 
 /// WhatsApp Community information
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -37,8 +39,8 @@ pub struct CommunityInfo {
     pub linked_groups: Vec<JID>,
     /// Community membership (includes all members from linked groups)
     pub members: Vec<JID>,
-    /// Community announcement group (optional)
-    pub announcement_group: Option<JID>,
+    /// The community's default announcement group, created by the server
+    pub announcement_group_jid: Option<JID>,
 }
 
 impl CommunityInfo {
@@ -61,7 +63,7 @@ impl CommunityInfo {
             avatar: None,
             linked_groups: Vec::new(),
             members: Vec::new(),
-            announcement_group: None,
+            announcement_group_jid: None,
         }
     }
     
@@ -482,13 +484,25 @@ pub enum CommunityEvent {
     },
 }
 
+/// A group linked to a community, as reported by the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkedGroup {
+    /// Group JID
+    pub jid: JID,
+    /// Group subject
+    pub subject: String,
+    /// Whether this is the community's default announcement group
+    pub is_default_sub_group: bool,
+}
+
 /// Community manager for handling community operations
-#[derive(Debug)]
 pub struct CommunityManager {
     /// Cache of community information
     communities: HashMap<JID, CommunityInfo>,
     /// Mapping of groups to their parent communities
     group_to_community: HashMap<JID, JID>,
+    /// Sender for community queries, absent while offline
+    iq_sender: Option<Arc<dyn IqSender>>,
 }
 
 impl CommunityManager {
@@ -497,9 +511,25 @@ impl CommunityManager {
         Self {
             communities: HashMap::new(),
             group_to_community: HashMap::new(),
+            iq_sender: None,
         }
     }
     
+    /// Use the given sender for community queries
+    pub fn with_iq_sender(mut self, iq_sender: Arc<dyn IqSender>) -> Self {
+        self.iq_sender = Some(iq_sender);
+        self
+    }
+    
+    /// Set the sender for community queries
+    pub fn set_iq_sender(&mut self, iq_sender: Arc<dyn IqSender>) {
+        self.iq_sender = Some(iq_sender);
+    }
+    
+    fn iq_sender(&self) -> Result<&Arc<dyn IqSender>> {
+        self.iq_sender.as_ref().ok_or(Error::NotLoggedIn)
+    }
+    
     /// Create a new community
     pub async fn create_community(
         &mut self,
@@ -509,16 +539,21 @@ impl CommunityManager {
         // Validate request
         request.validate()?;
         
-        // Generate community JID (would normally be from server)
-        let community_jid = self.generate_community_jid();
+        // The server assigns the community JID and creation timestamp
+        let key = Uuid::new_v4().to_string();
+        let response = self.iq_sender()?
+            .send_iq(protocol::create_community_query(&request, &key))
+            .await?;
+        let created = protocol::parse_group_info(&response)?;
         
         // Create community info
         let mut community_info = CommunityInfo::new(
-            community_jid.clone(),
+            created.jid.clone(),
             request.name,
             creator.clone(),
             request.description,
         );
+        community_info.created_at = created.created_at;
         
         // Apply custom settings if provided
         if let Some(settings) = request.settings {
@@ -537,8 +572,15 @@ impl CommunityManager {
         community_info.validate()?;
         
         // Store community
-        self.communities.insert(community_jid.clone(), community_info.clone());
+        let community_jid = community_info.jid.clone();
+        self.communities.insert(community_jid.clone(), community_info);
+        
+        // The server creates the default announcement group along with the community
+        if request.create_announcement_group {
+            self.get_linked_groups(&community_jid).await?;
+        }
         
+        let community_info = self.communities[&community_jid].clone();
         tracing::info!("Created community: {}", community_info.name);
         
         Ok(community_info)
@@ -553,36 +595,54 @@ impl CommunityManager {
         // Validate request
         request.validate()?;
         
-        // Get community
-        let community = self.communities.get_mut(&request.community_jid)
-            .ok_or_else(|| Error::Protocol("Community not found".to_string()))?;
+        if !self.communities.contains_key(&request.community_jid) {
+            return Err(Error::Protocol("Community not found".to_string()));
+        }
+        
+        self.link_group(&request.community_jid, &request.group_jid).await?;
+        
+        // Merge members if requested
+        if request.merge_members {
+            if let Some(community) = self.communities.get_mut(&request.community_jid) {
+                for participant in &group_info.participants {
+                    if !community.members.contains(participant) {
+                        community.members.push(participant.clone());
+                    }
+                }
+            }
+        }
         
+        Ok(())
+    }
+    
+    /// Link a group into a community on the server.
+    ///
+    /// The community does not need to be cached; if it is, the cache is updated.
+    pub async fn link_group(&mut self, community_jid: &JID, group_jid: &JID) -> Result<()> {
         // Check if group is already in a community
-        if self.group_to_community.contains_key(&request.group_jid) {
+        if self.group_to_community.contains_key(group_jid) {
             return Err(Error::Protocol("Group already belongs to a community".to_string()));
         }
         
         // Check group limit
-        if community.linked_groups.len() >= 50 {
-            return Err(Error::Protocol("Community group limit reached".to_string()));
+        if let Some(community) = self.communities.get(community_jid) {
+            if community.linked_groups.len() >= 50 {
+                return Err(Error::Protocol("Community group limit reached".to_string()));
+            }
         }
         
-        // Add group to community
-        community.add_group(request.group_jid.clone());
+        self.iq_sender()?
+            .send_iq(protocol::link_group_query(community_jid, group_jid))
+            .await?;
         
-        // Update group-to-community mapping
-        self.group_to_community.insert(request.group_jid.clone(), request.community_jid.clone());
-        
-        // Merge members if requested
-        if request.merge_members {
-            for participant in &group_info.participants {
-                if !community.members.contains(participant) {
-                    community.members.push(participant.clone());
-                }
-            }
+        if let Some(community) = self.communities.get_mut(community_jid) {
+            community.add_group(group_jid.clone());
         }
         
-        tracing::info!("Added group {} to community {}", request.group_jid, request.community_jid);
+        // Update group-to-community mapping
+        self.group_to_community.insert(group_jid.clone(), community_jid.clone());
+        
+        tracing::info!("Added group {} to community {}", group_jid, community_jid);
         
         Ok(())
     }
@@ -593,12 +653,14 @@ impl CommunityManager {
         community_jid: &JID,
         group_jid: &JID,
     ) -> Result<()> {
-        // Get community
-        let community = self.communities.get_mut(community_jid)
-            .ok_or_else(|| Error::Protocol("Community not found".to_string()))?;
+        self.iq_sender()?
+            .send_iq(protocol::unlink_group_query(community_jid, group_jid))
+            .await?;
         
         // Remove group from community
-        community.remove_group(group_jid);
+        if let Some(community) = self.communities.get_mut(community_jid) {
+            community.remove_group(group_jid);
+        }
         
         // Update mapping
         self.group_to_community.remove(group_jid);
@@ -608,6 +670,82 @@ impl CommunityManager {
         Ok(())
     }
     
+    /// Fetch the groups linked to a community from the server.
+    ///
+    /// The cached community, if any, is updated with the result.
+    pub async fn get_linked_groups(&mut self, community_jid: &JID) -> Result<Vec<LinkedGroup>> {
+        let response = self.iq_sender()?
+            .send_iq(protocol::sub_groups_query(community_jid))
+            .await?;
+        let groups = protocol::parse_sub_groups(&response)?;
+        
+        if let Some(community) = self.communities.get_mut(community_jid) {
+            for group in &groups {
+                if group.is_default_sub_group {
+                    community.announcement_group_jid = Some(group.jid.clone());
+                }
+                community.add_group(group.jid.clone());
+                self.group_to_community.insert(group.jid.clone(), community_jid.clone());
+            }
+        }
+        
+        Ok(groups)
+    }
+    
+    /// JID of a community's announcement group, fetched from the server if not cached
+    pub async fn announcement_group_jid(&mut self, community_jid: &JID) -> Result<JID> {
+        let cached = self.communities
+            .get(community_jid)
+            .and_then(|c| c.announcement_group_jid.clone());
+        if let Some(jid) = cached {
+            return Ok(jid);
+        }
+        
+        self.get_linked_groups(community_jid)
+            .await?
+            .into_iter()
+            .find(|g| g.is_default_sub_group)
+            .map(|g| g.jid)
+            .ok_or_else(|| Error::Protocol("Community has no announcement group".to_string()))
+    }
+    
+    /// Fetch the pending requests to join a community
+    pub async fn get_join_requests(&self, community_jid: &JID) -> Result<Vec<MembershipRequest>> {
+        let response = self.iq_sender()?
+            .send_iq(protocol::membership_requests_query(community_jid))
+            .await?;
+        protocol::parse_membership_requests(&response)
+    }
+    
+    /// Approve or reject pending requests to join a community
+    pub async fn update_join_requests(
+        &mut self,
+        community_jid: &JID,
+        participants: Vec<JID>,
+        approve: bool,
+    ) -> Result<ParticipantOperationResult> {
+        if participants.is_empty() {
+            return Err(Error::Protocol("No participants specified".to_string()));
+        }
+        
+        let response = self.iq_sender()?
+            .send_iq(protocol::membership_requests_action_query(community_jid, approve, &participants))
+            .await?;
+        let result = protocol::parse_membership_requests_action_response(&response, approve, &participants)?;
+        
+        if approve {
+            if let Some(community) = self.communities.get_mut(community_jid) {
+                for jid in &result.successful {
+                    if !community.members.contains(jid) {
+                        community.members.push(jid.clone());
+                    }
+                }
+            }
+        }
+        
+        Ok(result)
+    }
+    
     /// Update community metadata
     pub async fn update_community_metadata(
         &mut self,
@@ -661,19 +799,6 @@ impl CommunityManager {
     pub fn is_group_in_community(&self, group_jid: &JID) -> bool {
         self.group_to_community.contains_key(group_jid)
     }
-    
-    /// Generate a community JID (simplified - normally from server)
-    fn generate_community_jid(&self) -> JID {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        let community_id = format!("community_{}", timestamp);
-        JID::new(community_id, "g.us".to_string())
-    }
 }
 
 impl Default for CommunityManager {
@@ -685,6 +810,7 @@ impl Default for CommunityManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::binary::Node;
     
     fn create_test_jid(user: &str) -> JID {
         JID::new(user.to_string(), "s.whatsapp.net".to_string())
@@ -779,23 +905,6 @@ mod tests {
         assert!(invalid_request.validate().is_err());
     }
     
-    #[tokio::test]
-    async fn test_community_manager() {
-        let mut manager = CommunityManager::new();
-        let creator = create_test_jid("creator");
-        
-        // Create community
-        let request = CreateCommunityRequest::new("Test Community".to_string());
-        let community_info = manager.create_community(request, creator.clone()).await.unwrap();
-        
-        assert_eq!(community_info.name, "Test Community");
-        assert_eq!(community_info.creator, creator);
-        
-        // Check if community exists
-        assert!(manager.get_community(&community_info.jid).is_some());
-        assert_eq!(manager.get_all_communities().len(), 1);
-    }
-    
     #[test]
     fn test_community_metadata_update() {
         let update = CommunityMetadataUpdate::new()
@@ -835,4 +944,107 @@ mod tests {
         assert!(!community_info.has_group(&group_jid));
         assert_eq!(community_info.group_count(), 0);
     }
-}
\ No newline at end of file
+    
+    fn iq(children: Vec<Node>) -> Node {
+        Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(children)
+    }
+    
+    fn sub_groups_node() -> Node {
+        iq(vec![Node::new("sub_groups".to_string()).with_children(vec![
+            Node::new("group".to_string())
+                .attr("id".to_string(), "announce_1".to_string())
+                .attr("subject".to_string(), "Announcements".to_string())
+                .with_children(vec![Node::new("default_sub_group".to_string())]),
+        ])])
+    }
+    
+    #[tokio::test]
+    async fn test_community_manager() {
+        let sender = Arc::new(crate::request::StaticIqSender::sequence(vec![
+            iq(vec![protocol::test_group_node("community_123")]),
+            sub_groups_node(),
+            iq(vec![]),
+        ]));
+        let mut manager = CommunityManager::new().with_iq_sender(sender.clone());
+        let creator = create_test_jid("creator");
+        
+        let request = CreateCommunityRequest::new("Neighbours".to_string()).with_announcement_group(true);
+        let community = manager.create_community(request, creator.clone()).await.unwrap();
+        assert_eq!(community.jid, create_test_community_jid());
+        assert_eq!(community.name, "Neighbours");
+        assert_eq!(community.creator, creator);
+        assert!(manager.get_community(&community.jid).is_some());
+        assert_eq!(manager.get_all_communities().len(), 1);
+        let announcement = JID::new("announce_1".to_string(), "g.us".to_string());
+        assert_eq!(community.announcement_group_jid, Some(announcement.clone()));
+        assert_eq!(manager.announcement_group_jid(&community.jid).await.unwrap(), announcement);
+        assert_eq!(manager.find_community_for_group(&announcement), Some(&community.jid));
+        
+        let group_jid = create_test_group_jid();
+        manager.link_group(&community.jid, &group_jid).await.unwrap();
+        assert!(manager.is_group_in_community(&group_jid));
+        assert!(manager.link_group(&community.jid, &group_jid).await.is_err());
+        
+        manager.remove_group_from_community(&community.jid, &group_jid).await.unwrap();
+        assert!(!manager.is_group_in_community(&group_jid));
+        
+        let queries = sender.queries.lock().unwrap();
+        assert_eq!(queries.len(), 4);
+        let link = queries[2].to_node("1");
+        assert!(link.find_child("links").is_some());
+    }
+    
+    #[tokio::test]
+    async fn test_announcement_group_lookup() {
+        let mut manager = CommunityManager::new()
+            .with_iq_sender(Arc::new(crate::request::StaticIqSender::new(sub_groups_node())));
+        let community_jid = create_test_community_jid();
+        let announcement = manager.announcement_group_jid(&community_jid).await.unwrap();
+        assert_eq!(announcement, JID::new("announce_1".to_string(), "g.us".to_string()));
+        
+        let mut manager = CommunityManager::new()
+            .with_iq_sender(Arc::new(crate::request::StaticIqSender::new(iq(vec![Node::new("sub_groups".to_string())]))));
+        assert!(manager.announcement_group_jid(&community_jid).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_community_join_requests() {
+        let asker = create_test_jid("asker");
+        let sender = Arc::new(crate::request::StaticIqSender::sequence(vec![
+            iq(vec![Node::new("membership_approval_requests".to_string()).with_children(vec![
+                Node::new("membership_approval_request".to_string()).attr("jid".to_string(), asker.to_string()),
+            ])]),
+            iq(vec![Node::new("membership_requests_action".to_string()).with_children(vec![
+                Node::new("approve".to_string()).with_children(vec![
+                    Node::new("participant".to_string()).attr("jid".to_string(), asker.to_string()),
+                ]),
+            ])]),
+        ]));
+        let mut manager = CommunityManager::new().with_iq_sender(sender);
+        let community_jid = create_test_community_jid();
+        
+        let requests = manager.get_join_requests(&community_jid).await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].jid, asker);
+        
+        let result = manager.update_join_requests(&community_jid, vec![asker.clone()], true).await.unwrap();
+        assert_eq!(result.successful, vec![asker]);
+    }
+    
+    #[tokio::test]
+    async fn test_community_requires_connection() {
+        let mut manager = CommunityManager::new();
+        let request = CreateCommunityRequest::new("Neighbours".to_string());
+        
+        assert!(matches!(
+            manager.create_community(request, create_test_jid("creator")).await,
+            Err(Error::NotLoggedIn)
+        ));
+        assert!(matches!(
+            manager.get_linked_groups(&create_test_community_jid()).await,
+            Err(Error::NotLoggedIn)
+        ));
+    }
+}
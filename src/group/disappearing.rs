@@ -8,6 +8,7 @@ use crate::{
     error::{Error, Result},
     types::JID,
     group::{GroupInfo, GroupSettings, DisappearingMessageSettings},
+    server_time,
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -208,7 +209,7 @@ impl DisappearingMessage {
         timer: DisappearingTimer,
         content_type: MessageContentType,
     ) -> Self {
-        let sent_at = SystemTime::now();
+        let sent_at = server_time::now();
         let disappear_at = sent_at + Duration::from_secs(timer.duration_seconds());
         
         Self {
@@ -521,41 +522,36 @@ impl GroupDisappearingManager {
     
     /// Process disappearing messages (should be called periodically)
     pub async fn process_disappearing_messages(&mut self) -> Result<Vec<(JID, String)>> {
+        let disappeared = self.take_disappeared_messages();
+        Self::cleanup_media_files(&disappeared).await;
+        Ok(disappeared.into_iter().map(|message| (message.group_jid, message.message_id)).collect())
+    }
+    
+    /// Remove the messages whose timer ran out and update the statistics.
+    ///
+    /// Their media files are left to [`cleanup_media_files`](Self::cleanup_media_files),
+    /// which does not need the manager, so callers can release their lock first.
+    pub fn take_disappeared_messages(&mut self) -> Vec<DisappearingMessage> {
         let mut disappeared_messages = Vec::new();
         
         for (group_jid, scheduled) in &mut self.scheduled_messages {
-            let mut messages_to_remove = Vec::new();
+            let (mut expired, pending): (Vec<_>, Vec<_>) = std::mem::take(scheduled)
+                .into_iter()
+                .partition(DisappearingMessage::should_disappear);
+            *scheduled = pending;
             
-            for (index, message) in scheduled.iter_mut().enumerate() {
-                if message.should_disappear() {
-                    // Mark as deleted
-                    message.mark_deleted();
-                    
-                    // Add to cleanup list  
-                    disappeared_messages.push((group_jid.clone(), message.message_id.clone()));
-                    
-                    // Clean up media files
-                    for media_file in &message.media_files {
-                        if let Err(e) = self.cleanup_media_file(media_file).await {
-                            tracing::warn!("Failed to cleanup media file {}: {}", media_file, e);
-                        }
-                    }
-                    
-                    messages_to_remove.push(index);
-                    
-                    // Update stats
-                    if let Some(stats) = self.stats.get_mut(group_jid) {
-                        stats.message_disappeared(message.media_files.len() as u32);
-                    }
-                    
-                    tracing::info!("Message {} disappeared from group {}", message.message_id, group_jid);
+            for message in &mut expired {
+                // Mark as deleted
+                message.mark_deleted();
+                
+                // Update stats
+                if let Some(stats) = self.stats.get_mut(group_jid) {
+                    stats.message_disappeared(message.media_files.len() as u32);
                 }
+                
+                tracing::info!("Message {} disappeared from group {}", message.message_id, group_jid);
             }
-            
-            // Remove disappeared messages (in reverse order to maintain indices)
-            for index in messages_to_remove.into_iter().rev() {
-                scheduled.remove(index);
-            }
+            disappeared_messages.extend(expired);
             
             // Update cleanup time
             if let Some(stats) = self.stats.get_mut(group_jid) {
@@ -563,11 +559,20 @@ impl GroupDisappearingManager {
             }
         }
         
-        Ok(disappeared_messages)
+        disappeared_messages
+    }
+    
+    /// Clean up the media files of disappeared messages
+    pub async fn cleanup_media_files(messages: &[DisappearingMessage]) {
+        for media_file in messages.iter().flat_map(|message| &message.media_files) {
+            if let Err(e) = Self::cleanup_media_file(media_file).await {
+                tracing::warn!("Failed to cleanup media file {}: {}", media_file, e);
+            }
+        }
     }
     
     /// Clean up media file
-    async fn cleanup_media_file(&self, file_path: &str) -> Result<()> {
+    async fn cleanup_media_file(file_path: &str) -> Result<()> {
         // This would actually delete the file from storage
         // For now, just log the action
         tracing::info!("Cleaning up media file: {}", file_path);
@@ -773,6 +778,16 @@ mod tests {
         let stats = manager.get_stats(&group_jid).unwrap();
         assert_eq!(stats.total_scheduled, 1);
         assert_eq!(stats.pending_count, 1);
+        
+        // Nothing is due yet
+        assert!(manager.take_disappeared_messages().is_empty());
+        
+        manager.scheduled_messages.get_mut(&group_jid).unwrap()[0].disappear_at = SystemTime::UNIX_EPOCH;
+        let disappeared = manager.take_disappeared_messages();
+        assert_eq!(disappeared.len(), 1);
+        assert!(disappeared[0].deleted);
+        assert_eq!(manager.get_pending_count(&group_jid), 0);
+        assert_eq!(manager.get_stats(&group_jid).unwrap().disappeared_count, 1);
     }
     
     #[test]
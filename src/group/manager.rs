@@ -4,17 +4,20 @@ use crate::{
     error::{Error, Result},
     types::JID,
     group::{
-        GroupInfo, GroupSettings, CreateGroupRequest, GroupMetadataUpdate,
-        GroupEvent,
+        GroupInfo, GroupInviteInfo, GroupPhoto, GroupSettings, CreateGroupRequest, GroupMetadataUpdate,
+        GroupEvent, MemberAddMode, MembershipRequest, ParticipantPermission, protocol,
     },
+    request::{InfoQueryType, IqSender},
+    types::GroupInviteMessage,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 use uuid::Uuid;
 
 // Import from participants module
-use crate::group::participants::ParticipantOperationResult;
+use crate::group::participants::{ParticipantOperationResult, ParticipantOperationType};
 
 /// Configuration for group manager
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,15 +44,28 @@ impl Default for GroupManagerConfig {
 }
 
 /// Group manager handles core group operations
+///
+/// All methods take `&self` and keep their state behind short-lived
+/// synchronous locks, so the manager is shared without a lock held across
+/// server queries.
 pub struct GroupManager {
     /// Configuration
     config: GroupManagerConfig,
     /// Event handlers for group events
-    event_handlers: Vec<Box<dyn Fn(&GroupEvent) + Send + Sync>>,
+    event_handlers: RwLock<Vec<GroupEventHandler>>,
     /// Group operation history
-    operation_history: Vec<GroupOperation>,
+    operation_history: RwLock<Vec<GroupOperation>>,
+    /// Sender for server queries
+    iq_sender: RwLock<Option<Arc<dyn IqSender>>>,
+    /// Group info fetched from the server, kept current by notifications
+    cache: RwLock<HashMap<JID, GroupInfo>>,
+    /// Our own JID, recorded as the author of our operations
+    own_jid: RwLock<Option<JID>>,
 }
 
+/// Callback receiving the events of group operations
+type GroupEventHandler = Box<dyn Fn(&GroupEvent) + Send + Sync>;
+
 /// Group operation record for history/audit
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupOperation {
@@ -83,6 +99,8 @@ pub enum GroupOperationType {
     GetInviteLink,
     RevokeInviteLink,
     JoinViaInvite,
+    ApproveJoinRequests,
+    RejectJoinRequests,
 }
 
 /// Operation result
@@ -103,29 +121,129 @@ impl GroupManager {
     pub fn with_config(config: GroupManagerConfig) -> Self {
         Self {
             config,
-            event_handlers: Vec::new(),
-            operation_history: Vec::new(),
+            event_handlers: RwLock::new(Vec::new()),
+            operation_history: RwLock::new(Vec::new()),
+            iq_sender: RwLock::new(None),
+            cache: RwLock::new(HashMap::new()),
+            own_jid: RwLock::new(None),
         }
     }
     
+    /// Use the given sender for server queries
+    pub fn with_iq_sender(self, iq_sender: Arc<dyn IqSender>) -> Self {
+        self.set_iq_sender(iq_sender);
+        self
+    }
+    
+    /// Set the sender used for server queries
+    pub fn set_iq_sender(&self, iq_sender: Arc<dyn IqSender>) {
+        *self.iq_sender.write().unwrap() = Some(iq_sender);
+    }
+    
+    fn iq_sender(&self) -> Result<Arc<dyn IqSender>> {
+        self.iq_sender.read().unwrap().clone().ok_or(Error::NotLoggedIn)
+    }
+    
+    /// Use the given JID as our own
+    pub fn with_own_jid(self, jid: JID) -> Self {
+        self.set_own_jid(jid);
+        self
+    }
+    
+    /// Set our own JID once the device is known
+    pub fn set_own_jid(&self, jid: JID) {
+        *self.own_jid.write().unwrap() = Some(jid.to_non_ad());
+    }
+    
+    fn own_jid(&self) -> Result<JID> {
+        self.own_jid.read().unwrap().clone().ok_or(Error::NotLoggedIn)
+    }
+    
     /// Add event handler
-    pub fn add_event_handler<F>(&mut self, handler: F)
+    pub fn add_event_handler<F>(&self, handler: F)
     where
         F: Fn(&GroupEvent) + Send + Sync + 'static,
     {
-        self.event_handlers.push(Box::new(handler));
+        self.event_handlers.write().unwrap().push(Box::new(handler));
     }
     
     /// Emit group event
     fn emit_event(&self, event: &GroupEvent) {
-        for handler in &self.event_handlers {
+        for handler in self.event_handlers.read().unwrap().iter() {
             handler(event);
         }
     }
     
+    /// Group info as of the last fetch plus the notifications received since
+    pub fn cached_group_info(&self, group_jid: &JID) -> Option<GroupInfo> {
+        self.cache.read().unwrap().get(group_jid).cloned()
+    }
+    
+    /// Apply a group change pushed by the server to the cached group info
+    /// and pass it to the event handlers
+    pub fn apply_event(&self, event: &GroupEvent) {
+        {
+            let mut cache = self.cache.write().unwrap();
+            match event {
+                GroupEvent::SubjectChanged { group_jid, subject, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.name = subject.clone();
+                    }
+                }
+                GroupEvent::DescriptionChanged { group_jid, description, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.description = description.clone();
+                    }
+                }
+                GroupEvent::AnnounceChanged { group_jid, announce, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.settings.announcement_only = *announce;
+                        group.settings.send_messages = permission(*announce);
+                    }
+                }
+                GroupEvent::LockedChanged { group_jid, locked, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.settings.edit_group_info = permission(*locked);
+                    }
+                }
+                GroupEvent::ParticipantsAdded { group_jid, participants, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        for participant in participants {
+                            if !group.participants.contains(participant) {
+                                group.participants.push(participant.clone());
+                            }
+                        }
+                    }
+                }
+                GroupEvent::ParticipantsRemoved { group_jid, participants, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.participants.retain(|p| !participants.contains(p));
+                        group.admins.retain(|p| !participants.contains(p));
+                    }
+                }
+                GroupEvent::ParticipantsPromoted { group_jid, participants, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        for participant in participants {
+                            if !group.admins.contains(participant) {
+                                group.admins.push(participant.clone());
+                            }
+                        }
+                    }
+                }
+                GroupEvent::ParticipantsDemoted { group_jid, participants, .. } => {
+                    if let Some(group) = cache.get_mut(group_jid) {
+                        group.admins.retain(|p| !participants.contains(p));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.emit_event(event);
+    }
+    
     /// Record operation in history
     fn record_operation(
-        &mut self,
+        &self,
         operation_type: GroupOperationType,
         group_jid: &JID,
         performed_by: &JID,
@@ -142,16 +260,17 @@ impl GroupManager {
             context,
         };
         
-        self.operation_history.push(operation);
+        let mut history = self.operation_history.write().unwrap();
+        history.push(operation);
         
         // Keep history size manageable
-        if self.operation_history.len() > 1000 {
-            self.operation_history.remove(0);
+        if history.len() > 1000 {
+            history.remove(0);
         }
     }
     
     /// Create a new WhatsApp group
-    pub async fn create_group(&mut self, request: CreateGroupRequest) -> Result<GroupInfo> {
+    pub async fn create_group(&self, request: CreateGroupRequest) -> Result<GroupInfo> {
         // Validate request
         request.validate()?;
         
@@ -163,34 +282,28 @@ impl GroupManager {
             )));
         }
         
-        // Generate group JID
-        let group_id = self.generate_group_id();
-        let group_jid = JID::new(group_id, "g.us".to_string());
-        
-        // For now, we'll simulate the creator JID
-        // In a real implementation, this would come from the authenticated session
-        let creator_jid = JID::new("creator".to_string(), "s.whatsapp.net".to_string());
-        
-        // Create participants list including creator
-        let mut participants = vec![creator_jid.clone()];
-        participants.extend(request.participants.clone());
-        
-        // Remove duplicates
-        participants.sort();
-        participants.dedup();
-        
-        // Create group info
-        let group_info = GroupInfo {
-            jid: group_jid.clone(),
-            name: request.name.clone(),
-            description: request.description.clone(),
-            participants,
-            admins: vec![creator_jid.clone()],
-            creator: creator_jid.clone(),
-            created_at: SystemTime::now(),
-            settings: request.settings.unwrap_or_default(),
-            invite_link: None,
-        };
+        let iq_sender = self.iq_sender()?;
+        
+        // The server assigns the group JID and creation timestamp
+        let key = Uuid::new_v4().to_string();
+        let response = iq_sender.send_iq(protocol::create_group_query(&request, &key)).await?;
+        let mut group_info = protocol::parse_group_info(&response)?;
+        let group_jid = group_info.jid.clone();
+        // The create query has no room for settings, they follow one by one
+        if let Some(settings) = request.settings.clone() {
+            for query in protocol::group_settings_queries(&group_jid, &group_info.settings, &settings) {
+                iq_sender.send_iq(query).await.map_err(|e| {
+                    Error::Protocol(format!("Created group {} but failed to apply its settings: {}", group_jid, e))
+                })?;
+            }
+            group_info.join_approval_mode = settings.membership_approval;
+            group_info.member_add_mode = match settings.add_participants {
+                ParticipantPermission::AdminsOnly => MemberAddMode::AdminAdd,
+                ParticipantPermission::Everyone => MemberAddMode::AllMemberAdd,
+            };
+            group_info.settings = settings;
+        }
+        let creator_jid = group_info.creator.clone();
         
         // Validate final group info
         group_info.validate()?;
@@ -220,46 +333,67 @@ impl GroupManager {
         Ok(group_info)
     }
     
-    /// Add participants to a group
-    pub async fn add_participants(
-        &mut self,
+    /// Send a participant change to the server, validating participants first
+    async fn change_participants(
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
+        operation: ParticipantOperationType,
     ) -> Result<ParticipantOperationResult> {
-        let mut result = ParticipantOperationResult::new();
-        
-        // Simulate current user
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
+        let action = protocol::participant_action_tag(&operation)
+            .ok_or_else(|| Error::Protocol(format!("Unsupported participant operation: {:?}", operation)))?;
+        let iq_sender = self.iq_sender()?;
         
-        // Validate each participant
+        let mut invalid = Vec::new();
+        let mut valid = Vec::new();
         for participant in participants {
             if self.config.validate_participants {
                 if let Err(e) = self.validate_participant(&participant) {
-                    result.add_failure(participant, e.to_string());
+                    invalid.push((participant, e.to_string()));
                     continue;
                 }
             }
-            
-            // In a real implementation, this would send the actual WhatsApp protocol messages
-            // For now, we'll simulate success
-            result.add_success(participant);
+            valid.push(participant);
         }
         
-        // Record operation
+        let mut result = if valid.is_empty() {
+            ParticipantOperationResult::with_operation(operation)
+        } else {
+            let response = iq_sender
+                .send_iq(protocol::participant_change_query(group_jid, action, &valid))
+                .await?;
+            protocol::parse_participant_change_response(&response, action, &valid, operation)
+        };
+        result.failed.extend(invalid);
+        
+        Ok(result)
+    }
+    
+    /// Record and announce the outcome of a participant change
+    fn finish_participant_change(
+        &self,
+        group_jid: &JID,
+        current_user: JID,
+        operation_type: GroupOperationType,
+        result: &ParticipantOperationResult,
+    ) {
         let operation_result = if result.all_successful() {
             OperationResult::Success
         } else if result.any_successful() {
             OperationResult::PartialSuccess
         } else {
-            OperationResult::Failed("All participants failed to be added".to_string())
+            OperationResult::Failed(format!("All participants failed: {:?}", operation_type))
         };
         
         let mut context = HashMap::new();
         context.insert("successful_count".to_string(), result.success_count().to_string());
         context.insert("failed_count".to_string(), result.failure_count().to_string());
+        for (participant, reason) in &result.failed {
+            context.insert(format!("failed:{}", participant), reason.clone());
+        }
         
         self.record_operation(
-            GroupOperationType::AddParticipants,
+            operation_type.clone(),
             group_jid,
             &current_user,
             operation_result,
@@ -268,162 +402,90 @@ impl GroupManager {
         
         // Emit event if any were successful
         if result.any_successful() {
-            let event = GroupEvent::ParticipantsAdded {
-                group_jid: group_jid.clone(),
-                participants: result.successful.clone(),
-                by: current_user,
+            let group_jid = group_jid.clone();
+            let participants = result.successful.clone();
+            let by = current_user;
+            let event = match operation_type {
+                GroupOperationType::AddParticipants | GroupOperationType::ApproveJoinRequests => {
+                    Some(GroupEvent::ParticipantsAdded { group_jid, participants, by })
+                }
+                GroupOperationType::RemoveParticipants => Some(GroupEvent::ParticipantsRemoved { group_jid, participants, by }),
+                GroupOperationType::PromoteParticipants => Some(GroupEvent::ParticipantsPromoted { group_jid, participants, by }),
+                GroupOperationType::DemoteParticipants => Some(GroupEvent::ParticipantsDemoted { group_jid, participants, by }),
+                _ => None,
             };
-            self.emit_event(&event);
+            if let Some(event) = event {
+                self.emit_event(&event);
+            }
         }
         
         tracing::info!(
-            "Added participants to group {}: {} successful, {} failed",
+            "{:?} in group {}: {} successful, {} failed",
+            operation_type,
             group_jid,
             result.success_count(),
             result.failure_count()
         );
-        
+    }
+    
+    /// Add participants to a group
+    pub async fn add_participants(
+        &self,
+        group_jid: &JID,
+        participants: Vec<JID>,
+    ) -> Result<ParticipantOperationResult> {
+        let own_jid = self.own_jid()?;
+        let result = self.change_participants(group_jid, participants, ParticipantOperationType::Add).await?;
+        self.finish_participant_change(group_jid, own_jid, GroupOperationType::AddParticipants, &result);
         Ok(result)
     }
     
     /// Remove participants from a group
     pub async fn remove_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
-        let mut result = ParticipantOperationResult::new();
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
-        
-        // Simulate removal (in real implementation, would send protocol messages)
-        for participant in participants {
-            // For simulation, assume all removals succeed
-            result.add_success(participant);
-        }
-        
-        // Record operation
-        let operation_result = if result.all_successful() {
-            OperationResult::Success
-        } else if result.any_successful() {
-            OperationResult::PartialSuccess
-        } else {
-            OperationResult::Failed("All participants failed to be removed".to_string())
-        };
-        
-        let mut context = HashMap::new();
-        context.insert("successful_count".to_string(), result.success_count().to_string());
-        context.insert("failed_count".to_string(), result.failure_count().to_string());
-        
-        self.record_operation(
-            GroupOperationType::RemoveParticipants,
-            group_jid,
-            &current_user,
-            operation_result,
-            context,
-        );
-        
-        // Emit event
-        if result.any_successful() {
-            let event = GroupEvent::ParticipantsRemoved {
-                group_jid: group_jid.clone(),
-                participants: result.successful.clone(),
-                by: current_user,
-            };
-            self.emit_event(&event);
-        }
-        
-        tracing::info!(
-            "Removed participants from group {}: {} successful, {} failed",
-            group_jid,
-            result.success_count(),
-            result.failure_count()
-        );
-        
+        let own_jid = self.own_jid()?;
+        let result = self.change_participants(group_jid, participants, ParticipantOperationType::Remove).await?;
+        self.finish_participant_change(group_jid, own_jid, GroupOperationType::RemoveParticipants, &result);
         Ok(result)
     }
     
     /// Promote participants to admin
     pub async fn promote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
-        let mut result = ParticipantOperationResult::new();
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
-        
-        // Simulate promotion
-        for participant in participants {
-            result.add_success(participant);
-        }
-        
-        // Record operation
-        self.record_operation(
-            GroupOperationType::PromoteParticipants,
-            group_jid,
-            &current_user,
-            OperationResult::Success,
-            HashMap::new(),
-        );
-        
-        // Emit event
-        if result.any_successful() {
-            let event = GroupEvent::ParticipantsPromoted {
-                group_jid: group_jid.clone(),
-                participants: result.successful.clone(),
-                by: current_user,
-            };
-            self.emit_event(&event);
-        }
-        
+        let own_jid = self.own_jid()?;
+        let result = self.change_participants(group_jid, participants, ParticipantOperationType::Promote).await?;
+        self.finish_participant_change(group_jid, own_jid, GroupOperationType::PromoteParticipants, &result);
         Ok(result)
     }
     
     /// Demote participants from admin
     pub async fn demote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
-        let mut result = ParticipantOperationResult::new();
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
-        
-        // Simulate demotion
-        for participant in participants {
-            result.add_success(participant);
-        }
-        
-        // Record operation
-        self.record_operation(
-            GroupOperationType::DemoteParticipants,
-            group_jid,
-            &current_user,
-            OperationResult::Success,
-            HashMap::new(),
-        );
-        
-        // Emit event
-        if result.any_successful() {
-            let event = GroupEvent::ParticipantsDemoted {
-                group_jid: group_jid.clone(),
-                participants: result.successful.clone(),
-                by: current_user,
-            };
-            self.emit_event(&event);
-        }
-        
+        let own_jid = self.own_jid()?;
+        let result = self.change_participants(group_jid, participants, ParticipantOperationType::Demote).await?;
+        self.finish_participant_change(group_jid, own_jid, GroupOperationType::DemoteParticipants, &result);
         Ok(result)
     }
     
     /// Update group metadata
     pub async fn update_metadata(
-        &mut self,
+        &self,
         group_jid: &JID,
         metadata: GroupMetadataUpdate,
     ) -> Result<GroupInfo> {
         // Validate metadata
         metadata.validate()?;
         
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
+        let current_user = self.own_jid()?;
         
         // Create a placeholder group info for the response
         // In a real implementation, this would fetch the current group info and update it
@@ -437,6 +499,11 @@ impl GroupManager {
             created_at: SystemTime::now(),
             settings: GroupSettings::default(),
             invite_link: None,
+            is_parent: false,
+            is_default_sub_group: false,
+            linked_parent_jid: None,
+            member_add_mode: Default::default(),
+            join_approval_mode: false,
         };
         
         // Record operation
@@ -474,11 +541,11 @@ impl GroupManager {
     
     /// Update group settings
     pub async fn update_settings(
-        &mut self,
+        &self,
         group_jid: &JID,
         settings: GroupSettings,
     ) -> Result<GroupInfo> {
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
+        let current_user = self.own_jid()?;
         
         // Create updated group info
         let updated_group = GroupInfo {
@@ -491,6 +558,11 @@ impl GroupManager {
             created_at: SystemTime::now(),
             settings: settings.clone(),
             invite_link: None,
+            is_parent: false,
+            is_default_sub_group: false,
+            linked_parent_jid: None,
+            member_add_mode: Default::default(),
+            join_approval_mode: settings.membership_approval,
         };
         
         // Record operation
@@ -515,29 +587,168 @@ impl GroupManager {
         Ok(updated_group)
     }
     
-    /// Get group information
-    pub async fn get_group_info(&self, group_jid: &JID) -> Result<GroupInfo> {
-        // In a real implementation, this would fetch from WhatsApp servers
-        // For now, return a placeholder
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
+    /// Turn admin approval of new members on or off
+    pub async fn set_membership_approval_mode(&self, group_jid: &JID, enabled: bool) -> Result<()> {
+        let iq_sender = self.iq_sender()?;
+        let current_user = self.own_jid()?;
+        iq_sender
+            .send_iq(protocol::membership_approval_mode_query(group_jid, enabled))
+            .await?;
         
-        let group_info = GroupInfo {
-            jid: group_jid.clone(),
-            name: "Sample Group".to_string(),
-            description: Some("A sample group for testing".to_string()),
-            participants: vec![current_user.clone()],
-            admins: vec![current_user.clone()],
-            creator: current_user,
-            created_at: SystemTime::now(),
-            settings: GroupSettings::default(),
-            invite_link: None,
+        let mut context = HashMap::new();
+        context.insert("membership_approval".to_string(), enabled.to_string());
+        self.record_operation(
+            GroupOperationType::UpdateSettings,
+            group_jid,
+            &current_user,
+            OperationResult::Success,
+            context,
+        );
+        
+        tracing::info!("Set membership approval for group {} to {}", group_jid, enabled);
+        
+        Ok(())
+    }
+    
+    /// Set the group picture to a square JPEG, returning the new picture ID.
+    ///
+    /// Participants, including our other devices, learn about the change
+    /// through a `picture` notification.
+    pub async fn set_group_photo(&self, group_jid: &JID, image: Vec<u8>) -> Result<String> {
+        let image = protocol::prepare_group_photo(image)?;
+        let picture_id = self.send_group_photo(group_jid, Some(image)).await?;
+        tracing::info!("Set picture {} for group {}", picture_id, group_jid);
+        Ok(picture_id)
+    }
+    
+    /// Remove the group picture
+    pub async fn remove_group_photo(&self, group_jid: &JID) -> Result<()> {
+        self.send_group_photo(group_jid, None).await?;
+        tracing::info!("Removed picture of group {}", group_jid);
+        Ok(())
+    }
+    
+    async fn send_group_photo(&self, group_jid: &JID, image: Option<Vec<u8>>) -> Result<String> {
+        if !group_jid.is_group() {
+            return Err(Error::InvalidJID(format!("Not a group JID: {}", group_jid)));
+        }
+        let iq_sender = self.iq_sender()?;
+        let current_user = self.own_jid()?;
+        
+        let removing = image.is_none();
+        let response = iq_sender
+            .send_iq(protocol::set_group_photo_query(group_jid, image))
+            .await
+            .map_err(|e| match e {
+                Error::Iq { code: 401 | 403, .. } => Error::NotInGroup,
+                other => other,
+            })?;
+        let picture_id = if removing {
+            String::new()
+        } else {
+            protocol::parse_set_group_photo_response(&response)?
+        };
+        
+        let mut context = HashMap::new();
+        context.insert("picture_id".to_string(), picture_id.clone());
+        self.record_operation(
+            GroupOperationType::UpdateMetadata,
+            group_jid,
+            &current_user,
+            OperationResult::Success,
+            context,
+        );
+        
+        Ok(picture_id)
+    }
+    
+    /// Get the group picture, or its low resolution `preview`.
+    ///
+    /// Returns `None` when the group has no picture.
+    pub async fn get_group_photo(&self, group_jid: &JID, preview: bool) -> Result<Option<GroupPhoto>> {
+        if !group_jid.is_group() {
+            return Err(Error::InvalidJID(format!("Not a group JID: {}", group_jid)));
+        }
+        let iq_sender = self.iq_sender()?;
+        
+        match iq_sender.send_iq(protocol::get_group_photo_query(group_jid, preview)).await {
+            Ok(response) => Ok(protocol::parse_group_photo(&response)),
+            Err(Error::Iq { code: 404, .. }) => Ok(None),
+            Err(Error::Iq { code: 401 | 403, .. }) => Err(Error::NotInGroup),
+            Err(e) => Err(e),
+        }
+    }
+    
+    /// Get the pending requests to join a group
+    pub async fn get_join_requests(&self, group_jid: &JID) -> Result<Vec<MembershipRequest>> {
+        let iq_sender = self.iq_sender()?;
+        let response = iq_sender.send_iq(protocol::membership_requests_query(group_jid)).await?;
+        protocol::parse_membership_requests(&response)
+    }
+    
+    /// Approve or reject pending requests to join a group
+    pub async fn handle_join_requests(
+        &self,
+        group_jid: &JID,
+        participants: Vec<JID>,
+        approve: bool,
+    ) -> Result<ParticipantOperationResult> {
+        if participants.is_empty() {
+            return Err(Error::Protocol("No participants specified".to_string()));
+        }
+        let iq_sender = self.iq_sender()?;
+        let own_jid = self.own_jid()?;
+        
+        let response = iq_sender
+            .send_iq(protocol::membership_requests_action_query(group_jid, approve, &participants))
+            .await?;
+        let result = protocol::parse_membership_requests_action_response(&response, approve, &participants)?;
+        
+        let operation_type = if approve {
+            GroupOperationType::ApproveJoinRequests
+        } else {
+            GroupOperationType::RejectJoinRequests
         };
+        self.finish_participant_change(group_jid, own_jid, operation_type, &result);
+        
+        Ok(result)
+    }
+    
+    /// Get group information from the server
+    pub async fn get_group_info(&self, group_jid: &JID) -> Result<GroupInfo> {
+        if !group_jid.is_group() {
+            return Err(Error::InvalidJID(format!("Not a group JID: {}", group_jid)));
+        }
+        let iq_sender = self.iq_sender()?;
+        
+        let response = iq_sender.send_iq(protocol::group_info_query(group_jid)).await.map_err(|e| match e {
+            Error::Iq { code: 403, .. } => Error::NotInGroup,
+            other => other,
+        })?;
+        let group_info = protocol::parse_group_info(&response)?;
+        
+        tracing::debug!("Fetched info for group {} with {} participants", group_jid, group_info.participants.len());
+        self.cache.write().unwrap().insert(group_jid.clone(), group_info.clone());
         
         Ok(group_info)
     }
     
-    /// Leave a group
-    pub async fn leave_group(&mut self, group_jid: &JID, user_jid: &JID) -> Result<()> {
+    /// Get all groups we participate in
+    pub async fn get_joined_groups(&self) -> Result<Vec<GroupInfo>> {
+        let iq_sender = self.iq_sender()?;
+        let response = iq_sender.send_iq(protocol::joined_groups_query()).await?;
+        protocol::parse_joined_groups(&response)
+    }
+    
+    /// Leave a group, `user_jid` being our own JID
+    pub async fn leave_group(&self, group_jid: &JID, user_jid: &JID) -> Result<()> {
+        if !group_jid.is_group() {
+            return Err(Error::InvalidJID(format!("Not a group JID: {}", group_jid)));
+        }
+        let iq_sender = self.iq_sender()?;
+        iq_sender.send_iq(protocol::leave_group_query(group_jid)).await?;
+        self.cache.write().unwrap().remove(group_jid);
+        
         // Record operation
         self.record_operation(
             GroupOperationType::LeaveGroup,
@@ -560,12 +771,9 @@ impl GroupManager {
     }
     
     /// Get group invite link
-    pub async fn get_invite_link(&mut self, group_jid: &JID) -> Result<String> {
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
-        
-        // Generate a sample invite link
-        let invite_code = Uuid::new_v4().simple().to_string()[0..16].to_string();
-        let invite_link = format!("https://chat.whatsapp.com/{}", invite_code);
+    pub async fn get_invite_link(&self, group_jid: &JID) -> Result<String> {
+        let current_user = self.own_jid()?;
+        let invite_link = self.query_invite_link(group_jid, false).await?;
         
         // Record operation
         let mut context = HashMap::new();
@@ -587,18 +795,15 @@ impl GroupManager {
         };
         self.emit_event(&event);
         
-        tracing::info!("Generated invite link for group {}", group_jid);
+        tracing::info!("Fetched invite link for group {}", group_jid);
         
         Ok(invite_link)
     }
     
-    /// Revoke group invite link
-    pub async fn revoke_invite_link(&mut self, group_jid: &JID) -> Result<String> {
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
-        
-        // Generate new invite link
-        let new_invite_code = Uuid::new_v4().simple().to_string()[0..16].to_string();
-        let new_invite_link = format!("https://chat.whatsapp.com/{}", new_invite_code);
+    /// Revoke group invite link, returning the newly generated one
+    pub async fn revoke_invite_link(&self, group_jid: &JID) -> Result<String> {
+        let current_user = self.own_jid()?;
+        let new_invite_link = self.query_invite_link(group_jid, true).await?;
         
         // Record operation
         self.record_operation(
@@ -624,57 +829,74 @@ impl GroupManager {
         };
         self.emit_event(&new_link_event);
         
-        tracing::info!("Revoked and regenerated invite link for group {}", group_jid);
+        tracing::info!("Revoked invite link for group {}", group_jid);
         
         Ok(new_invite_link)
     }
     
-    /// Parse invite link to extract group JID
-    pub fn parse_invite_link(&self, invite_link: &str) -> Result<JID> {
-        if !invite_link.starts_with("https://chat.whatsapp.com/") {
-            return Err(Error::Protocol("Invalid invite link format".to_string()));
-        }
-        
-        let invite_code = invite_link.strip_prefix("https://chat.whatsapp.com/")
-            .ok_or_else(|| Error::Protocol("Invalid invite link".to_string()))?;
-        
-        if invite_code.len() < 10 {
-            return Err(Error::Protocol("Invalid invite code".to_string()));
-        }
-        
-        // Generate a group JID based on the invite code
-        // In reality, this would involve resolving the invite code with WhatsApp servers
-        let group_id = format!("group_{}", invite_code);
-        let group_jid = JID::new(group_id, "g.us".to_string());
+    async fn query_invite_link(&self, group_jid: &JID, reset: bool) -> Result<String> {
+        let iq_sender = self.iq_sender()?;
+        let response = iq_sender.send_iq(protocol::invite_link_query(group_jid, reset)).await?;
+        protocol::parse_invite_link_response(&response)
+    }
+    
+    /// Extract the invite code from an invite link
+    pub fn parse_invite_link(&self, invite_link: &str) -> Result<String> {
+        protocol::parse_invite_code(invite_link)
+    }
+    
+    /// Preview the group behind an invite link without joining it
+    pub async fn get_invite_info(&self, invite_link: &str) -> Result<GroupInviteInfo> {
+        let code = self.parse_invite_link(invite_link)?;
+        let iq_sender = self.iq_sender()?;
         
-        Ok(group_jid)
+        let response = iq_sender.send_iq(protocol::invite_code_query(&code, InfoQueryType::Get)).await?;
+        protocol::parse_invite_info(&response)
     }
     
     /// Join group via invite link
-    pub async fn join_via_invite(&mut self, invite_link: &str) -> Result<GroupInfo> {
-        let group_jid = self.parse_invite_link(invite_link)?;
-        let current_user = JID::new("current_user".to_string(), "s.whatsapp.net".to_string());
+    pub async fn join_via_invite(&self, invite_link: &str) -> Result<GroupInfo> {
+        let code = self.parse_invite_link(invite_link)?;
+        let iq_sender = self.iq_sender()?;
+        let own_jid = self.own_jid()?;
         
-        // Create group info for joined group
-        let group_info = GroupInfo {
-            jid: group_jid.clone(),
-            name: "Joined Group".to_string(),
-            description: Some("Joined via invite link".to_string()),
-            participants: vec![current_user.clone()],
-            admins: vec![], // We're not admin when joining
-            creator: JID::new("creator".to_string(), "s.whatsapp.net".to_string()),
-            created_at: SystemTime::now(),
-            settings: GroupSettings::default(),
-            invite_link: Some(invite_link.to_string()),
-        };
+        let response = iq_sender.send_iq(protocol::invite_code_query(&code, InfoQueryType::Set)).await?;
+        let group_jid = protocol::parse_joined_group(&response)?;
         
-        // Record operation
         let mut context = HashMap::new();
         context.insert("invite_link".to_string(), invite_link.to_string());
+        self.finish_join(&group_jid, own_jid, context).await
+    }
+    
+    /// Accept a group invite received in a `groupInviteMessage` from `inviter`
+    pub async fn accept_group_invite(&self, inviter: &JID, invite: &GroupInviteMessage) -> Result<GroupInfo> {
+        if let Some(expiration) = invite.invite_expiration {
+            if expiration < SystemTime::now() {
+                return Err(Error::Protocol("Group invite has expired".to_string()));
+            }
+        }
+        let iq_sender = self.iq_sender()?;
+        let own_jid = self.own_jid()?;
+        
+        iq_sender.send_iq(protocol::accept_invite_query(
+            &invite.group_jid,
+            inviter,
+            &invite.invite_code,
+            invite.invite_expiration,
+        )).await?;
+        
+        let mut context = HashMap::new();
+        context.insert("inviter".to_string(), inviter.to_string());
+        self.finish_join(&invite.group_jid, own_jid, context).await
+    }
+    
+    async fn finish_join(&self, group_jid: &JID, current_user: JID, context: HashMap<String, String>) -> Result<GroupInfo> {
+        let mut group_info = self.get_group_info(group_jid).await?;
+        group_info.invite_link = context.get("invite_link").cloned();
         
         self.record_operation(
             GroupOperationType::JoinViaInvite,
-            &group_jid,
+            group_jid,
             &current_user,
             OperationResult::Success,
             context,
@@ -687,26 +909,26 @@ impl GroupManager {
         };
         self.emit_event(&event);
         
-        tracing::info!("Joined group {} via invite link", group_jid);
+        tracing::info!("Joined group {} via invite", group_jid);
         
         Ok(group_info)
     }
     
     /// Get operation history
-    pub fn get_operation_history(&self) -> &[GroupOperation] {
-        &self.operation_history
+    pub fn get_operation_history(&self) -> Vec<GroupOperation> {
+        self.operation_history.read().unwrap().clone()
     }
     
     /// Clear operation history
-    pub fn clear_operation_history(&mut self) {
-        self.operation_history.clear();
+    pub fn clear_operation_history(&self) {
+        self.operation_history.write().unwrap().clear();
     }
     
     /// Get operation statistics
     pub fn get_operation_stats(&self) -> HashMap<GroupOperationType, usize> {
         let mut stats = HashMap::new();
         
-        for operation in &self.operation_history {
+        for operation in self.operation_history.read().unwrap().iter() {
             *stats.entry(operation.operation_type.clone()).or_insert(0) += 1;
         }
         
@@ -715,18 +937,6 @@ impl GroupManager {
     
     // Helper methods
     
-    fn generate_group_id(&self) -> String {
-        // Generate a unique group ID
-        // In reality, this would be assigned by WhatsApp servers
-        format!("{}{}", 
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            rand::random::<u32>() % 1000000
-        )
-    }
-    
     fn validate_participant(&self, participant: &JID) -> Result<()> {
         // Basic validation
         if participant.user.is_empty() {
@@ -747,6 +957,15 @@ impl Default for GroupManager {
     }
 }
 
+/// Permission for an action restricted to admins or open to everyone
+fn permission(admins_only: bool) -> ParticipantPermission {
+    if admins_only {
+        ParticipantPermission::AdminsOnly
+    } else {
+        ParticipantPermission::Everyone
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -759,19 +978,147 @@ mod tests {
         JID::new("1234567890".to_string(), "g.us".to_string())
     }
     
+    fn create_test_manager(response_child: crate::binary::Node) -> GroupManager {
+        let response = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![response_child]);
+        GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(response)))
+    }
+    
+    fn participant_list(action: &str, participants: &[(&JID, Option<&str>)]) -> crate::binary::Node {
+        crate::binary::Node::new(action.to_string()).with_children(
+            participants
+                .iter()
+                .map(|(jid, error)| {
+                    let node = crate::binary::Node::new("participant".to_string()).attr("jid".to_string(), jid.to_string());
+                    match error {
+                        Some(code) => node.attr("error".to_string(), code.to_string()),
+                        None => node,
+                    }
+                })
+                .collect(),
+        )
+    }
+    
+    fn created_group_node(participants: &[&JID]) -> crate::binary::Node {
+        let mut children = vec![crate::binary::Node::new("participant".to_string())
+            .attr("jid".to_string(), "creator@s.whatsapp.net".to_string())
+            .attr("type".to_string(), "superadmin".to_string())];
+        children.extend(participants.iter().map(|jid| {
+            crate::binary::Node::new("participant".to_string()).attr("jid".to_string(), jid.to_string())
+        }));
+        crate::binary::Node::new("group".to_string())
+            .attr("id".to_string(), "120363000000000001".to_string())
+            .attr("subject".to_string(), "Test Group".to_string())
+            .attr("creator".to_string(), "creator@s.whatsapp.net".to_string())
+            .attr("creation".to_string(), "1700000000".to_string())
+            .with_children(children)
+    }
+    
     #[tokio::test]
     async fn test_group_manager_creation() {
         let manager = GroupManager::new();
         assert_eq!(manager.config.max_participants, 1024);
-        assert!(manager.event_handlers.is_empty());
-        assert!(manager.operation_history.is_empty());
+        assert!(manager.event_handlers.read().unwrap().is_empty());
+        assert!(manager.get_operation_history().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_get_group_info_over_iq() {
+        let group_jid = create_test_group_jid();
+        let response = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![protocol::test_group_node(&group_jid.user)]);
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(response)));
+        
+        let info = manager.get_group_info(&group_jid).await.unwrap();
+        assert_eq!(info.jid, group_jid);
+        assert_eq!(info.name, "Weekend plans");
+        assert_eq!(info.participants.len(), 3);
+        
+        let not_found = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "error".to_string())
+            .with_children(vec![crate::binary::Node::new("error".to_string())
+                .attr("code".to_string(), "404".to_string())
+                .attr("text".to_string(), "item-not-found".to_string())]);
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(not_found)));
+        assert!(matches!(manager.get_group_info(&group_jid).await, Err(Error::Iq { code: 404, .. })));
+        
+        let forbidden = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "error".to_string())
+            .with_children(vec![crate::binary::Node::new("error".to_string())
+                .attr("code".to_string(), "403".to_string())
+                .attr("text".to_string(), "forbidden".to_string())]);
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(forbidden)));
+        assert!(matches!(manager.get_group_info(&group_jid).await, Err(Error::NotInGroup)));
+        
+        assert!(matches!(GroupManager::new().get_group_info(&group_jid).await, Err(Error::NotLoggedIn)));
+    }
+    
+    #[tokio::test]
+    async fn test_group_photo_over_iq() {
+        let group_jid = create_test_group_jid();
+        let manager = create_test_manager(crate::binary::Node::new("picture".to_string())
+            .attr("id".to_string(), "1700000002".to_string())
+            .attr("type".to_string(), "image".to_string())
+            .attr("url".to_string(), "https://pps.whatsapp.net/v/t61/full".to_string()));
+        
+        assert!(manager.set_group_photo(&group_jid, b"not a jpeg".to_vec()).await.is_err());
+        let photo = manager.get_group_photo(&group_jid, false).await.unwrap().unwrap();
+        assert_eq!(photo.id, "1700000002");
+        assert!(!photo.preview);
+        manager.remove_group_photo(&group_jid).await.unwrap();
+        assert_eq!(manager.get_operation_history().len(), 1);
+        
+        let not_found = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "error".to_string())
+            .with_children(vec![crate::binary::Node::new("error".to_string())
+                .attr("code".to_string(), "404".to_string())
+                .attr("text".to_string(), "item-not-found".to_string())]);
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(not_found)));
+        assert!(manager.get_group_photo(&group_jid, true).await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_notifications_update_cache() {
+        let group_jid = create_test_group_jid();
+        let response = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![protocol::test_group_node(&group_jid.user)]);
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(Arc::new(crate::request::StaticIqSender::new(response)));
+        let info = manager.get_group_info(&group_jid).await.unwrap();
+        let admin = info.participants[0].clone();
+        let newcomer = create_test_jid("newcomer");
+        
+        for event in [
+            GroupEvent::SubjectChanged { group_jid: group_jid.clone(), subject: "Renamed".to_string(), by: Some(admin.clone()) },
+            GroupEvent::LockedChanged { group_jid: group_jid.clone(), locked: false, by: Some(admin.clone()) },
+            GroupEvent::ParticipantsAdded { group_jid: group_jid.clone(), participants: vec![newcomer.clone()], by: admin.clone() },
+            GroupEvent::ParticipantsPromoted { group_jid: group_jid.clone(), participants: vec![newcomer.clone()], by: admin.clone() },
+        ] {
+            manager.apply_event(&event);
+        }
+        
+        let cached = manager.cached_group_info(&group_jid).unwrap();
+        assert_eq!(cached.name, "Renamed");
+        assert_eq!(cached.settings.edit_group_info, ParticipantPermission::Everyone);
+        assert_eq!(cached.participants.len(), 4);
+        assert!(cached.admins.contains(&newcomer));
+        
+        manager.apply_event(&GroupEvent::ParticipantsRemoved {
+            group_jid: group_jid.clone(),
+            participants: vec![newcomer.clone()],
+            by: newcomer.clone(),
+        });
+        let cached = manager.cached_group_info(&group_jid).unwrap();
+        assert!(!cached.participants.contains(&newcomer) && !cached.admins.contains(&newcomer));
     }
     
     #[tokio::test]
     async fn test_create_group() {
-        let mut manager = GroupManager::new();
         let participant1 = create_test_jid("participant1");
         let participant2 = create_test_jid("participant2");
+        let manager = create_test_manager(created_group_node(&[&participant1, &participant2]));
         
         let request = CreateGroupRequest::new(
             "Test Group".to_string(),
@@ -784,18 +1131,76 @@ mod tests {
         assert!(group_info.participants.len() >= 2); // Creator + participants
         assert_eq!(group_info.admin_count(), 1);
         assert!(group_info.jid.server.ends_with("g.us"));
+        assert_eq!(group_info.jid.user, "120363000000000001");
+        assert_eq!(group_info.created_at, SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1700000000));
         
         // Check operation was recorded
-        assert_eq!(manager.operation_history.len(), 1);
-        assert_eq!(manager.operation_history[0].operation_type, GroupOperationType::CreateGroup);
+        assert_eq!(manager.get_operation_history().len(), 1);
+        assert_eq!(manager.get_operation_history()[0].operation_type, GroupOperationType::CreateGroup);
+    }
+    
+    #[tokio::test]
+    async fn test_create_group_with_settings() {
+        let participant = create_test_jid("participant");
+        let result = |child: crate::binary::Node| {
+            crate::binary::Node::new("iq".to_string())
+                .attr("type".to_string(), "result".to_string())
+                .with_children(vec![child])
+        };
+        let sender = Arc::new(crate::request::StaticIqSender::sequence(vec![
+            result(created_group_node(&[&participant])),
+            crate::binary::Node::new("iq".to_string()).attr("type".to_string(), "result".to_string()),
+            crate::binary::Node::new("iq".to_string()).attr("type".to_string(), "result".to_string()),
+            crate::binary::Node::new("iq".to_string()).attr("type".to_string(), "result".to_string()),
+        ]));
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(sender.clone());
+        
+        let settings = GroupSettings {
+            announcement_only: true,
+            send_messages: ParticipantPermission::AdminsOnly,
+            membership_approval: true,
+            ..Default::default()
+        };
+        let request = CreateGroupRequest::new("Test Group".to_string(), vec![participant]).with_settings(settings.clone());
+        let group_info = manager.create_group(request).await.unwrap();
+        assert_eq!(group_info.settings, settings);
+        
+        // Only the settings differing from the created group are sent
+        let queries = sender.queries.lock().unwrap();
+        assert_eq!(queries.len(), 4);
+        let announce = queries[1].to_node("2");
+        assert_eq!(announce.get_attr("to").unwrap(), "120363000000000001@g.us");
+        assert!(announce.find_child("announcement").is_some());
+        assert!(queries[2].to_node("3").find_child("locked").is_some());
+        assert!(queries[3].to_node("4").find_child("membership_approval_mode").is_some());
+        
+        // History visibility has no server setting
+        let hidden = GroupSettings { history_visible: false, ..Default::default() };
+        let request = CreateGroupRequest::new("Test Group".to_string(), vec![create_test_jid("other")]).with_settings(hidden);
+        assert!(manager.create_group(request).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_leave_group() {
+        let group_jid = create_test_group_jid();
+        let response = crate::binary::Node::new("iq".to_string()).attr("type".to_string(), "result".to_string());
+        let sender = Arc::new(crate::request::StaticIqSender::new(response));
+        let manager = GroupManager::new().with_iq_sender(sender.clone());
+        
+        manager.leave_group(&group_jid, &create_test_jid("me")).await.unwrap();
+        let leave = sender.queries.lock().unwrap()[0].to_node("1");
+        assert_eq!(leave.get_attr("to").unwrap(), "g.us");
+        let group = leave.find_child("leave").and_then(|leave| leave.find_child("group")).unwrap();
+        assert_eq!(group.get_attr("id").unwrap(), "1234567890@g.us");
+        assert_eq!(manager.get_operation_history()[0].performed_by, create_test_jid("me"));
     }
     
     #[tokio::test]
     async fn test_add_participants() {
-        let mut manager = GroupManager::new();
         let group_jid = create_test_group_jid();
         let participant1 = create_test_jid("participant1");
         let participant2 = create_test_jid("participant2");
+        let manager = create_test_manager(participant_list("add", &[(&participant1, None), (&participant2, None)]));
         
         let result = manager.add_participants(
             &group_jid,
@@ -807,15 +1212,16 @@ mod tests {
         assert!(result.all_successful());
         
         // Check operation was recorded
-        assert_eq!(manager.operation_history.len(), 1);
-        assert_eq!(manager.operation_history[0].operation_type, GroupOperationType::AddParticipants);
+        assert_eq!(manager.get_operation_history().len(), 1);
+        assert_eq!(manager.get_operation_history()[0].operation_type, GroupOperationType::AddParticipants);
+        assert_eq!(manager.get_operation_history()[0].performed_by, create_test_jid("me"));
     }
     
     #[tokio::test]
     async fn test_remove_participants() {
-        let mut manager = GroupManager::new();
         let group_jid = create_test_group_jid();
         let participant = create_test_jid("participant");
+        let manager = create_test_manager(participant_list("remove", &[(&participant, None)]));
         
         let result = manager.remove_participants(
             &group_jid,
@@ -826,13 +1232,76 @@ mod tests {
         assert_eq!(result.failure_count(), 0);
         
         // Check operation was recorded
-        assert_eq!(manager.operation_history.len(), 1);
-        assert_eq!(manager.operation_history[0].operation_type, GroupOperationType::RemoveParticipants);
+        assert_eq!(manager.get_operation_history().len(), 1);
+        assert_eq!(manager.get_operation_history()[0].operation_type, GroupOperationType::RemoveParticipants);
+    }
+    
+    #[tokio::test]
+    async fn test_add_participants_error_codes() {
+        let group_jid = create_test_group_jid();
+        let added = create_test_jid("added");
+        let private = create_test_jid("private");
+        let left = create_test_jid("left");
+        let existing = create_test_jid("existing");
+        let invalid = JID::new("".to_string(), "s.whatsapp.net".to_string());
+        let manager = create_test_manager(participant_list("add", &[
+            (&added, None),
+            (&private, Some("403")),
+            (&left, Some("408")),
+            (&existing, Some("409")),
+        ]));
+        
+        let result = manager.add_participants(
+            &group_jid,
+            vec![added.clone(), private.clone(), left.clone(), existing.clone(), invalid],
+        ).await.unwrap();
+        
+        assert_eq!(result.successful, vec![added]);
+        assert_eq!(result.failure_count(), 4);
+        let reason = |jid: &JID| result.failed.iter().find(|(j, _)| j == jid).unwrap().1.clone();
+        assert!(reason(&private).contains("403"));
+        assert!(reason(&left).contains("408"));
+        assert!(reason(&existing).contains("409"));
+        assert_eq!(manager.get_operation_history()[0].result, OperationResult::PartialSuccess);
+    }
+    
+    #[tokio::test]
+    async fn test_handle_join_requests() {
+        let group_jid = create_test_group_jid();
+        let approved = create_test_jid("approved");
+        let stale = create_test_jid("stale");
+        let action = crate::binary::Node::new("membership_requests_action".to_string())
+            .with_children(vec![participant_list("approve", &[(&approved, None), (&stale, Some("404"))])]);
+        let manager = create_test_manager(action);
+        
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        manager.add_event_handler(move |event| events_clone.lock().unwrap().push(event.clone()));
+        
+        let result = manager
+            .handle_join_requests(&group_jid, vec![approved.clone(), stale.clone()], true)
+            .await
+            .unwrap();
+        
+        assert_eq!(result.successful, vec![approved.clone()]);
+        assert_eq!(result.failure_count(), 1);
+        assert_eq!(manager.get_operation_history()[0].operation_type, GroupOperationType::ApproveJoinRequests);
+        assert!(matches!(
+            &events.lock().unwrap()[0],
+            GroupEvent::ParticipantsAdded { participants, .. } if participants == &vec![approved]
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_participant_changes_require_connection() {
+        let manager = GroupManager::new();
+        let result = manager.promote_participants(&create_test_group_jid(), vec![create_test_jid("member")]).await;
+        assert!(matches!(result, Err(Error::NotLoggedIn)));
     }
     
     #[tokio::test]
     async fn test_update_metadata() {
-        let mut manager = GroupManager::new();
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me"));
         let group_jid = create_test_group_jid();
         
         let metadata = GroupMetadataUpdate::new()
@@ -845,39 +1314,92 @@ mod tests {
         assert_eq!(updated_group.description, Some("New description".to_string()));
         
         // Check operation was recorded
-        assert_eq!(manager.operation_history.len(), 1);
-        assert_eq!(manager.operation_history[0].operation_type, GroupOperationType::UpdateMetadata);
+        assert_eq!(manager.get_operation_history().len(), 1);
+        assert_eq!(manager.get_operation_history()[0].operation_type, GroupOperationType::UpdateMetadata);
     }
     
     #[tokio::test]
     async fn test_invite_link_operations() {
-        let mut manager = GroupManager::new();
         let group_jid = create_test_group_jid();
+        let result = |child: crate::binary::Node| {
+            crate::binary::Node::new("iq".to_string())
+                .attr("type".to_string(), "result".to_string())
+                .with_children(vec![child])
+        };
+        let invite = |code: &str| crate::binary::Node::new("invite".to_string()).attr("code".to_string(), code.to_string());
+        let sender = Arc::new(crate::request::StaticIqSender::sequence(vec![
+            result(invite("FirstCode1")),
+            result(invite("SecondCode2")),
+            result(protocol::test_group_node(&group_jid.user).attr("size".to_string(), "3".to_string())),
+            result(crate::binary::Node::new("group".to_string()).attr("jid".to_string(), group_jid.to_string())),
+            result(protocol::test_group_node(&group_jid.user)),
+        ]));
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(sender.clone());
         
         // Get invite link
         let invite_link = manager.get_invite_link(&group_jid).await.unwrap();
-        assert!(invite_link.starts_with("https://chat.whatsapp.com/"));
+        assert_eq!(invite_link, "https://chat.whatsapp.com/FirstCode1");
         
         // Parse invite link
-        let parsed_jid = manager.parse_invite_link(&invite_link).unwrap();
-        assert!(parsed_jid.server.ends_with("g.us"));
+        assert_eq!(manager.parse_invite_link(&invite_link).unwrap(), "FirstCode1");
         
         // Revoke invite link
         let new_link = manager.revoke_invite_link(&group_jid).await.unwrap();
-        assert!(new_link.starts_with("https://chat.whatsapp.com/"));
-        assert_ne!(invite_link, new_link);
+        assert_eq!(new_link, "https://chat.whatsapp.com/SecondCode2");
+        
+        // Preview before joining
+        let preview = manager.get_invite_info(&new_link).await.unwrap();
+        assert_eq!(preview.jid, group_jid);
+        assert_eq!(preview.size, Some(3));
         
         // Join via invite
         let group_info = manager.join_via_invite(&new_link).await.unwrap();
+        assert_eq!(group_info.jid, group_jid);
         assert_eq!(group_info.invite_link, Some(new_link));
         
+        let queries = sender.queries.lock().unwrap();
+        assert_eq!(queries[1].query_type, InfoQueryType::Set);
+        assert_eq!(queries[3].to.to_string(), "g.us");
+        
         // Check operations were recorded
-        assert_eq!(manager.operation_history.len(), 3); // get, revoke, join (parse doesn't record)
+        assert_eq!(manager.get_operation_history().len(), 3); // get, revoke, join (parse and preview don't record)
+    }
+    
+    #[tokio::test]
+    async fn test_accept_group_invite_message() {
+        let group_jid = create_test_group_jid();
+        let inviter = create_test_jid("creator");
+        let response = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![protocol::test_group_node(&group_jid.user)]);
+        let sender = Arc::new(crate::request::StaticIqSender::new(response));
+        let manager = GroupManager::new().with_own_jid(create_test_jid("me")).with_iq_sender(sender.clone());
+        
+        let mut invite = GroupInviteMessage {
+            group_jid: group_jid.clone(),
+            invite_code: "InviteCode1".to_string(),
+            invite_expiration: Some(SystemTime::now() + std::time::Duration::from_secs(3600)),
+            group_name: Some("Weekend plans".to_string()),
+            group_type: None,
+            jpeg_thumbnail: None,
+            caption: None,
+            context_info: None,
+        };
+        
+        let group_info = manager.accept_group_invite(&inviter, &invite).await.unwrap();
+        assert_eq!(group_info.jid, group_jid);
+        let accept = sender.queries.lock().unwrap()[0].to_node("1");
+        let accept = accept.find_child("accept").unwrap();
+        assert_eq!(accept.get_attr("code").unwrap(), "InviteCode1");
+        assert_eq!(accept.get_attr("admin").unwrap(), "creator@s.whatsapp.net");
+        
+        invite.invite_expiration = Some(SystemTime::UNIX_EPOCH);
+        assert!(manager.accept_group_invite(&inviter, &invite).await.is_err());
     }
     
     #[tokio::test]
     async fn test_event_handling() {
-        let mut manager = GroupManager::new();
+        let manager = create_test_manager(created_group_node(&[&create_test_jid("participant")]));
         let _events_received: Vec<GroupEvent> = Vec::new();
         
         // Add event handler
@@ -906,7 +1428,7 @@ mod tests {
     
     #[test]
     fn test_operation_statistics() {
-        let mut manager = GroupManager::new();
+        let manager = GroupManager::new();
         let group_jid = create_test_group_jid();
         let user_jid = create_test_jid("user");
         
@@ -3,15 +3,18 @@
 use crate::{
     error::{Error, Result},
     types::JID,
+    util::cache::TtlLruCache,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+
+pub use crate::util::cache::CacheStats;
 
 /// Group metadata manager
 pub struct GroupMetadataManager {
     /// Cached metadata
-    metadata_cache: HashMap<JID, CachedGroupMetadata>,
+    metadata_cache: TtlLruCache<JID, CachedGroupMetadata>,
     /// Configuration
     config: MetadataManagerConfig,
 }
@@ -45,8 +48,6 @@ impl Default for MetadataManagerConfig {
 pub struct CachedGroupMetadata {
     /// The metadata
     pub metadata: GroupMetadata,
-    /// Cache timestamp
-    pub cached_at: SystemTime,
     /// Last updated timestamp
     pub last_updated: SystemTime,
 }
@@ -197,7 +198,7 @@ impl GroupMetadataManager {
     /// Create metadata manager with custom config
     pub fn with_config(config: MetadataManagerConfig) -> Self {
         Self {
-            metadata_cache: HashMap::new(),
+            metadata_cache: TtlLruCache::new("group_metadata", config.max_cache_size, Duration::from_secs(config.cache_ttl)),
             config,
         }
     }
@@ -206,9 +207,7 @@ impl GroupMetadataManager {
     pub async fn get_metadata(&mut self, group_jid: &JID) -> Result<GroupMetadata> {
         // Check cache first
         if let Some(cached) = self.metadata_cache.get(group_jid) {
-            if !self.is_cache_expired(cached) {
-                return Ok(cached.metadata.clone());
-            }
+            return Ok(cached.metadata.clone());
         }
         
         // Fetch fresh metadata
@@ -390,40 +389,14 @@ impl GroupMetadataManager {
     
     /// Cache metadata
     fn cache_metadata(&mut self, group_jid: JID, metadata: GroupMetadata) {
-        // Enforce cache size limit
-        if self.metadata_cache.len() >= self.config.max_cache_size {
-            self.evict_oldest_cache_entry();
-        }
-        
         let cached = CachedGroupMetadata {
             metadata,
-            cached_at: SystemTime::now(),
             last_updated: SystemTime::now(),
         };
         
         self.metadata_cache.insert(group_jid, cached);
     }
     
-    /// Check if cache entry is expired
-    fn is_cache_expired(&self, cached: &CachedGroupMetadata) -> bool {
-        if let Ok(elapsed) = cached.cached_at.elapsed() {
-            elapsed.as_secs() > self.config.cache_ttl
-        } else {
-            true // If we can't determine age, consider it expired
-        }
-    }
-    
-    /// Evict oldest cache entry
-    fn evict_oldest_cache_entry(&mut self) {
-        if let Some((oldest_key, _)) = self.metadata_cache
-            .iter()
-            .min_by_key(|(_, cached)| cached.cached_at)
-            .map(|(key, cached)| (key.clone(), cached.clone()))
-        {
-            self.metadata_cache.remove(&oldest_key);
-        }
-    }
-    
     /// Clear cache
     pub fn clear_cache(&mut self) {
         self.metadata_cache.clear();
@@ -431,11 +404,7 @@ impl GroupMetadataManager {
     
     /// Get cache statistics
     pub fn get_cache_stats(&self) -> CacheStats {
-        CacheStats {
-            total_entries: self.metadata_cache.len(),
-            max_entries: self.config.max_cache_size,
-            hit_ratio: 0.0, // Would need to track hits/misses for real ratio
-        }
+        self.metadata_cache.stats()
     }
     
     /// Refresh all cached metadata
@@ -490,17 +459,6 @@ pub struct StatisticsUpdate {
     pub peak_activity_hour: Option<u8>,
 }
 
-/// Cache statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CacheStats {
-    /// Total cached entries
-    pub total_entries: usize,
-    /// Maximum cache entries allowed
-    pub max_entries: usize,
-    /// Cache hit ratio (0.0 to 1.0)
-    pub hit_ratio: f64,
-}
-
 impl Default for GroupMetadataManager {
     fn default() -> Self {
         Self::new()
@@ -6,66 +6,120 @@ pub mod metadata;
 pub mod participants;
 pub mod permissions;
 pub mod community;
-pub mod announcement;
 pub mod disappearing;
+pub mod protocol;
 
 use crate::{
     error::{Error, Result},
     types::JID,
     signal::SignalProtocolManager,
     auth::multidevice::MultiDeviceManager,
+    store::DeviceStore,
 };
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
 
-pub use types::{GroupInfo, GroupSettings, CreateGroupRequest, GroupMetadataUpdate, GroupEvent, ParticipantPermission, DisappearingMessageSettings};
+pub use types::{GroupInfo, GroupKind, GroupInviteInfo, GroupPhoto, MemberAddMode, MembershipRequest, GroupSettings, CreateGroupRequest, GroupMetadataUpdate, GroupEvent, ParticipantPermission, DisappearingMessageSettings};
 pub use manager::{GroupManager, GroupManagerConfig};
 pub use metadata::{GroupMetadataManager, GroupMetadata};
 pub use participants::{ParticipantManager, GroupParticipant, ParticipantRole, ParticipantStatus, ParticipantOperationResult};
 pub use permissions::{PermissionManager, GroupPermissions};
-pub use community::{CommunityInfo, CommunityManager, CommunitySettings, CreateCommunityRequest, CommunityEvent, AddGroupToCommunityRequest};
-pub use announcement::{AnnouncementGroupManager, AnnouncementGroupConfig, AnnouncementMessage, AnnouncementPriority, MemberAnnouncementStatus};
+pub use community::{LinkedGroup, CommunityInfo, CommunityManager, CommunitySettings, CreateCommunityRequest, CommunityEvent, AddGroupToCommunityRequest};
+pub use protocol::{parse_group_events, parse_group_info, parse_group_notification, prepare_group_photo, GROUP_NAMESPACE};
 pub use disappearing::{GroupDisappearingManager, GroupDisappearingConfig, DisappearingTimer, DisappearingMessage, MessageContentType};
 
 /// Group management service for WhatsApp groups
+///
+/// All methods take `&self`: the managers sit behind locks, so one service can
+/// be shared between tasks, see [`crate::Client::groups`].
 pub struct GroupService {
     /// Group manager for operations
-    group_manager: GroupManager,
+    group_manager: Arc<GroupManager>,
     /// Signal protocol manager for encryption
-    signal_manager: SignalProtocolManager,
-    /// Multi-device manager for device coordination
-    device_manager: MultiDeviceManager,
+    signal_manager: Arc<Mutex<SignalProtocolManager>>,
+    /// Where our own JID comes from
+    own_jid: OwnJid,
     /// Cache of group information
-    group_cache: HashMap<JID, GroupInfo>,
+    group_cache: RwLock<HashMap<JID, GroupInfo>>,
     /// Community manager for community groups
-    community_manager: CommunityManager,
-    /// Announcement group manager
-    announcement_manager: AnnouncementGroupManager,
+    community_manager: Arc<Mutex<CommunityManager>>,
     /// Disappearing messages manager
-    disappearing_manager: GroupDisappearingManager,
+    disappearing_manager: tokio::sync::RwLock<GroupDisappearingManager>,
     /// Permission manager
     permission_manager: PermissionManager,
 }
 
+/// Source of our own JID for permission checks
+enum OwnJid {
+    /// Account of a multi-device manager
+    Fixed(JID),
+    /// The device of a client, known once it is paired
+    Store(Arc<dyn DeviceStore>),
+}
+
 impl GroupService {
     /// Create new group service
     pub fn new(
         signal_manager: SignalProtocolManager,
         device_manager: MultiDeviceManager,
+    ) -> Self {
+        Self::with_parts(
+            Arc::new(GroupManager::new()),
+            Arc::new(Mutex::new(CommunityManager::new())),
+            Arc::new(Mutex::new(signal_manager)),
+            OwnJid::Fixed(device_manager.get_own_jid()),
+        )
+    }
+    
+    /// Create a service on the managers of a client
+    pub(crate) fn shared(
+        group_manager: Arc<GroupManager>,
+        community_manager: Arc<Mutex<CommunityManager>>,
+        signal_manager: Arc<Mutex<SignalProtocolManager>>,
+        store: Arc<dyn DeviceStore>,
+    ) -> Self {
+        Self::with_parts(group_manager, community_manager, signal_manager, OwnJid::Store(store))
+    }
+    
+    fn with_parts(
+        group_manager: Arc<GroupManager>,
+        community_manager: Arc<Mutex<CommunityManager>>,
+        signal_manager: Arc<Mutex<SignalProtocolManager>>,
+        own_jid: OwnJid,
     ) -> Self {
         Self {
-            group_manager: GroupManager::new(),
+            group_manager,
             signal_manager,
-            device_manager,
-            group_cache: HashMap::new(),
-            community_manager: CommunityManager::new(),
-            announcement_manager: AnnouncementGroupManager::new(),
-            disappearing_manager: GroupDisappearingManager::new(),
+            own_jid,
+            group_cache: RwLock::new(HashMap::new()),
+            community_manager,
+            disappearing_manager: tokio::sync::RwLock::new(GroupDisappearingManager::new()),
             permission_manager: PermissionManager::new(),
         }
     }
     
+    /// Use the given sender for group queries to the server.
+    ///
+    /// Fails if the community manager is locked by another task.
+    pub fn with_iq_sender(self, iq_sender: Arc<dyn crate::request::IqSender>) -> Result<Self> {
+        self.group_manager.set_iq_sender(iq_sender.clone());
+        self.community_manager.try_lock()
+            .map_err(|_| Error::Protocol("Community manager is in use by another task".to_string()))?
+            .set_iq_sender(iq_sender);
+        Ok(self)
+    }
+    
+    /// Our own JID, without device
+    async fn own_jid(&self) -> Result<JID> {
+        match &self.own_jid {
+            OwnJid::Fixed(jid) => Ok(jid.clone()),
+            OwnJid::Store(store) => Ok(store.load_device().await?.ok_or(Error::NotLoggedIn)?.jid.to_non_ad()),
+        }
+    }
+    
     /// Create a new WhatsApp group
-    pub async fn create_group(&mut self, request: CreateGroupRequest) -> Result<GroupInfo> {
+    pub async fn create_group(&self, request: CreateGroupRequest) -> Result<GroupInfo> {
         // Validate request
         request.validate()?;
         
@@ -76,14 +130,14 @@ impl GroupService {
         self.setup_group_encryption(&group_info).await?;
         
         // Cache group info
-        self.group_cache.insert(group_info.jid.clone(), group_info.clone());
+        self.group_cache.write().unwrap().insert(group_info.jid.clone(), group_info.clone());
         
         Ok(group_info)
     }
     
     /// Add participants to an existing group
     pub async fn add_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
@@ -91,7 +145,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_add_permission(&group_info)?;
+        self.check_add_permission(&group_info, &self.own_jid().await?)?;
         
         // Add participants
         let result = self.group_manager
@@ -106,20 +160,14 @@ impl GroupService {
         }
         
         // Update cache
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
-            for participant in &result.successful {
-                if !cached_group.participants.contains(participant) {
-                    cached_group.participants.push(participant.clone());
-                }
-            }
-        }
+        self.add_cached_participants(group_jid, &result.successful);
         
         Ok(result)
     }
     
     /// Remove participants from a group
     pub async fn remove_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
@@ -127,7 +175,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_remove_permission(&group_info, &participants)?;
+        self.check_remove_permission(&group_info, &participants, &self.own_jid().await?)?;
         
         // Remove participants
         let result = self.group_manager
@@ -142,7 +190,7 @@ impl GroupService {
         }
         
         // Update cache
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
             cached_group.participants.retain(|p| !result.successful.contains(p));
         }
         
@@ -151,7 +199,7 @@ impl GroupService {
     
     /// Update group metadata (name, description, etc.)
     pub async fn update_metadata(
-        &mut self,
+        &self,
         group_jid: &JID,
         metadata: GroupMetadataUpdate,
     ) -> Result<GroupInfo> {
@@ -159,7 +207,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_metadata_permission(&group_info)?;
+        self.check_metadata_permission(&group_info, &self.own_jid().await?)?;
         
         // Update metadata
         let updated_group = self.group_manager
@@ -167,15 +215,15 @@ impl GroupService {
             .await?;
         
         // Update cache
-        self.group_cache.insert(group_jid.clone(), updated_group.clone());
+        self.group_cache.write().unwrap().insert(group_jid.clone(), updated_group.clone());
         
         Ok(updated_group)
     }
     
     /// Get group information
-    pub async fn get_group_info(&mut self, group_jid: &JID) -> Result<GroupInfo> {
+    pub async fn get_group_info(&self, group_jid: &JID) -> Result<GroupInfo> {
         // Check cache first
-        if let Some(cached) = self.group_cache.get(group_jid) {
+        if let Some(cached) = self.group_cache.read().unwrap().get(group_jid) {
             return Ok(cached.clone());
         }
         
@@ -183,29 +231,29 @@ impl GroupService {
         let group_info = self.group_manager.get_group_info(group_jid).await?;
         
         // Cache result
-        self.group_cache.insert(group_jid.clone(), group_info.clone());
+        self.group_cache.write().unwrap().insert(group_jid.clone(), group_info.clone());
         
         Ok(group_info)
     }
     
     /// Leave a group
-    pub async fn leave_group(&mut self, group_jid: &JID) -> Result<()> {
+    pub async fn leave_group(&self, group_jid: &JID) -> Result<()> {
         // Remove ourselves from the group
-        let own_jid = self.device_manager.get_own_jid();
+        let own_jid = self.own_jid().await?;
         self.group_manager.leave_group(group_jid, &own_jid).await?;
         
         // Clean up encryption
         self.cleanup_group_encryption(group_jid).await?;
         
         // Remove from cache
-        self.group_cache.remove(group_jid);
+        self.group_cache.write().unwrap().remove(group_jid);
         
         Ok(())
     }
     
     /// Promote participants to admin
     pub async fn promote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
@@ -213,7 +261,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_admin_permission(&group_info)?;
+        self.check_admin_permission(&group_info, &self.own_jid().await?)?;
         
         // Promote participants
         let result = self.group_manager
@@ -221,7 +269,7 @@ impl GroupService {
             .await?;
         
         // Update cache
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
             for participant in &result.successful {
                 cached_group.admins.push(participant.clone());
             }
@@ -232,7 +280,7 @@ impl GroupService {
     
     /// Demote participants from admin
     pub async fn demote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
     ) -> Result<ParticipantOperationResult> {
@@ -240,7 +288,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_admin_permission(&group_info)?;
+        self.check_admin_permission(&group_info, &self.own_jid().await?)?;
         
         // Demote participants
         let result = self.group_manager
@@ -248,7 +296,7 @@ impl GroupService {
             .await?;
         
         // Update cache
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
             cached_group.admins.retain(|p| !result.successful.contains(p));
         }
         
@@ -257,7 +305,7 @@ impl GroupService {
     
     /// Update group settings (permissions, etc.)
     pub async fn update_settings(
-        &mut self,
+        &self,
         group_jid: &JID,
         settings: GroupSettings,
     ) -> Result<GroupInfo> {
@@ -265,7 +313,7 @@ impl GroupService {
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_admin_permission(&group_info)?;
+        self.check_admin_permission(&group_info, &self.own_jid().await?)?;
         
         // Update settings
         let updated_group = self.group_manager
@@ -273,18 +321,18 @@ impl GroupService {
             .await?;
         
         // Update cache
-        self.group_cache.insert(group_jid.clone(), updated_group.clone());
+        self.group_cache.write().unwrap().insert(group_jid.clone(), updated_group.clone());
         
         Ok(updated_group)
     }
     
     /// Get group invite link
-    pub async fn get_invite_link(&mut self, group_jid: &JID) -> Result<String> {
+    pub async fn get_invite_link(&self, group_jid: &JID) -> Result<String> {
         // Get group info
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_admin_permission(&group_info)?;
+        self.check_admin_permission(&group_info, &self.own_jid().await?)?;
         
         // Get invite link
         let invite_link = self.group_manager.get_invite_link(group_jid).await?;
@@ -293,12 +341,12 @@ impl GroupService {
     }
     
     /// Revoke group invite link
-    pub async fn revoke_invite_link(&mut self, group_jid: &JID) -> Result<String> {
+    pub async fn revoke_invite_link(&self, group_jid: &JID) -> Result<String> {
         // Get group info
         let group_info = self.get_group_info(group_jid).await?;
         
         // Check permissions
-        self.check_admin_permission(&group_info)?;
+        self.check_admin_permission(&group_info, &self.own_jid().await?)?;
         
         // Revoke and get new link
         let new_link = self.group_manager.revoke_invite_link(group_jid).await?;
@@ -306,31 +354,119 @@ impl GroupService {
         Ok(new_link)
     }
     
+    /// Preview the group behind an invite link without joining it
+    pub async fn get_invite_info(&self, invite_link: &str) -> Result<GroupInviteInfo> {
+        self.group_manager.get_invite_info(invite_link).await
+    }
+    
     /// Join group via invite link
-    pub async fn join_via_invite(&mut self, invite_link: &str) -> Result<GroupInfo> {
-        // Parse invite link
-        let group_jid = self.group_manager.parse_invite_link(invite_link)?;
-        
-        // Join group
+    pub async fn join_via_invite(&self, invite_link: &str) -> Result<GroupInfo> {
         let group_info = self.group_manager.join_via_invite(invite_link).await?;
-        
+        self.finish_join(group_info).await
+    }
+    
+    /// Accept a group invite received in a `groupInviteMessage` from `inviter`
+    pub async fn accept_group_invite(
+        &self,
+        inviter: &JID,
+        invite: &crate::types::GroupInviteMessage,
+    ) -> Result<GroupInfo> {
+        let group_info = self.group_manager.accept_group_invite(inviter, invite).await?;
+        self.finish_join(group_info).await
+    }
+    
+    async fn finish_join(&self, group_info: GroupInfo) -> Result<GroupInfo> {
         // Set up encryption for new group
         self.setup_group_encryption(&group_info).await?;
         
         // Cache group info
-        self.group_cache.insert(group_jid, group_info.clone());
+        self.group_cache.write().unwrap().insert(group_info.jid.clone(), group_info.clone());
         
         Ok(group_info)
     }
     
     /// Clear group cache
-    pub fn clear_cache(&mut self) {
-        self.group_cache.clear();
+    pub fn clear_cache(&self) {
+        self.group_cache.write().unwrap().clear();
     }
     
     /// Get cached groups
-    pub fn get_cached_groups(&self) -> Vec<&GroupInfo> {
-        self.group_cache.values().collect()
+    pub fn get_cached_groups(&self) -> Vec<GroupInfo> {
+        self.group_cache.read().unwrap().values().cloned().collect()
+    }
+    
+    fn add_cached_participants(&self, group_jid: &JID, participants: &[JID]) {
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
+            for participant in participants {
+                if !cached_group.participants.contains(participant) {
+                    cached_group.participants.push(participant.clone());
+                }
+            }
+        }
+    }
+    
+    /// Get the pending requests to join a group
+    pub async fn get_join_requests(&self, group_jid: &JID) -> Result<Vec<MembershipRequest>> {
+        self.group_manager.get_join_requests(group_jid).await
+    }
+    
+    /// Approve or reject pending requests to join a group
+    pub async fn handle_join_requests(
+        &self,
+        group_jid: &JID,
+        participants: Vec<JID>,
+        approve: bool,
+    ) -> Result<ParticipantOperationResult> {
+        let result = self.group_manager.handle_join_requests(group_jid, participants, approve).await?;
+        if !approve {
+            return Ok(result);
+        }
+        
+        // Approved members join the group like added participants
+        for participant in &result.successful {
+            self.add_participant_to_encryption(group_jid, participant).await?;
+        }
+        
+        self.add_cached_participants(group_jid, &result.successful);
+        
+        Ok(result)
+    }
+    
+    /// Turn admin approval of new members on or off
+    pub async fn set_membership_approval_mode(&self, group_jid: &JID, enabled: bool) -> Result<()> {
+        self.group_manager.set_membership_approval_mode(group_jid, enabled).await?;
+        
+        if let Some(group_info) = self.group_cache.write().unwrap().get_mut(group_jid) {
+            group_info.settings.membership_approval = enabled;
+            group_info.join_approval_mode = enabled;
+        }
+        
+        Ok(())
+    }
+    
+    /// Set the group picture, returning the new picture ID.
+    ///
+    /// `image` must be a square JPEG of at most 640x640 pixels, see
+    /// [`prepare_group_photo`].
+    pub async fn set_group_photo(&self, group_jid: &JID, image: Vec<u8>) -> Result<String> {
+        let image = prepare_group_photo(image)?;
+        let group_info = self.get_group_info(group_jid).await?;
+        self.check_metadata_permission(&group_info, &self.own_jid().await?)?;
+        
+        self.group_manager.set_group_photo(group_jid, image).await
+    }
+    
+    /// Remove the group picture
+    pub async fn remove_group_photo(&self, group_jid: &JID) -> Result<()> {
+        let group_info = self.get_group_info(group_jid).await?;
+        self.check_metadata_permission(&group_info, &self.own_jid().await?)?;
+        
+        self.group_manager.remove_group_photo(group_jid).await
+    }
+    
+    /// Get the group picture (or its `preview` thumbnail), `None` if the group has none
+    pub async fn get_group_photo(&self, group_jid: &JID, preview: bool) -> Result<Option<GroupPhoto>> {
+        self.group_manager.get_group_photo(group_jid, preview).await
     }
     
     // ========== PHASE 4: ADVANCED GROUP FEATURES ==========
@@ -339,11 +475,11 @@ impl GroupService {
     
     /// Create a new community
     pub async fn create_community(
-        &mut self,
+        &self,
         request: CreateCommunityRequest,
     ) -> Result<CommunityInfo> {
-        let creator = self.device_manager.get_own_jid();
-        let community_info = self.community_manager
+        let creator = self.own_jid().await?;
+        let community_info = self.community_manager.lock().await
             .create_community(request, creator)
             .await?;
         
@@ -354,7 +490,7 @@ impl GroupService {
     
     /// Add a group to a community
     pub async fn add_group_to_community(
-        &mut self,
+        &self,
         community_jid: &JID,
         group_jid: &JID,
         merge_members: bool,
@@ -365,7 +501,7 @@ impl GroupService {
         let request = AddGroupToCommunityRequest::new(community_jid.clone(), group_jid.clone())
             .with_merge_members(merge_members);
         
-        self.community_manager
+        self.community_manager.lock().await
             .add_group_to_community(request, &group_info)
             .await?;
         
@@ -376,11 +512,11 @@ impl GroupService {
     
     /// Remove a group from a community
     pub async fn remove_group_from_community(
-        &mut self,
+        &self,
         community_jid: &JID,
         group_jid: &JID,
     ) -> Result<()> {
-        self.community_manager
+        self.community_manager.lock().await
             .remove_group_from_community(community_jid, group_jid)
             .await?;
         
@@ -390,113 +526,59 @@ impl GroupService {
     }
     
     /// Get community information
-    pub fn get_community(&self, community_jid: &JID) -> Option<&CommunityInfo> {
-        self.community_manager.get_community(community_jid)
+    pub async fn get_community(&self, community_jid: &JID) -> Option<CommunityInfo> {
+        self.community_manager.lock().await.get_community(community_jid).cloned()
     }
     
     /// Get all communities
-    pub fn get_all_communities(&self) -> Vec<&CommunityInfo> {
-        self.community_manager.get_all_communities()
+    pub async fn get_all_communities(&self) -> Vec<CommunityInfo> {
+        self.community_manager.lock().await.get_all_communities().into_iter().cloned().collect()
     }
     
     /// Find community for a group
-    pub fn find_community_for_group(&self, group_jid: &JID) -> Option<&JID> {
-        self.community_manager.find_community_for_group(group_jid)
+    pub async fn find_community_for_group(&self, group_jid: &JID) -> Option<JID> {
+        self.community_manager.lock().await.find_community_for_group(group_jid).cloned()
     }
     
     // ===== Announcement Groups =====
     
-    /// Configure group as announcement group
-    pub fn configure_announcement_group(
-        &mut self,
-        group_jid: &JID,
-        config: AnnouncementGroupConfig,
-    ) -> Result<()> {
-        self.announcement_manager
-            .configure_announcement_group(group_jid.clone(), config)?;
-        
-        // Update group settings
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
-            AnnouncementGroupManager::convert_to_announcement_group(&mut cached_group.settings);
+    /// JID of a community's announcement group.
+    ///
+    /// The server creates this admin-only group together with the community.
+    /// Announcements are ordinary messages sent to it; only community admins
+    /// can post, everyone else receives them read-only.
+    pub async fn announcement_group_jid(&self, community_jid: &JID) -> Result<JID> {
+        self.community_manager.lock().await.announcement_group_jid(community_jid).await
+    }
+    
+    /// Check that we may post to a community's announcement group and return its JID
+    pub async fn prepare_announcement(&self, community_jid: &JID) -> Result<JID> {
+        let announcement_jid = self.announcement_group_jid(community_jid).await?;
+        let group_info = self.get_group_info(&announcement_jid).await?;
+        if !group_info.can_send_messages(&self.own_jid().await?) {
+            return Err(Error::Protocol("Only community admins can post announcements".to_string()));
         }
-        
-        tracing::info!("Configured announcement group: {}", group_jid);
-        
-        Ok(())
-    }
-    
-    /// Post announcement to group
-    pub async fn post_announcement(
-        &mut self,
-        group_jid: &JID,
-        announcement: AnnouncementMessage,
-    ) -> Result<String> {
-        let sender = self.device_manager.get_own_jid();
-        let group_info = self.get_group_info(group_jid).await?;
-        
-        let announcement_id = self.announcement_manager
-            .post_announcement(group_jid, announcement, &sender, &group_info)?;
-        
-        tracing::info!("Posted announcement {} to group {}", announcement_id, group_jid);
-        
-        Ok(announcement_id)
-    }
-    
-    /// Pin an announcement
-    pub async fn pin_announcement(
-        &mut self,
-        group_jid: &JID,
-        announcement_id: &str,
-    ) -> Result<()> {
-        let sender = self.device_manager.get_own_jid();
-        let group_info = self.get_group_info(group_jid).await?;
-        
-        self.announcement_manager
-            .pin_announcement(group_jid, announcement_id, &sender, &group_info)?;
-        
-        tracing::info!("Pinned announcement {} in group {}", announcement_id, group_jid);
-        
-        Ok(())
-    }
-    
-    /// Get announcements for group
-    pub fn get_announcements(&self, group_jid: &JID) -> Option<&Vec<AnnouncementMessage>> {
-        self.announcement_manager.get_announcements(group_jid)
-    }
-    
-    /// Get pinned announcements
-    pub fn get_pinned_announcements(&self, group_jid: &JID) -> Vec<&AnnouncementMessage> {
-        self.announcement_manager.get_pinned_announcements(group_jid)
-    }
-    
-    /// Mark announcement as read
-    pub fn mark_announcement_read(
-        &mut self,
-        group_jid: &JID,
-        announcement_id: &str,
-    ) -> Result<()> {
-        let member = self.device_manager.get_own_jid();
-        self.announcement_manager
-            .mark_announcement_read(group_jid, announcement_id, &member)
+        Ok(announcement_jid)
     }
     
     // ===== Disappearing Messages =====
     
     /// Enable disappearing messages for a group
     pub async fn enable_disappearing_messages(
-        &mut self,
+        &self,
         group_jid: &JID,
         timer: DisappearingTimer,
     ) -> Result<()> {
-        let enabled_by = self.device_manager.get_own_jid();
+        let enabled_by = self.own_jid().await?;
         let group_info = self.get_group_info(group_jid).await?;
         
-        self.disappearing_manager
+        let mut disappearing_manager = self.disappearing_manager.write().await;
+        disappearing_manager
             .enable_disappearing_messages(group_jid, timer.clone(), enabled_by, &group_info)?;
         
         // Update cached group settings
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
-            let config = self.disappearing_manager.get_config(group_jid).unwrap();
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
+            let config = disappearing_manager.get_config(group_jid).unwrap();
             GroupDisappearingManager::apply_to_group_settings(config, &mut cached_group.settings);
         }
         
@@ -507,17 +589,17 @@ impl GroupService {
     
     /// Disable disappearing messages for a group
     pub async fn disable_disappearing_messages(
-        &mut self,
+        &self,
         group_jid: &JID,
     ) -> Result<()> {
-        let disabled_by = self.device_manager.get_own_jid();
+        let disabled_by = self.own_jid().await?;
         let group_info = self.get_group_info(group_jid).await?;
         
-        self.disappearing_manager
+        self.disappearing_manager.write().await
             .disable_disappearing_messages(group_jid, disabled_by, &group_info)?;
         
         // Update cached group settings
-        if let Some(cached_group) = self.group_cache.get_mut(group_jid) {
+        if let Some(cached_group) = self.group_cache.write().unwrap().get_mut(group_jid) {
             cached_group.settings.disappearing_messages = None;
         }
         
@@ -527,32 +609,34 @@ impl GroupService {
     }
     
     /// Schedule a message for disappearing
-    pub fn schedule_disappearing_message(
-        &mut self,
+    pub async fn schedule_disappearing_message(
+        &self,
         message_id: String,
         group_jid: &JID,
         content_type: MessageContentType,
     ) -> Result<()> {
-        let sender = self.device_manager.get_own_jid();
-        self.disappearing_manager
+        let sender = self.own_jid().await?;
+        self.disappearing_manager.write().await
             .schedule_message(message_id, group_jid, sender, content_type)
     }
     
     /// Process all disappearing messages (should be called periodically)
-    pub async fn process_disappearing_messages(&mut self) -> Result<Vec<(JID, String)>> {
-        self.disappearing_manager.process_disappearing_messages().await
+    pub async fn process_disappearing_messages(&self) -> Result<Vec<(JID, String)>> {
+        let disappeared = self.disappearing_manager.write().await.take_disappeared_messages();
+        GroupDisappearingManager::cleanup_media_files(&disappeared).await;
+        Ok(disappeared.into_iter().map(|message| (message.group_jid, message.message_id)).collect())
     }
     
     /// Check if disappearing messages are enabled for a group
-    pub fn are_disappearing_messages_enabled(&self, group_jid: &JID) -> bool {
-        self.disappearing_manager.is_enabled(group_jid)
+    pub async fn are_disappearing_messages_enabled(&self, group_jid: &JID) -> bool {
+        self.disappearing_manager.read().await.is_enabled(group_jid)
     }
     
     // ===== Advanced Permissions =====
     
     /// Check if a participant has a specific permission
     pub async fn has_permission(
-        &mut self,
+        &self,
         group_jid: &JID,
         participant_jid: &JID,
         permission: &str,
@@ -565,7 +649,7 @@ impl GroupService {
     
     /// Apply permission template to group
     pub async fn apply_permission_template(
-        &mut self,
+        &self,
         group_jid: &JID,
         template_id: &str,
     ) -> Result<GroupPermissions> {
@@ -579,24 +663,22 @@ impl GroupService {
     }
     
     /// Get permissions for a group
-    pub async fn get_group_permissions(&mut self, group_jid: &JID) -> Result<GroupPermissions> {
+    pub async fn get_group_permissions(&self, group_jid: &JID) -> Result<GroupPermissions> {
         self.permission_manager.get_permissions(group_jid).await
     }
     
     /// Get available permission templates
-    pub fn get_permission_templates(&self) -> &HashMap<String, permissions::PermissionTemplate> {
+    pub fn get_permission_templates(&self) -> HashMap<String, permissions::PermissionTemplate> {
         self.permission_manager.get_templates()
     }
     
     // Permission checking methods
     
-    fn check_add_permission(&self, group_info: &GroupInfo) -> Result<()> {
-        let own_jid = self.device_manager.get_own_jid();
-        
+    fn check_add_permission(&self, group_info: &GroupInfo, own_jid: &JID) -> Result<()> {
         match group_info.settings.add_participants {
             ParticipantPermission::Everyone => Ok(()),
             ParticipantPermission::AdminsOnly => {
-                if group_info.admins.contains(&own_jid) {
+                if group_info.admins.contains(own_jid) {
                     Ok(())
                 } else {
                     Err(Error::Protocol("Only admins can add participants".to_string()))
@@ -605,17 +687,15 @@ impl GroupService {
         }
     }
     
-    fn check_remove_permission(&self, group_info: &GroupInfo, participants: &[JID]) -> Result<()> {
-        let own_jid = self.device_manager.get_own_jid();
-        
+    fn check_remove_permission(&self, group_info: &GroupInfo, participants: &[JID], own_jid: &JID) -> Result<()> {
         // Check if we're admin
-        if !group_info.admins.contains(&own_jid) {
+        if !group_info.admins.contains(own_jid) {
             return Err(Error::Protocol("Only admins can remove participants".to_string()));
         }
         
         // Check if trying to remove other admins
         for participant in participants {
-            if group_info.admins.contains(participant) && participant != &own_jid {
+            if group_info.admins.contains(participant) && participant != own_jid {
                 return Err(Error::Protocol("Cannot remove other admins".to_string()));
             }
         }
@@ -623,13 +703,11 @@ impl GroupService {
         Ok(())
     }
     
-    fn check_metadata_permission(&self, group_info: &GroupInfo) -> Result<()> {
-        let own_jid = self.device_manager.get_own_jid();
-        
+    fn check_metadata_permission(&self, group_info: &GroupInfo, own_jid: &JID) -> Result<()> {
         match group_info.settings.edit_group_info {
             ParticipantPermission::Everyone => Ok(()),
             ParticipantPermission::AdminsOnly => {
-                if group_info.admins.contains(&own_jid) {
+                if group_info.admins.contains(own_jid) {
                     Ok(())
                 } else {
                     Err(Error::Protocol("Only admins can edit group info".to_string()))
@@ -638,10 +716,8 @@ impl GroupService {
         }
     }
     
-    fn check_admin_permission(&self, group_info: &GroupInfo) -> Result<()> {
-        let own_jid = self.device_manager.get_own_jid();
-        
-        if group_info.admins.contains(&own_jid) {
+    fn check_admin_permission(&self, group_info: &GroupInfo, own_jid: &JID) -> Result<()> {
+        if group_info.admins.contains(own_jid) {
             Ok(())
         } else {
             Err(Error::Protocol("Admin privileges required".to_string()))
@@ -650,7 +726,7 @@ impl GroupService {
     
     // Encryption management methods
     
-    async fn setup_group_encryption(&mut self, group_info: &GroupInfo) -> Result<()> {
+    async fn setup_group_encryption(&self, group_info: &GroupInfo) -> Result<()> {
         // Set up Signal group session for the group
         // This would involve creating sender keys and distributing them
         tracing::info!("Setting up group encryption for {}", group_info.jid);
@@ -664,7 +740,7 @@ impl GroupService {
         Ok(())
     }
     
-    async fn add_participant_to_encryption(&mut self, group_jid: &JID, participant: &JID) -> Result<()> {
+    async fn add_participant_to_encryption(&self, group_jid: &JID, participant: &JID) -> Result<()> {
         tracing::info!("Adding {} to group encryption for {}", participant, group_jid);
         
         // TODO: Implement adding participant to group encryption
@@ -673,7 +749,7 @@ impl GroupService {
         Ok(())
     }
     
-    async fn remove_participant_from_encryption(&mut self, group_jid: &JID, participant: &JID) -> Result<()> {
+    async fn remove_participant_from_encryption(&self, group_jid: &JID, participant: &JID) -> Result<()> {
         tracing::info!("Removing {} from group encryption for {}", participant, group_jid);
         
         // TODO: Implement removing participant from group encryption
@@ -682,7 +758,7 @@ impl GroupService {
         Ok(())
     }
     
-    async fn cleanup_group_encryption(&mut self, group_jid: &JID) -> Result<()> {
+    async fn cleanup_group_encryption(&self, group_jid: &JID) -> Result<()> {
         tracing::info!("Cleaning up group encryption for {}", group_jid);
         
         // TODO: Implement cleanup of group encryption state
@@ -752,7 +828,7 @@ mod tests {
         let device_manager = create_test_device_manager();
         
         let group_service = GroupService::new(signal_manager, device_manager);
-        assert!(group_service.group_cache.is_empty());
+        assert!(group_service.group_cache.read().unwrap().is_empty());
     }
     
     #[tokio::test]
@@ -760,24 +836,36 @@ mod tests {
         let signal_manager = create_test_signal_manager();
         let device_manager = create_test_device_manager();
         
-        let mut group_service = GroupService::new(signal_manager, device_manager);
+        let group_service = Arc::new(GroupService::new(signal_manager, device_manager));
         
         // Initially empty
         assert!(group_service.get_cached_groups().is_empty());
         
-        // Clear empty cache
-        group_service.clear_cache();
+        // Shared with another task
+        let shared = group_service.clone();
+        tokio::spawn(async move { shared.clear_cache() }).await.unwrap();
         assert!(group_service.get_cached_groups().is_empty());
+        assert!(!group_service.are_disappearing_messages_enabled(&JID::new_group("1-2")).await);
+    }
+    
+    #[tokio::test]
+    async fn test_with_iq_sender_while_locked() {
+        let service = GroupService::new(create_test_signal_manager(), create_test_device_manager());
+        let sender: Arc<dyn crate::request::IqSender> = Arc::new(crate::request::StaticIqSender::new(crate::binary::Node::new("iq".to_string())));
+        
+        // Another task holds the community manager
+        let community_manager = service.community_manager.clone();
+        let _guard = community_manager.lock().await;
+        assert!(matches!(service.with_iq_sender(sender), Err(Error::Protocol(_))));
     }
     
     #[test]
     fn test_permission_checking() {
         let signal_manager = create_test_signal_manager();
         let device_manager = create_test_device_manager();
+        let own_jid = device_manager.get_own_jid();
         let group_service = GroupService::new(signal_manager, device_manager);
         
-        let own_jid = group_service.device_manager.get_own_jid();
-        
         // Create test group info
         let group_info = GroupInfo {
             jid: JID::new("group".to_string(), "g.us".to_string()),
@@ -789,12 +877,20 @@ mod tests {
             created_at: std::time::SystemTime::now(),
             settings: GroupSettings::default(),
             invite_link: None,
+            is_parent: false,
+            is_default_sub_group: false,
+            linked_parent_jid: None,
+            member_add_mode: Default::default(),
+            join_approval_mode: false,
         };
         
         // Admin should have all permissions
-        assert!(group_service.check_admin_permission(&group_info).is_ok());
-        assert!(group_service.check_metadata_permission(&group_info).is_ok());
-        assert!(group_service.check_add_permission(&group_info).is_ok());
-        assert!(group_service.check_remove_permission(&group_info, &[]).is_ok());
+        assert!(group_service.check_admin_permission(&group_info, &own_jid).is_ok());
+        assert!(group_service.check_metadata_permission(&group_info, &own_jid).is_ok());
+        assert!(group_service.check_add_permission(&group_info, &own_jid).is_ok());
+        assert!(group_service.check_remove_permission(&group_info, &[], &own_jid).is_ok());
+        
+        let stranger = JID::new_user("9999");
+        assert!(group_service.check_admin_permission(&group_info, &stranger).is_err());
     }
 }
\ No newline at end of file
@@ -2,18 +2,24 @@
 
 use crate::{
     error::{Error, Result},
+    group::protocol,
+    request::IqSender,
     types::JID,
+    util::cache::{CacheStats, TtlLruCache},
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
 /// Group participant manager
 pub struct ParticipantManager {
     /// Participant cache by group
-    participant_cache: HashMap<JID, CachedParticipants>,
+    participant_cache: RwLock<TtlLruCache<JID, CachedParticipants>>,
     /// Configuration
     config: ParticipantManagerConfig,
+    /// Sender for server queries
+    iq_sender: Option<Arc<dyn IqSender>>,
 }
 
 /// Configuration for participant manager
@@ -45,8 +51,6 @@ impl Default for ParticipantManagerConfig {
 pub struct CachedParticipants {
     /// List of participants
     pub participants: Vec<GroupParticipant>,
-    /// Cache timestamp
-    pub cached_at: SystemTime,
     /// Last sync timestamp
     pub last_sync: SystemTime,
 }
@@ -296,18 +300,23 @@ impl ParticipantManager {
     /// Create participant manager with custom config
     pub fn with_config(config: ParticipantManagerConfig) -> Self {
         Self {
-            participant_cache: HashMap::new(),
+            participant_cache: RwLock::new(TtlLruCache::new("group_participants", config.max_cache_size, Duration::from_secs(config.cache_ttl))),
             config,
+            iq_sender: None,
         }
     }
     
+    /// Use the given sender for server queries
+    pub fn with_iq_sender(mut self, iq_sender: Arc<dyn IqSender>) -> Self {
+        self.iq_sender = Some(iq_sender);
+        self
+    }
+    
     /// Get participants for a group
-    pub async fn get_participants(&mut self, group_jid: &JID) -> Result<Vec<GroupParticipant>> {
+    pub async fn get_participants(&self, group_jid: &JID) -> Result<Vec<GroupParticipant>> {
         // Check cache first
-        if let Some(cached) = self.participant_cache.get(group_jid) {
-            if !self.is_cache_expired(cached) {
-                return Ok(cached.participants.clone());
-            }
+        if let Some(cached) = self.participant_cache.write().unwrap().get(group_jid) {
+            return Ok(cached.participants.clone());
         }
         
         // Fetch fresh participant list
@@ -319,66 +328,20 @@ impl ParticipantManager {
         Ok(participants)
     }
     
-    /// Fetch participants from server (placeholder implementation)
+    /// Fetch participants from the server with a `w:g2` group query
     async fn fetch_participants(&self, group_jid: &JID) -> Result<Vec<GroupParticipant>> {
-        // In a real implementation, this would fetch from WhatsApp servers
-        let creator = JID::new("creator".to_string(), "s.whatsapp.net".to_string());
-        let member1 = JID::new("member1".to_string(), "s.whatsapp.net".to_string());
-        let member2 = JID::new("member2".to_string(), "s.whatsapp.net".to_string());
-        
-        let participants = vec![
-            GroupParticipant {
-                jid: creator.clone(),
-                display_name: Some("Group Creator".to_string()),
-                role: ParticipantRole::Creator,
-                joined_at: SystemTime::now(),
-                added_by: None,
-                status: ParticipantStatus::Active,
-                permissions: ParticipantPermissions {
-                    can_send_messages: true,
-                    can_send_media: true,
-                    can_add_participants: true,
-                    can_edit_group_info: true,
-                    can_change_permissions: true,
-                    can_delete_messages: true,
-                    can_pin_messages: true,
-                },
-                attributes: HashMap::new(),
-                last_seen: Some(SystemTime::now()),
-                message_stats: MessageStats {
-                    total_messages: 50,
-                    text_messages: 40,
-                    media_messages: 10,
-                    messages_today: 5,
-                    avg_messages_per_day: 2.5,
-                    last_message_at: Some(SystemTime::now()),
-                },
-            },
-            GroupParticipant {
-                jid: member1.clone(),
-                display_name: Some("Member One".to_string()),
-                role: ParticipantRole::Member,
-                joined_at: SystemTime::now(),
-                added_by: Some(creator.clone()),
-                status: ParticipantStatus::Active,
-                permissions: ParticipantPermissions::default(),
-                attributes: HashMap::new(),
-                last_seen: Some(SystemTime::now()),
-                message_stats: MessageStats::default(),
-            },
-            GroupParticipant {
-                jid: member2,
-                display_name: Some("Member Two".to_string()),
-                role: ParticipantRole::Member,
-                joined_at: SystemTime::now(),
-                added_by: Some(creator),
-                status: ParticipantStatus::Active,
-                permissions: ParticipantPermissions::default(),
-                attributes: HashMap::new(),
-                last_seen: None,
-                message_stats: MessageStats::default(),
-            },
-        ];
+        let iq_sender = self.iq_sender.as_ref().ok_or(Error::NotLoggedIn)?;
+        
+        let response = iq_sender.send_iq(protocol::group_info_query(group_jid)).await?;
+        let group = response
+            .find_child("group")
+            .ok_or_else(|| Error::ElementMissing("group".to_string()))?;
+        
+        let now = SystemTime::now();
+        let participants: Vec<GroupParticipant> = protocol::parse_group_participants(group)
+            .iter()
+            .map(|entry| protocol::to_group_participant(entry, now))
+            .collect();
         
         tracing::info!("Fetched {} participants for group {}", participants.len(), group_jid);
         
@@ -387,7 +350,7 @@ impl ParticipantManager {
     
     /// Add participants to group
     pub async fn add_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
         added_by: &JID,
@@ -421,7 +384,7 @@ impl ParticipantManager {
                 result.successful.push(participant.clone());
                 
                 // Update cache
-                if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+                if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
                     let new_participant = GroupParticipant {
                         jid: participant.clone(),
                         display_name: None,
@@ -452,7 +415,7 @@ impl ParticipantManager {
     
     /// Remove participants from group
     pub async fn remove_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
         _removed_by: &JID,
@@ -487,7 +450,7 @@ impl ParticipantManager {
             result.successful.push(participant.clone());
             
             // Update cache
-            if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+            if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
                 cached.participants.retain(|p| p.jid != participant);
                 cached.last_sync = SystemTime::now();
             }
@@ -505,7 +468,7 @@ impl ParticipantManager {
     
     /// Promote participants to admin
     pub async fn promote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
         _promoted_by: &JID,
@@ -520,7 +483,7 @@ impl ParticipantManager {
         
         for participant in participants {
             // Update role in cache
-            if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+            if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
                 if let Some(p) = cached.participants.iter_mut().find(|p| p.jid == participant) {
                     if p.role != ParticipantRole::Admin && p.role != ParticipantRole::Creator {
                         p.role = ParticipantRole::Admin;
@@ -555,7 +518,7 @@ impl ParticipantManager {
     
     /// Demote participants from admin
     pub async fn demote_participants(
-        &mut self,
+        &self,
         group_jid: &JID,
         participants: Vec<JID>,
         _demoted_by: &JID,
@@ -570,7 +533,7 @@ impl ParticipantManager {
         
         for participant in participants {
             // Update role in cache
-            if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+            if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
                 if let Some(p) = cached.participants.iter_mut().find(|p| p.jid == participant) {
                     if p.role == ParticipantRole::Admin {
                         p.role = ParticipantRole::Member;
@@ -604,12 +567,12 @@ impl ParticipantManager {
     
     /// Update participant permissions
     pub async fn update_permissions(
-        &mut self,
+        &self,
         group_jid: &JID,
         participant_jid: &JID,
         permissions: ParticipantPermissions,
     ) -> Result<()> {
-        if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+        if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
             if let Some(p) = cached.participants.iter_mut().find(|p| p.jid == *participant_jid) {
                 p.permissions = permissions;
                 cached.last_sync = SystemTime::now();
@@ -624,7 +587,7 @@ impl ParticipantManager {
     
     /// Get participant by JID
     pub async fn get_participant(
-        &mut self,
+        &self,
         group_jid: &JID,
         participant_jid: &JID,
     ) -> Result<GroupParticipant> {
@@ -638,7 +601,7 @@ impl ParticipantManager {
     
     /// Get participants by role
     pub async fn get_participants_by_role(
-        &mut self,
+        &self,
         group_jid: &JID,
         role: ParticipantRole,
     ) -> Result<Vec<GroupParticipant>> {
@@ -651,7 +614,7 @@ impl ParticipantManager {
     }
     
     /// Get active participants
-    pub async fn get_active_participants(&mut self, group_jid: &JID) -> Result<Vec<GroupParticipant>> {
+    pub async fn get_active_participants(&self, group_jid: &JID) -> Result<Vec<GroupParticipant>> {
         let participants = self.get_participants(group_jid).await?;
         
         Ok(participants
@@ -662,12 +625,12 @@ impl ParticipantManager {
     
     /// Update participant stats
     pub async fn update_participant_stats(
-        &mut self,
+        &self,
         group_jid: &JID,
         participant_jid: &JID,
         stats_update: StatsUpdate,
     ) -> Result<()> {
-        if let Some(cached) = self.participant_cache.get_mut(group_jid) {
+        if let Some(cached) = self.participant_cache.write().unwrap().get_mut(group_jid) {
             if let Some(p) = cached.participants.iter_mut().find(|p| p.jid == *participant_jid) {
                 if let Some(total) = stats_update.total_messages {
                     p.message_stats.total_messages = total;
@@ -698,44 +661,23 @@ impl ParticipantManager {
     }
     
     /// Cache participants
-    fn cache_participants(&mut self, group_jid: JID, participants: Vec<GroupParticipant>) {
-        // Enforce cache size limit
-        if self.participant_cache.len() >= self.config.max_cache_size {
-            self.evict_oldest_cache_entry();
-        }
-        
+    fn cache_participants(&self, group_jid: JID, participants: Vec<GroupParticipant>) {
         let cached = CachedParticipants {
             participants,
-            cached_at: SystemTime::now(),
             last_sync: SystemTime::now(),
         };
         
-        self.participant_cache.insert(group_jid, cached);
+        self.participant_cache.write().unwrap().insert(group_jid, cached);
     }
     
-    /// Check if cache entry is expired
-    fn is_cache_expired(&self, cached: &CachedParticipants) -> bool {
-        if let Ok(elapsed) = cached.cached_at.elapsed() {
-            elapsed.as_secs() > self.config.cache_ttl
-        } else {
-            true
-        }
-    }
-    
-    /// Evict oldest cache entry
-    fn evict_oldest_cache_entry(&mut self) {
-        if let Some((oldest_key, _)) = self.participant_cache
-            .iter()
-            .min_by_key(|(_, cached)| cached.cached_at)
-            .map(|(key, cached)| (key.clone(), cached.clone()))
-        {
-            self.participant_cache.remove(&oldest_key);
-        }
+    /// Clear cache
+    pub fn clear_cache(&self) {
+        self.participant_cache.write().unwrap().clear();
     }
     
-    /// Clear cache
-    pub fn clear_cache(&mut self) {
-        self.participant_cache.clear();
+    /// Get cache statistics
+    pub fn get_cache_stats(&self) -> CacheStats {
+        self.participant_cache.read().unwrap().stats()
     }
     
     /// Validate participant JID
@@ -772,6 +714,7 @@ impl Default for ParticipantManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::request::StaticIqSender;
     
     fn create_test_jid(user: &str) -> JID {
         JID::new(user.to_string(), "s.whatsapp.net".to_string())
@@ -781,16 +724,27 @@ mod tests {
         JID::new("1234567890".to_string(), "g.us".to_string())
     }
     
+    fn test_iq_sender() -> Arc<StaticIqSender> {
+        let response = crate::binary::Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![protocol::test_group_node("1234567890")]);
+        Arc::new(StaticIqSender::new(response))
+    }
+    
+    fn create_test_manager() -> ParticipantManager {
+        ParticipantManager::new().with_iq_sender(test_iq_sender())
+    }
+    
     #[tokio::test]
     async fn test_participant_manager_creation() {
         let manager = ParticipantManager::new();
-        assert!(manager.participant_cache.is_empty());
+        assert!(manager.participant_cache.read().unwrap().is_empty());
         assert_eq!(manager.config.max_cache_size, 500);
     }
     
     #[tokio::test]
     async fn test_get_participants() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         
         let participants = manager.get_participants(&group_jid).await.unwrap();
@@ -800,12 +754,35 @@ mod tests {
         assert_eq!(participants.iter().filter(|p| p.role == ParticipantRole::Member).count(), 2);
         
         // Should be cached now
-        assert_eq!(manager.participant_cache.len(), 1);
+        assert_eq!(manager.participant_cache.read().unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_fetch_participants_over_iq() {
+        let sender = test_iq_sender();
+        let manager = ParticipantManager::new().with_iq_sender(sender.clone());
+        let group_jid = create_test_group_jid();
+        
+        let creator = manager.get_participant(&group_jid, &create_test_jid("creator")).await.unwrap();
+        assert_eq!(creator.role, ParticipantRole::Creator);
+        assert!(creator.permissions.can_change_permissions);
+        
+        let queries = sender.queries.lock().unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].namespace, protocol::GROUP_NAMESPACE);
+        assert_eq!(queries[0].to, group_jid);
+    }
+    
+    #[tokio::test]
+    async fn test_get_participants_without_connection() {
+        let manager = ParticipantManager::new();
+        let result = manager.get_participants(&create_test_group_jid()).await;
+        assert!(matches!(result, Err(Error::NotLoggedIn)));
     }
     
     #[tokio::test]
     async fn test_add_participants() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         let new_participant1 = create_test_jid("new_member1");
         let new_participant2 = create_test_jid("new_member2");
@@ -829,7 +806,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_remove_participants() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         let removed_by = create_test_jid("admin");
         
@@ -859,7 +836,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_promote_demote_participants() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         let promoter = create_test_jid("creator");
         
@@ -905,7 +882,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_update_permissions() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         
         let participants = manager.get_participants(&group_jid).await.unwrap();
@@ -934,7 +911,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_get_participants_by_role() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         
         let admins = manager.get_participants_by_role(&group_jid, ParticipantRole::Creator).await.unwrap();
@@ -946,7 +923,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_update_participant_stats() {
-        let mut manager = ParticipantManager::new();
+        let manager = create_test_manager();
         let group_jid = create_test_group_jid();
         
         let participants = manager.get_participants(&group_jid).await.unwrap();
@@ -1000,12 +977,12 @@ mod tests {
     
     #[tokio::test]
     async fn test_cache_operations() {
-        let mut manager = ParticipantManager::with_config(ParticipantManagerConfig {
+        let manager = ParticipantManager::with_config(ParticipantManagerConfig {
             max_cache_size: 2,
             cache_ttl: 1,
             auto_sync: false,
             batch_size: 10,
-        });
+        }).with_iq_sender(test_iq_sender());
         
         let group1 = create_test_group_jid();
         let group2 = JID::new("2234567890".to_string(), "g.us".to_string());
@@ -1014,14 +991,20 @@ mod tests {
         // Fill cache
         manager.get_participants(&group1).await.unwrap();
         manager.get_participants(&group2).await.unwrap();
-        assert_eq!(manager.participant_cache.len(), 2);
+        assert_eq!(manager.participant_cache.read().unwrap().len(), 2);
         
-        // Adding third should evict oldest
+        // Adding third should evict the least recently used
+        manager.get_participants(&group1).await.unwrap();
         manager.get_participants(&group3).await.unwrap();
-        assert_eq!(manager.participant_cache.len(), 2);
+        assert_eq!(manager.participant_cache.read().unwrap().len(), 2);
+        assert!(manager.participant_cache.read().unwrap().contains_key(&group1));
+        assert!(!manager.participant_cache.read().unwrap().contains_key(&group2));
+        
+        let stats = manager.get_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 1));
         
         // Clear cache
         manager.clear_cache();
-        assert!(manager.participant_cache.is_empty());
+        assert!(manager.participant_cache.read().unwrap().is_empty());
     }
 }
\ No newline at end of file
@@ -4,17 +4,21 @@ use crate::{
     error::{Error, Result},
     types::JID,
     group::ParticipantRole,
+    util::cache::{CacheStats, TtlLruCache},
 };
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::time::SystemTime;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
 
 /// Group permissions manager
+///
+/// All methods take `&self`, so the manager can be shared between tasks.
 pub struct PermissionManager {
     /// Permission cache by group
-    permission_cache: HashMap<JID, CachedPermissions>,
+    permission_cache: RwLock<TtlLruCache<JID, CachedPermissions>>,
     /// Permission templates
-    templates: HashMap<String, PermissionTemplate>,
+    templates: RwLock<HashMap<String, PermissionTemplate>>,
     /// Configuration
     config: PermissionManagerConfig,
 }
@@ -28,6 +32,9 @@ pub struct PermissionManagerConfig {
     pub default_template: String,
     /// Cache TTL in seconds
     pub cache_ttl: u64,
+    /// Maximum cache size (number of groups)
+    #[serde(default = "default_max_cache_size")]
+    pub max_cache_size: usize,
     /// Whether to audit permission changes
     pub enable_audit: bool,
 }
@@ -38,18 +45,21 @@ impl Default for PermissionManagerConfig {
             enable_fine_grained: true,
             default_template: "default".to_string(),
             cache_ttl: 3600, // 1 hour
+            max_cache_size: default_max_cache_size(),
             enable_audit: true,
         }
     }
 }
 
+fn default_max_cache_size() -> usize {
+    1000
+}
+
 /// Cached permissions with metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedPermissions {
     /// Group permissions
     pub permissions: GroupPermissions,
-    /// Cache timestamp
-    pub cached_at: SystemTime,
     /// Last updated timestamp
     pub last_updated: SystemTime,
 }
@@ -506,8 +516,8 @@ impl PermissionManager {
     /// Create permission manager with custom config
     pub fn with_config(config: PermissionManagerConfig) -> Self {
         Self {
-            permission_cache: HashMap::new(),
-            templates: HashMap::new(),
+            permission_cache: RwLock::new(TtlLruCache::new("group_permissions", config.max_cache_size, Duration::from_secs(config.cache_ttl))),
+            templates: RwLock::new(HashMap::new()),
             config,
         }
     }
@@ -556,9 +566,10 @@ impl PermissionManager {
             restrictions: PermissionRestrictions::default(),
         };
         
-        self.templates.insert("default".to_string(), default_template);
-        self.templates.insert("strict".to_string(), strict_template);
-        self.templates.insert("open".to_string(), open_template);
+        let templates = self.templates.get_mut().unwrap();
+        templates.insert("default".to_string(), default_template);
+        templates.insert("strict".to_string(), strict_template);
+        templates.insert("open".to_string(), open_template);
     }
     
     /// Create default role permissions
@@ -663,12 +674,10 @@ impl PermissionManager {
     }
     
     /// Get permissions for a group
-    pub async fn get_permissions(&mut self, group_jid: &JID) -> Result<GroupPermissions> {
+    pub async fn get_permissions(&self, group_jid: &JID) -> Result<GroupPermissions> {
         // Check cache first
-        if let Some(cached) = self.permission_cache.get(group_jid) {
-            if !self.is_cache_expired(cached) {
-                return Ok(cached.permissions.clone());
-            }
+        if let Some(cached) = self.permission_cache.write().unwrap().get(group_jid) {
+            return Ok(cached.permissions.clone());
         }
         
         // Fetch or create permissions
@@ -684,7 +693,8 @@ impl PermissionManager {
     async fn fetch_or_create_permissions(&self, group_jid: &JID) -> Result<GroupPermissions> {
         // In a real implementation, this would fetch from storage
         // For now, create default permissions
-        let template = self.templates.get(&self.config.default_template)
+        let templates = self.templates.read().unwrap();
+        let template = templates.get(&self.config.default_template)
             .ok_or_else(|| Error::Protocol("Default template not found".to_string()))?;
         
         let permissions = GroupPermissions {
@@ -701,7 +711,7 @@ impl PermissionManager {
     
     /// Check if a participant has a specific permission
     pub async fn has_permission(
-        &mut self,
+        &self,
         group_jid: &JID,
         participant_jid: &JID,
         permission: &str,
@@ -749,11 +759,11 @@ impl PermissionManager {
     
     /// Apply permission template to group
     pub async fn apply_template(
-        &mut self,
+        &self,
         group_jid: &JID,
         template_id: &str,
     ) -> Result<GroupPermissions> {
-        let template = self.templates.get(template_id)
+        let template = self.templates.read().unwrap().get(template_id)
             .ok_or_else(|| Error::Protocol("Template not found".to_string()))?
             .clone();
         
@@ -776,7 +786,7 @@ impl PermissionManager {
     
     /// Update permissions for a group
     pub async fn update_permissions(
-        &mut self,
+        &self,
         group_jid: &JID,
         updates: PermissionUpdate,
     ) -> Result<GroupPermissions> {
@@ -810,38 +820,33 @@ impl PermissionManager {
     }
     
     /// Cache permissions
-    fn cache_permissions(&mut self, group_jid: JID, permissions: GroupPermissions) {
+    fn cache_permissions(&self, group_jid: JID, permissions: GroupPermissions) {
         let cached = CachedPermissions {
             permissions,
-            cached_at: SystemTime::now(),
             last_updated: SystemTime::now(),
         };
         
-        self.permission_cache.insert(group_jid, cached);
+        self.permission_cache.write().unwrap().insert(group_jid, cached);
     }
     
-    /// Check if cache entry is expired
-    fn is_cache_expired(&self, cached: &CachedPermissions) -> bool {
-        if let Ok(elapsed) = cached.cached_at.elapsed() {
-            elapsed.as_secs() > self.config.cache_ttl
-        } else {
-            true
-        }
+    /// Clear permission cache
+    pub fn clear_cache(&self) {
+        self.permission_cache.write().unwrap().clear();
     }
     
-    /// Clear permission cache
-    pub fn clear_cache(&mut self) {
-        self.permission_cache.clear();
+    /// Get cache statistics
+    pub fn get_cache_stats(&self) -> CacheStats {
+        self.permission_cache.read().unwrap().stats()
     }
     
     /// Get available templates
-    pub fn get_templates(&self) -> &HashMap<String, PermissionTemplate> {
-        &self.templates
+    pub fn get_templates(&self) -> HashMap<String, PermissionTemplate> {
+        self.templates.read().unwrap().clone()
     }
     
     /// Add custom template
-    pub fn add_template(&mut self, template: PermissionTemplate) {
-        self.templates.insert(template.id.clone(), template);
+    pub fn add_template(&self, template: PermissionTemplate) {
+        self.templates.write().unwrap().insert(template.id.clone(), template);
     }
 }
 
@@ -881,13 +886,13 @@ mod tests {
     #[tokio::test]
     async fn test_permission_manager_creation() {
         let manager = PermissionManager::new();
-        assert_eq!(manager.templates.len(), 3); // default, strict, open
-        assert!(manager.permission_cache.is_empty());
+        assert_eq!(manager.get_templates().len(), 3); // default, strict, open
+        assert!(manager.permission_cache.read().unwrap().is_empty());
     }
     
     #[tokio::test]
     async fn test_get_permissions() {
-        let mut manager = PermissionManager::new();
+        let manager = PermissionManager::new();
         let group_jid = create_test_group_jid();
         
         let permissions = manager.get_permissions(&group_jid).await.unwrap();
@@ -898,12 +903,12 @@ mod tests {
         assert!(permissions.role_permissions.contains_key(&ParticipantRole::Member));
         
         // Should be cached now
-        assert_eq!(manager.permission_cache.len(), 1);
+        assert_eq!(manager.permission_cache.read().unwrap().len(), 1);
     }
     
     #[tokio::test]
     async fn test_has_permission() {
-        let mut manager = PermissionManager::new();
+        let manager = PermissionManager::new();
         let group_jid = create_test_group_jid();
         let participant_jid = create_test_jid("participant");
         
@@ -937,7 +942,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_apply_template() {
-        let mut manager = PermissionManager::new();
+        let manager = PermissionManager::new();
         let group_jid = create_test_group_jid();
         
         // Apply strict template
@@ -958,7 +963,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_update_permissions() {
-        let mut manager = PermissionManager::new();
+        let manager = PermissionManager::new();
         let group_jid = create_test_group_jid();
         let participant_jid = create_test_jid("participant");
         
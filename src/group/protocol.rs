@@ -0,0 +1,1238 @@
+/// Wire format of the `w:g2` group protocol
+///
+/// Group metadata is fetched with an IQ in the `w:g2` namespace addressed to
+/// the group JID, and changes are pushed by the server as `w:gp2`
+/// notifications. This module builds the queries and parses both into the
+/// crate's group types.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    group::{
+        participants::{
+            GroupParticipant, MessageStats, ParticipantOperationResult, ParticipantOperationType,
+            ParticipantPermissions, ParticipantStatus,
+        },
+        community::{CreateCommunityRequest, LinkedGroup},
+        CreateGroupRequest, DisappearingMessageSettings, GroupEvent, GroupInfo, GroupInviteInfo, GroupPhoto, GroupSettings,
+        MemberAddMode, MembershipRequest, ParticipantPermission, ParticipantRole,
+    },
+    request::{node_content_string, server_jid, InfoQuery, InfoQueryType},
+    types::{GroupInfoChangedEvent, JID},
+};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Namespace of group IQ queries
+pub const GROUP_NAMESPACE: &str = "w:g2";
+
+/// Notification type of group change notifications
+pub const GROUP_NOTIFICATION_TYPE: &str = "w:gp2";
+
+/// Prefix of group invite links
+pub const INVITE_LINK_PREFIX: &str = "https://chat.whatsapp.com/";
+
+/// Namespace of profile picture queries, shared by users and groups
+pub const PICTURE_NAMESPACE: &str = "w:profile:picture";
+
+/// Largest side length the server accepts for a group picture
+pub const MAX_GROUP_PHOTO_SIZE: u16 = 640;
+
+/// Build the IQ fetching the full metadata of a group
+pub fn group_info_query(group_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Get, group_jid.clone())
+        .content(vec![Node::builder("query").attr("request", "interactive").build()])
+}
+
+/// Build the IQ listing all groups we participate in
+pub fn joined_groups_query() -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Get, group_server_jid()).content(vec![Node::builder("participating")
+        .empty_child("participants")
+        .empty_child("description")
+        .build()])
+}
+
+/// Parse the `<groups>` list answering [`joined_groups_query`]
+pub fn parse_joined_groups(response: &Node) -> Result<Vec<GroupInfo>> {
+    let groups = response
+        .find_child("groups")
+        .ok_or_else(|| Error::ElementMissing("groups".to_string()))?;
+    groups
+        .get_children()
+        .into_iter()
+        .flatten()
+        .filter(|group| group.tag == "group")
+        .map(parse_group_info)
+        .collect()
+}
+
+/// Build the IQ creating a new group.
+///
+/// `key` is a client generated id that lets the server deduplicate retried
+/// create requests.
+pub fn create_group_query(request: &CreateGroupRequest, key: &str) -> InfoQuery {
+    let mut create = Node::builder("create")
+        .attr("subject", &request.name)
+        .attr("key", key)
+        .nodes(participant_nodes(&request.participants));
+    if let Some(description) = &request.description {
+        create = create.node(description_node(description, key));
+    }
+
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_server_jid()).content(vec![create.build()])
+}
+
+/// `participant` children for a list of JIDs
+fn participant_nodes(participants: &[JID]) -> Vec<Node> {
+    participants
+        .iter()
+        .map(|jid| Node::builder("participant").attr("jid", jid).build())
+        .collect()
+}
+
+/// `description` child of a create query
+fn description_node(description: &str, key: &str) -> Node {
+    Node::builder("description")
+        .attr("id", key)
+        .child("body", |body| body.text(description))
+        .build()
+}
+
+/// Wire tag of a participant operation, if it can be sent to the server
+pub fn participant_action_tag(operation: &ParticipantOperationType) -> Option<&'static str> {
+    match operation {
+        ParticipantOperationType::Add => Some("add"),
+        ParticipantOperationType::Remove => Some("remove"),
+        ParticipantOperationType::Promote => Some("promote"),
+        ParticipantOperationType::Demote => Some("demote"),
+        _ => None,
+    }
+}
+
+/// Build the IQ adding, removing, promoting or demoting group participants
+pub fn participant_change_query(group_jid: &JID, action: &str, participants: &[JID]) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_jid.clone())
+        .content(vec![Node::builder(action).nodes(participant_nodes(participants)).build()])
+}
+
+/// Describe a per-participant error code returned by a participant change
+pub fn participant_error_reason(code: &str) -> String {
+    let reason = match code {
+        "401" => "participant has blocked the admin",
+        "403" => "participant's privacy settings require an invite",
+        "404" => "participant is not on WhatsApp",
+        "406" => "participant cannot be added to this group",
+        "408" => "participant recently left the group",
+        "409" => "participant is already in the group",
+        "500" => "group is full",
+        _ => "server rejected the participant",
+    };
+    format!("{} (error {})", reason, code)
+}
+
+/// Parse the response to a participant change query.
+///
+/// Participants the server rejected end up in `failed` with the decoded error
+/// code; participants missing from the response are reported as failed too.
+pub fn parse_participant_change_response(
+    response: &Node,
+    action: &str,
+    requested: &[JID],
+    operation: ParticipantOperationType,
+) -> ParticipantOperationResult {
+    let mut result = ParticipantOperationResult::with_operation(operation);
+    let entries = response
+        .find_child(action)
+        .and_then(|a| a.get_children())
+        .map(|c| c.as_slice())
+        .unwrap_or_default();
+
+    for jid in requested {
+        let entry = entries
+            .iter()
+            .filter(|e| e.tag == "participant")
+            .find(|e| e.get_attr("jid").and_then(|j| j.parse::<JID>().ok()).as_ref() == Some(jid));
+
+        match entry.and_then(|e| e.get_attr("error")) {
+            Some(code) => result.add_failure(jid.clone(), participant_error_reason(code)),
+            None if entry.is_some() => result.add_success(jid.clone()),
+            None => result.add_failure(jid.clone(), "no response from server for participant".to_string()),
+        }
+    }
+
+    result
+}
+
+/// JID of the group server, the recipient of queries not tied to one group
+pub fn group_server_jid() -> JID {
+    JID::new(String::new(), "g.us".to_string())
+}
+
+/// Extract the invite code from an invite link (or a bare code)
+pub fn parse_invite_code(link: &str) -> Result<String> {
+    let code = link.trim().strip_prefix(INVITE_LINK_PREFIX).unwrap_or(link.trim());
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::Protocol(format!("Invalid invite link: {}", link)));
+    }
+    Ok(code.to_string())
+}
+
+/// Build the IQ getting (or with `reset`, revoking and regenerating) a group's invite link
+pub fn invite_link_query(group_jid: &JID, reset: bool) -> InfoQuery {
+    let query_type = if reset { InfoQueryType::Set } else { InfoQueryType::Get };
+    InfoQuery::new(GROUP_NAMESPACE, query_type, group_jid.clone()).content(vec![Node::builder("invite").build()])
+}
+
+/// Parse the response to an invite link query into the full link
+pub fn parse_invite_link_response(response: &Node) -> Result<String> {
+    let code = response
+        .find_child("invite")
+        .and_then(|i| i.get_attr("code"))
+        .ok_or_else(|| Error::ElementMissing("invite code".to_string()))?;
+    Ok(format!("{}{}", INVITE_LINK_PREFIX, code))
+}
+
+/// Build the IQ previewing (`get`) or joining (`set`) a group by invite code
+pub fn invite_code_query(code: &str, query_type: InfoQueryType) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, query_type, group_server_jid())
+        .content(vec![Node::builder("invite").attr("code", code).build()])
+}
+
+/// Parse the group preview returned for an invite code
+pub fn parse_invite_info(response: &Node) -> Result<GroupInviteInfo> {
+    let group = response
+        .find_child("group")
+        .ok_or_else(|| Error::ElementMissing("group".to_string()))?;
+    let info = parse_group_info(group)?;
+
+    Ok(GroupInviteInfo {
+        subject: info.name,
+        description: info.description,
+        owner: group.get_attr("creator").and_then(|c| c.parse().ok()),
+        size: group.get_attr("size").and_then(|s| s.parse().ok()),
+        created_at: parse_timestamp(group.get_attr("creation")),
+        jid: info.jid,
+    })
+}
+
+/// Parse the JID of the group joined through an invite code
+pub fn parse_joined_group(response: &Node) -> Result<JID> {
+    let group = response
+        .find_child("group")
+        .ok_or_else(|| Error::ElementMissing("group".to_string()))?;
+
+    if group.find_child("membership_approval_request").is_some() {
+        return Err(Error::Protocol("Joining this group requires admin approval".to_string()));
+    }
+
+    group
+        .get_attr("jid")
+        .ok_or_else(|| Error::ElementMissing("group jid".to_string()))?
+        .parse()
+}
+
+/// Build the IQ accepting an invite received in a `groupInviteMessage`
+pub fn accept_invite_query(group_jid: &JID, inviter: &JID, code: &str, expiration: Option<SystemTime>) -> InfoQuery {
+    let expiration = expiration
+        .and_then(|e| e.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|e| e.as_secs())
+        .unwrap_or_default();
+
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_jid.clone()).content(vec![Node::builder("accept")
+        .attr("code", code)
+        .attr("expiration", expiration)
+        .attr("admin", inviter.to_non_ad())
+        .build()])
+}
+
+/// Check that an image can be used as a group picture.
+///
+/// The server only accepts square baseline or progressive JPEGs of at most
+/// [`MAX_GROUP_PHOTO_SIZE`] pixels per side, and silently drops anything
+/// else. Images must be cropped and scaled by the caller; this returns the
+/// bytes unchanged once they pass.
+pub fn prepare_group_photo(image: Vec<u8>) -> Result<Vec<u8>> {
+    let (width, height) = jpeg_dimensions(&image)
+        .ok_or_else(|| Error::Protocol("Group picture must be a JPEG image".to_string()))?;
+    if width != height {
+        return Err(Error::Protocol(format!("Group picture must be square, got {}x{}", width, height)));
+    }
+    if width == 0 || width > MAX_GROUP_PHOTO_SIZE {
+        return Err(Error::Protocol(format!(
+            "Group picture must be at most {0}x{0}, got {1}x{1}",
+            MAX_GROUP_PHOTO_SIZE, width
+        )));
+    }
+    Ok(image)
+}
+
+/// Read the dimensions of a JPEG from its start-of-frame segment
+fn jpeg_dimensions(data: &[u8]) -> Option<(u16, u16)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes and markers without a length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let is_frame = (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_frame {
+            let frame = data.get(pos + 4..pos + 9)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]);
+            let width = u16::from_be_bytes([frame[3], frame[4]]);
+            return Some((width, height));
+        }
+        if marker == 0xDA || marker == 0xD9 || length < 2 {
+            return None;
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Build the IQ setting a group's picture, or removing it when `image` is `None`
+pub fn set_group_photo_query(group_jid: &JID, image: Option<Vec<u8>>) -> InfoQuery {
+    let content = image
+        .map(|image| vec![Node::builder("picture").attr("type", "image").bytes(image).build()])
+        .unwrap_or_default();
+    InfoQuery::new(PICTURE_NAMESPACE, InfoQueryType::Set, server_jid())
+        .target(group_jid.clone())
+        .content(content)
+}
+
+/// Parse the ID of the picture that was just set
+pub fn parse_set_group_photo_response(response: &Node) -> Result<String> {
+    response
+        .find_child("picture")
+        .and_then(|p| p.get_attr("id"))
+        .cloned()
+        .ok_or_else(|| Error::ElementMissing("picture id".to_string()))
+}
+
+/// Build the IQ fetching the URL of a group's picture or of its preview
+pub fn get_group_photo_query(group_jid: &JID, preview: bool) -> InfoQuery {
+    let picture_type = if preview { "preview" } else { "image" };
+    InfoQuery::new(PICTURE_NAMESPACE, InfoQueryType::Get, server_jid())
+        .target(group_jid.clone())
+        .content(vec![Node::builder("picture").attr("type", picture_type).attr("query", "url").build()])
+}
+
+/// Parse the picture answering [`get_group_photo_query`], `None` if the group has none
+pub fn parse_group_photo(response: &Node) -> Option<GroupPhoto> {
+    let picture = response.find_child("picture")?;
+    Some(GroupPhoto {
+        id: picture.get_attr("id")?.clone(),
+        url: picture.get_attr("url")?.clone(),
+        direct_path: picture.get_attr("direct_path").cloned(),
+        preview: picture.get_attr("type").map(|t| t.as_str()) == Some("preview"),
+    })
+}
+
+/// Build the IQ creating a community (a parent group).
+///
+/// The server creates the community's default announcement group itself.
+pub fn create_community_query(request: &CreateCommunityRequest, key: &str) -> InfoQuery {
+    let approval_mode = match &request.settings {
+        Some(settings) if !settings.approval_required => "auto",
+        _ => "request_required",
+    };
+
+    let mut create = Node::builder("create")
+        .attr("subject", &request.name)
+        .attr("key", key)
+        .child("parent", |parent| parent.attr("default_membership_approval_mode", approval_mode));
+    if let Some(description) = &request.description {
+        create = create.node(description_node(description, key));
+    }
+
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_server_jid()).content(vec![create.build()])
+}
+
+/// Build the IQ linking a group into a community
+pub fn link_group_query(community_jid: &JID, group_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, community_jid.clone()).content(vec![Node::builder("links")
+        .child("link", |link| {
+            link.attr("link_type", "sub_group")
+                .child("group", |group| group.attr("jid", group_jid))
+        })
+        .build()])
+}
+
+/// Build the IQ unlinking a group from a community
+pub fn unlink_group_query(community_jid: &JID, group_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, community_jid.clone()).content(vec![Node::builder("unlink")
+        .attr("unlink_type", "sub_group")
+        .child("group", |group| group.attr("jid", group_jid))
+        .build()])
+}
+
+/// Build the IQ listing the groups linked to a community
+pub fn sub_groups_query(community_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Get, community_jid.clone())
+        .content(vec![Node::builder("sub_groups").build()])
+}
+
+/// Parse the response to a sub group query
+pub fn parse_sub_groups(response: &Node) -> Result<Vec<LinkedGroup>> {
+    let sub_groups = response
+        .find_child("sub_groups")
+        .ok_or_else(|| Error::ElementMissing("sub_groups".to_string()))?;
+
+    Ok(sub_groups
+        .get_children()
+        .map(|c| c.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|g| g.tag == "group")
+        .filter_map(|group| {
+            let id = group.get_attr("id")?;
+            let jid = if id.contains('@') { id.parse().ok()? } else { JID::new_group(id) };
+            Some(LinkedGroup {
+                jid,
+                subject: group.get_attr("subject").cloned().unwrap_or_default(),
+                is_default_sub_group: group.find_child("default_sub_group").is_some(),
+            })
+        })
+        .collect())
+}
+
+/// Build the IQ listing pending membership requests of a group
+pub fn membership_requests_query(group_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Get, group_jid.clone())
+        .content(vec![Node::builder("membership_approval_requests").build()])
+}
+
+/// Parse the pending membership requests of a group
+pub fn parse_membership_requests(response: &Node) -> Result<Vec<MembershipRequest>> {
+    let requests = response
+        .find_child("membership_approval_requests")
+        .ok_or_else(|| Error::ElementMissing("membership_approval_requests".to_string()))?;
+
+    Ok(requests
+        .get_children()
+        .map(|c| c.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|r| r.tag == "membership_approval_request")
+        .filter_map(parse_membership_request)
+        .collect())
+}
+
+fn parse_membership_request(node: &Node) -> Option<MembershipRequest> {
+    Some(MembershipRequest {
+        jid: node.get_attr("jid")?.parse().ok()?,
+        requested_at: parse_timestamp(node.get_attr("request_time")),
+        request_method: node.get_attr("request_method").cloned(),
+    })
+}
+
+/// Build the IQ turning admin approval of new members on or off
+pub fn membership_approval_mode_query(group_jid: &JID, enabled: bool) -> InfoQuery {
+    let state = if enabled { "on" } else { "off" };
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_jid.clone()).content(vec![
+        Node::builder("membership_approval_mode")
+            .child("group_join", |join| join.attr("state", state))
+            .build(),
+    ])
+}
+
+/// Build the IQs changing the settings of a group from `current` to `wanted`.
+///
+/// Announcement mode covers who may send messages. History visibility has
+/// no server setting and is not sent.
+pub fn group_settings_queries(group_jid: &JID, current: &GroupSettings, wanted: &GroupSettings) -> Vec<InfoQuery> {
+    let set = |child: Node| InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_jid.clone()).content(vec![child]);
+    let announce = |settings: &GroupSettings| {
+        settings.announcement_only || settings.send_messages == ParticipantPermission::AdminsOnly
+    };
+    let expiration = |settings: &GroupSettings| settings.disappearing_messages.as_ref().map_or(0, |d| d.duration);
+    
+    let mut queries = Vec::new();
+    if announce(current) != announce(wanted) {
+        let tag = if announce(wanted) { "announcement" } else { "not_announcement" };
+        queries.push(set(Node::builder(tag).build()));
+    }
+    if current.edit_group_info != wanted.edit_group_info {
+        let tag = if wanted.edit_group_info == ParticipantPermission::AdminsOnly { "locked" } else { "unlocked" };
+        queries.push(set(Node::builder(tag).build()));
+    }
+    if current.add_participants != wanted.add_participants {
+        let mode = match wanted.add_participants {
+            ParticipantPermission::AdminsOnly => "admin_add",
+            ParticipantPermission::Everyone => "all_member_add",
+        };
+        queries.push(set(Node::builder("member_add_mode").text(mode).build()));
+    }
+    if expiration(current) != expiration(wanted) {
+        let child = match expiration(wanted) {
+            0 => Node::builder("not_ephemeral").build(),
+            duration => Node::builder("ephemeral").attr("expiration", duration).build(),
+        };
+        queries.push(set(child));
+    }
+    if current.membership_approval != wanted.membership_approval {
+        queries.push(membership_approval_mode_query(group_jid, wanted.membership_approval));
+    }
+    queries
+}
+
+/// Build the IQ leaving a group
+pub fn leave_group_query(group_jid: &JID) -> InfoQuery {
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_server_jid()).content(vec![Node::builder("leave")
+        .child("group", |group| group.attr("id", group_jid))
+        .build()])
+}
+
+/// Build the IQ approving or rejecting membership requests
+pub fn membership_requests_action_query(group_jid: &JID, approve: bool, participants: &[JID]) -> InfoQuery {
+    let action = if approve { "approve" } else { "reject" };
+    InfoQuery::new(GROUP_NAMESPACE, InfoQueryType::Set, group_jid.clone()).content(vec![
+        Node::builder("membership_requests_action")
+            .child(action, |action| action.nodes(participant_nodes(participants)))
+            .build(),
+    ])
+}
+
+/// Parse the response to a membership request action
+pub fn parse_membership_requests_action_response(
+    response: &Node,
+    approve: bool,
+    requested: &[JID],
+) -> Result<ParticipantOperationResult> {
+    let action = if approve { "approve" } else { "reject" };
+    let actions = response
+        .find_child("membership_requests_action")
+        .ok_or_else(|| Error::ElementMissing("membership_requests_action".to_string()))?;
+    let operation = if approve { ParticipantOperationType::Add } else { ParticipantOperationType::Remove };
+
+    Ok(parse_participant_change_response(actions, action, requested, operation))
+}
+
+/// Parse the membership requests announced by a `w:gp2` notification.
+///
+/// Returns the group the requests are for together with the new requests.
+pub fn parse_membership_request_notification(node: &Node) -> Option<(JID, Vec<MembershipRequest>)> {
+    if node.tag != "notification" || node.get_attr("type").map(|t| t.as_str()) != Some(GROUP_NOTIFICATION_TYPE) {
+        return None;
+    }
+    let group = node.get_attr("from")?.parse::<JID>().ok()?;
+    let timestamp = parse_timestamp(node.get_attr("t"));
+
+    let mut requests = Vec::new();
+    for change in node.get_children().map(|c| c.as_slice()).unwrap_or_default() {
+        match change.tag.as_str() {
+            "membership_approval_request" => requests.extend(parse_membership_request(change)),
+            "created_membership_requests" => {
+                let request_method = change.get_attr("request_method").cloned();
+                requests.extend(
+                    change
+                        .get_children()
+                        .map(|c| c.as_slice())
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|user| user.get_attr("jid").and_then(|j| j.parse().ok()))
+                        .map(|jid| MembershipRequest {
+                            jid,
+                            requested_at: timestamp,
+                            request_method: request_method.clone(),
+                        }),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if requests.is_empty() {
+        None
+    } else {
+        Some((group, requests))
+    }
+}
+
+/// A participant entry of a `<group>` node
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupParticipantEntry {
+    pub jid: JID,
+    pub role: ParticipantRole,
+}
+
+/// Parse the `<participant>` children of a `<group>` node
+pub fn parse_group_participants(group: &Node) -> Vec<GroupParticipantEntry> {
+    group
+        .get_children()
+        .map(|children| children.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|c| c.tag == "participant")
+        .filter_map(|c| {
+            let jid = c.get_attr("jid")?.parse::<JID>().ok()?;
+            let role = match c.get_attr("type").map(|t| t.as_str()) {
+                Some("superadmin") => ParticipantRole::Creator,
+                Some("admin") => ParticipantRole::Admin,
+                _ => ParticipantRole::Member,
+            };
+            Some(GroupParticipantEntry { jid, role })
+        })
+        .collect()
+}
+
+/// Parse a `<group>` node (or an IQ response wrapping one) into [`GroupInfo`]
+pub fn parse_group_info(node: &Node) -> Result<GroupInfo> {
+    let group = if node.tag == "group" {
+        node
+    } else {
+        node.find_child("group")
+            .ok_or_else(|| Error::ElementMissing("group".to_string()))?
+    };
+
+    let id = group
+        .get_attr("id")
+        .ok_or_else(|| Error::ElementMissing("group id".to_string()))?;
+    let jid = if id.contains('@') {
+        id.parse::<JID>()?
+    } else {
+        JID::new_group(id)
+    };
+
+    let entries = parse_group_participants(group);
+    let participants = entries.iter().map(|p| p.jid.clone()).collect();
+    let admins = entries
+        .iter()
+        .filter(|p| p.role != ParticipantRole::Member)
+        .map(|p| p.jid.clone())
+        .collect();
+
+    // Old groups may lack the creator attribute, fall back to the superadmin
+    let creator = group
+        .get_attr("creator")
+        .and_then(|c| c.parse::<JID>().ok())
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|p| p.role == ParticipantRole::Creator)
+                .map(|p| p.jid.clone())
+        })
+        .unwrap_or_else(server_jid);
+
+    let description = group
+        .find_child("description")
+        .and_then(|d| d.find_child("body"))
+        .and_then(node_content_string);
+
+    let announcement_only = group.find_child("announcement").is_some();
+    let locked = group.find_child("locked").is_some();
+    let member_add_mode = match group.find_child("member_add_mode").and_then(node_content_string).as_deref() {
+        Some("all_member_add") => MemberAddMode::AllMemberAdd,
+        _ => MemberAddMode::AdminAdd,
+    };
+    let admin_add = member_add_mode == MemberAddMode::AdminAdd;
+
+    let membership_approval = group
+        .find_child("membership_approval_mode")
+        .and_then(|m| m.find_child("group_join"))
+        .and_then(|j| j.get_attr("state"))
+        .map(|state| state == "on")
+        .unwrap_or(false);
+
+    let disappearing_messages = group
+        .find_child("ephemeral")
+        .and_then(|e| e.get_attr("expiration"))
+        .and_then(|e| e.parse::<u64>().ok())
+        .map(|duration| DisappearingMessageSettings::new(duration, locked));
+
+    let permission = |admins_only: bool| {
+        if admins_only {
+            ParticipantPermission::AdminsOnly
+        } else {
+            ParticipantPermission::Everyone
+        }
+    };
+
+    Ok(GroupInfo {
+        jid,
+        name: group.get_attr("subject").cloned().unwrap_or_default(),
+        description,
+        participants,
+        admins,
+        creator,
+        created_at: parse_timestamp(group.get_attr("creation")).unwrap_or(SystemTime::UNIX_EPOCH),
+        settings: GroupSettings {
+            add_participants: permission(admin_add),
+            edit_group_info: permission(locked),
+            send_messages: permission(announcement_only),
+            announcement_only,
+            history_visible: GroupSettings::default().history_visible,
+            disappearing_messages,
+            membership_approval,
+        },
+        invite_link: None,
+        is_parent: group.find_child("parent").is_some(),
+        is_default_sub_group: group.find_child("default_sub_group").is_some(),
+        linked_parent_jid: group
+            .find_child("linked_parent")
+            .and_then(|parent| parent.get_attr("jid"))
+            .and_then(|jid| jid.parse().ok()),
+        member_add_mode,
+        join_approval_mode: membership_approval,
+    })
+}
+
+/// Convert a participant entry into the detailed participant representation
+pub fn to_group_participant(entry: &GroupParticipantEntry, joined_at: SystemTime) -> GroupParticipant {
+    let mut permissions = ParticipantPermissions::default();
+    if entry.role != ParticipantRole::Member {
+        permissions.can_add_participants = true;
+        permissions.can_edit_group_info = true;
+        permissions.can_delete_messages = true;
+        permissions.can_pin_messages = true;
+    }
+    if entry.role == ParticipantRole::Creator {
+        permissions.can_change_permissions = true;
+    }
+
+    GroupParticipant {
+        jid: entry.jid.clone(),
+        display_name: None,
+        role: entry.role.clone(),
+        joined_at,
+        added_by: None,
+        status: ParticipantStatus::Active,
+        permissions,
+        attributes: HashMap::new(),
+        last_seen: None,
+        message_stats: MessageStats::default(),
+    }
+}
+
+/// Parse a `<notification type="w:gp2">` stanza.
+///
+/// Returns `None` if the node is not a group notification.
+pub fn parse_group_notification(node: &Node) -> Option<GroupInfoChangedEvent> {
+    if node.tag != "notification" || node.get_attr("type").map(|t| t.as_str()) != Some(GROUP_NOTIFICATION_TYPE) {
+        return None;
+    }
+
+    let jid = node.get_attr("from")?.parse::<JID>().ok()?;
+    let mut event = GroupInfoChangedEvent {
+        jid,
+        author: node.get_attr("participant").and_then(|p| p.parse().ok()),
+        timestamp: parse_timestamp(node.get_attr("t")),
+        name: None,
+        description: None,
+        announce: None,
+        locked: None,
+        ephemeral_expiration: None,
+        membership_approval: None,
+        joined: Vec::new(),
+        left: Vec::new(),
+        promoted: Vec::new(),
+        demoted: Vec::new(),
+    };
+
+    for change in node.get_children().map(|c| c.as_slice()).unwrap_or_default() {
+        match change.tag.as_str() {
+            "subject" => event.name = change.get_attr("subject").cloned(),
+            "description" => {
+                event.description = Some(
+                    change
+                        .find_child("body")
+                        .and_then(node_content_string)
+                        .unwrap_or_default(),
+                )
+            }
+            "announcement" => event.announce = Some(true),
+            "not_announcement" => event.announce = Some(false),
+            "locked" => event.locked = Some(true),
+            "unlocked" => event.locked = Some(false),
+            "ephemeral" => {
+                event.ephemeral_expiration = change.get_attr("expiration").and_then(|e| e.parse().ok())
+            }
+            "not_ephemeral" => event.ephemeral_expiration = Some(0),
+            "membership_approval_mode" => {
+                event.membership_approval = change
+                    .find_child("group_join")
+                    .and_then(|j| j.get_attr("state"))
+                    .map(|state| state == "on")
+            }
+            "add" => event.joined.extend(change_participants(change)),
+            "remove" | "leave" => event.left.extend(change_participants(change)),
+            "promote" => event.promoted.extend(change_participants(change)),
+            "demote" => event.demoted.extend(change_participants(change)),
+            _ => {}
+        }
+    }
+
+    Some(event)
+}
+
+/// Parse a group notification into typed [`GroupEvent`]s.
+///
+/// Handles `w:gp2` notifications and `picture` notifications from groups.
+/// Membership changes made without an author (someone leaving or joining
+/// through a link) are attributed to the first affected participant.
+pub fn parse_group_events(node: &Node) -> Vec<GroupEvent> {
+    if let Some(event) = parse_group_picture_notification(node) {
+        return vec![event];
+    }
+    let Some(change) = parse_group_notification(node) else {
+        return Vec::new();
+    };
+    
+    let group_jid = change.jid;
+    let by = change.author;
+    let mut events = Vec::new();
+    if let Some(subject) = change.name {
+        events.push(GroupEvent::SubjectChanged { group_jid: group_jid.clone(), subject, by: by.clone() });
+    }
+    if let Some(description) = change.description {
+        events.push(GroupEvent::DescriptionChanged {
+            group_jid: group_jid.clone(),
+            description: Some(description).filter(|d| !d.is_empty()),
+            by: by.clone(),
+        });
+    }
+    if let Some(announce) = change.announce {
+        events.push(GroupEvent::AnnounceChanged { group_jid: group_jid.clone(), announce, by: by.clone() });
+    }
+    if let Some(locked) = change.locked {
+        events.push(GroupEvent::LockedChanged { group_jid: group_jid.clone(), locked, by: by.clone() });
+    }
+    
+    let membership: [(Vec<JID>, fn(JID, Vec<JID>, JID) -> GroupEvent); 4] = [
+        (change.joined, |group_jid, participants, by| GroupEvent::ParticipantsAdded { group_jid, participants, by }),
+        (change.left, |group_jid, participants, by| GroupEvent::ParticipantsRemoved { group_jid, participants, by }),
+        (change.promoted, |group_jid, participants, by| GroupEvent::ParticipantsPromoted { group_jid, participants, by }),
+        (change.demoted, |group_jid, participants, by| GroupEvent::ParticipantsDemoted { group_jid, participants, by }),
+    ];
+    for (participants, event) in membership {
+        let Some(actor) = by.clone().or_else(|| participants.first().cloned()) else {
+            continue;
+        };
+        events.push(event(group_jid.clone(), participants, actor));
+    }
+    events
+}
+
+/// Parse a `picture` notification of a group
+fn parse_group_picture_notification(node: &Node) -> Option<GroupEvent> {
+    if node.tag != "notification" || node.get_attr("type").map(|t| t.as_str()) != Some("picture") {
+        return None;
+    }
+    let group_jid = node.get_attr("from")?.parse::<JID>().ok().filter(JID::is_group)?;
+    let change = node.get_children()?.iter().find(|c| c.tag == "set" || c.tag == "delete")?;
+    Some(GroupEvent::PictureChanged {
+        group_jid,
+        picture_id: if change.tag == "set" { change.get_attr("id").cloned() } else { None },
+        by: change.get_attr("author")
+            .or_else(|| node.get_attr("participant"))
+            .and_then(|author| author.parse().ok()),
+    })
+}
+
+fn change_participants(change: &Node) -> Vec<JID> {
+    change
+        .get_children()
+        .map(|c| c.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|p| p.tag == "participant")
+        .filter_map(|p| p.get_attr("jid").and_then(|j| j.parse().ok()))
+        .collect()
+}
+
+fn parse_timestamp(value: Option<&String>) -> Option<SystemTime> {
+    value
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+pub(crate) fn test_group_node(id: &str) -> Node {
+    Node::new("group".to_string())
+        .attr("id".to_string(), id.to_string())
+        .attr("subject".to_string(), "Weekend plans".to_string())
+        .attr("creator".to_string(), "creator@s.whatsapp.net".to_string())
+        .attr("creation".to_string(), "1700000000".to_string())
+        .with_children(vec![
+            Node::new("participant".to_string())
+                .attr("jid".to_string(), "creator@s.whatsapp.net".to_string())
+                .attr("type".to_string(), "superadmin".to_string()),
+            Node::new("participant".to_string()).attr("jid".to_string(), "member1@s.whatsapp.net".to_string()),
+            Node::new("participant".to_string()).attr("jid".to_string(), "member2@s.whatsapp.net".to_string()),
+            Node::new("description".to_string())
+                .attr("id".to_string(), "desc-1".to_string())
+                .with_children(vec![Node::new("body".to_string()).with_text("Bring snacks".to_string())]),
+            Node::new("locked".to_string()),
+            Node::new("ephemeral".to_string()).attr("expiration".to_string(), "604800".to_string()),
+        ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group::GroupKind;
+
+    #[test]
+    fn test_group_info_query() {
+        let group = JID::new_group("120363000000000000");
+        let node = group_info_query(&group).to_node("1");
+
+        assert_eq!(node.get_attr("xmlns").unwrap(), GROUP_NAMESPACE);
+        assert_eq!(node.get_attr("to").unwrap(), "120363000000000000@g.us");
+        assert_eq!(node.find_child("query").unwrap().get_attr("request").unwrap(), "interactive");
+    }
+
+    #[test]
+    fn test_parse_group_info() {
+        let response = Node::new("iq".to_string())
+            .attr("type".to_string(), "result".to_string())
+            .with_children(vec![test_group_node("120363000000000000")]);
+
+        let info = parse_group_info(&response).unwrap();
+        assert_eq!(info.jid, JID::new_group("120363000000000000"));
+        assert_eq!(info.name, "Weekend plans");
+        assert_eq!(info.description.as_deref(), Some("Bring snacks"));
+        assert_eq!(info.participants.len(), 3);
+        assert_eq!(info.admins, vec![info.creator.clone()]);
+        assert_eq!(info.creator.user, "creator");
+        assert_eq!(info.created_at, SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000));
+        assert_eq!(info.settings.edit_group_info, ParticipantPermission::AdminsOnly);
+        assert!(!info.settings.announcement_only);
+        assert_eq!(info.settings.disappearing_messages.unwrap().duration, 604800);
+        assert_eq!(info.kind(), GroupKind::Regular);
+        assert_eq!(info.member_add_mode, MemberAddMode::AdminAdd);
+    }
+
+    #[test]
+    fn test_parse_community_attributes() {
+        let community = test_group_node("111").with_children(vec![
+            Node::builder("parent").attr("default_membership_approval_mode", "request_required").build(),
+            Node::builder("membership_approval_mode")
+                .nodes(vec![Node::builder("group_join").attr("state", "on").build()])
+                .build(),
+        ]);
+        let info = parse_group_info(&community).unwrap();
+        assert_eq!(info.kind(), GroupKind::Community);
+        assert!(info.join_approval_mode);
+
+        let announcement = test_group_node("222").with_children(vec![
+            Node::new("default_sub_group".to_string()),
+            Node::builder("linked_parent").attr("jid", "111@g.us").build(),
+            Node::new("member_add_mode".to_string()).with_text("all_member_add".to_string()),
+        ]);
+        let info = parse_group_info(&announcement).unwrap();
+        assert_eq!(info.kind(), GroupKind::CommunityAnnouncement);
+        assert_eq!(info.linked_parent_jid, Some(JID::new_group("111")));
+        assert_eq!(info.member_add_mode, MemberAddMode::AllMemberAdd);
+        assert_eq!(info.settings.add_participants, ParticipantPermission::Everyone);
+    }
+
+    #[test]
+    fn test_create_group_query() {
+        let request = CreateGroupRequest::new(
+            "Weekend plans".to_string(),
+            vec![JID::new("member1".to_string(), "s.whatsapp.net".to_string())],
+        )
+        .with_description("Bring snacks".to_string());
+        let node = create_group_query(&request, "key-1").to_node("1");
+
+        assert_eq!(node.get_attr("type").unwrap(), "set");
+        assert_eq!(node.get_attr("to").unwrap(), "g.us");
+        let create = node.find_child("create").unwrap();
+        assert_eq!(create.get_attr("subject").unwrap(), "Weekend plans");
+        assert_eq!(create.get_attr("key").unwrap(), "key-1");
+        assert!(create.find_child("participant").is_some());
+        assert!(create.find_child("description").is_some());
+    }
+
+    #[test]
+    fn test_parse_participant_change_response() {
+        let ok = JID::new("ok".to_string(), "s.whatsapp.net".to_string());
+        let private = JID::new("private".to_string(), "s.whatsapp.net".to_string());
+        let missing = JID::new("missing".to_string(), "s.whatsapp.net".to_string());
+        let response = Node::new("iq".to_string()).with_children(vec![Node::new("add".to_string()).with_children(vec![
+            Node::new("participant".to_string()).attr("jid".to_string(), ok.to_string()),
+            Node::new("participant".to_string())
+                .attr("jid".to_string(), private.to_string())
+                .attr("error".to_string(), "403".to_string()),
+        ])]);
+
+        let result = parse_participant_change_response(
+            &response,
+            "add",
+            &[ok.clone(), private.clone(), missing.clone()],
+            ParticipantOperationType::Add,
+        );
+        assert_eq!(result.successful, vec![ok]);
+        assert_eq!(result.failed.len(), 2);
+        assert_eq!(result.failed[0].0, private);
+        assert!(result.failed[0].1.contains("403"));
+        assert_eq!(result.failed[1].0, missing);
+    }
+
+    #[test]
+    fn test_invite_codes_and_links() {
+        assert_eq!(parse_invite_code("https://chat.whatsapp.com/AbC123xyz").unwrap(), "AbC123xyz");
+        assert_eq!(parse_invite_code("AbC123xyz").unwrap(), "AbC123xyz");
+        assert!(parse_invite_code("https://chat.whatsapp.com/").is_err());
+        assert!(parse_invite_code("https://example.com/AbC").is_err());
+
+        let query = invite_link_query(&JID::new_group("123"), true).to_node("1");
+        assert_eq!(query.get_attr("type").unwrap(), "set");
+        let response = Node::new("iq".to_string())
+            .with_children(vec![Node::new("invite".to_string()).attr("code".to_string(), "NewCode1".to_string())]);
+        assert_eq!(parse_invite_link_response(&response).unwrap(), "https://chat.whatsapp.com/NewCode1");
+    }
+
+    #[test]
+    fn test_parse_invite_info_and_join() {
+        let preview = Node::new("iq".to_string()).with_children(vec![test_group_node("123").attr("size".to_string(), "42".to_string())]);
+        let info = parse_invite_info(&preview).unwrap();
+        assert_eq!(info.subject, "Weekend plans");
+        assert_eq!(info.size, Some(42));
+        assert_eq!(info.owner.unwrap().user, "creator");
+
+        let joined = Node::new("iq".to_string())
+            .with_children(vec![Node::new("group".to_string()).attr("jid".to_string(), "123@g.us".to_string())]);
+        assert_eq!(parse_joined_group(&joined).unwrap(), JID::new_group("123"));
+
+        let pending = Node::new("iq".to_string()).with_children(vec![Node::new("group".to_string())
+            .attr("jid".to_string(), "123@g.us".to_string())
+            .with_children(vec![Node::new("membership_approval_request".to_string())])]);
+        assert!(parse_joined_group(&pending).is_err());
+    }
+
+    #[test]
+    fn test_community_queries() {
+        let community = JID::new_group("100");
+        let group = JID::new_group("200");
+
+        let link = link_group_query(&community, &group).to_node("1");
+        let link = link.find_child("links").unwrap().find_child("link").unwrap();
+        assert_eq!(link.get_attr("link_type").unwrap(), "sub_group");
+        assert_eq!(link.find_child("group").unwrap().get_attr("jid").unwrap(), "200@g.us");
+
+        let unlink = unlink_group_query(&community, &group).to_node("2");
+        assert_eq!(unlink.find_child("unlink").unwrap().get_attr("unlink_type").unwrap(), "sub_group");
+
+        let create = create_community_query(&CreateCommunityRequest::new("Neighbours".to_string()), "k").to_node("3");
+        let parent = create.find_child("create").unwrap().find_child("parent").unwrap();
+        assert_eq!(parent.get_attr("default_membership_approval_mode").unwrap(), "request_required");
+
+        let response = Node::new("iq".to_string()).with_children(vec![Node::new("sub_groups".to_string()).with_children(vec![
+            Node::new("group".to_string())
+                .attr("id".to_string(), "300".to_string())
+                .attr("subject".to_string(), "Announcements".to_string())
+                .with_children(vec![Node::new("default_sub_group".to_string())]),
+            Node::new("group".to_string())
+                .attr("id".to_string(), "200".to_string())
+                .attr("subject".to_string(), "Garden".to_string()),
+        ])]);
+        let groups = parse_sub_groups(&response).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert!(groups[0].is_default_sub_group);
+        assert_eq!(groups[1].jid, group);
+    }
+
+    #[test]
+    fn test_membership_approval_mode() {
+        let group = JID::new_group("100");
+        let query = membership_approval_mode_query(&group, true).to_node("1");
+        let mode = query.find_child("membership_approval_mode").unwrap();
+        assert_eq!(mode.find_child("group_join").unwrap().get_attr("state").unwrap(), "on");
+
+        let node = test_group_node("100");
+        assert!(!parse_group_info(&node).unwrap().settings.membership_approval);
+        let mut children = node.get_children().unwrap().clone();
+        children.push(mode.clone());
+        assert!(parse_group_info(&node.with_children(children)).unwrap().settings.membership_approval);
+
+        let notification = Node::new("notification".to_string())
+            .attr("type".to_string(), GROUP_NOTIFICATION_TYPE.to_string())
+            .attr("from".to_string(), "100@g.us".to_string())
+            .with_children(vec![mode.clone()]);
+        assert_eq!(parse_group_notification(&notification).unwrap().membership_approval, Some(true));
+    }
+
+    #[test]
+    fn test_membership_requests() {
+        let response = Node::new("iq".to_string()).with_children(vec![Node::new("membership_approval_requests".to_string())
+            .with_children(vec![Node::new("membership_approval_request".to_string())
+                .attr("jid".to_string(), "asker@s.whatsapp.net".to_string())
+                .attr("request_time".to_string(), "1700000000".to_string())])]);
+        let requests = parse_membership_requests(&response).unwrap();
+        assert_eq!(requests[0].jid.user, "asker");
+        assert_eq!(requests[0].requested_at, Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000)));
+
+        let notification = Node::new("notification".to_string())
+            .attr("type".to_string(), GROUP_NOTIFICATION_TYPE.to_string())
+            .attr("from".to_string(), "100@g.us".to_string())
+            .with_children(vec![Node::new("created_membership_requests".to_string())
+                .attr("request_method".to_string(), "invite_link".to_string())
+                .with_children(vec![Node::new("requested_user".to_string())
+                    .attr("jid".to_string(), "asker@s.whatsapp.net".to_string())])]);
+        let (group, requests) = parse_membership_request_notification(&notification).unwrap();
+        assert_eq!(group, JID::new_group("100"));
+        assert_eq!(requests[0].request_method.as_deref(), Some("invite_link"));
+
+        let asker = JID::new("asker".to_string(), "s.whatsapp.net".to_string());
+        let action = Node::new("iq".to_string()).with_children(vec![Node::new("membership_requests_action".to_string())
+            .with_children(vec![Node::new("approve".to_string()).with_children(vec![
+                Node::new("participant".to_string()).attr("jid".to_string(), asker.to_string()),
+            ])])]);
+        let result = parse_membership_requests_action_response(&action, true, &[asker]).unwrap();
+        assert!(result.all_successful());
+    }
+
+    #[test]
+    fn test_joined_groups() {
+        let node = joined_groups_query().to_node("1");
+        assert_eq!(node.get_attr("to").unwrap(), "g.us");
+        assert!(node.find_child("participating").unwrap().find_child("participants").is_some());
+
+        let response = Node::new("iq".to_string()).with_children(vec![Node::new("groups".to_string())
+            .with_children(vec![test_group_node("1"), test_group_node("2")])]);
+        let groups = parse_joined_groups(&response).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].jid, JID::new_group("2"));
+        assert!(parse_joined_groups(&Node::new("iq".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_parse_group_info_missing_group() {
+        let response = Node::new("iq".to_string());
+        assert!(matches!(parse_group_info(&response), Err(Error::ElementMissing(_))));
+    }
+
+    #[test]
+    fn test_parse_group_notification() {
+        let participant = |jid: &str| Node::new("participant".to_string()).attr("jid".to_string(), jid.to_string());
+        let node = Node::new("notification".to_string())
+            .attr("type".to_string(), GROUP_NOTIFICATION_TYPE.to_string())
+            .attr("from".to_string(), "123@g.us".to_string())
+            .attr("participant".to_string(), "admin@s.whatsapp.net".to_string())
+            .attr("t".to_string(), "1700000100".to_string())
+            .with_children(vec![
+                Node::new("subject".to_string()).attr("subject".to_string(), "New name".to_string()),
+                Node::new("announcement".to_string()),
+                Node::new("add".to_string()).with_children(vec![participant("new@s.whatsapp.net")]),
+                Node::new("demote".to_string()).with_children(vec![participant("old@s.whatsapp.net")]),
+            ]);
+
+        let event = parse_group_notification(&node).unwrap();
+        assert_eq!(event.jid.to_string(), "123@g.us");
+        assert_eq!(event.author.unwrap().user, "admin");
+        assert_eq!(event.name.as_deref(), Some("New name"));
+        assert_eq!(event.announce, Some(true));
+        assert_eq!(event.joined[0].user, "new");
+        assert_eq!(event.demoted[0].user, "old");
+        assert!(event.locked.is_none());
+
+        let other = Node::new("notification".to_string()).attr("type".to_string(), "devices".to_string());
+        assert!(parse_group_notification(&other).is_none());
+        
+        let group = JID::new_group("123");
+        let admin: JID = "admin@s.whatsapp.net".parse().unwrap();
+        assert_eq!(parse_group_events(&node), vec![
+            GroupEvent::SubjectChanged { group_jid: group.clone(), subject: "New name".to_string(), by: Some(admin.clone()) },
+            GroupEvent::AnnounceChanged { group_jid: group.clone(), announce: true, by: Some(admin.clone()) },
+            GroupEvent::ParticipantsAdded {
+                group_jid: group.clone(),
+                participants: vec!["new@s.whatsapp.net".parse().unwrap()],
+                by: admin.clone(),
+            },
+            GroupEvent::ParticipantsDemoted {
+                group_jid: group.clone(),
+                participants: vec!["old@s.whatsapp.net".parse().unwrap()],
+                by: admin.clone(),
+            },
+        ]);
+    }
+    
+    #[test]
+    fn test_parse_group_picture_and_leave() {
+        let leaver: JID = "leaver@s.whatsapp.net".parse().unwrap();
+        let leave = Node::new("notification".to_string())
+            .attr("type".to_string(), GROUP_NOTIFICATION_TYPE.to_string())
+            .attr("from".to_string(), "123@g.us".to_string())
+            .with_children(vec![Node::new("leave".to_string()).with_children(vec![
+                Node::new("participant".to_string()).attr("jid".to_string(), leaver.to_string()),
+            ])]);
+        assert_eq!(parse_group_events(&leave), vec![GroupEvent::ParticipantsRemoved {
+            group_jid: JID::new_group("123"),
+            participants: vec![leaver.clone()],
+            by: leaver,
+        }]);
+        
+        let picture = Node::new("notification".to_string())
+            .attr("type".to_string(), "picture".to_string())
+            .attr("from".to_string(), "123@g.us".to_string())
+            .with_children(vec![Node::new("set".to_string())
+                .attr("id".to_string(), "1700000000".to_string())
+                .attr("author".to_string(), "admin@s.whatsapp.net".to_string())]);
+        assert!(matches!(
+            &parse_group_events(&picture)[..],
+            [GroupEvent::PictureChanged { picture_id: Some(id), by: Some(_), .. }] if id == "1700000000"
+        ));
+    }
+    
+    fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10];
+        data.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        data.extend_from_slice(&[0xFF, 0xC2, 0x00, 0x11, 0x08]);
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&[0x03; 10]);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+    
+    #[test]
+    fn test_prepare_group_photo() {
+        assert_eq!(jpeg_dimensions(&jpeg_header(640, 480)), Some((640, 480)));
+        assert!(prepare_group_photo(jpeg_header(640, 640)).is_ok());
+        assert!(prepare_group_photo(jpeg_header(640, 480)).is_err());
+        assert!(prepare_group_photo(jpeg_header(1024, 1024)).is_err());
+        assert!(prepare_group_photo(b"\x89PNG\r\n\x1a\n".to_vec()).is_err());
+        assert!(prepare_group_photo(vec![0xFF, 0xD8, 0xFF]).is_err());
+    }
+    
+    #[test]
+    fn test_group_photo_queries() {
+        let group: JID = "123-456@g.us".parse().unwrap();
+        let set = set_group_photo_query(&group, Some(vec![1, 2, 3])).to_node("1");
+        assert_eq!(set.get_attr("xmlns").map(|s| s.as_str()), Some(PICTURE_NAMESPACE));
+        assert_eq!(set.get_attr("target").map(|s| s.as_str()), Some("123-456@g.us"));
+        assert!(set.find_child("picture").is_some());
+        let remove = set_group_photo_query(&group, None).to_node("2");
+        assert!(remove.find_child("picture").is_none());
+        
+        let get = get_group_photo_query(&group, true).to_node("3");
+        let picture = get.find_child("picture").unwrap();
+        assert_eq!(picture.get_attr("type").map(|s| s.as_str()), Some("preview"));
+        
+        let response = Node::new("iq".to_string()).with_children(vec![Node::new("picture".to_string())
+            .attr("id".to_string(), "1700000001".to_string())
+            .attr("type".to_string(), "preview".to_string())
+            .attr("url".to_string(), "https://pps.whatsapp.net/v/t61/abc".to_string())
+            .attr("direct_path".to_string(), "/v/t61/abc".to_string())]);
+        let photo = parse_group_photo(&response).unwrap();
+        assert_eq!(photo.id, "1700000001");
+        assert!(photo.preview);
+        assert_eq!(photo.direct_path.as_deref(), Some("/v/t61/abc"));
+        assert_eq!(parse_set_group_photo_response(&response).unwrap(), "1700000001");
+        assert!(parse_group_photo(&Node::new("iq".to_string())).is_none());
+    }
+}
@@ -28,6 +28,41 @@ pub struct GroupInfo {
     pub settings: GroupSettings,
     /// Group invite link (if available)
     pub invite_link: Option<String>,
+    /// Whether this is a community (the parent of linked groups)
+    #[serde(default)]
+    pub is_parent: bool,
+    /// Whether this is the announcement group of a community
+    #[serde(default)]
+    pub is_default_sub_group: bool,
+    /// Community this group is linked to
+    #[serde(default)]
+    pub linked_parent_jid: Option<JID>,
+    /// Who may add participants
+    #[serde(default)]
+    pub member_add_mode: MemberAddMode,
+    /// Whether joining requires admin approval
+    #[serde(default)]
+    pub join_approval_mode: bool,
+}
+
+/// Who may add participants to a group, the `member_add_mode` of the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemberAddMode {
+    #[default]
+    AdminAdd,
+    AllMemberAdd,
+}
+
+/// What kind of chat a group is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupKind {
+    /// A community, whose members only talk in its linked groups
+    Community,
+    /// The announcement group of a community, where only admins post
+    CommunityAnnouncement,
+    /// A group linked to a community
+    CommunitySubGroup,
+    Regular,
 }
 
 impl GroupInfo {
@@ -50,6 +85,24 @@ impl GroupInfo {
             created_at: SystemTime::now(),
             settings: GroupSettings::default(),
             invite_link: None,
+            is_parent: false,
+            is_default_sub_group: false,
+            linked_parent_jid: None,
+            member_add_mode: MemberAddMode::default(),
+            join_approval_mode: false,
+        }
+    }
+    
+    /// Whether the group is a community, its announcement group or a linked or regular group
+    pub fn kind(&self) -> GroupKind {
+        if self.is_parent {
+            GroupKind::Community
+        } else if self.is_default_sub_group {
+            GroupKind::CommunityAnnouncement
+        } else if self.linked_parent_jid.is_some() {
+            GroupKind::CommunitySubGroup
+        } else {
+            GroupKind::Regular
         }
     }
     
@@ -68,6 +121,11 @@ impl GroupInfo {
         self.creator == *jid
     }
     
+    /// Check if a JID may send messages, which only admins can in announcement groups
+    pub fn can_send_messages(&self, jid: &JID) -> bool {
+        !self.settings.announcement_only || self.is_admin(jid)
+    }
+    
     /// Get participant count
     pub fn participant_count(&self) -> usize {
         self.participants.len()
@@ -143,6 +201,8 @@ pub struct GroupSettings {
     pub history_visible: bool,
     /// Whether disappearing messages are enabled
     pub disappearing_messages: Option<DisappearingMessageSettings>,
+    /// Whether joining requires admin approval
+    pub membership_approval: bool,
 }
 
 impl Default for GroupSettings {
@@ -154,6 +214,7 @@ impl Default for GroupSettings {
             announcement_only: false,
             history_visible: true,
             disappearing_messages: None,
+            membership_approval: false,
         }
     }
 }
@@ -270,6 +331,11 @@ impl CreateGroupRequest {
             }
         }
         
+        // Every other setting is sent after the group is created
+        if self.settings.as_ref().is_some_and(|settings| !settings.history_visible) {
+            return Err(Error::Protocol("Group history visibility cannot be set".to_string()));
+        }
+        
         Ok(())
     }
 }
@@ -357,6 +423,47 @@ impl Default for GroupMetadataUpdate {
     }
 }
 
+/// Preview of a group resolved from an invite link, available before joining
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupInviteInfo {
+    /// Group JID
+    pub jid: JID,
+    /// Group name/subject
+    pub subject: String,
+    /// Group description
+    pub description: Option<String>,
+    /// Group owner, if disclosed by the server
+    pub owner: Option<JID>,
+    /// Number of participants
+    pub size: Option<u32>,
+    /// Group creation timestamp
+    pub created_at: Option<SystemTime>,
+}
+
+/// A group's profile picture as returned by the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupPhoto {
+    /// Picture ID, changes whenever the picture is replaced
+    pub id: String,
+    /// Download URL of the JPEG
+    pub url: String,
+    /// Path of the picture on the media servers
+    pub direct_path: Option<String>,
+    /// Whether this is the low resolution preview
+    pub preview: bool,
+}
+
+/// A pending request to join a group that requires admin approval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MembershipRequest {
+    /// User asking to join
+    pub jid: JID,
+    /// When the request was made
+    pub requested_at: Option<SystemTime>,
+    /// How the user found the group (e.g. `invite_link`, `linked_group_join`)
+    pub request_method: Option<String>,
+}
+
 /// Result of participant operations (add/remove/promote/demote) from types module
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GroupParticipantOperationResult {
@@ -480,6 +587,36 @@ pub enum GroupEvent {
         group_jid: JID,
         participant: JID,
     },
+    /// The group subject was changed
+    SubjectChanged {
+        group_jid: JID,
+        subject: String,
+        by: Option<JID>,
+    },
+    /// The group description was changed, `None` when it was removed
+    DescriptionChanged {
+        group_jid: JID,
+        description: Option<String>,
+        by: Option<JID>,
+    },
+    /// The group picture was changed, `None` when it was removed
+    PictureChanged {
+        group_jid: JID,
+        picture_id: Option<String>,
+        by: Option<JID>,
+    },
+    /// Only admins may send messages (or everyone again)
+    AnnounceChanged {
+        group_jid: JID,
+        announce: bool,
+        by: Option<JID>,
+    },
+    /// Only admins may edit the group info (or everyone again)
+    LockedChanged {
+        group_jid: JID,
+        locked: bool,
+        by: Option<JID>,
+    },
 }
 
 #[cfg(test)]
@@ -0,0 +1,274 @@
+/// On-demand history sync
+///
+/// Companion devices only receive a recent window of each chat when they are
+/// linked; the primary phone keeps the rest. Older messages are requested
+/// with a peer data operation: a protocol message sent only to our own
+/// primary device, naming the oldest message we have of a chat. The phone
+/// answers with a history sync notification of type
+/// [`HistorySyncType::OnDemandSync`] pointing to a zlib compressed
+/// `HistorySync` blob, which is downloaded, decoded and surfaced as
+/// [`Event::HistorySync`](crate::types::Event::HistorySync).
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    media::{MediaInfo, MediaType, MEDIA_HOST},
+    proto::{wa_e2e, wa_history_sync, wa_web, ProtoUtils},
+    types::{HistorySyncNotification, HistorySyncType, MessageInfo, MessageType, SendableMessage, TextMessage, JID},
+};
+use flate2::read::ZlibDecoder;
+use prost::Message as _;
+use serde::Serialize;
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Most messages the phone sends for one on-demand request
+pub const MAX_ON_DEMAND_MESSAGES: u32 = 50;
+
+/// The message older history is requested before: the oldest one we have of a chat
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryAnchor {
+    pub chat: JID,
+    /// `None` if no message of the chat is known, the timestamp alone is used then
+    pub id: Option<String>,
+    pub from_me: bool,
+    pub timestamp: SystemTime,
+}
+
+impl HistoryAnchor {
+    /// Anchor at the oldest of `messages` sent at or after `before`
+    pub fn before(chat: &JID, before: SystemTime, messages: &[MessageInfo]) -> Self {
+        let oldest = messages
+            .iter()
+            .filter(|message| message.timestamp >= before)
+            .min_by_key(|message| message.timestamp);
+        match oldest {
+            Some(message) => Self {
+                chat: chat.clone(),
+                id: Some(message.id.clone()),
+                from_me: message.from_me,
+                timestamp: message.timestamp,
+            },
+            None => Self { chat: chat.clone(), id: None, from_me: false, timestamp: before },
+        }
+    }
+}
+
+/// Messages of one chat in a history sync
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryConversation {
+    pub chat: JID,
+    /// Oldest first
+    pub messages: Vec<MessageInfo>,
+}
+
+/// Decoded history sync blob
+#[derive(Debug, Clone, Serialize)]
+pub struct HistorySyncEvent {
+    pub sync_type: HistorySyncType,
+    pub chunk_order: Option<u32>,
+    /// Percentage of the full sync done, for initial syncs
+    pub progress: Option<u32>,
+    pub conversations: Vec<HistoryConversation>,
+}
+
+/// Build the protocol message asking the phone for `count` messages older than `anchor`
+pub fn build_on_demand_request(anchor: &HistoryAnchor, count: u32) -> wa_e2e::Message {
+    let timestamp_ms = anchor.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let request = wa_e2e::PeerDataOperationRequestMessage {
+        peer_data_operation_request_type: Some(wa_e2e::PeerDataOperationRequestType::HistorySyncOnDemand as i32),
+        history_sync_on_demand_request: Some(wa_e2e::peer_data_operation_request_message::HistorySyncOnDemandRequest {
+            chat_jid: Some(anchor.chat.to_string()),
+            oldest_msg_id: anchor.id.clone(),
+            oldest_msg_from_me: Some(anchor.from_me),
+            on_demand_msg_count: Some(count.clamp(1, MAX_ON_DEMAND_MESSAGES) as i32),
+            oldest_msg_timestamp_ms: Some(timestamp_ms),
+            account_lid: None,
+        }),
+        ..Default::default()
+    };
+    wa_e2e::Message {
+        protocol_message: Some(Box::new(wa_e2e::ProtocolMessage {
+            r#type: Some(wa_e2e::protocol_message::Type::PeerDataOperationRequestMessage as i32),
+            peer_data_operation_request_message: Some(request),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+/// Wrap the encrypted copies of a peer message sent to our own devices
+pub fn peer_message_node(message_id: &str, own: &JID, participants: Node) -> Node {
+    Node::builder("message")
+        .attr("id", message_id)
+        .attr("to", own.to_non_ad())
+        .attr("type", "text")
+        .attr("category", "peer")
+        .attr("push_priority", "high_force")
+        .nodes(vec![participants])
+        .build()
+}
+
+/// Where to download the blob a history sync notification points to
+pub fn notification_media_info(notification: &HistorySyncNotification) -> MediaInfo {
+    MediaInfo::new(
+        format!("{}{}", MEDIA_HOST, notification.direct_path),
+        Some(notification.direct_path.clone()),
+        notification.media_key.clone(),
+        notification.file_sha256.clone(),
+        notification.file_enc_sha256.clone(),
+        notification.file_length,
+        "application/x-protobuf".to_string(),
+        MediaType::Document,
+    )
+}
+
+/// Inflate and decode a downloaded history sync blob
+pub fn decode_history_blob(data: &[u8]) -> Result<wa_history_sync::HistorySync> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .read_to_end(&mut inflated)
+        .map_err(|e| Error::Protocol(format!("Failed to inflate history sync blob: {}", e)))?;
+    Ok(wa_history_sync::HistorySync::decode(inflated.as_slice())?)
+}
+
+/// Convert a decoded history sync into the event surfaced to the application
+pub fn history_sync_event(history: &wa_history_sync::HistorySync) -> HistorySyncEvent {
+    let conversations = history
+        .conversations
+        .iter()
+        .filter_map(|conversation| {
+            let chat = JID::parse(&conversation.id).ok()?;
+            let mut messages: Vec<MessageInfo> = conversation
+                .messages
+                .iter()
+                .filter_map(|message| message_from_history(&chat, message.message.as_ref()?))
+                .collect();
+            messages.sort_by_key(|message| message.timestamp);
+            Some(HistoryConversation { chat, messages })
+        })
+        .collect();
+
+    HistorySyncEvent {
+        sync_type: sync_type_from_proto(history.sync_type),
+        chunk_order: history.chunk_order,
+        progress: history.progress,
+        conversations,
+    }
+}
+
+/// Read a message of a history sync, `None` if it has no id
+fn message_from_history(chat: &JID, message: &wa_web::WebMessageInfo) -> Option<MessageInfo> {
+    let id = message.key.id.clone()?;
+    let from_me = message.key.from_me.unwrap_or(false);
+    let sender = message
+        .participant
+        .as_deref()
+        .or(message.key.participant.as_deref())
+        .and_then(|participant| JID::parse(participant).ok())
+        .unwrap_or_else(|| chat.clone());
+    let text = message.message.as_ref().and_then(|content| {
+        ProtoUtils::extract_text_message(&content.encode_to_vec()).ok()
+    });
+
+    Some(MessageInfo {
+        id,
+        chat: chat.clone(),
+        sender,
+        sender_alt: None,
+        push_name: message.push_name.clone(),
+        timestamp: UNIX_EPOCH + Duration::from_secs(message.message_timestamp.unwrap_or(0)),
+        message_type: if text.is_some() { MessageType::Text } else { MessageType::Unknown },
+        from_me,
+        edited_at: None,
+        revoked: false,
+        quoted: None,
+        mentioned_jids: Vec::new(),
+        mentions_all: false,
+        group_mentions: Vec::new(),
+        forwarding_score: None,
+        content: text.map(|text| SendableMessage::Text(TextMessage { text })),
+        starred: false,
+        kept: false,
+    })
+}
+
+fn sync_type_from_proto(sync_type: i32) -> HistorySyncType {
+    use wa_history_sync::history_sync::HistorySyncType as Proto;
+    match Proto::try_from(sync_type) {
+        Ok(Proto::InitialBootstrap) => HistorySyncType::InitialBootstrap,
+        Ok(Proto::InitialStatusV3) => HistorySyncType::InitialStatusV3,
+        Ok(Proto::Full) => HistorySyncType::Full,
+        Ok(Proto::PushName) => HistorySyncType::PushName,
+        Ok(Proto::NonBlockingData) => HistorySyncType::NonBlockingData,
+        Ok(Proto::OnDemand) => HistorySyncType::OnDemandSync,
+        _ => HistorySyncType::Recent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn test_on_demand_request() {
+        let chat = JID::new_user("1234");
+        let anchor = HistoryAnchor {
+            chat: chat.clone(),
+            id: Some("OLDEST".to_string()),
+            from_me: true,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        let message = build_on_demand_request(&anchor, 500);
+        let protocol = message.protocol_message.unwrap();
+        assert_eq!(protocol.r#type, Some(wa_e2e::protocol_message::Type::PeerDataOperationRequestMessage as i32));
+        let request = protocol.peer_data_operation_request_message.unwrap().history_sync_on_demand_request.unwrap();
+        assert_eq!(request.chat_jid.as_deref(), Some("1234@s.whatsapp.net"));
+        assert_eq!(request.oldest_msg_id.as_deref(), Some("OLDEST"));
+        assert_eq!(request.on_demand_msg_count, Some(MAX_ON_DEMAND_MESSAGES as i32));
+        assert_eq!(request.oldest_msg_timestamp_ms, Some(1_700_000_000_000));
+
+        let node = peer_message_node("REQ", &chat.with_device(3), Node::new("participants".to_string()));
+        assert_eq!(node.get_attr("category").map(String::as_str), Some("peer"));
+        assert_eq!(node.get_attr("to").map(String::as_str), Some("1234@s.whatsapp.net"));
+    }
+
+    #[test]
+    fn test_decode_on_demand_blob() {
+        let history = wa_history_sync::HistorySync {
+            sync_type: wa_history_sync::history_sync::HistorySyncType::OnDemand as i32,
+            conversations: vec![wa_history_sync::Conversation {
+                id: "1234@s.whatsapp.net".to_string(),
+                messages: [("NEW", 200), ("OLD", 100)]
+                    .into_iter()
+                    .map(|(id, timestamp)| wa_history_sync::HistorySyncMsg {
+                        message: Some(wa_web::WebMessageInfo {
+                            key: ProtoUtils::create_message_key("1234@s.whatsapp.net", id, false),
+                            message: Some(ProtoUtils::create_text_message(id)),
+                            message_timestamp: Some(timestamp),
+                            ..Default::default()
+                        }),
+                        msg_order_id: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&history.encode_to_vec()).unwrap();
+        let blob = encoder.finish().unwrap();
+
+        let event = history_sync_event(&decode_history_blob(&blob).unwrap());
+        assert_eq!(event.sync_type, HistorySyncType::OnDemandSync);
+        let messages = &event.conversations[0].messages;
+        assert_eq!(messages.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["OLD", "NEW"]);
+        assert!(matches!(&messages[0].content, Some(SendableMessage::Text(text)) if text.text == "OLD"));
+
+        let anchor = HistoryAnchor::before(&event.conversations[0].chat, UNIX_EPOCH + Duration::from_secs(150), messages);
+        assert_eq!(anchor.id.as_deref(), Some("NEW"));
+        assert!(decode_history_blob(b"not zlib").is_err());
+    }
+}
@@ -11,24 +11,52 @@
 //! This is a port of the Go library [whatsmeow](https://github.com/tulir/whatsmeow)
 //! to Rust, providing async/await support and Rust ecosystem integration.
 
+pub mod anti_spam;
 pub mod appstate;
 pub mod auth;
 pub mod binary;
+pub mod blocklist;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+pub mod business;
+pub mod chat_export;
+pub mod chat_state;
 pub mod client;
 pub mod connection;
+pub mod contacts;
 pub mod database;
+pub mod decrypt_retry;
+pub mod dedup;
+pub mod devices;
+pub mod dispatch;
 pub mod error;
 pub mod group;
+pub mod history_sync;
 pub mod media;
 pub mod messaging;
+pub mod metrics;
+pub mod msg_transport;
+pub mod notification;
+pub mod prekeys;
+pub mod presence;
 pub mod proto;
+pub mod push;
+pub mod receipt_batch;
+pub mod replay;
+pub mod request;
+pub mod safety;
+pub mod server_time;
 pub mod signal;
 pub mod socket;
+pub mod stanza;
 pub mod store;
 pub mod types;
+pub mod usync;
 pub mod util;
+pub mod vcard;
+pub mod wirelog;
 
-pub use client::Client;
+pub use client::{Client, ClientBuilder};
 pub use error::{Error, Result};
 pub use types::*;
 
@@ -1,90 +1,213 @@
+//! Command line client
+//!
+//! ```text
+//! whatsmeow login [--png <path>]  pair this device by scanning a QR code
+//! whatsmeow logout               unlink this device
+//! whatsmeow send <jid> <text>     send a text message
+//! whatsmeow listen [--json]       print events until interrupted
+//! whatsmeow groups list           list the groups we participate in
+//! whatsmeow import <path>         take over the session of a Go whatsmeow database
+//! ```
+//!
+//! The session is kept in the SQLite database given with `--db`.
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{info, Level};
-use tracing_subscriber;
+use tokio::sync::mpsc;
+use tracing::Level;
 use whatsmeow::{
-    Client, 
-    auth::AuthState,
-    store::{MemoryStore, DeviceStore}, 
-    types::{Event, SendableMessage, TextMessage, JID}
+    auth::{qr::QRDisplay, LogoutOptions},
+    database::{sqlite::SqliteDeviceStore, Database, DatabaseConfig},
+    store,
+    types::{Event, JID},
+    Client,
 };
 
+#[derive(Parser)]
+#[command(name = "whatsmeow", version, about = "WhatsApp Web multidevice client")]
+struct Cli {
+    /// SQLite database holding the session
+    #[arg(long, global = true, default_value = "whatsmeow.db")]
+    db: String,
+
+    /// Log verbosely to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pair this device by scanning a QR code with the phone
+    Login {
+        /// Write the code to this PNG file instead of the terminal
+        #[arg(long)]
+        png: Option<PathBuf>,
+    },
+    /// Unlink this device and delete its credentials
+    Logout {
+        /// Keep message history, chats and contacts in the database
+        #[arg(long)]
+        keep_messages: bool,
+    },
+    /// Send a text message
+    Send {
+        /// Recipient, e.g. 1234567890@s.whatsapp.net
+        jid: JID,
+        text: String,
+    },
+    /// Print incoming events until interrupted
+    Listen {
+        /// One JSON object per line instead of readable output
+        #[arg(long)]
+        json: bool,
+    },
+    /// Group commands
+    Groups {
+        #[command(subcommand)]
+        command: GroupCommand,
+    },
+    /// Import the accounts of a database written by Go whatsmeow
+    Import {
+        /// whatsmeow SQLite database
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum GroupCommand {
+    /// List the groups we participate in
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
+async fn main() {
+    let cli = Cli::parse();
     tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
+        .with_max_level(if cli.verbose { Level::DEBUG } else { Level::WARN })
+        .with_writer(std::io::stderr)
         .init();
 
-    info!("Starting WhatsApp client...");
+    if let Err(e) = run(cli).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> whatsmeow::Result<()> {
+    let config = DatabaseConfig { database_url: format!("sqlite:{}", cli.db), ..Default::default() };
+    let database = Arc::new(Database::new(config).await?);
+    let store = Arc::new(SqliteDeviceStore::new(database.pool().clone()));
+    let new_client = || Client::new(store.clone(), database.clone());
+
+    match cli.command {
+        Command::Import { path } => {
+            let imported = store::migrate_database_from_whatsmeow(path, &database).await?;
+            println!(
+                "Imported {} device(s), {} session(s), {} contact(s)",
+                imported.devices, imported.sessions, imported.contacts
+            );
+            Ok(())
+        }
+        Command::Login { png } => login(&new_client().await?, png.map_or(QRDisplay::Terminal, QRDisplay::Png)).await,
+        Command::Logout { keep_messages } => {
+            let client = new_client().await?;
+            client.connect().await?;
+            client.logout(LogoutOptions { keep_messages }).await?;
+            println!("Logged out");
+            Ok(())
+        }
+        Command::Send { jid, text } => {
+            let client = new_client().await?;
+            client.connect().await?;
+            let id = client.send_text(&jid, text).await?;
+            println!("{}", id);
+            client.disconnect().await
+        }
+        Command::Listen { json } => listen(&new_client().await?, json).await,
+        Command::Groups { command: GroupCommand::List { json } } => {
+            let client = new_client().await?;
+            client.connect().await?;
+            for group in client.get_joined_groups().await? {
+                if json {
+                    println!("{}", serde_json::to_string(&group)?);
+                } else {
+                    println!("{}\t{}\t{} participants", group.jid, group.name, group.participants.len());
+                }
+            }
+            client.disconnect().await
+        }
+    }
+}
+
+/// Forward all client events into a channel
+async fn event_channel(client: &Client) -> mpsc::UnboundedReceiver<Event> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    client
+        .add_event_handler(Box::new(move |event| {
+            let _ = sender.send(event);
+            true
+        }))
+        .await;
+    receiver
+}
 
-    // Create a memory store
-    let store: Arc<dyn DeviceStore> = Arc::new(MemoryStore::new());
-    
-    // Create client
-    let client = Client::new(store);
-    
-    // Add event handler
-    client.add_event_handler(Box::new(|event| {
-        match event {
-            Event::Connected => info!("Connected to WhatsApp!"),
-            Event::Disconnected { reason } => info!("Disconnected: {}", reason),
-            Event::QRCode { code } => {
-                info!("QR Code generated: {}", code);
-                info!("Please scan this QR code with your WhatsApp mobile app");
+async fn login(client: &Client, display: QRDisplay) -> whatsmeow::Result<()> {
+    let mut events = event_channel(client).await;
+    client.connect().await?;
+    if client.is_logged_in() {
+        println!("Already logged in");
+        return client.disconnect().await;
+    }
+
+    // The server sends the pairing refs right after connecting
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::QRCode { code }) => {
+                    println!("Scan this code with WhatsApp > Linked devices");
+                    display.show(&code)?;
+                    if let QRDisplay::Png(path) = &display {
+                        println!("QR code written to {}", path.display());
+                    }
+                }
+                Some(Event::QRTimeout) => {
+                    return Err(whatsmeow::Error::Auth("QR code was not scanned in time".to_string()));
+                }
+                Some(Event::LoggedIn) => {
+                    println!("Logged in");
+                    break;
+                }
+                Some(Event::LoggedOut { reason }) => {
+                    return Err(whatsmeow::Error::Auth(format!("Pairing failed: {}", reason)));
+                }
+                Some(_) => {}
+                None => break,
             },
-            Event::LoggedIn => info!("Successfully logged in!"),
-            Event::Message(msg) => info!("Received message: {:?}", msg),
-            _ => info!("Received event: {:?}", event),
+            _ = tokio::signal::ctrl_c() => break,
         }
-        true // Continue processing events
-    })).await;
-    
-    // Connect (this is just a placeholder for now)
-    if let Err(e) = client.connect().await {
-        eprintln!("Failed to connect: {}", e);
-        return Ok(());
     }
-    
-    // Check authentication state
-    match client.auth_state().await {
-        AuthState::Unauthenticated => {
-            info!("Not authenticated, generating QR code...");
-            if let Ok(qr) = client.generate_qr().await {
-                info!("QR Code: {}", qr);
-            }
-        },
-        AuthState::Authenticated(_) => {
-            info!("Already authenticated!");
-            
-            // Demo: Send a test message (this won't actually work without full implementation)
-            let test_jid: JID = "1234567890@s.whatsapp.net".parse().unwrap();
-            let test_message = SendableMessage::Text(TextMessage {
-                text: "Hello from Rust WhatsApp client!".to_string(),
-            });
-            
-            if let Ok(msg_id) = client.send_message(&test_jid, test_message).await {
-                info!("Message queued with ID: {}", msg_id);
-            }
-        },
-        state => info!("Authentication state: {:?}", state),
+    client.disconnect().await
+}
+
+async fn listen(client: &Client, json: bool) -> whatsmeow::Result<()> {
+    let mut events = event_channel(client).await;
+    client.connect().await?;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) if json => println!("{}", serde_json::to_string(&event)?),
+                Some(event) => println!("{:?}", event),
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        }
     }
-    
-    info!("WhatsApp client (Rust port) initialized successfully!");
-    info!("✅ Core architecture implemented:");
-    info!("  • Binary protocol decoder/encoder with token support");
-    info!("  • Noise protocol handshake framework");
-    info!("  • Authentication flow with QR code generation");
-    info!("  • Message building and queuing system");
-    info!("  • Event-driven client architecture");
-    info!("  • Modular store abstraction");
-    info!("");
-    info!("🚧 Next steps for full functionality:");
-    info!("  • Complete Noise protocol implementation");
-    info!("  • Add proper X25519 key exchange");
-    info!("  • Implement WebSocket connection to WhatsApp servers");
-    info!("  • Add protobuf message definitions");
-    info!("  • Complete authentication handshake");
-    info!("  • Add end-to-end encryption");
-    
-    Ok(())
+    client.disconnect().await
 }
@@ -0,0 +1,228 @@
+/// Automatic download of received media
+///
+/// Like the phone's "media auto-download" settings, an
+/// [`AutoDownloadPolicy`] decides which media is fetched as soon as the
+/// message arrives. Each [`AutoDownloadRule`] filters by media type, size,
+/// kind of chat and sender; media matching any rule is downloaded in the
+/// background into the media cache and announced with
+/// [`Event::MediaDownloaded`](crate::types::Event::MediaDownloaded) carrying
+/// the local path. Bridges can then forward the file without downloading it
+/// on demand.
+
+use crate::{
+    media::{MediaInfo, MediaType},
+    types::{MediaMessage, MessageInfo, SendableMessage, JID},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Kind of chat a message arrived in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatKind {
+    Direct,
+    Group,
+    /// Status updates and broadcast lists
+    Broadcast,
+    Newsletter,
+}
+
+impl ChatKind {
+    pub fn of(chat: &JID) -> Self {
+        if chat.is_group() {
+            ChatKind::Group
+        } else if chat.is_broadcast() {
+            ChatKind::Broadcast
+        } else if chat.is_newsletter() {
+            ChatKind::Newsletter
+        } else {
+            ChatKind::Direct
+        }
+    }
+}
+
+/// Media to download automatically. Empty filters match everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AutoDownloadRule {
+    pub media_types: Vec<MediaType>,
+    /// Largest file downloaded; media of unknown size never matches a limit
+    pub max_size: Option<u64>,
+    pub chat_kinds: Vec<ChatKind>,
+    /// Senders whose media is downloaded, compared without device
+    pub senders: Vec<JID>,
+}
+
+impl AutoDownloadRule {
+    /// Whether the rule matches a media of `size` bytes received in `message`
+    pub fn matches(&self, message: &MessageInfo, media_type: &MediaType, size: Option<u64>) -> bool {
+        let type_matches = self.media_types.is_empty() || self.media_types.contains(media_type);
+        let size_matches = match self.max_size {
+            Some(max) => size.is_some_and(|size| size <= max),
+            None => true,
+        };
+        let chat_matches = self.chat_kinds.is_empty() || self.chat_kinds.contains(&ChatKind::of(&message.chat));
+        let sender = message.sender.to_non_ad();
+        let sender_matches = self.senders.is_empty() || self.senders.iter().any(|jid| jid.to_non_ad() == sender);
+        type_matches && size_matches && chat_matches && sender_matches
+    }
+}
+
+/// Rules deciding which received media is downloaded right away
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AutoDownloadPolicy {
+    /// Media matching any rule is downloaded; no rules disables auto-download
+    pub rules: Vec<AutoDownloadRule>,
+}
+
+impl AutoDownloadPolicy {
+    /// The phone's defaults on mobile data: photos everywhere, voice notes in
+    /// direct chats, nothing larger than 16 MB
+    pub fn phone_defaults() -> Self {
+        Self {
+            rules: vec![
+                AutoDownloadRule {
+                    media_types: vec![MediaType::Image, MediaType::Sticker],
+                    max_size: Some(16 * 1024 * 1024),
+                    ..Default::default()
+                },
+                AutoDownloadRule {
+                    media_types: vec![MediaType::VoiceNote],
+                    max_size: Some(16 * 1024 * 1024),
+                    chat_kinds: vec![ChatKind::Direct],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Media of a received message to download, if any rule matches it
+    pub fn media_to_download(&self, message: &MessageInfo) -> Option<MediaInfo> {
+        if message.from_me || !self.is_enabled() {
+            return None;
+        }
+        let (media_type, media) = received_media(message.content.as_ref()?)?;
+        if !self.rules.iter().any(|rule| rule.matches(message, &media_type, media.file_length)) {
+            return None;
+        }
+        media_info(media_type, media)
+    }
+}
+
+/// Received media that was downloaded automatically
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaDownloadedEvent {
+    pub chat: JID,
+    pub sender: JID,
+    pub message_id: String,
+    pub media_type: MediaType,
+    /// Location of the decrypted file in the media cache
+    pub path: PathBuf,
+}
+
+fn received_media(content: &SendableMessage) -> Option<(MediaType, &MediaMessage)> {
+    match content {
+        SendableMessage::Image(media) => Some((MediaType::Image, media)),
+        SendableMessage::Video(media) => Some((MediaType::Video, media)),
+        SendableMessage::Audio(media) => Some((MediaType::Audio, media)),
+        SendableMessage::Voice(media) => Some((MediaType::VoiceNote, media)),
+        SendableMessage::Document(media) => Some((MediaType::Document, media)),
+        SendableMessage::Sticker(media) => Some((MediaType::Sticker, media)),
+        _ => None,
+    }
+}
+
+/// Download information of a received media, `None` if it lacks the keys or hashes
+fn media_info(media_type: MediaType, media: &MediaMessage) -> Option<MediaInfo> {
+    Some(MediaInfo::new(
+        media.url.clone()?,
+        media.direct_path.clone(),
+        media.media_key.clone()?,
+        media.file_sha256.clone()?,
+        media.file_enc_sha256.clone()?,
+        media.file_length.unwrap_or(0),
+        media.mime_type.clone().unwrap_or_default(),
+        media_type,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn image(chat: JID, sender: JID, size: u64) -> MessageInfo {
+        MessageInfo {
+            id: "MSG1".to_string(),
+            chat,
+            sender,
+            sender_alt: None,
+            push_name: None,
+            timestamp: SystemTime::now(),
+            message_type: crate::types::MessageType::Image,
+            from_me: false,
+            edited_at: None,
+            revoked: false,
+            quoted: None,
+            mentioned_jids: Vec::new(),
+            mentions_all: false,
+            group_mentions: Vec::new(),
+            forwarding_score: None,
+            content: Some(SendableMessage::Image(MediaMessage {
+                url: Some("https://mmg.whatsapp.net/d/f/1".to_string()),
+                direct_path: Some("/d/f/1".to_string()),
+                media_key: Some(vec![1; 32]),
+                file_sha256: Some(vec![2; 32]),
+                file_enc_sha256: Some(vec![3; 32]),
+                file_length: Some(size),
+                mime_type: Some("image/jpeg".to_string()),
+                caption: None,
+                width: None,
+                height: None,
+                page_count: None,
+                seconds: None,
+                ptt: None,
+                gif_playback: None,
+                jpeg_thumbnail: None,
+                context_info: None,
+            })),
+            starred: false,
+            kept: false,
+        }
+    }
+
+    #[test]
+    fn test_rules_filter_type_size_chat_and_sender() {
+        let alice = JID::new_user("1111");
+        let group = JID::new_group("group");
+        let policy = AutoDownloadPolicy {
+            rules: vec![AutoDownloadRule {
+                media_types: vec![MediaType::Image],
+                max_size: Some(1000),
+                chat_kinds: vec![ChatKind::Direct],
+                senders: vec![alice.clone()],
+            }],
+        };
+
+        let info = policy.media_to_download(&image(alice.clone(), alice.with_device(2), 500)).unwrap();
+        assert_eq!(info.file_enc_sha256, vec![3; 32]);
+        assert!(policy.media_to_download(&image(alice.clone(), alice.clone(), 5000)).is_none());
+        assert!(policy.media_to_download(&image(group.clone(), alice.clone(), 500)).is_none());
+        assert!(policy.media_to_download(&image(JID::new_user("2222"), JID::new_user("2222"), 500)).is_none());
+        assert!(AutoDownloadPolicy::default().media_to_download(&image(alice.clone(), alice.clone(), 500)).is_none());
+    }
+
+    #[test]
+    fn test_phone_defaults() {
+        let policy = AutoDownloadPolicy::phone_defaults();
+        let group = JID::new_group("group");
+        let sender = JID::new_user("1111");
+        assert!(policy.media_to_download(&image(group.clone(), sender.clone(), 1024)).is_some());
+
+        let mut own = image(group, sender, 1024);
+        own.from_me = true;
+        assert!(policy.media_to_download(&own).is_none());
+    }
+}
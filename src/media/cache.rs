@@ -0,0 +1,343 @@
+/// Content-addressed disk cache of downloaded media
+///
+/// Decrypted media is stored under the hex encoded `file_enc_sha256` of the
+/// encrypted upload, which identifies the same media however often it is
+/// forwarded or sent again. Files live in a two level layout
+/// (`<dir>/ab/abcdef…`) to keep directories small. When the cache grows past
+/// its maximum size the least recently used files are deleted; recency
+/// survives restarts through the files' modification times.
+///
+/// Cached data is checked against `file_sha256` before it is returned, so a
+/// damaged file is dropped and downloaded again instead of being served.
+
+use crate::{
+    error::{Error, Result},
+    media::MediaInfo,
+    metrics,
+    util::crypto::sha256,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Default maximum size of the media cache
+pub const DEFAULT_MEDIA_CACHE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Counters of the media cache
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaCacheStats {
+    /// Files in the cache
+    pub entries: usize,
+    /// Bytes used by cached files
+    pub total_bytes: u64,
+    /// Size the cache is trimmed to
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Files deleted to make room
+    pub evictions: u64,
+}
+
+struct Entry {
+    size: u64,
+    /// Position in the recency order
+    used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    total_bytes: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let used = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.used);
+            entry.used = used;
+            self.recency.insert(used, key.to_string());
+        }
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        self.remove(&key);
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, Entry { size, used: self.clock });
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.recency.remove(&entry.used);
+        self.total_bytes -= entry.size;
+        true
+    }
+}
+
+/// Disk cache of decrypted media keyed by `file_enc_sha256`
+pub struct MediaCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<Index>,
+}
+
+impl MediaCache {
+    /// Open the cache in `dir`, creating it if needed and indexing files already there
+    pub fn open<P: AsRef<Path>>(dir: P, max_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut found = Vec::new();
+        for shard in std::fs::read_dir(&dir)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(shard.path())? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                let name = file.file_name().to_string_lossy().to_string();
+                if metadata.is_file() && is_cache_key(&name) {
+                    found.push((metadata.modified().ok(), name, metadata.len()));
+                }
+            }
+        }
+        // Oldest first so the most recently written files are evicted last
+        found.sort();
+
+        let mut index = Index::default();
+        for (_, key, size) in found {
+            index.insert(key, size);
+        }
+        debug!("Opened media cache in {} with {} files", dir.display(), index.entries.len());
+        Ok(Self { dir, max_bytes, index: Mutex::new(index) })
+    }
+
+    /// Cached data of a media, `None` if it is not cached
+    pub async fn get(&self, media_info: &MediaInfo) -> Result<Option<Vec<u8>>> {
+        let Some(key) = cache_key(media_info) else {
+            return Ok(None);
+        };
+        if !self.index.lock().unwrap().entries.contains_key(&key) {
+            self.record_miss();
+            return Ok(None);
+        }
+
+        let data = match tokio::fs::read(self.path(&key)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.index.lock().unwrap().remove(&key);
+                self.record_miss();
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !media_info.file_sha256.is_empty() && sha256(&data) != media_info.file_sha256 {
+            warn!("Dropping damaged cached media {}", key);
+            self.remove_file(&key).await?;
+            self.record_miss();
+            return Ok(None);
+        }
+
+        let mut index = self.index.lock().unwrap();
+        index.touch(&key);
+        index.hits += 1;
+        metrics::cache_hit("media");
+        Ok(Some(data))
+    }
+
+    /// Store the decrypted data of a media, evicting old files if the cache is full
+    pub async fn put(&self, media_info: &MediaInfo, data: &[u8]) -> Result<()> {
+        let Some(key) = cache_key(media_info) else {
+            return Ok(());
+        };
+        if data.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
+
+        // Write under a temporary name so readers never see a partial file
+        let path = self.path(&key);
+        if let Some(shard) = path.parent() {
+            tokio::fs::create_dir_all(shard).await?;
+        }
+        let partial = path.with_extension("part");
+        tokio::fs::write(&partial, data).await?;
+        tokio::fs::rename(&partial, &path).await?;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(key.clone(), data.len() as u64);
+            let mut evicted = Vec::new();
+            while index.total_bytes > self.max_bytes {
+                let Some((_, oldest)) = index.recency.pop_first() else {
+                    break;
+                };
+                if let Some(entry) = index.entries.remove(&oldest) {
+                    index.total_bytes -= entry.size;
+                }
+                index.evictions += 1;
+                evicted.push(oldest);
+            }
+            evicted
+        };
+        for key in evicted {
+            self.delete(&key).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether a media is cached, without reading it
+    pub fn contains(&self, media_info: &MediaInfo) -> bool {
+        cache_key(media_info).is_some_and(|key| self.index.lock().unwrap().entries.contains_key(&key))
+    }
+
+    /// Location of a cached media on disk
+    pub fn path_of(&self, media_info: &MediaInfo) -> Option<PathBuf> {
+        let key = cache_key(media_info)?;
+        self.index.lock().unwrap().entries.contains_key(&key).then(|| self.path(&key))
+    }
+
+    /// Delete a cached media
+    pub async fn remove(&self, media_info: &MediaInfo) -> Result<bool> {
+        match cache_key(media_info) {
+            Some(key) => self.remove_file(&key).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Delete all cached media, keeping the counters
+    pub async fn clear(&self) -> Result<()> {
+        let keys: Vec<String> = {
+            let mut index = self.index.lock().unwrap();
+            index.recency.clear();
+            index.total_bytes = 0;
+            index.entries.drain().map(|(key, _)| key).collect()
+        };
+        for key in keys {
+            self.delete(&key).await?;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> MediaCacheStats {
+        let index = self.index.lock().unwrap();
+        MediaCacheStats {
+            entries: index.entries.len(),
+            total_bytes: index.total_bytes,
+            max_bytes: self.max_bytes,
+            hits: index.hits,
+            misses: index.misses,
+            evictions: index.evictions,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+
+    fn record_miss(&self) {
+        self.index.lock().unwrap().misses += 1;
+        metrics::cache_miss("media");
+    }
+
+    async fn remove_file(&self, key: &str) -> Result<bool> {
+        let removed = self.index.lock().unwrap().remove(key);
+        self.delete(key).await?;
+        Ok(removed)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+}
+
+/// Hex encoded `file_enc_sha256`, `None` if the media has none
+fn cache_key(media_info: &MediaInfo) -> Option<String> {
+    (media_info.file_enc_sha256.len() == 32).then(|| hex::encode(&media_info.file_enc_sha256))
+}
+
+fn is_cache_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::MediaType;
+    use tempfile::TempDir;
+
+    fn media(data: &[u8], enc_hash: u8) -> MediaInfo {
+        MediaInfo::new(
+            "https://mmg.whatsapp.net/d/f/test".to_string(),
+            None,
+            vec![0; 32],
+            sha256(data).to_vec(),
+            vec![enc_hash; 32],
+            data.len() as u64,
+            "image/jpeg".to_string(),
+            MediaType::Image,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_get_put_and_lru_eviction() {
+        let dir = TempDir::new().unwrap();
+        let cache = MediaCache::open(dir.path(), 10).unwrap();
+        let (a, b, c) = (media(b"aaaa", 1), media(b"bbbb", 2), media(b"cccc", 3));
+
+        assert!(cache.get(&a).await.unwrap().is_none());
+        cache.put(&a, b"aaaa").await.unwrap();
+        cache.put(&b, b"bbbb").await.unwrap();
+        assert_eq!(cache.get(&a).await.unwrap().as_deref(), Some(&b"aaaa"[..]));
+
+        // "b" was used least recently
+        cache.put(&c, b"cccc").await.unwrap();
+        assert!(cache.contains(&a) && !cache.contains(&b) && cache.contains(&c));
+        assert!(!dir.path().join("02").join(hex::encode([2u8; 32])).exists());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.total_bytes, stats.evictions), (2, 8, 1));
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        // The index is rebuilt from disk
+        let reopened = MediaCache::open(dir.path(), 10).unwrap();
+        assert_eq!(reopened.stats().entries, 2);
+        assert_eq!(reopened.get(&c).await.unwrap().as_deref(), Some(&b"cccc"[..]));
+        assert_eq!(std::fs::read(reopened.path_of(&c).unwrap()).unwrap(), b"cccc");
+        assert!(reopened.path_of(&b).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_damaged_file_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let cache = MediaCache::open(dir.path(), 1024).unwrap();
+        let a = media(b"aaaa", 1);
+        cache.put(&a, b"aaaa").await.unwrap();
+        std::fs::write(cache.path(&hex::encode([1u8; 32])), b"evil").unwrap();
+
+        assert!(cache.get(&a).await.unwrap().is_none());
+        assert!(!cache.contains(&a));
+        cache.clear().await.unwrap();
+        assert_eq!(cache.stats().total_bytes, 0);
+    }
+}
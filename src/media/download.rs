@@ -3,6 +3,7 @@
 use crate::{
     error::{Error, Result},
     media::{MediaInfo, ProgressInfo},
+    socket::ProxyConfig,
     util::crypto::{sha256, AesGcm},
 };
 use std::path::Path;
@@ -28,6 +29,8 @@ pub struct DownloadConfig {
     pub max_retries: u32,
     /// Retry delay in seconds
     pub retry_delay_seconds: u64,
+    /// Proxy for download requests
+    pub proxy: Option<ProxyConfig>,
 }
 
 impl Default for DownloadConfig {
@@ -40,6 +43,7 @@ impl Default for DownloadConfig {
             user_agent: "WhatsApp/0.1.0".to_string(),
             max_retries: 3,
             retry_delay_seconds: 2,
+            proxy: None,
         }
     }
 }
@@ -141,9 +145,13 @@ pub struct MediaDownloader {
 impl MediaDownloader {
     /// Create new media downloader
     pub fn new(config: DownloadConfig) -> Self {
-        let http_client = reqwest::Client::builder()
+        let mut builder = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(config.timeout_seconds))
-            .user_agent(&config.user_agent)
+            .user_agent(&config.user_agent);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy.to_reqwest_proxy().expect("Invalid proxy configuration"));
+        }
+        let http_client = builder
             .build()
             .expect("Failed to create HTTP client");
         
@@ -181,6 +189,7 @@ impl MediaDownloader {
         loop {
             match self.try_download(media_info).await {
                 Ok(data) => return Ok(data),
+                Err(Error::MediaExpired) => return Err(Error::MediaExpired),
                 Err(e) => {
                     retry_count += 1;
                     if retry_count >= self.config.max_retries {
@@ -269,6 +278,10 @@ impl MediaDownloader {
             .await
             .map_err(|e| Error::Protocol(format!("Download request failed: {}", e)))?;
         
+        // The CDN drops media after a while; the sender has to upload it again
+        if matches!(response.status(), reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) {
+            return Err(Error::MediaExpired);
+        }
         if !response.status().is_success() {
             return Err(Error::Protocol(format!(
                 "Download failed with status: {}",
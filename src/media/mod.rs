@@ -5,18 +5,29 @@ pub mod upload;
 pub mod download;
 pub mod processing;
 pub mod encryption;
+pub mod sticker;
+pub mod cache;
+pub mod auto_download;
+pub mod retry;
 
 use crate::{
     error::{Error, Result},
+    metrics::{self, MediaDirection},
+    socket::ProxyConfig,
 };
 use std::path::Path;
 use std::collections::HashMap;
+use tracing::warn;
 
 pub use types::*;
 pub use upload::*;
 pub use download::*;
 pub use processing::*;
 pub use encryption::*;
+pub use sticker::*;
+pub use cache::{MediaCache, MediaCacheStats, DEFAULT_MEDIA_CACHE_SIZE};
+pub use auto_download::{AutoDownloadPolicy, AutoDownloadRule, ChatKind, MediaDownloadedEvent};
+pub use retry::{MediaRetryEvent, MediaRetryOutcome};
 
 /// Media manager for handling all media operations
 pub struct MediaManager {
@@ -30,6 +41,8 @@ pub struct MediaManager {
     active_downloads: HashMap<String, DownloadSession>,
     /// Media cache directory
     cache_directory: Option<String>,
+    /// Downloaded media kept in the cache directory
+    cache: Option<MediaCache>,
 }
 
 impl MediaManager {
@@ -41,17 +54,27 @@ impl MediaManager {
             active_uploads: HashMap::new(),
             active_downloads: HashMap::new(),
             cache_directory: None,
+            cache: None,
         }
     }
     
     /// Create media manager with custom cache directory
     pub fn with_cache_dir<P: AsRef<Path>>(cache_dir: P) -> Self {
+        Self::with_cache(cache_dir, DEFAULT_MEDIA_CACHE_SIZE)
+    }
+    
+    /// Create media manager caching up to `max_bytes` of downloaded media in `cache_dir`
+    pub fn with_cache<P: AsRef<Path>>(cache_dir: P, max_bytes: u64) -> Self {
+        let cache = MediaCache::open(&cache_dir, max_bytes)
+            .inspect_err(|e| warn!("Media cache disabled, failed to open {}: {}", cache_dir.as_ref().display(), e))
+            .ok();
         Self {
             upload_config: UploadConfig::default(),
             download_config: DownloadConfig::default(),
             active_uploads: HashMap::new(),
             active_downloads: HashMap::new(),
             cache_directory: Some(cache_dir.as_ref().to_string_lossy().to_string()),
+            cache,
         }
     }
     
@@ -65,10 +88,17 @@ impl MediaManager {
         self.download_config = config;
     }
     
+    /// Route upload and download requests through the given proxy
+    pub fn set_proxy(&mut self, proxy: Option<ProxyConfig>) {
+        self.upload_config.proxy = proxy.clone();
+        self.download_config.proxy = proxy;
+    }
+    
     /// Upload media file and get media info for message
     pub async fn upload_media<P: AsRef<Path>>(&mut self, file_path: P, media_type: MediaType) -> Result<MediaInfo> {
         let uploader = MediaUploader::new(self.upload_config.clone());
         let media_info = uploader.upload_file(file_path, media_type).await?;
+        metrics::media_bytes(MediaDirection::Upload, media_info.file_length);
         Ok(media_info)
     }
     
@@ -76,23 +106,64 @@ impl MediaManager {
     pub async fn upload_media_bytes(&mut self, data: &[u8], filename: &str, media_type: MediaType) -> Result<MediaInfo> {
         let uploader = MediaUploader::new(self.upload_config.clone());
         let media_info = uploader.upload_bytes(data, filename, media_type).await?;
+        metrics::media_bytes(MediaDirection::Upload, data.len() as u64);
         Ok(media_info)
     }
     
     /// Download media to file
     pub async fn download_media<P: AsRef<Path>>(&mut self, media_info: &MediaInfo, output_path: P) -> Result<()> {
-        let downloader = MediaDownloader::new(self.download_config.clone());
-        downloader.download_to_file(media_info, output_path).await?;
+        if self.cache.is_none() {
+            let downloader = MediaDownloader::new(self.download_config.clone());
+            downloader.download_to_file(media_info, output_path).await?;
+            metrics::media_bytes(MediaDirection::Download, media_info.file_length);
+            return Ok(());
+        }
+        let data = self.download_media_bytes(media_info).await?;
+        tokio::fs::write(output_path, data).await?;
         Ok(())
     }
     
-    /// Download media to bytes
+    /// Download media to bytes, served from the cache if it was downloaded before
     pub async fn download_media_bytes(&mut self, media_info: &MediaInfo) -> Result<Vec<u8>> {
+        if let Some(data) = self.get_cached(media_info).await {
+            return Ok(data);
+        }
+        
         let downloader = MediaDownloader::new(self.download_config.clone());
         let data = downloader.download_to_bytes(media_info).await?;
+        metrics::media_bytes(MediaDirection::Download, data.len() as u64);
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.put(media_info, &data).await {
+                warn!("Failed to cache downloaded media: {}", e);
+            }
+        }
         Ok(data)
     }
     
+    /// Cached data of a media downloaded before, without going to the network
+    pub async fn get_cached(&self, media_info: &MediaInfo) -> Option<Vec<u8>> {
+        let cache = self.cache.as_ref()?;
+        cache.get(media_info).await
+            .inspect_err(|e| warn!("Failed to read cached media: {}", e))
+            .ok()
+            .flatten()
+    }
+    
+    /// Location of a cached media on disk
+    pub fn cached_path(&self, media_info: &MediaInfo) -> Option<std::path::PathBuf> {
+        self.cache.as_ref()?.path_of(media_info)
+    }
+    
+    /// Whether downloads are kept in a cache directory
+    pub fn has_cache(&self) -> bool {
+        self.cache.is_some()
+    }
+    
+    /// Statistics of the media cache, `None` without a cache directory
+    pub fn cache_stats(&self) -> Option<MediaCacheStats> {
+        self.cache.as_ref().map(MediaCache::stats)
+    }
+    
     /// Create image message
     pub async fn create_image_message<P: AsRef<Path>>(&mut self, file_path: P, caption: Option<String>) -> Result<MediaMessage> {
         // Process image to generate thumbnail
@@ -248,6 +319,9 @@ impl MediaManager {
     
     /// Clear cache directory
     pub async fn clear_cache(&self) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            cache.clear().await?;
+        }
         if let Some(cache_dir) = &self.cache_directory {
             tokio::fs::remove_dir_all(cache_dir).await
                 .map_err(|e| Error::from(e))?;
@@ -0,0 +1,222 @@
+/// Media retry protocol
+///
+/// Media stays on the CDN for a limited time only, after that downloads fail
+/// with [`Error::MediaExpired`]. The recipient can then ask the sender's phone
+/// to upload the file again: it sends a `server-error` receipt for the
+/// message carrying an encrypted `ServerErrorReceipt`, and the server answers
+/// with a `mediaretry` notification holding an encrypted
+/// `MediaRetryNotification` with the new direct path. Both payloads are
+/// encrypted with AES-GCM under a key derived from the media key, with the
+/// message id as associated data.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    proto::wa_mms_retry::{media_retry_notification::ResultType, MediaRetryNotification, ServerErrorReceipt},
+    types::{MessageInfo, JID},
+    util::crypto::{hkdf_expand, random_bytes, AesGcm},
+};
+use prost::Message as _;
+use serde::Serialize;
+use std::time::Duration;
+
+/// How long to wait for the sender's phone to upload the media again
+pub const MEDIA_RETRY_TIMEOUT: Duration = Duration::from_secs(60);
+
+const MEDIA_RETRY_INFO: &[u8] = b"WhatsApp Media Retry Notification";
+
+/// Key the retry payloads of a media are encrypted with
+pub fn media_retry_key(media_key: &[u8]) -> Result<Vec<u8>> {
+    hkdf_expand(media_key, MEDIA_RETRY_INFO, 32)
+}
+
+/// Build the receipt asking the sender of `message` to upload its media again
+pub fn build_retry_receipt(message: &MessageInfo, own: &JID, media_key: &[u8]) -> Result<Node> {
+    let receipt = ServerErrorReceipt { stanza_id: Some(message.id.clone()) }.encode_to_vec();
+    let iv = random_bytes(12);
+    let ciphertext = AesGcm::new(&media_retry_key(media_key)?)?.encrypt_with_aad(&iv, &receipt, message.id.as_bytes())?;
+
+    let mut rmr = Node::builder("rmr").attr("jid", &message.chat).attr("from_me", message.from_me);
+    if message.chat.is_group() {
+        rmr = rmr.attr("participant", message.sender.to_non_ad());
+    }
+    Ok(Node::builder("receipt")
+        .attr("id", &message.id)
+        .attr("to", own.to_non_ad())
+        .attr("type", "server-error")
+        .nodes(vec![
+            Node::builder("encrypt")
+                .nodes(vec![
+                    Node::builder("enc_p").bytes(ciphertext).build(),
+                    Node::builder("enc_iv").bytes(iv).build(),
+                ])
+                .build(),
+            rmr.build(),
+        ])
+        .build())
+}
+
+/// What the sender's phone answered to a media retry request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum MediaRetryOutcome {
+    /// The media was uploaded again to `direct_path`
+    Uploaded { direct_path: String },
+    /// The phone no longer has the media
+    NotFound,
+    /// The phone could not decrypt the request
+    DecryptionError,
+    GeneralError,
+    /// The server refused the request
+    ServerError { code: u16 },
+}
+
+/// Result of a media retry request, from a `mediaretry` notification
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MediaRetryEvent {
+    pub message_id: String,
+    pub chat: Option<JID>,
+    pub from_me: bool,
+    /// Sender of the message in a group
+    pub participant: Option<JID>,
+    pub outcome: MediaRetryOutcome,
+}
+
+/// Message id a `mediaretry` notification answers
+pub fn media_retry_message_id(node: &Node) -> Option<&str> {
+    if node.tag != "notification" || node.get_attr("type").map(String::as_str) != Some("mediaretry") {
+        return None;
+    }
+    node.get_attr("id").map(String::as_str)
+}
+
+/// Decrypt a `mediaretry` notification with the media key of the message
+pub fn parse_media_retry_notification(node: &Node, media_key: &[u8]) -> Result<MediaRetryEvent> {
+    let message_id = media_retry_message_id(node)
+        .ok_or_else(|| Error::Protocol("Not a mediaretry notification".to_string()))?
+        .to_string();
+    let rmr = node.find_child("rmr").ok_or_else(|| Error::ElementMissing("rmr".to_string()))?;
+    let mut event = MediaRetryEvent {
+        message_id,
+        chat: rmr.get_attr("jid").and_then(|jid| jid.parse().ok()),
+        from_me: rmr.get_attr("from_me").map(String::as_str) == Some("true"),
+        participant: rmr.get_attr("participant").and_then(|jid| jid.parse().ok()),
+        outcome: MediaRetryOutcome::GeneralError,
+    };
+
+    if let Some(error) = node.find_child("error") {
+        let code = error.get_attr("code").and_then(|code| code.parse().ok()).unwrap_or(0);
+        event.outcome = MediaRetryOutcome::ServerError { code };
+        return Ok(event);
+    }
+
+    let encrypted = node.find_child("encrypt").ok_or_else(|| Error::ElementMissing("encrypt".to_string()))?;
+    let payload = encrypted.find_child("enc_p").and_then(Node::get_binary);
+    let iv = encrypted.find_child("enc_iv").and_then(Node::get_binary);
+    let (Some(payload), Some(iv)) = (payload, iv) else {
+        return Err(Error::ElementMissing("enc_p or enc_iv".to_string()));
+    };
+    let plaintext = AesGcm::new(&media_retry_key(media_key)?)?.decrypt_with_aad(iv, payload, event.message_id.as_bytes())?;
+    let notification = MediaRetryNotification::decode(plaintext.as_slice())?;
+
+    event.outcome = match ResultType::try_from(notification.result.unwrap_or_default()) {
+        Ok(ResultType::Success) => match notification.direct_path {
+            Some(direct_path) if !direct_path.is_empty() => MediaRetryOutcome::Uploaded { direct_path },
+            _ => MediaRetryOutcome::GeneralError,
+        },
+        Ok(ResultType::NotFound) => MediaRetryOutcome::NotFound,
+        Ok(ResultType::DecryptionError) => MediaRetryOutcome::DecryptionError,
+        _ => MediaRetryOutcome::GeneralError,
+    };
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn message(chat: JID, sender: JID) -> MessageInfo {
+        MessageInfo {
+            id: "MSG1".to_string(),
+            chat,
+            sender,
+            sender_alt: None,
+            push_name: None,
+            timestamp: SystemTime::now(),
+            message_type: crate::types::MessageType::Image,
+            from_me: false,
+            edited_at: None,
+            revoked: false,
+            quoted: None,
+            mentioned_jids: Vec::new(),
+            mentions_all: false,
+            group_mentions: Vec::new(),
+            forwarding_score: None,
+            content: None,
+            starred: false,
+            kept: false,
+        }
+    }
+
+    fn notification(media_key: &[u8], result: ResultType, direct_path: Option<&str>) -> Node {
+        let plaintext = MediaRetryNotification {
+            stanza_id: Some("MSG1".to_string()),
+            direct_path: direct_path.map(str::to_string),
+            result: Some(result as i32),
+            message_secret: None,
+        }
+        .encode_to_vec();
+        let iv = vec![7; 12];
+        let key = media_retry_key(media_key).unwrap();
+        let payload = AesGcm::new(&key).unwrap().encrypt_with_aad(&iv, &plaintext, b"MSG1").unwrap();
+        Node::builder("notification")
+            .attr("type", "mediaretry")
+            .attr("id", "MSG1")
+            .nodes(vec![
+                Node::builder("rmr").attr("jid", "1-2@g.us").attr("from_me", "false").attr("participant", "1111@s.whatsapp.net").build(),
+                Node::builder("encrypt")
+                    .nodes(vec![Node::builder("enc_p").bytes(payload).build(), Node::builder("enc_iv").bytes(iv).build()])
+                    .build(),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn test_retry_receipt() {
+        let media_key = vec![9; 32];
+        let group = JID::new_group("1-2");
+        let receipt = build_retry_receipt(&message(group, JID::new_user("1111").with_device(3)), &JID::new_user("2222").with_device(5), &media_key).unwrap();
+        assert_eq!(receipt.get_attr("type").map(String::as_str), Some("server-error"));
+        assert_eq!(receipt.get_attr("to").map(String::as_str), Some("2222@s.whatsapp.net"));
+        let rmr = receipt.find_child("rmr").unwrap();
+        assert_eq!(rmr.get_attr("participant").map(String::as_str), Some("1111@s.whatsapp.net"));
+
+        let encrypted = receipt.find_child("encrypt").unwrap();
+        let payload = encrypted.find_child("enc_p").and_then(Node::get_binary).unwrap();
+        let iv = encrypted.find_child("enc_iv").and_then(Node::get_binary).unwrap();
+        let key = media_retry_key(&media_key).unwrap();
+        let plaintext = AesGcm::new(&key).unwrap().decrypt_with_aad(iv, payload, b"MSG1").unwrap();
+        assert_eq!(ServerErrorReceipt::decode(plaintext.as_slice()).unwrap().stanza_id.as_deref(), Some("MSG1"));
+        assert!(AesGcm::new(&key).unwrap().decrypt_with_aad(iv, payload, b"OTHER").is_err());
+    }
+
+    #[test]
+    fn test_parse_notification() {
+        let media_key = vec![9; 32];
+        let event = parse_media_retry_notification(&notification(&media_key, ResultType::Success, Some("/v/t62/new")), &media_key).unwrap();
+        assert_eq!(event.outcome, MediaRetryOutcome::Uploaded { direct_path: "/v/t62/new".to_string() });
+        assert_eq!(event.participant, Some(JID::new_user("1111")));
+
+        let event = parse_media_retry_notification(&notification(&media_key, ResultType::NotFound, None), &media_key).unwrap();
+        assert_eq!(event.outcome, MediaRetryOutcome::NotFound);
+        assert!(parse_media_retry_notification(&notification(&media_key, ResultType::Success, None), &[1; 32]).is_err());
+
+        let error = Node::builder("notification")
+            .attr("type", "mediaretry")
+            .attr("id", "MSG1")
+            .nodes(vec![Node::builder("rmr").attr("jid", "1111@s.whatsapp.net").build(), Node::builder("error").attr("code", 2).build()])
+            .build();
+        assert_eq!(parse_media_retry_notification(&error, &media_key).unwrap().outcome, MediaRetryOutcome::ServerError { code: 2 });
+    }
+}
@@ -0,0 +1,322 @@
+/// Sticker metadata and WebP container handling
+///
+/// Stickers are WebP images of at most 512x512 pixels. WhatsApp keeps the
+/// sticker pack they belong to and the emojis they express in an EXIF chunk
+/// of the WebP container: a minimal little endian TIFF header with a single
+/// IFD entry (tag `0x5741`) pointing at a JSON document. Adding that chunk
+/// requires the extended (`VP8X`) WebP format, so simple files are
+/// converted to it on the way.
+
+use crate::{
+    error::{Error, Result},
+    proto::wa_e2e,
+    types::StickerMessage,
+};
+use serde::{Deserialize, Serialize};
+
+/// Largest side length of a sticker
+pub const MAX_STICKER_SIZE: u32 = 512;
+
+/// IFD tag of the sticker JSON in the EXIF blob
+const STICKER_EXIF_TAG: u16 = 0x5741;
+
+/// VP8X flag: the file has an EXIF chunk
+const VP8X_FLAG_EXIF: u8 = 0x08;
+/// VP8X flag: the file has an alpha channel
+const VP8X_FLAG_ALPHA: u8 = 0x10;
+/// VP8X flag: the file is animated
+const VP8X_FLAG_ANIMATION: u8 = 0x02;
+
+/// Pack and emoji information embedded in a sticker
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StickerMetadata {
+    #[serde(rename = "sticker-pack-id", default)]
+    pub pack_id: String,
+    #[serde(rename = "sticker-pack-name", default)]
+    pub pack_name: String,
+    #[serde(rename = "sticker-pack-publisher", default)]
+    pub publisher: String,
+    /// Emojis the sticker is suggested for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emojis: Vec<String>,
+}
+
+impl StickerMetadata {
+    /// Metadata for a sticker of the given pack
+    pub fn new(pack_name: impl Into<String>, publisher: impl Into<String>) -> Self {
+        Self {
+            pack_id: uuid::Uuid::new_v4().to_string(),
+            pack_name: pack_name.into(),
+            publisher: publisher.into(),
+            emojis: Vec::new(),
+        }
+    }
+
+    /// Associate emojis with the sticker
+    pub fn with_emojis(mut self, emojis: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.emojis.extend(emojis.into_iter().map(Into::into));
+        self
+    }
+}
+
+/// A chunk of a RIFF container
+struct Chunk<'a> {
+    fourcc: [u8; 4],
+    data: &'a [u8],
+}
+
+fn parse_chunks(webp: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    if webp.len() < 12 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(Error::Protocol("Sticker must be a WebP image".to_string()));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= webp.len() {
+        let fourcc = [webp[pos], webp[pos + 1], webp[pos + 2], webp[pos + 3]];
+        let size = u32::from_le_bytes([webp[pos + 4], webp[pos + 5], webp[pos + 6], webp[pos + 7]]) as usize;
+        let data = webp
+            .get(pos + 8..pos + 8 + size)
+            .ok_or_else(|| Error::Protocol("Truncated WebP chunk".to_string()))?;
+        chunks.push(Chunk { fourcc, data });
+        // Chunks are padded to an even size
+        pos += 8 + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+fn write_chunks(chunks: &[Chunk<'_>]) -> Vec<u8> {
+    let mut body = b"WEBP".to_vec();
+    for chunk in chunks {
+        body.extend_from_slice(&chunk.fourcc);
+        body.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(chunk.data);
+        if chunk.data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+
+    let mut webp = b"RIFF".to_vec();
+    webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    webp.extend_from_slice(&body);
+    webp
+}
+
+/// Canvas size and alpha of a simple (`VP8 ` or `VP8L`) WebP image
+fn image_info(chunk: &Chunk<'_>) -> Option<(u32, u32, bool)> {
+    match &chunk.fourcc {
+        b"VP8 " => {
+            let data = chunk.data.get(..10)?;
+            if data[3..6] != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let width = u16::from_le_bytes([data[6], data[7]]) & 0x3FFF;
+            let height = u16::from_le_bytes([data[8], data[9]]) & 0x3FFF;
+            Some((width as u32, height as u32, false))
+        }
+        b"VP8L" => {
+            let data = chunk.data.get(..5)?;
+            if data[0] != 0x2F {
+                return None;
+            }
+            let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, bits & (1 << 28) != 0))
+        }
+        _ => None,
+    }
+}
+
+/// Size of the sticker canvas
+pub fn sticker_dimensions(webp: &[u8]) -> Result<(u32, u32)> {
+    let chunks = parse_chunks(webp)?;
+    let first = chunks.first().ok_or_else(|| Error::Protocol("Empty WebP image".to_string()))?;
+    if &first.fourcc == b"VP8X" && first.data.len() >= 10 {
+        let d = first.data;
+        let width = u32::from_le_bytes([d[4], d[5], d[6], 0]) + 1;
+        let height = u32::from_le_bytes([d[7], d[8], d[9], 0]) + 1;
+        return Ok((width, height));
+    }
+    image_info(first)
+        .map(|(width, height, _)| (width, height))
+        .ok_or_else(|| Error::Protocol("Unsupported WebP image".to_string()))
+}
+
+/// Whether the WebP image is animated
+pub fn is_animated_webp(webp: &[u8]) -> bool {
+    parse_chunks(webp).is_ok_and(|chunks| {
+        chunks.iter().any(|chunk| {
+            &chunk.fourcc == b"ANIM"
+                || (&chunk.fourcc == b"VP8X" && chunk.data.first().is_some_and(|f| f & VP8X_FLAG_ANIMATION != 0))
+        })
+    })
+}
+
+/// Build the EXIF blob holding the sticker JSON
+fn sticker_exif(metadata: &StickerMetadata) -> Result<Vec<u8>> {
+    let json = serde_json::to_vec(metadata)?;
+    // TIFF header, one IFD entry of type UNDEFINED whose value follows the IFD
+    let mut exif = vec![0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01, 0x00];
+    exif.extend_from_slice(&STICKER_EXIF_TAG.to_le_bytes());
+    exif.extend_from_slice(&7u16.to_le_bytes());
+    exif.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    exif.extend_from_slice(&22u32.to_le_bytes());
+    exif.extend_from_slice(&json);
+    Ok(exif)
+}
+
+/// Embed sticker metadata into a WebP image, replacing any existing EXIF data
+pub fn inject_sticker_metadata(webp: &[u8], metadata: &StickerMetadata) -> Result<Vec<u8>> {
+    let mut chunks: Vec<Chunk<'_>> = parse_chunks(webp)?
+        .into_iter()
+        .filter(|chunk| &chunk.fourcc != b"EXIF")
+        .collect();
+    let first = chunks.first().ok_or_else(|| Error::Protocol("Empty WebP image".to_string()))?;
+
+    let vp8x = if &first.fourcc == b"VP8X" {
+        let mut header = first.data.to_vec();
+        header[0] |= VP8X_FLAG_EXIF;
+        chunks.remove(0);
+        header
+    } else {
+        let (width, height, alpha) = image_info(first)
+            .ok_or_else(|| Error::Protocol("Unsupported WebP image".to_string()))?;
+        let alpha = alpha || chunks.iter().any(|chunk| &chunk.fourcc == b"ALPH");
+        let mut header = vec![VP8X_FLAG_EXIF | if alpha { VP8X_FLAG_ALPHA } else { 0 }, 0, 0, 0];
+        header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        header
+    };
+    let exif = sticker_exif(metadata)?;
+
+    chunks.insert(0, Chunk { fourcc: *b"VP8X", data: &vp8x });
+    chunks.push(Chunk { fourcc: *b"EXIF", data: &exif });
+    Ok(write_chunks(&chunks))
+}
+
+/// Read the sticker metadata embedded in a WebP image
+pub fn read_sticker_metadata(webp: &[u8]) -> Option<StickerMetadata> {
+    let chunks = parse_chunks(webp).ok()?;
+    let exif = chunks.iter().find(|chunk| &chunk.fourcc == b"EXIF")?.data;
+    if exif.get(..4)? != [0x49, 0x49, 0x2A, 0x00] {
+        return None;
+    }
+
+    let ifd = u32::from_le_bytes(exif.get(4..8)?.try_into().ok()?) as usize;
+    let entries = u16::from_le_bytes(exif.get(ifd..ifd + 2)?.try_into().ok()?) as usize;
+    (0..entries).find_map(|i| {
+        let entry = exif.get(ifd + 2 + i * 12..ifd + 14 + i * 12)?;
+        if u16::from_le_bytes([entry[0], entry[1]]) != STICKER_EXIF_TAG {
+            return None;
+        }
+        let count = u32::from_le_bytes(entry[4..8].try_into().ok()?) as usize;
+        let offset = u32::from_le_bytes(entry[8..12].try_into().ok()?) as usize;
+        serde_json::from_slice(exif.get(offset..offset + count)?).ok()
+    })
+}
+
+/// Check that an image can be sent as a sticker, returning whether it is animated
+pub fn validate_sticker(webp: &[u8]) -> Result<bool> {
+    let (width, height) = sticker_dimensions(webp)?;
+    if width != height {
+        return Err(Error::Protocol("Sticker must be square".to_string()));
+    }
+    if width > MAX_STICKER_SIZE {
+        return Err(Error::Protocol(format!(
+            "Sticker size must not exceed {0}x{0}",
+            MAX_STICKER_SIZE
+        )));
+    }
+    Ok(is_animated_webp(webp))
+}
+
+/// Extract the sticker from a decrypted message, if it is one
+pub fn parse_sticker_message(message: &wa_e2e::Message) -> Option<StickerMessage> {
+    let sticker = message.sticker_message.as_deref()?;
+    Some(StickerMessage {
+        url: sticker.url.clone(),
+        direct_path: sticker.direct_path.clone(),
+        media_key: sticker.media_key.clone(),
+        file_sha256: sticker.file_sha256.clone(),
+        file_enc_sha256: sticker.file_enc_sha256.clone(),
+        file_length: sticker.file_length,
+        mime_type: sticker.mimetype.clone(),
+        width: sticker.width,
+        height: sticker.height,
+        is_animated: sticker.is_animated.unwrap_or(false),
+        is_avatar: sticker.is_avatar.unwrap_or(false),
+        is_lottie: sticker.is_lottie.unwrap_or(false),
+        accessibility_label: sticker.accessibility_label.clone(),
+        png_thumbnail: sticker.png_thumbnail.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A lossless 64x64 WebP header, enough for container handling
+    fn vp8l_webp(size: u32, alpha: bool) -> Vec<u8> {
+        let bits = (size - 1) | ((size - 1) << 14) | if alpha { 1 << 28 } else { 0 };
+        let mut data = vec![0x2F];
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.extend_from_slice(&[0xAA; 4]);
+        write_chunks(&[Chunk { fourcc: *b"VP8L", data: &data }])
+    }
+
+    #[test]
+    fn test_inject_and_read_metadata() {
+        let webp = vp8l_webp(64, true);
+        assert_eq!(sticker_dimensions(&webp).unwrap(), (64, 64));
+        assert!(read_sticker_metadata(&webp).is_none());
+
+        let metadata = StickerMetadata::new("Cats", "whatsmeow").with_emojis(["😺", "❤"]);
+        let tagged = inject_sticker_metadata(&webp, &metadata).unwrap();
+        assert_eq!(read_sticker_metadata(&tagged), Some(metadata.clone()));
+        assert_eq!(sticker_dimensions(&tagged).unwrap(), (64, 64));
+
+        let chunks = parse_chunks(&tagged).unwrap();
+        assert_eq!(&chunks[0].fourcc, b"VP8X");
+        assert_eq!(chunks[0].data[0], VP8X_FLAG_EXIF | VP8X_FLAG_ALPHA);
+        assert_eq!(u32::from_le_bytes(tagged[4..8].try_into().unwrap()) as usize, tagged.len() - 8);
+
+        // Re-tagging replaces the metadata instead of adding a second chunk
+        let renamed = StickerMetadata { pack_name: "Dogs".to_string(), ..metadata };
+        let retagged = inject_sticker_metadata(&tagged, &renamed).unwrap();
+        assert_eq!(read_sticker_metadata(&retagged).unwrap().pack_name, "Dogs");
+        let exif_chunks = parse_chunks(&retagged).unwrap().iter().filter(|c| &c.fourcc == b"EXIF").count();
+        assert_eq!(exif_chunks, 1);
+    }
+
+    #[test]
+    fn test_validate_sticker() {
+        assert!(!validate_sticker(&vp8l_webp(512, false)).unwrap());
+        assert!(validate_sticker(&vp8l_webp(1024, false)).is_err());
+        assert!(validate_sticker(b"\x89PNG\r\n\x1a\n").is_err());
+
+        let animated = write_chunks(&[
+            Chunk { fourcc: *b"VP8X", data: &[VP8X_FLAG_ANIMATION, 0, 0, 0, 255, 1, 0, 255, 1, 0] },
+            Chunk { fourcc: *b"ANIM", data: &[0; 6] },
+        ]);
+        assert_eq!(sticker_dimensions(&animated).unwrap(), (512, 512));
+        assert!(validate_sticker(&animated).unwrap());
+    }
+
+    #[test]
+    fn test_parse_sticker_message() {
+        let message = wa_e2e::Message {
+            sticker_message: Some(Box::new(wa_e2e::StickerMessage {
+                mimetype: Some("image/webp".to_string()),
+                width: Some(512),
+                height: Some(512),
+                is_animated: Some(true),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let sticker = parse_sticker_message(&message).unwrap();
+        assert!(sticker.is_animated);
+        assert!(!sticker.is_avatar);
+        assert_eq!(sticker.width, Some(512));
+        assert!(parse_sticker_message(&wa_e2e::Message::default()).is_none());
+    }
+}
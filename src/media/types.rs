@@ -87,6 +87,9 @@ impl MediaType {
     }
 }
 
+/// Host media direct paths are downloaded from
+pub const MEDIA_HOST: &str = "https://mmg.whatsapp.net";
+
 /// Media information for uploaded content
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MediaInfo {
@@ -150,6 +153,13 @@ impl MediaInfo {
         }
     }
     
+    /// Point the media to a new direct path, e.g. after the sender uploaded it again
+    pub fn with_direct_path(mut self, direct_path: String) -> Self {
+        self.url = format!("{}{}", MEDIA_HOST, direct_path);
+        self.direct_path = Some(direct_path);
+        self
+    }
+    
     /// Validate media info completeness
     pub fn validate(&self) -> bool {
         !self.url.is_empty() &&
@@ -3,6 +3,7 @@
 use crate::{
     error::{Error, Result},
     media::{MediaInfo, MediaType, ProgressInfo},
+    socket::ProxyConfig,
     util::crypto::{sha256, AesGcm},
 };
 use std::path::Path;
@@ -26,6 +27,8 @@ pub struct UploadConfig {
     pub enable_resume: bool,
     /// User agent string
     pub user_agent: String,
+    /// Proxy for upload requests
+    pub proxy: Option<ProxyConfig>,
 }
 
 impl Default for UploadConfig {
@@ -37,6 +40,7 @@ impl Default for UploadConfig {
             chunk_size: 1024 * 1024, // 1 MB chunks
             enable_resume: true,
             user_agent: "WhatsApp/0.1.0".to_string(),
+            proxy: None,
         }
     }
 }
@@ -128,9 +132,13 @@ pub struct MediaUploader {
 impl MediaUploader {
     /// Create new media uploader
     pub fn new(config: UploadConfig) -> Self {
-        let http_client = reqwest::Client::builder()
+        let mut builder = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(config.timeout_seconds))
-            .user_agent(&config.user_agent)
+            .user_agent(&config.user_agent);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy.to_reqwest_proxy().expect("Invalid proxy configuration"));
+        }
+        let http_client = builder
             .build()
             .expect("Failed to create HTTP client");
         
@@ -156,10 +164,7 @@ impl MediaUploader {
         
         // Check file size limits
         if file_size > media_type.max_file_size() {
-            return Err(Error::Protocol(format!(
-                "File size {} exceeds limit {} for media type {:?}",
-                file_size, media_type.max_file_size(), media_type
-            )));
+            return Err(Error::MediaSizeExceeded { size: file_size, max: media_type.max_file_size() });
         }
         
         // Read file data
@@ -189,10 +194,7 @@ impl MediaUploader {
         
         // Check file size limits
         if file_size > media_type.max_file_size() {
-            return Err(Error::Protocol(format!(
-                "Data size {} exceeds limit {} for media type {:?}",
-                file_size, media_type.max_file_size(), media_type
-            )));
+            return Err(Error::MediaSizeExceeded { size: file_size, max: media_type.max_file_size() });
         }
         
         // Generate media key and encrypt file
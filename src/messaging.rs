@@ -1,19 +1,25 @@
 use crate::{
-    binary::{Node, NodeContent},
+    binary::{MessageStanzaBuilder, Node, NodeContent},
     error::{Error, Result},
     types::{
         JID, SendableMessage, TextMessage, ExtendedTextMessage, MessageInfo, MessageType,
         MediaMessage, LocationMessage, ContactMessage, ReactionMessage, PollMessage,
         QuotedMessage, GroupInviteMessage, ProtocolMessage, MessageReceipt, MessageStatus,
-        ContextInfo, MessageKey, ProtocolMessageType
+        ContextInfo, MessageKey, ProtocolMessageType, PollUpdateMessage, DeliverySummary, GroupMention,
+        ProductMessage, ContactsArrayMessage, KeepInChatMessage, PollOption, PollVote, EncryptedPollVote,
     },
-    proto::ProtoUtils,
+    proto::{wa_common, wa_e2e, ProtoUtils},
     media::MediaManager,
+    server_time,
+    util::crypto::{hkdf_expand, random_bytes, AesGcm},
 };
-use std::collections::HashMap;
-use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::debug;
 use uuid::Uuid;
 use base64;
 
@@ -25,6 +31,7 @@ pub struct MessageBuilder {
     context_info: Option<ContextInfo>,
     quoted_message: Option<QuotedMessage>,
     ephemeral_expiration: Option<u32>,
+    device_fanout: bool,
 }
 
 impl MessageBuilder {
@@ -37,15 +44,37 @@ impl MessageBuilder {
             context_info: None,
             quoted_message: None,
             ephemeral_expiration: None,
+            device_fanout: true,
         }
     }
     
     /// Set context information (for replies, mentions, etc.)
+    ///
+    /// An `ephemeral_setting` in the context makes the message disappearing,
+    /// unless an expiration was set explicitly.
     pub fn with_context(mut self, context_info: ContextInfo) -> Self {
+        if self.ephemeral_expiration.is_none() {
+            self.ephemeral_expiration = context_info.ephemeral_setting.filter(|e| *e > 0);
+        }
         self.context_info = Some(context_info);
         self
     }
     
+    /// Mention everyone in the group (`@all`) without listing each participant
+    pub fn mention_all(mut self) -> Self {
+        self.context_info.get_or_insert_with(ContextInfo::default).non_jid_mentions = Some(1);
+        self
+    }
+    
+    /// Mention a group, e.g. a community's group or a group in a status update
+    pub fn mention_group(mut self, group_jid: JID, group_subject: String) -> Self {
+        self.context_info
+            .get_or_insert_with(ContextInfo::default)
+            .group_mentions
+            .push(GroupMention { group_jid, group_subject });
+        self
+    }
+    
     /// Set quoted message (for replies)
     pub fn reply_to(mut self, quoted_message: QuotedMessage) -> Self {
         self.quoted_message = Some(quoted_message);
@@ -58,6 +87,12 @@ impl MessageBuilder {
         self
     }
     
+    /// Whether our own other devices get a copy of the message (the default)
+    pub fn device_fanout(mut self, fanout: bool) -> Self {
+        self.device_fanout = fanout;
+        self
+    }
+    
     /// Set text message content
     pub fn text(mut self, text: String) -> Self {
         self.message_type = MessageType::Text;
@@ -114,6 +149,20 @@ impl MessageBuilder {
         self
     }
     
+    /// Set contacts array message
+    pub fn contacts_array(mut self, contacts: ContactsArrayMessage) -> Self {
+        self.message_type = MessageType::ContactsArray;
+        self.content = Some(SendableMessage::ContactsArray(contacts));
+        self
+    }
+    
+    /// Keep a message of a disappearing chat, or undo that
+    pub fn keep_in_chat(mut self, keep: KeepInChatMessage) -> Self {
+        self.message_type = MessageType::KeepInChat;
+        self.content = Some(SendableMessage::KeepInChat(keep));
+        self
+    }
+    
     /// Set reaction message
     pub fn reaction(mut self, reaction: ReactionMessage) -> Self {
         self.message_type = MessageType::Reaction;
@@ -128,12 +177,19 @@ impl MessageBuilder {
         self
     }
     
+    /// Set product message, sharing an item of a business catalog
+    pub fn product(mut self, product: ProductMessage) -> Self {
+        self.message_type = MessageType::Product;
+        self.content = Some(SendableMessage::Product(product));
+        self
+    }
+    
     /// Build the message into a WhatsApp node
     pub fn build(&self, message_id: String, from_jid: JID) -> Result<Node> {
         let content = self.content.as_ref()
             .ok_or_else(|| Error::Protocol("No message content set".to_string()))?;
             
-        match content {
+        let mut node = match content {
             SendableMessage::Text(text_msg) => {
                 self.build_text_message(message_id, from_jid, &text_msg.text)
             },
@@ -164,40 +220,54 @@ impl MessageBuilder {
             SendableMessage::Contact(contact) => {
                 self.build_contact_message(message_id, from_jid, contact)
             },
+            SendableMessage::ContactsArray(contacts) => {
+                self.build_contacts_array_message(message_id, from_jid, contacts)
+            },
             SendableMessage::Reaction(reaction) => {
                 self.build_reaction_message(message_id, from_jid, reaction)
             },
             SendableMessage::Poll(poll) => {
                 self.build_poll_message(message_id, from_jid, poll)
             },
+            SendableMessage::Product(product) => {
+                self.build_product_message(message_id, from_jid, product)
+            },
+            SendableMessage::KeepInChat(keep) => {
+                self.build_keep_in_chat_message(message_id, from_jid, keep)
+            },
             _ => Err(Error::Protocol("Unsupported message type".to_string())),
+        }?;
+        
+        // A context set on the builder wins over the one the content carries
+        let context = self.context_info.as_ref().or_else(|| content.context_info());
+        if let Some(context_node) = context.map(|c| self.build_context_node(c)).transpose()?.flatten() {
+            if let NodeContent::Children(children) = &mut node.content {
+                children.push(context_node);
+            }
         }
+        Ok(node)
     }
     
-    /// Build common message attributes
-    fn build_message_attrs(&self, message_id: String, from_jid: JID, msg_type: &str) -> HashMap<String, String> {
-        let mut attrs = HashMap::new();
-        attrs.insert("id".to_string(), message_id);
-        attrs.insert("type".to_string(), msg_type.to_string());
-        attrs.insert("to".to_string(), self.to.to_string());
-        attrs.insert("from".to_string(), from_jid.to_string());
-        
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        attrs.insert("t".to_string(), timestamp.to_string());
+    /// Start the message stanza with the common attributes
+    fn message_stanza(&self, message_id: String, from_jid: JID, msg_type: &str) -> MessageStanzaBuilder {
+        let timestamp = server_time::unix_now();
         
+        let mut stanza = Node::message(&message_id, &self.to)
+            .kind(msg_type)
+            .from(&from_jid)
+            .attr("t", timestamp);
         if let Some(expiration) = self.ephemeral_expiration {
-            attrs.insert("ephemeral".to_string(), expiration.to_string());
+            stanza = stanza.attr("ephemeral", expiration);
         }
-        
-        attrs
+        if !self.device_fanout {
+            stanza = stanza.attr(crate::devices::DEVICE_FANOUT_ATTR, "false");
+        }
+        stanza
     }
     
     /// Build a text message node using protobuf structures
     fn build_text_message(&self, message_id: String, from_jid: JID, text: &str) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id.clone(), from_jid, "text");
+        let stanza = self.message_stanza(message_id.clone(), from_jid, "text");
         
         // Create protobuf message key
         let _message_key = ProtoUtils::create_message_key(
@@ -211,29 +281,49 @@ impl MessageBuilder {
         
         // Create the message content with protobuf binary data
         let proto_data = ProtoUtils::text_to_bytes(&text_message);
-        let mut children = vec![Node {
+        let children = vec![Node {
             tag: "body".to_string(),
             attrs: HashMap::new(),
             content: NodeContent::Binary(proto_data),
         }];
         
-        // Add context info if present
-        if let Some(context) = &self.context_info {
-            if let Some(quoted) = &context.quoted_message {
-                children.push(self.build_quoted_node(quoted)?);
-            }
+        Ok(stanza.nodes(children).build())
+    }
+    
+    /// Build the context info node: the quoted message, mentions and forwarding info
+    fn build_context_node(&self, context: &ContextInfo) -> Result<Option<Node>> {
+        let mut children = Vec::new();
+        if let Some(quoted) = &context.quoted_message {
+            children.push(self.build_quoted_node(quoted)?);
+        }
+        for jid in &context.mentioned_jids {
+            children.push(Node::new("mentionedJid".to_string()).with_text(jid.to_string()));
+        }
+        if let Some(count) = context.non_jid_mentions.filter(|c| *c > 0) {
+            children.push(Node::new("nonJidMentions".to_string()).with_text(count.to_string()));
+        }
+        for mention in &context.group_mentions {
+            children.push(Node::new("groupMentions".to_string())
+                .attr("groupJid".to_string(), mention.group_jid.to_string())
+                .attr("groupSubject".to_string(), mention.group_subject.clone()));
         }
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(children),
-        })
+        let mut node = Node::new("contextInfo".to_string());
+        if let Some(score) = context.forwarding_score.filter(|s| *s > 0) {
+            node = node.attr("forwardingScore".to_string(), score.to_string());
+        }
+        if context.is_forwarded.or(context.forwarded).unwrap_or(false) {
+            node = node.attr("isForwarded".to_string(), "true".to_string());
+        }
+        if children.is_empty() && node.attrs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(node.with_children(children)))
     }
     
     /// Build an extended text message node
     fn build_extended_text_message(&self, message_id: String, from_jid: JID, ext_text: &ExtendedTextMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, "text");
+        let stanza = self.message_stanza(message_id, from_jid, "text");
         
         let mut ext_attrs = HashMap::new();
         if let Some(url) = &ext_text.canonical_url {
@@ -252,16 +342,12 @@ impl MessageBuilder {
             content: NodeContent::Text(ext_text.text.clone()),
         };
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(vec![ext_text_node]),
-        })
+        Ok(stanza.nodes(vec![ext_text_node]).build())
     }
     
     /// Build a media message node
     fn build_media_message(&self, message_id: String, from_jid: JID, media_type: &str, media: &MediaMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, media_type);
+        let stanza = self.message_stanza(message_id, from_jid, media_type);
         
         let mut media_attrs = HashMap::new();
         if let Some(url) = &media.url {
@@ -274,6 +360,15 @@ impl MessageBuilder {
             media_attrs.insert("fileSha256".to_string(), base64::encode(media.file_sha256.as_ref().unwrap_or(&vec![])));
             media_attrs.insert("fileLength".to_string(), length.to_string());
         }
+        if let Some(hash) = &media.file_enc_sha256 {
+            media_attrs.insert("fileEncSha256".to_string(), base64::encode(hash));
+        }
+        if let Some(media_key) = &media.media_key {
+            media_attrs.insert("mediaKey".to_string(), base64::encode(media_key));
+        }
+        if let Some(direct_path) = &media.direct_path {
+            media_attrs.insert("directPath".to_string(), direct_path.clone());
+        }
         if let Some(width) = media.width {
             media_attrs.insert("width".to_string(), width.to_string());
         }
@@ -300,16 +395,12 @@ impl MessageBuilder {
         };
         children.push(media_node);
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(children),
-        })
+        Ok(stanza.nodes(children).build())
     }
     
     /// Build a location message node
     fn build_location_message(&self, message_id: String, from_jid: JID, location: &LocationMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, "location");
+        let stanza = self.message_stanza(message_id, from_jid, "location");
         
         let mut loc_attrs = HashMap::new();
         loc_attrs.insert("degreesLatitude".to_string(), location.latitude.to_string());
@@ -328,16 +419,12 @@ impl MessageBuilder {
             content: NodeContent::Text(String::new()),
         };
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(vec![location_node]),
-        })
+        Ok(stanza.nodes(vec![location_node]).build())
     }
     
     /// Build a contact message node
     fn build_contact_message(&self, message_id: String, from_jid: JID, contact: &ContactMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, "contact");
+        let stanza = self.message_stanza(message_id, from_jid, "contact");
         
         let mut contact_attrs = HashMap::new();
         contact_attrs.insert("displayName".to_string(), contact.display_name.clone());
@@ -348,16 +435,33 @@ impl MessageBuilder {
             content: NodeContent::Text(contact.vcard.clone()),
         };
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(vec![contact_node]),
-        })
+        Ok(stanza.nodes(vec![contact_node]).build())
+    }
+    
+    /// Build a contacts array message node
+    fn build_contacts_array_message(&self, message_id: String, from_jid: JID, contacts: &ContactsArrayMessage) -> Result<Node> {
+        if contacts.contacts.is_empty() {
+            return Err(Error::Protocol("Contacts array message has no contacts".to_string()));
+        }
+        let stanza = self.message_stanza(message_id, from_jid, "contactsArray");
+        
+        let contact_nodes: Vec<Node> = contacts.contacts.iter()
+            .map(|contact| Node::builder("contactMessage")
+                .attr("displayName", &contact.display_name)
+                .text(contact.vcard.as_str())
+                .build())
+            .collect();
+        let array_node = Node::builder("contactsArrayMessage")
+            .attr("displayName", &contacts.display_name)
+            .nodes(contact_nodes)
+            .build();
+        
+        Ok(stanza.nodes(vec![array_node]).build())
     }
     
     /// Build a reaction message node
     fn build_reaction_message(&self, message_id: String, from_jid: JID, reaction: &ReactionMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, "reaction");
+        let stanza = self.message_stanza(message_id, from_jid, "reaction");
         
         let mut reaction_attrs = HashMap::new();
         reaction_attrs.insert("text".to_string(), reaction.text.clone());
@@ -369,20 +473,19 @@ impl MessageBuilder {
             content: NodeContent::Text(String::new()),
         };
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(vec![reaction_node]),
-        })
+        Ok(stanza.nodes(vec![reaction_node]).build())
     }
     
     /// Build a poll message node
     fn build_poll_message(&self, message_id: String, from_jid: JID, poll: &PollMessage) -> Result<Node> {
-        let attrs = self.build_message_attrs(message_id, from_jid, "poll");
+        let stanza = self.message_stanza(message_id, from_jid, "poll");
         
         let mut poll_attrs = HashMap::new();
         poll_attrs.insert("name".to_string(), poll.name.clone());
         poll_attrs.insert("selectableCount".to_string(), poll.selectable_options_count.to_string());
+        if let Some(secret) = &poll.message_secret {
+            poll_attrs.insert("messageSecret".to_string(), base64::encode(secret));
+        }
         
         let mut option_nodes = vec![];
         for option in &poll.options {
@@ -400,11 +503,35 @@ impl MessageBuilder {
             content: NodeContent::Children(option_nodes),
         };
         
-        Ok(Node {
-            tag: "message".to_string(),
-            attrs,
-            content: NodeContent::Children(vec![poll_node]),
-        })
+        Ok(stanza.nodes(vec![poll_node]).build())
+    }
+    
+    /// Build a product message node carrying the encoded `productMessage`
+    fn build_product_message(&self, message_id: String, from_jid: JID, product: &ProductMessage) -> Result<Node> {
+        let stanza = self.message_stanza(message_id, from_jid, "media");
+        
+        let message = crate::business::product_message_to_proto(product);
+        let body = Node::new("body".to_string()).with_binary(ProtoUtils::text_to_bytes(&message));
+        
+        Ok(stanza.nodes(vec![body]).build())
+    }
+    
+    /// Build a keep-in-chat message node carrying the encoded `keepInChatMessage`
+    fn build_keep_in_chat_message(&self, message_id: String, from_jid: JID, keep: &KeepInChatMessage) -> Result<Node> {
+        let stanza = self.message_stanza(message_id, from_jid, "keepInChat");
+        
+        let keep_type = if keep.keep { wa_e2e::KeepType::KeepForAll } else { wa_e2e::KeepType::UndoKeepForAll };
+        let message = wa_e2e::Message {
+            keep_in_chat_message: Some(wa_e2e::KeepInChatMessage {
+                key: Some(message_key_to_proto(&keep.key)),
+                keep_type: Some(keep_type as i32),
+                timestamp_ms: Some(keep.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as i64),
+            }),
+            ..Default::default()
+        };
+        let body = Node::new("body".to_string()).with_binary(ProtoUtils::text_to_bytes(&message));
+        
+        Ok(stanza.nodes(vec![body]).build())
     }
     
     /// Build a quoted message node
@@ -433,19 +560,35 @@ impl MessageBuilder {
 pub struct MessageProcessor;
 
 impl MessageProcessor {
+    fn parse_quoted_message(node: &Node) -> Option<QuotedMessage> {
+        let text = node.get_text().filter(|text| !text.is_empty()).cloned();
+        Some(QuotedMessage {
+            id: node.get_attr("id")?.clone(),
+            remote_jid: node.get_attr("remoteJid")?.parse().ok()?,
+            participant: node.get_attr("participant").and_then(|p| p.parse().ok()),
+            message_type: if text.is_some() { MessageType::Text } else { MessageType::Unknown },
+            text,
+            media_type: node.get_attr("mediaType").cloned(),
+        })
+    }
+    
     /// Process an incoming message node
     pub fn process_message(node: &Node) -> Result<MessageInfo> {
         let id = node.get_attr("id")
             .ok_or_else(|| Error::Protocol("Message missing ID".to_string()))?
             .clone();
             
-        let from_str = node.get_attr("from")
-            .ok_or_else(|| Error::Protocol("Message missing from".to_string()))?;
-        let sender: JID = from_str.parse()?;
+        let from: JID = node.get_attr("from")
+            .ok_or_else(|| Error::Protocol("Message missing from".to_string()))?
+            .parse()?;
         
-        let to_str = node.get_attr("to")
-            .ok_or_else(|| Error::Protocol("Message missing to".to_string()))?;
-        let chat: JID = to_str.parse()?;
+        // Group messages from the server name their author in `participant`,
+        // stanzas built by `MessageBuilder` name the chat in `to`
+        let (chat, sender) = match (node.get_attr("participant"), node.get_attr("to")) {
+            (Some(participant), _) => (from, participant.parse()?),
+            (None, Some(to)) => (to.parse()?, from),
+            (None, None) => (from.to_non_ad(), from),
+        };
         
         let timestamp_str = node.get_attr("t")
             .ok_or_else(|| Error::Protocol("Message missing timestamp".to_string()))?;
@@ -469,6 +612,7 @@ impl MessageProcessor {
             Some("pollUpdate") => MessageType::PollUpdate,
             Some("groupInvite") => MessageType::GroupInvite,
             Some("call") => MessageType::Call,
+            Some("keepInChat") => MessageType::KeepInChat,
             Some("protocol") => MessageType::ProtocolMessage,
             _ => MessageType::Unknown,
         };
@@ -476,15 +620,195 @@ impl MessageProcessor {
         // TODO: Determine if message is from us
         let from_me = false;
         
+        // Context may be wrapped in a <contextInfo> node or sit directly on the message
+        let context = node.find_child("contextInfo").unwrap_or(node);
+        let quoted = context.find_child("quotedMessage").and_then(Self::parse_quoted_message);
+        let mentioned_jids = context.get_children()
+            .map(|children| {
+                children.iter()
+                    .filter(|c| c.tag == "mentionedJid")
+                    .filter_map(|c| c.get_text().and_then(|jid| jid.parse().ok()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mentions_all = context.find_child("nonJidMentions")
+            .and_then(|c| c.get_text())
+            .and_then(|count| count.parse::<u32>().ok())
+            .is_some_and(|count| count > 0);
+        let group_mentions = context.get_children()
+            .map(|children| {
+                children.iter()
+                    .filter(|c| c.tag == "groupMentions")
+                    .filter_map(|c| Some(GroupMention {
+                        group_jid: c.get_attr("groupJid")?.parse().ok()?,
+                        group_subject: c.get_attr("groupSubject").cloned().unwrap_or_default(),
+                    }))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let forwarding_score = context.get_attr("forwardingScore")
+            .and_then(|score| score.parse().ok())
+            .or_else(|| (context.get_attr("isForwarded").map(|f| f.as_str()) == Some("true")).then_some(1));
+        
+        // The server announces the other identifier of the sender on LID migrated chats
+        let sender_alt = ["participant_lid", "participant_pn", "sender_lid", "sender_pn"]
+            .iter()
+            .find_map(|attr| node.get_attr(attr))
+            .and_then(|jid| jid.parse().ok());
+        let push_name = node.get_attr("notify").filter(|name| !name.is_empty()).cloned();
+        let content = Self::parse_content(node, &message_type);
+        
         Ok(MessageInfo {
             id,
             chat,
             sender,
+            sender_alt,
+            push_name,
             timestamp,
             message_type,
             from_me,
+            edited_at: None,
+            revoked: false,
+            quoted,
+            mentioned_jids,
+            mentions_all,
+            group_mentions,
+            forwarding_score,
+            content,
+            starred: false,
+            kept: false,
         })
     }
+    
+    /// Read the content of a message stanza as built by [`MessageBuilder`]
+    fn parse_content(node: &Node, message_type: &MessageType) -> Option<SendableMessage> {
+        if let Some(body) = node.find_child("body").and_then(|body| body.get_binary()) {
+            let message = <wa_e2e::Message as prost::Message>::decode(body.as_slice()).ok()?;
+            if let Some(keep) = &message.keep_in_chat_message {
+                return Some(SendableMessage::KeepInChat(KeepInChatMessage {
+                    key: message_key_from_proto(keep.key.as_ref()?)?,
+                    keep: keep.keep_type() == wa_e2e::KeepType::KeepForAll,
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(keep.timestamp_ms.unwrap_or(0).max(0) as u64),
+                }));
+            }
+            if let Some(poll) = message.poll_creation_message.as_ref()
+                .or(message.poll_creation_message_v2.as_ref())
+                .or(message.poll_creation_message_v3.as_ref())
+            {
+                return Some(SendableMessage::Poll(PollMessage {
+                    name: poll.name.clone().unwrap_or_default(),
+                    options: poll.options.iter()
+                        .map(|option| PollOption { name: option.option_name.clone().unwrap_or_default() })
+                        .collect(),
+                    selectable_options_count: poll.selectable_options_count.unwrap_or(0),
+                    context_info: None,
+                    message_secret: message.message_context_info.as_ref().and_then(|info| info.message_secret.clone()),
+                }));
+            }
+            if let Some(update) = &message.poll_update_message {
+                let vote = update.vote.as_ref()?;
+                return Some(SendableMessage::PollUpdate(PollUpdateMessage {
+                    poll_creation_message_key: message_key_from_proto(update.poll_creation_message_key.as_ref()?)?,
+                    // Filled in once the vote is decrypted with the secret of its poll
+                    vote: PollVote { selected_options: Vec::new() },
+                    sender_timestamp: update.sender_timestamp_ms
+                        .map(|ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64)),
+                    encrypted_vote: Some(EncryptedPollVote {
+                        payload: vote.enc_payload.clone()?,
+                        iv: vote.enc_iv.clone()?,
+                    }),
+                }));
+            }
+            let text = ProtoUtils::extract_text_message(body).ok()?;
+            return Some(SendableMessage::Text(TextMessage { text }));
+        }
+        if let Some(ext) = node.find_child("extendedTextMessage") {
+            return Some(SendableMessage::ExtendedText(ExtendedTextMessage {
+                text: ext.get_text().cloned().unwrap_or_default(),
+                matched_text: None,
+                canonical_url: ext.get_attr("url").cloned(),
+                description: ext.get_attr("description").cloned(),
+                title: ext.get_attr("title").cloned(),
+                text_arg_b: None,
+                thumbnail: None,
+                jpeg_thumbnail: None,
+                context_info: None,
+                font: None,
+                preview_type: None,
+            }));
+        }
+        if let Some(location) = node.find_child("locationMessage") {
+            return Some(SendableMessage::Location(LocationMessage {
+                latitude: location.get_attr("degreesLatitude")?.parse().ok()?,
+                longitude: location.get_attr("degreesLongitude")?.parse().ok()?,
+                name: location.get_attr("name").cloned(),
+                address: location.get_attr("address").cloned(),
+                context_info: None,
+            }));
+        }
+        if let Some(poll) = node.find_child("pollCreationMessage") {
+            return Some(SendableMessage::Poll(PollMessage {
+                name: poll.get_attr("name").cloned().unwrap_or_default(),
+                options: poll.get_children()
+                    .map(|children| {
+                        children.iter()
+                            .filter(|c| c.tag == "option")
+                            .map(|c| PollOption { name: c.get_text().cloned().unwrap_or_default() })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                selectable_options_count: poll.get_attr("selectableCount").and_then(|count| count.parse().ok()).unwrap_or(0),
+                context_info: None,
+                message_secret: poll.get_attr("messageSecret").and_then(|secret| base64::decode(secret).ok()),
+            }));
+        }
+        let parse_contact = |contact: &Node| ContactMessage {
+            display_name: contact.get_attr("displayName").cloned().unwrap_or_default(),
+            vcard: contact.get_text().cloned().unwrap_or_default(),
+            context_info: None,
+        };
+        if let Some(contact) = node.find_child("contactMessage") {
+            return Some(SendableMessage::Contact(parse_contact(contact)));
+        }
+        if let Some(array) = node.find_child("contactsArrayMessage") {
+            return Some(SendableMessage::ContactsArray(ContactsArrayMessage {
+                display_name: array.get_attr("displayName").cloned().unwrap_or_default(),
+                contacts: array.get_children()
+                    .map(|children| children.iter().filter(|c| c.tag == "contactMessage").map(parse_contact).collect())
+                    .unwrap_or_default(),
+                context_info: None,
+            }));
+        }
+        
+        let (tag, wrap): (&str, fn(MediaMessage) -> SendableMessage) = match message_type {
+            MessageType::Image => ("imageMessage", SendableMessage::Image),
+            MessageType::Video => ("videoMessage", SendableMessage::Video),
+            MessageType::Audio => ("audioMessage", SendableMessage::Audio),
+            MessageType::Voice => ("pttMessage", SendableMessage::Voice),
+            MessageType::Document => ("documentMessage", SendableMessage::Document),
+            MessageType::Sticker => ("stickerMessage", SendableMessage::Sticker),
+            _ => return None,
+        };
+        let media = node.find_child(tag)?;
+        Some(wrap(MediaMessage {
+            url: media.get_attr("url").cloned(),
+            direct_path: media.get_attr("directPath").cloned(),
+            media_key: media.get_attr("mediaKey").and_then(|key| base64::decode(key).ok()),
+            file_sha256: media.get_attr("fileSha256").and_then(|hash| base64::decode(hash).ok()),
+            file_enc_sha256: media.get_attr("fileEncSha256").and_then(|hash| base64::decode(hash).ok()),
+            file_length: media.get_attr("fileLength").and_then(|value| value.parse().ok()),
+            mime_type: media.get_attr("mimetype").cloned(),
+            caption: media.get_text().filter(|caption| !caption.is_empty()).cloned(),
+            width: media.get_attr("width").and_then(|value| value.parse().ok()),
+            height: media.get_attr("height").and_then(|value| value.parse().ok()),
+            page_count: None,
+            seconds: media.get_attr("seconds").and_then(|value| value.parse().ok()),
+            ptt: media.get_attr("ptt").map(|ptt| ptt == "true"),
+            gif_playback: None,
+            jpeg_thumbnail: None,
+            context_info: None,
+        }))
+    }
 }
 
 /// Failed message information
@@ -495,9 +819,13 @@ pub struct FailedMessage {
     pub failed_at: SystemTime,
 }
 
-/// Enhanced message queue with receipt tracking
+/// Enhanced message queue with receipt tracking.
+///
+/// Pending messages are kept in one FIFO per chat so each chat can be
+/// resent independently of the others.
 pub struct MessageQueue {
-    pending: Vec<PendingMessage>,
+    chats: HashMap<JID, VecDeque<PendingMessage>>,
+    next_seq: u64,
     receipts: HashMap<String, MessageReceipt>,
     failed_messages: Vec<FailedMessage>,
 }
@@ -505,6 +833,9 @@ pub struct MessageQueue {
 #[derive(Debug, Clone)]
 pub struct PendingMessage {
     pub id: String,
+    pub chat: JID,
+    /// Position in the global send order
+    pub seq: u64,
     pub node: Node,
     pub retry_count: u8,
     pub created_at: SystemTime,
@@ -514,7 +845,8 @@ impl MessageQueue {
     /// Create a new message queue
     pub fn new() -> Self {
         Self {
-            pending: Vec::new(),
+            chats: HashMap::new(),
+            next_seq: 0,
             receipts: HashMap::new(),
             failed_messages: Vec::new(),
         }
@@ -532,9 +864,9 @@ impl MessageQueue {
     
     /// Mark a message as failed
     pub fn mark_failed(&mut self, message_id: &str, error: String) {
-        if let Some(pos) = self.pending.iter().position(|msg| msg.id == message_id) {
+        if let Some(message) = self.remove(message_id) {
             let failed_message = FailedMessage {
-                message: self.pending.remove(pos),
+                message,
                 error,
                 failed_at: SystemTime::now(),
             };
@@ -553,43 +885,99 @@ impl MessageQueue {
             let mut failed = self.failed_messages.remove(pos);
             failed.message.retry_count += 1;
             failed.message.created_at = SystemTime::now();
+            failed.message.seq = self.take_seq();
             let pending = failed.message.clone();
-            self.pending.push(failed.message);
+            self.chats.entry(pending.chat.clone()).or_default().push_back(failed.message);
             Some(pending)
         } else {
             None
         }
     }
     
-    /// Add a message to the queue
-    pub fn enqueue(&mut self, id: String, node: Node) {
+    /// Add a message for the given chat to the queue
+    pub fn enqueue(&mut self, id: String, chat: &JID, node: Node) {
         let pending = PendingMessage {
             id,
+            chat: chat.clone(),
+            seq: self.take_seq(),
             node,
             retry_count: 0,
             created_at: SystemTime::now(),
         };
-        self.pending.push(pending);
+        self.chats.entry(chat.clone()).or_default().push_back(pending);
+    }
+    
+    /// Messages waiting for a server ack, oldest first
+    pub fn pending(&self) -> Vec<PendingMessage> {
+        let mut pending: Vec<_> = self.chats.values().flatten().cloned().collect();
+        pending.sort_by_key(|msg| msg.seq);
+        pending
+    }
+    
+    /// Messages waiting for a server ack grouped by chat, oldest first within
+    /// each chat. Chats are ordered by their oldest message.
+    pub fn pending_by_chat(&self) -> Vec<Vec<PendingMessage>> {
+        let mut chats: Vec<Vec<PendingMessage>> = self
+            .chats
+            .values()
+            .map(|queue| queue.iter().cloned().collect())
+            .collect();
+        chats.sort_by_key(|queue| queue.first().map(|msg| msg.seq));
+        chats
     }
     
-    /// Get the next message to send
+    /// Whether a message is waiting for a server ack
+    pub fn is_pending(&self, message_id: &str) -> bool {
+        self.chats.values().flatten().any(|msg| msg.id == message_id)
+    }
+    
+    /// Get the next message to send, the oldest across all chats
     pub fn next(&mut self) -> Option<PendingMessage> {
-        self.pending.pop()
+        let chat = self
+            .chats
+            .iter()
+            .filter_map(|(chat, queue)| queue.front().map(|msg| (msg.seq, chat)))
+            .min_by_key(|(seq, _)| *seq)
+            .map(|(_, chat)| chat.clone())?;
+        let queue = self.chats.get_mut(&chat)?;
+        let message = queue.pop_front();
+        if queue.is_empty() {
+            self.chats.remove(&chat);
+        }
+        message
     }
     
     /// Mark a message as acknowledged
     pub fn acknowledge(&mut self, message_id: &str) {
-        self.pending.retain(|msg| msg.id != message_id);
+        self.remove(message_id);
     }
     
     /// Get pending message count
     pub fn len(&self) -> usize {
-        self.pending.len()
+        self.chats.values().map(VecDeque::len).sum()
     }
     
     /// Check if queue is empty
     pub fn is_empty(&self) -> bool {
-        self.pending.is_empty()
+        self.chats.is_empty()
+    }
+    
+    fn take_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+    
+    fn remove(&mut self, message_id: &str) -> Option<PendingMessage> {
+        let (chat, pos) = self.chats.iter().find_map(|(chat, queue)| {
+            queue.iter().position(|msg| msg.id == message_id).map(|pos| (chat.clone(), pos))
+        })?;
+        let queue = self.chats.get_mut(&chat)?;
+        let message = queue.remove(pos);
+        if queue.is_empty() {
+            self.chats.remove(&chat);
+        }
+        message
     }
 }
 
@@ -599,9 +987,163 @@ impl Default for MessageQueue {
     }
 }
 
+/// Default number of chats sending at the same time
+pub const DEFAULT_MAX_PARALLEL_CHATS: usize = 16;
+
+/// Serializes sends per chat while letting different chats send in parallel.
+///
+/// Each send holds a [`ChatSendPermit`] from before the message is encrypted
+/// until the server acknowledged it. The per-chat lock is fair, so messages
+/// leave in the order their senders asked for a permit. A semaphore bounds
+/// how many chats are sending at once.
+pub struct ChatSendScheduler {
+    chats: std::sync::Mutex<HashMap<JID, Arc<tokio::sync::Mutex<()>>>>,
+    permits: Arc<Semaphore>,
+    max_parallel_chats: usize,
+}
+
+/// Permission to send one message to a chat, released on drop
+pub struct ChatSendPermit {
+    _chat: OwnedMutexGuard<()>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ChatSendScheduler {
+    /// Number of idle chat locks kept before they are cleaned up
+    const MAX_IDLE_CHATS: usize = 256;
+    
+    /// Create a scheduler allowing `max_parallel_chats` chats to send at once
+    pub fn new(max_parallel_chats: usize) -> Self {
+        Self {
+            chats: std::sync::Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_parallel_chats.max(1))),
+            max_parallel_chats: max_parallel_chats.max(1),
+        }
+    }
+    
+    /// Wait until the chat is free and a parallel slot is available
+    pub async fn acquire(&self, chat: &JID) -> ChatSendPermit {
+        let lock = {
+            let mut chats = self.chats.lock().unwrap();
+            if chats.len() > Self::MAX_IDLE_CHATS {
+                chats.retain(|_, lock| Arc::strong_count(lock) > 1);
+            }
+            chats.entry(chat.clone()).or_default().clone()
+        };
+        
+        // Take the chat lock first so queued messages of a busy chat
+        // don't hold slots other chats could use
+        let chat_guard = lock.lock_owned().await;
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("send scheduler semaphore is never closed");
+        
+        ChatSendPermit { _chat: chat_guard, _permit: permit }
+    }
+    
+    /// Number of chats that could start sending right now
+    pub fn available_slots(&self) -> usize {
+        self.permits.available_permits()
+    }
+    
+    /// Wait until every send holding a permit has finished
+    pub async fn drain(&self) {
+        let _all = self
+            .permits
+            .acquire_many(self.max_parallel_chats as u32)
+            .await
+            .expect("send scheduler semaphore is never closed");
+    }
+}
+
+impl Default for ChatSendScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PARALLEL_CHATS)
+    }
+}
+
+/// Per-recipient receipts of a group message
+#[derive(Debug, Default)]
+struct ParticipantReceipts {
+    statuses: HashMap<JID, MessageStatus>,
+    /// Last aggregate status reported, so each transition is reported once
+    reported: Option<MessageStatus>,
+}
+
+impl ParticipantReceipts {
+    fn summary(&self) -> DeliverySummary {
+        let mut summary = DeliverySummary { total: self.statuses.len(), ..Default::default() };
+        for status in self.statuses.values() {
+            match status {
+                MessageStatus::Delivered => summary.delivered += 1,
+                MessageStatus::Read => summary.read += 1,
+                MessageStatus::Played => summary.played += 1,
+                _ => summary.pending += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Parse the receipts of our messages in a `<receipt>` stanza.
+///
+/// Delivery, read and played receipts are returned, one per acknowledged
+/// message. Receipts from our own devices (`read-self`, `played-self`) and
+/// retry requests are not statuses of sent messages and are skipped.
+pub fn parse_receipt(node: &Node) -> Vec<MessageReceipt> {
+    if node.tag != "receipt" {
+        return Vec::new();
+    }
+    let status = match node.get_attr("type").map(String::as_str) {
+        None | Some("") | Some("delivery") => MessageStatus::Delivered,
+        Some("read") => MessageStatus::Read,
+        Some("played") => MessageStatus::Played,
+        _ => return Vec::new(),
+    };
+    let Some(id) = node.get_attr("id") else {
+        return Vec::new();
+    };
+    
+    let timestamp = node.get_attr("t")
+        .and_then(|t| t.parse::<u64>().ok())
+        .map(|t| SystemTime::UNIX_EPOCH + Duration::from_secs(t))
+        .unwrap_or_else(SystemTime::now);
+    let participant = node.get_attr("participant").and_then(|p| JID::parse(p).ok());
+    
+    let more_ids = node.find_child("list")
+        .and_then(Node::get_children)
+        .into_iter()
+        .flatten()
+        .filter(|item| item.tag == "item")
+        .filter_map(|item| item.get_attr("id"));
+    std::iter::once(id)
+        .chain(more_ids)
+        .map(|message_id| MessageReceipt {
+            message_id: message_id.clone(),
+            status: status.clone(),
+            timestamp,
+            participant: participant.clone(),
+        })
+        .collect()
+}
+
+/// Order of receipt statuses, a receipt never downgrades a recipient's status
+fn receipt_rank(status: &MessageStatus) -> u8 {
+    match status {
+        MessageStatus::Delivered => 1,
+        MessageStatus::Read => 2,
+        MessageStatus::Played => 3,
+        _ => 0,
+    }
+}
+
 /// Message status tracker for handling receipts and delivery status
 pub struct MessageStatusTracker {
     message_status: Arc<RwLock<HashMap<String, MessageStatus>>>,
+    participant_receipts: RwLock<HashMap<String, ParticipantReceipts>>,
     status_callbacks: Vec<Box<dyn Fn(&str, MessageStatus) + Send + Sync>>,
 }
 
@@ -610,6 +1152,7 @@ impl MessageStatusTracker {
     pub fn new() -> Self {
         Self {
             message_status: Arc::new(RwLock::new(HashMap::new())),
+            participant_receipts: RwLock::new(HashMap::new()),
             status_callbacks: Vec::new(),
         }
     }
@@ -641,9 +1184,82 @@ impl MessageStatusTracker {
         self.status_callbacks.push(Box::new(callback));
     }
     
-    /// Process receipt message
-    pub async fn process_receipt(&self, receipt: &MessageReceipt) {
-        self.update_status(&receipt.message_id, receipt.status.clone()).await;
+    /// Track receipts of a group message from each of the given recipients
+    pub async fn expect_receipts(&self, message_id: &str, participants: &[JID]) {
+        let statuses = participants
+            .iter()
+            .map(|participant| (participant.to_non_ad(), MessageStatus::ServerAck))
+            .collect();
+        self.participant_receipts.write().await.insert(
+            message_id.to_string(),
+            ParticipantReceipts { statuses, reported: None },
+        );
+    }
+    
+    /// Get the receipt counts of a tracked group message
+    pub async fn get_delivery_summary(&self, message_id: &str) -> Option<DeliverySummary> {
+        self.participant_receipts.read().await.get(message_id).map(ParticipantReceipts::summary)
+    }
+    
+    /// Get the latest receipt status of every recipient of a group message
+    pub async fn get_participant_statuses(&self, message_id: &str) -> Option<HashMap<JID, MessageStatus>> {
+        self.participant_receipts.read().await.get(message_id).map(|receipts| receipts.statuses.clone())
+    }
+    
+    /// Process receipt message.
+    ///
+    /// Receipts of tracked group messages update the sender's status only;
+    /// the message status changes once all recipients delivered, read or
+    /// played it, which is returned as an aggregate transition.
+    pub async fn process_receipt(&self, receipt: &MessageReceipt) -> Option<(MessageStatus, DeliverySummary)> {
+        let transition = match &receipt.participant {
+            Some(participant) => {
+                let mut all_receipts = self.participant_receipts.write().await;
+                let Some(receipts) = all_receipts.get_mut(&receipt.message_id) else {
+                    drop(all_receipts);
+                    self.update_status(&receipt.message_id, receipt.status.clone()).await;
+                    return None;
+                };
+                
+                let status = receipts.statuses
+                    .entry(participant.to_non_ad())
+                    .or_insert(MessageStatus::ServerAck);
+                if receipt_rank(&receipt.status) > receipt_rank(status) {
+                    *status = receipt.status.clone();
+                }
+                
+                let summary = receipts.summary();
+                let aggregate = if summary.all_played() {
+                    Some(MessageStatus::Played)
+                } else if summary.all_read() {
+                    Some(MessageStatus::Read)
+                } else if summary.all_delivered() {
+                    Some(MessageStatus::Delivered)
+                } else {
+                    None
+                };
+                match aggregate {
+                    Some(aggregate) if receipts.reported.as_ref().map(receipt_rank) < Some(receipt_rank(&aggregate)) => {
+                        receipts.reported = Some(aggregate.clone());
+                        Some((aggregate, summary))
+                    }
+                    _ => None,
+                }
+            }
+            None => {
+                // A late read receipt must not hide that the message was played
+                let current = self.get_status(&receipt.message_id).await;
+                if current.as_ref().map_or(0, receipt_rank) < receipt_rank(&receipt.status) {
+                    self.update_status(&receipt.message_id, receipt.status.clone()).await;
+                }
+                return None;
+            }
+        };
+        
+        if let Some((status, _)) = &transition {
+            self.update_status(&receipt.message_id, status.clone()).await;
+        }
+        transition
     }
 }
 
@@ -658,7 +1274,7 @@ pub struct MessageEditor;
 
 impl MessageEditor {
     /// Create an edit message
-    pub fn create_edit_message(original_key: MessageKey, _new_text: String) -> SendableMessage {
+    pub fn create_edit_message(original_key: MessageKey, new_text: String) -> SendableMessage {
         let protocol_msg = ProtocolMessage {
             key: Some(original_key),
             message_type: ProtocolMessageType::MessageEdit,
@@ -668,6 +1284,7 @@ impl MessageEditor {
             app_state_sync_key_share: None,
             initial_security_notification_setting_sync: None,
             app_state_sync_key_request: None,
+            edited_text: Some(new_text),
         };
         
         SendableMessage::Protocol(protocol_msg)
@@ -684,15 +1301,142 @@ impl MessageEditor {
             app_state_sync_key_share: None,
             initial_security_notification_setting_sync: None,
             app_state_sync_key_request: None,
+            edited_text: None,
         };
         
         SendableMessage::Protocol(protocol_msg)
     }
 }
 
+fn message_key_to_proto(key: &MessageKey) -> wa_common::MessageKey {
+    wa_common::MessageKey {
+        remote_jid: Some(key.remote_jid.to_string()),
+        from_me: Some(key.from_me),
+        id: Some(key.id.clone()),
+        participant: key.participant.as_ref().map(|participant| participant.to_string()),
+    }
+}
+
+fn message_key_from_proto(key: &wa_common::MessageKey) -> Option<MessageKey> {
+    Some(MessageKey {
+        remote_jid: key.remote_jid.as_ref()?.parse().ok()?,
+        from_me: key.from_me.unwrap_or(false),
+        id: key.id.clone()?,
+        participant: key.participant.as_ref().and_then(|participant| participant.parse().ok()),
+    })
+}
+
+/// Replace `@<phone number>` mention placeholders with display names.
+///
+/// Only users listed in `mentioned_jids` are replaced; mentions without a
+/// known name keep their placeholder.
+pub fn resolve_mentions(text: &str, mentioned_jids: &[JID], names: &HashMap<JID, String>) -> String {
+    let mut resolved = text.to_string();
+    for jid in mentioned_jids {
+        let user = JID::new(jid.user.clone(), jid.server.clone());
+        if let Some(name) = names.get(&user) {
+            resolved = replace_mention(&resolved, &jid.user, name);
+        }
+    }
+    resolved
+}
+
+/// Replace `@user` where it is not followed by more digits, so `@123` does not match `@1234`
+fn replace_mention(text: &str, user: &str, name: &str) -> String {
+    let placeholder = format!("@{}", user);
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find(&placeholder) {
+        let after = &rest[index + placeholder.len()..];
+        result.push_str(&rest[..index]);
+        if after.starts_with(|c: char| c.is_ascii_digit()) {
+            result.push_str(&placeholder);
+        } else {
+            result.push('@');
+            result.push_str(name);
+        }
+        rest = after;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Forwarding score from which a message is labelled "Forwarded many times"
+pub const FREQUENTLY_FORWARDED_THRESHOLD: u32 = 5;
+
+/// Most chats a message can be forwarded to at once
+pub const MAX_FORWARD_CHATS: usize = 5;
+
+/// Whether a message with the given forwarding score counts as frequently forwarded
+pub fn is_frequently_forwarded(forwarding_score: u32) -> bool {
+    forwarding_score >= FREQUENTLY_FORWARDED_THRESHOLD
+}
+
+/// Build the content that forwards a received message
+///
+/// Every hop raises the forwarding score by one. Own messages that were never
+/// forwarded are sent again without the forwarded label, as the official
+/// clients do. Quotes are dropped, mentions are kept.
+pub fn forward_content(original: &MessageInfo) -> Result<SendableMessage> {
+    let content = original.content.clone()
+        .ok_or_else(|| Error::Protocol(format!("Message {} has no content to forward", original.id)))?;
+    let forwarding_score = match original.forwarding_score {
+        Some(score) => Some(score.saturating_add(1)),
+        None if original.from_me => None,
+        None => Some(1),
+    };
+    let context = ContextInfo {
+        mentioned_jids: original.mentioned_jids.clone(),
+        forwarding_score,
+        is_forwarded: forwarding_score.map(|_| true),
+        ..Default::default()
+    };
+    content.with_context_info(context)
+        .ok_or_else(|| Error::Protocol(format!("{:?} messages cannot be forwarded", original.message_type)))
+}
+
+/// Check that a message may be forwarded to `chats` chats at once
+///
+/// Frequently forwarded messages may only go to one chat at a time.
+pub fn check_forward_targets(original: &MessageInfo, chats: usize) -> Result<()> {
+    if chats == 0 {
+        return Err(Error::Protocol("No chats to forward to".to_string()));
+    }
+    if chats > MAX_FORWARD_CHATS {
+        return Err(Error::SendBlocked(format!("Messages can be forwarded to at most {} chats at once", MAX_FORWARD_CHATS)));
+    }
+    if chats > 1 && original.forwarding_score.is_some_and(is_frequently_forwarded) {
+        return Err(Error::SendBlocked("Frequently forwarded messages can only be forwarded to one chat at a time".to_string()));
+    }
+    Ok(())
+}
+
+/// How long after sending a message its sender may edit it
+pub const EDIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// How long after sending a message it may be deleted for everyone
+pub const REVOKE_WINDOW: Duration = Duration::from_secs(60 * 60 * 60);
+
+/// Check that `at` lies within `window` after the message was sent
+fn check_window(message: &MessageInfo, at: SystemTime, window: Duration, action: &str) -> Result<()> {
+    let elapsed = at.duration_since(message.timestamp).unwrap_or_default();
+    if elapsed > window {
+        return Err(Error::Protocol(format!(
+            "{} of message {} arrived {}s after sending, outside the {}s window",
+            action,
+            message.id,
+            elapsed.as_secs(),
+            window.as_secs()
+        )));
+    }
+    Ok(())
+}
+
 /// Message thread manager for handling conversation threading
 pub struct MessageThreadManager {
     threads: HashMap<String, Vec<MessageInfo>>,
+    /// Local expiry time of disappearing messages, by chat and message id
+    expirations: HashMap<(String, String), SystemTime>,
 }
 
 impl MessageThreadManager {
@@ -700,7 +1444,30 @@ impl MessageThreadManager {
     pub fn new() -> Self {
         Self {
             threads: HashMap::new(),
+            expirations: HashMap::new(),
+        }
+    }
+    
+    /// Delete a message from the local history once `expires_at` has passed
+    pub fn schedule_expiry(&mut self, chat_id: &str, message_id: &str, expires_at: SystemTime) {
+        self.expirations.insert((chat_id.to_string(), message_id.to_string()), expires_at);
+    }
+    
+    /// Remove all expired messages, returning their chat and message ids
+    pub fn remove_expired(&mut self, now: SystemTime) -> Vec<(String, String)> {
+        let expired: Vec<(String, String)> = self.expirations.iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        
+        for key in &expired {
+            self.expirations.remove(key);
+            if let Some(messages) = self.threads.get_mut(&key.0) {
+                messages.retain(|m| m.id != key.1);
+            }
         }
+        
+        expired
     }
     
     /// Add message to thread
@@ -711,6 +1478,19 @@ impl MessageThreadManager {
             .push(message);
     }
     
+    /// Insert messages older than the thread from a history sync before it.
+    ///
+    /// `messages` must be oldest first; those already in the thread are skipped.
+    /// Returns how many were added.
+    pub fn prepend_history(&mut self, chat_id: &str, messages: Vec<MessageInfo>) -> usize {
+        let thread = self.threads.entry(chat_id.to_string()).or_default();
+        let known: HashSet<&str> = thread.iter().map(|m| m.id.as_str()).collect();
+        let older: Vec<MessageInfo> = messages.into_iter().filter(|m| !known.contains(m.id.as_str())).collect();
+        let added = older.len();
+        thread.splice(0..0, older);
+        added
+    }
+    
     /// Get thread messages
     pub fn get_thread(&self, chat_id: &str) -> Option<&Vec<MessageInfo>> {
         self.threads.get(chat_id)
@@ -724,10 +1504,1399 @@ impl MessageThreadManager {
             Vec::new()
         }
     }
+    
+    /// Remove the messages of a chat, optionally keeping the starred ones
+    ///
+    /// Returns the number of removed messages.
+    pub fn clear_thread(&mut self, chat_id: &str, keep_starred: bool) -> usize {
+        let Some(messages) = self.threads.get_mut(chat_id) else {
+            return 0;
+        };
+        let before = messages.len();
+        messages.retain(|m| keep_starred && m.starred);
+        let remaining: Vec<String> = messages.iter().map(|m| m.id.clone()).collect();
+        if remaining.is_empty() {
+            self.threads.remove(chat_id);
+        }
+        self.expirations.retain(|(chat, id), _| chat != chat_id || remaining.contains(id));
+        before - remaining.len()
+    }
+    
+    /// Star or unstar a stored message, returning it if it is in the history
+    pub fn set_starred(&mut self, chat_id: &str, message_id: &str, starred: bool) -> Option<MessageInfo> {
+        let message = self.find_message_mut(chat_id, message_id)?;
+        message.starred = starred;
+        Some(message.clone())
+    }
+    
+    /// Get the starred messages of a chat, oldest first
+    pub fn get_starred_messages(&self, chat_id: &str) -> Vec<&MessageInfo> {
+        self.threads.get(chat_id)
+            .map(|messages| messages.iter().filter(|m| m.starred).collect())
+            .unwrap_or_default()
+    }
+    
+    /// Keep a stored message from expiring, or let it expire again
+    ///
+    /// Keeping cancels a scheduled expiry; the caller reschedules it when the
+    /// message is unkept. Returns the message if it is in the history.
+    pub fn apply_keep(&mut self, chat_id: &str, message_id: &str, kept: bool) -> Option<MessageInfo> {
+        if kept {
+            self.expirations.remove(&(chat_id.to_string(), message_id.to_string()));
+        }
+        let message = self.find_message_mut(chat_id, message_id)?;
+        message.kept = kept;
+        Some(message.clone())
+    }
+    
+    /// Find a message in a thread
+    pub fn find_message(&self, chat_id: &str, message_id: &str) -> Option<&MessageInfo> {
+        self.threads.get(chat_id)?.iter().find(|m| m.id == message_id)
+    }
+    
+    /// Apply an edit to a stored message.
+    ///
+    /// Only the original sender may edit, and only within [`EDIT_WINDOW`].
+    /// Returns `Ok(None)` if the message is not in the history.
+    pub fn apply_edit(
+        &mut self,
+        chat_id: &str,
+        message_id: &str,
+        editor: &JID,
+        edited_at: SystemTime,
+    ) -> Result<Option<MessageInfo>> {
+        let Some(message) = self.find_message_mut(chat_id, message_id) else {
+            return Ok(None);
+        };
+        
+        if message.revoked {
+            return Err(Error::Protocol(format!("Message {} was already deleted", message_id)));
+        }
+        if !same_user(&message.sender, editor) {
+            return Err(Error::Protocol(format!("{} cannot edit message {} of {}", editor, message_id, message.sender)));
+        }
+        check_window(message, edited_at, EDIT_WINDOW, "Edit")?;
+        
+        message.edited_at = Some(edited_at);
+        Ok(Some(message.clone()))
+    }
+    
+    /// Mark a stored message as deleted for everyone.
+    ///
+    /// Besides the sender, group admins may revoke messages in groups; admin
+    /// status is checked by the server, so any group participant is accepted.
+    /// Returns `Ok(None)` if the message is not in the history.
+    pub fn apply_revoke(
+        &mut self,
+        chat_id: &str,
+        message_id: &str,
+        revoker: &JID,
+        revoked_at: SystemTime,
+    ) -> Result<Option<MessageInfo>> {
+        let Some(message) = self.find_message_mut(chat_id, message_id) else {
+            return Ok(None);
+        };
+        
+        if !same_user(&message.sender, revoker) && !message.chat.is_group() {
+            return Err(Error::Protocol(format!("{} cannot delete message {} of {}", revoker, message_id, message.sender)));
+        }
+        check_window(message, revoked_at, REVOKE_WINDOW, "Delete")?;
+        
+        message.revoked = true;
+        Ok(Some(message.clone()))
+    }
+    
+    fn find_message_mut(&mut self, chat_id: &str, message_id: &str) -> Option<&mut MessageInfo> {
+        self.threads.get_mut(chat_id)?.iter_mut().find(|m| m.id == message_id)
+    }
+}
+
+/// Disappearing message timers of individual chats.
+///
+/// Timers are changed by `ephemeral` setting protocol messages; a setting is
+/// only applied if it is newer than the current one, so replayed or
+/// out-of-order changes cannot revert a timer.
+pub struct EphemeralTimers {
+    timers: Arc<RwLock<HashMap<String, (u32, Option<SystemTime>)>>>,
+}
+
+impl EphemeralTimers {
+    /// Create an empty timer store
+    pub fn new() -> Self {
+        Self {
+            timers: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+    
+    /// Set the timer of a chat, 0 turns disappearing messages off.
+    ///
+    /// Returns `false` if a newer setting is already in place.
+    pub async fn set_timer(&self, chat: &JID, expiration: u32, setting_timestamp: Option<SystemTime>) -> bool {
+        let mut timers = self.timers.write().await;
+        if let Some((_, Some(current))) = timers.get(&chat.to_non_ad().to_string()) {
+            if setting_timestamp.map_or(false, |at| at < *current) {
+                debug!("Ignoring outdated ephemeral setting for {}", chat);
+                return false;
+            }
+        }
+        timers.insert(chat.to_non_ad().to_string(), (expiration, setting_timestamp));
+        true
+    }
+    
+    /// Get the active timer of a chat in seconds
+    pub async fn get_timer(&self, chat: &JID) -> Option<u32> {
+        let timers = self.timers.read().await;
+        timers.get(&chat.to_non_ad().to_string())
+            .map(|(expiration, _)| *expiration)
+            .filter(|expiration| *expiration > 0)
+    }
+}
+
+impl Default for EphemeralTimers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn same_user(a: &JID, b: &JID) -> bool {
+    a.user == b.user && a.server == b.server
 }
 
 impl Default for MessageThreadManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Derive the key and additional data of the votes of `voter` on a poll
+fn poll_vote_key(poll_id: &str, poll_creator: &JID, voter: &JID, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let creator = poll_creator.to_non_ad().to_string();
+    let voter = voter.to_non_ad().to_string();
+    let use_case = [poll_id, &creator, &voter, "Poll Vote"].concat();
+    let key = hkdf_expand(secret, use_case.as_bytes(), 32)?;
+    Ok((key, format!("{}\x00{}", poll_id, voter).into_bytes()))
+}
+
+/// Decrypt a vote of `voter` on the poll with ID `poll_id` sent by `poll_creator`.
+///
+/// The selected options are returned as hex-encoded SHA-256 hashes of the
+/// option names, as accepted by [`PollResultsTracker::apply_vote`].
+pub fn decrypt_poll_vote(encrypted: &EncryptedPollVote, poll_id: &str, poll_creator: &JID, voter: &JID, secret: &[u8]) -> Result<PollVote> {
+    let (key, aad) = poll_vote_key(poll_id, poll_creator, voter, secret)?;
+    let payload = AesGcm::new(&key)?.decrypt_with_aad(&encrypted.iv, &encrypted.payload, &aad)?;
+    let vote = <wa_e2e::PollVoteMessage as prost::Message>::decode(payload.as_slice())?;
+    Ok(PollVote {
+        selected_options: vote.selected_options.iter().map(hex::encode).collect(),
+    })
+}
+
+/// Encrypt a vote for the options named `selected`, see [`decrypt_poll_vote`]
+pub fn encrypt_poll_vote(selected: &[String], poll_id: &str, poll_creator: &JID, voter: &JID, secret: &[u8]) -> Result<EncryptedPollVote> {
+    let (key, aad) = poll_vote_key(poll_id, poll_creator, voter, secret)?;
+    let vote = wa_e2e::PollVoteMessage {
+        selected_options: selected.iter().map(|name| Sha256::digest(name.as_bytes()).to_vec()).collect(),
+    };
+    let iv = random_bytes(12);
+    let payload = AesGcm::new(&key)?.encrypt_with_aad(&iv, &prost::Message::encode_to_vec(&vote), &aad)?;
+    Ok(EncryptedPollVote { payload, iv })
+}
+
+/// Aggregated results of a poll
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollResults {
+    pub poll_key: MessageKey,
+    pub question: String,
+    pub selectable_options_count: u32,
+    pub options: Vec<PollOptionResult>,
+    /// Number of distinct participants with a non-empty vote
+    pub total_voters: usize,
+}
+
+/// Vote tally for a single poll option
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollOptionResult {
+    pub name: String,
+    pub vote_count: usize,
+    /// Participants who selected this option, empty if voters are hidden
+    pub voters: Vec<JID>,
+}
+
+/// A tracked poll as persisted, see [`PollResultsTracker::restore`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPoll {
+    pub key: MessageKey,
+    pub poll: PollMessage,
+    pub votes: Vec<StoredPollVote>,
+}
+
+/// Latest vote of a participant on a poll, with the options resolved to their names
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredPollVote {
+    pub voter: JID,
+    pub selected: Vec<String>,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Internal tally state for a tracked poll
+struct PollTally {
+    key: MessageKey,
+    poll: PollMessage,
+    /// Latest vote per voter, with the sender timestamp of that vote
+    votes: HashMap<JID, (Vec<String>, Option<SystemTime>)>,
+}
+
+/// Tracks poll votes and aggregates them into per-option results
+pub struct PollResultsTracker {
+    polls: Arc<RwLock<HashMap<(String, String), PollTally>>>,
+    include_voters: bool,
+}
+
+impl PollResultsTracker {
+    /// Create a new poll tracker that records voter lists
+    pub fn new() -> Self {
+        Self {
+            polls: Arc::new(RwLock::new(HashMap::new())),
+            include_voters: true,
+        }
+    }
+    
+    /// Create a tracker that only records vote counts, without voter lists
+    pub fn without_voters() -> Self {
+        Self {
+            include_voters: false,
+            ..Self::new()
+        }
+    }
+    
+    fn poll_id(key: &MessageKey) -> (String, String) {
+        (key.remote_jid.to_non_ad().to_string(), key.id.clone())
+    }
+    
+    /// Start tracking a poll (sent or received), returning `false` if it is tracked already
+    pub async fn register_poll(&self, key: MessageKey, poll: PollMessage) -> bool {
+        let mut polls = self.polls.write().await;
+        let id = Self::poll_id(&key);
+        if polls.contains_key(&id) {
+            return false;
+        }
+        polls.insert(id, PollTally {
+            key,
+            poll,
+            votes: HashMap::new(),
+        });
+        true
+    }
+    
+    /// Apply a vote to its poll, replacing any earlier vote from the same voter.
+    ///
+    /// Selected options may be given either as option names or as the hex-encoded
+    /// SHA-256 hash of the option name, which is how WhatsApp transmits them.
+    /// Returns the updated results, or `None` if the poll is not tracked.
+    pub async fn apply_vote(&self, voter: &JID, update: &PollUpdateMessage) -> Option<PollResults> {
+        let mut polls = self.polls.write().await;
+        let tally = polls.get_mut(&Self::poll_id(&update.poll_creation_message_key))?;
+        
+        let voter = JID::new(voter.user.clone(), voter.server.clone());
+        if let Some((_, Some(previous))) = tally.votes.get(&voter) {
+            if let Some(current) = update.sender_timestamp {
+                if current < *previous {
+                    debug!("Ignoring outdated poll vote from {}", voter);
+                    return Some(self.build_results(tally));
+                }
+            }
+        }
+        
+        let selected = update.vote.selected_options.iter()
+            .filter_map(|option| Self::resolve_option(&tally.poll, option))
+            .collect();
+        tally.votes.insert(voter, (selected, update.sender_timestamp));
+        
+        Some(self.build_results(tally))
+    }
+    
+    /// Get a tracked poll and the key it was registered with
+    pub async fn get_poll(&self, poll_key: &MessageKey) -> Option<(MessageKey, PollMessage)> {
+        let polls = self.polls.read().await;
+        polls.get(&Self::poll_id(poll_key)).map(|tally| (tally.key.clone(), tally.poll.clone()))
+    }
+    
+    /// Get the vote of `voter` currently counted for a poll
+    pub async fn vote_of(&self, poll_key: &MessageKey, voter: &JID) -> Option<StoredPollVote> {
+        let polls = self.polls.read().await;
+        let voter = voter.to_non_ad();
+        let (selected, timestamp) = polls.get(&Self::poll_id(poll_key))?.votes.get(&voter)?.clone();
+        Some(StoredPollVote { voter, selected, timestamp })
+    }
+    
+    /// Load persisted polls, replacing the tallies of polls tracked already
+    pub async fn restore(&self, stored: Vec<StoredPoll>) {
+        let mut polls = self.polls.write().await;
+        for stored in stored {
+            let votes = stored.votes.into_iter()
+                .map(|vote| (vote.voter.to_non_ad(), (vote.selected, vote.timestamp)))
+                .collect();
+            polls.insert(Self::poll_id(&stored.key), PollTally {
+                key: stored.key,
+                poll: stored.poll,
+                votes,
+            });
+        }
+    }
+    
+    /// Get the current results of a poll
+    pub async fn get_results(&self, poll_key: &MessageKey) -> Option<PollResults> {
+        let polls = self.polls.read().await;
+        polls.get(&Self::poll_id(poll_key)).map(|tally| self.build_results(tally))
+    }
+    
+    /// Stop tracking a poll
+    pub async fn remove_poll(&self, poll_key: &MessageKey) -> bool {
+        let mut polls = self.polls.write().await;
+        polls.remove(&Self::poll_id(poll_key)).is_some()
+    }
+    
+    fn resolve_option(poll: &PollMessage, selected: &str) -> Option<String> {
+        poll.options.iter()
+            .find(|option| {
+                option.name == selected
+                    || hex::encode(Sha256::digest(option.name.as_bytes())).eq_ignore_ascii_case(selected)
+            })
+            .map(|option| option.name.clone())
+    }
+    
+    fn build_results(&self, tally: &PollTally) -> PollResults {
+        let mut voters: Vec<&JID> = tally.votes.keys().collect();
+        voters.sort();
+        
+        let options = tally.poll.options.iter()
+            .map(|option| {
+                let option_voters: Vec<JID> = voters.iter()
+                    .filter(|voter| tally.votes[**voter].0.contains(&option.name))
+                    .map(|voter| (*voter).clone())
+                    .collect();
+                PollOptionResult {
+                    name: option.name.clone(),
+                    vote_count: option_voters.len(),
+                    voters: if self.include_voters { option_voters } else { Vec::new() },
+                }
+            })
+            .collect();
+        
+        PollResults {
+            poll_key: tally.key.clone(),
+            question: tally.poll.name.clone(),
+            selectable_options_count: tally.poll.selectable_options_count,
+            options,
+            total_voters: tally.votes.values().filter(|(selected, _)| !selected.is_empty()).count(),
+        }
+    }
+}
+
+impl Default for PollResultsTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Aggregated reactions on a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReactions {
+    pub message_key: MessageKey,
+    /// Reactions grouped by emoji, most used first
+    pub reactions: Vec<ReactionCount>,
+}
+
+impl MessageReactions {
+    /// Total number of users currently reacting
+    pub fn total(&self) -> usize {
+        self.reactions.iter().map(|r| r.count).sum()
+    }
+    
+    /// The emoji a user currently reacts with
+    pub fn reaction_of(&self, sender: &JID) -> Option<&str> {
+        self.reactions.iter()
+            .find(|r| r.senders.iter().any(|s| s.user == sender.user && s.server == sender.server))
+            .map(|r| r.emoji.as_str())
+    }
+}
+
+/// Users reacting with a single emoji
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: usize,
+    pub senders: Vec<JID>,
+}
+
+/// A reaction of one sender, as saved by [`ReactionTracker::snapshot`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReaction {
+    pub key: MessageKey,
+    pub sender: JID,
+    pub emoji: String,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Internal reaction state of a message
+struct ReactionState {
+    key: MessageKey,
+    /// Latest reaction per sender, an empty emoji marks a removed reaction
+    by_sender: HashMap<JID, (String, Option<SystemTime>)>,
+}
+
+/// Applies reaction messages to the messages they target.
+///
+/// Each user has at most one reaction per message: a new reaction replaces the
+/// previous one and a reaction with empty text removes it.
+pub struct ReactionTracker {
+    messages: Arc<RwLock<HashMap<(String, String), ReactionState>>>,
+}
+
+impl ReactionTracker {
+    /// Create a new reaction tracker
+    pub fn new() -> Self {
+        Self {
+            messages: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+    
+    fn message_id(key: &MessageKey) -> (String, String) {
+        (key.remote_jid.to_non_ad().to_string(), key.id.clone())
+    }
+    
+    /// Apply a reaction from `sender` and return the updated reactions.
+    ///
+    /// Reactions older than the sender's current one are ignored.
+    pub async fn apply_reaction(&self, sender: &JID, reaction: &ReactionMessage) -> MessageReactions {
+        let mut messages = self.messages.write().await;
+        let state = messages.entry(Self::message_id(&reaction.key)).or_insert_with(|| ReactionState {
+            key: reaction.key.clone(),
+            by_sender: HashMap::new(),
+        });
+        
+        let sender = JID::new(sender.user.clone(), sender.server.clone());
+        if let Some((_, Some(previous))) = state.by_sender.get(&sender) {
+            if let Some(current) = reaction.sender_timestamp {
+                if current < *previous {
+                    debug!("Ignoring outdated reaction from {}", sender);
+                    return Self::build_reactions(state);
+                }
+            }
+        }
+        
+        state.by_sender.insert(sender, (reaction.text.clone(), reaction.sender_timestamp));
+        Self::build_reactions(state)
+    }
+    
+    /// Get the current reactions of a message
+    pub async fn get_reactions(&self, message_key: &MessageKey) -> Option<MessageReactions> {
+        let messages = self.messages.read().await;
+        messages.get(&Self::message_id(message_key)).map(Self::build_reactions)
+    }
+    
+    /// Forget the reactions of a message, e.g. after it was deleted
+    pub async fn remove_message(&self, message_key: &MessageKey) -> bool {
+        let mut messages = self.messages.write().await;
+        messages.remove(&Self::message_id(message_key)).is_some()
+    }
+    
+    /// Every reaction currently tracked, for saving across restarts
+    pub async fn snapshot(&self) -> Vec<StoredReaction> {
+        let messages = self.messages.read().await;
+        messages.values()
+            .flat_map(|state| state.by_sender.iter().map(|(sender, (emoji, timestamp))| StoredReaction {
+                key: state.key.clone(),
+                sender: sender.clone(),
+                emoji: emoji.clone(),
+                timestamp: *timestamp,
+            }))
+            .collect()
+    }
+    
+    /// Load reactions saved by [`snapshot`](Self::snapshot)
+    ///
+    /// Reactions already tracked win over older saved ones.
+    pub async fn restore(&self, reactions: Vec<StoredReaction>) {
+        for stored in reactions {
+            let reaction = ReactionMessage {
+                key: stored.key,
+                text: stored.emoji,
+                sender_timestamp: stored.timestamp,
+            };
+            self.apply_reaction(&stored.sender, &reaction).await;
+        }
+    }
+    
+    fn build_reactions(state: &ReactionState) -> MessageReactions {
+        let mut by_emoji: HashMap<&str, Vec<JID>> = HashMap::new();
+        for (sender, (emoji, _)) in &state.by_sender {
+            if !emoji.is_empty() {
+                by_emoji.entry(emoji.as_str()).or_default().push(sender.clone());
+            }
+        }
+        
+        let mut reactions: Vec<ReactionCount> = by_emoji.into_iter()
+            .map(|(emoji, mut senders)| {
+                senders.sort();
+                ReactionCount {
+                    emoji: emoji.to_string(),
+                    count: senders.len(),
+                    senders,
+                }
+            })
+            .collect();
+        reactions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.emoji.cmp(&b.emoji)));
+        
+        MessageReactions {
+            message_key: state.key.clone(),
+            reactions,
+        }
+    }
+}
+
+impl Default for ReactionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Outgoing message format with `{placeholder}` substitution
+///
+/// Placeholders are ASCII letters, digits and underscores; `{{` and `}}`
+/// stand for literal braces. Values are inserted verbatim and never parsed
+/// again, so user supplied values cannot inject further placeholders.
+/// Variants for other locales must use the same placeholders as the default
+/// body, which [`TemplateRegistry::register`] checks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageTemplate {
+    body: String,
+    locales: HashMap<String, String>,
+}
+
+enum TemplateSegment<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+impl MessageTemplate {
+    /// Create a template from its default body
+    pub fn new(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            locales: HashMap::new(),
+        }
+    }
+    
+    /// Add the body used for a locale such as `pt_BR` or `de`
+    pub fn with_locale(mut self, locale: &str, body: impl Into<String>) -> Self {
+        self.locales.insert(normalize_locale(locale), body.into());
+        self
+    }
+    
+    /// Placeholders used by the default body
+    pub fn placeholders(&self) -> Result<BTreeSet<String>> {
+        template_placeholders(&self.body)
+    }
+    
+    /// Check the syntax of every body and that all locales use the same placeholders
+    pub fn validate(&self) -> Result<()> {
+        let placeholders = self.placeholders()?;
+        for (locale, body) in &self.locales {
+            if template_placeholders(body)? != placeholders {
+                return Err(Error::Protocol(format!(
+                    "Template variant {} does not use the placeholders {:?}", locale, placeholders
+                )));
+            }
+        }
+        Ok(())
+    }
+    
+    /// Body for a locale, falling back from `pt_BR` to `pt` to the default body
+    pub fn body_for(&self, locale: Option<&str>) -> &str {
+        let Some(locale) = locale.map(normalize_locale) else {
+            return &self.body;
+        };
+        let language = locale.split('_').next().unwrap_or_default();
+        self.locales.get(&locale)
+            .or_else(|| self.locales.get(language))
+            .unwrap_or(&self.body)
+    }
+    
+    /// Render the template, failing if a placeholder has no value
+    pub fn render(&self, locale: Option<&str>, values: &HashMap<String, String>) -> Result<String> {
+        let body = self.body_for(locale);
+        let mut rendered = String::with_capacity(body.len());
+        for segment in parse_template(body)? {
+            match segment {
+                TemplateSegment::Literal(text) => rendered.push_str(text),
+                TemplateSegment::Placeholder(name) => {
+                    let value = values.get(name)
+                        .ok_or_else(|| Error::Protocol(format!("No value for template placeholder {}", name)))?;
+                    rendered.push_str(value);
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('-', "_").to_ascii_lowercase()
+}
+
+fn template_placeholders(body: &str) -> Result<BTreeSet<String>> {
+    Ok(parse_template(body)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            TemplateSegment::Placeholder(name) => Some(name.to_string()),
+            TemplateSegment::Literal(_) => None,
+        })
+        .collect())
+}
+
+fn parse_template(body: &str) -> Result<Vec<TemplateSegment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+    while let Some(index) = rest.find(['{', '}']) {
+        if index > 0 {
+            segments.push(TemplateSegment::Literal(&rest[..index]));
+        }
+        let brace = &rest[index..index + 1];
+        let after = &rest[index + 1..];
+        if after.starts_with(brace) {
+            segments.push(TemplateSegment::Literal(brace));
+            rest = &after[1..];
+            continue;
+        }
+        if brace == "}" {
+            return Err(Error::Protocol(format!("Unmatched '}}' in template {:?}", body)));
+        }
+        let end = after.find('}')
+            .ok_or_else(|| Error::Protocol(format!("Unclosed placeholder in template {:?}", body)))?;
+        let name = &after[..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::Protocol(format!("Invalid placeholder {{{}}} in template", name)));
+        }
+        segments.push(TemplateSegment::Placeholder(name));
+        rest = &after[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(TemplateSegment::Literal(rest));
+    }
+    Ok(segments)
+}
+
+/// Named message templates, validated when they are registered
+#[derive(Debug, Clone, Default)]
+pub struct TemplateRegistry {
+    templates: HashMap<String, MessageTemplate>,
+}
+
+impl TemplateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Register a template under `name`, replacing any template of that name
+    pub fn register(&mut self, name: impl Into<String>, template: MessageTemplate) -> Result<()> {
+        template.validate()?;
+        self.templates.insert(name.into(), template);
+        Ok(())
+    }
+    
+    pub fn get(&self, name: &str) -> Option<&MessageTemplate> {
+        self.templates.get(name)
+    }
+    
+    pub fn remove(&mut self, name: &str) -> Option<MessageTemplate> {
+        self.templates.remove(name)
+    }
+    
+    /// Render a registered template
+    pub fn render(&self, name: &str, locale: Option<&str>, values: &HashMap<String, String>) -> Result<String> {
+        self.templates.get(name)
+            .ok_or_else(|| Error::Protocol(format!("Unknown message template {}", name)))?
+            .render(locale, values)
+    }
+    
+    /// Render a registered template as a text message
+    pub fn render_message(&self, name: &str, locale: Option<&str>, values: &HashMap<String, String>) -> Result<SendableMessage> {
+        let text = self.render(name, locale, values)?;
+        Ok(SendableMessage::Text(TextMessage { text }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn poll_key() -> MessageKey {
+        MessageKey {
+            remote_jid: JID::new_group("poll-group"),
+            from_me: true,
+            id: "POLL1".to_string(),
+            participant: None,
+        }
+    }
+    
+    fn vote(options: &[&str], at: u64) -> PollUpdateMessage {
+        PollUpdateMessage {
+            poll_creation_message_key: poll_key(),
+            vote: PollVote {
+                selected_options: options.iter().map(|o| o.to_string()).collect(),
+            },
+            sender_timestamp: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(at)),
+            encrypted_vote: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_poll_results_aggregation() {
+        let tracker = PollResultsTracker::new();
+        tracker.register_poll(poll_key(), PollMessage {
+            name: "Lunch?".to_string(),
+            options: vec![PollOption { name: "Pizza".to_string() }, PollOption { name: "Sushi".to_string() }],
+            selectable_options_count: 1,
+            context_info: None,
+            message_secret: None,
+        }).await;
+        
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        
+        tracker.apply_vote(&alice, &vote(&["Pizza"], 10)).await.unwrap();
+        let sushi_hash = hex::encode(Sha256::digest(b"Sushi"));
+        tracker.apply_vote(&bob, &vote(&[&sushi_hash], 11)).await.unwrap();
+        
+        // Alice changes her vote, then an outdated vote arrives
+        tracker.apply_vote(&alice, &vote(&["Sushi"], 12)).await.unwrap();
+        let results = tracker.apply_vote(&alice, &vote(&["Pizza"], 5)).await.unwrap();
+        
+        assert_eq!(results.total_voters, 2);
+        assert_eq!(results.options[0].vote_count, 0);
+        assert_eq!(results.options[1].vote_count, 2);
+        assert_eq!(results.options[1].voters, vec![alice.clone(), bob]);
+        
+        // Retracting a vote removes the voter from the totals
+        let results = tracker.apply_vote(&alice, &vote(&[], 13)).await.unwrap();
+        assert_eq!(results.total_voters, 1);
+        assert_eq!(tracker.get_results(&poll_key()).await.unwrap().options[1].vote_count, 1);
+    }
+    
+    fn reaction(emoji: &str, at: u64) -> ReactionMessage {
+        ReactionMessage {
+            key: poll_key(),
+            text: emoji.to_string(),
+            sender_timestamp: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(at)),
+        }
+    }
+    
+    fn stored_message(chat: &JID, sender: &JID, sent_at: u64) -> MessageInfo {
+        MessageInfo {
+            id: "MSG1".to_string(),
+            chat: chat.clone(),
+            sender: sender.clone(),
+            sender_alt: None,
+            push_name: None,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(sent_at),
+            message_type: MessageType::Text,
+            from_me: false,
+            edited_at: None,
+            revoked: false,
+            quoted: None,
+            mentioned_jids: Vec::new(),
+            mentions_all: false,
+            group_mentions: Vec::new(),
+            forwarding_score: None,
+            content: None,
+            starred: false,
+            kept: false,
+        }
+    }
+    
+    #[test]
+    fn test_apply_edit_window() {
+        let sender = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let other = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let mut threads = MessageThreadManager::new();
+        threads.add_to_thread("111@s.whatsapp.net", stored_message(&sender, &sender, 1000));
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        
+        assert!(threads.apply_edit("111@s.whatsapp.net", "MSG1", &other, at(1010)).is_err());
+        assert!(threads.apply_edit("111@s.whatsapp.net", "MSG1", &sender, at(1000 + 16 * 60)).is_err());
+        let edited = threads.apply_edit("111@s.whatsapp.net", "MSG1", &sender.with_device(2), at(1010)).unwrap().unwrap();
+        assert_eq!(edited.edited_at, Some(at(1010)));
+        assert!(threads.apply_edit("111@s.whatsapp.net", "OTHER", &sender, at(1010)).unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_prepend_history() {
+        let sender = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let mut threads = MessageThreadManager::new();
+        threads.add_to_thread("111@s.whatsapp.net", stored_message(&sender, &sender, 1000));
+        let older = MessageInfo { id: "MSG0".to_string(), ..stored_message(&sender, &sender, 900) };
+        
+        assert_eq!(threads.prepend_history("111@s.whatsapp.net", vec![older, stored_message(&sender, &sender, 1000)]), 1);
+        let thread = threads.get_thread("111@s.whatsapp.net").unwrap();
+        assert_eq!(thread.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), ["MSG0", "MSG1"]);
+        assert_eq!(threads.get_recent_messages("111@s.whatsapp.net", 1)[0].id, "MSG1");
+    }
+    
+    #[test]
+    fn test_apply_revoke() {
+        let sender = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let admin = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let group = JID::new_group("group");
+        let mut threads = MessageThreadManager::new();
+        threads.add_to_thread("111@s.whatsapp.net", stored_message(&sender, &sender, 1000));
+        threads.add_to_thread("group@g.us", stored_message(&group, &sender, 1000));
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        
+        // Only the sender may delete in private chats, admins too in groups
+        assert!(threads.apply_revoke("111@s.whatsapp.net", "MSG1", &admin, at(1010)).is_err());
+        assert!(threads.apply_revoke("group@g.us", "MSG1", &admin, at(1010)).unwrap().unwrap().revoked);
+        assert!(threads.apply_revoke("111@s.whatsapp.net", "MSG1", &sender, at(1000) + REVOKE_WINDOW * 2).is_err());
+        assert!(threads.apply_revoke("111@s.whatsapp.net", "MSG1", &sender, at(1010)).unwrap().unwrap().revoked);
+        
+        // Deleted messages cannot be edited
+        assert!(threads.apply_edit("111@s.whatsapp.net", "MSG1", &sender, at(1020)).is_err());
+    }
+    
+    #[test]
+    fn test_process_message_context() {
+        let node = Node::new("message".to_string())
+            .attr("id".to_string(), "MSG2".to_string())
+            .attr("from".to_string(), "111@s.whatsapp.net".to_string())
+            .attr("to".to_string(), "group@g.us".to_string())
+            .attr("t".to_string(), "1700000000".to_string())
+            .with_children(vec![Node::new("contextInfo".to_string())
+                .attr("forwardingScore".to_string(), "4".to_string())
+                .with_children(vec![
+                    Node::new("quotedMessage".to_string())
+                        .attr("id".to_string(), "MSG1".to_string())
+                        .attr("remoteJid".to_string(), "group@g.us".to_string())
+                        .attr("participant".to_string(), "222@s.whatsapp.net".to_string())
+                        .with_text("original".to_string()),
+                    Node::new("mentionedJid".to_string()).with_text("222@s.whatsapp.net".to_string()),
+                ])]);
+        
+        let info = MessageProcessor::process_message(&node).unwrap();
+        let quoted = info.quoted.unwrap();
+        assert_eq!(quoted.id, "MSG1");
+        assert_eq!(quoted.text.as_deref(), Some("original"));
+        assert_eq!(quoted.participant.unwrap().user, "222");
+        assert_eq!(info.mentioned_jids.len(), 1);
+        assert_eq!(info.forwarding_score, Some(4));
+        assert!(info.sender_alt.is_none());
+        
+        let node = node
+            .attr("participant_lid".to_string(), "8888@lid".to_string())
+            .attr("notify".to_string(), "Alice".to_string());
+        let info = MessageProcessor::process_message(&node).unwrap();
+        assert_eq!(info.sender_alt, Some(JID::new_lid("8888")));
+        assert_eq!(info.push_name.as_deref(), Some("Alice"));
+    }
+    
+    #[test]
+    fn test_mention_all_round_trip() {
+        let group = JID::new("123-456".to_string(), "g.us".to_string());
+        let community_group = JID::new("789-012".to_string(), "g.us".to_string());
+        let me = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let node = MessageBuilder::new(group.clone())
+            .mention_all()
+            .mention_group(community_group.clone(), "Neighbours".to_string())
+            .text("@all meeting at 8".to_string())
+            .build("MSG3".to_string(), me)
+            .unwrap();
+        
+        let info = MessageProcessor::process_message(&node).unwrap();
+        assert!(info.mentions_all);
+        assert!(info.mentioned_jids.is_empty());
+        assert_eq!(info.group_mentions, vec![GroupMention {
+            group_jid: community_group,
+            group_subject: "Neighbours".to_string(),
+        }]);
+        
+        let plain = MessageBuilder::new(group.clone())
+            .text("hi".to_string())
+            .build("MSG4".to_string(), group)
+            .unwrap();
+        let info = MessageProcessor::process_message(&plain).unwrap();
+        assert!(!info.mentions_all);
+        assert!(info.group_mentions.is_empty());
+    }
+    
+    #[test]
+    fn test_forward_message() {
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let node = MessageBuilder::new(bob.clone())
+            .text("look at this".to_string())
+            .build("MSG7".to_string(), alice.clone())
+            .unwrap();
+        let original = MessageProcessor::process_message(&node).unwrap();
+        assert!(original.forwarding_score.is_none());
+        
+        let forwarded = forward_content(&original).unwrap();
+        let context = forwarded.context_info().unwrap().clone();
+        assert_eq!(context.forwarding_score, Some(1));
+        let SendableMessage::ExtendedText(text) = &forwarded else { panic!("text must be forwarded as extended text") };
+        let node = MessageBuilder::new(alice.clone())
+            .with_context(context)
+            .extended_text(text.text.clone())
+            .build("MSG8".to_string(), bob.clone())
+            .unwrap();
+        let mut received = MessageProcessor::process_message(&node).unwrap();
+        assert_eq!(received.forwarding_score, Some(1));
+        assert!(matches!(&received.content, Some(SendableMessage::ExtendedText(ext)) if ext.text == "look at this"));
+        assert!(check_forward_targets(&received, MAX_FORWARD_CHATS).is_ok());
+        assert!(check_forward_targets(&received, MAX_FORWARD_CHATS + 1).is_err());
+        
+        // From the fifth hop on the message is frequently forwarded and goes to one chat at a time
+        received.forwarding_score = Some(FREQUENTLY_FORWARDED_THRESHOLD - 1);
+        let score = forward_content(&received).unwrap().context_info().unwrap().forwarding_score.unwrap();
+        assert!(is_frequently_forwarded(score));
+        received.forwarding_score = Some(score);
+        assert!(check_forward_targets(&received, 1).is_ok());
+        assert!(matches!(check_forward_targets(&received, 2), Err(Error::SendBlocked(_))));
+        
+        // Own messages are forwarded without the label, reactions not at all
+        let own = MessageInfo { from_me: true, ..original.clone() };
+        let context = forward_content(&own).unwrap().context_info().cloned().unwrap();
+        assert_eq!((context.forwarding_score, context.is_forwarded), (None, None));
+        let reaction = MessageInfo {
+            content: Some(SendableMessage::Reaction(reaction("👍", 1))),
+            ..original
+        };
+        assert!(forward_content(&reaction).is_err());
+    }
+    
+    #[test]
+    fn test_star_and_keep_in_chat() {
+        let chat = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let me = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let key = MessageKey { remote_jid: chat.clone(), from_me: false, id: "MSG1".to_string(), participant: None };
+        let node = MessageBuilder::new(chat.clone())
+            .keep_in_chat(KeepInChatMessage { key: key.clone(), keep: true, timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1000) })
+            .build("MSG9".to_string(), me)
+            .unwrap();
+        let info = MessageProcessor::process_message(&node).unwrap();
+        assert_eq!(info.message_type, MessageType::KeepInChat);
+        let Some(SendableMessage::KeepInChat(keep)) = info.content else { panic!("keep-in-chat content expected") };
+        assert!(keep.keep);
+        assert_eq!(keep.key.id, "MSG1");
+        assert_eq!(keep.key.remote_jid, chat);
+        assert_eq!(keep.timestamp, SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        
+        let mut threads = MessageThreadManager::new();
+        let chat_id = chat.to_string();
+        threads.add_to_thread(&chat_id, stored_message(&chat, &chat, 1000));
+        threads.schedule_expiry(&chat_id, "MSG1", SystemTime::UNIX_EPOCH + Duration::from_secs(2000));
+        
+        assert!(threads.set_starred(&chat_id, "MSG1", true).unwrap().starred);
+        assert_eq!(threads.get_starred_messages(&chat_id).len(), 1);
+        assert!(threads.set_starred(&chat_id, "MISSING", true).is_none());
+        
+        // A kept message survives its expiry
+        assert!(threads.apply_keep(&chat_id, "MSG1", true).unwrap().kept);
+        assert!(threads.remove_expired(SystemTime::UNIX_EPOCH + Duration::from_secs(3000)).is_empty());
+        assert!(threads.find_message(&chat_id, "MSG1").is_some());
+        
+        threads.set_starred(&chat_id, "MSG1", false);
+        assert!(threads.get_starred_messages(&chat_id).is_empty());
+        
+        // Clearing keeps starred messages only if asked to
+        threads.set_starred(&chat_id, "MSG1", true);
+        assert_eq!(threads.clear_thread(&chat_id, true), 0);
+        assert_eq!(threads.clear_thread(&chat_id, false), 1);
+        assert!(threads.get_thread(&chat_id).is_none());
+    }
+    
+    #[test]
+    fn test_contacts_array_message() {
+        let to = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let me = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let contacts = vec![
+            crate::vcard::VCard::new("Alice").phone("+1 555 111 2222").to_contact_message(),
+            crate::vcard::VCard::new("Bob").phone("+1 555 333 4444").to_contact_message(),
+        ];
+        let node = MessageBuilder::new(to.clone())
+            .contacts_array(ContactsArrayMessage::new(contacts))
+            .build("MSG5".to_string(), me.clone())
+            .unwrap();
+        
+        assert_eq!(node.get_attr("type").map(|s| s.as_str()), Some("contactsArray"));
+        let array = node.find_child("contactsArrayMessage").unwrap();
+        assert_eq!(array.get_attr("displayName").map(|s| s.as_str()), Some("2 contacts"));
+        let names: Vec<_> = array.get_children().unwrap().iter()
+            .filter_map(|c| c.get_attr("displayName").cloned())
+            .collect();
+        assert_eq!(names, vec!["Alice".to_string(), "Bob".to_string()]);
+        assert_eq!(MessageProcessor::process_message(&node).unwrap().message_type, MessageType::ContactsArray);
+        
+        let empty = MessageBuilder::new(to)
+            .contacts_array(ContactsArrayMessage::new(Vec::new()))
+            .build("MSG6".to_string(), me);
+        assert!(empty.is_err());
+    }
+    
+    #[test]
+    fn test_resolve_mentions() {
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("1112".to_string(), "s.whatsapp.net".to_string());
+        let carol = JID::new("333".to_string(), "s.whatsapp.net".to_string());
+        let names = HashMap::from([(alice.clone(), "Alice".to_string()), (bob.clone(), "Bob".to_string())]);
+        
+        let text = "hi @111 and @1112, also @333";
+        assert_eq!(resolve_mentions(text, &[alice, bob, carol], &names), "hi @Alice and @Bob, also @333");
+    }
+    
+    #[test]
+    fn test_remove_expired_messages() {
+        let sender = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let mut threads = MessageThreadManager::new();
+        threads.add_to_thread("111@s.whatsapp.net", stored_message(&sender, &sender, 1000));
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        threads.schedule_expiry("111@s.whatsapp.net", "MSG1", at(2000));
+        
+        assert!(threads.remove_expired(at(1999)).is_empty());
+        assert_eq!(threads.remove_expired(at(2000)).len(), 1);
+        assert!(threads.find_message("111@s.whatsapp.net", "MSG1").is_none());
+        assert!(threads.remove_expired(at(3000)).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_ephemeral_timers() {
+        let timers = EphemeralTimers::new();
+        let chat = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let at = |secs: u64| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        
+        assert!(timers.set_timer(&chat, 86400, at(10)).await);
+        assert_eq!(timers.get_timer(&chat.with_device(1)).await, Some(86400));
+        assert!(!timers.set_timer(&chat, 604800, at(5)).await);
+        assert!(timers.set_timer(&chat, 0, at(20)).await);
+        assert_eq!(timers.get_timer(&chat).await, None);
+    }
+    
+    #[test]
+    fn test_context_ephemeral_setting() {
+        let chat = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let context = ContextInfo {
+            quoted_message: None,
+            mentioned_jids: Vec::new(),
+            non_jid_mentions: None,
+            group_mentions: Vec::new(),
+            forwarded: None,
+            forwarding_score: None,
+            is_forwarded: None,
+            ephemeral_setting: Some(86400),
+            ephemeral_shared_secret: None,
+            external_ad_reply: None,
+        };
+        let node = MessageBuilder::new(chat.clone())
+            .with_context(context)
+            .text("hi".to_string())
+            .build("ID".to_string(), chat)
+            .unwrap();
+        assert_eq!(node.get_attr("ephemeral").unwrap(), "86400");
+    }
+    
+    #[tokio::test]
+    async fn test_reaction_aggregation() {
+        let tracker = ReactionTracker::new();
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let carol = JID::new("333".to_string(), "s.whatsapp.net".to_string());
+        
+        tracker.apply_reaction(&alice, &reaction("👍", 10)).await;
+        tracker.apply_reaction(&bob.with_device(2), &reaction("👍", 11)).await;
+        tracker.apply_reaction(&carol, &reaction("❤️", 12)).await;
+        
+        // Alice switches emoji, then an outdated reaction arrives
+        tracker.apply_reaction(&alice, &reaction("❤️", 13)).await;
+        let reactions = tracker.apply_reaction(&alice, &reaction("👍", 5)).await;
+        
+        assert_eq!(reactions.total(), 3);
+        assert_eq!(reactions.reactions[0].emoji, "❤️");
+        assert_eq!(reactions.reactions[0].senders, vec![alice.clone(), carol]);
+        assert_eq!(reactions.reactions[1].senders, vec![bob.clone()]);
+        assert_eq!(reactions.reaction_of(&bob), Some("👍"));
+        
+        // Empty text removes the reaction
+        let reactions = tracker.apply_reaction(&bob, &reaction("", 14)).await;
+        assert_eq!(reactions.reactions.len(), 1);
+        assert_eq!(reactions.reaction_of(&bob), None);
+        
+        assert!(tracker.remove_message(&poll_key()).await);
+        assert!(tracker.get_reactions(&poll_key()).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_reaction_snapshot_restore() {
+        let tracker = ReactionTracker::new();
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        tracker.apply_reaction(&alice, &reaction("👍", 10)).await;
+        tracker.apply_reaction(&bob, &reaction("❤️", 11)).await;
+        
+        let saved = serde_json::to_string(&tracker.snapshot().await).unwrap();
+        let restored = ReactionTracker::new();
+        restored.apply_reaction(&alice, &reaction("😂", 20)).await;
+        restored.restore(serde_json::from_str(&saved).unwrap()).await;
+        
+        let reactions = restored.get_reactions(&poll_key()).await.unwrap();
+        assert_eq!(reactions.reaction_of(&alice), Some("😂"));
+        assert_eq!(reactions.reaction_of(&bob), Some("❤️"));
+    }
+    
+    #[tokio::test]
+    async fn test_untracked_poll_and_hidden_voters() {
+        let tracker = PollResultsTracker::without_voters();
+        let voter = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        assert!(tracker.apply_vote(&voter, &vote(&["Pizza"], 1)).await.is_none());
+        
+        tracker.register_poll(poll_key(), PollMessage {
+            name: "Q".to_string(),
+            options: vec![PollOption { name: "Pizza".to_string() }],
+            selectable_options_count: 1,
+            context_info: None,
+            message_secret: None,
+        }).await;
+        let results = tracker.apply_vote(&voter, &vote(&["Pizza"], 1)).await.unwrap();
+        assert_eq!(results.options[0].vote_count, 1);
+        assert!(results.options[0].voters.is_empty());
+        
+        assert!(tracker.remove_poll(&poll_key()).await);
+        assert!(tracker.get_results(&poll_key()).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_encrypted_poll_vote() {
+        let group = JID::new_group("poll-group");
+        let me = JID::new("222".to_string(), "s.whatsapp.net".to_string());
+        let alice = JID::new("111".to_string(), "s.whatsapp.net".to_string());
+        let secret = vec![7u8; 32];
+        
+        let node = MessageBuilder::new(group.clone())
+            .poll(PollMessage {
+                name: "Lunch?".to_string(),
+                options: vec![PollOption { name: "Pizza".to_string() }, PollOption { name: "Sushi".to_string() }],
+                selectable_options_count: 1,
+                context_info: None,
+                message_secret: Some(secret.clone()),
+            })
+            .build("POLL1".to_string(), me.clone())
+            .unwrap();
+        let Some(SendableMessage::Poll(poll)) = MessageProcessor::process_message(&node).unwrap().content else {
+            panic!("poll content expected")
+        };
+        assert_eq!(poll.options.len(), 2);
+        assert_eq!(poll.message_secret.as_deref(), Some(secret.as_slice()));
+        let tracker = PollResultsTracker::new();
+        assert!(tracker.register_poll(poll_key(), poll).await);
+        
+        // Alice's device encrypts the vote for the poll we created
+        let encrypted = encrypt_poll_vote(&["Sushi".to_string()], "POLL1", &me, &alice, &secret).unwrap();
+        let message = wa_e2e::Message {
+            poll_update_message: Some(wa_e2e::PollUpdateMessage {
+                poll_creation_message_key: Some(message_key_to_proto(&poll_key())),
+                vote: Some(wa_e2e::PollEncValue { enc_payload: Some(encrypted.payload), enc_iv: Some(encrypted.iv) }),
+                sender_timestamp_ms: Some(5000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let node = Node::new("message".to_string())
+            .attr("id".to_string(), "VOTE1".to_string())
+            .attr("from".to_string(), alice.to_string())
+            .attr("to".to_string(), group.to_string())
+            .attr("t".to_string(), "5".to_string())
+            .attr("type".to_string(), "pollUpdate".to_string())
+            .with_children(vec![Node::new("body".to_string()).with_binary(ProtoUtils::text_to_bytes(&message))]);
+        let Some(SendableMessage::PollUpdate(mut update)) = MessageProcessor::process_message(&node).unwrap().content else {
+            panic!("poll update content expected")
+        };
+        assert_eq!(update.sender_timestamp, Some(SystemTime::UNIX_EPOCH + Duration::from_secs(5)));
+        let encrypted = update.encrypted_vote.clone().unwrap();
+        
+        // The key is bound to the voter
+        assert!(decrypt_poll_vote(&encrypted, "POLL1", &me, &me, &secret).is_err());
+        update.vote = decrypt_poll_vote(&encrypted, "POLL1", &me, &alice, &secret).unwrap();
+        let results = tracker.apply_vote(&alice, &update).await.unwrap();
+        assert_eq!(results.options[1].voters, vec![alice.clone()]);
+        assert_eq!(tracker.vote_of(&poll_key(), &alice).await.unwrap().selected, vec!["Sushi".to_string()]);
+    }
+    
+    #[test]
+    fn test_message_queue_per_chat() {
+        let alice = JID::new("alice".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("bob".to_string(), "s.whatsapp.net".to_string());
+        let node = || Node::new("message".to_string());
+        
+        let mut queue = MessageQueue::new();
+        queue.enqueue("a1".to_string(), &alice, node());
+        queue.enqueue("b1".to_string(), &bob, node());
+        queue.enqueue("a2".to_string(), &alice, node());
+        assert_eq!(queue.len(), 3);
+        
+        let ids: Vec<_> = queue.pending().into_iter().map(|msg| msg.id).collect();
+        assert_eq!(ids, vec!["a1", "b1", "a2"]);
+        
+        let by_chat: Vec<Vec<_>> = queue
+            .pending_by_chat()
+            .into_iter()
+            .map(|chat| chat.into_iter().map(|msg| msg.id).collect())
+            .collect();
+        assert_eq!(by_chat, vec![vec!["a1", "a2"], vec!["b1"]]);
+        
+        queue.acknowledge("b1");
+        queue.mark_failed("a1", "rejected".to_string());
+        assert_eq!(queue.pending_by_chat().len(), 1);
+        assert!(queue.is_pending("a2"));
+        assert!(!queue.is_pending("b1"));
+        
+        // A retried message goes to the back of its chat
+        queue.retry_failed("a1").unwrap();
+        assert_eq!(queue.next().unwrap().id, "a2");
+        assert_eq!(queue.next().unwrap().id, "a1");
+        assert!(queue.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_chat_send_scheduler() {
+        let scheduler = Arc::new(ChatSendScheduler::new(2));
+        let alice = JID::new("alice".to_string(), "s.whatsapp.net".to_string());
+        let bob = JID::new("bob".to_string(), "s.whatsapp.net".to_string());
+        
+        // Different chats send in parallel
+        let first = scheduler.acquire(&alice).await;
+        let other_chat = scheduler.acquire(&bob).await;
+        assert_eq!(scheduler.available_slots(), 0);
+        drop(other_chat);
+        
+        // The same chat waits for the previous send, in request order
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for i in 0..3 {
+            let scheduler = scheduler.clone();
+            let alice = alice.clone();
+            let order = order.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(&alice).await;
+                order.lock().unwrap().push(i);
+            }));
+            tokio::task::yield_now().await;
+        }
+        tokio::task::yield_now().await;
+        assert!(order.lock().unwrap().is_empty());
+        
+        drop(first);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        
+        // Draining waits for in-flight sends
+        let in_flight = scheduler.acquire(&bob).await;
+        let drain = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.drain().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!drain.is_finished());
+        drop(in_flight);
+        drain.await.unwrap();
+        assert_eq!(scheduler.available_slots(), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_group_receipt_aggregation() {
+        let tracker = MessageStatusTracker::new();
+        let alice = JID::new_user("111");
+        let bob = JID::new_user("222");
+        tracker.expect_receipts("MSG1", &[alice.clone(), bob.clone()]).await;
+        
+        let receipt = |participant: &JID, status: MessageStatus| MessageReceipt {
+            message_id: "MSG1".to_string(),
+            status,
+            timestamp: SystemTime::now(),
+            participant: Some(participant.with_device(1)),
+        };
+        
+        assert!(tracker.process_receipt(&receipt(&alice, MessageStatus::Read)).await.is_none());
+        // A late delivery receipt does not downgrade a read message
+        assert!(tracker.process_receipt(&receipt(&alice, MessageStatus::Delivered)).await.is_none());
+        let summary = tracker.get_delivery_summary("MSG1").await.unwrap();
+        assert_eq!(summary, DeliverySummary { total: 2, pending: 1, delivered: 0, read: 1, played: 0 });
+        
+        let (status, summary) = tracker.process_receipt(&receipt(&bob, MessageStatus::Delivered)).await.unwrap();
+        assert_eq!(status, MessageStatus::Delivered);
+        assert!(summary.all_delivered() && !summary.all_read());
+        assert_eq!(tracker.get_status("MSG1").await, Some(MessageStatus::Delivered));
+        
+        assert!(tracker.process_receipt(&receipt(&bob, MessageStatus::Delivered)).await.is_none());
+        let (status, _) = tracker.process_receipt(&receipt(&bob, MessageStatus::Read)).await.unwrap();
+        assert_eq!(status, MessageStatus::Read);
+        assert_eq!(tracker.get_status("MSG1").await, Some(MessageStatus::Read));
+        
+        assert!(tracker.process_receipt(&receipt(&alice, MessageStatus::Played)).await.is_none());
+        let (status, summary) = tracker.process_receipt(&receipt(&bob, MessageStatus::Played)).await.unwrap();
+        assert_eq!(status, MessageStatus::Played);
+        assert_eq!(summary.played, 2);
+        assert_eq!(tracker.get_status("MSG1").await, Some(MessageStatus::Played));
+    }
+    
+    #[tokio::test]
+    async fn test_played_receipts() {
+        let chat = JID::new_user("111");
+        let node = Node::receipt("VOICE1", &chat).kind("played").timestamp(1700000000).more_ids(["VOICE2"]).build();
+        let receipts = parse_receipt(&node);
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[1].message_id, "VOICE2");
+        assert_eq!(receipts[0].status, MessageStatus::Played);
+        assert_eq!(receipts[0].timestamp, SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000));
+        
+        let own_device = Node::receipt("VOICE1", &chat).kind("played-self").build();
+        assert!(parse_receipt(&own_device).is_empty());
+        
+        // In a one-to-one chat a later read receipt keeps the played status
+        let tracker = MessageStatusTracker::new();
+        for receipt in receipts {
+            tracker.process_receipt(&receipt).await;
+        }
+        let read = Node::receipt("VOICE1", &chat).kind("read").build();
+        tracker.process_receipt(&parse_receipt(&read)[0]).await;
+        assert_eq!(tracker.get_status("VOICE1").await, Some(MessageStatus::Played));
+    }
+    
+    #[test]
+    fn test_message_templates() {
+        let values = HashMap::from([
+            ("name".to_string(), "Ana {code}".to_string()),
+            ("code".to_string(), "42".to_string()),
+        ]);
+        let template = MessageTemplate::new("Hello {name}, your code is {code} {{literal}}")
+            .with_locale("pt-BR", "Olá {name}, seu código é {code}")
+            .with_locale("de", "Hallo {name}, dein Code ist {code}");
+        let mut registry = TemplateRegistry::new();
+        registry.register("otp", template).unwrap();
+        
+        // Values are inserted verbatim, not expanded again
+        assert_eq!(registry.render("otp", None, &values).unwrap(), "Hello Ana {code}, your code is 42 {literal}");
+        assert_eq!(registry.render("otp", Some("pt_br"), &values).unwrap(), "Olá Ana {code}, seu código é 42");
+        assert_eq!(registry.render("otp", Some("de-AT"), &values).unwrap(), "Hallo Ana {code}, dein Code ist 42");
+        assert_eq!(registry.render("otp", Some("fr"), &values).unwrap(), "Hello Ana {code}, your code is 42 {literal}");
+        
+        let missing = HashMap::from([("name".to_string(), "Ana".to_string())]);
+        assert!(registry.render("otp", None, &missing).is_err());
+        assert!(registry.render("unknown", None, &values).is_err());
+        
+        // Invalid templates are rejected when registered
+        for body in ["Hello {name", "Hello name}", "Hello {first name}", "Hello {}"] {
+            assert!(registry.register("bad", MessageTemplate::new(body)).is_err(), "{}", body);
+        }
+        let mismatched = MessageTemplate::new("Hello {name}").with_locale("de", "Hallo {vorname}");
+        assert!(registry.register("bad", mismatched).is_err());
+        assert!(registry.get("bad").is_none());
+    }
+}
@@ -0,0 +1,212 @@
+/// Client metrics
+///
+/// With the `metrics` feature enabled the client reports counters and
+/// histograms through the [`metrics`](https://docs.rs/metrics) facade, so any
+/// installed recorder picks them up. [`prometheus`] contains a ready-made
+/// recorder and a minimal HTTP endpoint for Prometheus to scrape. Without
+/// the feature the recording functions compile to nothing.
+///
+/// | Metric | Type | Labels |
+/// |---|---|---|
+/// | `whatsmeow_messages_sent_total` | counter | |
+/// | `whatsmeow_messages_received_total` | counter | |
+/// | `whatsmeow_decrypt_failures_total` | counter | |
+/// | `whatsmeow_reconnects_total` | counter | |
+/// | `whatsmeow_iq_duration_seconds` | histogram | `namespace` |
+/// | `whatsmeow_media_bytes_total` | counter | `direction` |
+/// | `whatsmeow_cache_lookups_total` | counter | `cache`, `result` |
+/// | `whatsmeow_anti_spam_rejected_total` | counter | `reason` |
+/// | `whatsmeow_anti_spam_delay_seconds` | histogram | |
+
+use std::time::Duration;
+
+pub const MESSAGES_SENT: &str = "whatsmeow_messages_sent_total";
+pub const MESSAGES_RECEIVED: &str = "whatsmeow_messages_received_total";
+pub const DECRYPT_FAILURES: &str = "whatsmeow_decrypt_failures_total";
+pub const RECONNECTS: &str = "whatsmeow_reconnects_total";
+pub const IQ_DURATION: &str = "whatsmeow_iq_duration_seconds";
+pub const MEDIA_BYTES: &str = "whatsmeow_media_bytes_total";
+pub const CACHE_LOOKUPS: &str = "whatsmeow_cache_lookups_total";
+pub const ANTI_SPAM_REJECTED: &str = "whatsmeow_anti_spam_rejected_total";
+pub const ANTI_SPAM_DELAY: &str = "whatsmeow_anti_spam_delay_seconds";
+
+/// Direction of a media transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaDirection {
+    Upload,
+    Download,
+}
+
+impl MediaDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaDirection::Upload => "upload",
+            MediaDirection::Download => "download",
+        }
+    }
+}
+
+/// Register descriptions of all metrics with the installed recorder
+pub fn describe() {
+    #[cfg(feature = "metrics")]
+    {
+        use ::metrics::{describe_counter, describe_histogram, Unit};
+        describe_counter!(MESSAGES_SENT, "Messages accepted by the server");
+        describe_counter!(MESSAGES_RECEIVED, "Messages received from other users");
+        describe_counter!(DECRYPT_FAILURES, "Incoming messages that could not be decrypted");
+        describe_counter!(RECONNECTS, "Reconnection attempts");
+        describe_histogram!(IQ_DURATION, Unit::Seconds, "Time until an IQ was answered");
+        describe_counter!(MEDIA_BYTES, Unit::Bytes, "Media bytes transferred");
+        describe_counter!(CACHE_LOOKUPS, "In-memory cache lookups by hit or miss");
+        describe_counter!(ANTI_SPAM_REJECTED, "Messages refused by the anti-spam guard");
+        describe_histogram!(ANTI_SPAM_DELAY, Unit::Seconds, "Random pause added before sending a message");
+    }
+}
+
+/// Count a message sent by us
+pub fn message_sent() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(MESSAGES_SENT).increment(1);
+}
+
+/// Count a message received from another user
+pub fn message_received() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(MESSAGES_RECEIVED).increment(1);
+}
+
+/// Count an incoming message that failed to decrypt
+pub fn decrypt_failure() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(DECRYPT_FAILURES).increment(1);
+}
+
+/// Count a reconnection attempt
+pub fn reconnect() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(RECONNECTS).increment(1);
+}
+
+/// Record the round trip time of an IQ in the given namespace
+pub fn iq_latency(namespace: &str, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(IQ_DURATION, "namespace" => namespace.to_string()).record(elapsed.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = (namespace, elapsed);
+}
+
+/// Count transferred media bytes
+pub fn media_bytes(direction: MediaDirection, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(MEDIA_BYTES, "direction" => direction.as_str()).increment(bytes);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (direction, bytes);
+}
+
+/// Count a lookup that found an entry in the named cache
+pub fn cache_hit(cache: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(CACHE_LOOKUPS, "cache" => cache, "result" => "hit").increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = cache;
+}
+
+/// Count a lookup that missed the named cache
+pub fn cache_miss(cache: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(CACHE_LOOKUPS, "cache" => cache, "result" => "miss").increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = cache;
+}
+
+/// Count a message refused by the anti-spam guard
+pub fn anti_spam_rejected(reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(ANTI_SPAM_REJECTED, "reason" => reason).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = reason;
+}
+
+/// Record the pause the anti-spam guard added before a message
+pub fn anti_spam_delay(delay: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(ANTI_SPAM_DELAY).record(delay.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = delay;
+}
+
+/// Prometheus exporter
+#[cfg(feature = "metrics")]
+pub mod prometheus {
+    use crate::error::{Error, Result};
+    use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tracing::{debug, warn};
+
+    /// Install a Prometheus recorder as the global recorder.
+    ///
+    /// The returned handle renders the current values in the Prometheus text format.
+    pub fn install() -> Result<PrometheusHandle> {
+        let handle = PrometheusBuilder::new()
+            .install_recorder()
+            .map_err(|e| Error::Protocol(format!("Failed to install metrics recorder: {}", e)))?;
+        super::describe();
+        Ok(handle)
+    }
+
+    /// Serve the metrics rendered by `handle` to every HTTP request on `addr`
+    pub async fn serve(addr: SocketAddr, handle: PrometheusHandle) -> Result<tokio::task::JoinHandle<()>> {
+        let listener = TcpListener::bind(addr).await?;
+        debug!("Serving Prometheus metrics on {}", listener.local_addr()?);
+
+        Ok(tokio::spawn(async move {
+            loop {
+                let mut stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                let body = handle.render();
+                tokio::spawn(async move {
+                    // The request itself does not matter, every path gets the metrics
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::*;
+        use metrics_exporter_prometheus::PrometheusBuilder;
+
+        #[test]
+        fn test_prometheus_render() {
+            let recorder = PrometheusBuilder::new().build_recorder();
+            let handle = recorder.handle();
+            ::metrics::with_local_recorder(&recorder, || {
+                message_sent();
+                message_sent();
+                media_bytes(MediaDirection::Download, 100);
+                iq_latency("usync", Duration::from_millis(20));
+            });
+
+            let rendered = handle.render();
+            assert!(rendered.contains(&format!("{} 2", MESSAGES_SENT)));
+            assert!(rendered.contains(r#"whatsmeow_media_bytes_total{direction="download"} 100"#));
+            assert!(rendered.contains(r#"namespace="usync""#));
+        }
+    }
+}
@@ -0,0 +1,277 @@
+/// MessageTransport envelope around Signal plaintexts
+///
+/// Current WhatsApp clients no longer encrypt the bare `Message` proto.
+/// The plaintext handed to the Signal layer is a [`MessageTransport`] whose
+/// payload carries the application message as a versioned sub-protocol,
+/// while the protocol part holds random padding and, for messages
+/// mirrored to our own devices, the device-sent metadata naming the
+/// original recipient. Such `enc` nodes are marked with `v="3"`.
+///
+/// Older peers still send `v="2"` plaintexts: the encoded `Message` proto
+/// followed by PKCS#7 style padding. [`open`] understands both versions.
+
+use crate::{
+    error::{Error, Result},
+    proto::{wa_common::SubProtocol, wa_e2e, wa_msg_transport::message_transport},
+    types::JID,
+};
+use prost::Message;
+use rand::Rng;
+
+pub use crate::proto::wa_msg_transport::MessageTransport;
+
+/// `enc` version of plaintexts that are a padded `Message` proto
+pub const ENC_VERSION_LEGACY: &str = "2";
+
+/// `enc` version of plaintexts wrapped in a [`MessageTransport`]
+pub const ENC_VERSION_TRANSPORT: &str = "3";
+
+/// Version of the application payload sub-protocol
+pub const APPLICATION_PAYLOAD_VERSION: i32 = 2;
+
+/// Device-sent metadata of a message mirrored to our own devices
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSentMetadata {
+    /// Chat the message was originally sent to
+    pub destination: JID,
+    /// Participant list hash of the original recipients
+    pub phash: Option<String>,
+}
+
+/// Sender key distribution message carried in the envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderKeyDistribution {
+    pub group_id: String,
+    pub distribution_message: Vec<u8>,
+}
+
+/// Contents of a decoded envelope
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedTransport {
+    /// Encoded application message
+    pub payload: Vec<u8>,
+    /// Version of the application payload, `None` for legacy plaintexts
+    pub payload_version: Option<i32>,
+    /// Set if the message was sent by another of our devices
+    pub device_sent: Option<DeviceSentMetadata>,
+    /// Sender key for the group the message belongs to, if attached
+    pub sender_key_distribution: Option<SenderKeyDistribution>,
+}
+
+impl DecodedTransport {
+    /// Decode the application payload as a `Message` proto
+    pub fn message(&self) -> Result<wa_e2e::Message> {
+        Ok(wa_e2e::Message::decode(self.payload.as_slice())?)
+    }
+}
+
+/// Wrap an encoded application message into a padded envelope
+pub fn seal(payload: Vec<u8>, device_sent: Option<&DeviceSentMetadata>) -> Vec<u8> {
+    seal_transport(payload, device_sent, None)
+}
+
+/// Wrap a sender key distribution message into an envelope without application message.
+///
+/// Sent to group participant devices that have not received our sender key yet.
+pub fn seal_sender_key_distribution(skdm: &SenderKeyDistribution) -> Vec<u8> {
+    seal_transport(Vec::new(), None, Some(skdm))
+}
+
+fn seal_transport(
+    payload: Vec<u8>,
+    device_sent: Option<&DeviceSentMetadata>,
+    skdm: Option<&SenderKeyDistribution>,
+) -> Vec<u8> {
+    let transport = MessageTransport {
+        payload: Some(message_transport::Payload {
+            application_payload: Some(SubProtocol {
+                payload: Some(payload),
+                version: Some(APPLICATION_PAYLOAD_VERSION),
+            }),
+            future_proof: None,
+        }),
+        protocol: Some(message_transport::Protocol {
+            integral: Some(message_transport::protocol::Integral {
+                padding: Some(random_padding()),
+                dsm: device_sent.map(|dsm| message_transport::protocol::integral::DeviceSentMessage {
+                    destination_jid: Some(dsm.destination.to_string()),
+                    phash: dsm.phash.clone(),
+                }),
+            }),
+            ancillary: skdm.map(|skdm| message_transport::protocol::Ancillary {
+                skdm: Some(message_transport::protocol::ancillary::SenderKeyDistributionMessage {
+                    group_id: Some(skdm.group_id.clone()),
+                    axolotl_sender_key_distribution_message: Some(skdm.distribution_message.clone()),
+                }),
+                ..Default::default()
+            }),
+        }),
+    };
+    transport.encode_to_vec()
+}
+
+/// Wrap a `Message` proto into a padded envelope
+pub fn seal_message(message: &wa_e2e::Message, device_sent: Option<&DeviceSentMetadata>) -> Vec<u8> {
+    seal(message.encode_to_vec(), device_sent)
+}
+
+/// Decode a decrypted plaintext of the given `enc` version
+pub fn open(version: &str, plaintext: &[u8]) -> Result<DecodedTransport> {
+    match version {
+        ENC_VERSION_TRANSPORT => decode(plaintext),
+        ENC_VERSION_LEGACY => Ok(DecodedTransport {
+            payload: unpad(plaintext)?.to_vec(),
+            payload_version: None,
+            device_sent: None,
+            sender_key_distribution: None,
+        }),
+        version => Err(Error::Protocol(format!("Unsupported enc version: {}", version))),
+    }
+}
+
+/// Decode a [`MessageTransport`] plaintext
+pub fn decode(plaintext: &[u8]) -> Result<DecodedTransport> {
+    let transport = MessageTransport::decode(plaintext)?;
+
+    let application = transport
+        .payload
+        .and_then(|payload| payload.application_payload)
+        .ok_or_else(|| Error::Protocol("Message transport without application payload".to_string()))?;
+    let payload = application
+        .payload
+        .ok_or_else(|| Error::Protocol("Empty application payload".to_string()))?;
+
+    let protocol = transport.protocol.unwrap_or_default();
+    let device_sent = match protocol.integral.and_then(|integral| integral.dsm) {
+        Some(dsm) => {
+            let destination = dsm
+                .destination_jid
+                .ok_or_else(|| Error::Protocol("Device-sent message without destination".to_string()))?
+                .parse()?;
+            Some(DeviceSentMetadata { destination, phash: dsm.phash })
+        }
+        None => None,
+    };
+    let sender_key_distribution = protocol
+        .ancillary
+        .and_then(|ancillary| ancillary.skdm)
+        .and_then(|skdm| {
+            Some(SenderKeyDistribution {
+                group_id: skdm.group_id?,
+                distribution_message: skdm.axolotl_sender_key_distribution_message?,
+            })
+        });
+
+    Ok(DecodedTransport {
+        payload,
+        payload_version: application.version,
+        device_sent,
+        sender_key_distribution,
+    })
+}
+
+/// Append legacy padding: 1 to 15 bytes, each holding the padding length
+pub fn pad(mut plaintext: Vec<u8>) -> Vec<u8> {
+    plaintext.extend(random_padding());
+    plaintext
+}
+
+/// Strip legacy padding
+pub fn unpad(plaintext: &[u8]) -> Result<&[u8]> {
+    let len = *plaintext
+        .last()
+        .ok_or_else(|| Error::Protocol("Empty plaintext".to_string()))? as usize;
+    if len == 0 || len > plaintext.len() {
+        return Err(Error::Protocol(format!("Invalid plaintext padding length {}", len)));
+    }
+    Ok(&plaintext[..plaintext.len() - len])
+}
+
+fn random_padding() -> Vec<u8> {
+    let len = rand::thread_rng().gen_range(1..=15u8);
+    vec![len; len as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ProtoUtils;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let message = ProtoUtils::create_text_message("hello");
+        let destination = JID::new("friend".to_string(), "s.whatsapp.net".to_string());
+        let dsm = DeviceSentMetadata { destination, phash: Some("2:abc".to_string()) };
+
+        let plaintext = seal_message(&message, Some(&dsm));
+        let decoded = open(ENC_VERSION_TRANSPORT, &plaintext).unwrap();
+        assert_eq!(decoded.message().unwrap(), message);
+        assert_eq!(decoded.payload_version, Some(APPLICATION_PAYLOAD_VERSION));
+        assert_eq!(decoded.device_sent, Some(dsm));
+        assert!(decoded.sender_key_distribution.is_none());
+
+        let decoded = decode(&seal(message.encode_to_vec(), None)).unwrap();
+        assert!(decoded.device_sent.is_none());
+    }
+
+    #[test]
+    fn test_open_legacy() {
+        let message = ProtoUtils::create_text_message("hello");
+        let plaintext = pad(message.encode_to_vec());
+        let decoded = open(ENC_VERSION_LEGACY, &plaintext).unwrap();
+        assert_eq!(decoded.message().unwrap(), message);
+        assert!(decoded.payload_version.is_none());
+
+        assert!(unpad(&[]).is_err());
+        assert!(unpad(&[1, 2, 9]).is_err());
+        assert!(open("1", &plaintext).is_err());
+    }
+
+    #[test]
+    fn test_decode_sender_key_distribution() {
+        let transport = MessageTransport {
+            payload: Some(message_transport::Payload {
+                application_payload: Some(SubProtocol { payload: Some(vec![1, 2]), version: Some(2) }),
+                future_proof: None,
+            }),
+            protocol: Some(message_transport::Protocol {
+                integral: None,
+                ancillary: Some(message_transport::protocol::Ancillary {
+                    skdm: Some(message_transport::protocol::ancillary::SenderKeyDistributionMessage {
+                        group_id: Some("123@g.us".to_string()),
+                        axolotl_sender_key_distribution_message: Some(vec![7]),
+                    }),
+                    ..Default::default()
+                }),
+            }),
+        };
+        let decoded = decode(&transport.encode_to_vec()).unwrap();
+        assert_eq!(decoded.payload, vec![1, 2]);
+        assert_eq!(decoded.sender_key_distribution.unwrap().group_id, "123@g.us");
+
+        assert!(decode(&MessageTransport::default().encode_to_vec()).is_err());
+
+        let skdm = SenderKeyDistribution { group_id: "123@g.us".to_string(), distribution_message: vec![7, 8] };
+        let decoded = open(ENC_VERSION_TRANSPORT, &seal_sender_key_distribution(&skdm)).unwrap();
+        assert!(decoded.payload.is_empty());
+        assert_eq!(decoded.sender_key_distribution, Some(skdm));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_open_never_panics(plaintext in prop::collection::vec(any::<u8>(), 0..512)) {
+            for version in [ENC_VERSION_TRANSPORT, ENC_VERSION_LEGACY] {
+                if let Ok(decoded) = open(version, &plaintext) {
+                    let _ = decoded.message();
+                }
+            }
+        }
+
+        #[test]
+        fn prop_seal_roundtrip(payload in prop::collection::vec(any::<u8>(), 0..512)) {
+            prop_assert_eq!(decode(&seal(payload.clone(), None)).unwrap().payload, payload.clone());
+            prop_assert_eq!(open(ENC_VERSION_LEGACY, &pad(payload.clone())).unwrap().payload, payload);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+/// Routing of `<notification>` stanzas
+///
+/// The server uses `<notification>` stanzas for many unrelated updates, told
+/// apart by their `type` attribute. [`NotificationKind::of`] classifies a
+/// stanza so the client can hand it to the subsystem owning it: app state,
+/// pre-keys, the device cache, groups or profiles. Notifications no
+/// subsystem handles are surfaced as
+/// [`Event::UnhandledNotification`](crate::types::Event::UnhandledNotification)
+/// with the raw stanza, so applications can handle new types themselves.
+
+use crate::{
+    appstate::PatchName,
+    binary::Node,
+    blocklist::BLOCKLIST_NAMESPACE,
+    group::protocol::GROUP_NOTIFICATION_TYPE,
+    types::JID,
+    wirelog,
+};
+use serde::{Serialize, Serializer};
+
+/// Type of a `<notification>` stanza
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// App state collections changed on another device
+    ServerSync,
+    /// Settings of the account changed, e.g. its devices or block list
+    AccountSync,
+    /// Our pre-key count is low or a contact's identity changed
+    Encrypt,
+    /// Device list of a user changed
+    Devices,
+    /// Profile picture of a user or group changed
+    Picture,
+    /// Group change, `w:gp2`
+    Group,
+    /// About text of a user changed
+    Status,
+    /// Answer to a media re-upload request
+    MediaRetry,
+    /// Block list changed
+    Blocklist,
+    Other(String),
+}
+
+impl NotificationKind {
+    /// Classify a stanza, `None` if it is not a notification
+    pub fn of(node: &Node) -> Option<Self> {
+        if node.tag != "notification" {
+            return None;
+        }
+        let kind = match node.get_attr("type").map(String::as_str).unwrap_or_default() {
+            "server_sync" => NotificationKind::ServerSync,
+            "account_sync" => NotificationKind::AccountSync,
+            "encrypt" => NotificationKind::Encrypt,
+            "devices" => NotificationKind::Devices,
+            "picture" => NotificationKind::Picture,
+            GROUP_NOTIFICATION_TYPE => NotificationKind::Group,
+            "status" => NotificationKind::Status,
+            "mediaretry" => NotificationKind::MediaRetry,
+            BLOCKLIST_NAMESPACE => NotificationKind::Blocklist,
+            other => NotificationKind::Other(other.to_string()),
+        };
+        Some(kind)
+    }
+
+    /// The `type` attribute of the notification
+    pub fn as_str(&self) -> &str {
+        match self {
+            NotificationKind::ServerSync => "server_sync",
+            NotificationKind::AccountSync => "account_sync",
+            NotificationKind::Encrypt => "encrypt",
+            NotificationKind::Devices => "devices",
+            NotificationKind::Picture => "picture",
+            NotificationKind::Group => GROUP_NOTIFICATION_TYPE,
+            NotificationKind::Status => "status",
+            NotificationKind::MediaRetry => "mediaretry",
+            NotificationKind::Blocklist => BLOCKLIST_NAMESPACE,
+            NotificationKind::Other(kind) => kind,
+        }
+    }
+}
+
+/// Change of a user's profile announced by a notification
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProfileChange {
+    /// New profile picture, `picture_id` is `None` if it was removed
+    Picture { jid: JID, picture_id: Option<String>, author: Option<JID> },
+    About { jid: JID, about: String },
+}
+
+/// Notification no subsystem handled
+#[derive(Debug, Clone, Serialize)]
+pub struct UnhandledNotification {
+    pub kind: String,
+    pub from: Option<JID>,
+    /// The stanza, serialized as redacted XML
+    #[serde(serialize_with = "serialize_node")]
+    pub node: Node,
+}
+
+impl UnhandledNotification {
+    pub fn new(kind: &NotificationKind, node: &Node) -> Self {
+        Self {
+            kind: kind.as_str().to_string(),
+            from: node.get_attr("from").and_then(|from| from.parse().ok()),
+            node: node.clone(),
+        }
+    }
+}
+
+/// App state collections named in a `server_sync` notification
+pub fn server_sync_collections(node: &Node) -> Vec<PatchName> {
+    node.get_children()
+        .into_iter()
+        .flatten()
+        .filter(|child| child.tag == "collection")
+        .filter_map(|collection| {
+            let name = collection.get_attr("name")?;
+            PatchName::ALL.into_iter().find(|patch| patch.as_str() == name)
+        })
+        .collect()
+}
+
+/// Tags of the children of an `account_sync` notification
+pub fn account_sync_changes(node: &Node) -> Vec<&str> {
+    node.get_children().into_iter().flatten().map(|child| child.tag.as_str()).collect()
+}
+
+/// Parse a `picture` notification of a user or a `status` notification
+pub fn parse_profile_change(node: &Node) -> Option<ProfileChange> {
+    let from: JID = node.get_attr("from")?.parse().ok()?;
+    if from.is_group() {
+        return None;
+    }
+    match NotificationKind::of(node)? {
+        NotificationKind::Picture => {
+            let change = node.get_children()?.iter().find(|c| c.tag == "set" || c.tag == "delete")?;
+            Some(ProfileChange::Picture {
+                jid: change.get_attr("jid").and_then(|jid| jid.parse().ok()).unwrap_or(from),
+                picture_id: if change.tag == "set" { change.get_attr("id").cloned() } else { None },
+                author: change.get_attr("author").and_then(|author| author.parse().ok()),
+            })
+        }
+        NotificationKind::Status => {
+            let set = node.find_child("set")?;
+            Some(ProfileChange::About { jid: from, about: set.get_text().cloned().unwrap_or_default() })
+        }
+        _ => None,
+    }
+}
+
+fn serialize_node<S: Serializer>(node: &Node, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&wirelog::render(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(kind: &str, from: &str, children: Vec<Node>) -> Node {
+        Node::builder("notification").attr("type", kind).attr("from", from).nodes(children).build()
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(NotificationKind::of(&notification("w:gp2", "1-2@g.us", vec![])), Some(NotificationKind::Group));
+        assert_eq!(NotificationKind::of(&notification("mediaretry", "s.whatsapp.net", vec![])), Some(NotificationKind::MediaRetry));
+        let other = NotificationKind::of(&notification("newsletter", "s.whatsapp.net", vec![])).unwrap();
+        assert_eq!(other.as_str(), "newsletter");
+        assert!(NotificationKind::of(&Node::new("message".to_string())).is_none());
+
+        let unhandled = UnhandledNotification::new(&other, &notification("newsletter", "s.whatsapp.net", vec![]));
+        let json = serde_json::to_value(&unhandled).unwrap();
+        assert_eq!(json["kind"], "newsletter");
+        assert!(json["node"].as_str().unwrap().starts_with("<notification"));
+    }
+
+    #[test]
+    fn test_parse_payloads() {
+        let sync = notification("server_sync", "s.whatsapp.net", vec![
+            Node::builder("collection").attr("name", "regular_high").attr("version", 12).build(),
+            Node::builder("collection").attr("name", "unknown").build(),
+        ]);
+        assert_eq!(server_sync_collections(&sync), vec![PatchName::RegularHigh]);
+
+        let picture = notification("picture", "1234@s.whatsapp.net", vec![
+            Node::builder("set").attr("jid", "1234@s.whatsapp.net").attr("id", "99").build(),
+        ]);
+        assert_eq!(
+            parse_profile_change(&picture),
+            Some(ProfileChange::Picture { jid: JID::new_user("1234"), picture_id: Some("99".to_string()), author: None })
+        );
+
+        let status = notification("status", "1234@s.whatsapp.net", vec![
+            Node::new("set".to_string()).with_text("Busy".to_string()),
+        ]);
+        assert_eq!(parse_profile_change(&status), Some(ProfileChange::About { jid: JID::new_user("1234"), about: "Busy".to_string() }));
+
+        // Group pictures are group events
+        let group_picture = notification("picture", "1-2@g.us", vec![Node::new("delete".to_string())]);
+        assert!(parse_profile_change(&group_picture).is_none());
+    }
+}
@@ -0,0 +1,384 @@
+/// One-time pre-key upload and replenishment
+///
+/// Peers consume one of our one-time pre-keys for every new Signal session
+/// they start with this device. The server tells us how many are left
+/// (`<count>` query in the `encrypt` namespace, and `encrypt` notifications
+/// when it runs low); once fewer than [`MIN_PREKEY_COUNT`] remain a fresh
+/// batch is generated, written to the persistent store and uploaded together
+/// with the identity key and current signed pre-key. The keys are marked
+/// uploaded only once the server accepted them.
+///
+/// The same namespace hands out the pre-key bundles of other devices
+/// (`<key>` query), which start the Signal sessions messages to them are
+/// encrypted with.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    request::{InfoQuery, IqSender},
+    signal::{PreKey, PreKeyBundle, SignalProtocolManager, SignedPreKey, DJB_TYPE},
+    types::JID,
+    util::keys::ECKeyPair,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// Namespace of pre-key queries
+pub const ENCRYPT_NAMESPACE: &str = "encrypt";
+
+/// Upload more pre-keys once the server has fewer than this many
+pub const MIN_PREKEY_COUNT: usize = 5;
+
+/// Number of pre-keys uploaded in one batch
+pub const WANTED_PREKEY_COUNT: u32 = 50;
+
+/// Default interval of the background pre-key count check
+pub const DEFAULT_PREKEY_CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Default age after which the signed pre-key is replaced
+pub const DEFAULT_SIGNED_PREKEY_ROTATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Build the query for the number of our pre-keys left on the server
+pub fn build_count_query() -> InfoQuery {
+    InfoQuery::get(ENCRYPT_NAMESPACE).content(vec![Node::builder("count").build()])
+}
+
+/// Parse the response to [`build_count_query`]
+pub fn parse_count_response(response: &Node) -> Result<usize> {
+    response
+        .find_child("count")
+        .and_then(|count| count.get_attr("value"))
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| Error::Protocol("Pre-key count response without value".to_string()))
+}
+
+/// Build the query uploading our keys and a batch of one-time pre-keys
+pub fn build_upload_query(
+    registration_id: u32,
+    identity_key: &[u8; 32],
+    signed_prekey: &SignedPreKey,
+    prekeys: &[PreKey],
+) -> InfoQuery {
+    let keys = prekeys.iter().map(|prekey| {
+        Node::builder("key")
+            .child("id", |id| id.bytes(prekey_id_bytes(prekey.id)))
+            .child("value", |value| value.bytes(prekey.public_key().to_vec()))
+            .build()
+    });
+
+    InfoQuery::set(ENCRYPT_NAMESPACE).content(vec![
+        Node::builder("registration").bytes(registration_id.to_be_bytes().to_vec()).build(),
+        Node::builder("type").bytes(vec![DJB_TYPE]).build(),
+        Node::builder("identity").bytes(identity_key.to_vec()).build(),
+        Node::builder("list").nodes(keys).build(),
+        signed_prekey_node(signed_prekey),
+    ])
+}
+
+/// Build the query replacing our signed pre-key on the server
+pub fn build_rotate_query(signed_prekey: &SignedPreKey) -> InfoQuery {
+    InfoQuery::set(ENCRYPT_NAMESPACE).content(vec![
+        Node::builder("rotate").node(signed_prekey_node(signed_prekey)).build(),
+    ])
+}
+
+/// Build the `<skey>` node of a signed pre-key
+pub fn signed_prekey_node(signed_prekey: &SignedPreKey) -> Node {
+    Node::builder("skey")
+        .child("id", |id| id.bytes(prekey_id_bytes(signed_prekey.id)))
+        .child("value", |value| value.bytes(signed_prekey.public_key().to_vec()))
+        .child("signature", |signature| signature.bytes(signed_prekey.signature.clone()))
+        .build()
+}
+
+/// Encode a pre-key ID as the 3 byte big-endian integer used on the wire
+fn prekey_id_bytes(id: u32) -> Vec<u8> {
+    id.to_be_bytes()[1..].to_vec()
+}
+
+/// Build the query for the pre-key bundles of other devices
+pub fn build_bundle_query(devices: &[JID]) -> InfoQuery {
+    let users = devices.iter().map(|device| Node::builder("user").attr("jid", device).build());
+    InfoQuery::get(ENCRYPT_NAMESPACE).content(vec![Node::builder("key").nodes(users).build()])
+}
+
+/// Parse the response to [`build_bundle_query`].
+///
+/// Devices the server reports an error for, or whose bundle is malformed,
+/// are left out with a warning.
+pub fn parse_bundle_response(response: &Node) -> Result<Vec<(JID, PreKeyBundle)>> {
+    let list = response
+        .find_child("list")
+        .ok_or_else(|| Error::ElementMissing("list".to_string()))?;
+    let mut bundles = Vec::new();
+    for user in list.get_children().map(Vec::as_slice).unwrap_or_default() {
+        let Some(jid) = user.get_attr("jid").and_then(|jid| jid.parse::<JID>().ok()) else {
+            continue;
+        };
+        if let Some(error) = user.find_child("error") {
+            warn!("No pre-key bundle for {}: error {}", jid, error.get_attr("code").map(String::as_str).unwrap_or("?"));
+            continue;
+        }
+        match parse_bundle(&jid, user) {
+            Ok(bundle) => bundles.push((jid, bundle)),
+            Err(e) => warn!("Ignoring pre-key bundle of {}: {}", jid, e),
+        }
+    }
+    Ok(bundles)
+}
+
+fn parse_bundle(jid: &JID, user: &Node) -> Result<PreKeyBundle> {
+    let binary = |node: &Node, tag: &str| -> Result<Vec<u8>> {
+        node.find_child(tag)
+            .and_then(Node::get_binary)
+            .cloned()
+            .ok_or_else(|| Error::ElementMissing(tag.to_string()))
+    };
+    let registration: [u8; 4] = binary(user, "registration")?
+        .try_into()
+        .map_err(|_| Error::Protocol("Invalid registration ID".to_string()))?;
+    let skey = user.find_child("skey").ok_or_else(|| Error::ElementMissing("skey".to_string()))?;
+    let prekey = match user.find_child("key") {
+        Some(key) => Some(PreKey {
+            id: parse_prekey_id(&binary(key, "id")?)?,
+            keypair: remote_keypair(&binary(key, "value")?)?,
+        }),
+        None => None,
+    };
+    Ok(PreKeyBundle {
+        identity_key: binary(user, "identity")?,
+        signed_prekey: SignedPreKey {
+            id: parse_prekey_id(&binary(skey, "id")?)?,
+            keypair: remote_keypair(&binary(skey, "value")?)?,
+            signature: binary(skey, "signature")?,
+            timestamp: 0,
+        },
+        prekey,
+        registration_id: u32::from_be_bytes(registration),
+        device_id: jid.device as u32,
+    })
+}
+
+/// Decode a 3 byte pre-key ID, see [`prekey_id_bytes`]
+fn parse_prekey_id(data: &[u8]) -> Result<u32> {
+    match data {
+        [a, b, c] => Ok(u32::from_be_bytes([0, *a, *b, *c])),
+        _ => Err(Error::Protocol(format!("Invalid pre-key ID length {}", data.len()))),
+    }
+}
+
+/// Key pair of another device, of which only the public key is known
+fn remote_keypair(public_key: &[u8]) -> Result<ECKeyPair> {
+    let public_key = public_key
+        .try_into()
+        .map_err(|_| Error::Crypto(format!("Invalid pre-key length {}", public_key.len())))?;
+    Ok(ECKeyPair { private_key: [0; 32], public_key })
+}
+
+/// Fetch the pre-key bundles of other devices, see [`parse_bundle_response`]
+pub async fn fetch_bundles(iq_sender: &dyn IqSender, devices: &[JID]) -> Result<Vec<(JID, PreKeyBundle)>> {
+    let response = iq_sender.send_iq(build_bundle_query(devices)).await?;
+    parse_bundle_response(&response)
+}
+
+/// Get the number of our pre-keys left on the server
+pub async fn get_server_count(iq_sender: &dyn IqSender) -> Result<usize> {
+    let response = iq_sender.send_iq(build_count_query()).await?;
+    parse_count_response(&response)
+}
+
+/// Generate, store and upload a batch of `count` pre-keys.
+///
+/// Keys left over from a failed upload are sent again first. New keys are
+/// persisted before the upload, so a peer using one of them can always be
+/// answered, and all of them are marked uploaded once the server took them.
+pub async fn upload_prekeys(
+    iq_sender: &dyn IqSender,
+    signal: &Mutex<SignalProtocolManager>,
+    count: u32,
+) -> Result<()> {
+    let (query, last_id) = {
+        let mut signal = signal.lock().await;
+        let signed_prekey = signal.get_or_create_signed_prekey()?;
+        let mut prekeys = signal.unuploaded_prekeys().await?;
+        prekeys.truncate(count as usize);
+        let missing = count - prekeys.len() as u32;
+        prekeys.extend(signal.generate_prekeys(missing));
+        signal.persist().await?;
+        let query = build_upload_query(signal.registration_id(), &signal.identity_public_key()?, &signed_prekey, &prekeys);
+        (query, prekeys.last().map(|prekey| prekey.id))
+    };
+
+    iq_sender.send_iq(query).await?;
+    if let Some(last_id) = last_id {
+        signal.lock().await.mark_prekeys_uploaded(last_id).await?;
+    }
+    info!("Uploaded {} pre-keys", count);
+    Ok(())
+}
+
+/// Generate a new signed pre-key, store and upload it
+pub async fn rotate_signed_prekey(iq_sender: &dyn IqSender, signal: &Mutex<SignalProtocolManager>) -> Result<SignedPreKey> {
+    let signed_prekey = {
+        let mut signal = signal.lock().await;
+        let signed_prekey = signal.rotate_signed_prekey()?;
+        signal.persist().await?;
+        signed_prekey
+    };
+    iq_sender.send_iq(build_rotate_query(&signed_prekey)).await?;
+    info!("Rotated signed pre-key, new ID {}", signed_prekey.id);
+    Ok(signed_prekey)
+}
+
+/// Time until the signed pre-key created at `timestamp` (Unix seconds) is due for rotation
+pub fn next_rotation_in(timestamp: u64, rotation: Duration, now: SystemTime) -> Duration {
+    let due = UNIX_EPOCH + Duration::from_secs(timestamp) + rotation;
+    due.duration_since(now).unwrap_or(Duration::ZERO)
+}
+
+/// Upload a new batch if the server is running low on pre-keys.
+///
+/// Returns the number of uploaded pre-keys.
+pub async fn replenish_prekeys(iq_sender: &dyn IqSender, signal: &Mutex<SignalProtocolManager>) -> Result<u32> {
+    let count = get_server_count(iq_sender).await?;
+    if count >= MIN_PREKEY_COUNT {
+        debug!("Server has {} pre-keys left, not uploading", count);
+        return Ok(0);
+    }
+
+    upload_prekeys(iq_sender, signal, WANTED_PREKEY_COUNT).await?;
+    Ok(WANTED_PREKEY_COUNT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::InfoQueryType;
+    use async_trait::async_trait;
+    use std::sync::Mutex as StdMutex;
+
+    /// Answers count queries with a fixed count and records uploads
+    struct FakeServer {
+        count: usize,
+        uploads: StdMutex<Vec<InfoQuery>>,
+    }
+
+    /// Rejects every query
+    struct FailingServer;
+
+    #[async_trait]
+    impl IqSender for FailingServer {
+        async fn send_iq(&self, _query: InfoQuery) -> Result<Node> {
+            Err(Error::Protocol("rejected".to_string()))
+        }
+    }
+
+    #[async_trait]
+    impl IqSender for FakeServer {
+        async fn send_iq(&self, query: InfoQuery) -> Result<Node> {
+            match query.query_type {
+                InfoQueryType::Get => Ok(Node::builder("iq")
+                    .child("count", |c| c.attr("value", self.count))
+                    .build()),
+                InfoQueryType::Set => {
+                    self.uploads.lock().unwrap().push(query);
+                    Ok(Node::builder("iq").build())
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replenish_prekeys() {
+        let signal = Mutex::new(SignalProtocolManager::new_with_memory_stores(42));
+
+        let server = FakeServer { count: 20, uploads: StdMutex::new(Vec::new()) };
+        assert_eq!(replenish_prekeys(&server, &signal).await.unwrap(), 0);
+        assert!(server.uploads.lock().unwrap().is_empty());
+
+        let server = FakeServer { count: 2, uploads: StdMutex::new(Vec::new()) };
+        assert_eq!(replenish_prekeys(&server, &signal).await.unwrap(), WANTED_PREKEY_COUNT);
+        let uploads = server.uploads.lock().unwrap();
+        let upload = &uploads[0];
+        assert_eq!(upload.namespace, ENCRYPT_NAMESPACE);
+
+        let find = |tag: &str| upload.content.iter().find(|node| node.tag == tag).unwrap();
+        assert_eq!(find("registration").get_binary(), Some(&42u32.to_be_bytes().to_vec()));
+        assert_eq!(find("list").get_children().unwrap().len(), WANTED_PREKEY_COUNT as usize);
+        let first_id = find("list").get_children().unwrap()[0].find_child("id").unwrap().get_binary().cloned();
+        assert_eq!(first_id, Some(vec![0, 0, 1]));
+        assert!(find("skey").find_child("signature").is_some());
+
+        // The next batch continues after the stored IDs
+        let next = signal.lock().await.generate_prekeys(1);
+        assert_eq!(next[0].id, WANTED_PREKEY_COUNT + 1);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_signed_prekey() {
+        let signal = Mutex::new(SignalProtocolManager::new_with_memory_stores(42));
+        let first = signal.lock().await.get_or_create_signed_prekey().unwrap();
+
+        let server = FakeServer { count: 0, uploads: StdMutex::new(Vec::new()) };
+        let rotated = rotate_signed_prekey(&server, &signal).await.unwrap();
+        assert_eq!(rotated.id, first.id + 1);
+        assert_eq!(signal.lock().await.current_signed_prekey().unwrap().id, rotated.id);
+        let uploads = server.uploads.lock().unwrap();
+        assert!(uploads[0].content[0].find_child("skey").is_some());
+
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let week = DEFAULT_SIGNED_PREKEY_ROTATION;
+        assert_eq!(next_rotation_in(1000, week, now), week);
+        assert_eq!(next_rotation_in(0, Duration::from_secs(10), now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_parse_count_response() {
+        let response = Node::builder("iq").child("count", |c| c.attr("value", "7")).build();
+        assert_eq!(parse_count_response(&response).unwrap(), 7);
+        assert!(parse_count_response(&Node::builder("iq").build()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prekeys_are_stored_before_upload() {
+        use crate::store::{DeviceData, MemoryStore, Store};
+        use crate::types::JID;
+        use crate::util::keys::SigningKeyPair;
+
+        let store: std::sync::Arc<dyn Store> = std::sync::Arc::new(MemoryStore::new());
+        let device = DeviceData {
+            jid: JID::new_user("1234").with_device(1),
+            registration_id: 42,
+            noise_key: vec![1; 32],
+            identity_key: SigningKeyPair::generate().private_bytes().to_vec(),
+            signed_pre_key: vec![],
+            signed_pre_key_id: 0,
+            signed_pre_key_signature: vec![],
+        };
+        store.save_device(&device).await.unwrap();
+        let pre_keys = store.device(&device.jid).pre_keys;
+        let signal = Mutex::new(SignalProtocolManager::load(store.clone(), &device).await.unwrap());
+
+        // A failed upload leaves the keys stored but not uploaded
+        assert!(upload_prekeys(&FailingServer, &signal, 3).await.is_err());
+        assert_eq!(pre_keys.all_pre_keys().await.unwrap().len(), 3);
+        assert_eq!(pre_keys.uploaded_pre_key_count().await.unwrap(), 0);
+        assert!(store.load_device().await.unwrap().unwrap().signed_pre_key_id > 0);
+
+        // The next upload sends the same keys again
+        let server = FakeServer { count: 0, uploads: StdMutex::new(Vec::new()) };
+        upload_prekeys(&server, &signal, 3).await.unwrap();
+        assert_eq!(pre_keys.all_pre_keys().await.unwrap().len(), 3);
+        assert_eq!(pre_keys.uploaded_pre_key_count().await.unwrap(), 3);
+
+        upload_prekeys(&server, &signal, 3).await.unwrap();
+        assert_eq!(pre_keys.uploaded_pre_key_count().await.unwrap(), 6);
+        let uploads = server.uploads.lock().unwrap();
+        let last_ids = uploads[1].content.iter().find(|node| node.tag == "list").unwrap().get_children().unwrap()
+            .iter()
+            .map(|key| key.find_child("id").unwrap().get_binary().cloned().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(last_ids, vec![vec![0, 0, 4], vec![0, 0, 5], vec![0, 0, 6]]);
+    }
+}
@@ -0,0 +1,94 @@
+/// Online status of contacts
+///
+/// After subscribing with [`Client::subscribe_presence`](crate::Client::subscribe_presence)
+/// the server sends a `<presence>` stanza whenever the contact comes online or
+/// goes offline. Offline updates carry the last seen time in `last`, unless
+/// the contact hides it. [`PresenceTracker`] keeps the latest update of every
+/// contact.
+
+use crate::{binary::Node, types::{PresenceEvent, JID}};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parse an incoming `<presence>` stanza
+pub fn parse_presence(node: &Node) -> Option<PresenceEvent> {
+    if node.tag != "presence" {
+        return None;
+    }
+    let from: JID = node.get_attr("from")?.parse().ok()?;
+    let unavailable = node.get_attr("type").map(String::as_str) == Some("unavailable");
+    // `last` is "deny" if the contact hides their last seen time
+    let last_seen = node
+        .get_attr("last")
+        .and_then(|last| last.parse::<u64>().ok())
+        .filter(|last| *last > 0)
+        .map(|last| UNIX_EPOCH + Duration::from_secs(last));
+    Some(PresenceEvent { from: from.to_non_ad(), unavailable, last_seen })
+}
+
+/// Latest presence of every contact we received one for
+#[derive(Default)]
+pub struct PresenceTracker {
+    contacts: RwLock<HashMap<JID, PresenceEvent>>,
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a presence update, keeping the last seen time if the update has none
+    pub fn update(&self, event: &PresenceEvent) {
+        let mut contacts = self.contacts.write().unwrap();
+        let last_seen = event.last_seen.or_else(|| contacts.get(&event.from).and_then(|known| known.last_seen));
+        contacts.insert(event.from.clone(), PresenceEvent { last_seen, ..event.clone() });
+    }
+
+    /// Latest presence of a contact
+    pub fn get(&self, jid: &JID) -> Option<PresenceEvent> {
+        self.contacts.read().unwrap().get(&jid.to_non_ad()).cloned()
+    }
+
+    /// Whether the contact was online in its latest update
+    pub fn is_online(&self, jid: &JID) -> bool {
+        self.get(jid).is_some_and(|presence| !presence.unavailable)
+    }
+
+    /// When the contact was last seen, if they share it
+    pub fn last_seen(&self, jid: &JID) -> Option<SystemTime> {
+        self.get(jid).and_then(|presence| presence.last_seen)
+    }
+
+    /// Forget all presences, e.g. after reconnecting when subscriptions are gone
+    pub fn clear(&self) {
+        self.contacts.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_presence() {
+        let alice = JID::new_user("1111");
+        let tracker = PresenceTracker::new();
+
+        let offline = Node::builder("presence").attr("from", "1111@s.whatsapp.net").attr("type", "unavailable").attr("last", 1_792_152_180).build();
+        tracker.update(&parse_presence(&offline).unwrap());
+        assert!(!tracker.is_online(&alice));
+        assert_eq!(tracker.last_seen(&alice), Some(UNIX_EPOCH + Duration::from_secs(1_792_152_180)));
+
+        let online = Node::builder("presence").attr("from", "1111:3@s.whatsapp.net").build();
+        tracker.update(&parse_presence(&online).unwrap());
+        assert!(tracker.is_online(&alice));
+        assert!(tracker.last_seen(&alice).is_some());
+
+        let hidden = Node::builder("presence").attr("from", "2222@s.whatsapp.net").attr("type", "unavailable").attr("last", "deny").build();
+        assert_eq!(parse_presence(&hidden).unwrap().last_seen, None);
+
+        tracker.clear();
+        assert!(tracker.get(&alice).is_none());
+    }
+}
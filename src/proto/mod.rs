@@ -1,73 +1,91 @@
 // WhatsApp Protocol Buffer Definitions
 //
-// This module contains the generated protobuf structs from WhatsApp's .proto files
-
-// Include generated protobuf modules if compilation succeeded  
-// The generated files will be placed in src/proto/generated/ by build.rs
-pub mod generated {
-    #![allow(warnings, unused)] // Generated code may have warnings
-    
-    // Conditionally include generated protobuf files
-    // These will only be available if protoc compilation succeeded
-    macro_rules! include_proto {
-        ($path:expr) => {
-            #[cfg(all(feature = "default", not(any(target_os = "windows"))))]
-            include!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/proto/generated/", $path, ".rs"));
-        };
-    }
-    
+// This module contains the protobuf structs generated from WhatsApp's .proto
+// files. build.rs compiles them with prost-build into OUT_DIR, using a
+// vendored protoc when none is installed, so the types exist on every
+// platform.
+
+macro_rules! include_proto {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"));
+    };
+}
+
+// Module names follow the protobuf packages, generated code refers to other
+// packages through them (e.g. `super::wa_common::MessageKey`)
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_common {
     include_proto!("wa_common");
-    include_proto!("wa_web");
-    include_proto!("wa_e2e");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_adv {
+    include_proto!("wa_adv");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_companion_reg {
+    include_proto!("wa_companion_reg");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_mms_retry {
+    include_proto!("wa_mms_retry");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_status_attributions {
+    include_proto!("wa_status_attributions");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_web_protobufs_e2e {
+    include_proto!("wa_web_protobufs_e2e");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_web_protobufs_web {
+    include_proto!("wa_web_protobufs_web");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_web_protobufs_history_sync {
+    include_proto!("wa_web_protobufs_history_sync");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_msg_transport {
     include_proto!("wa_msg_transport");
 }
 
-// Fallback structures when protobuf compilation is not available
-pub mod fallback {
-    use serde::{Deserialize, Serialize};
-    
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct MessageProto {
-        pub key: String,
-        pub content: Vec<u8>,
-    }
-
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct HandshakeMessage {
-        pub client_hello: Vec<u8>,
-        pub server_hello: Vec<u8>,
-    }
-    
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct MessageKey {
-        pub remote_jid: Option<String>,
-        pub from_me: Option<bool>,
-        pub id: Option<String>,
-        pub participant: Option<String>,
-    }
-    
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct MessageText {
-        pub text: Option<String>,
-        pub mentioned_jid: Vec<String>,
-    }
-    
-    /// Placeholder message for testing
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct PlaceholderMessage {
-        pub content: String,
-    }
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_multi_device {
+    include_proto!("wa_multi_device");
 }
 
-// Protobuf utility functions
-pub mod utils;
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_server_sync {
+    include_proto!("wa_server_sync");
+}
+
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_sync_action {
+    include_proto!("wa_sync_action");
+}
 
-// Try to use generated protobuf, fall back to manual definitions
-// This allows the library to work even without protoc installed
-pub use fallback::*;
+#[allow(clippy::all, clippy::pedantic)]
+pub mod wa_web_protobufs_wa6 {
+    include_proto!("wa_web_protobufs_wa6");
+}
 
-// Also re-export generated types when available (for advanced users)
-pub use generated as proto_generated;
+// Short names matching the upstream `waE2E`, `waWeb`, ... packages
+pub use wa_web_protobufs_e2e as wa_e2e;
+pub use wa_web_protobufs_web as wa_web;
+pub use wa_web_protobufs_history_sync as wa_history_sync;
+pub use wa_web_protobufs_wa6 as wa_wa6;
+
+// Protobuf utility functions
+pub mod utils;
 
 // Re-export utilities
-pub use utils::ProtoUtils;
\ No newline at end of file
+pub use utils::ProtoUtils;
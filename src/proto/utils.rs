@@ -1,7 +1,7 @@
 use crate::{
     binary::Node,
     error::{Error, Result},
-    proto::{MessageKey, MessageText}
+    proto::{wa_common::MessageKey, wa_e2e},
 };
 use prost::Message;
 
@@ -35,24 +35,26 @@ impl ProtoUtils {
         }
     }
     
-    /// Extract message text from a protobuf message
+    /// Extract the text of an encoded `Message`, plain or extended
     pub fn extract_text_message(data: &[u8]) -> Result<String> {
-        // For fallback implementation, just return the text directly
-        String::from_utf8(data.to_vec())
-            .map_err(|_| Error::Protocol("Invalid UTF-8 in message".to_string()))
+        let message = wa_e2e::Message::decode(data)?;
+        message
+            .conversation
+            .or_else(|| message.extended_text_message.and_then(|ext| ext.text))
+            .ok_or_else(|| Error::Protocol("Message has no text".to_string()))
     }
     
     /// Create a text message protobuf
-    pub fn create_text_message(text: &str) -> MessageText {
-        MessageText {
-            text: Some(text.to_string()),
-            mentioned_jid: vec![],
+    pub fn create_text_message(text: &str) -> wa_e2e::Message {
+        wa_e2e::Message {
+            conversation: Some(text.to_string()),
+            ..Default::default()
         }
     }
     
-    /// Convert MessageText to bytes (fallback implementation)
-    pub fn text_to_bytes(message: &MessageText) -> Vec<u8> {
-        message.text.as_ref().unwrap_or(&String::new()).as_bytes().to_vec()
+    /// Encode a message to bytes
+    pub fn text_to_bytes(message: &wa_e2e::Message) -> Vec<u8> {
+        message.encode_to_vec()
     }
     
     /// Create a message key protobuf
@@ -73,8 +75,10 @@ mod tests {
     #[test]
     fn test_create_text_message() {
         let msg = ProtoUtils::create_text_message("Hello, World!");
-        assert_eq!(msg.text, Some("Hello, World!".to_string()));
-        assert!(msg.mentioned_jid.is_empty());
+        assert_eq!(msg.conversation, Some("Hello, World!".to_string()));
+        
+        let bytes = ProtoUtils::text_to_bytes(&msg);
+        assert_eq!(ProtoUtils::extract_text_message(&bytes).unwrap(), "Hello, World!");
     }
     
     #[test]
@@ -84,4 +88,31 @@ mod tests {
         assert_eq!(key.id, Some("msg123".to_string()));
         assert_eq!(key.from_me, Some(true));
     }
+    
+    #[test]
+    fn test_generated_packages() {
+        use crate::proto::{wa_history_sync, wa_msg_transport, wa_web};
+        
+        let history = wa_history_sync::HistorySync {
+            sync_type: wa_history_sync::history_sync::HistorySyncType::Recent as i32,
+            conversations: vec![wa_history_sync::Conversation {
+                id: "123@s.whatsapp.net".to_string(),
+                messages: vec![wa_history_sync::HistorySyncMsg {
+                    message: Some(wa_web::WebMessageInfo {
+                        key: ProtoUtils::create_message_key("123@s.whatsapp.net", "msg1", false),
+                        message: Some(ProtoUtils::create_text_message("hi")),
+                        ..Default::default()
+                    }),
+                    msg_order_id: Some(1),
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let decoded = wa_history_sync::HistorySync::decode(history.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, history);
+        
+        let transport = wa_msg_transport::MessageTransport::default();
+        assert!(transport.encode_to_vec().is_empty());
+    }
 }
\ No newline at end of file
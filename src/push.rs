@@ -0,0 +1,111 @@
+/// Push notification registration
+///
+/// Mobile and embedded integrations cannot keep the WebSocket open all the
+/// time. A companion registers a push endpoint with a `urn:xmpp:whatsapp:push`
+/// set IQ; while it is offline the server then wakes it through that endpoint
+/// when messages are waiting. The application receives the push itself (FCM,
+/// APNs or a web push subscription) and hands it to
+/// [`Client::handle_push_wakeup`](crate::Client::handle_push_wakeup), which
+/// reconnects so the pending messages are delivered.
+
+use crate::{binary::Node, request::InfoQuery};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Namespace of push registration queries
+pub const PUSH_NAMESPACE: &str = "urn:xmpp:whatsapp:push";
+
+/// Endpoint the server sends wakeups to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PushConfig {
+    /// Firebase Cloud Messaging registration token
+    Fcm { token: String },
+    /// Apple Push Notification service device token
+    Apns {
+        token: String,
+        /// Token of the VoIP push channel, for call wakeups
+        voip_token: Option<String>,
+        /// Key the server encrypts message ids in pushes with
+        msg_id_enc_key: Option<Vec<u8>>,
+    },
+    /// Web push subscription
+    WebPush {
+        endpoint: String,
+        /// Authentication secret of the subscription
+        auth: Vec<u8>,
+        /// Public key of the subscription
+        p256dh: Vec<u8>,
+    },
+}
+
+impl PushConfig {
+    /// Platform name used by the server
+    pub fn platform(&self) -> &'static str {
+        match self {
+            PushConfig::Fcm { .. } => "gcm",
+            PushConfig::Apns { .. } => "apple",
+            PushConfig::WebPush { .. } => "web",
+        }
+    }
+
+    /// The `<config>` element of the registration query
+    fn to_node(&self) -> Node {
+        let base64 = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+        let config = Node::builder("config").attr("platform", self.platform());
+        match self {
+            PushConfig::Fcm { token } => config.attr("id", token).attr("num_acc", 1).build(),
+            PushConfig::Apns { token, voip_token, msg_id_enc_key } => {
+                let mut config = config.attr("id", token);
+                if let Some(voip_token) = voip_token {
+                    config = config.attr("voip", voip_token);
+                }
+                if let Some(key) = msg_id_enc_key {
+                    config = config.attr("pkey", base64(key));
+                }
+                config.build()
+            }
+            PushConfig::WebPush { endpoint, auth, p256dh } => config
+                .attr("endpoint", endpoint)
+                .attr("auth", base64(auth))
+                .attr("p256dh", base64(p256dh))
+                .build(),
+        }
+    }
+}
+
+/// Build the query registering a push endpoint
+pub fn build_register_query(config: &PushConfig) -> InfoQuery {
+    InfoQuery::set(PUSH_NAMESPACE).content(vec![config.to_node()])
+}
+
+/// Build the query removing the push endpoint, so the server stops waking us
+pub fn build_unregister_query() -> InfoQuery {
+    InfoQuery::set(PUSH_NAMESPACE).content(vec![Node::builder("config").attr("platform", "none").build()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_queries() {
+        let query = build_register_query(&PushConfig::Fcm { token: "fcm-token".to_string() });
+        assert_eq!(query.namespace, PUSH_NAMESPACE);
+        let config = &query.content[0];
+        assert_eq!(config.get_attr("platform").map(String::as_str), Some("gcm"));
+        assert_eq!(config.get_attr("id").map(String::as_str), Some("fcm-token"));
+
+        let query = build_register_query(&PushConfig::WebPush {
+            endpoint: "https://push.example.com/abc".to_string(),
+            auth: vec![1, 2, 3],
+            p256dh: vec![4, 5, 6],
+        });
+        let config = &query.content[0];
+        assert_eq!(config.get_attr("platform").map(String::as_str), Some("web"));
+        assert_eq!(config.get_attr("auth").map(String::as_str), Some("AQID"));
+        assert_eq!(config.get_attr("p256dh").map(String::as_str), Some("BAUG"));
+
+        let config = &build_unregister_query().content[0];
+        assert_eq!(config.get_attr("platform").map(String::as_str), Some("none"));
+    }
+}
@@ -0,0 +1,164 @@
+/// Batching of outgoing receipts
+///
+/// A receipt stanza can acknowledge several messages: the first id goes in
+/// the `id` attribute and the others in a `<list>` of `<item>`s. In busy
+/// groups the client would otherwise send one delivery or read receipt per
+/// message, so receipts to the same chat, sender and of the same type are
+/// collected for a short window and sent as one stanza.
+/// [`ReceiptBatcher`] only keeps the pending ids; the client decides when
+/// a batch is due.
+
+use crate::{binary::Node, types::JID};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Ids acknowledged by one receipt before the batch is sent early
+pub const MAX_BATCH_IDS: usize = 50;
+
+/// Receipts sharing a key go into the same stanza
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReceiptKey {
+    pub chat: JID,
+    /// Author of the messages in a group, `None` in one-to-one chats
+    pub sender: Option<JID>,
+    /// Receipt type such as `read`; `None` for delivery receipts
+    pub kind: Option<String>,
+}
+
+/// Messages acknowledged by one receipt stanza
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptBatch {
+    pub key: ReceiptKey,
+    pub ids: Vec<String>,
+}
+
+impl ReceiptBatch {
+    /// Build the receipt; typed receipts carry the time they were sent
+    pub fn to_node(&self, timestamp: u64) -> Option<Node> {
+        let (first, rest) = self.ids.split_first()?;
+        let mut receipt = Node::receipt(first, &self.key.chat);
+        if let Some(kind) = &self.key.kind {
+            receipt = receipt.kind(kind).timestamp(timestamp);
+        }
+        if let Some(sender) = &self.key.sender {
+            receipt = receipt.participant(sender);
+        }
+        Some(receipt.more_ids(rest.iter().map(String::as_str)).build())
+    }
+}
+
+/// Outcome of adding a receipt to the batcher
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Batched {
+    /// The receipt started a new batch, which is due after the window
+    Started,
+    /// The receipt joined a pending batch
+    Added,
+    /// The batch reached [`MAX_BATCH_IDS`] and has to be sent right away
+    Full(ReceiptBatch),
+}
+
+/// Pending receipts per chat, sender and type
+pub struct ReceiptBatcher {
+    max_ids: usize,
+    pending: Mutex<HashMap<ReceiptKey, Vec<String>>>,
+}
+
+impl Default for ReceiptBatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReceiptBatcher {
+    pub fn new() -> Self {
+        Self::with_max_ids(MAX_BATCH_IDS)
+    }
+
+    pub fn with_max_ids(max_ids: usize) -> Self {
+        Self {
+            max_ids: max_ids.max(1),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queue the receipt of message `id`
+    pub fn add(&self, key: ReceiptKey, id: String) -> Batched {
+        let mut pending = self.pending.lock().unwrap();
+        let ids = pending.entry(key.clone()).or_default();
+        if ids.contains(&id) {
+            return Batched::Added;
+        }
+        ids.push(id);
+        if ids.len() >= self.max_ids {
+            let ids = pending.remove(&key).unwrap_or_default();
+            return Batched::Full(ReceiptBatch { key, ids });
+        }
+        if ids.len() == 1 {
+            Batched::Started
+        } else {
+            Batched::Added
+        }
+    }
+
+    /// Take the pending batch of a key once it is due
+    pub fn take(&self, key: &ReceiptKey) -> Option<ReceiptBatch> {
+        let ids = self.pending.lock().unwrap().remove(key)?;
+        Some(ReceiptBatch { key: key.clone(), ids })
+    }
+
+    /// Take every pending batch, e.g. before disconnecting
+    pub fn take_all(&self) -> Vec<ReceiptBatch> {
+        self.pending
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(key, ids)| ReceiptBatch { key, ids })
+            .collect()
+    }
+
+    /// Number of receipts waiting to be sent
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().unwrap().values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_receipts() {
+        let group: JID = "123-456@g.us".parse().unwrap();
+        let key = ReceiptKey {
+            chat: group.clone(),
+            sender: Some(JID::new_user("1111")),
+            kind: Some("read".to_string()),
+        };
+        let batcher = ReceiptBatcher::with_max_ids(3);
+
+        assert_eq!(batcher.add(key.clone(), "M1".to_string()), Batched::Started);
+        assert_eq!(batcher.add(key.clone(), "M2".to_string()), Batched::Added);
+        assert_eq!(batcher.add(key.clone(), "M2".to_string()), Batched::Added);
+        let delivery = ReceiptKey { kind: None, ..key.clone() };
+        assert_eq!(batcher.add(delivery.clone(), "M1".to_string()), Batched::Started);
+        assert_eq!(batcher.pending_count(), 3);
+
+        let Batched::Full(batch) = batcher.add(key.clone(), "M3".to_string()) else {
+            panic!("batch should be full");
+        };
+        assert_eq!(batch.ids, ["M1", "M2", "M3"]);
+        assert!(batcher.take(&key).is_none());
+
+        let node = batch.to_node(1_792_152_180).unwrap();
+        assert_eq!(node.get_attr("id").map(String::as_str), Some("M1"));
+        assert_eq!(node.get_attr("type").map(String::as_str), Some("read"));
+        assert_eq!(node.get_attr("participant").map(String::as_str), Some("1111@s.whatsapp.net"));
+        assert_eq!(node.find_child("list").and_then(Node::get_children).map(Vec::len), Some(2));
+
+        let node = batcher.take(&delivery).unwrap().to_node(1_792_152_180).unwrap();
+        assert_eq!(node.get_attr("type"), None);
+        assert_eq!(node.get_attr("t"), None);
+        assert!(batcher.take_all().is_empty());
+    }
+}
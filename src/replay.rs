@@ -0,0 +1,295 @@
+/// Replay protection for inbound stanzas
+///
+/// After a resume the server may re-deliver frames that were already
+/// processed. Handling them twice corrupts state (double-counted receipts,
+/// duplicate message events, acks applied to already finished messages), so
+/// the client remembers the ids of recently processed stanzas per sender and
+/// drops anything it has seen before. Dropped stanzas are still acked
+/// (see [`ack_for`]), otherwise the server keeps re-delivering them.
+
+use crate::binary::Node;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default number of stanza ids remembered per sender
+pub const DEFAULT_IDS_PER_SENDER: usize = 512;
+
+/// Default time an id is remembered
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Default number of senders tracked before idle ones are pruned
+pub const DEFAULT_MAX_SENDERS: usize = 4096;
+
+/// Stanza tags subject to replay protection
+const TRACKED_TAGS: &[&str] = &["message", "receipt", "ack", "notification", "call"];
+
+/// Stanza tags the server expects an `<ack>` for
+const ACKED_TAGS: &[&str] = &["message", "receipt", "notification", "call"];
+
+/// Configuration of the replay filter
+#[derive(Debug, Clone)]
+pub struct ReplayFilterConfig {
+    /// Number of ids remembered per sender
+    pub ids_per_sender: usize,
+    /// Time an id is remembered
+    pub window: Duration,
+    /// Number of senders tracked before idle ones are pruned
+    pub max_senders: usize,
+}
+
+impl Default for ReplayFilterConfig {
+    fn default() -> Self {
+        Self {
+            ids_per_sender: DEFAULT_IDS_PER_SENDER,
+            window: DEFAULT_REPLAY_WINDOW,
+            max_senders: DEFAULT_MAX_SENDERS,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SenderWindow {
+    seen: HashSet<String>,
+    order: VecDeque<(String, Instant)>,
+}
+
+impl SenderWindow {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((key, seen_at)) = self.order.front() {
+            if now.duration_since(*seen_at) < window {
+                break;
+            }
+            self.seen.remove(key);
+            self.order.pop_front();
+        }
+    }
+}
+
+/// Filter dropping re-delivered stanzas
+pub struct ReplayFilter {
+    config: ReplayFilterConfig,
+    senders: Mutex<HashMap<String, SenderWindow>>,
+}
+
+impl ReplayFilter {
+    /// Create a new filter with default configuration
+    pub fn new() -> Self {
+        Self::with_config(ReplayFilterConfig::default())
+    }
+
+    /// Create a new filter with custom configuration
+    pub fn with_config(config: ReplayFilterConfig) -> Self {
+        Self {
+            config,
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a received stanza.
+    ///
+    /// Returns `false` if the stanza was already processed and should be
+    /// dropped. Stanzas without an id or of untracked types always pass.
+    pub fn check(&self, node: &Node, now: Instant) -> bool {
+        let Some((sender, key)) = replay_key(node) else {
+            return true;
+        };
+
+        let mut senders = self.senders.lock().unwrap();
+        if !senders.contains_key(&sender) && senders.len() >= self.config.max_senders {
+            let window = self.config.window;
+            senders.retain(|_, w| {
+                w.expire(now, window);
+                !w.order.is_empty()
+            });
+        }
+
+        let entry = senders.entry(sender).or_default();
+        entry.expire(now, self.config.window);
+        if entry.seen.contains(&key) {
+            return false;
+        }
+
+        entry.seen.insert(key.clone());
+        entry.order.push_back((key, now));
+        if entry.order.len() > self.config.ids_per_sender {
+            if let Some((oldest, _)) = entry.order.pop_front() {
+                entry.seen.remove(&oldest);
+            }
+        }
+
+        true
+    }
+
+    /// Forget a processed stanza so it is accepted again, e.g. a message
+    /// the sender was asked to retry
+    pub fn forget(&self, node: &Node) {
+        let Some((sender, key)) = replay_key(node) else {
+            return;
+        };
+        if let Some(entry) = self.senders.lock().unwrap().get_mut(&sender) {
+            if entry.seen.remove(&key) {
+                entry.order.retain(|(seen, _)| *seen != key);
+            }
+        }
+    }
+
+    /// Number of senders currently tracked
+    pub fn tracked_senders(&self) -> usize {
+        self.senders.lock().unwrap().len()
+    }
+
+    /// Forget all processed stanzas
+    pub fn clear(&self) {
+        self.senders.lock().unwrap().clear();
+    }
+}
+
+impl Default for ReplayFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build the `<ack>` confirming a received stanza.
+///
+/// The server keeps re-delivering stanzas it got no ack for, so this is sent
+/// for replayed stanzas too. Returns `None` for stanzas that are not acked.
+pub fn ack_for(node: &Node) -> Option<Node> {
+    if !ACKED_TAGS.contains(&node.tag.as_str()) {
+        return None;
+    }
+
+    let mut ack = Node::new("ack".to_string())
+        .attr("class".to_string(), node.tag.clone())
+        .attr("id".to_string(), node.get_attr("id")?.clone());
+    if let Some(from) = node.get_attr("from") {
+        ack = ack.attr("to".to_string(), from.clone());
+    }
+    for name in ["participant", "recipient"] {
+        if let Some(value) = node.get_attr(name) {
+            ack = ack.attr(name.to_string(), value.clone());
+        }
+    }
+    // Unlike other acks, message acks carry no type
+    if node.tag != "message" {
+        if let Some(kind) = node.get_attr("type") {
+            ack = ack.attr("type".to_string(), kind.clone());
+        }
+    }
+    Some(ack)
+}
+
+/// Build the `(sender, key)` pair identifying a stanza.
+///
+/// Receipts and acks reuse the id of the message they refer to, so the
+/// receipt/ack type (and class for acks) is part of the key: a `read` receipt
+/// following a delivery receipt for the same message is not a replay.
+fn replay_key(node: &Node) -> Option<(String, String)> {
+    if !TRACKED_TAGS.contains(&node.tag.as_str()) {
+        return None;
+    }
+
+    let id = node.get_attr("id")?;
+    let from = node.get_attr("from").map(|f| f.as_str()).unwrap_or_default();
+    let sender = match node.get_attr("participant") {
+        Some(participant) => format!("{}/{}", from, participant),
+        None => from.to_string(),
+    };
+
+    let attr = |name: &str| node.get_attr(name).map(|v| v.as_str()).unwrap_or_default();
+    let key = format!("{}|{}|{}|{}", node.tag, attr("class"), attr("type"), id);
+
+    Some((sender, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stanza(tag: &str, id: &str, from: &str) -> Node {
+        Node::new(tag.to_string())
+            .attr("id".to_string(), id.to_string())
+            .attr("from".to_string(), from.to_string())
+    }
+
+    #[test]
+    fn test_drops_replays() {
+        let filter = ReplayFilter::new();
+        let now = Instant::now();
+        let message = stanza("message", "ABC", "1234@s.whatsapp.net");
+
+        assert!(filter.check(&message, now));
+        assert!(!filter.check(&message, now));
+        // Same id from another sender is unrelated
+        assert!(filter.check(&stanza("message", "ABC", "5678@s.whatsapp.net"), now));
+        // Untracked stanzas always pass
+        let iq = stanza("iq", "1", "s.whatsapp.net");
+        assert!(filter.check(&iq, now));
+        assert!(filter.check(&iq, now));
+
+        // A retried message is accepted again once forgotten
+        filter.forget(&message);
+        assert!(filter.check(&message, now));
+    }
+
+    #[test]
+    fn test_receipt_types_are_distinct() {
+        let filter = ReplayFilter::new();
+        let now = Instant::now();
+        let delivered = stanza("receipt", "ABC", "1234@s.whatsapp.net");
+        let read = delivered.clone().attr("type".to_string(), "read".to_string());
+
+        assert!(filter.check(&delivered, now));
+        assert!(filter.check(&read, now));
+        assert!(!filter.check(&read, now));
+
+        let ack = stanza("ack", "ABC", "1234@s.whatsapp.net").attr("class".to_string(), "message".to_string());
+        assert!(filter.check(&ack, now));
+        assert!(!filter.check(&ack, now));
+    }
+
+    #[test]
+    fn test_window_and_capacity() {
+        let filter = ReplayFilter::with_config(ReplayFilterConfig {
+            ids_per_sender: 2,
+            window: Duration::from_secs(60),
+            max_senders: 1,
+        });
+        let now = Instant::now();
+        let from = "1234@s.whatsapp.net";
+
+        assert!(filter.check(&stanza("message", "1", from), now));
+        assert!(!filter.check(&stanza("message", "1", from), now + Duration::from_secs(30)));
+        assert!(filter.check(&stanza("message", "1", from), now + Duration::from_secs(61)));
+
+        filter.check(&stanza("message", "2", from), now + Duration::from_secs(62));
+        filter.check(&stanza("message", "3", from), now + Duration::from_secs(62));
+        // Evicted by capacity
+        assert!(filter.check(&stanza("message", "1", from), now + Duration::from_secs(62)));
+
+        // A new sender prunes expired ones
+        filter.check(&stanza("message", "1", "5678@s.whatsapp.net"), now + Duration::from_secs(200));
+        assert_eq!(filter.tracked_senders(), 1);
+    }
+
+    #[test]
+    fn test_ack_for() {
+        let message = stanza("message", "ABC", "123@g.us")
+            .attr("participant".to_string(), "1234@s.whatsapp.net".to_string())
+            .attr("type".to_string(), "text".to_string());
+        let ack = ack_for(&message).unwrap();
+        assert_eq!(ack.tag, "ack");
+        assert_eq!(ack.get_attr("class").unwrap(), "message");
+        assert_eq!(ack.get_attr("id").unwrap(), "ABC");
+        assert_eq!(ack.get_attr("to").unwrap(), "123@g.us");
+        assert_eq!(ack.get_attr("participant").unwrap(), "1234@s.whatsapp.net");
+        assert!(ack.get_attr("type").is_none());
+
+        let read = stanza("receipt", "ABC", "1234@s.whatsapp.net").attr("type".to_string(), "read".to_string());
+        assert_eq!(ack_for(&read).unwrap().get_attr("type").unwrap(), "read");
+
+        assert!(ack_for(&stanza("ack", "ABC", "1234@s.whatsapp.net")).is_none());
+        assert!(ack_for(&stanza("iq", "1", "s.whatsapp.net")).is_none());
+    }
+}
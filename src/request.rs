@@ -0,0 +1,434 @@
+/// Info query (IQ) request/response handling
+///
+/// IQ stanzas are the request/response primitive of the WhatsApp protocol. Each
+/// outgoing query carries a unique `id`, and the server answers with an `iq`
+/// of type `result` or `error` carrying the same id.
+
+use crate::{
+    binary::{BinaryEncoder, Node, NodeContent},
+    error::{Error, Result},
+    metrics,
+    socket::NoiseSocket,
+    types::JID,
+    wirelog::{Direction, WireLog},
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// Default time to wait for an IQ response
+pub const DEFAULT_IQ_TIMEOUT: Duration = Duration::from_secs(75);
+
+pub use crate::types::DEFAULT_USER_SERVER;
+
+/// IQ request type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoQueryType {
+    Get,
+    Set,
+}
+
+impl InfoQueryType {
+    /// Wire representation of the query type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InfoQueryType::Get => "get",
+            InfoQueryType::Set => "set",
+        }
+    }
+}
+
+/// An outgoing info query
+#[derive(Debug, Clone)]
+pub struct InfoQuery {
+    /// Protocol namespace (`xmlns` attribute)
+    pub namespace: String,
+    /// Query type
+    pub query_type: InfoQueryType,
+    /// Recipient of the query
+    pub to: JID,
+    /// Optional target JID (used e.g. for group queries)
+    pub target: Option<JID>,
+    /// Explicit request id, generated by the client if unset
+    pub id: Option<String>,
+    /// Child nodes of the query
+    pub content: Vec<Node>,
+    /// Response timeout, defaults to [`DEFAULT_IQ_TIMEOUT`]
+    pub timeout: Option<Duration>,
+}
+
+impl InfoQuery {
+    /// Create a new query for the given namespace
+    pub fn new(namespace: &str, query_type: InfoQueryType, to: JID) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            query_type,
+            to,
+            target: None,
+            id: None,
+            content: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Create a `get` query addressed to the default server
+    pub fn get(namespace: &str) -> Self {
+        Self::new(namespace, InfoQueryType::Get, server_jid())
+    }
+
+    /// Create a `set` query addressed to the default server
+    pub fn set(namespace: &str) -> Self {
+        Self::new(namespace, InfoQueryType::Set, server_jid())
+    }
+
+    /// Set the query recipient
+    pub fn to(mut self, to: JID) -> Self {
+        self.to = to;
+        self
+    }
+
+    /// Set the query target
+    pub fn target(mut self, target: JID) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Set the child nodes of the query
+    pub fn content(mut self, content: Vec<Node>) -> Self {
+        self.content = content;
+        self
+    }
+
+    /// Set a custom response timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Build the `iq` node for this query with the given request id
+    pub fn to_node(&self, id: &str) -> Node {
+        let mut iq = Node::iq().id(id).to(&self.to);
+        if let Some(target) = &self.target {
+            iq = iq.target(target);
+        }
+
+        let iq = match self.query_type {
+            InfoQueryType::Get => iq.get(),
+            InfoQueryType::Set => iq.set(),
+        };
+        iq.query(&self.namespace, |q| q.nodes(self.content.iter().cloned())).build()
+    }
+}
+
+/// Get the JID of the default WhatsApp server
+pub fn server_jid() -> JID {
+    JID::server_jid()
+}
+
+/// Tracks in-flight IQ requests waiting for a response
+pub struct ResponseWaiters {
+    prefix: String,
+    counter: AtomicU64,
+    waiters: Mutex<HashMap<String, oneshot::Sender<Node>>>,
+}
+
+impl ResponseWaiters {
+    /// Create a new waiter table with a random id prefix
+    pub fn new() -> Self {
+        Self {
+            prefix: format!("{}.{}", fastrand::u16(..), fastrand::u16(..)),
+            counter: AtomicU64::new(0),
+            waiters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Generate a unique request id
+    pub fn generate_request_id(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+        format!("{}-{}", self.prefix, count)
+    }
+
+    /// Register a waiter for the given request id
+    pub fn add(&self, id: &str) -> oneshot::Receiver<Node> {
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.lock().unwrap().insert(id.to_string(), sender);
+        receiver
+    }
+
+    /// Remove a waiter without delivering a response
+    pub fn cancel(&self, id: &str) {
+        self.waiters.lock().unwrap().remove(id);
+    }
+
+    /// Number of requests currently waiting for a response
+    pub fn pending(&self) -> usize {
+        self.waiters.lock().unwrap().len()
+    }
+
+    /// Deliver a received node to its waiter.
+    ///
+    /// Handles IQ responses as well as server acks of sent messages, which
+    /// are waited for under the message id.
+    ///
+    /// Returns `true` if the node was a response to a pending request.
+    pub fn receive_response(&self, node: &Node) -> bool {
+        match node.tag.as_str() {
+            "iq" => match node.get_attr("type").map(|t| t.as_str()) {
+                Some("result") | Some("error") => {}
+                _ => return false,
+            },
+            "ack" if node.get_attr("class").map(|c| c.as_str()) == Some("message") => {}
+            _ => return false,
+        }
+
+        let Some(id) = node.get_attr("id") else {
+            return false;
+        };
+
+        let waiter = self.waiters.lock().unwrap().remove(id);
+        match waiter {
+            Some(sender) => {
+                if sender.send(node.clone()).is_err() {
+                    debug!("Response for {} arrived after the waiter was dropped", id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wait for the response to a request, cleaning up on timeout
+    pub async fn wait(&self, id: &str, receiver: oneshot::Receiver<Node>, timeout: Duration) -> Result<Node> {
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(node)) => parse_iq_response(node),
+            Ok(Err(_)) => Err(Error::Disconnected(format!("Response waiter for {} was dropped", id))),
+            Err(_) => {
+                self.cancel(id);
+                Err(Error::Connection(format!("Timed out waiting for response to {}", id)))
+            }
+        }
+    }
+}
+
+impl Default for ResponseWaiters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Something that can send info queries and wait for their responses.
+///
+/// Managers that talk to the server (groups, participants, ...) hold an
+/// `Arc<dyn IqSender>` instead of a reference to the whole client.
+#[async_trait]
+pub trait IqSender: Send + Sync {
+    /// Send an info query and wait for the response
+    async fn send_iq(&self, query: InfoQuery) -> Result<Node>;
+}
+
+/// [`IqSender`] writing queries to the noise socket of a client
+pub struct SocketIqSender {
+    socket: Arc<tokio::sync::Mutex<Option<NoiseSocket>>>,
+    waiters: Arc<ResponseWaiters>,
+    wire_log: Option<Arc<WireLog>>,
+}
+
+impl SocketIqSender {
+    /// Create a sender sharing the client's socket and waiter table
+    pub fn new(socket: Arc<tokio::sync::Mutex<Option<NoiseSocket>>>, waiters: Arc<ResponseWaiters>) -> Self {
+        Self { socket, waiters, wire_log: None }
+    }
+
+    /// Record every sent node in `wire_log`
+    pub fn with_wire_log(mut self, wire_log: Arc<WireLog>) -> Self {
+        self.wire_log = Some(wire_log);
+        self
+    }
+
+    /// Encode and send a single node
+    pub async fn send_node(&self, node: &Node) -> Result<()> {
+        let data = BinaryEncoder::new().encode(node)?;
+        if let Some(wire_log) = &self.wire_log {
+            wire_log.record(Direction::Sent, node);
+        }
+
+        let mut socket_guard = self.socket.lock().await;
+        match socket_guard.as_mut() {
+            Some(socket) => socket.send(data).await,
+            None => Err(Error::Connection("Socket not connected".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl IqSender for SocketIqSender {
+    async fn send_iq(&self, query: InfoQuery) -> Result<Node> {
+        let id = query.id.clone().unwrap_or_else(|| self.waiters.generate_request_id());
+        let receiver = self.waiters.add(&id);
+
+        if let Err(e) = self.send_node(&query.to_node(&id)).await {
+            self.waiters.cancel(&id);
+            return Err(e);
+        }
+
+        let started = std::time::Instant::now();
+        let timeout = query.timeout.unwrap_or(DEFAULT_IQ_TIMEOUT);
+        let response = self.waiters.wait(&id, receiver, timeout).await;
+        metrics::iq_latency(&query.namespace, started.elapsed());
+        response
+    }
+}
+
+/// [`IqSender`] answering queries with canned responses, for tests.
+///
+/// Responses are returned in order; the last one answers all further queries.
+#[cfg(test)]
+pub(crate) struct StaticIqSender {
+    pub responses: Mutex<Vec<Node>>,
+    pub queries: Mutex<Vec<InfoQuery>>,
+}
+
+#[cfg(test)]
+impl StaticIqSender {
+    pub fn new(response: Node) -> Self {
+        Self::sequence(vec![response])
+    }
+
+    pub fn sequence(responses: Vec<Node>) -> Self {
+        Self {
+            responses: Mutex::new(responses),
+            queries: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl IqSender for StaticIqSender {
+    async fn send_iq(&self, query: InfoQuery) -> Result<Node> {
+        self.queries.lock().unwrap().push(query);
+        let mut responses = self.responses.lock().unwrap();
+        let response = if responses.len() > 1 {
+            responses.remove(0)
+        } else {
+            responses[0].clone()
+        };
+        parse_iq_response(response)
+    }
+}
+
+/// Convert an IQ response into an error if it has type `error`
+pub fn parse_iq_response(node: Node) -> Result<Node> {
+    if node.get_attr("type").map(|t| t.as_str()) != Some("error") {
+        return Ok(node);
+    }
+
+    let (code, text) = match node.find_child("error") {
+        Some(error) => (
+            error.get_attr("code").and_then(|c| c.parse().ok()).unwrap_or(0),
+            error.get_attr("text").cloned().unwrap_or_default(),
+        ),
+        None => (0, String::new()),
+    };
+
+    Err(Error::iq(code, text, node))
+}
+
+/// Get the text content of a node, accepting both text and binary payloads
+pub fn node_content_string(node: &Node) -> Option<String> {
+    match &node.content {
+        NodeContent::Text(text) => Some(text.clone()),
+        NodeContent::Binary(data) => String::from_utf8(data.clone()).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_to_node() {
+        let query = InfoQuery::get("usync").content(vec![Node::new("usync".to_string())]);
+        let node = query.to_node("abc-1");
+
+        assert_eq!(node.tag, "iq");
+        assert_eq!(node.get_attr("id").unwrap(), "abc-1");
+        assert_eq!(node.get_attr("xmlns").unwrap(), "usync");
+        assert_eq!(node.get_attr("type").unwrap(), "get");
+        assert_eq!(node.get_attr("to").unwrap(), "s.whatsapp.net");
+        assert!(node.find_child("usync").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_response_delivery() {
+        let waiters = ResponseWaiters::new();
+        let id = waiters.generate_request_id();
+        let receiver = waiters.add(&id);
+        assert_eq!(waiters.pending(), 1);
+
+        let response = Node::new("iq".to_string())
+            .attr("id".to_string(), id.clone())
+            .attr("type".to_string(), "result".to_string());
+        assert!(waiters.receive_response(&response));
+        assert_eq!(waiters.pending(), 0);
+
+        let node = waiters.wait(&id, receiver, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(node.get_attr("id"), Some(&id));
+    }
+
+    #[tokio::test]
+    async fn test_message_ack_delivery() {
+        let waiters = ResponseWaiters::new();
+        let receiver = waiters.add("3EB0ABCD");
+
+        let receipt_ack = Node::new("ack".to_string())
+            .attr("id".to_string(), "3EB0ABCD".to_string())
+            .attr("class".to_string(), "receipt".to_string());
+        assert!(!waiters.receive_response(&receipt_ack));
+
+        let ack = Node::new("ack".to_string())
+            .attr("id".to_string(), "3EB0ABCD".to_string())
+            .attr("class".to_string(), "message".to_string())
+            .attr("t".to_string(), "1700000000".to_string());
+        assert!(waiters.receive_response(&ack));
+
+        let node = waiters.wait("3EB0ABCD", receiver, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(node.tag, "ack");
+    }
+
+    #[tokio::test]
+    async fn test_error_response_and_timeout() {
+        let waiters = ResponseWaiters::new();
+        let id = waiters.generate_request_id();
+        let receiver = waiters.add(&id);
+
+        let response = Node::new("iq".to_string())
+            .attr("id".to_string(), id.clone())
+            .attr("type".to_string(), "error".to_string())
+            .with_children(vec![Node::new("error".to_string())
+                .attr("code".to_string(), "404".to_string())
+                .attr("text".to_string(), "item-not-found".to_string())]);
+        assert!(waiters.receive_response(&response));
+
+        match waiters.wait(&id, receiver, Duration::from_secs(1)).await {
+            Err(Error::Iq { code, text, node }) => {
+                assert_eq!(code, 404);
+                assert_eq!(text, "item-not-found");
+                assert_eq!(node.unwrap().get_attr("id"), Some(&id));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let id = waiters.generate_request_id();
+        let receiver = waiters.add(&id);
+        assert!(waiters.wait(&id, receiver, Duration::from_millis(10)).await.is_err());
+        assert_eq!(waiters.pending(), 0);
+    }
+}
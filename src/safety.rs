@@ -0,0 +1,189 @@
+/// Runtime guardrails for outbound traffic
+///
+/// Operators of automated accounts need a way to stop a misbehaving bot
+/// immediately without tearing down the connection, which would lose
+/// in-flight sync state and trigger a full resync on reconnect. The
+/// [`SendGuard`] sits in the send pipeline and offers two controls:
+///
+/// * a global kill-switch pausing every stanza sent on the user's behalf
+///   (messages, receipts, presence, chat states and calls), and
+/// * a per-JID blocklist of recipients that must never be sent to.
+///
+/// IQs and acks are never blocked so keepalives, app state sync and
+/// delivery bookkeeping keep working while sending is paused.
+///
+/// A client in passive mode additionally refuses the same stanzas with
+/// [`Error::PassiveMode`], except delivery and retry receipts which the
+/// server and senders need to keep delivering and re-encrypting messages.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    types::JID,
+};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Stanzas sent on the user's behalf, stopped by the kill-switch
+const GUARDED_TAGS: &[&str] = &["message", "receipt", "presence", "chatstate", "call"];
+
+/// Receipt types a passive client still sends; no type is a delivery receipt
+const PASSIVE_RECEIPT_TYPES: &[&str] = &["retry"];
+
+/// Kill-switch and recipient blocklist enforced before sending
+#[derive(Debug, Default)]
+pub struct SendGuard {
+    paused: AtomicBool,
+    passive: AtomicBool,
+    blocked: RwLock<HashSet<String>>,
+}
+
+impl SendGuard {
+    /// Create a guard that lets everything through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop all guarded outbound stanzas until [`resume`](Self::resume) is called
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Allow guarded outbound stanzas again
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether the kill-switch is engaged
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Refuse everything sent on the user's behalf, see the module docs
+    pub fn set_passive(&self, passive: bool) {
+        self.passive.store(passive, Ordering::SeqCst);
+    }
+
+    /// Whether the client is a passive listener
+    pub fn is_passive(&self) -> bool {
+        self.passive.load(Ordering::SeqCst)
+    }
+
+    /// Never send anything to the given JID (all of its devices)
+    pub fn block(&self, jid: &JID) {
+        self.blocked.write().unwrap().insert(blocklist_key(jid));
+    }
+
+    /// Remove a JID from the blocklist. Returns whether it was blocked.
+    pub fn unblock(&self, jid: &JID) -> bool {
+        self.blocked.write().unwrap().remove(&blocklist_key(jid))
+    }
+
+    /// Whether the given JID is on the blocklist
+    pub fn is_blocked(&self, jid: &JID) -> bool {
+        self.blocked.read().unwrap().contains(&blocklist_key(jid))
+    }
+
+    /// JIDs currently on the blocklist
+    pub fn blocked(&self) -> Vec<String> {
+        self.blocked.read().unwrap().iter().cloned().collect()
+    }
+
+    /// Check whether a message may be sent to `to`
+    pub fn check_recipient(&self, to: &JID) -> Result<()> {
+        if self.is_passive() {
+            return Err(Error::PassiveMode);
+        }
+        if self.is_paused() {
+            return Err(Error::SendBlocked("outbound traffic is paused".to_string()));
+        }
+        if self.is_blocked(to) {
+            return Err(Error::SendBlocked(format!("{} is on the send blocklist", to)));
+        }
+        Ok(())
+    }
+
+    /// Check whether a stanza may be sent
+    pub fn check_node(&self, node: &Node) -> Result<()> {
+        if !GUARDED_TAGS.contains(&node.tag.as_str()) {
+            return Ok(());
+        }
+        if self.is_passive() && !is_passive_receipt(node) {
+            return Err(Error::PassiveMode);
+        }
+        match node.get_attr("to").and_then(|to| to.parse::<JID>().ok()) {
+            Some(to) => self.check_recipient(&to),
+            None if self.is_paused() => Err(Error::SendBlocked("outbound traffic is paused".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_passive_receipt(node: &Node) -> bool {
+    node.tag == "receipt"
+        && node.get_attr("type").is_none_or(|kind| PASSIVE_RECEIPT_TYPES.contains(&kind.as_str()))
+}
+
+fn blocklist_key(jid: &JID) -> String {
+    jid.to_non_ad().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stanza(tag: &str, to: &str) -> Node {
+        Node::new(tag.to_string()).attr("to".to_string(), to.to_string())
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        let guard = SendGuard::new();
+        let message = stanza("message", "1234@s.whatsapp.net");
+        let iq = stanza("iq", "s.whatsapp.net");
+
+        assert!(guard.check_node(&message).is_ok());
+        guard.pause();
+        assert!(matches!(guard.check_node(&message), Err(Error::SendBlocked(_))));
+        assert!(guard.check_node(&Node::new("presence".to_string())).is_err());
+        // Keepalives and sync keep flowing
+        assert!(guard.check_node(&iq).is_ok());
+        assert!(guard.check_node(&stanza("ack", "1234@s.whatsapp.net")).is_ok());
+        guard.resume();
+        assert!(guard.check_node(&message).is_ok());
+    }
+
+    #[test]
+    fn test_passive_mode() {
+        let guard = SendGuard::new();
+        guard.set_passive(true);
+        let delivery = stanza("receipt", "1234@s.whatsapp.net");
+        let read = stanza("receipt", "1234@s.whatsapp.net").attr("type".to_string(), "read".to_string());
+
+        assert!(matches!(guard.check_node(&stanza("message", "1234@s.whatsapp.net")), Err(Error::PassiveMode)));
+        assert!(matches!(guard.check_node(&read), Err(Error::PassiveMode)));
+        assert!(guard.check_node(&Node::new("presence".to_string())).is_err());
+        assert!(guard.check_node(&delivery).is_ok());
+        assert!(guard.check_node(&stanza("iq", "s.whatsapp.net")).is_ok());
+        assert!(matches!(guard.check_recipient(&JID::new_user("1234")), Err(Error::PassiveMode)));
+
+        guard.set_passive(false);
+        assert!(guard.check_node(&read).is_ok());
+    }
+
+    #[test]
+    fn test_blocklist_covers_all_devices() {
+        let guard = SendGuard::new();
+        let user = JID::new("1234".to_string(), "s.whatsapp.net".to_string());
+        guard.block(&user.with_device(3));
+
+        assert!(guard.is_blocked(&user));
+        assert!(guard.check_recipient(&user).is_err());
+        assert!(guard.check_node(&stanza("message", "1234:7@s.whatsapp.net")).is_err());
+        assert!(guard.check_node(&stanza("message", "5678@s.whatsapp.net")).is_ok());
+
+        assert!(guard.unblock(&user));
+        assert!(guard.check_recipient(&user).is_ok());
+    }
+}
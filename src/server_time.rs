@@ -0,0 +1,104 @@
+/// Server time and clock skew
+///
+/// Message timestamps, receipts, app state patches and disappearing message
+/// expiry must agree with the server's clock, but the local clock of a
+/// server or embedded device is often off by minutes. The skew is measured
+/// from the `t` attribute the server puts on the login `<success>` node and on
+/// the result of a `w:p` ping, and [`now`] returns the local time corrected by
+/// it. Every client in the process shares the local clock, so the skew is
+/// kept process wide.
+
+use crate::{binary::Node, request::InfoQuery};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Skews smaller than this are within the one second precision of the server time
+pub const MIN_SKEW: Duration = Duration::from_secs(1);
+
+static SKEW_MILLIS: AtomicI64 = AtomicI64::new(0);
+
+/// Current time on the server's clock
+pub fn now() -> SystemTime {
+    apply_skew(SystemTime::now(), skew_millis())
+}
+
+/// Current server time in unix seconds
+pub fn unix_now() -> u64 {
+    now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Milliseconds the server's clock is ahead of the local one, negative if behind
+pub fn skew_millis() -> i64 {
+    SKEW_MILLIS.load(Ordering::Relaxed)
+}
+
+/// Replace the measured skew
+pub fn set_skew_millis(skew: i64) {
+    SKEW_MILLIS.store(skew, Ordering::Relaxed);
+}
+
+/// Skew between a server time and the local time it was observed at.
+///
+/// `sent` and `received` bracket the request the server answered; the
+/// server time is compared with their midpoint. Skews below [`MIN_SKEW`] are
+/// rounding noise and reported as zero.
+pub fn measure_skew(server_time: SystemTime, sent: SystemTime, received: SystemTime) -> i64 {
+    let sent = unix_millis(sent);
+    let local = sent + (unix_millis(received) - sent) / 2;
+    let skew = unix_millis(server_time) - local;
+    if skew.unsigned_abs() < MIN_SKEW.as_millis() as u64 {
+        0
+    } else {
+        skew
+    }
+}
+
+/// Server time in the `t` attribute of a node
+pub fn parse_server_time(node: &Node) -> Option<SystemTime> {
+    let seconds: u64 = node.get_attr("t")?.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Build the ping whose result carries the server time
+pub fn build_time_query() -> InfoQuery {
+    InfoQuery::get("w:p").content(vec![Node::new("ping".to_string())])
+}
+
+fn apply_skew(time: SystemTime, skew: i64) -> SystemTime {
+    let offset = Duration::from_millis(skew.unsigned_abs());
+    if skew >= 0 {
+        time + offset
+    } else {
+        time - offset
+    }
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_and_apply_skew() {
+        let at = |seconds: u64, millis: u64| UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_millis(millis);
+
+        // The local clock is 90 seconds behind, the round trip took 400 ms
+        let skew = measure_skew(at(1_090, 0), at(999, 800), at(1_000, 200));
+        assert_eq!(skew, 90_000);
+        assert_eq!(apply_skew(at(1_000, 0), skew), at(1_090, 0));
+        assert_eq!(apply_skew(at(1_000, 0), -skew), at(910, 0));
+
+        // Sub-second differences are the truncation of the server time
+        assert_eq!(measure_skew(at(1_000, 0), at(1_000, 300), at(1_000, 500)), 0);
+
+        let node = Node::builder("iq").attr("type", "result").attr("t", "1700000000").build();
+        assert_eq!(parse_server_time(&node), Some(at(1_700_000_000, 0)));
+        assert!(parse_server_time(&Node::new("iq".to_string())).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+/// Safety numbers for verifying identity keys out of band
+///
+/// Both sides of a chat derive the same 60-digit number from the two
+/// identity keys and the stable identifiers (phone numbers) of the users.
+/// When the numbers shown on both phones match, no one is sitting in the
+/// middle of the conversation. The scannable variant is the
+/// `CombinedFingerprints` protobuf put into the verification QR code.
+
+use super::{IdentityKey, DJB_TYPE};
+use prost::Message;
+use sha2::{Digest, Sha512};
+
+/// Hash iterations per fingerprint, as used by WhatsApp
+pub const FINGERPRINT_ITERATIONS: usize = 5200;
+
+/// Version prefixed to the hashed data
+const FINGERPRINT_VERSION: u16 = 0;
+
+/// Version of the scannable fingerprint
+const SCANNABLE_VERSION: u32 = 1;
+
+/// Number of fingerprint bytes in the QR code
+const SCANNABLE_LENGTH: usize = 32;
+
+#[derive(Clone, PartialEq, Message)]
+struct LogicalFingerprint {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    content: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct CombinedFingerprints {
+    #[prost(uint32, optional, tag = "1")]
+    version: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    local_fingerprint: Option<LogicalFingerprint>,
+    #[prost(message, optional, tag = "3")]
+    remote_fingerprint: Option<LogicalFingerprint>,
+}
+
+/// Safety number of a chat between two identities
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyNumber {
+    /// The 60 digits, identical on both sides
+    pub digits: String,
+    /// Contents of the verification QR code
+    pub qr_data: Vec<u8>,
+    local: Vec<u8>,
+    remote: Vec<u8>,
+}
+
+impl SafetyNumber {
+    /// Compute the safety number from our and the peer's identifier and identity key
+    pub fn new(local_id: &str, local_key: &IdentityKey, remote_id: &str, remote_key: &IdentityKey) -> Self {
+        let local = fingerprint_hash(local_id, local_key);
+        let remote = fingerprint_hash(remote_id, remote_key);
+
+        let local_digits = displayable(&local);
+        let remote_digits = displayable(&remote);
+        let digits = if local_digits <= remote_digits {
+            local_digits + &remote_digits
+        } else {
+            remote_digits + &local_digits
+        };
+
+        let local = local[..SCANNABLE_LENGTH].to_vec();
+        let remote = remote[..SCANNABLE_LENGTH].to_vec();
+        let qr_data = CombinedFingerprints {
+            version: Some(SCANNABLE_VERSION),
+            local_fingerprint: Some(LogicalFingerprint { content: Some(local.clone()) }),
+            remote_fingerprint: Some(LogicalFingerprint { content: Some(remote.clone()) }),
+        }
+        .encode_to_vec();
+
+        Self { digits, qr_data, local, remote }
+    }
+
+    /// The digits in 12 groups of 5, as shown in the app
+    pub fn formatted(&self) -> String {
+        self.digits
+            .as_bytes()
+            .chunks(5)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Check QR data scanned from the peer's screen against this safety number
+    pub fn matches_scanned(&self, scanned: &[u8]) -> bool {
+        let Ok(scanned) = CombinedFingerprints::decode(scanned) else {
+            return false;
+        };
+        let content = |fingerprint: Option<LogicalFingerprint>| fingerprint.and_then(|f| f.content);
+
+        // The peer's local fingerprint is our remote one and vice versa
+        scanned.version == Some(SCANNABLE_VERSION)
+            && content(scanned.local_fingerprint).as_deref() == Some(self.remote.as_slice())
+            && content(scanned.remote_fingerprint).as_deref() == Some(self.local.as_slice())
+    }
+}
+
+fn fingerprint_hash(id: &str, key: &IdentityKey) -> Vec<u8> {
+    let mut public_key = vec![DJB_TYPE];
+    public_key.extend_from_slice(&key.public_key);
+
+    let mut hash = FINGERPRINT_VERSION.to_be_bytes().to_vec();
+    hash.extend_from_slice(&public_key);
+    hash.extend_from_slice(id.as_bytes());
+    for _ in 0..FINGERPRINT_ITERATIONS {
+        let mut digest = Sha512::new();
+        digest.update(&hash);
+        digest.update(&public_key);
+        hash = digest.finalize().to_vec();
+    }
+    hash
+}
+
+/// Encode the first 30 bytes of a fingerprint as 6 chunks of 5 digits
+fn displayable(hash: &[u8]) -> String {
+    hash[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = IdentityKey::new([1; 32]);
+        let bob = IdentityKey::new([2; 32]);
+
+        let ours = SafetyNumber::new("111", &alice, "222", &bob);
+        let theirs = SafetyNumber::new("222", &bob, "111", &alice);
+        assert_eq!(ours.digits.len(), 60);
+        assert!(ours.digits.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(ours.digits, theirs.digits);
+        assert_eq!(ours.formatted().split(' ').count(), 12);
+
+        assert!(ours.matches_scanned(&theirs.qr_data));
+        assert!(!ours.matches_scanned(&ours.qr_data));
+
+        let mallory = SafetyNumber::new("222", &IdentityKey::new([3; 32]), "111", &alice);
+        assert_ne!(ours.digits, mallory.digits);
+        assert!(!ours.matches_scanned(&mallory.qr_data));
+    }
+}
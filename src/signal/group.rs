@@ -9,7 +9,7 @@ use crate::{
     },
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Sender key for group messaging
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -254,6 +254,8 @@ pub struct GroupSession {
     pub our_sender_key: Option<SenderKeyState>,
     /// Other participants' sender keys
     pub participant_keys: HashMap<String, SenderKeyState>,
+    /// Devices our current sender key was distributed to
+    pub distributed_to: HashSet<String>,
 }
 
 impl GroupSession {
@@ -263,6 +265,7 @@ impl GroupSession {
             group_id,
             our_sender_key: None,
             participant_keys: HashMap::new(),
+            distributed_to: HashSet::new(),
         }
     }
     
@@ -279,8 +282,9 @@ impl GroupSession {
         let signing_keypair = ECKeyPair::generate();
         let signing_key = signing_keypair.public_bytes();
         
-        // Create our sender key state
+        // Create our sender key state, which nobody has received yet
         self.our_sender_key = Some(SenderKeyState::new(sender_key_id, chain_key, signing_key));
+        self.distributed_to.clear();
         
         // Create distribution message
         Ok(SenderKeyDistribution::new(sender_key_id, 0, chain_key, signing_key))
@@ -292,18 +296,32 @@ impl GroupSession {
         sender_address: &str,
         distribution: &SenderKeyDistribution,
     ) -> Result<()> {
-        // Create sender key state for this participant
-        let sender_key_state = SenderKeyState::new(
+        // Create sender key state for this participant, starting where the
+        // sender's chain was when the key was distributed
+        let mut sender_key_state = SenderKeyState::new(
             distribution.id,
             distribution.chain_key,
             distribution.signing_key,
         );
+        sender_key_state.sender_key.iteration = distribution.iteration;
         
         self.participant_keys.insert(sender_address.to_string(), sender_key_state);
         
         Ok(())
     }
     
+    /// Distribution message for the current state of our sender key
+    pub fn sender_key_distribution(&self) -> Option<SenderKeyDistribution> {
+        self.our_sender_key.as_ref().map(|state| {
+            SenderKeyDistribution::new(
+                state.sender_key_id,
+                state.sender_key.iteration,
+                state.sender_key.chain_key,
+                state.sender_key.signing_key,
+            )
+        })
+    }
+    
     /// Encrypt message for the group
     pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<SignalMessage> {
         match &mut self.our_sender_key {
@@ -458,6 +476,22 @@ mod tests {
         assert_eq!(decrypted, plaintext);
     }
     
+    #[test]
+    fn test_late_sender_key_distribution() {
+        let mut sender = GroupSession::new("test-group".to_string());
+        sender.initialize_sender_key(1).unwrap();
+        sender.encrypt(b"before").unwrap();
+        
+        // A participant joining later starts at the current iteration
+        let distribution = sender.sender_key_distribution().unwrap();
+        assert_eq!(distribution.iteration, 1);
+        let mut receiver = GroupSession::new("test-group".to_string());
+        receiver.process_sender_key_distribution("bob", &distribution).unwrap();
+        
+        let encrypted = sender.encrypt(b"after").unwrap();
+        assert_eq!(receiver.decrypt("bob", &encrypted).unwrap(), b"after");
+    }
+    
     #[test]
     fn test_memory_group_session_store() {
         let mut store = MemoryGroupSessionStore::new();
@@ -1,9 +1,11 @@
 /// Identity key management for Signal protocol
 
+use super::DJB_TYPE;
 use crate::{
     error::{Error, Result},
     util::keys::SigningKeyPair,
 };
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -53,6 +55,28 @@ impl Default for TrustLevel {
     }
 }
 
+/// What to do when a peer shows up with a different identity key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IdentityChangePolicy {
+    /// Accept the new key, like the official apps do
+    #[default]
+    AutoTrust,
+    /// Store the new key as blocked until it is explicitly trusted
+    Block,
+}
+
+/// Outcome of comparing an incoming identity key with the stored one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityCheck {
+    /// No key was stored for the address yet
+    New,
+    /// The key matches the stored one
+    Unchanged,
+    /// The key differs from the stored one; `blocked` is set if the
+    /// policy refused it
+    Changed { blocked: bool },
+}
+
 /// Identity key record with trust information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityKeyRecord {
@@ -106,12 +130,18 @@ pub trait IdentityKeyStore {
     
     /// Set trust level for an identity key
     fn set_trust_level(&mut self, address: &str, trust_level: TrustLevel) -> Result<()>;
+    
+    /// Get the identity key of an address with its trust information
+    fn get_identity_record(&self, address: &str) -> Option<IdentityKeyRecord>;
+    
+    /// Restore a previously saved identity record as is
+    fn store_identity_record(&mut self, address: &str, record: IdentityKeyRecord);
 }
 
 /// In-memory identity key store implementation
 #[derive(Debug)]
 pub struct MemoryIdentityKeyStore {
-    identity_keypair: SigningKeyPair,
+    identity_keypair: Option<SigningKeyPair>,
     local_registration_id: u32,
     identity_keys: HashMap<String, IdentityKeyRecord>,
 }
@@ -120,16 +150,25 @@ impl MemoryIdentityKeyStore {
     /// Create a new memory identity key store
     pub fn new(registration_id: u32) -> Self {
         Self {
-            identity_keypair: SigningKeyPair::generate(),
+            identity_keypair: Some(SigningKeyPair::generate()),
             local_registration_id: registration_id,
             identity_keys: HashMap::new(),
         }
     }
     
+    /// Create a store without identity key, for a device that is not paired yet
+    pub fn unregistered() -> Self {
+        Self {
+            identity_keypair: None,
+            local_registration_id: 0,
+            identity_keys: HashMap::new(),
+        }
+    }
+    
     /// Create with existing identity keypair
     pub fn with_keypair(keypair: SigningKeyPair, registration_id: u32) -> Self {
         Self {
-            identity_keypair: keypair,
+            identity_keypair: Some(keypair),
             local_registration_id: registration_id,
             identity_keys: HashMap::new(),
         }
@@ -138,7 +177,7 @@ impl MemoryIdentityKeyStore {
 
 impl IdentityKeyStore for MemoryIdentityKeyStore {
     fn get_identity_keypair(&self) -> Option<SigningKeyPair> {
-        Some(self.identity_keypair.clone())
+        self.identity_keypair.clone()
     }
     
     fn get_local_registration_id(&self) -> u32 {
@@ -185,6 +224,39 @@ impl IdentityKeyStore for MemoryIdentityKeyStore {
             None => Err(Error::Protocol(format!("No identity key found for {}", address))),
         }
     }
+    
+    fn get_identity_record(&self, address: &str) -> Option<IdentityKeyRecord> {
+        self.identity_keys.get(address).cloned()
+    }
+    
+    fn store_identity_record(&mut self, address: &str, record: IdentityKeyRecord) {
+        self.identity_keys.insert(address.to_string(), record);
+    }
+}
+
+/// Fields of a serialized `PreKeySignalMessage` we need before decrypting it
+#[derive(Clone, PartialEq, Message)]
+struct PreKeyMessageIdentity {
+    #[prost(bytes = "vec", optional, tag = "3")]
+    identity_key: Option<Vec<u8>>,
+}
+
+/// Extract the sender's identity key from a `pkmsg` payload
+/// (version byte followed by the `PreKeySignalMessage` protobuf)
+pub fn prekey_message_identity(serialized: &[u8]) -> Result<IdentityKey> {
+    let body = serialized
+        .get(1..)
+        .ok_or_else(|| Error::Protocol("Empty pre-key message".to_string()))?;
+    let key = PreKeyMessageIdentity::decode(body)?
+        .identity_key
+        .ok_or_else(|| Error::Protocol("Pre-key message without identity key".to_string()))?;
+    let key = match key.as_slice() {
+        [DJB_TYPE, rest @ ..] if rest.len() == 32 => rest,
+        key => key,
+    };
+    key.try_into()
+        .map(IdentityKey::new)
+        .map_err(|_| Error::Crypto("Invalid identity key length".to_string()))
 }
 
 /// Direction of the protocol exchange
@@ -284,4 +356,16 @@ mod tests {
         // Save different identity - should be new/changed
         assert!(store.save_identity(address, &identity2).unwrap());
     }
+    
+    #[test]
+    fn test_prekey_message_identity() {
+        let mut key = vec![DJB_TYPE];
+        key.extend_from_slice(&[9; 32]);
+        let mut serialized = vec![0x33];
+        PreKeyMessageIdentity { identity_key: Some(key) }.encode(&mut serialized).unwrap();
+        
+        assert_eq!(prekey_message_identity(&serialized).unwrap().public_bytes(), [9; 32]);
+        assert!(prekey_message_identity(&[]).is_err());
+        assert!(prekey_message_identity(&[0x33]).is_err());
+    }
 }
\ No newline at end of file
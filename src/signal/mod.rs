@@ -13,7 +13,11 @@ pub mod session;
 pub mod prekey;
 pub mod identity;
 pub mod group;
+pub mod fingerprint;
+pub mod record;
+mod persist;
 
+pub use fingerprint::SafetyNumber;
 pub use session::*;
 pub use prekey::*; 
 pub use identity::*;
@@ -22,6 +26,9 @@ pub use group::*;
 /// Signal protocol version used by WhatsApp
 pub const SIGNAL_PROTOCOL_VERSION: u8 = 3;
 
+/// Highest pre-key ID, IDs are sent as 3 byte integers
+pub const MAX_PREKEY_ID: u32 = 0xFF_FFFF;
+
 /// Key type identifiers
 pub const DJB_TYPE: u8 = 0x05;
 pub const EC_TYPE: u8 = 0x05;
@@ -192,11 +199,20 @@ impl Default for SignalSession {
 }
 
 /// Complete Signal protocol manager for WhatsApp
+///
+/// Works on the stores it was created with. A manager opened with
+/// [`load`](Self::load) keeps track of the records it changes and writes
+/// them to the persistent [`Store`](crate::store::Store) on
+/// [`persist`](Self::persist).
 pub struct SignalProtocolManager {
     identity_store: Box<dyn IdentityKeyStore + Send + Sync>,
     session_store: Box<dyn SessionStore + Send + Sync>,
     prekey_store: Box<dyn PreKeyStore + Send + Sync>,
     group_store: Box<dyn GroupSessionStore + Send + Sync>,
+    /// Persistent store of the device, `None` for memory-only managers
+    backend: Option<persist::Backend>,
+    /// Records changed since the last persist
+    changes: persist::Changes,
 }
 
 impl SignalProtocolManager {
@@ -207,9 +223,24 @@ impl SignalProtocolManager {
             session_store: Box::new(MemorySessionStore::new()),
             prekey_store: Box::new(MemoryPreKeyStore::new()),
             group_store: Box::new(MemoryGroupSessionStore::new()),
+            backend: None,
+            changes: persist::Changes::default(),
         }
     }
     
+    /// Create a manager for a device that is not paired yet.
+    ///
+    /// It has no identity key, so it cannot encrypt or generate keys; it is
+    /// replaced by [`load`](Self::load) once the device credentials exist.
+    pub fn unregistered() -> Self {
+        Self::new_with_stores(
+            Box::new(MemoryIdentityKeyStore::unregistered()),
+            Box::new(MemorySessionStore::new()),
+            Box::new(MemoryPreKeyStore::new()),
+            Box::new(MemoryGroupSessionStore::new()),
+        )
+    }
+    
     /// Create new Signal protocol manager with custom stores
     pub fn new_with_stores(
         identity_store: Box<dyn IdentityKeyStore + Send + Sync>,
@@ -222,6 +253,8 @@ impl SignalProtocolManager {
             session_store,
             prekey_store,
             group_store,
+            backend: None,
+            changes: persist::Changes::default(),
         }
     }
     
@@ -236,16 +269,76 @@ impl SignalProtocolManager {
         let signed_prekey_id = 1; // In real implementation, should be incremented
         let signed_prekey = SignedPreKey::generate(signed_prekey_id, &identity_keypair)?;
         self.prekey_store.store_signed_prekey(signed_prekey.clone());
+        self.changes.signed_prekey = true;
         
         // Generate one-time pre-key
         let prekey_id = 1; // In real implementation, should be incremented
         let prekey = PreKey::generate(prekey_id);
         self.prekey_store.store_prekey(prekey.clone());
+        self.changes.prekeys.insert(prekey_id);
         
         // Create bundle
         PreKeyBundle::new(&identity_keypair, signed_prekey_id, Some(prekey_id), registration_id, device_id)
     }
     
+    /// Get our local registration ID
+    pub fn registration_id(&self) -> u32 {
+        self.identity_store.get_local_registration_id()
+    }
+    
+    /// Get the public part of our identity key
+    pub fn identity_public_key(&self) -> Result<[u8; 32]> {
+        self.identity_store.get_identity_keypair()
+            .map(|keypair| keypair.public_bytes())
+            .ok_or_else(|| Error::Protocol("No identity key available".to_string()))
+    }
+    
+    /// Generate and store a batch of one-time pre-keys.
+    ///
+    /// IDs continue after the highest stored ID and wrap around within the
+    /// 24 bits available on the wire.
+    pub fn generate_prekeys(&mut self, count: u32) -> Vec<PreKey> {
+        let mut next_id = self.prekey_store.load_prekey_ids().into_iter().max().unwrap_or(0);
+        (0..count)
+            .map(|_| {
+                next_id = next_id % MAX_PREKEY_ID + 1;
+                let prekey = PreKey::generate(next_id);
+                self.prekey_store.store_prekey(prekey.clone());
+                self.changes.prekeys.insert(next_id);
+                prekey
+            })
+            .collect()
+    }
+    
+    /// Get the newest signed pre-key
+    pub fn current_signed_prekey(&self) -> Option<SignedPreKey> {
+        self.prekey_store.load_signed_prekey_ids()
+            .into_iter()
+            .max()
+            .and_then(|id| self.prekey_store.load_signed_prekey(id))
+    }
+    
+    /// Get the newest signed pre-key, generating the first one if none exists
+    pub fn get_or_create_signed_prekey(&mut self) -> Result<SignedPreKey> {
+        match self.current_signed_prekey() {
+            Some(signed_prekey) => Ok(signed_prekey),
+            None => self.rotate_signed_prekey(),
+        }
+    }
+    
+    /// Generate and store a new signed pre-key with the next ID.
+    ///
+    /// Older signed pre-keys are kept so sessions started with them still work.
+    pub fn rotate_signed_prekey(&mut self) -> Result<SignedPreKey> {
+        let identity_keypair = self.identity_store.get_identity_keypair()
+            .ok_or_else(|| Error::Protocol("No identity key available".to_string()))?;
+        let id = self.prekey_store.load_signed_prekey_ids().into_iter().max().unwrap_or(0) % MAX_PREKEY_ID + 1;
+        let signed_prekey = SignedPreKey::generate(id, &identity_keypair)?;
+        self.prekey_store.store_signed_prekey(signed_prekey.clone());
+        self.changes.signed_prekey = true;
+        Ok(signed_prekey)
+    }
+    
     /// Initialize session with a contact (Alice side - initiator)
     pub fn initialize_outgoing_session(&mut self, address: &str, bundle: &PreKeyBundle) -> Result<()> {
         let identity_keypair = self.identity_store.get_identity_keypair()
@@ -262,13 +355,17 @@ impl SignalProtocolManager {
         
         // Store session
         self.session_store.store_session(address, session);
+        self.changes.sessions.insert(address.to_string());
         
-        // Save peer identity
+        // Pin the peer identity, keeping the trust level of a known key
         let peer_identity = IdentityKey::new(
             bundle.identity_key.as_slice().try_into()
                 .map_err(|_| Error::Crypto("Invalid identity key length".to_string()))?
         );
-        self.identity_store.save_identity(address, &peer_identity)?;
+        if self.identity_store.get_identity(address).map(|identity| identity.public_key) != Some(peer_identity.public_key) {
+            self.identity_store.save_identity(address, &peer_identity)?;
+            self.changes.identities.insert(address.to_string());
+        }
         
         Ok(())
     }
@@ -280,6 +377,7 @@ impl SignalProtocolManager {
         if let Some(mut session) = self.session_store.load_session(address) {
             let plaintext = session.decrypt(message)?;
             self.session_store.store_session(address, session);
+            self.changes.sessions.insert(address.to_string());
             Ok(plaintext)
         } else {
             Err(Error::Protocol("No session found for pre-key message".to_string()))
@@ -293,6 +391,7 @@ impl SignalProtocolManager {
         
         let encrypted = session.encrypt(plaintext)?;
         self.session_store.store_session(address, session);
+        self.changes.sessions.insert(address.to_string());
         
         Ok(encrypted)
     }
@@ -304,6 +403,7 @@ impl SignalProtocolManager {
         
         let plaintext = session.decrypt(message)?;
         self.session_store.store_session(address, session);
+        self.changes.sessions.insert(address.to_string());
         
         Ok(plaintext)
     }
@@ -314,7 +414,7 @@ impl SignalProtocolManager {
         let sender_key_id = 1; // In real implementation, should be unique
         
         let distribution = group_session.initialize_sender_key(sender_key_id)?;
-        self.group_store.store_group_session(group_session);
+        self.store_group_session(group_session);
         
         Ok(distribution)
     }
@@ -330,18 +430,48 @@ impl SignalProtocolManager {
             .unwrap_or_else(|| GroupSession::new(group_id.to_string()));
         
         group_session.process_sender_key_distribution(sender_address, distribution)?;
-        self.group_store.store_group_session(group_session);
+        self.store_group_session(group_session);
         
         Ok(())
     }
     
+    /// Our sender key for a group, created on first use
+    pub fn group_sender_key(&mut self, group_id: &str) -> Result<SenderKeyDistribution> {
+        let mut group_session = self.group_store.load_group_session(group_id)
+            .unwrap_or_else(|| GroupSession::new(group_id.to_string()));
+        
+        let distribution = match group_session.sender_key_distribution() {
+            Some(distribution) => distribution,
+            None => group_session.initialize_sender_key(1)?,
+        };
+        self.store_group_session(group_session);
+        
+        Ok(distribution)
+    }
+    
+    /// Devices of `addresses` that have not received our sender key for a group
+    pub fn missing_sender_key(&self, group_id: &str, addresses: &[String]) -> Vec<String> {
+        let distributed = self.group_store.load_group_session(group_id)
+            .map(|session| session.distributed_to)
+            .unwrap_or_default();
+        addresses.iter().filter(|address| !distributed.contains(*address)).cloned().collect()
+    }
+    
+    /// Remember that devices received our sender key for a group
+    pub fn mark_sender_key_distributed(&mut self, group_id: &str, addresses: &[String]) {
+        if let Some(mut group_session) = self.group_store.load_group_session(group_id) {
+            group_session.distributed_to.extend(addresses.iter().cloned());
+            self.store_group_session(group_session);
+        }
+    }
+    
     /// Encrypt message for a group
     pub fn encrypt_group_message(&mut self, group_id: &str, plaintext: &[u8]) -> Result<SignalMessage> {
         let mut group_session = self.group_store.load_group_session(group_id)
             .ok_or_else(|| Error::Protocol("No group session found".to_string()))?;
         
         let encrypted = group_session.encrypt(plaintext)?;
-        self.group_store.store_group_session(group_session);
+        self.store_group_session(group_session);
         
         Ok(encrypted)
     }
@@ -357,11 +487,17 @@ impl SignalProtocolManager {
             .ok_or_else(|| Error::Protocol("No group session found".to_string()))?;
         
         let plaintext = group_session.decrypt(sender_address, message)?;
-        self.group_store.store_group_session(group_session);
+        self.store_group_session(group_session);
         
         Ok(plaintext)
     }
     
+    /// Store a group session and remember to persist it
+    fn store_group_session(&mut self, group_session: GroupSession) {
+        self.changes.groups.insert(group_session.group_id.clone());
+        self.group_store.store_group_session(group_session);
+    }
+    
     /// Check if we have a session with an address
     pub fn has_session(&self, address: &str) -> bool {
         self.session_store.contains_session(address)
@@ -379,7 +515,49 @@ impl SignalProtocolManager {
     
     /// Set trust level for an identity
     pub fn set_trust_level(&mut self, address: &str, trust_level: TrustLevel) -> Result<()> {
-        self.identity_store.set_trust_level(address, trust_level)
+        self.identity_store.set_trust_level(address, trust_level)?;
+        self.changes.identities.insert(address.to_string());
+        Ok(())
+    }
+    
+    /// Get the stored identity key of an address
+    pub fn get_identity(&self, address: &str) -> Option<IdentityKey> {
+        self.identity_store.get_identity(address)
+    }
+    
+    /// Compare an incoming identity key with the stored one and store it
+    /// according to `policy`
+    pub fn check_identity(
+        &mut self,
+        address: &str,
+        identity_key: &IdentityKey,
+        policy: IdentityChangePolicy,
+    ) -> Result<IdentityCheck> {
+        match self.identity_store.get_identity(address) {
+            None => {
+                self.identity_store.save_identity(address, identity_key)?;
+                self.changes.identities.insert(address.to_string());
+                Ok(IdentityCheck::New)
+            }
+            Some(stored) if stored.public_key == identity_key.public_key => Ok(IdentityCheck::Unchanged),
+            Some(_) => {
+                self.identity_store.save_identity(address, identity_key)?;
+                self.changes.identities.insert(address.to_string());
+                let blocked = policy == IdentityChangePolicy::Block;
+                if blocked {
+                    self.identity_store.set_trust_level(address, TrustLevel::Blocked)?;
+                }
+                Ok(IdentityCheck::Changed { blocked })
+            }
+        }
+    }
+    
+    /// Compute the safety number of a chat with the given peer
+    pub fn safety_number(&self, local_id: &str, remote_id: &str, remote_address: &str) -> Result<SafetyNumber> {
+        let local_key = IdentityKey::new(self.identity_public_key()?);
+        let remote_key = self.identity_store.get_identity(remote_address)
+            .ok_or_else(|| Error::Protocol(format!("No identity key known for {}", remote_address)))?;
+        Ok(SafetyNumber::new(local_id, &local_key, remote_id, &remote_key))
     }
     
     /// Check if identity is trusted
@@ -481,4 +659,29 @@ mod tests {
         let _encrypted = alice.encrypt_message("bob@example.com", plaintext);
         // Note: Decryption would require proper session initialization
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_identity_change_policy() {
+        let mut manager = SignalProtocolManager::new_with_memory_stores(11111);
+        let first = IdentityKey::new([1; 32]);
+        let second = IdentityKey::new([2; 32]);
+        let address = "1234:0";
+        
+        assert_eq!(manager.check_identity(address, &first, IdentityChangePolicy::Block).unwrap(), IdentityCheck::New);
+        assert_eq!(manager.check_identity(address, &first, IdentityChangePolicy::Block).unwrap(), IdentityCheck::Unchanged);
+        
+        let check = manager.check_identity(address, &second, IdentityChangePolicy::Block).unwrap();
+        assert_eq!(check, IdentityCheck::Changed { blocked: true });
+        assert!(!manager.is_trusted_identity(address, &second));
+        manager.set_trust_level(address, TrustLevel::Trusted).unwrap();
+        assert!(manager.is_trusted_identity(address, &second));
+        
+        let check = manager.check_identity(address, &first, IdentityChangePolicy::AutoTrust).unwrap();
+        assert_eq!(check, IdentityCheck::Changed { blocked: false });
+        assert!(manager.is_trusted_identity(address, &first));
+        
+        let safety_number = manager.safety_number("111", "1234", address).unwrap();
+        assert_eq!(safety_number.digits.len(), 60);
+        assert!(manager.safety_number("111", "5678", "5678:0").is_err());
+    }
+}
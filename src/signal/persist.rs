@@ -0,0 +1,347 @@
+/// Persistence of the Signal state of a paired device
+///
+/// The [`SignalProtocolManager`] encrypts and decrypts with in-memory stores.
+/// [`SignalProtocolManager::load`] fills them with the state of one device
+/// from a [`Store`], and every operation records which sessions, identities,
+/// pre-keys and group sessions it changed. [`SignalProtocolManager::persist`]
+/// writes those records back as [`record`](super::record) envelopes; records
+/// written before the envelopes existed still load and are rewritten in the
+/// new format the next time they change. Callers
+/// persist while they still hold the manager, right after the operation, so
+/// a ratchet step is on disk before the message using it is sent.
+
+use super::{
+    record::{self, RecordKind},
+    GroupSession, PreKey, SenderKeyState, SessionState, SignalProtocolManager,
+    MemoryGroupSessionStore, MemoryIdentityKeyStore, MemoryPreKeyStore, MemorySessionStore,
+    GroupSessionStore, IdentityKeyStore, PreKeyStore, SessionStore,
+};
+use crate::{
+    error::{Error, Result},
+    store::{self, DeviceData, Store},
+    types::JID,
+    util::keys::SigningKeyPair,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Persistent store a manager writes its changes to
+pub(super) struct Backend {
+    store: Arc<dyn Store>,
+    device: store::Device,
+}
+
+/// Records changed since the last [`SignalProtocolManager::persist`]
+#[derive(Debug, Default)]
+pub(super) struct Changes {
+    pub sessions: BTreeSet<String>,
+    pub identities: BTreeSet<String>,
+    pub prekeys: BTreeSet<u32>,
+    pub groups: BTreeSet<String>,
+    pub signed_prekey: bool,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+            && self.identities.is_empty()
+            && self.prekeys.is_empty()
+            && self.groups.is_empty()
+            && !self.signed_prekey
+    }
+
+    /// Add changes that failed to persist back to the pending ones
+    fn merge(&mut self, other: Changes) {
+        self.sessions.extend(other.sessions);
+        self.identities.extend(other.identities);
+        self.prekeys.extend(other.prekeys);
+        self.groups.extend(other.groups);
+        self.signed_prekey |= other.signed_prekey;
+    }
+}
+
+impl SignalProtocolManager {
+    /// Open the Signal state of a paired device.
+    ///
+    /// The identity key and registration ID come from `device`, sessions,
+    /// identities, pre-keys and sender keys from the sub-stores of
+    /// `store` for the device. Corrupt records are dropped with a warning,
+    /// like the [`record`] helpers do.
+    pub async fn load(store: Arc<dyn Store>, device: &DeviceData) -> Result<Self> {
+        let identity_keypair = SigningKeyPair::from_private_bytes(&device.identity_key)
+            .map_err(|e| Error::Protocol(format!("Invalid stored identity key: {}", e)))?;
+        let backend = store.device(&device.jid);
+
+        let mut identity_store = MemoryIdentityKeyStore::with_keypair(identity_keypair, device.registration_id);
+        for (address, data) in backend.identities.all_identities().await? {
+            match record::decode_identity(&data) {
+                Ok(identity) => identity_store.store_identity_record(&address, identity),
+                Err(e) => {
+                    warn!("Dropping identity key of {}: {}", address, e);
+                    backend.identities.delete_identity(&address).await?;
+                }
+            }
+        }
+
+        let mut session_store = MemorySessionStore::new();
+        for (address, data) in backend.sessions.all_sessions().await? {
+            match record::decode_record::<SessionState>(RecordKind::Session, &data) {
+                Ok(session) => session_store.store_session(&address, session),
+                Err(e) => {
+                    warn!("Dropping session with {}, a new one will be established: {}", address, e);
+                    backend.sessions.delete_session(&address).await?;
+                }
+            }
+        }
+
+        let mut prekey_store = MemoryPreKeyStore::new();
+        for (key_id, data, _) in backend.pre_keys.all_pre_keys().await? {
+            match record::decode_record::<PreKey>(RecordKind::PreKey, &data) {
+                Ok(prekey) => prekey_store.store_prekey(prekey),
+                Err(e) => {
+                    warn!("Dropping pre-key {}: {}", key_id, e);
+                    backend.pre_keys.remove_pre_key(key_id).await?;
+                }
+            }
+        }
+        if !device.signed_pre_key.is_empty() {
+            if let Some(signed_prekey) = record::decode_signed_pre_key(&device.signed_pre_key) {
+                prekey_store.store_signed_prekey(signed_prekey);
+            }
+        }
+
+        // Our own sender key of a group is stored under our own address
+        let own_address = device.jid.signal_address();
+        let mut groups: HashMap<String, GroupSession> = HashMap::new();
+        for (group, sender, data) in backend.sender_keys.all_sender_keys().await? {
+            let state = match record::decode_record::<SenderKeyState>(RecordKind::SenderKey, &data) {
+                Ok(state) => state,
+                Err(e) => {
+                    warn!("Ignoring sender key of {} in {}: {}", sender, group, e);
+                    continue;
+                }
+            };
+            let session = groups.entry(group.clone()).or_insert_with(|| GroupSession::new(group));
+            if sender == own_address {
+                session.our_sender_key = Some(state);
+            } else {
+                session.participant_keys.insert(sender, state);
+            }
+        }
+        let mut group_store = MemoryGroupSessionStore::new();
+        for session in groups.into_values() {
+            group_store.store_group_session(session);
+        }
+
+        let mut manager = Self::new_with_stores(
+            Box::new(identity_store),
+            Box::new(session_store),
+            Box::new(prekey_store),
+            Box::new(group_store),
+        );
+        manager.backend = Some(Backend { store, device: backend });
+        Ok(manager)
+    }
+
+    /// Device whose persistent state this manager works on
+    pub fn device_jid(&self) -> Option<&JID> {
+        self.backend.as_ref().map(|backend| &backend.device.jid)
+    }
+
+    /// Write the records changed since the last call to the persistent store.
+    ///
+    /// Does nothing for managers not opened with [`load`](Self::load). If a
+    /// write fails, the changes are kept and written by the next call.
+    pub async fn persist(&mut self) -> Result<()> {
+        let changes = std::mem::take(&mut self.changes);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let Some(backend) = &self.backend else {
+            return Ok(());
+        };
+        if let Err(e) = self.write_changes(backend, &changes).await {
+            self.changes.merge(changes);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Stored one-time pre-keys the server did not confirm yet, by ID.
+    ///
+    /// They are left over from a failed upload and are uploaded again before
+    /// new ones are generated. Always empty for memory-only managers.
+    pub async fn unuploaded_prekeys(&self) -> Result<Vec<PreKey>> {
+        let Some(backend) = &self.backend else {
+            return Ok(Vec::new());
+        };
+        let mut ids: Vec<u32> = backend.device.pre_keys.all_pre_keys().await?
+            .into_iter()
+            .filter(|(_, _, uploaded)| !uploaded)
+            .map(|(key_id, _, _)| key_id)
+            .collect();
+        ids.sort_unstable();
+        Ok(ids.into_iter().filter_map(|key_id| self.prekey_store.load_prekey(key_id)).collect())
+    }
+
+    /// Remember that the server has every one-time pre-key up to an ID
+    pub async fn mark_prekeys_uploaded(&self, up_to_id: u32) -> Result<()> {
+        match &self.backend {
+            Some(backend) => backend.device.pre_keys.mark_pre_keys_uploaded(up_to_id).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn write_changes(&self, backend: &Backend, changes: &Changes) -> Result<()> {
+        let device = &backend.device;
+        for address in &changes.sessions {
+            match self.session_store.load_session(address) {
+                Some(session) => record::store_session(device.sessions.as_ref(), address, &session).await?,
+                None => device.sessions.delete_session(address).await?,
+            }
+        }
+        for address in &changes.identities {
+            match self.identity_store.get_identity_record(address) {
+                Some(identity) => record::store_identity(device.identities.as_ref(), address, &identity).await?,
+                None => device.identities.delete_identity(address).await?,
+            }
+        }
+        for &key_id in &changes.prekeys {
+            match self.prekey_store.load_prekey(key_id) {
+                Some(prekey) => record::store_pre_key(device.pre_keys.as_ref(), &prekey).await?,
+                None => device.pre_keys.remove_pre_key(key_id).await?,
+            }
+        }
+        // Which devices got our sender key is not stored, after a restart
+        // it is distributed again
+        let own_address = device.jid.signal_address();
+        for group in &changes.groups {
+            let Some(session) = self.group_store.load_group_session(group) else {
+                continue;
+            };
+            if let Some(state) = &session.our_sender_key {
+                record::store_sender_key(device.sender_keys.as_ref(), group, &own_address, state).await?;
+            }
+            for (sender, state) in &session.participant_keys {
+                record::store_sender_key(device.sender_keys.as_ref(), group, sender, state).await?;
+            }
+        }
+        if changes.signed_prekey {
+            self.write_signed_prekey(backend).await?;
+        }
+        Ok(())
+    }
+
+    /// Save the current signed pre-key with the device credentials
+    async fn write_signed_prekey(&self, backend: &Backend) -> Result<()> {
+        let Some(signed_prekey) = self.current_signed_prekey() else {
+            return Ok(());
+        };
+        let mut data = match backend.store.load_device().await? {
+            Some(data) if data.jid == backend.device.jid => data,
+            _ => {
+                warn!("Device {} is no longer stored, not saving its signed pre-key", backend.device.jid);
+                return Ok(());
+            }
+        };
+        data.signed_pre_key = record::encode_record(RecordKind::SignedPreKey, &signed_prekey)?;
+        data.signed_pre_key_id = signed_prekey.id;
+        data.signed_pre_key_signature = signed_prekey.signature.clone();
+        backend.store.save_device(&data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::TrustLevel;
+    use crate::store::MemoryStore;
+
+    async fn paired_store() -> (Arc<dyn Store>, DeviceData) {
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let device = DeviceData {
+            jid: JID::new_user("1234").with_device(2),
+            registration_id: 777,
+            noise_key: vec![1; 32],
+            identity_key: SigningKeyPair::generate().private_bytes().to_vec(),
+            signed_pre_key: vec![],
+            signed_pre_key_id: 0,
+            signed_pre_key_signature: vec![],
+        };
+        store.save_device(&device).await.unwrap();
+        (store, device)
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_reload() {
+        let (store, device) = paired_store().await;
+        let mut alice = SignalProtocolManager::load(store.clone(), &device).await.unwrap();
+        assert_eq!(alice.registration_id(), 777);
+        assert_eq!(alice.device_jid(), Some(&device.jid));
+
+        let mut bob = SignalProtocolManager::new_with_memory_stores(1);
+        let bundle = bob.generate_prekey_bundle(0).unwrap();
+        alice.initialize_outgoing_session("5550001:0", &bundle).unwrap();
+        alice.encrypt_message("5550001:0", b"first").unwrap();
+        let prekeys = alice.generate_prekeys(3);
+        let signed_prekey = alice.rotate_signed_prekey().unwrap();
+        alice.initialize_group_session("123@g.us").unwrap();
+        alice.persist().await.unwrap();
+
+        let mut reloaded = SignalProtocolManager::load(store.clone(), &store.load_device().await.unwrap().unwrap()).await.unwrap();
+        assert_eq!(reloaded.identity_public_key().unwrap(), alice.identity_public_key().unwrap());
+        assert!(reloaded.has_session("5550001:0"));
+        assert!(reloaded.get_identity("5550001:0").is_some());
+        assert!(reloaded.has_group_session("123@g.us"));
+        assert_eq!(reloaded.current_signed_prekey().unwrap().id, signed_prekey.id);
+        assert_eq!(reloaded.generate_prekeys(1)[0].id, prekeys[2].id + 1);
+
+        // The reloaded session continues the ratchet where it stopped
+        let next = alice.encrypt_message("5550001:0", b"second").unwrap();
+        let resumed = reloaded.encrypt_message("5550001:0", b"second").unwrap();
+        assert_eq!(next.serialized, resumed.serialized);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_records_load() {
+        let (store, device) = paired_store().await;
+        let backend = store.device(&device.jid);
+        let mut bob = SignalProtocolManager::new_with_memory_stores(1);
+        let bob_identity = bob.identity_public_key().unwrap();
+        let mut alice = SignalProtocolManager::new_with_memory_stores(2);
+        alice.initialize_outgoing_session("5550001:0", &bob.generate_prekey_bundle(0).unwrap()).unwrap();
+        let session = alice.session_store.load_session("5550001:0").unwrap();
+        let prekey = alice.generate_prekeys(1).remove(0);
+
+        // Before the envelopes, records were plain JSON and identities bare keys
+        backend.sessions.put_session("5550001:0", &serde_json::to_vec(&session).unwrap()).await.unwrap();
+        backend.pre_keys.put_pre_key(prekey.id, &serde_json::to_vec(&prekey).unwrap()).await.unwrap();
+        backend.identities.put_identity("5550001:0", &bob_identity).await.unwrap();
+
+        let mut manager = SignalProtocolManager::load(store.clone(), &device).await.unwrap();
+        assert!(manager.has_session("5550001:0"));
+        assert_eq!(manager.get_identity("5550001:0").unwrap().public_key, bob_identity);
+        assert!(manager.prekey_store.load_prekey(prekey.id).is_some());
+        let expected = alice.encrypt_message("5550001:0", b"hi").unwrap();
+        assert_eq!(manager.encrypt_message("5550001:0", b"hi").unwrap().serialized, expected.serialized);
+
+        // Changed records are written back enveloped, trust included
+        manager.set_trust_level("5550001:0", TrustLevel::Blocked).unwrap();
+        manager.persist().await.unwrap();
+        let data = backend.sessions.get_session("5550001:0").await.unwrap().unwrap();
+        assert!(record::decode_record::<SessionState>(RecordKind::Session, &data).is_ok());
+        assert_ne!(data, serde_json::to_vec(&session).unwrap());
+        let data = backend.identities.get_identity("5550001:0").await.unwrap().unwrap();
+        assert!(record::decode_identity(&data).unwrap().is_blocked());
+    }
+
+    #[tokio::test]
+    async fn test_memory_manager_does_not_persist() {
+        let mut manager = SignalProtocolManager::new_with_memory_stores(1);
+        manager.generate_prekeys(2);
+        manager.persist().await.unwrap();
+        manager.mark_prekeys_uploaded(2).await.unwrap();
+        assert_eq!(manager.device_jid(), None);
+    }
+}
@@ -148,6 +148,9 @@ pub trait PreKeyStore {
     /// Remove a pre-key
     fn remove_prekey(&mut self, prekey_id: u32);
     
+    /// Get all pre-key IDs
+    fn load_prekey_ids(&self) -> Vec<u32>;
+    
     /// Load signed pre-key by ID
     fn load_signed_prekey(&self, signed_prekey_id: u32) -> Option<SignedPreKey>;
     
@@ -185,6 +188,10 @@ impl PreKeyStore for MemoryPreKeyStore {
         self.prekeys.remove(&prekey_id);
     }
     
+    fn load_prekey_ids(&self) -> Vec<u32> {
+        self.prekeys.keys().copied().collect()
+    }
+    
     fn load_signed_prekey(&self, signed_prekey_id: u32) -> Option<SignedPreKey> {
         self.signed_prekeys.get(&signed_prekey_id).cloned()
     }
@@ -0,0 +1,251 @@
+/// Versioned serialization of persisted Signal records
+///
+/// Sessions, sender keys, pre-keys and identities are stored as opaque blobs. Each blob
+/// is wrapped in a small envelope so the format can evolve:
+///
+/// ```text
+/// magic (2) | format version (1) | record kind (1) | payload | checksum (4)
+/// ```
+///
+/// The payload is the JSON encoding of the record. Unknown fields are ignored,
+/// so records written by newer versions can still be read, and fields added
+/// later are declared with serde defaults so older records keep loading. The checksum is the start of the SHA-256 of everything
+/// before it. Blobs written before the envelope existed are plain JSON and
+/// are read as format version 0, except identities, which were the raw
+/// 32-byte public key and are read as untrusted.
+///
+/// A record that fails to decode is corrupt: the `load_*` helpers drop just
+/// that record and report it missing, so a new session is negotiated instead
+/// of the whole store failing to load.
+
+use crate::{
+    error::{Error, Result},
+    signal::{IdentityKey, IdentityKeyRecord, PreKey, SenderKeyState, SessionState, SignedPreKey, TrustLevel},
+    store,
+    util::crypto::sha256,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::warn;
+
+/// Marks an enveloped record
+const RECORD_MAGIC: [u8; 2] = [0x57, 0x53];
+
+/// Envelope format written by this version
+pub const RECORD_FORMAT_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 4;
+
+/// Type of record held by an envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordKind {
+    Session = 1,
+    SenderKey = 2,
+    PreKey = 3,
+    SignedPreKey = 4,
+    Identity = 5,
+}
+
+/// Wrap a record into a versioned, checksummed envelope
+pub fn encode_record<T: Serialize>(kind: RecordKind, record: &T) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(record)?;
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+    data.extend_from_slice(&RECORD_MAGIC);
+    data.push(RECORD_FORMAT_VERSION);
+    data.push(kind as u8);
+    data.extend_from_slice(&payload);
+    let checksum = sha256(&data);
+    data.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+    Ok(data)
+}
+
+/// Read a record written by [`encode_record`] or a legacy plain JSON record
+pub fn decode_record<T: DeserializeOwned>(kind: RecordKind, data: &[u8]) -> Result<T> {
+    if !data.starts_with(&RECORD_MAGIC) {
+        return serde_json::from_slice(data)
+            .map_err(|e| Error::Serialization(format!("Corrupt legacy {:?} record: {}", kind, e)));
+    }
+    if data.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(Error::Serialization(format!("Truncated {:?} record", kind)));
+    }
+
+    let (body, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+    if sha256(body)[..CHECKSUM_LEN] != *checksum {
+        return Err(Error::Serialization(format!("Checksum mismatch in {:?} record", kind)));
+    }
+    if body[3] != kind as u8 {
+        return Err(Error::Serialization(format!("Expected {:?} record, found kind {}", kind, body[3])));
+    }
+    serde_json::from_slice(&body[HEADER_LEN..])
+        .map_err(|e| Error::Serialization(format!("Corrupt {:?} record (format {}): {}", kind, body[2], e)))
+}
+
+/// Load the session with an address, dropping it if it is corrupt
+pub async fn load_session(sessions: &dyn store::SessionRecordStore, address: &str) -> Result<Option<SessionState>> {
+    let Some(data) = sessions.get_session(address).await? else {
+        return Ok(None);
+    };
+    match decode_record(RecordKind::Session, &data) {
+        Ok(session) => Ok(Some(session)),
+        Err(e) => {
+            warn!("Dropping session with {}, a new one will be established: {}", address, e);
+            sessions.delete_session(address).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Store the session with an address
+pub async fn store_session(sessions: &dyn store::SessionRecordStore, address: &str, session: &SessionState) -> Result<()> {
+    sessions.put_session(address, &encode_record(RecordKind::Session, session)?).await
+}
+
+/// Load a one-time pre-key, dropping it if it is corrupt
+pub async fn load_pre_key(pre_keys: &dyn store::PreKeyRecordStore, key_id: u32) -> Result<Option<PreKey>> {
+    let Some(data) = pre_keys.get_pre_key(key_id).await? else {
+        return Ok(None);
+    };
+    match decode_record(RecordKind::PreKey, &data) {
+        Ok(pre_key) => Ok(Some(pre_key)),
+        Err(e) => {
+            warn!("Dropping pre-key {}: {}", key_id, e);
+            pre_keys.remove_pre_key(key_id).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Store a one-time pre-key
+pub async fn store_pre_key(pre_keys: &dyn store::PreKeyRecordStore, pre_key: &PreKey) -> Result<()> {
+    pre_keys.put_pre_key(pre_key.id, &encode_record(RecordKind::PreKey, pre_key)?).await
+}
+
+/// Load the sender key of a sender in a group.
+///
+/// A corrupt key is reported missing; it is replaced once the sender
+/// distributes their key again.
+pub async fn load_sender_key(
+    sender_keys: &dyn store::SenderKeyStore,
+    group: &str,
+    sender: &str,
+) -> Result<Option<SenderKeyState>> {
+    let Some(data) = sender_keys.get_sender_key(group, sender).await? else {
+        return Ok(None);
+    };
+    match decode_record(RecordKind::SenderKey, &data) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => {
+            warn!("Ignoring sender key of {} in {}: {}", sender, group, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Store the sender key of a sender in a group
+pub async fn store_sender_key(
+    sender_keys: &dyn store::SenderKeyStore,
+    group: &str,
+    sender: &str,
+    state: &SenderKeyState,
+) -> Result<()> {
+    sender_keys.put_sender_key(group, sender, &encode_record(RecordKind::SenderKey, state)?).await
+}
+
+/// Read a stored identity of a remote address
+pub fn decode_identity(data: &[u8]) -> Result<IdentityKeyRecord> {
+    // An envelope is always longer than a bare key
+    match <[u8; 32]>::try_from(data) {
+        Ok(key) => Ok(IdentityKeyRecord::new(IdentityKey::new(key), TrustLevel::Untrusted)),
+        Err(_) => decode_record(RecordKind::Identity, data),
+    }
+}
+
+/// Store the identity of a remote address
+pub async fn store_identity(identities: &dyn store::IdentityStore, address: &str, record: &IdentityKeyRecord) -> Result<()> {
+    identities.put_identity(address, &encode_record(RecordKind::Identity, record)?).await
+}
+
+/// Read a signed pre-key, `None` if the record is corrupt
+pub fn decode_signed_pre_key(data: &[u8]) -> Option<SignedPreKey> {
+    decode_record(RecordKind::SignedPreKey, data)
+        .inspect_err(|e| warn!("Ignoring signed pre-key: {}", e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::ChainState;
+    use crate::store::{MemoryStore, Store};
+    use crate::types::JID;
+
+    fn session() -> SessionState {
+        SessionState::new([1; 32], [2; 32], [3; 32])
+    }
+
+    #[test]
+    fn test_envelope_round_trip_and_checks() {
+        let data = encode_record(RecordKind::Session, &session()).unwrap();
+        assert_eq!(&data[..3], &[0x57, 0x53, RECORD_FORMAT_VERSION]);
+        let decoded: SessionState = decode_record(RecordKind::Session, &data).unwrap();
+        assert_eq!(decoded.root_key, [3; 32]);
+
+        // Wrong kind, flipped bits and truncation are all rejected
+        assert!(decode_record::<SessionState>(RecordKind::SenderKey, &data).is_err());
+        let mut flipped = data.clone();
+        flipped[10] ^= 0xFF;
+        assert!(decode_record::<SessionState>(RecordKind::Session, &flipped).is_err());
+        assert!(decode_record::<SessionState>(RecordKind::Session, &data[..6]).is_err());
+
+        // Legacy records are plain JSON
+        let legacy = serde_json::to_vec(&session()).unwrap();
+        assert!(decode_record::<SessionState>(RecordKind::Session, &legacy).is_ok());
+    }
+
+    #[test]
+    fn test_session_with_receiving_chains() {
+        let mut session = session();
+        session.receiving_chains.insert(vec![5; 32], ChainState { chain_key: [6; 32], message_number: 2, ephemeral_public: None });
+        let data = encode_record(RecordKind::Session, &session).unwrap();
+        let decoded: SessionState = decode_record(RecordKind::Session, &data).unwrap();
+        assert_eq!(decoded.receiving_chains[&vec![5; 32]].message_number, 2);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_ignored() {
+        let mut value = serde_json::to_value(session()).unwrap();
+        value["added_in_a_later_version"] = serde_json::json!(42);
+        let data = encode_record(RecordKind::Session, &value).unwrap();
+        let decoded: SessionState = decode_record(RecordKind::Session, &data).unwrap();
+        assert_eq!(decoded.local_identity_key, [1; 32]);
+    }
+
+    #[test]
+    fn test_identity_records() {
+        let record = IdentityKeyRecord::new(IdentityKey::new([7; 32]), TrustLevel::Blocked);
+        let data = encode_record(RecordKind::Identity, &record).unwrap();
+        let decoded = decode_identity(&data).unwrap();
+        assert_eq!(decoded.identity_key.public_key, [7; 32]);
+        assert!(decoded.is_blocked());
+
+        // Legacy identities are the bare public key
+        let legacy = decode_identity(&[7; 32]).unwrap();
+        assert_eq!(legacy.identity_key.public_key, [7; 32]);
+        assert_eq!(legacy.trust_level, TrustLevel::Untrusted);
+        assert!(decode_identity(&[7; 31]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_session_is_dropped() {
+        let store = MemoryStore::new();
+        let device = store.device(&JID::new_user("1234").with_device(1));
+        store_session(device.sessions.as_ref(), "1:0", &session()).await.unwrap();
+        device.sessions.put_session("2:0", b"\x57\x53\x01\x01garbage").await.unwrap();
+
+        assert!(load_session(device.sessions.as_ref(), "1:0").await.unwrap().is_some());
+        assert!(load_session(device.sessions.as_ref(), "2:0").await.unwrap().is_none());
+        assert!(device.sessions.get_session("2:0").await.unwrap().is_none());
+        assert!(device.sessions.get_session("1:0").await.unwrap().is_some());
+    }
+}
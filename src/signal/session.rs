@@ -23,6 +23,9 @@ fn advance_chain_key_impl(chain_key: &mut [u8; 32]) -> Result<()> {
     Ok(())
 }
 
+/// Version, message number and previous counter in front of the ciphertext
+const WHISPER_HEADER_LEN: usize = 9;
+
 /// Double Ratchet session state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionState {
@@ -43,11 +46,28 @@ pub struct SessionState {
     /// Previous counter for out-of-order messages
     pub previous_counter: u32,
     /// Receiving chains for handling out-of-order messages
+    #[serde(default, with = "chain_map")]
     pub receiving_chains: HashMap<Vec<u8>, ChainState>,
     /// Pending pre-key if this is a new session
+    #[serde(default)]
     pub pending_prekey: Option<PendingPreKey>,
 }
 
+/// Serialize the receiving chains as a list of pairs, as JSON maps only have string keys
+mod chain_map {
+    use super::ChainState;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(chains: &HashMap<Vec<u8>, ChainState>, serializer: S) -> Result<S::Ok, S::Error> {
+        chains.iter().collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Vec<u8>, ChainState>, D::Error> {
+        Ok(Vec::<(Vec<u8>, ChainState)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
 /// Chain state for Double Ratchet
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainState {
@@ -305,7 +325,7 @@ impl SessionState {
     
     /// Decrypt a whisper message
     fn decrypt_whisper_message(&mut self, message: &SignalMessage) -> Result<Vec<u8>> {
-        if message.serialized.len() < 12 {
+        if message.serialized.len() < WHISPER_HEADER_LEN {
             return Err(Error::Protocol("Invalid message format".to_string()));
         }
         
@@ -319,7 +339,7 @@ impl SessionState {
             message.serialized[5], message.serialized[6],
             message.serialized[7], message.serialized[8]
         ]);
-        let ciphertext = &message.serialized[12..];
+        let ciphertext = &message.serialized[WHISPER_HEADER_LEN..];
         
         if version != self.version {
             return Err(Error::Protocol("Version mismatch".to_string()));
@@ -533,6 +553,30 @@ mod tests {
         assert!(!store.contains_session(address));
     }
     
+    #[test]
+    fn test_whisper_message_header() {
+        let chain = || Some(ChainState { chain_key: [7u8; 32], message_number: 0, ephemeral_public: None });
+        let mut sender = SessionState::new([1u8; 32], [2u8; 32], [3u8; 32]);
+        sender.sending_chain_key = chain();
+        let mut receiver = SessionState::new([2u8; 32], [1u8; 32], [3u8; 32]);
+        receiver.receiving_chain_key = chain();
+        
+        // Version, message number and previous counter, then the ciphertext
+        let message = sender.encrypt(b"hello").unwrap();
+        assert_eq!(message.serialized[0], SIGNAL_PROTOCOL_VERSION);
+        assert_eq!(&message.serialized[1..5], &0u32.to_be_bytes());
+        assert_eq!(&message.serialized[5..WHISPER_HEADER_LEN], &0u32.to_be_bytes());
+        // AES-GCM ciphertext and tag
+        assert_eq!(message.serialized.len() - WHISPER_HEADER_LEN, b"hello".len() + 16);
+        assert_eq!(receiver.decrypt(&message).unwrap(), b"hello");
+        
+        let truncated = SignalMessage {
+            message_type: SignalMessageType::WhisperMessage,
+            serialized: message.serialized[..WHISPER_HEADER_LEN - 1].to_vec(),
+        };
+        assert!(receiver.decrypt(&truncated).is_err());
+    }
+    
     #[test]
     fn test_chain_state() {
         let chain = ChainState {
@@ -1,11 +1,15 @@
 use crate::error::{Error, Result};
-use tokio_tungstenite::{connect_async, tungstenite::Message, WebSocketStream, tungstenite::http::HeaderValue};
-use futures_util::{SinkExt, StreamExt};
-use tracing::{debug, info, warn};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tracing::{debug, info};
 use std::collections::HashMap;
 use url::Url;
 
 pub mod noise;
+pub mod proxy;
+pub mod transport;
+
+pub use proxy::{ProxyConfig, ProxyKind};
+pub use transport::{Transport, TransportFactory, WebSocketTransport};
 
 /// WhatsApp WebSocket endpoints
 pub const WHATSAPP_WS_URL: &str = "wss://web.whatsapp.com/ws/chat";
@@ -13,7 +17,7 @@ pub const WHATSAPP_WS_URL_2: &str = "wss://web.whatsapp.com/ws";
 
 /// Noise protocol socket for WhatsApp communication
 pub struct NoiseSocket {
-    ws_stream: Option<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>,
+    transport: Box<dyn Transport>,
     noise_handshake: Option<crate::socket::noise::NoiseHandshake>,
     connected: bool,
 }
@@ -23,11 +27,24 @@ impl NoiseSocket {
     pub async fn new() -> Result<Self> {
         info!("Creating new noise socket");
         
-        Ok(Self {
-            ws_stream: None,
+        Ok(Self::with_transport(Box::new(WebSocketTransport::new())))
+    }
+    
+    /// Create a noise socket on top of a custom transport
+    pub fn with_transport(transport: Box<dyn Transport>) -> Self {
+        Self {
+            transport,
             noise_handshake: None,
             connected: false,
-        })
+        }
+    }
+    
+    /// Route the WebSocket connection through the given proxy.
+    ///
+    /// Replaces the transport with a [`WebSocketTransport`] using the proxy.
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.transport = Box::new(WebSocketTransport::new().with_proxy(proxy));
+        self
     }
     
     /// Connect to WhatsApp WebSocket with proper headers and handshake
@@ -47,13 +64,7 @@ impl NoiseSocket {
             .append_pair("ed", "25519")  // Ed25519 support
             .append_pair("agent", "web"); // Web agent
         
-        debug!("Establishing WebSocket connection to: {}", parsed_url.as_str());
-        let (ws_stream, response) = connect_async(parsed_url.as_str()).await?;
-        
-        info!("WebSocket connected, status: {}", response.status());
-        debug!("Response headers: {:?}", response.headers());
-        
-        self.ws_stream = Some(ws_stream);
+        self.transport.connect(&parsed_url).await?;
         self.connected = true;
         
         // Initialize Noise handshake
@@ -87,26 +98,22 @@ impl NoiseSocket {
             return Err(Error::Connection("Socket not connected".to_string()));
         }
         
-        if let Some(ref mut stream) = self.ws_stream {
-            // If we have a completed noise handshake, encrypt the data
-            let encrypted_data = if let Some(ref mut handshake) = self.noise_handshake {
-                if handshake.is_completed() {
-                    debug!("Encrypting message of {} bytes", data.len());
-                    handshake.encrypt(&data)?
-                } else {
-                    debug!("Sending unencrypted handshake data");
-                    data
-                }
+        // If we have a completed noise handshake, encrypt the data
+        let encrypted_data = if let Some(ref mut handshake) = self.noise_handshake {
+            if handshake.is_completed() {
+                debug!("Encrypting message of {} bytes", data.len());
+                handshake.encrypt(&data)?
             } else {
+                debug!("Sending unencrypted handshake data");
                 data
-            };
-            
-            stream.send(Message::Binary(encrypted_data)).await?;
-            debug!("Message sent successfully");
-            Ok(())
+            }
         } else {
-            Err(Error::Connection("Socket not connected".to_string()))
-        }
+            data
+        };
+        
+        self.transport.send(encrypted_data).await?;
+        debug!("Message sent successfully");
+        Ok(())
     }
     
     /// Receive a message from the socket (with decryption if handshake complete)
@@ -115,59 +122,44 @@ impl NoiseSocket {
             return Err(Error::Connection("Socket not connected".to_string()));
         }
         
-        if let Some(ref mut stream) = self.ws_stream {
-            if let Some(msg) = stream.next().await {
-                match msg? {
-                    Message::Binary(encrypted_data) => {
-                        debug!("Received binary message of {} bytes", encrypted_data.len());
-                        
-                        // If we have a completed noise handshake, decrypt the data
-                        let decrypted_data = if let Some(ref mut handshake) = self.noise_handshake {
-                            if handshake.is_completed() {
-                                debug!("Decrypting received message");
-                                handshake.decrypt(&encrypted_data)?
-                            } else {
-                                debug!("Processing handshake data");
-                                encrypted_data
-                            }
-                        } else {
-                            encrypted_data
-                        };
-                        
-                        Ok(Some(decrypted_data))
-                    },
-                    Message::Text(text) => {
-                        debug!("Received text message: {}", text);
-                        Ok(Some(text.into_bytes()))
-                    },
-                    Message::Close(frame) => {
-                        warn!("WebSocket connection closed: {:?}", frame);
-                        self.connected = false;
-                        Err(Error::Disconnected("Connection closed by remote".to_string()))
-                    },
-                    Message::Ping(data) => {
-                        debug!("Received ping, sending pong");
-                        stream.send(Message::Pong(data)).await?;
-                        Ok(None)
-                    },
-                    Message::Pong(_) => {
-                        debug!("Received pong");
-                        Ok(None)
-                    },
-                    _ => Ok(None),
+        let encrypted_data = match self.transport.receive().await {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                if matches!(e, Error::Disconnected(_)) {
+                    self.connected = false;
                 }
+                return Err(e);
+            }
+        };
+        
+        // If we have a completed noise handshake, decrypt the data
+        let decrypted_data = if let Some(ref mut handshake) = self.noise_handshake {
+            if handshake.is_completed() {
+                debug!("Decrypting received message");
+                handshake.decrypt(&encrypted_data)?
             } else {
-                debug!("WebSocket stream ended");
-                self.connected = false;
-                Ok(None)
+                debug!("Processing handshake data");
+                encrypted_data
             }
         } else {
-            Err(Error::Connection("Socket not connected".to_string()))
-        }
+            encrypted_data
+        };
+        
+        Ok(Some(decrypted_data))
     }
     
-    /// Perform noise handshake with WhatsApp
+    /// Perform noise handshake with WhatsApp as a new, unpaired device
     pub async fn perform_handshake(&mut self) -> Result<()> {
+        self.perform_handshake_with(&crate::util::keys::ECKeyPair::generate(), &[]).await
+    }
+    
+    /// Perform noise handshake authenticating with a static key and client payload
+    pub async fn perform_handshake_with(
+        &mut self,
+        static_keypair: &crate::util::keys::ECKeyPair,
+        payload: &[u8],
+    ) -> Result<()> {
         if !self.connected {
             return Err(Error::Connection("Socket not connected".to_string()));
         }
@@ -187,7 +179,7 @@ impl NoiseSocket {
                 let finish_message = {
                     let handshake = self.noise_handshake.as_mut().unwrap();
                     handshake.process_server_response(&server_response)?;
-                    handshake.create_client_finish()?
+                    handshake.create_client_finish_with(static_keypair, payload)?
                 };
                 self.send(finish_message).await?;
                 
@@ -216,21 +208,44 @@ impl NoiseSocket {
     
     /// Send a ping frame
     pub async fn ping(&mut self) -> Result<()> {
-        if let Some(ref mut stream) = self.ws_stream {
-            stream.send(Message::Ping(vec![])).await?;
-            Ok(())
-        } else {
-            Err(Error::Connection("Socket not connected".to_string()))
+        if !self.connected {
+            return Err(Error::Connection("Socket not connected".to_string()));
         }
+        self.transport.ping().await
     }
     
     /// Close the socket connection
     pub async fn close(mut self) -> Result<()> {
-        if let Some(mut stream) = self.ws_stream.take() {
-            info!("Closing WebSocket connection");
-            stream.close(None).await?;
+        if self.connected {
+            self.transport.close().await?;
         }
         self.connected = false;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::ChannelTransport;
+    
+    #[tokio::test]
+    async fn test_custom_transport() {
+        let (transport, mut server_rx, server_tx) = ChannelTransport::pair();
+        let mut socket = NoiseSocket::with_transport(Box::new(transport));
+        assert!(socket.send(vec![1]).await.is_err());
+        
+        socket.connect_with_url("ws://localhost/ws").await.unwrap();
+        assert!(socket.is_connected());
+        
+        // Before the handshake frames pass through unencrypted
+        socket.send(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(server_rx.recv().await, Some(vec![1, 2, 3]));
+        server_tx.send(vec![4, 5]).unwrap();
+        assert_eq!(socket.receive().await.unwrap(), Some(vec![4, 5]));
+        
+        drop(server_tx);
+        assert!(matches!(socket.receive().await, Err(Error::Disconnected(_))));
+        assert!(!socket.is_connected());
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
     error::{Error, Result},
-    util::crypto::{AesGcm, hkdf_expand, sha256},
+    proto::wa_wa6::{handshake_message::ClientFinish, HandshakeMessage},
+    util::{crypto::{AesGcm, hkdf_expand, sha256}, keys::ECKeyPair},
 };
+use prost::Message;
 use std::sync::atomic::{AtomicU32, Ordering};
 
 /// Noise protocol handshake implementation for WhatsApp
@@ -11,6 +13,8 @@ pub struct NoiseHandshake {
     key: Option<AesGcm>,
     counter: AtomicU32,
     completed: bool,
+    /// Ephemeral key of the server, known after its response
+    server_ephemeral: Option<[u8; 32]>,
 }
 
 impl NoiseHandshake {
@@ -22,6 +26,7 @@ impl NoiseHandshake {
             key: None,
             counter: AtomicU32::new(0),
             completed: false,
+            server_ephemeral: None,
         }
     }
     
@@ -158,6 +163,7 @@ impl NoiseHandshake {
         
         // Mix the server's public key into our handshake state
         self.authenticate(server_public_key);
+        self.server_ephemeral = server_public_key.try_into().ok();
         
         // If there's payload, decrypt it
         if !payload.is_empty() {
@@ -168,25 +174,38 @@ impl NoiseHandshake {
         Ok(())
     }
     
-    /// Create client finish message
+    /// Create client finish message with a fresh static key and no payload
     pub fn create_client_finish(&mut self) -> Result<Vec<u8>> {
-        use crate::util::keys::ECKeyPair;
-        
-        // Generate static key pair for authentication
-        let static_keypair = ECKeyPair::generate();
-        
-        // Create finish message with static public key
-        let mut message = Vec::new();
-        message.extend_from_slice(&static_keypair.public_bytes());
-        
-        // Encrypt the message
-        let encrypted = self.encrypt(&message)?;
+        self.create_client_finish_with(&ECKeyPair::generate(), &[])
+    }
+    
+    /// Create client finish message authenticating with a static key.
+    ///
+    /// Paired devices use their stored noise key and a login payload here,
+    /// the server then recognizes the device instead of starting pairing.
+    pub fn create_client_finish_with(&mut self, static_keypair: &ECKeyPair, payload: &[u8]) -> Result<Vec<u8>> {
+        let encrypted_static = self.encrypt(&static_keypair.public_bytes())?;
+        
+        // Bind the payload to our static key
+        if let Some(server_ephemeral) = self.server_ephemeral {
+            self.mix_shared_secret_into_key(&static_keypair.private_bytes(), &server_ephemeral)?;
+        }
+        let encrypted_payload = self.encrypt(payload)?;
+        
+        let message = HandshakeMessage {
+            client_finish: Some(ClientFinish {
+                r#static: Some(encrypted_static),
+                payload: Some(encrypted_payload),
+            }),
+            ..Default::default()
+        }
+        .encode_to_vec();
         
         // Mark handshake as completed
         self.completed = true;
         
-        tracing::debug!("Created client finish message of {} bytes", encrypted.len());
-        Ok(encrypted)
+        tracing::debug!("Created client finish message of {} bytes", message.len());
+        Ok(message)
     }
 }
 
@@ -194,4 +213,23 @@ impl Default for NoiseHandshake {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_client_finish_message() {
+        let mut handshake = NoiseHandshake::new();
+        handshake.create_client_init().unwrap();
+        handshake.process_server_response(&ECKeyPair::generate().public_bytes()).unwrap();
+        
+        let message = handshake.create_client_finish_with(&ECKeyPair::generate(), b"payload").unwrap();
+        assert!(handshake.is_completed());
+        
+        let finish = HandshakeMessage::decode(message.as_slice()).unwrap().client_finish.unwrap();
+        // Both parts carry a 16 byte GCM tag
+        assert_eq!(finish.r#static.unwrap().len(), 32 + 16);
+        assert_eq!(finish.payload.unwrap().len(), b"payload".len() + 16);
+    }
+}
@@ -0,0 +1,189 @@
+/// Raw frame transport underneath the noise socket
+///
+/// [`NoiseSocket`](super::NoiseSocket) only needs something that can open a
+/// connection to a URL and exchange binary frames. The default
+/// [`WebSocketTransport`] uses tokio-tungstenite, optionally through a
+/// proxy. Custom implementations can dial through Tor, randomize the TLS
+/// fingerprint or connect to an in-process server in tests.
+
+use super::ProxyConfig;
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{client_async_tls, connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Creates a fresh transport for every connection attempt
+pub type TransportFactory = std::sync::Arc<dyn Fn() -> Box<dyn Transport> + Send + Sync>;
+
+/// A connection exchanging binary frames with the server
+#[async_trait]
+pub trait Transport: Send {
+    /// Open the connection to the given URL
+    async fn connect(&mut self, url: &Url) -> Result<()>;
+
+    /// Send one binary frame
+    async fn send(&mut self, data: Vec<u8>) -> Result<()>;
+
+    /// Receive the next frame.
+    ///
+    /// Returns `Ok(None)` for frames without payload (e.g. pings) and
+    /// [`Error::Disconnected`] once the connection is closed.
+    async fn receive(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// Close the connection
+    async fn close(&mut self) -> Result<()>;
+
+    /// Send a transport-level ping, if the transport has such a thing
+    async fn ping(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Default transport: a WebSocket over TLS, optionally tunneled through a proxy
+#[derive(Default)]
+pub struct WebSocketTransport {
+    stream: Option<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>,
+    proxy: Option<ProxyConfig>,
+}
+
+impl WebSocketTransport {
+    /// Create a transport connecting directly
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route the connection through the given proxy
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    fn stream(&mut self) -> Result<&mut WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| Error::Connection("Socket not connected".to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    async fn connect(&mut self, url: &Url) -> Result<()> {
+        debug!("Establishing WebSocket connection to: {}", url.as_str());
+        let (stream, response) = match &self.proxy {
+            Some(proxy) => {
+                let host = url.host_str()
+                    .ok_or_else(|| Error::Connection(format!("WebSocket URL has no host: {}", url)))?;
+                let port = url.port_or_known_default().unwrap_or(443);
+                debug!("Tunneling WebSocket through {:?} proxy {}:{}", proxy.kind, proxy.host, proxy.port);
+                let tcp = proxy.connect(host, port).await?;
+                client_async_tls(url.as_str(), tcp).await?
+            }
+            None => connect_async(url.as_str()).await?,
+        };
+
+        info!("WebSocket connected, status: {}", response.status());
+        debug!("Response headers: {:?}", response.headers());
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<()> {
+        self.stream()?.send(Message::Binary(data)).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<Vec<u8>>> {
+        let stream = self.stream()?;
+        match stream.next().await {
+            Some(msg) => match msg? {
+                Message::Binary(data) => {
+                    debug!("Received binary message of {} bytes", data.len());
+                    Ok(Some(data))
+                }
+                Message::Text(text) => {
+                    debug!("Received text message: {}", text);
+                    Ok(Some(text.into_bytes()))
+                }
+                Message::Close(frame) => {
+                    warn!("WebSocket connection closed: {:?}", frame);
+                    self.stream = None;
+                    Err(Error::Disconnected("Connection closed by remote".to_string()))
+                }
+                Message::Ping(data) => {
+                    debug!("Received ping, sending pong");
+                    stream.send(Message::Pong(data)).await?;
+                    Ok(None)
+                }
+                Message::Pong(_) => {
+                    debug!("Received pong");
+                    Ok(None)
+                }
+                _ => Ok(None),
+            },
+            None => {
+                debug!("WebSocket stream ended");
+                self.stream = None;
+                Err(Error::Disconnected("WebSocket stream ended".to_string()))
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            info!("Closing WebSocket connection");
+            stream.close(None).await?;
+        }
+        Ok(())
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.stream()?.send(Message::Ping(vec![])).await?;
+        Ok(())
+    }
+}
+
+/// In-process transport exchanging frames over channels, for tests
+#[cfg(test)]
+pub(crate) struct ChannelTransport {
+    pub outgoing: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+    pub incoming: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl ChannelTransport {
+    /// Create a transport and the server ends of its channels
+    pub fn pair() -> (Self, tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>, tokio::sync::mpsc::UnboundedSender<Vec<u8>>) {
+        let (outgoing, server_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (server_tx, incoming) = tokio::sync::mpsc::unbounded_channel();
+        (Self { outgoing, incoming }, server_rx, server_tx)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Transport for ChannelTransport {
+    async fn connect(&mut self, _url: &Url) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<()> {
+        self.outgoing
+            .send(data)
+            .map_err(|_| Error::Disconnected("Channel closed".to_string()))
+    }
+
+    async fn receive(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.incoming.recv().await {
+            Some(data) => Ok(Some(data)),
+            None => Err(Error::Disconnected("Channel closed".to_string())),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.incoming.close();
+        Ok(())
+    }
+}
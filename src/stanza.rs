@@ -0,0 +1,195 @@
+/// Stanza handler registry for protocol extensions
+///
+/// Lets downstream users hook into stanzas that the crate does not (yet) handle
+/// itself, without having to fork the receive loop. Handlers are matched on the
+/// node tag and, optionally, on its `xmlns` attribute.
+
+use crate::{binary::Node, error::Result};
+use async_trait::async_trait;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+/// Handler for raw protocol stanzas
+#[async_trait]
+pub trait StanzaHandler: Send + Sync {
+    /// Handle a stanza matched by the registry
+    async fn handle_stanza(&self, node: &Node) -> Result<()>;
+}
+
+/// Identifier returned when registering a handler, used to unregister it
+pub type StanzaHandlerId = u64;
+
+/// Matching rules for a registered handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StanzaMatcher {
+    /// Node tag to match (e.g. `notification`, `iq`, `ib`)
+    pub tag: String,
+    /// Optional `xmlns` attribute to match
+    pub xmlns: Option<String>,
+}
+
+impl StanzaMatcher {
+    /// Match every stanza with the given tag
+    pub fn tag(tag: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            xmlns: None,
+        }
+    }
+
+    /// Match stanzas with the given tag and namespace
+    pub fn with_xmlns(tag: &str, xmlns: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            xmlns: Some(xmlns.to_string()),
+        }
+    }
+
+    /// Check if the matcher applies to a node
+    pub fn matches(&self, node: &Node) -> bool {
+        if node.tag != self.tag {
+            return false;
+        }
+
+        match &self.xmlns {
+            Some(xmlns) => node.get_attr("xmlns") == Some(xmlns),
+            None => true,
+        }
+    }
+}
+
+struct RegisteredHandler {
+    id: StanzaHandlerId,
+    matcher: StanzaMatcher,
+    handler: Arc<dyn StanzaHandler>,
+}
+
+/// Registry of custom stanza handlers
+pub struct StanzaHandlerRegistry {
+    handlers: RwLock<Vec<RegisteredHandler>>,
+    next_id: AtomicU64,
+}
+
+impl StanzaHandlerRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            handlers: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Register a handler for stanzas matching the given rules
+    pub async fn register(&self, matcher: StanzaMatcher, handler: Arc<dyn StanzaHandler>) -> StanzaHandlerId {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        debug!("Registering stanza handler {} for {:?}", id, matcher);
+
+        let mut handlers = self.handlers.write().await;
+        handlers.push(RegisteredHandler { id, matcher, handler });
+        id
+    }
+
+    /// Remove a previously registered handler
+    pub async fn unregister(&self, id: StanzaHandlerId) -> bool {
+        let mut handlers = self.handlers.write().await;
+        let before = handlers.len();
+        handlers.retain(|h| h.id != id);
+        handlers.len() != before
+    }
+
+    /// Number of registered handlers
+    pub async fn len(&self) -> usize {
+        self.handlers.read().await.len()
+    }
+
+    /// Check if no handlers are registered
+    pub async fn is_empty(&self) -> bool {
+        self.handlers.read().await.is_empty()
+    }
+
+    /// Dispatch a node to every matching handler.
+    ///
+    /// Returns `true` if at least one handler matched. Handler errors are logged
+    /// and do not prevent the remaining handlers from running.
+    pub async fn dispatch(&self, node: &Node) -> bool {
+        let matching: Vec<(StanzaHandlerId, Arc<dyn StanzaHandler>)> = {
+            let handlers = self.handlers.read().await;
+            handlers
+                .iter()
+                .filter(|h| h.matcher.matches(node))
+                .map(|h| (h.id, Arc::clone(&h.handler)))
+                .collect()
+        };
+
+        for (id, handler) in &matching {
+            if let Err(e) = handler.handle_stanza(node).await {
+                warn!("Stanza handler {} failed for <{}>: {}", id, node.tag, e);
+            }
+        }
+
+        !matching.is_empty()
+    }
+}
+
+impl Default for StanzaHandlerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    struct CountingHandler {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StanzaHandler for CountingHandler {
+        async fn handle_stanza(&self, _node: &Node) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn iq_node(xmlns: &str) -> Node {
+        Node::new("iq".to_string()).attr("xmlns".to_string(), xmlns.to_string())
+    }
+
+    #[test]
+    fn test_matcher() {
+        let by_tag = StanzaMatcher::tag("iq");
+        assert!(by_tag.matches(&iq_node("w:g2")));
+        assert!(!by_tag.matches(&Node::new("message".to_string())));
+
+        let by_ns = StanzaMatcher::with_xmlns("iq", "w:g2");
+        assert!(by_ns.matches(&iq_node("w:g2")));
+        assert!(!by_ns.matches(&iq_node("usync")));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_and_unregister() {
+        let registry = StanzaHandlerRegistry::new();
+        let handler = Arc::new(CountingHandler { calls: AtomicUsize::new(0) });
+
+        let id = registry
+            .register(StanzaMatcher::with_xmlns("iq", "w:g2"), handler.clone())
+            .await;
+        assert_eq!(registry.len().await, 1);
+
+        assert!(registry.dispatch(&iq_node("w:g2")).await);
+        assert!(!registry.dispatch(&iq_node("usync")).await);
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+
+        assert!(registry.unregister(id).await);
+        assert!(!registry.unregister(id).await);
+        assert!(!registry.dispatch(&iq_node("w:g2")).await);
+        assert!(registry.is_empty().await);
+    }
+}
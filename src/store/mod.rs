@@ -1,5 +1,29 @@
-use crate::{error::Result, types::JID};
+/// Persistent client state
+///
+/// [`DeviceStore`] holds the credentials of the paired device. The Signal
+/// and app state sub-stores are handed out per device by a [`Store`]
+/// backend, bundled as a [`Device`] like whatsmeow's `store.Device`, so a
+/// single database can hold the state of several accounts. The
+/// [`SignalProtocolManager`](crate::signal::SignalProtocolManager) works on
+/// a copy of a device's Signal state loaded from these stores and writes
+/// its changes back, see [`SignalProtocolManager::load`](crate::signal::SignalProtocolManager::load).
+///
+/// Remote Signal addresses are written `user:device`, as returned by
+/// [`JID::signal_address`].
+///
+/// Backends that only implement [`DeviceStore`] still work through
+/// [`DeviceStoreAdapter`], which keeps the Signal and app state in memory
+/// like before the [`Store`] split. The client constructors take anything
+/// [`IntoStore`], so an `Arc<dyn DeviceStore>` is wrapped automatically.
+
+pub mod whatsmeow;
+
+use crate::{error::Result, types::{AppStateSyncKey, JID}};
 use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub use whatsmeow::{migrate_database_from_whatsmeow, migrate_from_whatsmeow, WhatsmeowImport};
 
 /// Device store trait for persisting device information
 #[async_trait]
@@ -22,24 +46,253 @@ pub trait DeviceStore: Send + Sync {
 pub struct DeviceData {
     pub jid: JID,
     pub registration_id: u32,
+    /// Private noise key
     pub noise_key: Vec<u8>,
+    /// Private Signal identity key
     pub identity_key: Vec<u8>,
+    /// Current signed pre-key, see [`record::decode_signed_pre_key`](crate::signal::record::decode_signed_pre_key)
     pub signed_pre_key: Vec<u8>,
     pub signed_pre_key_id: u32,
     pub signed_pre_key_signature: Vec<u8>,
 }
 
+/// Identity keys of remote Signal addresses
+#[async_trait]
+pub trait IdentityStore: Send + Sync {
+    /// Store the identity key of an address
+    async fn put_identity(&self, address: &str, key: &[u8]) -> Result<()>;
+    
+    /// Get the identity key of an address
+    async fn get_identity(&self, address: &str) -> Result<Option<Vec<u8>>>;
+    
+    /// Forget the identity key of an address
+    async fn delete_identity(&self, address: &str) -> Result<()>;
+    
+    /// Every stored identity key with its address
+    async fn all_identities(&self) -> Result<Vec<(String, Vec<u8>)>>;
+}
+
+/// Serialized Signal sessions with remote addresses
+#[async_trait]
+pub trait SessionRecordStore: Send + Sync {
+    /// Get the session with an address
+    async fn get_session(&self, address: &str) -> Result<Option<Vec<u8>>>;
+    
+    /// Check if there is a session with an address
+    async fn has_session(&self, address: &str) -> Result<bool> {
+        Ok(self.get_session(address).await?.is_some())
+    }
+    
+    /// Store the session with an address
+    async fn put_session(&self, address: &str, session: &[u8]) -> Result<()>;
+    
+    /// Delete the session with an address
+    async fn delete_session(&self, address: &str) -> Result<()>;
+    
+    /// Delete the sessions with every device of a user
+    async fn delete_all_sessions(&self, user: &str) -> Result<()>;
+    
+    /// Every stored session with its address
+    async fn all_sessions(&self) -> Result<Vec<(String, Vec<u8>)>>;
+}
+
+/// Serialized one-time pre-keys of our device
+#[async_trait]
+pub trait PreKeyRecordStore: Send + Sync {
+    /// Store a serialized pre-key
+    async fn put_pre_key(&self, key_id: u32, key: &[u8]) -> Result<()>;
+    
+    /// Get a pre-key by ID
+    async fn get_pre_key(&self, key_id: u32) -> Result<Option<Vec<u8>>>;
+    
+    /// Remove a pre-key once a session used it
+    async fn remove_pre_key(&self, key_id: u32) -> Result<()>;
+    
+    /// Mark every pre-key up to and including an ID as uploaded
+    async fn mark_pre_keys_uploaded(&self, up_to_id: u32) -> Result<()>;
+    
+    /// Number of pre-keys the server has
+    async fn uploaded_pre_key_count(&self) -> Result<usize>;
+    
+    /// Every stored pre-key as `(id, key, uploaded)`
+    async fn all_pre_keys(&self) -> Result<Vec<(u32, Vec<u8>, bool)>>;
+}
+
+/// Sender keys of group chats
+#[async_trait]
+pub trait SenderKeyStore: Send + Sync {
+    /// Store the sender key of a sender in a group
+    async fn put_sender_key(&self, group: &str, sender: &str, key: &[u8]) -> Result<()>;
+    
+    /// Get the sender key of a sender in a group
+    async fn get_sender_key(&self, group: &str, sender: &str) -> Result<Option<Vec<u8>>>;
+    
+    /// Every stored sender key as `(group, sender, key)`
+    async fn all_sender_keys(&self) -> Result<Vec<(String, String, Vec<u8>)>>;
+}
+
+/// App state sync keys shared by the primary device
+#[async_trait]
+pub trait AppStateKeyStore: Send + Sync {
+    /// Store a key, replacing an older copy with the same ID
+    async fn put_app_state_key(&self, key: &AppStateSyncKey) -> Result<()>;
+    
+    /// Get a key by ID
+    async fn get_app_state_key(&self, key_id: &[u8]) -> Result<Option<AppStateSyncKey>>;
+    
+    /// Get the most recently created key
+    async fn get_latest_app_state_key(&self) -> Result<Option<AppStateSyncKey>>;
+}
+
+/// Storage backend handing out the sub-stores of each device
+pub trait Store: DeviceStore {
+    /// Identity keys seen by a device
+    fn identities(&self, device: &JID) -> Arc<dyn IdentityStore>;
+    
+    /// Signal sessions of a device
+    fn sessions(&self, device: &JID) -> Arc<dyn SessionRecordStore>;
+    
+    /// Pre-keys of a device
+    fn pre_keys(&self, device: &JID) -> Arc<dyn PreKeyRecordStore>;
+    
+    /// Group sender keys of a device
+    fn sender_keys(&self, device: &JID) -> Arc<dyn SenderKeyStore>;
+    
+    /// App state sync keys of a device
+    fn app_state_keys(&self, device: &JID) -> Arc<dyn AppStateKeyStore>;
+    
+    /// All sub-stores of a device
+    fn device(&self, jid: &JID) -> Device {
+        Device {
+            jid: jid.clone(),
+            identities: self.identities(jid),
+            sessions: self.sessions(jid),
+            pre_keys: self.pre_keys(jid),
+            sender_keys: self.sender_keys(jid),
+            app_state_keys: self.app_state_keys(jid),
+        }
+    }
+}
+
+/// Typed sub-stores of one device
+#[derive(Clone)]
+pub struct Device {
+    pub jid: JID,
+    pub identities: Arc<dyn IdentityStore>,
+    pub sessions: Arc<dyn SessionRecordStore>,
+    pub pre_keys: Arc<dyn PreKeyRecordStore>,
+    pub sender_keys: Arc<dyn SenderKeyStore>,
+    pub app_state_keys: Arc<dyn AppStateKeyStore>,
+}
+
+/// Conversion into the [`Store`] a client runs on
+pub trait IntoStore {
+    fn into_store(self) -> Arc<dyn Store>;
+}
+
+impl IntoStore for Arc<dyn Store> {
+    fn into_store(self) -> Arc<dyn Store> {
+        self
+    }
+}
+
+impl<T: Store + 'static> IntoStore for Arc<T> {
+    fn into_store(self) -> Arc<dyn Store> {
+        self
+    }
+}
+
+impl IntoStore for Arc<dyn DeviceStore> {
+    fn into_store(self) -> Arc<dyn Store> {
+        Arc::new(DeviceStoreAdapter::new(self))
+    }
+}
+
+/// [`Store`] over a backend that only persists the device credentials
+///
+/// The per-device sub-stores live in memory and are lost on restart, so
+/// sessions have to be re-established after every run. Implement [`Store`]
+/// on the backend to keep them.
+pub struct DeviceStoreAdapter {
+    device: Arc<dyn DeviceStore>,
+    memory: MemoryStore,
+}
+
+impl DeviceStoreAdapter {
+    pub fn new(device: Arc<dyn DeviceStore>) -> Self {
+        Self { device, memory: MemoryStore::new() }
+    }
+}
+
+#[async_trait]
+impl DeviceStore for DeviceStoreAdapter {
+    async fn save_device(&self, data: &DeviceData) -> Result<()> {
+        self.device.save_device(data).await
+    }
+    
+    async fn load_device(&self) -> Result<Option<DeviceData>> {
+        self.device.load_device().await
+    }
+    
+    async fn delete_device(&self) -> Result<()> {
+        self.device.delete_device().await
+    }
+    
+    async fn is_registered(&self) -> Result<bool> {
+        self.device.is_registered().await
+    }
+}
+
+impl Store for DeviceStoreAdapter {
+    fn identities(&self, device: &JID) -> Arc<dyn IdentityStore> {
+        self.memory.identities(device)
+    }
+    
+    fn sessions(&self, device: &JID) -> Arc<dyn SessionRecordStore> {
+        self.memory.sessions(device)
+    }
+    
+    fn pre_keys(&self, device: &JID) -> Arc<dyn PreKeyRecordStore> {
+        self.memory.pre_keys(device)
+    }
+    
+    fn sender_keys(&self, device: &JID) -> Arc<dyn SenderKeyStore> {
+        self.memory.sender_keys(device)
+    }
+    
+    fn app_state_keys(&self, device: &JID) -> Arc<dyn AppStateKeyStore> {
+        self.memory.app_state_keys(device)
+    }
+}
+
+/// User part of a `user:device` Signal address
+pub fn address_user(address: &str) -> &str {
+    address.split_once(':').map_or(address, |(user, _)| user)
+}
+
 /// In-memory device store implementation
 pub struct MemoryStore {
     device_data: tokio::sync::RwLock<Option<DeviceData>>,
+    devices: Mutex<HashMap<String, Arc<MemoryDeviceStore>>>,
 }
 
 impl MemoryStore {
     pub fn new() -> Self {
         Self {
             device_data: tokio::sync::RwLock::new(None),
+            devices: Mutex::new(HashMap::new()),
         }
     }
+    
+    fn device_store(&self, device: &JID) -> Arc<MemoryDeviceStore> {
+        self.devices.lock().unwrap().entry(device.to_string()).or_default().clone()
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[async_trait]
@@ -65,4 +318,191 @@ impl DeviceStore for MemoryStore {
         let device_data = self.device_data.read().await;
         Ok(device_data.is_some())
     }
-}
\ No newline at end of file
+}
+
+impl Store for MemoryStore {
+    fn identities(&self, device: &JID) -> Arc<dyn IdentityStore> {
+        self.device_store(device)
+    }
+    
+    fn sessions(&self, device: &JID) -> Arc<dyn SessionRecordStore> {
+        self.device_store(device)
+    }
+    
+    fn pre_keys(&self, device: &JID) -> Arc<dyn PreKeyRecordStore> {
+        self.device_store(device)
+    }
+    
+    fn sender_keys(&self, device: &JID) -> Arc<dyn SenderKeyStore> {
+        self.device_store(device)
+    }
+    
+    fn app_state_keys(&self, device: &JID) -> Arc<dyn AppStateKeyStore> {
+        self.device_store(device)
+    }
+}
+
+/// In-memory sub-stores of one device
+#[derive(Default)]
+pub struct MemoryDeviceStore {
+    identities: tokio::sync::RwLock<HashMap<String, Vec<u8>>>,
+    sessions: tokio::sync::RwLock<HashMap<String, Vec<u8>>>,
+    pre_keys: tokio::sync::RwLock<HashMap<u32, (Vec<u8>, bool)>>,
+    sender_keys: tokio::sync::RwLock<HashMap<(String, String), Vec<u8>>>,
+    app_state_keys: tokio::sync::RwLock<Vec<AppStateSyncKey>>,
+}
+
+#[async_trait]
+impl IdentityStore for MemoryDeviceStore {
+    async fn put_identity(&self, address: &str, key: &[u8]) -> Result<()> {
+        self.identities.write().await.insert(address.to_string(), key.to_vec());
+        Ok(())
+    }
+    
+    async fn get_identity(&self, address: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.identities.read().await.get(address).cloned())
+    }
+    
+    async fn delete_identity(&self, address: &str) -> Result<()> {
+        self.identities.write().await.remove(address);
+        Ok(())
+    }
+    
+    async fn all_identities(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        Ok(self.identities.read().await.iter().map(|(address, key)| (address.clone(), key.clone())).collect())
+    }
+}
+
+#[async_trait]
+impl SessionRecordStore for MemoryDeviceStore {
+    async fn get_session(&self, address: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.sessions.read().await.get(address).cloned())
+    }
+    
+    async fn put_session(&self, address: &str, session: &[u8]) -> Result<()> {
+        self.sessions.write().await.insert(address.to_string(), session.to_vec());
+        Ok(())
+    }
+    
+    async fn delete_session(&self, address: &str) -> Result<()> {
+        self.sessions.write().await.remove(address);
+        Ok(())
+    }
+    
+    async fn delete_all_sessions(&self, user: &str) -> Result<()> {
+        self.sessions.write().await.retain(|address, _| address_user(address) != user);
+        Ok(())
+    }
+    
+    async fn all_sessions(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        Ok(self.sessions.read().await.iter().map(|(address, session)| (address.clone(), session.clone())).collect())
+    }
+}
+
+#[async_trait]
+impl PreKeyRecordStore for MemoryDeviceStore {
+    async fn put_pre_key(&self, key_id: u32, key: &[u8]) -> Result<()> {
+        self.pre_keys.write().await.insert(key_id, (key.to_vec(), false));
+        Ok(())
+    }
+    
+    async fn get_pre_key(&self, key_id: u32) -> Result<Option<Vec<u8>>> {
+        Ok(self.pre_keys.read().await.get(&key_id).map(|(key, _)| key.clone()))
+    }
+    
+    async fn remove_pre_key(&self, key_id: u32) -> Result<()> {
+        self.pre_keys.write().await.remove(&key_id);
+        Ok(())
+    }
+    
+    async fn mark_pre_keys_uploaded(&self, up_to_id: u32) -> Result<()> {
+        for (id, (_, uploaded)) in self.pre_keys.write().await.iter_mut() {
+            if *id <= up_to_id {
+                *uploaded = true;
+            }
+        }
+        Ok(())
+    }
+    
+    async fn uploaded_pre_key_count(&self) -> Result<usize> {
+        Ok(self.pre_keys.read().await.values().filter(|(_, uploaded)| *uploaded).count())
+    }
+    
+    async fn all_pre_keys(&self) -> Result<Vec<(u32, Vec<u8>, bool)>> {
+        Ok(self.pre_keys.read().await.iter().map(|(id, (key, uploaded))| (*id, key.clone(), *uploaded)).collect())
+    }
+}
+
+#[async_trait]
+impl SenderKeyStore for MemoryDeviceStore {
+    async fn put_sender_key(&self, group: &str, sender: &str, key: &[u8]) -> Result<()> {
+        self.sender_keys.write().await.insert((group.to_string(), sender.to_string()), key.to_vec());
+        Ok(())
+    }
+    
+    async fn get_sender_key(&self, group: &str, sender: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.sender_keys.read().await.get(&(group.to_string(), sender.to_string())).cloned())
+    }
+    
+    async fn all_sender_keys(&self) -> Result<Vec<(String, String, Vec<u8>)>> {
+        Ok(self.sender_keys.read().await.iter()
+            .map(|((group, sender), key)| (group.clone(), sender.clone(), key.clone()))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AppStateKeyStore for MemoryDeviceStore {
+    async fn put_app_state_key(&self, key: &AppStateSyncKey) -> Result<()> {
+        let mut keys = self.app_state_keys.write().await;
+        keys.retain(|existing| existing.key_id != key.key_id);
+        keys.push(key.clone());
+        Ok(())
+    }
+    
+    async fn get_app_state_key(&self, key_id: &[u8]) -> Result<Option<AppStateSyncKey>> {
+        Ok(self.app_state_keys.read().await.iter().find(|key| key.key_id == key_id).cloned())
+    }
+    
+    async fn get_latest_app_state_key(&self) -> Result<Option<AppStateSyncKey>> {
+        let keys = self.app_state_keys.read().await;
+        Ok(keys.iter()
+            .max_by(|a, b| (a.key_data.timestamp, &a.key_id).cmp(&(b.key_data.timestamp, &b.key_id)))
+            .cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn test_memory_store_devices() {
+        let store = MemoryStore::new();
+        let first = store.device(&JID::new_user("111").with_device(1));
+        let second = store.device(&JID::new_user("222").with_device(1));
+        
+        first.sessions.put_session("333:0", b"session").await.unwrap();
+        first.sessions.put_session("333:2", b"session").await.unwrap();
+        first.sessions.put_session("444:0", b"session").await.unwrap();
+        assert!(first.sessions.has_session("333:2").await.unwrap());
+        assert!(!second.sessions.has_session("333:2").await.unwrap());
+        
+        first.sessions.delete_all_sessions("333").await.unwrap();
+        assert!(!first.sessions.has_session("333:0").await.unwrap());
+        assert!(first.sessions.has_session("444:0").await.unwrap());
+        
+        // Handing out the same device again shares its state
+        let again = store.device(&first.jid);
+        assert!(again.sessions.has_session("444:0").await.unwrap());
+        
+        for id in 1..=3 {
+            first.pre_keys.put_pre_key(id, &[id as u8]).await.unwrap();
+        }
+        first.pre_keys.mark_pre_keys_uploaded(2).await.unwrap();
+        assert_eq!(first.pre_keys.uploaded_pre_key_count().await.unwrap(), 2);
+        first.pre_keys.remove_pre_key(1).await.unwrap();
+        assert_eq!(first.pre_keys.get_pre_key(1).await.unwrap(), None);
+        assert_eq!(first.pre_keys.get_pre_key(3).await.unwrap(), Some(vec![3]));
+    }
+}
@@ -0,0 +1,741 @@
+/// Import of databases written by the Go whatsmeow library
+///
+/// whatsmeow's `sqlstore` keeps every account in `whatsmeow_*` tables keyed
+/// by the device JID, the same layout as the per-device [`Store`]. Copying
+/// the credentials and Signal state over is meant to let a migrated account
+/// log in without pairing again.
+///
+/// The account data around the Signal state (app state versions and their
+/// mutation MACs, contacts, LID mappings, the push name and the ADV signed
+/// device identity) has no place in a [`Store`], only in a [`Database`].
+/// [`migrate_database_from_whatsmeow`] imports all of it; the plain
+/// [`Store`] import refuses databases that hold any, rather than dropping
+/// it silently.
+///
+/// whatsmeow stores sessions and sender keys as libsignal-protocol-go
+/// protobufs and pre-keys as bare private keys. They are converted to this
+/// crate's [`record`](crate::signal::record) envelopes on import; Signal
+/// addresses keep the `user:device` format both sides use. Rows that cannot
+/// be converted are skipped with a warning.
+
+use super::{DeviceData, Store};
+use crate::{
+    appstate::{HashState, PatchName},
+    database::{
+        sqlite::{SqliteAppStateVersionStore, SqliteContactStore, SqliteDeviceStore, SqliteLidStore, SqliteSettingsStore},
+        Database,
+    },
+    error::{Error, Result},
+    proto::wa_adv::AdvSignedDeviceIdentity,
+    signal::{
+        record::{self, RecordKind},
+        ChainState, IdentityKey, IdentityKeyRecord, PendingPreKey, PreKey, SenderKey, SenderKeyState,
+        SessionState, SignedPreKey, TrustLevel, DJB_TYPE,
+    },
+    types::{AppStateSyncKey, AppStateSyncKeyData, JID},
+    util::keys::ECKeyPair,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use prost::Message;
+use sqlx::{sqlite::SqliteConnectOptions, Row, SqlitePool};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+/// Number of rows imported from each whatsmeow table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhatsmeowImport {
+    pub devices: usize,
+    pub identities: usize,
+    pub sessions: usize,
+    pub pre_keys: usize,
+    pub sender_keys: usize,
+    pub app_state_keys: usize,
+    pub app_state_versions: usize,
+    pub contacts: usize,
+    pub lid_mappings: usize,
+}
+
+/// Setting holding the base64 `ADVSignedDeviceIdentity` of an imported account
+pub const ADV_IDENTITY_SETTING: &str = "adv_signed_device_identity";
+
+/// Import the Signal state of a whatsmeow SQLite database into `target`
+///
+/// `target` should be the store later given to the [`Client`](crate::Client).
+/// The source file is opened read-only. Rows already present in `target`
+/// are replaced, so the import can be repeated. Fails if the database holds
+/// account data a [`Store`] cannot keep, see [`migrate_database_from_whatsmeow`].
+pub async fn migrate_from_whatsmeow(path: impl AsRef<Path>, target: &dyn Store) -> Result<WhatsmeowImport> {
+    let source = open(path.as_ref()).await?;
+    let result = import(&source, target).await;
+    source.close().await;
+    result
+}
+
+/// Import every account of a whatsmeow SQLite database into `database`
+///
+/// Besides the Signal state this copies the app state versions, contacts,
+/// LID mappings, push names and ADV signed device identities.
+pub async fn migrate_database_from_whatsmeow(path: impl AsRef<Path>, database: &Database) -> Result<WhatsmeowImport> {
+    let source = open(path.as_ref()).await?;
+    let result = import_database(&source, database).await;
+    source.close().await;
+    result
+}
+
+async fn open(path: &Path) -> Result<SqlitePool> {
+    SqlitePool::connect_with(SqliteConnectOptions::new().filename(path).read_only(true))
+        .await
+        .map_err(|e| Error::Database(format!("Failed to open whatsmeow database: {}", e)))
+}
+
+/// Copy the Signal state of `source` into a [`Store`]
+///
+/// Fails without writing anything if `source` also holds account data.
+pub async fn import(source: &SqlitePool, target: &dyn Store) -> Result<WhatsmeowImport> {
+    let account_data = account_data(source).await?;
+    if !account_data.is_empty() {
+        return Err(Error::Database(format!(
+            "whatsmeow database holds {} which a Store cannot keep, import it into a Database instead",
+            account_data.join(", ")
+        )));
+    }
+    import_signal(source, target).await
+}
+
+/// Copy the whatsmeow tables of `source` into a [`Database`]
+pub async fn import_database(source: &SqlitePool, database: &Database) -> Result<WhatsmeowImport> {
+    let pool = database.pool();
+    let mut imported = import_signal(source, &SqliteDeviceStore::new(pool.clone())).await?;
+    
+    let contacts = SqliteContactStore::new(pool.clone());
+    let lids = SqliteLidStore::new(pool.clone());
+    let versions = SqliteAppStateVersionStore::new(pool.clone());
+    let settings = SqliteSettingsStore::new(pool.clone());
+    
+    for row in fetch_all(source, "SELECT * FROM whatsmeow_device").await? {
+        let jid = JID::parse(row.get("jid"))?;
+        if let Some(lid) = optional_column::<String>(&row, "lid") {
+            lids.put_mapping(&JID::parse(&lid)?.to_non_ad(), &jid.to_non_ad()).await?;
+            imported.lid_mappings += 1;
+        }
+        if let Some(push_name) = optional_column::<String>(&row, "push_name") {
+            contacts.update_push_name(&jid, &push_name).await?;
+        }
+        if let Some(details) = optional_column::<Vec<u8>>(&row, "adv_details") {
+            let identity = AdvSignedDeviceIdentity {
+                details: Some(details),
+                account_signature_key: optional_column(&row, "adv_account_sig_key"),
+                account_signature: optional_column(&row, "adv_account_sig"),
+                device_signature: optional_column(&row, "adv_device_sig"),
+            };
+            settings.set_setting(ADV_IDENTITY_SETTING, &STANDARD.encode(identity.encode_to_vec())).await?;
+        }
+        
+        let our_jid = row.get::<String, _>("jid");
+        if has_table(source, "whatsmeow_app_state_version").await? {
+            for row in fetch_for(source, "SELECT name, version, hash FROM whatsmeow_app_state_version WHERE jid = ?", &our_jid).await? {
+                let name: String = row.get("name");
+                let Some(patch_name) = PatchName::ALL.into_iter().find(|patch| patch.as_str() == name) else {
+                    warn!("Skipping unknown whatsmeow app state collection {}", name);
+                    continue;
+                };
+                let macs = sqlx::query("SELECT index_mac, value_mac FROM whatsmeow_app_state_mutation_macs WHERE jid = ? AND name = ?")
+                    .bind(&our_jid)
+                    .bind(&name)
+                    .fetch_all(source)
+                    .await
+                    .map_err(|e| Error::Database(format!("Failed to read whatsmeow table: {}", e)))?;
+                versions.put(patch_name, &HashState {
+                    version: row.get::<i64, _>("version") as u64,
+                    hash: row.get("hash"),
+                    value_macs: macs.iter().map(|row| (row.get("index_mac"), row.get("value_mac"))).collect(),
+                }).await?;
+                imported.app_state_versions += 1;
+            }
+        }
+        if has_table(source, "whatsmeow_contacts").await? {
+            for row in fetch_for(source, "SELECT * FROM whatsmeow_contacts WHERE our_jid = ?", &our_jid).await? {
+                let their_jid = match JID::parse(row.get("their_jid")) {
+                    Ok(jid) => jid,
+                    Err(e) => {
+                        warn!("Skipping whatsmeow contact: {}", e);
+                        continue;
+                    }
+                };
+                let first_name = optional_column::<String>(&row, "first_name");
+                let full_name = optional_column::<String>(&row, "full_name");
+                if first_name.is_some() || full_name.is_some() {
+                    contacts.update_contact_names(&their_jid, first_name.as_deref(), full_name.as_deref()).await?;
+                }
+                if let Some(push_name) = optional_column::<String>(&row, "push_name") {
+                    contacts.update_push_name(&their_jid, &push_name).await?;
+                }
+                if let Some(business_name) = optional_column::<String>(&row, "business_name") {
+                    contacts.update_business_name(&their_jid, &business_name).await?;
+                }
+                imported.contacts += 1;
+            }
+        }
+    }
+    
+    if has_table(source, "whatsmeow_lid_map").await? {
+        for row in fetch_all(source, "SELECT lid, pn FROM whatsmeow_lid_map").await? {
+            let (lid, pn): (String, String) = (row.get("lid"), row.get("pn"));
+            // whatsmeow keeps bare users here
+            let lid = JID::new_lid(&lid);
+            let pn = JID::new_user(&pn);
+            match lids.put_mapping(&lid, &pn).await {
+                Ok(()) => imported.lid_mappings += 1,
+                Err(e) => warn!("Skipping whatsmeow LID mapping {} -> {}: {}", lid, pn, e),
+            }
+        }
+    }
+    
+    tracing::info!("Imported whatsmeow account data: {:?}", imported);
+    Ok(imported)
+}
+
+/// Names of the account data in `source` that [`import`] cannot keep
+async fn account_data(source: &SqlitePool) -> Result<Vec<&'static str>> {
+    let mut present = Vec::new();
+    for row in fetch_all(source, "SELECT * FROM whatsmeow_device").await? {
+        for (column, name) in [("lid", "a LID"), ("push_name", "a push name"), ("adv_details", "an ADV signed device identity")] {
+            let set = optional_column::<String>(&row, column).is_some()
+                || optional_column::<Vec<u8>>(&row, column).is_some();
+            if set && !present.contains(&name) {
+                present.push(name);
+            }
+        }
+    }
+    for (table, name) in [
+        ("whatsmeow_app_state_version", "app state versions"),
+        ("whatsmeow_app_state_mutation_macs", "app state mutation MACs"),
+        ("whatsmeow_contacts", "contacts"),
+        ("whatsmeow_lid_map", "LID mappings"),
+    ] {
+        if has_table(source, table).await? && !fetch_all(source, &format!("SELECT 1 FROM {} LIMIT 1", table)).await?.is_empty() {
+            present.push(name);
+        }
+    }
+    Ok(present)
+}
+
+async fn has_table(source: &SqlitePool, table: &str) -> Result<bool> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(table)
+        .fetch_one(source)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to read whatsmeow schema: {}", e)))?;
+    Ok(count > 0)
+}
+
+/// A column older whatsmeow schemas may lack, empty values count as missing
+fn optional_column<'r, T>(row: &'r sqlx::sqlite::SqliteRow, column: &str) -> Option<T>
+where
+    T: sqlx::Decode<'r, sqlx::Sqlite> + sqlx::Type<sqlx::Sqlite> + AsRef<[u8]>,
+{
+    row.try_get::<Option<T>, _>(column).ok().flatten().filter(|value| !value.as_ref().is_empty())
+}
+
+/// Copy the Signal state of every account
+async fn import_signal(source: &SqlitePool, target: &dyn Store) -> Result<WhatsmeowImport> {
+    let mut imported = WhatsmeowImport::default();
+    
+    let devices = sqlx::query(
+        "SELECT jid, registration_id, noise_key, identity_key, signed_pre_key, signed_pre_key_id, signed_pre_key_sig FROM whatsmeow_device"
+    )
+    .fetch_all(source)
+    .await
+    .map_err(|e| Error::Database(format!("Failed to read whatsmeow devices: {}", e)))?;
+    
+    for row in devices {
+        let jid = JID::parse(row.get("jid"))?;
+        let signed_pre_key = SignedPreKey {
+            id: row.get::<i64, _>("signed_pre_key_id") as u32,
+            keypair: ECKeyPair::from_private_bytes(row.get("signed_pre_key"))?,
+            signature: row.get("signed_pre_key_sig"),
+            timestamp: 0,
+        };
+        target.save_device(&DeviceData {
+            jid: jid.clone(),
+            registration_id: row.get::<i64, _>("registration_id") as u32,
+            noise_key: row.get("noise_key"),
+            identity_key: row.get("identity_key"),
+            signed_pre_key: record::encode_record(RecordKind::SignedPreKey, &signed_pre_key)?,
+            signed_pre_key_id: signed_pre_key.id,
+            signed_pre_key_signature: signed_pre_key.signature.clone(),
+        }).await?;
+        imported.devices += 1;
+        
+        let our_jid = row.get::<String, _>("jid");
+        let device = target.device(&jid);
+        
+        for row in fetch_for(source, "SELECT their_id, identity FROM whatsmeow_identity_keys WHERE our_jid = ?", &our_jid).await? {
+            let address: &str = row.get("their_id");
+            match public_key(row.get("identity")) {
+                Ok(key) => {
+                    let identity = IdentityKeyRecord::new(IdentityKey::new(key), TrustLevel::Untrusted);
+                    record::store_identity(device.identities.as_ref(), address, &identity).await?;
+                    imported.identities += 1;
+                }
+                Err(e) => warn!("Skipping whatsmeow identity of {}: {}", address, e),
+            }
+        }
+        
+        for row in fetch_for(source, "SELECT their_id, session FROM whatsmeow_sessions WHERE our_jid = ?", &our_jid).await? {
+            let address: &str = row.get("their_id");
+            match convert_session(row.get("session")) {
+                Ok(session) => {
+                    record::store_session(device.sessions.as_ref(), address, &session).await?;
+                    imported.sessions += 1;
+                }
+                Err(e) => warn!("Skipping whatsmeow session with {}: {}", address, e),
+            }
+        }
+        
+        let mut last_uploaded = None;
+        for row in fetch_for(source, "SELECT key_id, key, uploaded FROM whatsmeow_pre_keys WHERE jid = ? ORDER BY key_id", &our_jid).await? {
+            let key_id = row.get::<i64, _>("key_id") as u32;
+            match ECKeyPair::from_private_bytes(row.get("key")) {
+                Ok(keypair) => {
+                    record::store_pre_key(device.pre_keys.as_ref(), &PreKey { id: key_id, keypair }).await?;
+                    imported.pre_keys += 1;
+                }
+                Err(e) => {
+                    warn!("Skipping whatsmeow pre-key {}: {}", key_id, e);
+                    continue;
+                }
+            }
+            if row.get::<bool, _>("uploaded") {
+                last_uploaded = Some(key_id);
+            }
+        }
+        // Pre-keys are uploaded in ID order, so the uploaded ones are a prefix
+        if let Some(key_id) = last_uploaded {
+            device.pre_keys.mark_pre_keys_uploaded(key_id).await?;
+        }
+        
+        for row in fetch_for(source, "SELECT chat_id, sender_id, sender_key FROM whatsmeow_sender_keys WHERE our_jid = ?", &our_jid).await? {
+            let (group, sender): (&str, &str) = (row.get("chat_id"), row.get("sender_id"));
+            match convert_sender_key(row.get("sender_key")) {
+                Ok(state) => {
+                    record::store_sender_key(device.sender_keys.as_ref(), group, sender, &state).await?;
+                    imported.sender_keys += 1;
+                }
+                Err(e) => warn!("Skipping whatsmeow sender key of {} in {}: {}", sender, group, e),
+            }
+        }
+        
+        for row in fetch_for(source, "SELECT key_id, key_data, timestamp, fingerprint FROM whatsmeow_app_state_sync_keys WHERE jid = ?", &our_jid).await? {
+            device.app_state_keys.put_app_state_key(&AppStateSyncKey {
+                key_id: row.get("key_id"),
+                key_data: AppStateSyncKeyData {
+                    key_data: row.get("key_data"),
+                    fingerprint: row.get("fingerprint"),
+                    timestamp: std::time::UNIX_EPOCH
+                        + std::time::Duration::from_millis(row.get::<i64, _>("timestamp").max(0) as u64),
+                },
+            }).await?;
+            imported.app_state_keys += 1;
+        }
+    }
+    
+    tracing::info!("Imported whatsmeow database: {:?}", imported);
+    Ok(imported)
+}
+
+async fn fetch_all(source: &SqlitePool, sql: &str) -> Result<Vec<sqlx::sqlite::SqliteRow>> {
+    sqlx::query(sql)
+        .fetch_all(source)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to read whatsmeow table: {}", e)))
+}
+
+async fn fetch_for(source: &SqlitePool, sql: &str, our_jid: &str) -> Result<Vec<sqlx::sqlite::SqliteRow>> {
+    sqlx::query(sql)
+        .bind(our_jid)
+        .fetch_all(source)
+        .await
+        .map_err(|e| Error::Database(format!("Failed to read whatsmeow table: {}", e)))
+}
+
+/// libsignal-protocol-go `RecordStructure`; previous sessions are not imported
+#[derive(Clone, PartialEq, Message)]
+struct GoSessionRecord {
+    #[prost(message, optional, tag = "1")]
+    current_session: Option<GoSession>,
+}
+
+/// libsignal-protocol-go `SessionStructure`
+#[derive(Clone, PartialEq, Message)]
+struct GoSession {
+    #[prost(uint32, optional, tag = "1")]
+    session_version: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    local_identity_public: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    remote_identity_public: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    root_key: Option<Vec<u8>>,
+    #[prost(uint32, optional, tag = "5")]
+    previous_counter: Option<u32>,
+    #[prost(message, optional, tag = "6")]
+    sender_chain: Option<GoChain>,
+    /// Oldest first
+    #[prost(message, repeated, tag = "7")]
+    receiver_chains: Vec<GoChain>,
+    #[prost(message, optional, tag = "9")]
+    pending_pre_key: Option<GoPendingPreKey>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoChain {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    sender_ratchet_key: Option<Vec<u8>>,
+    #[prost(message, optional, tag = "3")]
+    chain_key: Option<GoChainKey>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoChainKey {
+    #[prost(uint32, optional, tag = "1")]
+    index: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    key: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoPendingPreKey {
+    #[prost(uint32, optional, tag = "1")]
+    pre_key_id: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    base_key: Option<Vec<u8>>,
+    #[prost(int32, optional, tag = "3")]
+    signed_pre_key_id: Option<i32>,
+}
+
+/// libsignal-protocol-go `SenderKeyRecordStructure`
+#[derive(Clone, PartialEq, Message)]
+struct GoSenderKeyRecord {
+    /// Newest first
+    #[prost(message, repeated, tag = "1")]
+    sender_key_states: Vec<GoSenderKeyState>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoSenderKeyState {
+    #[prost(uint32, optional, tag = "1")]
+    sender_key_id: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    sender_chain_key: Option<GoSenderChainKey>,
+    #[prost(message, optional, tag = "3")]
+    sender_signing_key: Option<GoSenderSigningKey>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoSenderChainKey {
+    #[prost(uint32, optional, tag = "1")]
+    iteration: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    seed: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GoSenderSigningKey {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    public: Option<Vec<u8>>,
+}
+
+/// A 32-byte key, libsignal prefixes public keys with their type
+fn public_key(key: &[u8]) -> Result<[u8; 32]> {
+    let key = match key {
+        [DJB_TYPE, rest @ ..] if rest.len() == 32 => rest,
+        key => key,
+    };
+    key.try_into()
+        .map_err(|_| Error::Crypto(format!("Invalid key length {}", key.len())))
+}
+
+fn required_key(key: &Option<Vec<u8>>, field: &str) -> Result<[u8; 32]> {
+    public_key(key.as_deref().ok_or_else(|| Error::Protocol(format!("Missing {}", field)))?)
+}
+
+fn convert_chain(chain: &GoChain) -> Result<ChainState> {
+    let chain_key = chain.chain_key.as_ref()
+        .ok_or_else(|| Error::Protocol("Chain without chain key".to_string()))?;
+    Ok(ChainState {
+        chain_key: required_key(&chain_key.key, "chain key")?,
+        message_number: chain_key.index.unwrap_or_default(),
+        ephemeral_public: chain.sender_ratchet_key.as_deref().map(public_key).transpose()?,
+    })
+}
+
+/// Convert a serialized libsignal-protocol-go session record
+fn convert_session(data: &[u8]) -> Result<SessionState> {
+    let go = GoSessionRecord::decode(data)?
+        .current_session
+        .ok_or_else(|| Error::Protocol("Session record without current session".to_string()))?;
+    let mut session = SessionState::new(
+        required_key(&go.local_identity_public, "local identity")?,
+        required_key(&go.remote_identity_public, "remote identity")?,
+        required_key(&go.root_key, "root key")?,
+    );
+    if let Some(version) = go.session_version {
+        session.version = version as u8;
+    }
+    session.previous_counter = go.previous_counter.unwrap_or_default();
+    if let Some(chain) = &go.sender_chain {
+        let chain = convert_chain(chain)?;
+        session.send_message_number = chain.message_number;
+        session.sending_chain_key = Some(chain);
+    }
+    let mut receiving_chains = HashMap::new();
+    for chain in &go.receiver_chains {
+        let chain = convert_chain(chain)?;
+        let ratchet_key = chain.ephemeral_public.map(|key| key.to_vec()).unwrap_or_default();
+        session.receiving_chain_key = Some(chain.clone());
+        receiving_chains.insert(ratchet_key, chain);
+    }
+    session.receiving_chains = receiving_chains;
+    session.pending_prekey = go.pending_pre_key.as_ref().map(|pending| -> Result<PendingPreKey> {
+        Ok(PendingPreKey {
+            signed_prekey_id: pending.signed_pre_key_id.unwrap_or_default() as u32,
+            prekey_id: pending.pre_key_id,
+            base_key: required_key(&pending.base_key, "pending base key")?,
+        })
+    }).transpose()?;
+    Ok(session)
+}
+
+/// Convert the newest state of a serialized libsignal-protocol-go sender key record
+fn convert_sender_key(data: &[u8]) -> Result<SenderKeyState> {
+    let record = GoSenderKeyRecord::decode(data)?;
+    let state = record.sender_key_states.first()
+        .ok_or_else(|| Error::Protocol("Sender key record without states".to_string()))?;
+    let chain_key = state.sender_chain_key.as_ref()
+        .ok_or_else(|| Error::Protocol("Sender key without chain key".to_string()))?;
+    let signing_key = state.sender_signing_key.as_ref()
+        .ok_or_else(|| Error::Protocol("Sender key without signing key".to_string()))?;
+    let sender_key_id = state.sender_key_id.unwrap_or_default();
+    let iteration = chain_key.iteration.unwrap_or_default();
+    Ok(SenderKeyState {
+        sender_key_id,
+        sender_key: SenderKey {
+            id: sender_key_id,
+            iteration,
+            chain_key: required_key(&chain_key.seed, "sender chain seed")?,
+            signing_key: required_key(&signing_key.public, "sender signing key")?,
+        },
+        message_number: iteration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::{
+        MemoryGroupSessionStore, MemoryIdentityKeyStore, MemoryPreKeyStore, MemorySessionStore, SessionStore,
+        SignalProtocolManager,
+    };
+    use crate::store::{DeviceStore, MemoryStore};
+    use crate::util::keys::SigningKeyPair;
+    use std::sync::Arc;
+    
+    const GO_SCHEMA: &[&str] = &[
+        "CREATE TABLE whatsmeow_device (jid TEXT PRIMARY KEY, lid TEXT, registration_id BIGINT NOT NULL, noise_key bytea NOT NULL, identity_key bytea NOT NULL, signed_pre_key bytea NOT NULL, signed_pre_key_id INTEGER NOT NULL, signed_pre_key_sig bytea NOT NULL, adv_key bytea, adv_details bytea, adv_account_sig bytea, adv_account_sig_key bytea, adv_device_sig bytea, push_name TEXT NOT NULL DEFAULT '')",
+        "CREATE TABLE whatsmeow_identity_keys (our_jid TEXT, their_id TEXT, identity bytea NOT NULL, PRIMARY KEY (our_jid, their_id))",
+        "CREATE TABLE whatsmeow_pre_keys (jid TEXT, key_id INTEGER, key bytea NOT NULL, uploaded BOOLEAN NOT NULL, PRIMARY KEY (jid, key_id))",
+        "CREATE TABLE whatsmeow_sessions (our_jid TEXT, their_id TEXT, session bytea, PRIMARY KEY (our_jid, their_id))",
+        "CREATE TABLE whatsmeow_sender_keys (our_jid TEXT, chat_id TEXT, sender_id TEXT, sender_key bytea NOT NULL, PRIMARY KEY (our_jid, chat_id, sender_id))",
+        "CREATE TABLE whatsmeow_app_state_sync_keys (jid TEXT, key_id bytea, key_data bytea NOT NULL, timestamp BIGINT NOT NULL, fingerprint bytea NOT NULL, PRIMARY KEY (jid, key_id))",
+        "CREATE TABLE whatsmeow_app_state_version (jid TEXT, name TEXT, version BIGINT NOT NULL, hash bytea NOT NULL, PRIMARY KEY (jid, name))",
+        "CREATE TABLE whatsmeow_app_state_mutation_macs (jid TEXT, name TEXT, version BIGINT, index_mac bytea, value_mac bytea NOT NULL, PRIMARY KEY (jid, name, version, index_mac))",
+        "CREATE TABLE whatsmeow_contacts (our_jid TEXT, their_jid TEXT, first_name TEXT, full_name TEXT, push_name TEXT, business_name TEXT, PRIMARY KEY (our_jid, their_jid))",
+        "CREATE TABLE whatsmeow_lid_map (lid TEXT PRIMARY KEY, pn TEXT UNIQUE NOT NULL)",
+        "INSERT INTO whatsmeow_pre_keys VALUES ('1234567890:7@s.whatsapp.net', 1, zeroblob(32), TRUE), ('1234567890:7@s.whatsapp.net', 2, zeroblob(32), FALSE)",
+        "INSERT INTO whatsmeow_app_state_sync_keys VALUES ('1234567890:7@s.whatsapp.net', x'aa', x'0f', 1700000000000, x'10')",
+    ];
+    
+    const OUR_JID: &str = "1234567890:7@s.whatsapp.net";
+    
+    /// Key with the type prefix libsignal-protocol-go writes
+    fn djb(key: [u8; 32]) -> Vec<u8> {
+        [&[DJB_TYPE][..], &key].concat()
+    }
+    
+    /// A whatsmeow database of an account with a session with 5550001:0,
+    /// whose sending chain is `peer_chain`
+    async fn whatsmeow_database(identity: &SigningKeyPair, peer_identity: [u8; 32], peer_chain: &ChainState) -> SqlitePool {
+        let source = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        for sql in GO_SCHEMA {
+            sqlx::query(sql).execute(&source).await.unwrap();
+        }
+        sqlx::query("INSERT INTO whatsmeow_device (jid, registration_id, noise_key, identity_key, signed_pre_key, signed_pre_key_id, signed_pre_key_sig) VALUES (?, 4242, ?, ?, ?, 1, ?)")
+            .bind(OUR_JID)
+            .bind(vec![1u8; 32])
+            .bind(identity.private_bytes().to_vec())
+            .bind(vec![3u8; 32])
+            .bind(vec![4u8; 64])
+            .execute(&source).await.unwrap();
+        sqlx::query("INSERT INTO whatsmeow_identity_keys VALUES (?, '5550001:0', ?)")
+            .bind(OUR_JID)
+            .bind(peer_identity.to_vec())
+            .execute(&source).await.unwrap();
+        
+        let session = GoSessionRecord {
+            current_session: Some(GoSession {
+                session_version: Some(3),
+                local_identity_public: Some(djb(identity.public_bytes())),
+                remote_identity_public: Some(djb(peer_identity)),
+                root_key: Some(vec![8; 32]),
+                previous_counter: Some(0),
+                sender_chain: None,
+                receiver_chains: vec![GoChain {
+                    sender_ratchet_key: Some(djb(peer_chain.ephemeral_public.unwrap())),
+                    chain_key: Some(GoChainKey { index: Some(peer_chain.message_number), key: Some(peer_chain.chain_key.to_vec()) }),
+                }],
+                pending_pre_key: None,
+            }),
+        };
+        sqlx::query("INSERT INTO whatsmeow_sessions VALUES (?, '5550001:0', ?)")
+            .bind(OUR_JID)
+            .bind(session.encode_to_vec())
+            .execute(&source).await.unwrap();
+        
+        let sender_key = GoSenderKeyRecord {
+            sender_key_states: vec![GoSenderKeyState {
+                sender_key_id: Some(11),
+                sender_chain_key: Some(GoSenderChainKey { iteration: Some(4), seed: Some(vec![5; 32]) }),
+                sender_signing_key: Some(GoSenderSigningKey { public: Some(djb([6; 32])) }),
+            }],
+        };
+        sqlx::query("INSERT INTO whatsmeow_sender_keys VALUES (?, '123@g.us', '5550001:0', ?), (?, '123@g.us', '5550002:0', x'00')")
+            .bind(OUR_JID)
+            .bind(sender_key.encode_to_vec())
+            .bind(OUR_JID)
+            .execute(&source).await.unwrap();
+        source
+    }
+    
+    fn peer_chain() -> ChainState {
+        ChainState { chain_key: [9; 32], message_number: 0, ephemeral_public: Some([10; 32]) }
+    }
+    
+    #[tokio::test]
+    async fn test_import_whatsmeow() {
+        let identity = SigningKeyPair::generate();
+        let source = whatsmeow_database(&identity, [2; 32], &peer_chain()).await;
+        let target = MemoryStore::new();
+        
+        let imported = import(&source, &target).await.unwrap();
+        assert_eq!(imported, WhatsmeowImport {
+            devices: 1,
+            identities: 1,
+            sessions: 1,
+            pre_keys: 2,
+            // The corrupt sender key of 5550002:0 is skipped
+            sender_keys: 1,
+            app_state_keys: 1,
+            ..Default::default()
+        });
+        
+        let stored = target.load_device().await.unwrap().unwrap();
+        assert_eq!(stored.jid, JID::new_user("1234567890").with_device(7));
+        assert_eq!(stored.registration_id, 4242);
+        assert_eq!(stored.noise_key, vec![1; 32]);
+        let signed_pre_key = record::decode_signed_pre_key(&stored.signed_pre_key).unwrap();
+        assert_eq!(signed_pre_key.id, 1);
+        assert_eq!(signed_pre_key.signature, vec![4; 64]);
+        
+        // Addresses keep the user:device format the runtime uses
+        let device = target.device(&stored.jid);
+        let identity = device.identities.get_identity("5550001:0").await.unwrap().unwrap();
+        assert_eq!(record::decode_identity(&identity).unwrap().identity_key.public_key, [2; 32]);
+        let session = record::load_session(device.sessions.as_ref(), "5550001:0").await.unwrap().unwrap();
+        assert_eq!(session.root_key, [8; 32]);
+        assert!(record::load_pre_key(device.pre_keys.as_ref(), 2).await.unwrap().is_some());
+        assert_eq!(device.pre_keys.uploaded_pre_key_count().await.unwrap(), 1);
+        let sender_key = record::load_sender_key(device.sender_keys.as_ref(), "123@g.us", "5550001:0").await.unwrap().unwrap();
+        assert_eq!((sender_key.sender_key_id, sender_key.sender_key.iteration), (11, 4));
+        assert_eq!(sender_key.sender_key.signing_key, [6; 32]);
+        assert!(device.app_state_keys.get_app_state_key(&[0xaa]).await.unwrap().is_some());
+    }
+    
+    /// The account data of a paired whatsmeow device
+    const ACCOUNT_DATA: &[&str] = &[
+        "UPDATE whatsmeow_device SET lid = '9876:7@lid', push_name = 'Alice', adv_details = x'01', adv_account_sig = x'02', adv_account_sig_key = x'03', adv_device_sig = x'04'",
+        "INSERT INTO whatsmeow_app_state_version VALUES ('1234567890:7@s.whatsapp.net', 'regular', 5, x'0707')",
+        "INSERT INTO whatsmeow_app_state_mutation_macs VALUES ('1234567890:7@s.whatsapp.net', 'regular', 5, x'aa', x'bb')",
+        "INSERT INTO whatsmeow_contacts VALUES ('1234567890:7@s.whatsapp.net', '5550001@s.whatsapp.net', 'Bob', 'Bob Smith', 'bobby', NULL)",
+        "INSERT INTO whatsmeow_lid_map VALUES ('4444', '5550001')",
+    ];
+    
+    #[tokio::test]
+    async fn test_import_account_data() {
+        let source = whatsmeow_database(&SigningKeyPair::generate(), [2; 32], &peer_chain()).await;
+        for sql in ACCOUNT_DATA {
+            sqlx::query(sql).execute(&source).await.unwrap();
+        }
+        
+        // A plain Store cannot keep it, nothing is written
+        let target = MemoryStore::new();
+        let err = import(&source, &target).await.unwrap_err().to_string();
+        assert!(err.contains("push name") && err.contains("contacts"), "{}", err);
+        assert!(target.load_device().await.unwrap().is_none());
+        
+        let config = crate::database::DatabaseConfig { database_url: "sqlite::memory:".to_string(), ..Default::default() };
+        let database = Database::new(config).await.unwrap();
+        let imported = import_database(&source, &database).await.unwrap();
+        assert_eq!((imported.sessions, imported.app_state_versions, imported.contacts, imported.lid_mappings), (1, 1, 1, 2));
+        
+        let pool = database.pool().clone();
+        let state = SqliteAppStateVersionStore::new(pool.clone()).get(PatchName::Regular).await.unwrap().unwrap();
+        assert_eq!((state.version, state.hash), (5, vec![7, 7]));
+        assert_eq!(state.value_macs.get(&vec![0xaa]), Some(&vec![0xbb]));
+        
+        let contacts = SqliteContactStore::new(pool.clone());
+        let bob = contacts.load_contact(&JID::new_user("5550001")).await.unwrap().unwrap();
+        assert_eq!((bob.notify_name.as_deref(), bob.lid), (Some("bobby"), Some(JID::new_lid("4444"))));
+        let own = contacts.load_contact(&JID::new_user("1234567890")).await.unwrap().unwrap();
+        assert_eq!(own.notify_name.as_deref(), Some("Alice"));
+        let lid = SqliteLidStore::new(pool.clone()).get_lid(&JID::new_user("1234567890")).await.unwrap();
+        assert_eq!(lid, Some(JID::new_lid("9876")));
+        
+        let adv = SqliteSettingsStore::new(pool).get_setting(ADV_IDENTITY_SETTING).await.unwrap().unwrap();
+        let adv = AdvSignedDeviceIdentity::decode(STANDARD.decode(adv).unwrap().as_slice()).unwrap();
+        assert_eq!((adv.details, adv.device_signature), (Some(vec![1]), Some(vec![4])));
+        database.close().await;
+    }
+    
+    /// The session records here are built from this module's own message
+    /// definitions, so this checks the conversion into a working session,
+    /// not byte compatibility with records libsignal-protocol-go wrote.
+    #[tokio::test]
+    async fn test_converted_session_decrypts() {
+        let identity = SigningKeyPair::generate();
+        let peer_identity = SigningKeyPair::generate();
+        
+        // The peer sends on the chain the imported session receives on
+        let mut peer_session = SessionState::new(peer_identity.public_bytes(), identity.public_bytes(), [8; 32]);
+        peer_session.sending_chain_key = Some(peer_chain());
+        let mut peer_sessions = MemorySessionStore::new();
+        peer_sessions.store_session(OUR_JID, peer_session);
+        let mut peer = SignalProtocolManager::new_with_stores(
+            Box::new(MemoryIdentityKeyStore::with_keypair(peer_identity.clone(), 1)),
+            Box::new(peer_sessions),
+            Box::new(MemoryPreKeyStore::new()),
+            Box::new(MemoryGroupSessionStore::new()),
+        );
+        
+        let source = whatsmeow_database(&identity, peer_identity.public_bytes(), &peer_chain()).await;
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        import(&source, store.as_ref()).await.unwrap();
+        let device = store.load_device().await.unwrap().unwrap();
+        let mut client = SignalProtocolManager::load(store.clone(), &device).await.unwrap();
+        
+        let message = peer.encrypt_message(OUR_JID, b"hello from whatsmeow").unwrap();
+        assert_eq!(client.decrypt_message("5550001:0", &message).unwrap(), b"hello from whatsmeow");
+        assert_eq!(client.get_identity("5550001:0").unwrap().public_key, peer_identity.public_bytes());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::types::{JID, MessageInfo, MessageReceipt};
+use crate::types::{DeliverySummary, JID, MessageInfo, MessageKey, MessageReceipt, MessageStatus};
 use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 
@@ -6,29 +6,115 @@ use std::time::SystemTime;
 pub type EventHandler = Box<dyn Fn(Event) -> bool + Send + Sync>;
 
 /// All possible events that can be emitted by the WhatsApp client
-#[derive(Debug, Clone)]
+///
+/// Serializes as `{"type": "message", "data": {...}}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum Event {
     /// Connection state changed
     Connected,
     Disconnected { reason: String },
+    /// Delivery of the stanzas queued while we were offline is starting or finished
+    OfflineSync(crate::dedup::OfflineSync),
     
     /// Authentication events
     LoggedIn,
-    LoggedOut,
+    /// The session was logged out or the companion was unlinked; the stored
+    /// credentials have been deleted
+    LoggedOut { reason: String },
+    /// Another client connected with the same credentials and took over the session
+    StreamReplaced,
+    /// The account is temporarily banned, reconnecting is pointless before `expires`
+    TemporaryBan { code: Option<u32>, expires: Option<SystemTime> },
+    /// The server refused the announced WhatsApp Web `version` and no newer
+    /// one was configured or could be fetched
+    ClientOutdated { version: crate::auth::WebVersion },
     QRCode { code: String },
+    /// None of the pairing QR codes was scanned in time, reconnect for new ones
+    QRTimeout,
+    
+    /// The server reported the primary device (phone) as removed or logged us
+    /// out at the given time, the companion is running in degraded mode
+    PrimaryDeviceOffline { since: SystemTime },
+    /// The primary device was seen again after being offline
+    PrimaryDeviceOnline,
     
     /// Message events
     Message(MessageInfo),
+    /// A message could not be decrypted even after asking the sender to
+    /// retry. `info` is the placeholder stored in its place.
+    UndecryptableMessage { info: MessageInfo, reason: String },
     MessageReceipt { receipt: MessageReceipt },
-    MessageRevoke(MessageRevokeEvent),
+    /// All recipients of a group message delivered or read it
+    MessageReceiptAggregate(ReceiptAggregateEvent),
+    MessageEdited(MessageEditedEvent),
+    MessageRevoked(MessageRevokeEvent),
     MessageAck(MessageAckEvent),
+    ReactionUpdated(ReactionUpdatedEvent),
+    /// A message was starred or unstarred, possibly on another device
+    MessageStarred(MessageStarredEvent),
+    /// A message of a disappearing chat was kept or unkept
+    MessageKept(MessageKeptEvent),
+    
+    /// Messages of a history sync sent by the primary device, e.g. in answer
+    /// to [`crate::Client::fetch_history`]
+    HistorySync(crate::history_sync::HistorySyncEvent),
+    
+    /// Received media was downloaded by the auto-download policy, see [`crate::media::auto_download`]
+    MediaDownloaded(crate::media::MediaDownloadedEvent),
+    /// The sender's phone answered a request to upload expired media again
+    MediaRetry(crate::media::MediaRetryEvent),
+    
+    /// A business label was edited or attached to or removed from a chat or message
+    LabelChanged(crate::appstate::LabelChange),
+    /// Another device changed an app state item we modified too and the
+    /// [`ConflictStrategy`](crate::appstate::ConflictStrategy) of its data type
+    /// is `Manual`; settle it with [`crate::appstate::SyncContext::resolve_conflict`]
+    AppStateConflict(crate::appstate::SyncConflict),
+    /// Messages stored in the offline outbox were sent after reconnecting
+    OutboxFlushed(OutboxFlushedEvent),
+    /// A message scheduled with [`crate::Client::schedule_message`] was sent
+    ScheduledMessageSent(ScheduledMessageSentEvent),
+    
+    /// A contact's identity key differs from the stored one, e.g. after they
+    /// reinstalled the app. `blocked` is set if the configured
+    /// [`IdentityChangePolicy`](crate::signal::IdentityChangePolicy) refused the new key.
+    IdentityChanged { jid: JID, blocked: bool },
     
-    /// Presence events
+    /// Our block list was changed, possibly by another device
+    BlocklistChanged(BlocklistEvent),
+    
+    /// A contact came online or went offline, see [`crate::Client::subscribe_presence`]
     Presence(PresenceEvent),
+    /// A user started or stopped typing or recording, see [`crate::Client::get_active_typers`]
+    ChatState(crate::chat_state::ChatStateEvent),
+    
+    /// A request was held back by the client-side rate limiter of `category`,
+    /// see [`crate::connection::rate_limit`]
+    RateLimited { category: String, retry_after: std::time::Duration },
+    
+    /// Fields of the client configuration changed by [`crate::Client::update_config`]
+    ConfigChanged { fields: Vec<String> },
+    
+    /// The application handed a push wakeup to [`crate::Client::handle_push_wakeup`];
+    /// `connected` tells whether the client was already connected
+    PushWakeup { connected: bool },
     
     /// Group events
     GroupInfo(GroupInfoEvent),
+    GroupInfoChanged(GroupInfoChangedEvent),
+    /// A typed change of a group made by someone else, see [`crate::group::parse_group_events`]
+    GroupChange(crate::group::GroupEvent),
     GroupParticipants(GroupParticipantsEvent),
+    GroupJoinRequest(GroupJoinRequestEvent),
+    
+    /// Community events
+    CommunityJoinRequest(CommunityJoinRequestEvent),
+    
+    /// A user changed their profile picture or about text
+    ProfileChanged(crate::notification::ProfileChange),
+    /// A notification of a type no subsystem handles, see [`crate::notification`]
+    UnhandledNotification(crate::notification::UnhandledNotification),
     
     /// Other events
     Unknown,
@@ -48,6 +134,77 @@ pub struct MessageRevokeEvent {
     pub timestamp: SystemTime,
 }
 
+/// Change of a single block list entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlocklistChangeAction {
+    Block,
+    Unblock,
+}
+
+impl BlocklistChangeAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlocklistChangeAction::Block => "block",
+            BlocklistChangeAction::Unblock => "unblock",
+        }
+    }
+}
+
+impl std::str::FromStr for BlocklistChangeAction {
+    type Err = crate::error::Error;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(BlocklistChangeAction::Block),
+            "unblock" => Ok(BlocklistChangeAction::Unblock),
+            other => Err(crate::error::Error::Protocol(format!("Unknown block list action: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlocklistChange {
+    pub jid: JID,
+    pub action: BlocklistChangeAction,
+}
+
+/// Block list update pushed by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlocklistEvent {
+    /// `modify` if `changes` only lists changed entries, `None` if it is the whole list
+    pub action: Option<String>,
+    pub dhash: Option<String>,
+    pub prev_dhash: Option<String>,
+    pub changes: Vec<BlocklistChange>,
+}
+
+impl BlocklistEvent {
+    /// Whether the event only lists changed entries
+    pub fn is_modification(&self) -> bool {
+        self.action.as_deref() == Some("modify")
+    }
+}
+
+/// A group message reached a status with every recipient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptAggregateEvent {
+    pub message_id: String,
+    /// `Delivered` once everyone received the message, `Read` once everyone read it
+    pub status: MessageStatus,
+    pub summary: DeliverySummary,
+}
+
+/// The sender of a message edited it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEditedEvent {
+    pub chat: JID,
+    pub sender: JID,
+    pub id: String,
+    /// New text or caption of the message
+    pub new_text: Option<String>,
+    pub timestamp: SystemTime,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageAckEvent {
     pub chat: JID,
@@ -56,6 +213,57 @@ pub struct MessageAckEvent {
     pub timestamp: SystemTime,
 }
 
+/// Summary of an outbox flush after reconnecting
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxFlushedEvent {
+    /// Messages handed to the server
+    pub sent: usize,
+    /// Messages dropped because their TTL passed while offline
+    pub expired: usize,
+    /// Messages dropped because sending them failed permanently
+    pub failed: usize,
+    /// Messages still waiting because the connection dropped again
+    pub remaining: usize,
+}
+
+/// A scheduled message was handed to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessageSentEvent {
+    /// ID the message was scheduled and sent with
+    pub id: String,
+    pub chat: JID,
+    pub scheduled_for: SystemTime,
+    pub sent_at: SystemTime,
+}
+
+/// A user reacted to a message or removed their reaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionUpdatedEvent {
+    pub message_key: MessageKey,
+    pub sender: JID,
+    /// The new reaction, `None` if the reaction was removed
+    pub emoji: Option<String>,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// A message was starred or unstarred
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageStarredEvent {
+    pub message_key: MessageKey,
+    pub starred: bool,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// A message of a disappearing chat was kept or unkept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageKeptEvent {
+    pub message_key: MessageKey,
+    /// Who kept or unkept the message
+    pub sender: JID,
+    pub kept: bool,
+    pub timestamp: SystemTime,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresenceEvent {
     pub from: JID,
@@ -71,6 +279,44 @@ pub struct GroupInfoEvent {
     pub participants: Vec<JID>,
 }
 
+/// A `w:gp2` notification changing group metadata or membership.
+///
+/// Only the fields touched by the notification are set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupInfoChangedEvent {
+    pub jid: JID,
+    pub author: Option<JID>,
+    pub timestamp: Option<SystemTime>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub announce: Option<bool>,
+    pub locked: Option<bool>,
+    pub ephemeral_expiration: Option<u32>,
+    pub membership_approval: Option<bool>,
+    pub joined: Vec<JID>,
+    pub left: Vec<JID>,
+    pub promoted: Vec<JID>,
+    pub demoted: Vec<JID>,
+}
+
+/// A user asked to join a group that requires admin approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupJoinRequestEvent {
+    pub group: JID,
+    pub requester: JID,
+    pub request_method: Option<String>,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// A user asked to join a community that requires admin approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommunityJoinRequestEvent {
+    pub community: JID,
+    pub requester: JID,
+    pub request_method: Option<String>,
+    pub timestamp: Option<SystemTime>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupParticipantsEvent {
     pub jid: JID,
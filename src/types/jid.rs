@@ -4,6 +4,7 @@ use std::fmt;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     
     #[test]
     fn test_jid_parsing() {
@@ -20,8 +21,85 @@ mod tests {
         assert_eq!(group_jid.server, "g.us");
         assert!(group_jid.is_group());
     }
+    
+    #[test]
+    fn test_ad_jid() {
+        let jid: JID = "1234567890:12@s.whatsapp.net".parse().unwrap();
+        assert_eq!((jid.agent, jid.device), (0, 12));
+        assert!(jid.ad);
+        assert_eq!(jid.to_string(), "1234567890:12@s.whatsapp.net");
+        assert_eq!(jid.to_non_ad().to_string(), "1234567890@s.whatsapp.net");
+        
+        let jid: JID = "1234567890.1:3@hosted".parse().unwrap();
+        assert_eq!((jid.agent, jid.device), (1, 3));
+        assert_eq!(jid.to_string(), "1234567890.1:3@hosted");
+        
+        assert!("123:x@s.whatsapp.net".parse::<JID>().is_err());
+        assert!("123.x:1@s.whatsapp.net".parse::<JID>().is_err());
+    }
+    
+    #[test]
+    fn test_servers() {
+        let server: JID = "s.whatsapp.net".parse().unwrap();
+        assert_eq!(server, JID::server_jid());
+        assert!(server.is_server());
+        
+        let lid: JID = "123456789012345:4@lid".parse().unwrap();
+        assert!(lid.is_lid());
+        assert!(!lid.is_user());
+        assert_eq!(lid.to_non_ad(), JID::new_lid("123456789012345"));
+        
+        assert!(JID::status_broadcast().is_status_broadcast());
+        assert!(JID::status_broadcast().is_broadcast());
+        assert!("120363025246125486@newsletter".parse::<JID>().unwrap().is_newsletter());
+        assert!(!JID::new_group("1-2").is_user());
+    }
+    
+    proptest! {
+        #[test]
+        fn prop_parse_never_panics(s in "\\PC*") {
+            let _ = s.parse::<JID>();
+        }
+        
+        #[test]
+        fn prop_display_roundtrip(
+            user in "[0-9]{1,15}",
+            agent in any::<u8>(),
+            device in any::<u8>(),
+            server in prop::sample::select(vec![DEFAULT_USER_SERVER, GROUP_SERVER, HIDDEN_USER_SERVER, HOSTED_SERVER]),
+        ) {
+            let jid = JID { user, agent, device, server: server.to_string(), ad: agent != 0 || device != 0 };
+            prop_assert_eq!(jid.to_string().parse::<JID>().unwrap(), jid);
+        }
+    }
 }
 
+/// Regular user server
+pub const DEFAULT_USER_SERVER: &str = "s.whatsapp.net";
+/// Group server
+pub const GROUP_SERVER: &str = "g.us";
+/// Legacy user server, still used in some payloads
+pub const LEGACY_USER_SERVER: &str = "c.us";
+/// Broadcast lists and status updates
+pub const BROADCAST_SERVER: &str = "broadcast";
+/// Hidden users identified by a LID instead of their phone number
+pub const HIDDEN_USER_SERVER: &str = "lid";
+/// Channels
+pub const NEWSLETTER_SERVER: &str = "newsletter";
+/// Business accounts hosted by Meta
+pub const HOSTED_SERVER: &str = "hosted";
+/// Messenger users
+pub const MESSENGER_SERVER: &str = "msgr";
+/// Users of other messaging networks
+pub const INTEROP_SERVER: &str = "interop";
+/// AI bots
+pub const BOT_SERVER: &str = "bot";
+/// Calls
+pub const CALL_SERVER: &str = "call";
+
+/// User part of the status broadcast JID
+pub const STATUS_BROADCAST_USER: &str = "status";
+
 /// JID (Jabber ID) represents a WhatsApp user or group identifier
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct JID {
@@ -44,6 +122,37 @@ impl JID {
         }
     }
     
+    /// Create a JID addressing a device (`user.agent:device@server`)
+    pub fn new_ad(user: String, agent: u8, device: u8, server: String) -> Self {
+        Self {
+            user,
+            agent,
+            device,
+            server,
+            ad: true,
+        }
+    }
+    
+    /// Create a regular user JID from a phone number
+    pub fn new_user(phone: &str) -> Self {
+        Self::new(phone.to_string(), DEFAULT_USER_SERVER.to_string())
+    }
+    
+    /// Create a hidden user JID from a LID
+    pub fn new_lid(lid: &str) -> Self {
+        Self::new(lid.to_string(), HIDDEN_USER_SERVER.to_string())
+    }
+    
+    /// JID of the WhatsApp server itself
+    pub fn server_jid() -> Self {
+        Self::new(String::new(), DEFAULT_USER_SERVER.to_string())
+    }
+    
+    /// JID status updates are broadcast to
+    pub fn status_broadcast() -> Self {
+        Self::new(STATUS_BROADCAST_USER.to_string(), BROADCAST_SERVER.to_string())
+    }
+    
     /// Get device ID from JID
     pub fn device_id(&self) -> Option<u32> {
         if self.device > 0 {
@@ -53,46 +162,92 @@ impl JID {
         }
     }
     
-    /// Parse a JID from string format "user@server"
+    /// Parse a JID from string format `user@server`, `user.agent:device@server` or `server`
     pub fn parse(jid_str: &str) -> Result<Self, crate::error::Error> {
-        let parts: Vec<&str> = jid_str.split('@').collect();
-        if parts.len() != 2 {
-            return Err(crate::error::Error::InvalidJID(format!("Invalid JID format: {}", jid_str)));
-        }
-        
-        Ok(Self::new(parts[0].to_string(), parts[1].to_string()))
+        jid_str.parse()
     }
     
     /// Create a new group JID
     pub fn new_group(group_id: &str) -> Self {
-        Self::new(group_id.to_string(), "g.us".to_string())
+        Self::new(group_id.to_string(), GROUP_SERVER.to_string())
     }
     
-    /// Check if this is a user JID
+    /// Check if this is a phone number based user JID
     pub fn is_user(&self) -> bool {
-        self.server == "s.whatsapp.net"
+        self.server == DEFAULT_USER_SERVER
+    }
+    
+    /// Check if this is a hidden user (LID) JID
+    pub fn is_lid(&self) -> bool {
+        self.server == HIDDEN_USER_SERVER
     }
     
     /// Check if this is a group JID
     pub fn is_group(&self) -> bool {
-        self.server == "g.us"
+        self.server == GROUP_SERVER
+    }
+    
+    /// Check if this is a broadcast list or the status broadcast
+    pub fn is_broadcast(&self) -> bool {
+        self.server == BROADCAST_SERVER
+    }
+    
+    /// Check if this is the status broadcast
+    pub fn is_status_broadcast(&self) -> bool {
+        self.is_broadcast() && self.user == STATUS_BROADCAST_USER
+    }
+    
+    /// Check if this is a channel JID
+    pub fn is_newsletter(&self) -> bool {
+        self.server == NEWSLETTER_SERVER
     }
     
-    /// Check if this is a server JID
+    /// Check if this is a bot JID
+    pub fn is_bot(&self) -> bool {
+        self.server == BOT_SERVER
+    }
+    
+    /// Check if this is the JID of the server itself
     pub fn is_server(&self) -> bool {
-        self.server == "server"
+        self.user.is_empty() && self.server == DEFAULT_USER_SERVER
     }
     
-    /// Get the string representation without device info
-    pub fn to_non_ad(&self) -> String {
-        format!("{}@{}", self.user, self.server)
+    /// Create a copy of this JID addressing a specific device
+    pub fn with_device(&self, device: u8) -> Self {
+        Self {
+            user: self.user.clone(),
+            agent: self.agent,
+            device,
+            server: self.server.clone(),
+            ad: true,
+        }
+    }
+    
+    /// Get the Signal protocol address of this JID (`user:device`)
+    pub fn signal_address(&self) -> String {
+        format!("{}:{}", self.user, self.device)
+    }
+    
+    /// Format with agent and device always present (`user.agent:device@server`),
+    /// the form WhatsApp hashes device lists in
+    pub fn ad_string(&self) -> String {
+        format!("{}.{}:{}@{}", self.user, self.agent, self.device, self.server)
+    }
+    
+    /// Get the user JID without agent and device
+    pub fn to_non_ad(&self) -> JID {
+        Self::new(self.user.clone(), self.server.clone())
     }
 }
 
 impl fmt::Display for JID {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.agent != 0 || self.device != 0 {
+        if self.user.is_empty() {
+            write!(f, "{}", self.server)
+        } else if self.agent != 0 {
             write!(f, "{}.{}:{}@{}", self.user, self.agent, self.device, self.server)
+        } else if self.device != 0 {
+            write!(f, "{}:{}@{}", self.user, self.device, self.server)
         } else {
             write!(f, "{}@{}", self.user, self.server)
         }
@@ -103,40 +258,39 @@ impl std::str::FromStr for JID {
     type Err = crate::Error;
     
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split('@').collect();
-        if parts.len() != 2 {
-            return Err(crate::Error::Protocol(format!("Invalid JID format: {}", s)));
+        let Some((user_part, server)) = s.split_once('@') else {
+            // Server JIDs have no user part
+            return Ok(JID::new(String::new(), s.to_string()));
+        };
+        if server.contains('@') {
+            return Err(crate::Error::InvalidJID(format!("Invalid JID format: {}", s)));
         }
         
-        let user_part = parts[0];
-        let server = parts[1].to_string();
+        let (user_agent, device) = match user_part.split_once(':') {
+            Some((user_agent, device)) => {
+                let device = device.parse().map_err(|_| {
+                    crate::Error::InvalidJID(format!("Invalid device in JID: {}", s))
+                })?;
+                (user_agent, Some(device))
+            }
+            None => (user_part, None),
+        };
+        let (user, agent) = match user_agent.split_once('.') {
+            Some((user, agent)) => {
+                let agent = agent.parse().map_err(|_| {
+                    crate::Error::InvalidJID(format!("Invalid agent in JID: {}", s))
+                })?;
+                (user, Some(agent))
+            }
+            None => (user_agent, None),
+        };
         
-        if user_part.contains('.') && user_part.contains(':') {
-            let agent_device: Vec<&str> = user_part.split('.').collect();
-            let user = agent_device[0].to_string();
-            let device_parts: Vec<&str> = agent_device[1].split(':').collect();
-            let agent = device_parts[0].parse().map_err(|_| {
-                crate::Error::Protocol(format!("Invalid agent in JID: {}", s))
-            })?;
-            let device = device_parts[1].parse().map_err(|_| {
-                crate::Error::Protocol(format!("Invalid device in JID: {}", s))
-            })?;
-            
-            Ok(JID {
-                user,
-                agent,
-                device,
-                server,
-                ad: true,
-            })
-        } else {
-            Ok(JID {
-                user: user_part.to_string(),
-                agent: 0,
-                device: 0,
-                server,
-                ad: false,
-            })
-        }
+        Ok(JID {
+            user: user.to_string(),
+            agent: agent.unwrap_or(0),
+            device: device.unwrap_or(0),
+            server: server.to_string(),
+            ad: agent.is_some() || device.is_some(),
+        })
     }
-}
\ No newline at end of file
+}
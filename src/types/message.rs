@@ -7,9 +7,45 @@ pub struct MessageInfo {
     pub id: String,
     pub chat: JID,
     pub sender: JID,
+    /// The other identifier of the sender: the LID of a phone number sender or vice versa
+    #[serde(default)]
+    pub sender_alt: Option<JID>,
+    /// Push name the sender had set when sending the message
+    #[serde(default)]
+    pub push_name: Option<String>,
     pub timestamp: SystemTime,
     pub message_type: MessageType,
     pub from_me: bool,
+    /// When the message was last edited by its sender
+    #[serde(default)]
+    pub edited_at: Option<SystemTime>,
+    /// Whether the message was deleted for everyone
+    #[serde(default)]
+    pub revoked: bool,
+    /// The message this one replies to
+    #[serde(default)]
+    pub quoted: Option<QuotedMessage>,
+    /// Users mentioned in the message text as `@<phone number>`
+    #[serde(default)]
+    pub mentioned_jids: Vec<JID>,
+    /// Whether the message mentions everyone in the group (`@all`)
+    #[serde(default)]
+    pub mentions_all: bool,
+    /// Groups mentioned in the message, e.g. in a community or a status
+    #[serde(default)]
+    pub group_mentions: Vec<GroupMention>,
+    /// How often the message was forwarded, `None` if it is not a forward
+    #[serde(default)]
+    pub forwarding_score: Option<u32>,
+    /// Content of the message, when it could be read from the stanza
+    #[serde(default)]
+    pub content: Option<SendableMessage>,
+    /// Whether we starred the message
+    #[serde(default)]
+    pub starred: bool,
+    /// Whether the message is kept in a disappearing chat
+    #[serde(default)]
+    pub kept: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -39,7 +75,10 @@ pub enum MessageType {
     HighlyStructuredMessage,
     InteractiveMessage,
     Call,
+    KeepInChat,
     ProtocolMessage,
+    /// Placeholder for a message that could not be decrypted
+    Undecryptable,
     AppState,
     Unknown,
 }
@@ -55,6 +94,9 @@ pub struct MediaMessage {
     pub direct_path: Option<String>,
     pub media_key: Option<Vec<u8>>,
     pub file_sha256: Option<Vec<u8>>,
+    /// SHA-256 of the encrypted upload, identifies the media for caching
+    #[serde(default)]
+    pub file_enc_sha256: Option<Vec<u8>>,
     pub file_length: Option<u64>,
     pub mime_type: Option<String>,
     pub caption: Option<String>,
@@ -68,25 +110,81 @@ pub struct MediaMessage {
     pub context_info: Option<ContextInfo>,
 }
 
+/// A received sticker
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StickerMessage {
+    pub url: Option<String>,
+    pub direct_path: Option<String>,
+    pub media_key: Option<Vec<u8>>,
+    pub file_sha256: Option<Vec<u8>>,
+    pub file_enc_sha256: Option<Vec<u8>>,
+    pub file_length: Option<u64>,
+    pub mime_type: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub is_animated: bool,
+    /// Whether the sticker shows the sender's avatar
+    pub is_avatar: bool,
+    /// Whether the sticker is a Lottie animation rather than a WebP
+    pub is_lottie: bool,
+    pub accessibility_label: Option<String>,
+    pub png_thumbnail: Option<Vec<u8>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocationMessage {
     pub latitude: f64,
     pub longitude: f64,
     pub name: Option<String>,
     pub address: Option<String>,
+    #[serde(default)]
+    pub context_info: Option<ContextInfo>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContactMessage {
     pub display_name: String,
     pub vcard: String,
+    #[serde(default)]
+    pub context_info: Option<ContextInfo>,
+}
+
+/// Keep a message of a disappearing chat from expiring, or undo that
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeepInChatMessage {
+    pub key: MessageKey,
+    pub keep: bool,
+    pub timestamp: SystemTime,
+}
+
+/// Several contacts shared in one message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactsArrayMessage {
+    pub display_name: String,
+    pub contacts: Vec<ContactMessage>,
+    #[serde(default)]
+    pub context_info: Option<ContextInfo>,
+}
+
+impl ContactsArrayMessage {
+    /// Share the contacts under the caption the official clients use
+    pub fn new(contacts: Vec<ContactMessage>) -> Self {
+        Self {
+            display_name: format!("{} contacts", contacts.len()),
+            contacts,
+            context_info: None,
+        }
+    }
 }
 
 /// Message receipts and status
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum MessageStatus {
     Pending,
+    /// Written to the socket, waiting for the server ack
     Sent,
+    /// Accepted by the server
+    ServerAck,
     Delivered,
     Read,
     Played,
@@ -102,11 +200,48 @@ pub struct MessageReceipt {
     pub participant: Option<JID>, // For group messages
 }
 
+/// Receipt counts of a group message across its recipients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliverySummary {
+    /// Number of recipients expected to send receipts
+    pub total: usize,
+    /// Recipients that have not sent any receipt yet
+    pub pending: usize,
+    /// Recipients whose latest receipt is a delivery receipt
+    pub delivered: usize,
+    /// Recipients whose latest receipt is a read receipt
+    pub read: usize,
+    /// Recipients who played the voice message or video
+    pub played: usize,
+}
+
+impl DeliverySummary {
+    /// Check whether every recipient received the message
+    pub fn all_delivered(&self) -> bool {
+        self.total > 0 && self.pending == 0
+    }
+    
+    /// Check whether every recipient read the message
+    pub fn all_read(&self) -> bool {
+        self.total > 0 && self.read + self.played == self.total
+    }
+    
+    /// Check whether every recipient played the voice message or media
+    pub fn all_played(&self) -> bool {
+        self.total > 0 && self.played == self.total
+    }
+}
+
 /// Context information for messages (replies, forwards, etc.)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ContextInfo {
     pub quoted_message: Option<Box<QuotedMessage>>,
     pub mentioned_jids: Vec<JID>,
+    /// Number of mentions that are not users; 1 for a mention of everyone
+    #[serde(default)]
+    pub non_jid_mentions: Option<u32>,
+    #[serde(default)]
+    pub group_mentions: Vec<GroupMention>,
     pub forwarded: Option<bool>,
     pub forwarding_score: Option<u32>,
     pub is_forwarded: Option<bool>,
@@ -115,6 +250,13 @@ pub struct ContextInfo {
     pub external_ad_reply: Option<ExternalAdReply>,
 }
 
+/// A group mentioned in a message, rendered as `@<subject>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupMention {
+    pub group_jid: JID,
+    pub group_subject: String,
+}
+
 /// Quoted message information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuotedMessage {
@@ -161,6 +303,9 @@ pub struct PollMessage {
     pub options: Vec<PollOption>,
     pub selectable_options_count: u32,
     pub context_info: Option<ContextInfo>,
+    /// Key material the votes on this poll are encrypted with
+    #[serde(default)]
+    pub message_secret: Option<Vec<u8>>,
 }
 
 /// Poll option
@@ -175,6 +320,16 @@ pub struct PollUpdateMessage {
     pub poll_creation_message_key: MessageKey,
     pub vote: PollVote,
     pub sender_timestamp: Option<SystemTime>,
+    /// The vote as received, before it was decrypted into `vote`
+    #[serde(default)]
+    pub encrypted_vote: Option<EncryptedPollVote>,
+}
+
+/// AES-GCM encrypted [`PollVote`], see [`crate::messaging::decrypt_poll_vote`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedPollVote {
+    pub payload: Vec<u8>,
+    pub iv: Vec<u8>,
 }
 
 /// Poll vote
@@ -196,6 +351,48 @@ pub struct GroupInviteMessage {
     pub context_info: Option<ContextInfo>,
 }
 
+/// A product of a business catalog shared in a chat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductMessage {
+    pub business_owner_jid: JID,
+    pub product_id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub currency_code: Option<String>,
+    /// Price in thousandths of the currency unit
+    pub price_amount_1000: Option<i64>,
+    pub retailer_id: Option<String>,
+    pub url: Option<String>,
+    pub product_image_count: Option<u32>,
+    pub body: Option<String>,
+    pub footer: Option<String>,
+}
+
+/// State of an order placed with a business
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    Inquiry,
+    Accepted,
+    Declined,
+}
+
+/// An order placed from a business catalog
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderMessage {
+    pub order_id: String,
+    pub seller_jid: Option<JID>,
+    pub item_count: u32,
+    pub status: Option<OrderStatus>,
+    pub title: Option<String>,
+    pub message: Option<String>,
+    /// Token needed to fetch the order details from the server
+    pub token: Option<String>,
+    /// Total in thousandths of the currency unit
+    pub total_amount_1000: Option<i64>,
+    pub total_currency_code: Option<String>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
 /// Enhanced text message with formatting
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtendedTextMessage {
@@ -223,6 +420,8 @@ pub struct ProtocolMessage {
     pub app_state_sync_key_share: Option<AppStateSyncKeyShare>,
     pub initial_security_notification_setting_sync: Option<InitialSecurityNotificationSettingSync>,
     pub app_state_sync_key_request: Option<AppStateSyncKeyRequest>,
+    /// New text (or caption) of an edited message
+    pub edited_text: Option<String>,
 }
 
 /// Protocol message types
@@ -302,7 +501,7 @@ pub struct AppStateSyncKeyRequest {
 }
 
 /// Represents a message that can be sent
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SendableMessage {
     Text(TextMessage),
     ExtendedText(ExtendedTextMessage),
@@ -314,10 +513,66 @@ pub enum SendableMessage {
     Sticker(MediaMessage),
     Location(LocationMessage),
     Contact(ContactMessage),
+    ContactsArray(ContactsArrayMessage),
     Quote(QuotedMessage),
     Reaction(ReactionMessage),
     Poll(PollMessage),
     PollUpdate(PollUpdateMessage),
     GroupInvite(GroupInviteMessage),
+    Product(ProductMessage),
+    KeepInChat(KeepInChatMessage),
     Protocol(ProtocolMessage),
+}
+
+impl SendableMessage {
+    /// Context the message carries, for the types that can carry one
+    pub fn context_info(&self) -> Option<&ContextInfo> {
+        match self {
+            SendableMessage::ExtendedText(text) => text.context_info.as_ref(),
+            SendableMessage::Image(media)
+            | SendableMessage::Video(media)
+            | SendableMessage::Audio(media)
+            | SendableMessage::Voice(media)
+            | SendableMessage::Document(media)
+            | SendableMessage::Sticker(media) => media.context_info.as_ref(),
+            SendableMessage::Location(location) => location.context_info.as_ref(),
+            SendableMessage::Contact(contact) => contact.context_info.as_ref(),
+            SendableMessage::ContactsArray(contacts) => contacts.context_info.as_ref(),
+            _ => None,
+        }
+    }
+    
+    /// Attach a context to the message
+    ///
+    /// Plain text becomes extended text, which can carry a context. Returns
+    /// `None` for the types that cannot carry one.
+    pub fn with_context_info(self, context: ContextInfo) -> Option<Self> {
+        let context_info = Some(context);
+        Some(match self {
+            SendableMessage::Text(text) => SendableMessage::ExtendedText(ExtendedTextMessage {
+                text: text.text,
+                matched_text: None,
+                canonical_url: None,
+                description: None,
+                title: None,
+                text_arg_b: None,
+                thumbnail: None,
+                jpeg_thumbnail: None,
+                context_info,
+                font: None,
+                preview_type: None,
+            }),
+            SendableMessage::ExtendedText(text) => SendableMessage::ExtendedText(ExtendedTextMessage { context_info, ..text }),
+            SendableMessage::Image(media) => SendableMessage::Image(MediaMessage { context_info, ..media }),
+            SendableMessage::Video(media) => SendableMessage::Video(MediaMessage { context_info, ..media }),
+            SendableMessage::Audio(media) => SendableMessage::Audio(MediaMessage { context_info, ..media }),
+            SendableMessage::Voice(media) => SendableMessage::Voice(MediaMessage { context_info, ..media }),
+            SendableMessage::Document(media) => SendableMessage::Document(MediaMessage { context_info, ..media }),
+            SendableMessage::Sticker(media) => SendableMessage::Sticker(MediaMessage { context_info, ..media }),
+            SendableMessage::Location(location) => SendableMessage::Location(LocationMessage { context_info, ..location }),
+            SendableMessage::Contact(contact) => SendableMessage::Contact(ContactMessage { context_info, ..contact }),
+            SendableMessage::ContactsArray(contacts) => SendableMessage::ContactsArray(ContactsArrayMessage { context_info, ..contacts }),
+            _ => return None,
+        })
+    }
 }
\ No newline at end of file
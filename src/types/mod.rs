@@ -1,7 +1,9 @@
 pub mod jid;
 pub mod message;
 pub mod events;
+pub mod user;
 
 pub use jid::*;
 pub use message::*;
-pub use events::*;
\ No newline at end of file
+pub use events::*;
+pub use user::*;
\ No newline at end of file
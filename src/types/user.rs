@@ -0,0 +1,19 @@
+use crate::types::JID;
+use serde::{Deserialize, Serialize};
+
+/// Result of checking whether a phone number is registered on WhatsApp
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IsOnWhatsAppResult {
+    /// The phone number that was queried
+    pub query: String,
+    /// The JID the number resolved to
+    pub jid: Option<JID>,
+    /// Whether the number is registered on WhatsApp
+    pub is_in: bool,
+    /// Hidden user (LID) JID of the account, if the server returned one
+    pub lid: Option<JID>,
+    /// Whether the account is a business account
+    pub is_business: bool,
+    /// Raw serialized verified name certificate of a business account
+    pub verified_name_certificate: Option<Vec<u8>>,
+}
@@ -0,0 +1,242 @@
+/// USync protocol queries
+///
+/// `usync` is the user synchronization protocol used to look up information
+/// about a batch of users (registration status, LIDs, business profiles,
+/// device lists, ...) in a single IQ round trip.
+
+use crate::{
+    binary::Node,
+    error::{Error, Result},
+    request::node_content_string,
+    types::{IsOnWhatsAppResult, JID},
+};
+
+/// Namespace of usync IQ queries
+pub const USYNC_NAMESPACE: &str = "usync";
+
+/// Build the `<usync>` payload of a usync IQ.
+///
+/// `query` lists the protocols to request (e.g. `<contact/>`, `<devices/>`),
+/// and `users` the `<user>` nodes to look up.
+pub fn build_usync_node(sid: &str, mode: &str, context: &str, query: Vec<Node>, users: Vec<Node>) -> Node {
+    Node::new("usync".to_string())
+        .attr("sid".to_string(), sid.to_string())
+        .attr("mode".to_string(), mode.to_string())
+        .attr("last".to_string(), "true".to_string())
+        .attr("index".to_string(), "0".to_string())
+        .attr("context".to_string(), context.to_string())
+        .with_children(vec![
+            Node::new("query".to_string()).with_children(query),
+            Node::new("list".to_string()).with_children(users),
+        ])
+}
+
+/// Build a `<user>` node looking up a phone number
+pub fn contact_user_node(phone: &str) -> Node {
+    let phone = if phone.starts_with('+') {
+        phone.to_string()
+    } else {
+        format!("+{}", phone)
+    };
+    Node::new("user".to_string())
+        .with_children(vec![Node::new("contact".to_string()).with_text(phone)])
+}
+
+/// Build a `<user>` node looking up the device list of a JID
+pub fn device_user_node(jid: &JID) -> Node {
+    Node::new("user".to_string()).attr("jid".to_string(), jid.to_non_ad().to_string())
+}
+
+/// Device list of a single user returned by a device query
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserDeviceList {
+    /// The user the list belongs to (without device part)
+    pub user: JID,
+    /// Device JIDs of the user, including the primary device (id 0)
+    pub devices: Vec<JID>,
+    /// Timestamp of the signed key index list, if present
+    pub key_index_timestamp: Option<u64>,
+}
+
+/// Parse the `<devices>` section of a usync `<user>` result
+pub fn parse_device_list(user: &Node) -> Option<UserDeviceList> {
+    let jid = user.get_attr("jid")?.parse::<JID>().ok()?;
+    let user_jid = JID::new(jid.user.clone(), jid.server.clone());
+    let devices_node = user.find_child("devices")?;
+
+    let devices = devices_node
+        .find_child("device-list")
+        .and_then(|list| list.get_children())
+        .map(|children| {
+            children
+                .iter()
+                .filter(|c| c.tag == "device")
+                .filter_map(|c| c.get_attr("id").and_then(|id| id.parse::<u8>().ok()))
+                .map(|device_id| user_jid.with_device(device_id))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let key_index_timestamp = devices_node
+        .find_child("key-index-list")
+        .and_then(|k| k.get_attr("ts"))
+        .and_then(|ts| ts.parse().ok());
+
+    Some(UserDeviceList {
+        user: user_jid,
+        devices,
+        key_index_timestamp,
+    })
+}
+
+/// Extract the `<user>` result nodes from a usync IQ response
+pub fn parse_usync_list(response: &Node) -> Result<Vec<Node>> {
+    let usync = response
+        .find_child("usync")
+        .ok_or_else(|| Error::ElementMissing("usync".to_string()))?;
+    let list = usync
+        .find_child("list")
+        .ok_or_else(|| Error::ElementMissing("usync list".to_string()))?;
+
+    Ok(list
+        .get_children()
+        .map(|children| children.iter().filter(|c| c.tag == "user").cloned().collect())
+        .unwrap_or_default())
+}
+
+/// Parse a single `<user>` result of a contact query
+pub fn parse_is_on_whatsapp_user(user: &Node) -> IsOnWhatsAppResult {
+    let jid = user.get_attr("jid").and_then(|j| j.parse::<JID>().ok());
+    let contact = user.find_child("contact");
+
+    let query = contact.and_then(node_content_string).unwrap_or_default();
+    let is_in = contact
+        .and_then(|c| c.get_attr("type"))
+        .map(|t| t == "in")
+        .unwrap_or(false);
+
+    let lid = user
+        .find_child("lid")
+        .and_then(|l| l.get_attr("val"))
+        .and_then(|v| v.parse::<JID>().ok());
+
+    // The server answers the business protocol for every user, only
+    // business accounts have a verified name in it
+    let verified_name = user
+        .find_child("business")
+        .and_then(|b| b.find_child("verified_name"));
+    let verified_name_certificate = verified_name.and_then(|v| v.get_binary()).cloned();
+
+    IsOnWhatsAppResult {
+        query,
+        jid,
+        is_in,
+        lid,
+        is_business: verified_name.is_some(),
+        verified_name_certificate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_contact_query() {
+        let node = build_usync_node(
+            "sid-1",
+            "query",
+            "interactive",
+            vec![Node::new("contact".to_string())],
+            vec![contact_user_node("1234567890"), contact_user_node("+1987654321")],
+        );
+
+        assert_eq!(node.get_attr("mode").unwrap(), "query");
+        let list = node.find_child("list").unwrap().get_children().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].find_child("contact").unwrap().get_text().unwrap(), "+1234567890");
+        assert_eq!(list[1].find_child("contact").unwrap().get_text().unwrap(), "+1987654321");
+    }
+
+    #[test]
+    fn test_parse_is_on_whatsapp() {
+        let user = Node::new("user".to_string())
+            .attr("jid".to_string(), "1234567890@s.whatsapp.net".to_string())
+            .with_children(vec![
+                Node::new("contact".to_string())
+                    .attr("type".to_string(), "in".to_string())
+                    .with_text("+1234567890".to_string()),
+                Node::new("lid".to_string()).attr("val".to_string(), "9876@lid".to_string()),
+                Node::new("business".to_string()).with_children(vec![
+                    Node::new("verified_name".to_string()).with_binary(vec![1, 2, 3]),
+                ]),
+            ]);
+        let response = Node::new("iq".to_string()).with_children(vec![
+            Node::new("usync".to_string()).with_children(vec![
+                Node::new("list".to_string()).with_children(vec![user]),
+            ]),
+        ]);
+
+        let users = parse_usync_list(&response).unwrap();
+        assert_eq!(users.len(), 1);
+
+        let result = parse_is_on_whatsapp_user(&users[0]);
+        assert_eq!(result.query, "+1234567890");
+        assert!(result.is_in);
+        assert_eq!(result.jid.unwrap().user, "1234567890");
+        assert_eq!(result.lid.unwrap().server, "lid");
+        assert!(result.is_business);
+        assert_eq!(result.verified_name_certificate, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_parse_regular_user_with_business_node() {
+        let user = Node::new("user".to_string())
+            .attr("jid".to_string(), "1234567890@s.whatsapp.net".to_string())
+            .with_children(vec![
+                Node::new("contact".to_string())
+                    .attr("type".to_string(), "in".to_string())
+                    .with_text("+1234567890".to_string()),
+                Node::new("business".to_string()),
+            ]);
+
+        let result = parse_is_on_whatsapp_user(&user);
+        assert!(result.is_in);
+        assert!(!result.is_business);
+        assert!(result.verified_name_certificate.is_none());
+    }
+
+    #[test]
+    fn test_parse_device_list() {
+        let user = Node::new("user".to_string())
+            .attr("jid".to_string(), "1234567890@s.whatsapp.net".to_string())
+            .with_children(vec![Node::new("devices".to_string()).with_children(vec![
+                Node::new("device-list".to_string()).with_children(vec![
+                    Node::new("device".to_string()).attr("id".to_string(), "0".to_string()),
+                    Node::new("device".to_string())
+                        .attr("id".to_string(), "3".to_string())
+                        .attr("key-index".to_string(), "1".to_string()),
+                ]),
+                Node::new("key-index-list".to_string()).attr("ts".to_string(), "1700000000".to_string()),
+            ])]);
+
+        let list = parse_device_list(&user).unwrap();
+        assert_eq!(list.user.to_string(), "1234567890@s.whatsapp.net");
+        assert_eq!(list.devices.len(), 2);
+        assert_eq!(list.devices[0].device, 0);
+        assert_eq!(list.devices[1].device, 3);
+        assert_eq!(list.key_index_timestamp, Some(1700000000));
+    }
+
+    #[test]
+    fn test_parse_not_on_whatsapp() {
+        let user = Node::new("user".to_string()).with_children(vec![Node::new("contact".to_string())
+            .attr("type".to_string(), "out".to_string())
+            .with_text("+100".to_string())]);
+
+        let result = parse_is_on_whatsapp_user(&user);
+        assert!(!result.is_in);
+        assert!(!result.is_business);
+        assert!(result.jid.is_none());
+    }
+}
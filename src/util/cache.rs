@@ -0,0 +1,251 @@
+/// Size and time bounded in-memory cache
+///
+/// Entries expire `ttl` after they were inserted. When the cache is full the
+/// least recently used entry is evicted, after dropping expired ones. Hits
+/// and misses are counted per cache and reported to the metrics recorder
+/// under the cache's name.
+
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+struct Entry<V> {
+    value: V,
+    inserted_at: Instant,
+    /// Position in the recency order
+    used: u64,
+}
+
+/// Counters of a cache
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Entries currently cached
+    pub total_entries: usize,
+    /// Maximum number of entries
+    pub max_entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room
+    pub evictions: u64,
+    /// Entries dropped because they outlived the TTL
+    pub expirations: u64,
+    /// Cache hit ratio (0.0 to 1.0)
+    pub hit_ratio: f64,
+}
+
+/// Cache with a maximum size and a time to live per entry
+pub struct TtlLruCache<K, V> {
+    name: &'static str,
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<K, Entry<V>>,
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> TtlLruCache<K, V> {
+    /// Create a cache; `name` labels its metrics
+    pub fn new(name: &'static str, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            name,
+            capacity: capacity.max(1),
+            ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            expirations: 0,
+        }
+    }
+
+    /// Look up an entry, counting a hit or miss
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let found = self.touch(key, Instant::now());
+        if found {
+            self.hits += 1;
+            metrics::cache_hit(self.name);
+        } else {
+            self.misses += 1;
+            metrics::cache_miss(self.name);
+        }
+        self.entries.get(key).filter(|_| found).map(|entry| &entry.value)
+    }
+
+    /// Look up an entry to update it in place, without counting a hit or miss
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if !self.touch(key, Instant::now()) {
+            return None;
+        }
+        self.entries.get_mut(key).map(|entry| &mut entry.value)
+    }
+
+    /// Whether an unexpired entry exists, without affecting recency or counters
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.get(key).is_some_and(|entry| !self.is_expired(entry, Instant::now()))
+    }
+
+    /// Insert or replace an entry, restarting its TTL
+    pub fn insert(&mut self, key: K, value: V) {
+        let now = Instant::now();
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.used);
+        } else if self.entries.len() >= self.capacity {
+            self.purge_expired();
+            if self.entries.len() >= self.capacity {
+                self.evict_least_recent();
+            }
+        }
+
+        let used = self.next_use();
+        self.recency.insert(used, key.clone());
+        self.entries.insert(key, Entry { value, inserted_at: now, used });
+    }
+
+    /// Remove an entry
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.used);
+        Some(entry.value)
+    }
+
+    /// Drop all entries, keeping the counters
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Drop expired entries, returning how many were dropped
+    pub fn purge_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<K> = self.entries
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        self.expirations += expired.len() as u64;
+        expired.len()
+    }
+
+    /// Keys of all cached entries, including expired ones not yet dropped
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lookups = self.hits + self.misses;
+        CacheStats {
+            total_entries: self.entries.len(),
+            max_entries: self.capacity,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            expirations: self.expirations,
+            hit_ratio: if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 },
+        }
+    }
+
+    /// Mark an entry as used, dropping it if it expired. Returns whether it is cached.
+    fn touch(&mut self, key: &K, now: Instant) -> bool {
+        let expired = match self.entries.get(key) {
+            Some(entry) => self.is_expired(entry, now),
+            None => return false,
+        };
+        if expired {
+            self.remove(key);
+            self.expirations += 1;
+            return false;
+        }
+
+        let used = self.next_use();
+        let entry = self.entries.get_mut(key).expect("entry checked above");
+        self.recency.remove(&entry.used);
+        entry.used = used;
+        self.recency.insert(used, key.clone());
+        true
+    }
+
+    fn is_expired(&self, entry: &Entry<V>, now: Instant) -> bool {
+        now.duration_since(entry.inserted_at) > self.ttl
+    }
+
+    fn evict_least_recent(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            self.entries.remove(&key);
+            self.evictions += 1;
+        }
+    }
+
+    fn next_use(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = TtlLruCache::new("test", 2, Duration::from_secs(60));
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        // "b" was used least recently
+        cache.insert("c", 3);
+        assert!(cache.contains_key(&"a"));
+        assert!(!cache.contains_key(&"b"));
+        assert!(cache.contains_key(&"c"));
+
+        *cache.get_mut(&"c").unwrap() += 1;
+        assert_eq!(cache.get(&"c"), Some(&4));
+        assert_eq!(cache.get(&"b"), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 1, 1));
+        assert!((stats.hit_ratio - 2.0 / 3.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_expires_entries() {
+        let mut cache = TtlLruCache::new("test", 2, Duration::ZERO);
+        cache.insert("a", 1);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.is_empty());
+
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        std::thread::sleep(Duration::from_millis(5));
+        // Expired entries make room before anything is evicted
+        cache.insert("c", 3);
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(stats.expirations, 3);
+        assert_eq!(cache.len(), 1);
+    }
+}
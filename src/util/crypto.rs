@@ -1,10 +1,11 @@
 use crate::error::{Error, Result};
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
+use aes::cipher::{BlockDecrypt, BlockEncrypt};
 use hkdf::Hkdf;
-use ring::digest;
+use ring::{digest, hmac};
 use sha2::Sha256;
 
 #[cfg(test)]
@@ -18,6 +19,29 @@ mod tests {
         assert_eq!(hash.len(), 32);
     }
     
+    #[test]
+    fn test_aes_cbc_encryption() {
+        let key = [1u8; 32];
+        let iv = [2u8; 16];
+        
+        for plaintext in [&b""[..], b"test message", &[7u8; 32]] {
+            let ciphertext = aes_cbc_encrypt(&key, &iv, plaintext).unwrap();
+            assert_eq!(ciphertext.len() % 16, 0);
+            assert!(ciphertext.len() > plaintext.len());
+            assert_eq!(aes_cbc_decrypt(&key, &iv, &ciphertext).unwrap(), plaintext);
+        }
+        assert!(aes_cbc_decrypt(&key, &iv, &[0u8; 15]).is_err());
+        assert!(aes_cbc_encrypt(&key[..16], &iv, b"x").is_err());
+    }
+    
+    #[test]
+    fn test_hmac() {
+        // RFC 4231 test case 2
+        let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(hex::encode(mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(hmac_sha512(b"Jefe", &[b"what do ya want for nothing?"]).len(), 64);
+    }
+    
     #[test]
     fn test_aes_gcm_encryption() {
         let key = [1u8; 32];
@@ -73,6 +97,28 @@ impl AesGcm {
             .decrypt(nonce, data)
             .map_err(|e| Error::Crypto(format!("Decryption failed: {}", e)))
     }
+    
+    /// Encrypt data with the given nonce, authenticating `aad` along with it
+    pub fn encrypt_with_aad(&self, nonce: &[u8], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != 12 {
+            return Err(Error::Crypto("Nonce must be 12 bytes".to_string()));
+        }
+        
+        self.cipher
+            .encrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+            .map_err(|e| Error::Crypto(format!("Encryption failed: {}", e)))
+    }
+    
+    /// Decrypt data encrypted with [`AesGcm::encrypt_with_aad`]
+    pub fn decrypt_with_aad(&self, nonce: &[u8], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != 12 {
+            return Err(Error::Crypto("Nonce must be 12 bytes".to_string()));
+        }
+        
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+            .map_err(|e| Error::Crypto(format!("Decryption failed: {}", e)))
+    }
 }
 
 /// HKDF key derivation
@@ -97,4 +143,72 @@ pub fn random_bytes(length: usize) -> Vec<u8> {
     let mut bytes = vec![0u8; length];
     rng.fill(&mut bytes).unwrap();
     bytes
-}
\ No newline at end of file
+}
+
+/// HMAC-SHA256 over the concatenation of `data`
+pub fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    concat_and_hmac(hmac::HMAC_SHA256, key, data)
+}
+
+/// HMAC-SHA512 over the concatenation of `data`
+pub fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    concat_and_hmac(hmac::HMAC_SHA512, key, data)
+}
+
+fn concat_and_hmac(algorithm: hmac::Algorithm, key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let key = hmac::Key::new(algorithm, key);
+    let mut context = hmac::Context::with_key(&key);
+    for part in data {
+        context.update(part);
+    }
+    context.sign().as_ref().to_vec()
+}
+
+const AES_BLOCK_SIZE: usize = 16;
+
+fn aes256_cipher(key: &[u8], iv: &[u8]) -> Result<aes::Aes256> {
+    use aes::cipher::KeyInit;
+    if iv.len() != AES_BLOCK_SIZE {
+        return Err(Error::Crypto("AES-CBC IV must be 16 bytes".to_string()));
+    }
+    aes::Aes256::new_from_slice(key)
+        .map_err(|_| Error::Crypto("AES-256 key must be 32 bytes".to_string()))
+}
+
+/// AES-256-CBC encryption with PKCS#7 padding
+pub fn aes_cbc_encrypt(key: &[u8], iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = aes256_cipher(key, iv)?;
+    
+    let padding = AES_BLOCK_SIZE - plaintext.len() % AES_BLOCK_SIZE;
+    let mut data = plaintext.to_vec();
+    data.extend(std::iter::repeat(padding as u8).take(padding));
+    
+    let mut previous = iv;
+    for chunk in data.chunks_mut(AES_BLOCK_SIZE) {
+        chunk.iter_mut().zip(previous).for_each(|(byte, prev)| *byte ^= prev);
+        cipher.encrypt_block(aes::Block::from_mut_slice(chunk));
+        previous = chunk;
+    }
+    Ok(data)
+}
+
+/// AES-256-CBC decryption, removing PKCS#7 padding
+pub fn aes_cbc_decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = aes256_cipher(key, iv)?;
+    if ciphertext.is_empty() || ciphertext.len() % AES_BLOCK_SIZE != 0 {
+        return Err(Error::Crypto("AES-CBC ciphertext is not a multiple of the block size".to_string()));
+    }
+    
+    let mut data = ciphertext.to_vec();
+    for (chunk, previous) in data.chunks_mut(AES_BLOCK_SIZE).zip(std::iter::once(iv).chain(ciphertext.chunks(AES_BLOCK_SIZE))) {
+        cipher.decrypt_block(aes::Block::from_mut_slice(chunk));
+        chunk.iter_mut().zip(previous).for_each(|(byte, prev)| *byte ^= prev);
+    }
+    
+    let padding = *data.last().unwrap_or(&0) as usize;
+    if padding == 0 || padding > AES_BLOCK_SIZE || data[data.len() - padding..].iter().any(|&b| b as usize != padding) {
+        return Err(Error::Crypto("Invalid AES-CBC padding".to_string()));
+    }
+    data.truncate(data.len() - padding);
+    Ok(data)
+}
@@ -0,0 +1,341 @@
+/// vCard building and parsing
+///
+/// Contacts are shared as vCard 3.0 strings inside `contactMessage`s. The
+/// official clients add a `waid` parameter to every phone number that is on
+/// WhatsApp, which turns the number into a "Message" button on the receiving
+/// side. The builder here produces the same layout, hinting the `waid` from
+/// the digits of the number, and the parser reads cards written by the
+/// official clients back into their fields.
+
+use crate::{
+    error::{Error, Result},
+    proto::wa_e2e,
+    types::{ContactMessage, DEFAULT_USER_SERVER, JID},
+};
+use serde::{Deserialize, Serialize};
+
+/// Shortest and longest E.164 numbers, without the leading `+`
+const MIN_PHONE_DIGITS: usize = 7;
+const MAX_PHONE_DIGITS: usize = 15;
+
+/// A phone number on a vCard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VCardPhone {
+    /// The number as displayed, e.g. `+1 555 123 4567`
+    pub number: String,
+    /// The `type` parameter, e.g. `CELL`
+    pub kind: Option<String>,
+    /// WhatsApp user of the number
+    pub wa_id: Option<String>,
+}
+
+impl VCardPhone {
+    /// JID of the WhatsApp user behind the number, if hinted
+    pub fn jid(&self) -> Option<JID> {
+        self.wa_id.as_ref().map(|user| JID::new(user.clone(), DEFAULT_USER_SERVER.to_string()))
+    }
+}
+
+/// A contact card
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VCard {
+    pub full_name: String,
+    pub organization: Option<String>,
+    pub phones: Vec<VCardPhone>,
+    pub emails: Vec<String>,
+}
+
+impl VCard {
+    /// Start a card for the given name
+    pub fn new(full_name: impl Into<String>) -> Self {
+        Self {
+            full_name: full_name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a mobile number, hinting its WhatsApp user from the digits
+    pub fn phone(self, number: impl Into<String>) -> Self {
+        self.phone_with_type(number, "CELL")
+    }
+
+    /// Add a number with an explicit `type`, hinting its WhatsApp user from the digits
+    pub fn phone_with_type(mut self, number: impl Into<String>, kind: impl Into<String>) -> Self {
+        let number = number.into();
+        self.phones.push(VCardPhone {
+            wa_id: wa_id_hint(&number),
+            number,
+            kind: Some(kind.into()),
+        });
+        self
+    }
+
+    /// Add a number that is not on WhatsApp
+    pub fn phone_without_wa_id(mut self, number: impl Into<String>) -> Self {
+        self.phones.push(VCardPhone {
+            number: number.into(),
+            kind: Some("CELL".to_string()),
+            wa_id: None,
+        });
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.emails.push(email.into());
+        self
+    }
+
+    pub fn organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Serialize the card as a vCard 3.0 string
+    pub fn to_vcard_string(&self) -> String {
+        let mut lines = vec![
+            "BEGIN:VCARD".to_string(),
+            "VERSION:3.0".to_string(),
+            format!("N:;{};;;", escape(&self.full_name)),
+            format!("FN:{}", escape(&self.full_name)),
+        ];
+        if let Some(organization) = &self.organization {
+            lines.push(format!("ORG:{}", escape(organization)));
+        }
+        for phone in &self.phones {
+            let mut property = "TEL".to_string();
+            if let Some(kind) = &phone.kind {
+                property.push_str(&format!(";type={}", kind));
+            }
+            if let Some(wa_id) = &phone.wa_id {
+                property.push_str(&format!(";waid={}", wa_id));
+            }
+            lines.push(format!("{}:{}", property, escape(&phone.number)));
+        }
+        for email in &self.emails {
+            lines.push(format!("EMAIL:{}", escape(email)));
+        }
+        lines.push("END:VCARD".to_string());
+        lines.join("\n")
+    }
+
+    /// Parse a single vCard string
+    pub fn parse(vcard: &str) -> Result<Self> {
+        let lines = unfold(vcard);
+        if !lines.first().is_some_and(|line| line.eq_ignore_ascii_case("BEGIN:VCARD")) {
+            return Err(Error::Protocol("vCard missing BEGIN:VCARD".to_string()));
+        }
+
+        let mut card = Self::default();
+        let mut structured_name = None;
+        for line in &lines[1..] {
+            let Some((property, value)) = line.split_once(':') else {
+                continue;
+            };
+            let mut params = property.split(';');
+            let name = params.next().unwrap_or_default();
+            // Apple clients group properties as `item1.TEL`
+            let name = name.rsplit('.').next().unwrap_or(name).to_ascii_uppercase();
+            match name.as_str() {
+                "END" => return Ok(card.with_fallback_name(structured_name)),
+                "FN" => card.full_name = unescape(value),
+                "N" => structured_name = Some(structured_name_to_display(value)),
+                "ORG" => card.organization = Some(unescape(value.trim_end_matches(';'))),
+                "EMAIL" => card.emails.push(unescape(value)),
+                "TEL" => {
+                    let mut phone = VCardPhone {
+                        number: unescape(value),
+                        kind: None,
+                        wa_id: None,
+                    };
+                    for param in params {
+                        match param.split_once('=') {
+                            Some((key, v)) if key.eq_ignore_ascii_case("waid") => phone.wa_id = Some(v.to_string()),
+                            Some((key, v)) if key.eq_ignore_ascii_case("type") => phone.kind = Some(v.to_string()),
+                            // vCard 2.1 lists bare types, e.g. `TEL;CELL:`
+                            None if !param.is_empty() => phone.kind = Some(param.to_string()),
+                            _ => {}
+                        }
+                    }
+                    card.phones.push(phone);
+                }
+                _ => {}
+            }
+        }
+        Err(Error::Protocol("vCard missing END:VCARD".to_string()))
+    }
+
+    /// Wrap the card in a contact message
+    pub fn to_contact_message(&self) -> ContactMessage {
+        ContactMessage {
+            display_name: self.full_name.clone(),
+            vcard: self.to_vcard_string(),
+            context_info: None,
+        }
+    }
+
+    fn with_fallback_name(mut self, structured_name: Option<String>) -> Self {
+        if self.full_name.is_empty() {
+            self.full_name = structured_name.unwrap_or_default();
+        }
+        self
+    }
+}
+
+/// Guess the WhatsApp user of a phone number from its digits
+///
+/// Numbers must be in international format; anything too short or too
+/// long to be an E.164 number gets no hint.
+pub fn wa_id_hint(number: &str) -> Option<String> {
+    let number = number.trim();
+    let number = number.strip_prefix("00").unwrap_or(number);
+    let digits: String = number.chars().filter(|c| c.is_ascii_digit()).collect();
+    (MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits.len()).then_some(digits)
+}
+
+/// Parse the card of a contact message, naming it after the message if the card has no name
+pub fn parse_contact_message(contact: &ContactMessage) -> Result<VCard> {
+    let mut card = VCard::parse(&contact.vcard)?;
+    if card.full_name.is_empty() {
+        card.full_name = contact.display_name.clone();
+    }
+    Ok(card)
+}
+
+/// Extract the shared contacts from a decrypted message
+///
+/// Single contacts and contact arrays are both returned as a list; other
+/// messages yield an empty one.
+pub fn parse_contacts(message: &wa_e2e::Message) -> Vec<ContactMessage> {
+    let from_proto = |contact: &wa_e2e::ContactMessage| ContactMessage {
+        display_name: contact.display_name.clone().unwrap_or_default(),
+        vcard: contact.vcard.clone().unwrap_or_default(),
+        context_info: None,
+    };
+    if let Some(contact) = message.contact_message.as_deref() {
+        return vec![from_proto(contact)];
+    }
+    message.contacts_array_message.as_deref()
+        .map(|array| array.contacts.iter().map(from_proto).collect())
+        .unwrap_or_default()
+}
+
+/// Split the card into logical lines, joining folded continuation lines
+fn unfold(vcard: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in vcard.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.trim_end().to_string()),
+        }
+    }
+    lines
+}
+
+/// `N:Family;Given;Additional;Prefix;Suffix` as a display name
+fn structured_name_to_display(value: &str) -> String {
+    let parts: Vec<String> = value.split(';').map(unescape).collect();
+    let order = [3, 1, 2, 0, 4];
+    order.iter()
+        .filter_map(|&i| parts.get(i))
+        .filter(|part| !part.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wa_id_hint() {
+        assert_eq!(wa_id_hint("+1 (555) 123-4567").as_deref(), Some("15551234567"));
+        assert_eq!(wa_id_hint("0044 20 7946 0958").as_deref(), Some("442079460958"));
+        assert_eq!(wa_id_hint("112"), None);
+        assert_eq!(wa_id_hint("+1234567890123456"), None);
+    }
+
+    #[test]
+    fn test_vcard_round_trip() {
+        let card = VCard::new("Doe, John")
+            .organization("Example; Corp")
+            .phone("+1 555 123 4567")
+            .phone_without_wa_id("+1 555 000 0000")
+            .email("john@example.com");
+        let vcard = card.to_vcard_string();
+        assert!(vcard.contains("TEL;type=CELL;waid=15551234567:+1 555 123 4567"));
+        assert!(vcard.contains("FN:Doe\\, John"));
+
+        let parsed = VCard::parse(&vcard).unwrap();
+        assert_eq!(parsed, card);
+        assert_eq!(parsed.phones[0].jid(), Some(JID::new("15551234567".to_string(), DEFAULT_USER_SERVER.to_string())));
+        assert_eq!(parsed.phones[1].jid(), None);
+
+        let contact = card.to_contact_message();
+        assert_eq!(contact.display_name, "Doe, John");
+        assert_eq!(parse_contact_message(&contact).unwrap(), card);
+    }
+
+    #[test]
+    fn test_parse_client_vcard() {
+        let vcard = "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Appleseed;Jane;;Dr.;\r\nitem1.TEL;waid=4915112345678:+49 151 1234\r\n 5678\r\nitem1.X-ABLabel:Mobile\r\nTEL;CELL:+1 555\r\nEND:VCARD";
+        let card = VCard::parse(vcard).unwrap();
+        assert_eq!(card.full_name, "Dr. Jane Appleseed");
+        assert_eq!(card.phones[0].number, "+49 151 12345678");
+        assert_eq!(card.phones[0].wa_id.as_deref(), Some("4915112345678"));
+        assert_eq!(card.phones[1].kind.as_deref(), Some("CELL"));
+
+        assert!(VCard::parse("FN:Nobody").is_err());
+        assert!(VCard::parse("BEGIN:VCARD\nFN:Unterminated").is_err());
+    }
+
+    #[test]
+    fn test_parse_contacts() {
+        let cards = [VCard::new("Alice").phone("+1 555 111 2222"), VCard::new("Bob").phone("+1 555 333 4444")];
+        let message = wa_e2e::Message {
+            contacts_array_message: Some(Box::new(wa_e2e::ContactsArrayMessage {
+                display_name: Some("2 contacts".to_string()),
+                contacts: cards.iter()
+                    .map(|card| wa_e2e::ContactMessage {
+                        display_name: Some(card.full_name.clone()),
+                        vcard: Some(card.to_vcard_string()),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        let contacts = parse_contacts(&message);
+        assert_eq!(contacts.len(), 2);
+        let parsed: Vec<VCard> = contacts.iter().map(|c| parse_contact_message(c).unwrap()).collect();
+        assert_eq!(parsed, cards);
+        assert!(parse_contacts(&wa_e2e::Message::default()).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+/// Opt-in log of the stanzas exchanged with the server
+///
+/// Every decrypted node sent or received is rendered as indented XML and
+/// kept in a ring buffer, so protocol problems can be inspected after the
+/// fact. Rendering redacts what must not end up in a bug report: binary
+/// content (keys, encrypted payloads, media keys) is replaced by its length,
+/// message bodies by their length, and secret attributes by a placeholder.
+
+use crate::binary::{Node, NodeContent};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Tags whose text content is a message body or otherwise private
+const REDACTED_TEXT_TAGS: &[&str] = &["body", "conversation", "caption", "text", "ref", "pushname"];
+
+/// Attributes holding secrets or personal data
+const REDACTED_ATTRS: &[&str] = &["mediakey", "media_key", "token", "auth", "secret", "key", "notify", "phash"];
+
+/// Direction of a logged stanza
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A stanza as recorded by the [`WireLog`]
+#[derive(Debug, Clone)]
+pub struct LoggedStanza {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    /// The redacted, pretty-printed node
+    pub stanza: String,
+}
+
+impl fmt::Display for LoggedStanza {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "-->",
+            Direction::Received => "<--",
+        };
+        let millis = self.timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        write!(f, "{} {}\n{}", arrow, millis, self.stanza)
+    }
+}
+
+/// Ring buffer of the most recent stanzas.
+///
+/// A log with capacity 0 records nothing.
+pub struct WireLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<LoggedStanza>>,
+}
+
+impl WireLog {
+    /// Create a log keeping the last `capacity` stanzas
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+        }
+    }
+    
+    /// Whether stanzas are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    
+    /// Record a stanza, dropping the oldest when full
+    pub fn record(&self, direction: Direction, node: &Node) {
+        if !self.is_enabled() {
+            return;
+        }
+        let stanza = render(node);
+        tracing::trace!(target: "whatsmeow::wire", "{:?}\n{}", direction, stanza);
+        
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(LoggedStanza { direction, timestamp: SystemTime::now(), stanza });
+    }
+    
+    /// The recorded stanzas, oldest first
+    pub fn recent(&self) -> Vec<LoggedStanza> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+    
+    /// Forget all recorded stanzas
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Render a node as indented XML with sensitive content redacted
+pub fn render(node: &Node) -> String {
+    let mut out = String::new();
+    render_into(node, 0, &mut out);
+    out
+}
+
+fn render_into(node: &Node, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    out.push_str(&indent);
+    out.push('<');
+    out.push_str(&node.tag);
+    
+    // Sorted so the same stanza always renders the same way
+    let mut attrs: Vec<_> = node.attrs.iter().collect();
+    attrs.sort();
+    for (key, value) in attrs {
+        if REDACTED_ATTRS.contains(&key.as_str()) {
+            out.push_str(&format!(" {}=\"[redacted]\"", key));
+        } else {
+            out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+        }
+    }
+    
+    match &node.content {
+        NodeContent::None => out.push_str("/>\n"),
+        NodeContent::Text(text) if REDACTED_TEXT_TAGS.contains(&node.tag.as_str()) => {
+            out.push_str(&format!(">[{} chars redacted]</{}>\n", text.chars().count(), node.tag));
+        }
+        NodeContent::Text(text) => out.push_str(&format!(">{}</{}>\n", escape(text), node.tag)),
+        NodeContent::Binary(data) => out.push_str(&format!(">[{} bytes]</{}>\n", data.len(), node.tag)),
+        NodeContent::Children(children) => {
+            out.push_str(">\n");
+            for child in children {
+                render_into(child, depth + 1, out);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, node.tag));
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_render_redacts() {
+        let node = Node::new("message".to_string())
+            .attr("id".to_string(), "ABC".to_string())
+            .attr("to".to_string(), "123@s.whatsapp.net".to_string())
+            .with_children(vec![
+                Node::new("enc".to_string())
+                    .attr("type".to_string(), "pkmsg".to_string())
+                    .with_binary(vec![7; 32]),
+                Node::new("body".to_string()).with_text("secret plans".to_string()),
+                Node::new("media".to_string()).attr("mediakey".to_string(), "a2V5".to_string()),
+            ]);
+        
+        assert_eq!(render(&node), concat!(
+            "<message id=\"ABC\" to=\"123@s.whatsapp.net\">\n",
+            "  <enc type=\"pkmsg\">[32 bytes]</enc>\n",
+            "  <body>[12 chars redacted]</body>\n",
+            "  <media mediakey=\"[redacted]\"/>\n",
+            "</message>\n",
+        ));
+    }
+    
+    #[test]
+    fn test_ring_buffer() {
+        let disabled = WireLog::new(0);
+        disabled.record(Direction::Sent, &Node::new("iq".to_string()));
+        assert!(disabled.recent().is_empty());
+        
+        let log = WireLog::new(2);
+        for tag in ["a", "b", "c"] {
+            log.record(Direction::Received, &Node::new(tag.to_string()));
+        }
+        let recent = log.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].stanza, "<b/>\n");
+        assert!(recent[1].to_string().starts_with("<-- "));
+    }
+}
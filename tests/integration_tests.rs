@@ -134,7 +134,7 @@ fn test_ecdh() {
 #[test]
 fn test_protobuf_utils() {
     let text_msg = ProtoUtils::create_text_message("Test message");
-    assert_eq!(text_msg.text, Some("Test message".to_string()));
+    assert_eq!(text_msg.conversation, Some("Test message".to_string()));
     
     let msg_key = ProtoUtils::create_message_key("test@example.com", "msg123", true);
     assert_eq!(msg_key.remote_jid, Some("test@example.com".to_string()));